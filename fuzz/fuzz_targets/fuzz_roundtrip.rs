@@ -0,0 +1,11 @@
+//! Coverage guided version of `recert fuzz-roundtrip`, see recert::roundtrip::run_input. Run with
+//! `cargo +nightly fuzz run fuzz_roundtrip`, failures are saved under fuzz/artifacts and can be
+//! replayed with `recert fuzz-roundtrip --input ARTIFACT`
+
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    if let Err(err) = recert::roundtrip::run_input(data) {
+        panic!("{:?}", err);
+    }
+});
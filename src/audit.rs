@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use sha2::Digest;
+use std::{
+    io::Write,
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum_macros::Display)]
+#[strum(serialize_all = "kebab-case")]
+pub(crate) enum AuditAction {
+    FileRead,
+    FileWrite,
+    FileDelete,
+    EtcdGet,
+    EtcdPut,
+    EtcdDelete,
+}
+
+enum AuditSink {
+    /// Newline delimited JSON records
+    File(Mutex<std::fs::File>),
+    /// Structured journal entries, sent using the journald native protocol
+    Journald(UnixDatagram),
+}
+
+/// An audit trail of every file and etcd key recert reads or writes, along with a hash of the
+/// contents involved. This is separate from the normal stdout logging, and is meant for regulated
+/// environments where there needs to be a record of exactly what recert touched.
+static AUDIT_SINKS: OnceLock<Vec<AuditSink>> = OnceLock::new();
+
+pub(crate) fn init(audit_log: Option<PathBuf>, journald: bool) -> Result<()> {
+    let mut sinks = vec![];
+
+    if let Some(audit_log) = audit_log {
+        sinks.push(AuditSink::File(Mutex::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&audit_log)
+                .with_context(|| format!("opening audit log {:?}", audit_log))?,
+        )));
+    }
+
+    if journald {
+        let socket = UnixDatagram::unbound().context("creating journald socket")?;
+        socket.connect(JOURNALD_SOCKET).context("connecting to journald")?;
+        sinks.push(AuditSink::Journald(socket));
+    }
+
+    AUDIT_SINKS.set(sinks).ok().context("audit log already initialized")
+}
+
+/// Record an action in the audit log, if one is configured. Failing to record is an error, an
+/// audit trail with holes in it is worse than none at all.
+pub(crate) fn record(action: AuditAction, target: &str, contents: Option<&[u8]>) -> Result<()> {
+    let sinks = match AUDIT_SINKS.get() {
+        Some(sinks) if !sinks.is_empty() => sinks,
+        _ => return Ok(()),
+    };
+
+    let sha256 = contents.map(|contents| {
+        sha2::Sha256::digest(contents)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+    });
+
+    for sink in sinks {
+        match sink {
+            AuditSink::File(file) => {
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64();
+                let mut line = serde_json::to_string(&serde_json::json!({
+                    "timestamp": timestamp,
+                    "action": action.to_string(),
+                    "target": target,
+                    "sha256": sha256,
+                }))?;
+                line.push('\n');
+
+                let mut file = file.lock().ok().context("audit log lock poisoned")?;
+                file.write_all(line.as_bytes()).context("writing audit log")?;
+                file.flush().context("flushing audit log")?;
+            }
+            AuditSink::Journald(socket) => {
+                let mut fields = vec![
+                    ("MESSAGE", format!("{} {}", action, target)),
+                    ("SYSLOG_IDENTIFIER", "recert".to_string()),
+                    ("RECERT_AUDIT_ACTION", action.to_string()),
+                    ("RECERT_AUDIT_TARGET", target.to_string()),
+                ];
+                if let Some(sha256) = &sha256 {
+                    fields.push(("RECERT_AUDIT_SHA256", sha256.clone()));
+                }
+
+                socket
+                    .send(&encode_journald_fields(&fields))
+                    .context("sending audit record to journald")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// See https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+fn encode_journald_fields(fields: &[(&str, String)]) -> Vec<u8> {
+    let mut datagram = vec![];
+
+    for (key, value) in fields {
+        datagram.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            // Values with newlines have to use the length-prefixed binary form
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    }
+
+    datagram
+}
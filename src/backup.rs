@@ -0,0 +1,302 @@
+use crate::{
+    audit::{self, AuditAction},
+    cleanup, file_utils,
+};
+use anyhow::{bail, Context, Result};
+use etcd_client::Client as EtcdClient;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+use zeroize::Zeroizing;
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Something recert was about to modify, along with the name of the file within the backup dir
+/// holding its original contents, or None if it didn't exist before recert created it
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum BackupEntry {
+    File {
+        path: PathBuf,
+        original: Option<String>,
+        mode: Option<u32>,
+    },
+    /// The raw (still encoded) value, exactly as it was in etcd
+    EtcdKey { key: String, original: Option<String> },
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct BackupManifest {
+    entries: Vec<BackupEntry>,
+}
+
+/// The original contents of every file and etcd key recert modifies, taken right before the first
+/// write to each of them, from which everything can be restored with --rollback. The manifest is
+/// rewritten after every new entry, so that a backup of a run which crashed midway is still usable
+struct Backup {
+    dir: PathBuf,
+    manifest: BackupManifest,
+    files: HashSet<PathBuf>,
+    etcd_keys: HashSet<String>,
+}
+
+static BACKUP: OnceLock<Mutex<Backup>> = OnceLock::new();
+
+pub(crate) fn init(dir: Option<PathBuf>) -> Result<()> {
+    if let Some(dir) = dir {
+        BACKUP
+            .set(Mutex::new(Backup::create(&dir)?))
+            .ok()
+            .context("backup already initialized")?;
+        println!("Backing up everything modified to {:?}", dir);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn enabled() -> bool {
+    BACKUP.get().is_some()
+}
+
+fn backup() -> Result<Option<std::sync::MutexGuard<'static, Backup>>> {
+    BACKUP
+        .get()
+        .map(|backup| backup.lock().ok().context("backup lock poisoned"))
+        .transpose()
+}
+
+/// Save the original contents of a file which is about to be written or deleted, unless it was
+/// already saved earlier in the run
+pub(crate) fn backup_file(path: &Path) -> Result<()> {
+    match backup()? {
+        Some(mut backup) => backup.backup_file(path).with_context(|| format!("backing up {:?}", path)),
+        None => Ok(()),
+    }
+}
+
+/// Save the original raw values of etcd keys which are about to be written or deleted, None for
+/// keys which don't exist yet
+pub(crate) fn backup_etcd_keys(originals: Vec<(String, Option<Vec<u8>>)>) -> Result<()> {
+    match backup()? {
+        Some(mut backup) => backup.backup_etcd_keys(originals).context("backing up etcd keys"),
+        None => Ok(()),
+    }
+}
+
+/// Undo everything backed up so far in this run, after committing failed
+pub(crate) async fn rollback_current(etcd_client: &EtcdClient) -> Result<()> {
+    let Some(dir) = backup()?.map(|backup| backup.dir.clone()) else {
+        return Ok(());
+    };
+
+    rollback(&dir, etcd_client).await
+}
+
+/// Restore every file and etcd key in the backup to its original contents, deleting those which
+/// didn't exist before. Keeps going past failures so that as much as possible is restored
+pub(crate) async fn rollback(dir: &Path, etcd_client: &EtcdClient) -> Result<()> {
+    let manifest_path = dir.join(MANIFEST_FILE_NAME);
+    let manifest: BackupManifest =
+        serde_json::from_slice(&std::fs::read(&manifest_path).with_context(|| format!("reading {:?}", manifest_path))?)
+            .with_context(|| format!("parsing {:?}", manifest_path))?;
+
+    let mut failures = 0;
+    for entry in manifest.entries.iter().rev() {
+        let result = match entry {
+            BackupEntry::File { path, .. } => restore_file(dir, entry).with_context(|| format!("restoring {:?}", path)),
+            BackupEntry::EtcdKey { key, original } => restore_etcd_key(dir, etcd_client, key, original.as_deref())
+                .await
+                .with_context(|| format!("restoring etcd key {}", key)),
+        };
+
+        if let Err(err) = result {
+            println!("ERROR: {:#}", err);
+            failures += 1;
+        }
+    }
+
+    if failures > 0 {
+        bail!(
+            "failed to restore {} of the {} backed up files and etcd keys",
+            failures,
+            manifest.entries.len()
+        );
+    }
+
+    println!("Restored {} files and etcd keys from {:?}", manifest.entries.len(), dir);
+
+    Ok(())
+}
+
+fn restore_file(dir: &Path, entry: &BackupEntry) -> Result<()> {
+    let BackupEntry::File { path, original, mode } = entry else {
+        bail!("not a file entry");
+    };
+
+    match original {
+        Some(original) => {
+            let contents = Zeroizing::new(std::fs::read(dir.join(original)).context("reading backed up contents")?);
+            std::fs::write(path, contents.as_slice()).context("writing original contents")?;
+            if let Some(mode) = mode {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(*mode)).context("restoring permissions")?;
+            }
+            audit::record(AuditAction::FileWrite, &path.to_string_lossy(), Some(&contents))
+        }
+        None => {
+            match std::fs::remove_file(path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err).context("removing file created by recert"),
+            }
+            audit::record(AuditAction::FileDelete, &path.to_string_lossy(), None)
+        }
+    }
+}
+
+async fn restore_etcd_key(dir: &Path, etcd_client: &EtcdClient, key: &str, original: Option<&str>) -> Result<()> {
+    match original {
+        Some(original) => {
+            let value = Zeroizing::new(std::fs::read(dir.join(original)).context("reading backed up value")?);
+            etcd_client.kv_client().put(key.as_bytes(), value.to_vec(), None).await?;
+            audit::record(AuditAction::EtcdPut, key, Some(&value))
+        }
+        None => {
+            etcd_client.kv_client().delete(key.as_bytes(), None).await?;
+            audit::record(AuditAction::EtcdDelete, key, None)
+        }
+    }
+}
+
+impl Backup {
+    /// The backup holds private keys, so only its owner gets to read it
+    fn create(dir: &Path) -> Result<Self> {
+        file_utils::create_empty_private_dir(dir, "backup")?;
+
+        let backup = Self {
+            dir: dir.to_path_buf(),
+            manifest: BackupManifest::default(),
+            files: HashSet::new(),
+            etcd_keys: HashSet::new(),
+        };
+        backup.write_manifest()?;
+
+        Ok(backup)
+    }
+
+    fn backup_file(&mut self, path: &Path) -> Result<()> {
+        if !self.files.insert(path.to_path_buf()) {
+            return Ok(());
+        }
+
+        let entry = match std::fs::metadata(path) {
+            Ok(metadata) => BackupEntry::File {
+                path: path.to_path_buf(),
+                original: Some(self.store(&Zeroizing::new(std::fs::read(path).context("reading original contents")?))?),
+                mode: Some(metadata.permissions().mode() & 0o7777),
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BackupEntry::File {
+                path: path.to_path_buf(),
+                original: None,
+                mode: None,
+            },
+            Err(err) => return Err(err).context("reading original permissions"),
+        };
+        self.manifest.entries.push(entry);
+
+        self.write_manifest()
+    }
+
+    fn backup_etcd_keys(&mut self, originals: Vec<(String, Option<Vec<u8>>)>) -> Result<()> {
+        for (key, value) in originals {
+            if !self.etcd_keys.insert(key.clone()) {
+                continue;
+            }
+
+            let value = value.map(Zeroizing::new);
+            let entry = BackupEntry::EtcdKey {
+                original: value.as_deref().map(|value| self.store(value)).transpose()?,
+                key,
+            };
+            self.manifest.entries.push(entry);
+        }
+
+        self.write_manifest()
+    }
+
+    /// Save original contents under the index of the entry they belong to
+    fn store(&self, contents: &[u8]) -> Result<String> {
+        let name = self.manifest.entries.len().to_string();
+        file_utils::write_private(&self.dir.join(&name), contents)?;
+        Ok(name)
+    }
+
+    fn write_manifest(&self) -> Result<()> {
+        let path = self.dir.join(MANIFEST_FILE_NAME);
+
+        // Written to a temporary file first so that a crash never leaves a partially written manifest
+        let temporary_path = path.with_extension("json.tmp");
+        cleanup::register_temporary(&temporary_path)?;
+        file_utils::write_private(&temporary_path, serde_json::to_string_pretty(&self.manifest)?.as_bytes())?;
+        std::fs::rename(&temporary_path, &path).with_context(|| format!("renaming to {:?}", path))?;
+        cleanup::unregister_temporary(&temporary_path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_and_restore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().join("tls.key");
+        let created = dir.path().join("tls-new.key");
+        std::fs::write(&existing, "original").unwrap();
+        std::fs::set_permissions(&existing, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        let backup_dir = dir.path().join("backup");
+        let mut backup = Backup::create(&backup_dir).unwrap();
+        backup.backup_file(&existing).unwrap();
+        backup.backup_file(&created).unwrap();
+        // Only the contents from before the first write matter
+        std::fs::write(&existing, "regenerated").unwrap();
+        backup.backup_file(&existing).unwrap();
+        std::fs::set_permissions(&existing, std::fs::Permissions::from_mode(0o600)).unwrap();
+        std::fs::write(&created, "created").unwrap();
+
+        let manifest: BackupManifest = serde_json::from_slice(&std::fs::read(backup_dir.join(MANIFEST_FILE_NAME)).unwrap()).unwrap();
+        assert_eq!(
+            manifest.entries,
+            vec![
+                BackupEntry::File {
+                    path: existing.clone(),
+                    original: Some("0".to_string()),
+                    mode: Some(0o640),
+                },
+                BackupEntry::File {
+                    path: created.clone(),
+                    original: None,
+                    mode: None,
+                },
+            ]
+        );
+        assert_eq!(std::fs::metadata(backup_dir.join("0")).unwrap().permissions().mode() & 0o777, 0o600);
+
+        for entry in &manifest.entries {
+            restore_file(&backup_dir, entry).unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), "original");
+        assert_eq!(std::fs::metadata(&existing).unwrap().permissions().mode() & 0o777, 0o640);
+        assert!(!created.exists());
+
+        // Refuses to overwrite the backup it just took
+        assert!(Backup::create(&backup_dir).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use anyhow::{bail, ensure, Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Instant,
+};
+use tokio::sync::Semaphore;
+
+/// A manifest of independent recert jobs, e.g. one per appliance being imaged:
+///
+///   jobs:
+///     - name: appliance-1
+///       etcd-endpoint: localhost:2379
+///       static-dirs: [/mnt/appliance-1/etc/kubernetes]
+///       cn-san-replace: ["api.seed.com api.appliance-1.com"]
+///       cluster-rename: appliance-1,example.com
+///       extra-args: [--strict-rules]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BatchManifest {
+    jobs: Vec<BatchJob>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct BatchJob {
+    /// Identifies the job in the summary, and names its report files
+    name: String,
+    etcd_endpoint: String,
+    #[serde(default)]
+    static_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    cn_san_replace: Vec<String>,
+    cluster_rename: Option<String>,
+    /// Any other recert flags, passed as-is
+    #[serde(default)]
+    extra_args: Vec<String>,
+}
+
+impl BatchJob {
+    fn args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["--etcd-endpoint".into(), self.etcd_endpoint.clone().into()];
+
+        for static_dir in &self.static_dirs {
+            args.extend(["--static-dir".into(), static_dir.into()]);
+        }
+
+        for rule in &self.cn_san_replace {
+            args.extend(["--cn-san-replace".into(), rule.into()]);
+        }
+
+        if let Some(cluster_rename) = &self.cluster_rename {
+            args.extend(["--cluster-rename".into(), cluster_rename.into()]);
+        }
+
+        args.extend(self.extra_args.iter().map(OsString::from));
+
+        args
+    }
+}
+
+impl BatchManifest {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let manifest: Self = serde_yaml::from_str(&std::fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?)
+            .context("parsing manifest")?;
+
+        let mut names = HashSet::new();
+        for job in &manifest.jobs {
+            ensure!(
+                !job.name.is_empty() && !job.name.contains('/') && job.name != "." && job.name != "..",
+                "invalid job name {:?}, it's used as a file name",
+                job.name
+            );
+            ensure!(names.insert(&job.name), "duplicate job name {:?}", job.name);
+        }
+
+        Ok(manifest)
+    }
+}
+
+struct JobResult {
+    name: String,
+    success: bool,
+    exit_code: Option<i32>,
+    seconds: f64,
+    log: PathBuf,
+}
+
+/// Run all the jobs in the manifest concurrently (at most max_parallel at a time, if given).
+/// Every job is a separate recert process, so jobs can't interfere with each other. Each job's
+/// output goes to <report_dir>/<name>.log, and a summary of all jobs is printed and written to
+/// <report_dir>/summary.json. Fails if any of the jobs failed.
+pub(crate) async fn run(manifest: BatchManifest, report_dir: &Path, max_parallel: Option<usize>) -> Result<()> {
+    let recert = std::env::current_exe().context("finding recert executable")?;
+    run_with_executable(&recert, manifest, report_dir, max_parallel).await
+}
+
+async fn run_with_executable(executable: &Path, manifest: BatchManifest, report_dir: &Path, max_parallel: Option<usize>) -> Result<()> {
+    std::fs::create_dir_all(report_dir).with_context(|| format!("creating report dir {:?}", report_dir))?;
+
+    let semaphore = Arc::new(Semaphore::new(max_parallel.unwrap_or(Semaphore::MAX_PERMITS).max(1)));
+
+    let results = futures_util::future::join_all(manifest.jobs.iter().map(|job| {
+        let semaphore = Arc::clone(&semaphore);
+        async move {
+            let _permit = semaphore.acquire().await?;
+            run_job(executable, job, report_dir)
+                .await
+                .with_context(|| format!("job {}", job.name))
+        }
+    }))
+    .await
+    .into_iter()
+    .collect::<Result<Vec<_>>>()?;
+
+    let summary = serde_json::json!({
+        "jobs": results.iter().map(|result| serde_json::json!({
+            "name": result.name,
+            "success": result.success,
+            "exit_code": result.exit_code,
+            "seconds": result.seconds,
+            "log": result.log,
+        })).collect::<Vec<_>>(),
+        "succeeded": results.iter().filter(|result| result.success).count(),
+        "failed": results.iter().filter(|result| !result.success).count(),
+    });
+
+    let summary_path = report_dir.join("summary.json");
+    std::fs::write(&summary_path, serde_json::to_string_pretty(&summary)?).with_context(|| format!("writing {:?}", summary_path))?;
+
+    println!("Batch summary:");
+    for result in &results {
+        println!(
+            "- {}: {} in {:.1}s, see {:?}",
+            result.name,
+            if result.success { "succeeded" } else { "FAILED" },
+            result.seconds,
+            result.log
+        );
+    }
+
+    let failed = results.iter().filter(|result| !result.success).count();
+    if failed > 0 {
+        bail!("{} of {} jobs failed", failed, results.len());
+    }
+
+    Ok(())
+}
+
+async fn run_job(executable: &Path, job: &BatchJob, report_dir: &Path) -> Result<JobResult> {
+    let log = report_dir.join(format!("{}.log", job.name));
+    let log_file = std::fs::File::create(&log).with_context(|| format!("creating {:?}", log))?;
+
+    println!("Starting job {}", job.name);
+    let start = Instant::now();
+
+    let status = tokio::process::Command::new(executable)
+        .args(job.args())
+        .stdin(Stdio::null())
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file)
+        .status()
+        .await
+        .context("running recert")?;
+
+    Ok(JobResult {
+        name: job.name.clone(),
+        success: status.success(),
+        exit_code: status.code(),
+        seconds: start.elapsed().as_secs_f64(),
+        log,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+jobs:
+  - name: appliance-1
+    etcd-endpoint: localhost:2379
+    static-dirs: [/a, /b]
+    cn-san-replace: ["api.seed.com api.appliance-1.com"]
+    cluster-rename: appliance-1,example.com
+    extra-args: [--strict-rules]
+  - name: appliance-2
+    etcd-endpoint: localhost:2380
+"#;
+
+    fn load(contents: &str) -> Result<BatchManifest> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clusters.yaml");
+        std::fs::write(&path, contents).unwrap();
+        BatchManifest::load(&path)
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest = load(MANIFEST).unwrap();
+
+        assert_eq!(
+            manifest.jobs[0].args(),
+            [
+                "--etcd-endpoint",
+                "localhost:2379",
+                "--static-dir",
+                "/a",
+                "--static-dir",
+                "/b",
+                "--cn-san-replace",
+                "api.seed.com api.appliance-1.com",
+                "--cluster-rename",
+                "appliance-1,example.com",
+                "--strict-rules",
+            ]
+        );
+        assert_eq!(manifest.jobs[1].args(), ["--etcd-endpoint", "localhost:2380"]);
+
+        assert!(load("jobs: [{name: a, etcd-endpoint: x}, {name: a, etcd-endpoint: y}]").is_err());
+        assert!(load("jobs: [{name: ../a, etcd-endpoint: x}]").is_err());
+        assert!(load("jobs: [{name: a, etcd-endpoint: x, typo: y}]").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let report_dir = tempfile::tempdir().unwrap();
+
+        // echo stands in for recert, so we can check what each job was run with
+        run_with_executable(Path::new("echo"), load(MANIFEST).unwrap(), report_dir.path(), Some(1))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(report_dir.path().join("appliance-2.log")).unwrap(),
+            "--etcd-endpoint localhost:2380\n"
+        );
+
+        let summary: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(report_dir.path().join("summary.json")).unwrap()).unwrap();
+        assert_eq!(summary["succeeded"], 2);
+        assert_eq!(summary["failed"], 0);
+
+        assert!(
+            run_with_executable(Path::new("false"), load(MANIFEST).unwrap(), report_dir.path(), None)
+                .await
+                .is_err()
+        );
+        let summary: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(report_dir.path().join("summary.json")).unwrap()).unwrap();
+        assert_eq!(summary["failed"], 2);
+    }
+}
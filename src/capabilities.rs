@@ -0,0 +1,175 @@
+use crate::{
+    cluster_crypto::locations::K8sResourceLocation,
+    k8s_etcd::{get_etcd_yaml, InMemoryK8sEtcd},
+};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use strum::IntoEnumIterator;
+use version::VersionBehavior;
+pub(crate) use version::{ExpectedCryptoObject, OcpVersion};
+
+mod version;
+
+/// Optional cluster components which some discovery / postprocess steps depend on. Compact
+/// clusters and clusters installed with some capabilities disabled don't have all of them, in
+/// which case the steps which depend on them are skipped rather than failing the entire run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, strum_macros::Display, strum_macros::EnumIter)]
+pub(crate) enum Capability {
+    Authentication,
+    Console,
+    Ingress,
+    MachineConfig,
+    Monitoring,
+    Multus,
+    OperatorLifecycleManager,
+    OvnKubernetes,
+}
+
+impl Capability {
+    /// The namespace whose presence indicates the component is installed
+    fn namespace(&self) -> &'static str {
+        match self {
+            Capability::Authentication => "openshift-authentication",
+            Capability::Console => "openshift-console",
+            Capability::Ingress => "openshift-ingress",
+            Capability::MachineConfig => "openshift-machine-config-operator",
+            Capability::Monitoring => "openshift-monitoring",
+            Capability::Multus => "openshift-multus",
+            Capability::OperatorLifecycleManager => "openshift-operator-lifecycle-manager",
+            Capability::OvnKubernetes => "openshift-ovn-kubernetes",
+        }
+    }
+}
+
+/// What's installed in the cluster and which version it is
+#[derive(Clone, Debug)]
+pub(crate) struct Capabilities {
+    capabilities: HashSet<Capability>,
+    version: Option<OcpVersion>,
+}
+
+impl Capabilities {
+    /// Detect the capabilities and version of the cluster. The detected version can be overridden,
+    /// e.g. for clusters whose ClusterVersion is missing or can't be trusted
+    pub(crate) async fn detect(etcd_client: &InMemoryK8sEtcd, version_override: Option<OcpVersion>) -> Result<Self> {
+        let namespace_keys = etcd_client.list_keys("namespaces/").await?.into_iter().collect::<HashSet<_>>();
+
+        let version = match version_override {
+            Some(version) => Some(version),
+            None => detect_version(etcd_client).await.context("detecting cluster version")?,
+        };
+
+        Ok(Self {
+            capabilities: Self::from_namespace_keys(&namespace_keys),
+            version,
+        })
+    }
+
+    fn from_namespace_keys(namespace_keys: &HashSet<String>) -> HashSet<Capability> {
+        Capability::iter()
+            .filter(|capability| namespace_keys.contains(&format!("/kubernetes.io/namespaces/{}", capability.namespace())))
+            .collect()
+    }
+
+    pub(crate) fn has(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// The detected (or overridden) version, None if it's unknown
+    pub(crate) fn version(&self) -> Option<OcpVersion> {
+        self.version
+    }
+
+    /// Version specific behavior switches, see version::BEHAVIORS
+    pub(crate) fn behavior(&self) -> &'static VersionBehavior {
+        version::behavior_for(self.version)
+    }
+
+    /// Whether the step which depends on the given capability should run. If it shouldn't, the
+    /// skip is reported
+    pub(crate) fn allows(&self, capability: Capability, step: &str) -> bool {
+        if !self.has(capability) {
+            println!(
+                "Skipping {}, {} is not installed ({} namespace not found)",
+                step,
+                capability,
+                capability.namespace()
+            );
+        }
+
+        self.has(capability)
+    }
+
+    pub(crate) fn report(&self) {
+        match self.version {
+            Some(version) => println!("- Version: {} (using behavior of {}+)", version, self.behavior().min_version),
+            None => println!("- Version: unknown (using behavior of {}+)", self.behavior().min_version),
+        }
+
+        for capability in Capability::iter() {
+            println!("- {}: {}", capability, if self.has(capability) { "present" } else { "absent" });
+        }
+    }
+}
+
+/// The version of the cluster according to its ClusterVersion, which is the version of the last
+/// completed update (or install), or the one being installed if none has completed yet
+async fn detect_version(etcd_client: &InMemoryK8sEtcd) -> Result<Option<OcpVersion>> {
+    let k8s_resource_location = K8sResourceLocation::new(None, "ClusterVersion", "version", "config.openshift.io/v1");
+
+    if etcd_client
+        .list_keys("config.openshift.io/clusterversions/version")
+        .await?
+        .is_empty()
+    {
+        return Ok(None);
+    }
+
+    let cluster_version = get_etcd_yaml(etcd_client, &k8s_resource_location).await?;
+
+    let completed = cluster_version
+        .pointer("/status/history")
+        .and_then(|history| history.as_array())
+        .and_then(|history| {
+            history
+                .iter()
+                .find(|entry| entry.pointer("/state").and_then(|state| state.as_str()) == Some("Completed"))
+        })
+        .and_then(|entry| entry.pointer("/version"));
+
+    match completed.or(cluster_version.pointer("/status/desired/version")) {
+        Some(version) => Ok(Some(version.as_str().context("version not a string")?.parse()?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_namespace_keys() {
+        let capabilities = Capabilities {
+            version: None,
+            capabilities: Capabilities::from_namespace_keys(
+                &[
+                    "/kubernetes.io/namespaces/openshift-authentication",
+                    "/kubernetes.io/namespaces/openshift-ingress",
+                    // Only an exact match counts
+                    "/kubernetes.io/namespaces/openshift-console-operator",
+                    "/kubernetes.io/namespaces/openshift-monitoring-foo",
+                ]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            ),
+        };
+
+        assert!(capabilities.has(Capability::Authentication));
+        assert!(capabilities.has(Capability::Ingress));
+        assert!(!capabilities.has(Capability::Console));
+        assert!(!capabilities.has(Capability::Monitoring));
+        assert!(!capabilities.allows(Capability::OperatorLifecycleManager, "fixing olm"));
+        assert!(capabilities.allows(Capability::Ingress, "fixing router"));
+    }
+}
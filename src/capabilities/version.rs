@@ -0,0 +1,244 @@
+use super::Capability;
+use anyhow::{bail, Context, Result};
+use std::{fmt::Display, str::FromStr};
+
+/// An OCP minor release, e.g. 4.13. Patch versions don't change the crypto/resource layout
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct OcpVersion {
+    pub(crate) major: u32,
+    pub(crate) minor: u32,
+}
+
+impl OcpVersion {
+    pub(crate) const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl Display for OcpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for OcpVersion {
+    type Err = anyhow::Error;
+
+    /// Parses ClusterVersion versions such as 4.13.5, 4.14.0-rc.1 or 4.15.0-0.nightly-2023-10-01
+    fn from_str(version: &str) -> Result<Self> {
+        let mut components = version.split(['.', '-']);
+
+        let major = components
+            .next()
+            .context("missing major version")?
+            .parse()
+            .context("parsing major version")?;
+        let minor = components
+            .next()
+            .context("missing minor version")?
+            .parse()
+            .context("parsing minor version")?;
+
+        if major != 4 {
+            bail!("unsupported major version {} in {:?}", major, version);
+        }
+
+        Ok(Self::new(major, minor))
+    }
+}
+
+/// Everything recert does differently depending on the version of the cluster. Each entry in
+/// BEHAVIORS applies from its min_version until the min_version of the next one. Add a new entry
+/// (copying the previous one and changing what's different) whenever a release changes something
+/// recert depends on.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct VersionBehavior {
+    pub(crate) min_version: OcpVersion,
+    /// Monitoring routes whose hosts include the cluster domain
+    pub(crate) monitoring_routes: &'static [&'static str],
+    /// OVN-Kubernetes DaemonSets (and their container) that point at the API server. Before
+    /// OVN-Kubernetes interconnect (4.14), the control plane ran as the ovnkube-master DaemonSet
+    pub(crate) ovn_kubernetes_daemonsets: &'static [(&'static str, &'static str)],
+    /// The crypto objects every cluster has, see --strict-expected-set
+    pub(crate) expected_crypto_objects: &'static [ExpectedCryptoObject],
+}
+
+/// A crypto object (usually a CA's key) a healthy cluster always has, identified by the secret
+/// data entry holding it
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ExpectedCryptoObject {
+    pub(crate) description: &'static str,
+    pub(crate) namespace: &'static str,
+    pub(crate) secret: &'static str,
+    pub(crate) data_key: &'static str,
+    /// Only expected when the component is installed
+    pub(crate) capability: Option<Capability>,
+}
+
+impl ExpectedCryptoObject {
+    const fn new(description: &'static str, namespace: &'static str, secret: &'static str, data_key: &'static str) -> Self {
+        Self {
+            description,
+            namespace,
+            secret,
+            data_key,
+            capability: None,
+        }
+    }
+
+    const fn with_capability(self, capability: Capability) -> Self {
+        Self {
+            capability: Some(capability),
+            ..self
+        }
+    }
+}
+
+/// The signers a cluster can't do without, whatever its version
+const EXPECTED_CRYPTO_OBJECTS: &[ExpectedCryptoObject] = &[
+    ExpectedCryptoObject::new("etcd signer", "openshift-config", "etcd-signer", "tls.key"),
+    ExpectedCryptoObject::new("etcd metrics signer", "openshift-config", "etcd-metric-signer", "tls.key"),
+    ExpectedCryptoObject::new("service CA", "openshift-service-ca", "signing-key", "tls.key"),
+    ExpectedCryptoObject::new(
+        "service account token signer",
+        "openshift-kube-controller-manager",
+        "service-account-private-key",
+        "service-account.key",
+    ),
+    ExpectedCryptoObject::new(
+        "bound service account token signer",
+        "openshift-kube-apiserver-operator",
+        "next-bound-service-account-signing-key",
+        "service-account.key",
+    ),
+    ExpectedCryptoObject::new(
+        "kube-apiserver to kubelet signer",
+        "openshift-kube-apiserver-operator",
+        "kube-apiserver-to-kubelet-signer",
+        "tls.key",
+    ),
+    ExpectedCryptoObject::new(
+        "kube control plane signer",
+        "openshift-kube-apiserver-operator",
+        "kube-control-plane-signer",
+        "tls.key",
+    ),
+    ExpectedCryptoObject::new(
+        "aggregator client signer",
+        "openshift-kube-apiserver-operator",
+        "aggregator-client-signer",
+        "tls.key",
+    ),
+    ExpectedCryptoObject::new(
+        "localhost serving signer",
+        "openshift-kube-apiserver-operator",
+        "localhost-serving-signer",
+        "tls.key",
+    ),
+    ExpectedCryptoObject::new(
+        "service network serving signer",
+        "openshift-kube-apiserver-operator",
+        "service-network-serving-signer",
+        "tls.key",
+    ),
+    ExpectedCryptoObject::new(
+        "load balancer serving signer",
+        "openshift-kube-apiserver-operator",
+        "loadbalancer-serving-signer",
+        "tls.key",
+    ),
+    ExpectedCryptoObject::new(
+        "CSR signer",
+        "openshift-kube-controller-manager-operator",
+        "csr-signer-signer",
+        "tls.key",
+    ),
+    ExpectedCryptoObject::new("ingress CA", "openshift-ingress-operator", "router-ca", "tls.key").with_capability(Capability::Ingress),
+    // The cluster network operator's CAs for OVN's own connections (northbound / southbound
+    // databases) and for signing the ovnkube-node IPsec certs
+    ExpectedCryptoObject::new("OVN CA", "openshift-ovn-kubernetes", "ovn-ca", "tls.key").with_capability(Capability::OvnKubernetes),
+    ExpectedCryptoObject::new("OVN signer", "openshift-ovn-kubernetes", "signer-ca", "tls.key").with_capability(Capability::OvnKubernetes),
+];
+
+const BEHAVIORS: &[VersionBehavior] = &[
+    VersionBehavior {
+        min_version: OcpVersion::new(4, 0),
+        monitoring_routes: &["alertmanager-main", "prometheus-k8s", "thanos-querier"],
+        ovn_kubernetes_daemonsets: &[("ovnkube-master", "ovnkube-master"), ("ovnkube-node", "ovnkube-node")],
+        expected_crypto_objects: EXPECTED_CRYPTO_OBJECTS,
+    },
+    VersionBehavior {
+        min_version: OcpVersion::new(4, 12),
+        monitoring_routes: &["alertmanager-main", "prometheus-k8s", "prometheus-k8s-federate", "thanos-querier"],
+        ovn_kubernetes_daemonsets: &[("ovnkube-master", "ovnkube-master"), ("ovnkube-node", "ovnkube-node")],
+        expected_crypto_objects: EXPECTED_CRYPTO_OBJECTS,
+    },
+    VersionBehavior {
+        min_version: OcpVersion::new(4, 14),
+        monitoring_routes: &["alertmanager-main", "prometheus-k8s", "prometheus-k8s-federate", "thanos-querier"],
+        ovn_kubernetes_daemonsets: &[("ovnkube-node", "ovnkube-node")],
+        expected_crypto_objects: EXPECTED_CRYPTO_OBJECTS,
+    },
+];
+
+/// The behavior for the given version. When the version couldn't be detected, the behavior of the
+/// latest version is assumed
+pub(crate) fn behavior_for(version: Option<OcpVersion>) -> &'static VersionBehavior {
+    let latest = BEHAVIORS.last().expect("no behaviors");
+
+    match version {
+        Some(version) => BEHAVIORS
+            .iter()
+            .rev()
+            .find(|behavior| behavior.min_version <= version)
+            .unwrap_or(latest),
+        None => latest,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("4.13.5".parse::<OcpVersion>().unwrap(), OcpVersion::new(4, 13));
+        assert_eq!("4.14.0-rc.1".parse::<OcpVersion>().unwrap(), OcpVersion::new(4, 14));
+        assert_eq!(
+            "4.15.0-0.nightly-2023-10-01-000000".parse::<OcpVersion>().unwrap(),
+            OcpVersion::new(4, 15)
+        );
+        assert!("3.11.0".parse::<OcpVersion>().is_err());
+        assert!("garbage".parse::<OcpVersion>().is_err());
+    }
+
+    #[test]
+    fn test_behavior_for() {
+        assert!(BEHAVIORS.windows(2).all(|pair| pair[0].min_version < pair[1].min_version));
+
+        assert_eq!(behavior_for(Some(OcpVersion::new(4, 11))).min_version, OcpVersion::new(4, 0));
+        assert_eq!(behavior_for(Some(OcpVersion::new(4, 12))).min_version, OcpVersion::new(4, 12));
+        assert_eq!(behavior_for(Some(OcpVersion::new(4, 13))).min_version, OcpVersion::new(4, 12));
+        assert_eq!(behavior_for(Some(OcpVersion::new(4, 16))).min_version, OcpVersion::new(4, 14));
+        assert_eq!(behavior_for(None), BEHAVIORS.last().unwrap());
+    }
+
+    /// The latest release and the two before it must all be explicitly handled
+    #[test]
+    fn test_legacy_layouts() {
+        let latest = BEHAVIORS.last().unwrap().min_version;
+        let n_minus_2 = OcpVersion::new(latest.major, latest.minor - 2);
+
+        assert!(BEHAVIORS.iter().any(|behavior| behavior.min_version <= n_minus_2));
+
+        let legacy = behavior_for(Some(n_minus_2));
+        assert!(legacy.monitoring_routes.contains(&"prometheus-k8s-federate"));
+        assert!(legacy.ovn_kubernetes_daemonsets.contains(&("ovnkube-master", "ovnkube-master")));
+
+        let n_minus_1 = behavior_for(Some(OcpVersion::new(latest.major, latest.minor - 1)));
+        assert!(n_minus_1.ovn_kubernetes_daemonsets.contains(&("ovnkube-master", "ovnkube-master")));
+
+        let current = behavior_for(Some(latest));
+        assert_eq!(current.ovn_kubernetes_daemonsets, &[("ovnkube-node", "ovnkube-node")]);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::cluster_crypto::{
+    locations::{Location, Locations},
+    ClusterCryptoObjects,
+};
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, strum_macros::Display)]
+#[strum(serialize_all = "kebab-case")]
+pub(crate) enum PlannedAction {
+    Replace,
+    /// A flattened intermediate taken out of a bundle
+    Remove,
+}
+
+/// A single crypto object which would be rewritten at a location within an etcd resource or file
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct PlannedChange {
+    /// Where within the resource or file, e.g. /data/tls.crt:pem0(leaf)
+    location: String,
+    action: PlannedAction,
+    object: String,
+}
+
+/// Everything committing the regenerated crypto objects would rewrite, grouped by etcd key and by
+/// file path. This is what --dry-run produces instead of committing anything
+#[derive(Default)]
+pub(crate) struct ChangePlan {
+    etcd_keys: BTreeMap<String, BTreeSet<PlannedChange>>,
+    files: BTreeMap<String, BTreeSet<PlannedChange>>,
+}
+
+impl ChangePlan {
+    /// Follows ClusterCryptoObjects::commit_to_etcd_and_disk, recording locations instead of
+    /// writing to them
+    pub(crate) fn new(cluster_crypto: &ClusterCryptoObjects) -> Self {
+        let mut plan = Self::default();
+
+        // Everything is regenerated, unless only rotating what's about to expire
+        for cert_key_pair in &cluster_crypto.cert_key_pairs {
+            let cert_key_pair = (**cert_key_pair).borrow();
+            if !cert_key_pair.regenerated {
+                continue;
+            }
+            let subject = (*cert_key_pair.distributed_cert).borrow().certificate.subject.clone();
+            plan.add(
+                &(*cert_key_pair.distributed_cert).borrow().locations,
+                PlannedAction::Replace,
+                &format!("cert {}", subject),
+            );
+            if let Some(private_key) = &cert_key_pair.distributed_private_key {
+                plan.add(
+                    &(**private_key).borrow().locations,
+                    PlannedAction::Replace,
+                    &format!("private key of {}", subject),
+                );
+            }
+        }
+
+        for jwt in cluster_crypto.distributed_jwts.values() {
+            let jwt = (**jwt).borrow();
+            if jwt.regenerated {
+                plan.add(&jwt.locations, PlannedAction::Replace, "jwt");
+            }
+        }
+
+        for crl in cluster_crypto.distributed_crls.values() {
+            let crl = (**crl).borrow();
+            if crl.regenerated {
+                plan.add(&crl.locations, PlannedAction::Replace, &format!("crl of {}", crl.crl.issuer));
+            }
+        }
+
+        for private_key in cluster_crypto.distributed_private_keys.values() {
+            let private_key = (**private_key).borrow();
+            if private_key.regenerated {
+                plan.add(&private_key.locations, PlannedAction::Replace, "standalone private key");
+            }
+        }
+
+        for public_key in cluster_crypto.distributed_public_keys.values() {
+            let public_key = (**public_key).borrow();
+            if public_key.regenerated {
+                plan.add(&public_key.locations, PlannedAction::Replace, "public key");
+            }
+        }
+
+        for flattened_intermediate in &cluster_crypto.flattened_intermediates {
+            let flattened_intermediate = (**flattened_intermediate).borrow();
+            let distributed_cert = (*flattened_intermediate.distributed_cert).borrow();
+            plan.add(
+                &distributed_cert.locations,
+                PlannedAction::Remove,
+                &format!("cert {}", distributed_cert.certificate.subject),
+            );
+        }
+
+        plan
+    }
+
+    fn add(&mut self, locations: &Locations, action: PlannedAction, object: &str) {
+        for location in &locations.0 {
+            let (changes, location) = match location {
+                Location::K8s(k8s_location) => (
+                    self.etcd_keys.entry(k8s_location.resource_location.as_etcd_key()).or_default(),
+                    k8s_location.yaml_location.to_string(),
+                ),
+                Location::Filesystem(file_location) => (
+                    self.files.entry(file_location.path.clone()).or_default(),
+                    file_location.content_location.to_string(),
+                ),
+            };
+
+            changes.insert(PlannedChange {
+                location: location.trim_start_matches(':').to_string(),
+                action,
+                object: object.to_string(),
+            });
+        }
+    }
+
+    pub(crate) fn render(&self) -> Result<String> {
+        let render_changes = |changes: &BTreeMap<String, BTreeSet<PlannedChange>>| {
+            changes
+                .iter()
+                .map(|(target, changes)| {
+                    (
+                        target.clone(),
+                        serde_json::Value::Array(
+                            changes
+                                .iter()
+                                .map(|change| {
+                                    serde_json::json!({
+                                        "location": change.location,
+                                        "action": change.action.to_string(),
+                                        "object": change.object,
+                                    })
+                                })
+                                .collect(),
+                        ),
+                    )
+                })
+                .collect::<serde_json::Map<String, serde_json::Value>>()
+        };
+
+        Ok(serde_json::to_string_pretty(&serde_json::json!({
+            "etcd_keys": render_changes(&self.etcd_keys),
+            "files": render_changes(&self.files),
+        }))?)
+    }
+
+    /// Write the plan as JSON to the given path, or to stdout if there is none
+    pub(crate) fn emit(&self, path: Option<&Path>) -> Result<()> {
+        let rendered = self.render()?;
+
+        match path {
+            Some(path) => std::fs::write(path, rendered).with_context(|| format!("writing change plan to {:?}", path))?,
+            None => println!("{}", rendered),
+        }
+
+        println!(
+            "Dry run: {} etcd keys and {} files would be rewritten, nothing was committed",
+            self.etcd_keys.len(),
+            self.files.len()
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster_crypto::{
+            crypto_objects,
+            locations::{FileContentLocation, FileLocation, LocationValueType},
+            private_key_format::PrivateKeyPolicy,
+        },
+        cnsanreplace::CnSanReplaceRules,
+        rsa_key_pool::RsaKeyPool,
+        test_fixtures::CertFixture,
+    };
+
+    #[tokio::test]
+    async fn test_change_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = CertFixture::ca("root");
+        let leaf = CertFixture::leaf("leaf", &ca);
+        std::fs::write(dir.path().join("ca.crt"), &ca.cert_pem).unwrap();
+        std::fs::write(dir.path().join("ca.key"), &ca.key_pem).unwrap();
+        std::fs::write(dir.path().join("bundle.crt"), format!("{}{}", leaf.cert_pem, ca.cert_pem)).unwrap();
+
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        for file_name in ["ca.crt", "ca.key", "bundle.crt"] {
+            let path = dir.path().join(file_name);
+            cluster_crypto.register_discovered_crypto_objects(
+                crypto_objects::process_pem_bundle(
+                    &std::fs::read_to_string(&path).unwrap(),
+                    &Location::Filesystem(FileLocation {
+                        path: path.to_string_lossy().to_string(),
+                        content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+                    }),
+                    &PrivateKeyPolicy::default(),
+                )
+                .unwrap(),
+            );
+        }
+        cluster_crypto.pair_certs_and_keys().unwrap();
+        cluster_crypto.fill_cert_key_signers().unwrap();
+        cluster_crypto.fill_signees().unwrap();
+        cluster_crypto
+            .regenerate_crypto(
+                RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                CnSanReplaceRules::try_from(vec![]).unwrap(),
+            )
+            .unwrap();
+
+        let plan = ChangePlan::new(&cluster_crypto);
+        assert!(plan.etcd_keys.is_empty());
+
+        let planned = |file_name: &str| {
+            plan.files[&dir.path().join(file_name).to_string_lossy().to_string()]
+                .iter()
+                .map(|change| (change.location.as_str(), change.action, change.object.as_str()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(planned("ca.crt"), vec![("pem0", PlannedAction::Replace, "cert CN=root")]);
+        assert_eq!(planned("ca.key"), vec![("pem0", PlannedAction::Replace, "private key of CN=root")]);
+        assert_eq!(
+            planned("bundle.crt"),
+            vec![
+                ("pem0(leaf)", PlannedAction::Replace, "cert CN=leaf"),
+                ("pem1(chain)", PlannedAction::Replace, "cert CN=root"),
+            ]
+        );
+
+        let rendered: serde_json::Value = serde_json::from_str(&plan.render().unwrap()).unwrap();
+        assert_eq!(
+            rendered["files"][dir.path().join("ca.key").to_string_lossy().as_ref()][0]["action"],
+            "replace"
+        );
+    }
+}
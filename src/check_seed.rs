@@ -0,0 +1,336 @@
+use crate::{
+    capabilities::{Capabilities, OcpVersion},
+    cluster_crypto::{
+        crypto_objects::CryptoObject,
+        keys::PublicKey,
+        resource_kinds::ResourceKindPolicy,
+        scanning,
+        weak_crypto::{self, Weakness},
+        ClusterCryptoObjects, CryptoPolicies,
+    },
+    etcd_snapshot::EtcdSnapshot,
+    k8s_etcd::{EtcdAccess, InMemoryK8sEtcd},
+    namespace_filter::NamespaceFilter,
+    rsa_key_pool::MAX_RSA_KEY_SIZE,
+};
+use anyhow::{bail, ensure, Context, Result};
+use etcd_client::GetOptions;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// How a value is stored in etcd, see storage_encoding
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum StorageEncoding {
+    Protobuf,
+    Json,
+    /// By the kube-apiserver's encryption at rest, with the given provider and version, e.g.
+    /// aescbc:v1
+    Encrypted(String),
+    Unknown,
+}
+
+impl Display for StorageEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageEncoding::Protobuf => write!(f, "protobuf"),
+            StorageEncoding::Json => write!(f, "JSON"),
+            StorageEncoding::Encrypted(provider) => write!(f, "encrypted with {}", provider),
+            StorageEncoding::Unknown => write!(f, "an unknown encoding"),
+        }
+    }
+}
+
+/// The encryption at rest providers recert can decrypt and encrypt again, see etcd_encryption
+const SUPPORTED_ENCRYPTION_PROVIDERS: [&str; 3] = ["aescbc:v1", "aesgcm:v1", "kms:v1"];
+
+/// What was found about the seed. Requirements are what the run has to be configured with (or
+/// will do differently) because of the seed, blockers are what this build of recert can't handle
+/// at all
+#[derive(Debug, Default)]
+struct SeedReport {
+    facts: Vec<String>,
+    requirements: Vec<String>,
+    blockers: Vec<String>,
+}
+
+/// Inspect a seed (its etcd or etcd snapshot, and its static dirs) without modifying anything,
+/// and report whether this build of recert can recertify it and what the run would require.
+/// Fails if anything can't be handled. Encrypted values are read with the encryption config of the
+/// etcd access, if any
+pub(crate) async fn check_seed(
+    etcd_endpoint: Option<&str>,
+    etcd_access: &EtcdAccess,
+    etcd_snapshot: Option<&Path>,
+    static_dirs: Vec<PathBuf>,
+    ocp_version: Option<OcpVersion>,
+) -> Result<()> {
+    let mut report = SeedReport::default();
+
+    let in_memory_etcd_client = match (etcd_endpoint, etcd_snapshot) {
+        (Some(etcd_endpoint), None) => {
+            let etcd_client = etcd_access.connect(etcd_endpoint).await?;
+            let values = etcd_client
+                .kv_client()
+                .get("/kubernetes.io/", Some(GetOptions::new().with_prefix()))
+                .await
+                .context("listing etcd values")?;
+            check_storage(
+                values.kvs().iter().map(|kv| kv.value()),
+                etcd_access.encryption.is_some(),
+                &mut report,
+            );
+            InMemoryK8sEtcd::new(etcd_client, etcd_access, NamespaceFilter::default(), ResourceKindPolicy::default())
+        }
+        (None, Some(etcd_snapshot)) => {
+            let snapshot = EtcdSnapshot::open(etcd_snapshot).context("opening etcd snapshot")?;
+            let values = snapshot
+                .list_keys("/kubernetes.io/")
+                .iter()
+                .map(|key| snapshot.get(key)?.with_context(|| format!("{} vanished", key)))
+                .collect::<Result<Vec<_>>>()?;
+            check_storage(values.iter().map(Vec::as_slice), etcd_access.encryption.is_some(), &mut report);
+            // Only ever read from, never committed, so there's nothing to write out
+            InMemoryK8sEtcd::from_snapshot(
+                snapshot,
+                PathBuf::new(),
+                etcd_access,
+                NamespaceFilter::default(),
+                ResourceKindPolicy::default(),
+            )
+        }
+        _ => bail!("exactly one of --etcd-endpoint and --etcd-snapshot is required"),
+    };
+
+    check_static_dirs(&static_dirs, &mut report);
+
+    // Scanning needs every value decoded, which none of the blockers so far allow
+    if report.blockers.is_empty() {
+        let in_memory_etcd_client = Arc::new(in_memory_etcd_client);
+        let capabilities = Capabilities::detect(&in_memory_etcd_client, ocp_version)
+            .await
+            .context("detecting cluster capabilities")?;
+        match capabilities.version() {
+            Some(version) => report.facts.push(format!(
+                "OCP version {}, handled with the behavior of {}+",
+                version,
+                capabilities.behavior().min_version
+            )),
+            None => report.requirements.push(format!(
+                "The cluster's version can't be detected, it's handled with the behavior of {}+ unless --ocp-version says otherwise",
+                capabilities.behavior().min_version
+            )),
+        }
+
+        println!("Scanning etcd/filesystem... This might take a while");
+        let discovered_crypto_objects = scanning::crypto_scan(in_memory_etcd_client, static_dirs, capabilities, CryptoPolicies::default())
+            .await
+            .context("scanning")?;
+        check_keys(
+            discovered_crypto_objects.iter().map(|discovered| &discovered.crypto_object),
+            &mut report,
+        )?;
+
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        cluster_crypto.register_discovered_crypto_objects(discovered_crypto_objects);
+        cluster_crypto.pair_certs_and_keys().context("pairing certs and keys")?;
+        check_weak_crypto(&cluster_crypto, &mut report)?;
+    }
+
+    for fact in &report.facts {
+        println!("- {}", fact);
+    }
+    for requirement in &report.requirements {
+        println!("REQUIRES: {}", requirement);
+    }
+    for blocker in &report.blockers {
+        println!("UNSUPPORTED: {}", blocker);
+    }
+
+    ensure!(
+        report.blockers.is_empty(),
+        "this build of recert can't recertify the seed, {} unsupported",
+        report.blockers.len()
+    );
+    println!("The seed is supported");
+
+    Ok(())
+}
+
+/// Kubernetes stores resources as protobuf (prefixed by k8s\0) or as JSON (custom resources),
+/// and encrypted ones prefixed by k8s:enc:<provider>:<version>:
+fn storage_encoding(value: &[u8]) -> StorageEncoding {
+    if let Some(encrypted) = value.strip_prefix(b"k8s:enc:".as_slice()) {
+        let provider = encrypted.splitn(3, |byte| *byte == b':').take(2).collect::<Vec<_>>();
+        return StorageEncoding::Encrypted(String::from_utf8_lossy(&provider.join(b":".as_slice())).to_string());
+    }
+
+    if value.starts_with(b"k8s\x00") {
+        StorageEncoding::Protobuf
+    } else if value.first() == Some(&b'{') {
+        StorageEncoding::Json
+    } else {
+        StorageEncoding::Unknown
+    }
+}
+
+fn check_storage<'a>(values: impl Iterator<Item = &'a [u8]>, have_encryption_config: bool, report: &mut SeedReport) {
+    let mut encodings = BTreeMap::<StorageEncoding, usize>::new();
+    for value in values {
+        *encodings.entry(storage_encoding(value)).or_default() += 1;
+    }
+
+    let providers = encodings
+        .keys()
+        .filter_map(|encoding| match encoding {
+            StorageEncoding::Encrypted(provider) => Some(provider.as_str()),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    let (supported, unsupported): (Vec<_>, Vec<_>) = providers
+        .into_iter()
+        .partition(|provider| SUPPORTED_ENCRYPTION_PROVIDERS.contains(provider));
+
+    for (encoding, count) in &encodings {
+        report.facts.push(format!("{} values stored as {}", count, encoding));
+    }
+
+    if !unsupported.is_empty() {
+        report.blockers.push(format!(
+            "Values encrypted at rest with {}, which recert can't decrypt",
+            unsupported.join(", ")
+        ));
+    }
+    if !supported.is_empty() && !have_encryption_config {
+        report.blockers.push(format!(
+            "Values encrypted at rest with {}, which can only be read with --etcd-encryption-config",
+            supported.join(", ")
+        ));
+    } else if !supported.is_empty() {
+        report.requirements.push(format!(
+            "Encryption at rest ({}), the run needs --etcd-encryption-config",
+            supported.join(", ")
+        ));
+    }
+
+    if encodings.contains_key(&StorageEncoding::Protobuf) {
+        report
+            .requirements
+            .push("Protobuf values, the run needs ouger on the PATH".to_string());
+    }
+}
+
+fn check_static_dirs(static_dirs: &[PathBuf], report: &mut SeedReport) {
+    for static_dir in static_dirs {
+        if static_dir.is_dir() {
+            report.facts.push(format!("Static dir {:?}", static_dir));
+        } else {
+            report
+                .blockers
+                .push(format!("Static dir {:?} doesn't exist or isn't a dir", static_dir));
+        }
+    }
+}
+
+/// The kinds and sizes of the keys, of which EC keys and large RSA keys change how (or whether)
+/// they're regenerated
+fn check_keys<'a>(crypto_objects: impl Iterator<Item = &'a CryptoObject>, report: &mut SeedReport) -> Result<()> {
+    let public_keys = crypto_objects
+        .filter_map(|crypto_object| match crypto_object {
+            CryptoObject::PrivateKey(_, public_key) | CryptoObject::PublicKey(public_key) => Some(public_key.clone()),
+            CryptoObject::Certificate(certificate) => Some(certificate.public_key.clone()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    let mut kinds = BTreeMap::<String, usize>::new();
+    for public_key in &public_keys {
+        let kind = match public_key {
+            PublicKey::Rsa(_) => {
+                let size = public_key.rsa_key_size()?.context("RSA key without a size")?;
+                if size > MAX_RSA_KEY_SIZE {
+                    report.blockers.push(format!(
+                        "A {}-bit RSA key, recert can't sign with keys over {} bits",
+                        size, MAX_RSA_KEY_SIZE
+                    ));
+                }
+                format!("{}-bit RSA", size)
+            }
+            PublicKey::Ec(_) => match public_key.ec_curve() {
+                Some(curve) => format!("{} EC", curve),
+                None => "EC on other curves".to_string(),
+            },
+        };
+        *kinds.entry(kind).or_default() += 1;
+    }
+
+    for (kind, count) in &kinds {
+        report.facts.push(format!("{} distinct {} keys", count, kind));
+    }
+    if kinds.keys().any(|kind| kind.ends_with(" EC")) {
+        report
+            .requirements
+            .push("EC keys, which are regenerated as EC keys on the same curve".to_string());
+    }
+    if kinds.contains_key("EC on other curves") {
+        report
+            .requirements
+            .push("EC keys on curves other than P-256 and P-384, which are replaced by RSA keys".to_string());
+    }
+
+    Ok(())
+}
+
+fn check_weak_crypto(cluster_crypto: &ClusterCryptoObjects, report: &mut SeedReport) -> Result<()> {
+    let weak_crypto = weak_crypto::find_weak_crypto(cluster_crypto)?;
+
+    if weak_crypto
+        .iter()
+        .any(|weak_crypto| matches!(weak_crypto.weakness, Weakness::SmallRsaKey(_)))
+    {
+        report
+            .requirements
+            .push("RSA keys smaller than 2048 bits, the run needs --upgrade-weak-crypto".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage_encoding() {
+        assert_eq!(storage_encoding(b"k8s\x00\x0a\x0fv1"), StorageEncoding::Protobuf);
+        assert_eq!(storage_encoding(br#"{"kind":"Route"}"#), StorageEncoding::Json);
+        assert_eq!(
+            storage_encoding(b"k8s:enc:aescbc:v1:key1:data"),
+            StorageEncoding::Encrypted("aescbc:v1".to_string())
+        );
+        assert_eq!(storage_encoding(b"plain"), StorageEncoding::Unknown);
+    }
+
+    #[test]
+    fn test_check_storage() {
+        let values: [&[u8]; 3] = [b"k8s\x00", b"k8s:enc:aesgcm:v1:key1:data", b"k8s:enc:secretbox:v1:key1:data"];
+
+        let mut report = SeedReport::default();
+        check_storage(values.into_iter(), true, &mut report);
+        assert_eq!(
+            report.blockers,
+            vec!["Values encrypted at rest with secretbox:v1, which recert can't decrypt"]
+        );
+        assert_eq!(report.requirements.len(), 2);
+
+        let mut report = SeedReport::default();
+        check_storage(values[..2].iter().copied(), false, &mut report);
+        assert_eq!(
+            report.blockers,
+            vec!["Values encrypted at rest with aesgcm:v1, which can only be read with --etcd-encryption-config"]
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{error_catalog::CatalogedError, status};
+use anyhow::{Context, Result};
+use std::{
+    any::Any,
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Temporary files recert is in the middle of writing. Normally they're renamed into place or
+/// removed by whoever created them, but a failure (or panic) half way leaves them behind, so they
+/// are removed when the run fails.
+static TEMPORARIES: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+/// A panic caught at the top level, turned into a regular error so that it goes through the same
+/// failure handling
+#[derive(Debug)]
+pub(crate) struct Panicked(pub(crate) String);
+
+impl std::fmt::Display for Panicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "panicked: {}", self.0)
+    }
+}
+
+impl std::error::Error for Panicked {}
+
+impl Panicked {
+    pub(crate) fn from_payload(payload: Box<dyn Any + Send>) -> Self {
+        Self(if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic payload".to_string()
+        })
+    }
+}
+
+pub(crate) fn register_temporary(path: &Path) -> Result<()> {
+    TEMPORARIES
+        .lock()
+        .ok()
+        .context("temporaries lock poisoned")?
+        .get_or_insert_with(HashSet::new)
+        .insert(path.to_path_buf());
+    Ok(())
+}
+
+pub(crate) fn unregister_temporary(path: &Path) -> Result<()> {
+    if let Some(temporaries) = TEMPORARIES.lock().ok().context("temporaries lock poisoned")?.as_mut() {
+        temporaries.remove(path);
+    }
+    Ok(())
+}
+
+/// Remove all temporary files which are still around. Best effort, as this runs after something
+/// already went wrong
+pub(crate) fn remove_temporaries() {
+    // A panic while holding the lock doesn't make the set itself any less valid
+    let mut temporaries = TEMPORARIES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    for path in temporaries.take().unwrap_or_default() {
+        match std::fs::remove_file(&path) {
+            Ok(()) => println!("Removed leftover temporary file {:?}", path),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => println!("WARNING: failed to remove leftover temporary file {:?}: {}", path, err),
+        }
+    }
+}
+
+/// Write a JSON report of a failed run: the phase it failed in, the error along with all of its
+/// causes, whether it was a panic, and its code, parameters and message from the error catalog
+pub(crate) fn write_failure_report(path: &Path, error: &anyhow::Error) -> Result<()> {
+    std::fs::write(path, render_failure_report(error, &status::current_phase()?)?).with_context(|| format!("writing {:?}", path))
+}
+
+fn render_failure_report(error: &anyhow::Error, phase: &str) -> Result<String> {
+    let cataloged = CatalogedError::classify(error, phase);
+    Ok(serde_json::to_string_pretty(&serde_json::json!({
+        "code": cataloged.code.code(),
+        "message": cataloged.message(),
+        "params": cataloged.params,
+        "phase": phase,
+        "error": error.to_string(),
+        "causes": error.chain().skip(1).map(ToString::to_string).collect::<Vec<_>>(),
+        "panicked": error.chain().any(|cause| cause.is::<Panicked>()),
+    }))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_temporaries() {
+        let dir = std::env::temp_dir().join(format!("recert-cleanup-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let leftover = dir.join("leftover.tmp");
+        let renamed = dir.join("renamed.tmp");
+        std::fs::write(&leftover, "").unwrap();
+
+        register_temporary(&leftover).unwrap();
+        register_temporary(&renamed).unwrap();
+        std::fs::write(&renamed, "").unwrap();
+        std::fs::rename(&renamed, dir.join("renamed")).unwrap();
+        unregister_temporary(&renamed).unwrap();
+
+        remove_temporaries();
+
+        assert!(!leftover.exists());
+        assert!(dir.join("renamed").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failure_report() {
+        let error = anyhow::Error::new(Panicked::from_payload(Box::new("index out of bounds"))).context("regeneration");
+
+        let report: serde_json::Value = serde_json::from_str(&render_failure_report(&error, "regenerating").unwrap()).unwrap();
+        assert_eq!(report["phase"], "regenerating");
+        assert_eq!(report["error"], "regeneration");
+        assert_eq!(report["causes"], serde_json::json!(["panicked: index out of bounds"]));
+        assert_eq!(report["panicked"], true);
+        assert_eq!(report["code"], "RECERT-E0001");
+        assert_eq!(report["params"]["phase"], "regenerating");
+    }
+}
@@ -1,40 +1,108 @@
 use self::{
+    ca_graft::CaGrafts,
     cert_key_pair::CertKeyPair,
+    cn_filter::CnFilter,
     crypto_objects::DiscoveredCryptoObect,
+    distributed_crl::DistributedCrl,
     distributed_jwt::DistributedJwt,
     distributed_private_key::DistributedPrivateKey,
     distributed_public_key::DistributedPublicKey,
+    extension_policy::ExtensionPolicy,
+    external_ca::ExternalCa,
+    jwt::TokenPolicy,
     keys::{PrivateKey, PublicKey},
     locations::Locations,
+    private_key_format::PrivateKeyPolicy,
+    sa_signing_keys::SaSigningKeyRegeneration,
+    serial_policy::{SerialPolicy, SerialSequence},
+    signature_policy::SignaturePolicy,
+    validity_policy::ValidityPolicy,
+    yaml_crawl::ScanPolicy,
 };
 use crate::{
-    cluster_crypto::signee::Signee,
+    cluster_crypto::signee::{Signee, SigneeWalk},
     cnsanreplace::CnSanReplaceRules,
+    console, deterministic,
+    file_utils::PermissionPolicy,
     k8s_etcd::{self, InMemoryK8sEtcd},
-    rsa_key_pool::RsaKeyPool,
-    rules::KNOWN_MISSING_PRIVATE_KEY_CERTS,
+    rsa_key_pool::{KeyPoolUsage, PoolSize, RsaKeyPool},
 };
-use anyhow::{bail, Result};
+use anyhow::{bail, ensure, Context, Result};
 use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
-use x509_certificate::X509CertificateError;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use x509_certificate::{rfc5280, X509CertificateError};
 
+pub(crate) mod ca_graft;
 pub(crate) mod cert_key_pair;
 pub(crate) mod certificate;
+pub(crate) mod cn_filter;
+pub(crate) mod crl;
 pub(crate) mod crypto_objects;
 pub(crate) mod crypto_utils;
 pub(crate) mod distributed_cert;
+pub(crate) mod distributed_crl;
 pub(crate) mod distributed_jwt;
 pub(crate) mod distributed_private_key;
 pub(crate) mod distributed_public_key;
+pub(crate) mod entropy;
+pub(crate) mod expected_set;
+pub(crate) mod extension_policy;
+pub(crate) mod external_ca;
+pub(crate) mod ini;
 pub(crate) mod jwt;
 pub(crate) mod keys;
 pub(crate) mod locations;
+pub(crate) mod path_references;
 pub(crate) mod pem_utils;
+pub(crate) mod private_key_format;
+pub(crate) mod resource_kinds;
+pub(crate) mod sa_signing_keys;
 pub(crate) mod scanning;
+pub(crate) mod serial_policy;
+pub(crate) mod signature_policy;
 pub(crate) mod signee;
+pub(crate) mod ssh_keys;
+pub(crate) mod validity_policy;
+pub(crate) mod weak_crypto;
 pub(crate) mod yaml_crawl;
 
+/// How the crypto objects are found, regenerated and written back, as configured for the run
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CryptoPolicies {
+    /// The permissions of the files written, see --file-permissions
+    pub(crate) file_permissions: PermissionPolicy,
+
+    /// The serial numbers of the regenerated certs, see --serial-policy
+    pub(crate) serial: SerialPolicy,
+
+    /// How the regenerated certs and CRLs are signed, see --rsa-signature-digest and
+    /// --rsa-signature-padding
+    pub(crate) signature: SignaturePolicy,
+
+    /// The validity periods of the regenerated certs, see --cert-validity, --ca-validity and
+    /// --validity-override
+    pub(crate) validity: ValidityPolicy,
+
+    /// The changes to the extensions of the regenerated certs, see --extension-override
+    pub(crate) extension: ExtensionPolicy,
+
+    /// The claims of the re-signed tokens, see --token-expiry and --token-audience-replace
+    pub(crate) token: TokenPolicy,
+
+    /// How private keys are decrypted when found and encoded when written back, see
+    /// --private-key-format and --private-key-passphrase-file
+    pub(crate) private_key: PrivateKeyPolicy,
+
+    /// How resource values are decoded when looking for crypto objects
+    pub(crate) scan: ScanPolicy,
+}
+
 /// This is the main struct that holds all the crypto objects we've found in the cluster and the
 /// locations where we found them, and how they relate to each other.
 pub(crate) struct ClusterCryptoObjects {
@@ -50,6 +118,9 @@ pub(crate) struct ClusterCryptoObjects {
     pub(crate) distributed_certs: HashMap<certificate::Certificate, Rc<RefCell<distributed_cert::DistributedCert>>>,
     pub(crate) distributed_jwts: HashMap<jwt::Jwt, Rc<RefCell<DistributedJwt>>>,
 
+    /// CRLs, which are re-signed by their issuing CA once it's regenerated. See fill_crl_signers
+    pub(crate) distributed_crls: HashMap<crl::Crl, Rc<RefCell<DistributedCrl>>>,
+
     /// Every time we encounter a private key, we extract the public key
     /// from it and add to this mapping. This will later allow us to easily
     /// associate certificates with their matching private key (which would
@@ -60,6 +131,30 @@ pub(crate) struct ClusterCryptoObjects {
     /// find a private key that matches the public key of the cert (with the help of
     /// public_to_private) and populate this list of pairs.
     pub(crate) cert_key_pairs: Vec<Rc<RefCell<CertKeyPair>>>,
+
+    /// Intermediates flattened out of their chains, which are removed from all of the bundles they
+    /// were found in once everything is committed. See flatten_chain
+    pub(crate) flattened_intermediates: Vec<Rc<RefCell<CertKeyPair>>>,
+
+    /// Crypto objects found which recert doesn't handle, by their description. These are only
+    /// reported
+    pub(crate) unsupported_objects: HashMap<String, Locations>,
+
+    /// The CA re-signing the roots instead of their own new keys, see use_external_ca
+    pub(crate) external_ca: Option<ExternalCa>,
+
+    /// When set, only the certs expiring before this are regenerated, see rotate_only_expiring_within
+    pub(crate) rotate_expiring_before: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// The chains to regenerate (or leave alone) by CN, see filter_by_cn
+    pub(crate) cn_filter: CnFilter,
+
+    /// Which of the service account token signing keys to regenerate, see
+    /// regenerate_sa_signing_keys
+    pub(crate) sa_signing_key_regeneration: SaSigningKeyRegeneration,
+
+    /// How the crypto objects are regenerated and committed
+    pub(crate) policies: CryptoPolicies,
 }
 
 impl ClusterCryptoObjects {
@@ -69,8 +164,16 @@ impl ClusterCryptoObjects {
             distributed_public_keys: HashMap::new(),
             distributed_certs: HashMap::new(),
             distributed_jwts: HashMap::new(),
+            distributed_crls: HashMap::new(),
             public_to_private: HashMap::new(),
             cert_key_pairs: Vec::new(),
+            flattened_intermediates: Vec::new(),
+            unsupported_objects: HashMap::new(),
+            external_ca: None,
+            rotate_expiring_before: None,
+            cn_filter: CnFilter::default(),
+            sa_signing_key_regeneration: SaSigningKeyRegeneration::default(),
+            policies: CryptoPolicies::default(),
         }
     }
 
@@ -88,24 +191,77 @@ impl ClusterCryptoObjects {
         }
     }
 
+    /// List the crypto objects which were left untouched as recert doesn't support them, so that
+    /// users know what they have to take care of themselves
+    pub(crate) fn display_unsupported(&self) {
+        if self.unsupported_objects.is_empty() {
+            return;
+        }
+
+        println!("Unsupported objects (left untouched):");
+        let mut unsupported_objects = self.unsupported_objects.iter().collect::<Vec<_>>();
+        unsupported_objects.sort_by_key(|(description, _)| *description);
+        for (description, locations) in unsupported_objects {
+            println!("- {} at {}", description, locations);
+        }
+    }
+
+    /// How many certs are signed with each signature algorithm
+    pub(crate) fn signature_algorithm_counts(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for cert_key_pair in &self.cert_key_pairs {
+            let original = Arc::clone(&(*(**cert_key_pair).borrow().distributed_cert).borrow().certificate.original);
+            let certificate: &rfc5280::Certificate = original.as_ref().as_ref();
+            *counts
+                .entry(signature_policy::describe(&certificate.signature_algorithm))
+                .or_default() += 1;
+        }
+
+        counts
+    }
+
     /// Commit all the crypto objects to etcd and disk. This is called after all the crypto
     /// objects have been regenerated so that the newly generated objects are persisted in
     /// etcd and on disk.
+    // The graph is only ever borrowed from the committing task, so holding a borrow across the
+    // etcd cache's awaits can't conflict with another one
+    #[allow(clippy::await_holding_refcell_ref)]
     pub(crate) async fn commit_to_etcd_and_disk(&mut self, etcd_client: &InMemoryK8sEtcd) -> Result<()> {
+        // Resources usually hold several crypto objects (e.g. the cert and key of a TLS secret),
+        // each of which edits it separately
+        etcd_client.group_mutations().await;
+
+        let policies = &self.policies;
+
         for cert_key_pair in &self.cert_key_pairs {
-            (**cert_key_pair).borrow().commit_to_etcd_and_disk(etcd_client).await?;
+            (**cert_key_pair).borrow().commit_to_etcd_and_disk(etcd_client, policies).await?;
         }
 
         for jwt in self.distributed_jwts.values() {
             (**jwt).borrow().commit_to_etcd_and_disk(etcd_client).await?;
         }
 
+        for crl in self.distributed_crls.values() {
+            (**crl).borrow().commit_to_etcd_and_disk(etcd_client, policies).await?;
+        }
+
         for private_key in self.distributed_private_keys.values() {
-            (**private_key).borrow().commit_to_etcd_and_disk(etcd_client).await?;
+            (**private_key).borrow().commit_to_etcd_and_disk(etcd_client, policies).await?;
         }
 
         for public_key in self.distributed_public_keys.values() {
-            (**public_key).borrow().commit_to_etcd_and_disk(etcd_client).await?;
+            (**public_key).borrow().commit_to_etcd_and_disk(etcd_client, policies).await?;
+        }
+
+        for flattened_intermediate in &self.flattened_intermediates {
+            let flattened_intermediate = (**flattened_intermediate).borrow().clone();
+            flattened_intermediate.remove_from_bundles(etcd_client, policies).await?;
+        }
+
+        etcd_client.flush_grouped_mutations().await;
+
+        for cert_key_pair in &self.cert_key_pairs {
+            (**cert_key_pair).borrow().verify_committed_chains(etcd_client).await?;
         }
 
         Ok(())
@@ -116,18 +272,114 @@ impl ClusterCryptoObjects {
     /// that depend on them (signees). Requires that first the crypto objects have been paired and
     /// associated through the other methods.
     pub(crate) fn regenerate_crypto(&mut self, mut rsa_key_pool: RsaKeyPool, cn_san_replace_rules: CnSanReplaceRules) -> Result<()> {
+        // Pairs in a signer cycle have no root, so they would otherwise silently never be reached
         for cert_key_pair in &self.cert_key_pairs {
-            if (**cert_key_pair).borrow().signer.is_some() {
-                continue;
+            (**cert_key_pair).borrow().check_signer_chain()?;
+        }
+
+        sa_signing_keys::check_verification_keys(self.distributed_private_keys.values())?;
+
+        let mut chain_stats = Vec::new();
+        let mut serials = SerialSequence::new(self.policies.serial);
+
+        let external_ca_key_pair = self.external_ca.as_ref().map(ExternalCa::key_pair).transpose()?;
+
+        let walk_roots = match self.rotate_expiring_before {
+            Some(deadline) => {
+                let walk_roots = self.rotation_roots(|cert_key_pair| {
+                    (*cert_key_pair.distributed_cert).borrow().certificate.not_after() <= deadline && self.selected_by_cn(cert_key_pair)
+                });
+                println!("- Rotating {} chains of certs expiring before {}", walk_roots.len(), deadline);
+                walk_roots
+            }
+            None if !self.cn_filter.is_empty() => {
+                let walk_roots = self.rotation_roots(|cert_key_pair| self.selected_by_cn(cert_key_pair));
+                println!("- Regenerating {} chains selected by CN", walk_roots.len());
+                walk_roots
+            }
+            None => self
+                .cert_key_pairs
+                .iter()
+                .filter(|cert_key_pair| (***cert_key_pair).borrow().signer.is_none())
+                .cloned()
+                .collect(),
+        };
+
+        self.check_skipped_untouched(&walk_roots)?;
+
+        for cert_key_pair in &walk_roots {
+            let started = Instant::now();
+            let usage_before = rsa_key_pool.usage();
+
+            // Pairs rotated on their own are re-signed by their signer's existing key
+            let signer_key_pair = match &(**cert_key_pair).borrow().signer {
+                Some(signer) => Some(
+                    (**(**signer)
+                        .borrow()
+                        .distributed_private_key
+                        .as_ref()
+                        .context("rotated cert's signer has no key")?)
+                    .borrow()
+                    .key
+                    .signing_key_pair()?,
+                ),
+                None => None,
+            };
+
+            let mut signee_walk = SigneeWalk::new(&self.policies);
+            signee_walk.regenerate_cert_key_pair(
+                cert_key_pair,
+                signer_key_pair.as_ref().or(external_ca_key_pair.as_ref()),
+                &mut rsa_key_pool,
+                &mut serials,
+                &cn_san_replace_rules,
+                Vec::new(),
+            )?;
+            signee_walk.run(&mut rsa_key_pool, &mut serials)?;
+
+            chain_stats.push(ChainStats {
+                root: signee::subject(cert_key_pair),
+                duration: started.elapsed(),
+                usage: rsa_key_pool.usage().since(&usage_before),
+            });
+        }
+
+        // Standalone private keys don't expire and have no CN, so rotation and --only-cn leave
+        // them alone
+        if self.rotate_expiring_before.is_none() && !self.cn_filter.is_allow_list() {
+            let started = Instant::now();
+            let usage_before = rsa_key_pool.usage();
+            let mut regenerated = 0;
+            for private_key in self.distributed_private_keys.values() {
+                if !self.sa_signing_key_regeneration.regenerates(&(**private_key).borrow()) {
+                    continue;
+                }
+                (**private_key)
+                    .borrow_mut()
+                    .regenerate(&mut rsa_key_pool, &mut serials, &cn_san_replace_rules, &self.policies)?;
+                regenerated += 1;
             }
+            if regenerated > 0 {
+                chain_stats.push(ChainStats {
+                    root: format!("{} standalone private keys", regenerated),
+                    duration: started.elapsed(),
+                    usage: rsa_key_pool.usage().since(&usage_before),
+                });
+            }
+        }
+
+        print_chain_stats(chain_stats, &rsa_key_pool.usage());
 
-            (**cert_key_pair)
-                .borrow_mut()
-                .regenerate(None, &mut rsa_key_pool, &cn_san_replace_rules)?
+        if self.rotate_expiring_before.is_some() {
+            println!("- Rotation complete");
+            return Ok(());
         }
 
-        for private_key in self.distributed_private_keys.values() {
-            (**private_key).borrow_mut().regenerate(&mut rsa_key_pool, &cn_san_replace_rules)?
+        // The chains left out are left unregenerated on purpose, which is all the verification
+        // would find
+        if !self.cn_filter.is_empty() {
+            println!("- Regeneration of the chains selected by CN complete");
+            return Ok(());
         }
 
         println!("- Regeneration complete, verifying...");
@@ -136,10 +388,110 @@ impl ClusterCryptoObjects {
         Ok(())
     }
 
+    /// Limit regeneration to the certs expiring within the given window from now (along with
+    /// everything they signed), turning recert into a rotation of just what's about to expire
+    pub(crate) fn rotate_only_expiring_within(&mut self, window: chrono::Duration) {
+        self.rotate_expiring_before = Some(deterministic::now() + window);
+    }
+
+    /// Leave the chains of the certs with the skipped CNs as they are (e.g. custom ingress certs
+    /// managed outside the cluster), or regenerate only the chains of the certs with the selected
+    /// CNs. Everything a regenerated cert signed is regenerated along with it, whatever its CN
+    pub(crate) fn filter_by_cn(&mut self, cn_filter: CnFilter) {
+        self.cn_filter = cn_filter;
+    }
+
+    /// Regenerate only one of the service account token signing keys, where kube-controller-manager
+    /// and the apiserver keep distinct ones, rather than both of them in lockstep. The one left
+    /// alone keeps its public key in the apiserver's verification list, so the tokens it signed
+    /// stay valid alongside those signed by the regenerated one
+    pub(crate) fn regenerate_sa_signing_keys(&mut self, regeneration: SaSigningKeyRegeneration) {
+        self.sa_signing_key_regeneration = regeneration;
+    }
+
+    fn selected_by_cn(&self, cert_key_pair: &CertKeyPair) -> bool {
+        self.cn_filter.selects(common_name(cert_key_pair).as_deref())
+    }
+
+    /// A skipped cert can't be left as it is if it has to be regenerated along with its signer
+    /// (which re-signs it with its new key), or if it has no key of its own to re-sign the certs
+    /// it signed which aren't skipped
+    fn check_skipped_untouched(&self, walk_roots: &[Rc<RefCell<CertKeyPair>>]) -> Result<()> {
+        for cert_key_pair in &self.cert_key_pairs {
+            let common_name = common_name(&(**cert_key_pair).borrow());
+            if !self.cn_filter.skips(common_name.as_deref()) {
+                continue;
+            }
+
+            let mut current = Some(Rc::clone(cert_key_pair));
+            while let Some(pair) = current {
+                if walk_roots.iter().any(|walk_root| Rc::ptr_eq(walk_root, &pair)) {
+                    if Rc::ptr_eq(&pair, cert_key_pair) {
+                        bail!(
+                            "{} is skipped, but it has no key to re-sign the certs it signed which aren't skipped",
+                            signee::subject(cert_key_pair)
+                        );
+                    }
+                    bail!(
+                        "{} is skipped, but it's in the chain of {}, which is regenerated. Skip that one as well",
+                        signee::subject(cert_key_pair),
+                        signee::subject(&pair)
+                    );
+                }
+                current = (*pair).borrow().signer.clone();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The pairs to start regenerating from when only rotating the selected ones. Re-signing a
+    /// pair on its own requires the key of its signer, so pairs with keyless signers are rotated
+    /// along with them, up to the first signer which has its key. Pairs below another rotated pair
+    /// are rotated when walking its signees. Requires that signers have been checked for cycles
+    fn rotation_roots(&self, selected: impl Fn(&CertKeyPair) -> bool) -> Vec<Rc<RefCell<CertKeyPair>>> {
+        let mut rotated: Vec<Rc<RefCell<CertKeyPair>>> = vec![];
+        for cert_key_pair in &self.cert_key_pairs {
+            if !selected(&(**cert_key_pair).borrow()) {
+                continue;
+            }
+
+            let mut cert_key_pair = Rc::clone(cert_key_pair);
+            loop {
+                let signer = (*cert_key_pair).borrow().signer.clone();
+                match signer {
+                    Some(signer) if (*signer).borrow().distributed_private_key.is_none() => cert_key_pair = signer,
+                    _ => break,
+                }
+            }
+
+            if !rotated.iter().any(|rotated| Rc::ptr_eq(rotated, &cert_key_pair)) {
+                rotated.push(cert_key_pair);
+            }
+        }
+
+        let is_below_rotated = |cert_key_pair: &Rc<RefCell<CertKeyPair>>| {
+            let mut signer = (**cert_key_pair).borrow().signer.clone();
+            while let Some(current) = signer {
+                if rotated.iter().any(|rotated| Rc::ptr_eq(rotated, &current)) {
+                    return true;
+                }
+                signer = (*current).borrow().signer.clone();
+            }
+            false
+        };
+
+        rotated
+            .iter()
+            .filter(|cert_key_pair| !is_below_rotated(cert_key_pair))
+            .cloned()
+            .collect()
+    }
+
     fn assert_regeneration(&mut self) {
         // Assert all known objects have been regenerated.
         for cert_key_pair in &self.cert_key_pairs {
-            let signer = &(*(**cert_key_pair).borrow()).signer;
+            let signer = &(**cert_key_pair).borrow().signer;
             if let Some(signer) = signer {
                 assert!(
                     (**signer).borrow().regenerated,
@@ -159,7 +511,7 @@ impl ClusterCryptoObjects {
                 );
 
                 assert!(
-                    (**signer).borrow().signees.len() > 0,
+                    !(**signer).borrow().signees.is_empty(),
                     "Zero signees signer with cert at {} and keys at {}",
                     (*(**signer).borrow().distributed_cert).borrow().locations,
                     if let Some(key) = &(**signer).borrow().distributed_private_key {
@@ -187,6 +539,14 @@ impl ClusterCryptoObjects {
                                 (**signer).borrow(),
                             );
                         }
+                        signee::Signee::Crl(crl) => {
+                            assert!(
+                                (*crl).borrow().regenerated,
+                                "Didn't seem to regenerate CRL at {} signee of {}",
+                                (*crl).borrow().locations,
+                                (**signer).borrow(),
+                            );
+                        }
                     }
                 }
 
@@ -220,6 +580,9 @@ impl ClusterCryptoObjects {
         //     );
         // }
         for distributed_private_key in self.distributed_private_keys.values() {
+            if !self.sa_signing_key_regeneration.regenerates(&(*distributed_private_key).borrow()) {
+                continue;
+            }
             assert!(
                 (*distributed_private_key).borrow().regenerated,
                 "Didn't seem to regenerate private key {}",
@@ -239,23 +602,23 @@ impl ClusterCryptoObjects {
                 .subject_is_issuer()
             {
                 for potential_signing_cert_key_pair in &self.cert_key_pairs {
-                    match (*(**cert_key_pair).borrow().distributed_cert)
-                        .borrow()
-                        .certificate
-                        .original
-                        .verify_signed_by_certificate(
-                            &(*(*potential_signing_cert_key_pair).borrow().distributed_cert)
-                                .borrow()
-                                .certificate
-                                .original,
-                        ) {
-                        Ok(_) => true_signing_cert = Some(Rc::clone(&potential_signing_cert_key_pair)),
+                    match crypto_utils::verify_signed_by_certificate(
+                        &(*(**cert_key_pair).borrow().distributed_cert).borrow().certificate.original,
+                        &(*(*potential_signing_cert_key_pair).borrow().distributed_cert)
+                            .borrow()
+                            .certificate
+                            .original,
+                    ) {
+                        Ok(_) => true_signing_cert = Some(Rc::clone(potential_signing_cert_key_pair)),
                         Err(X509CertificateError::CertificateSignatureVerificationFailed) => {}
-                        Err(X509CertificateError::UnsupportedSignatureVerification(..)) => {
+                        Err(
+                            X509CertificateError::UnsupportedSignatureVerification(..)
+                            | X509CertificateError::UnknownSignatureAlgorithm(..),
+                        ) => {
                             // This is a hack to get around the fact this lib doesn't support
                             // all signature algorithms yet.
-                            if crypto_utils::openssl_is_signed(&potential_signing_cert_key_pair, &cert_key_pair)? {
-                                true_signing_cert = Some(Rc::clone(&potential_signing_cert_key_pair));
+                            if crypto_utils::openssl_is_signed(potential_signing_cert_key_pair, cert_key_pair)? {
+                                true_signing_cert = Some(Rc::clone(potential_signing_cert_key_pair));
                             }
                         }
                         unknown_err => unknown_err?,
@@ -263,7 +626,10 @@ impl ClusterCryptoObjects {
                 }
 
                 if true_signing_cert.is_none() {
-                    bail!("no signing cert found for cert in {}", (*(**cert_key_pair).borrow().distributed_cert).borrow().locations);
+                    bail!(
+                        "no signing cert found for cert in {}",
+                        (*(**cert_key_pair).borrow().distributed_cert).borrow().locations
+                    );
                 }
             }
 
@@ -289,7 +655,7 @@ impl ClusterCryptoObjects {
             if let Some(last_signer) = &last_signer {
                 match crypto_utils::verify_jwt(&PublicKey::try_from(&(*last_signer).borrow().key)?, &(**distributed_jwt).borrow()) {
                     Ok(_claims /* We don't care about the claims, only that the signature is correct */) => {
-                        maybe_signer = jwt::JwtSigner::PrivateKey(Rc::clone(&last_signer));
+                        maybe_signer = jwt::JwtSigner::PrivateKey(Rc::clone(last_signer));
                     }
                     Err(_error) => {}
                 }
@@ -301,7 +667,7 @@ impl ClusterCryptoObjects {
                     ) {
                         Ok(_claims /* We don't care about the claims, only that the signature is correct */) => {
                             maybe_signer = jwt::JwtSigner::PrivateKey(Rc::clone(distributed_private_key));
-                            last_signer = Some(Rc::clone(&distributed_private_key));
+                            last_signer = Some(Rc::clone(distributed_private_key));
                             break;
                         }
                         Err(_error) => {}
@@ -309,24 +675,21 @@ impl ClusterCryptoObjects {
                 }
             }
 
-            match &maybe_signer {
-                jwt::JwtSigner::Unknown => {
-                    for cert_key_pair in &self.cert_key_pairs {
-                        if let Some(distributed_private_key) = &(**cert_key_pair).borrow().distributed_private_key {
-                            match crypto_utils::verify_jwt(
-                                &PublicKey::try_from(&(**distributed_private_key).borrow().key)?,
-                                &(**distributed_jwt).borrow(),
-                            ) {
-                                Ok(_claims /* We don't care about the claims, only that the signature is correct */) => {
-                                    maybe_signer = jwt::JwtSigner::CertKeyPair(Rc::clone(cert_key_pair));
-                                    break;
-                                }
-                                Err(_error) => {}
+            if maybe_signer == jwt::JwtSigner::Unknown {
+                for cert_key_pair in &self.cert_key_pairs {
+                    if let Some(distributed_private_key) = &(**cert_key_pair).borrow().distributed_private_key {
+                        match crypto_utils::verify_jwt(
+                            &PublicKey::try_from(&(**distributed_private_key).borrow().key)?,
+                            &(**distributed_jwt).borrow(),
+                        ) {
+                            Ok(_claims /* We don't care about the claims, only that the signature is correct */) => {
+                                maybe_signer = jwt::JwtSigner::CertKeyPair(Rc::clone(cert_key_pair));
+                                break;
                             }
+                            Err(_error) => {}
                         }
                     }
                 }
-                _ => {}
             }
 
             if maybe_signer == jwt::JwtSigner::Unknown {
@@ -349,6 +712,40 @@ impl ClusterCryptoObjects {
         Ok(())
     }
 
+    /// For every CRL, find the cert-key pair of the CA which issued it. CRLs issued by CAs which
+    /// aren't in the cluster can't be re-signed, so they're reported as unsupported instead
+    pub(crate) fn fill_crl_signers(&mut self) -> Result<()> {
+        let mut unsigned_crls = vec![];
+
+        for (crl, distributed_crl) in &self.distributed_crls {
+            let mut signer = None;
+            for cert_key_pair in &self.cert_key_pairs {
+                if crl.is_issued_by(&(*(**cert_key_pair).borrow().distributed_cert).borrow().certificate.original)? {
+                    signer = Some(Rc::clone(cert_key_pair));
+                    break;
+                }
+            }
+
+            if signer.is_none() {
+                unsigned_crls.push(crl.clone());
+            }
+
+            (**distributed_crl).borrow_mut().signer = signer;
+        }
+
+        for unsigned_crl in unsigned_crls {
+            if let Some(distributed_crl) = self.distributed_crls.remove(&unsigned_crl) {
+                self.unsupported_objects
+                    .entry(format!("CRL of a CA which isn't in the cluster ({})", unsigned_crl.issuer))
+                    .or_insert_with(|| Locations(Default::default()))
+                    .0
+                    .extend((*distributed_crl).borrow().locations.0.iter().cloned());
+            }
+        }
+
+        Ok(())
+    }
+
     /// For every cert-key pair or private key, find all the crypto objects that depend on it and
     /// record them. This will later be used to know how to regenerate the crypto objects.
     pub(crate) fn fill_signees(&mut self) -> Result<()> {
@@ -362,7 +759,7 @@ impl ClusterCryptoObjects {
                         .original
                         == (*(**cert_key_pair).borrow().distributed_cert).borrow().certificate.original
                     {
-                        signees.push(signee::Signee::CertKeyPair(Rc::clone(&potential_signee)));
+                        signees.push(signee::Signee::CertKeyPair(Rc::clone(potential_signee)));
                     }
                 }
             }
@@ -379,6 +776,14 @@ impl ClusterCryptoObjects {
                 }
             }
 
+            for potential_crl_signee in self.distributed_crls.values() {
+                if let Some(crl_signer) = &(**potential_crl_signee).borrow().signer {
+                    if Rc::ptr_eq(crl_signer, cert_key_pair) {
+                        signees.push(signee::Signee::Crl(Rc::clone(potential_crl_signee)));
+                    }
+                }
+            }
+
             (**cert_key_pair).borrow_mut().signees = signees;
         }
 
@@ -415,6 +820,9 @@ impl ClusterCryptoObjects {
                 signer: None,
                 signees: Vec::new(),
                 associated_public_key: None,
+                new_issuer: None,
+                new_issuer_skid: None,
+                graft: None,
                 regenerated: false,
             }));
 
@@ -424,7 +832,7 @@ impl ClusterCryptoObjects {
                     (*pair).borrow_mut().distributed_private_key = Some(Rc::clone(distributed_private_key.get()));
 
                     // Remove the private key from the pool of private keys as it's now paired with a cert
-                    self.distributed_private_keys.remove(&private_key.get());
+                    self.distributed_private_keys.remove(private_key.get());
                 } else {
                     bail!(
                         "Private key not found for cert {}. The cert was found in {}",
@@ -432,9 +840,7 @@ impl ClusterCryptoObjects {
                         (**distributed_cert).borrow().locations,
                     );
                 }
-            } else if KNOWN_MISSING_PRIVATE_KEY_CERTS.iter().any(|known_missing_private_key_cert| {
-                known_missing_private_key_cert.is_match(&(**distributed_cert).borrow().certificate.subject)
-            }) {
+            } else if (**distributed_cert).borrow().is_known_missing_private_key() {
                 // This is a known missing private key cert, so we don't need to worry about it not
                 // having a private key.
             } else {
@@ -453,6 +859,289 @@ impl ClusterCryptoObjects {
             self.distributed_certs.remove(&paired_cer_to_remove);
         }
 
+        // The pairs come out of a hashmap, in an order which differs from run to run. They (and so
+        // their signees) are regenerated in this order, which sequential serial numbers follow
+        self.cert_key_pairs.sort_by_cached_key(|pair| {
+            (*(**pair).borrow().distributed_cert)
+                .borrow()
+                .certificate
+                .original
+                .constructed_data()
+                .to_vec()
+        });
+
+        Ok(())
+    }
+
+    /// Seeds sometimes contain multiple CAs with the same subject but different keys, left over
+    /// from past rotations. Report them along with what each of them signs, and if unify is set,
+    /// merge each group into a single CA, so that all of their signees are re-signed by the same
+    /// regenerated CA and all of their locations get that CA. Requires that signees have been
+    /// filled.
+    pub(crate) fn handle_duplicate_cas(&mut self, unify: bool) -> Result<()> {
+        let mut cas_by_subject: HashMap<String, Vec<Rc<RefCell<CertKeyPair>>>> = HashMap::new();
+        for cert_key_pair in &self.cert_key_pairs {
+            let is_ca = {
+                let cert_key_pair = (**cert_key_pair).borrow();
+                !cert_key_pair.signees.is_empty() || (*cert_key_pair.distributed_cert).borrow().certificate.original.subject_is_issuer()
+            };
+
+            if is_ca {
+                cas_by_subject
+                    .entry((*(**cert_key_pair).borrow().distributed_cert).borrow().certificate.subject.clone())
+                    .or_default()
+                    .push(Rc::clone(cert_key_pair));
+            }
+        }
+
+        let mut duplicate_groups = cas_by_subject.into_iter().filter(|(_, cas)| cas.len() > 1).collect::<Vec<_>>();
+        duplicate_groups.sort_by(|(subject_a, _), (subject_b, _)| subject_a.cmp(subject_b));
+
+        for (subject, mut cas) in duplicate_groups {
+            println!("WARNING: {} CAs with different keys share the subject {}:", cas.len(), subject);
+            for ca in &cas {
+                println!("  - {}", describe_duplicate_ca(&(**ca).borrow()));
+            }
+
+            if !unify {
+                continue;
+            }
+
+            // Keep the CA we have a private key for, preferring the one valid for the longest
+            let sort_key = |ca: &Rc<RefCell<CertKeyPair>>| {
+                let ca = (**ca).borrow();
+                let not_after = (*ca.distributed_cert).borrow().certificate.not_after();
+                (ca.distributed_private_key.is_some(), not_after)
+            };
+            cas.sort_by_key(|ca| std::cmp::Reverse(sort_key(ca)));
+            let (kept, merged) = cas.split_first().context("empty duplicate CA group")?;
+
+            for duplicate in merged {
+                merge_duplicate_ca(kept, duplicate)?;
+                self.cert_key_pairs.retain(|cert_key_pair| !Rc::ptr_eq(cert_key_pair, duplicate));
+            }
+
+            println!("  Unified under the CA with {} signees", (**kept).borrow().signees.len());
+        }
+
+        Ok(())
+    }
+
+    /// Take the intermediate CAs with the given CN out of their chains: their signees are moved
+    /// under the intermediate's own signer, which re-signs them directly, and once committed the
+    /// intermediate is removed from all the bundles it's in. The intermediate itself is still
+    /// regenerated, as a leaf of its signer, so that the locations it's alone in (usually its own
+    /// secret) stay consistent. Requires that signees have been filled.
+    pub(crate) fn flatten_chain(&mut self, common_name: &str) -> Result<()> {
+        let intermediates = self
+            .cert_key_pairs
+            .iter()
+            .filter(|cert_key_pair| {
+                (*(***cert_key_pair).borrow().distributed_cert)
+                    .borrow()
+                    .certificate
+                    .original
+                    .subject_common_name()
+                    == Some(common_name.to_string())
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if intermediates.is_empty() {
+            bail!("no cert with CN {} found to flatten", common_name);
+        }
+
+        for intermediate in intermediates {
+            let subject = signee::subject(&intermediate);
+            let (parent, signees) = {
+                let mut intermediate_mut = (*intermediate).borrow_mut();
+                let parent = intermediate_mut
+                    .signer
+                    .clone()
+                    .with_context(|| format!("{} is a root, only intermediates can be flattened", subject))?;
+                if intermediate_mut.signees.iter().any(|signee| matches!(signee, Signee::Jwt(_))) {
+                    bail!("{} signs JWTs, which can't be moved to another signer", subject);
+                }
+                if intermediate_mut.signees.iter().any(|signee| matches!(signee, Signee::Crl(_))) {
+                    bail!("{} issues CRLs, which can't be moved to another signer", subject);
+                }
+                (parent, std::mem::take(&mut intermediate_mut.signees))
+            };
+
+            let parent_subject = {
+                let parent_cert = Rc::clone(&(*parent).borrow().distributed_cert);
+                let parent_cert = &(*parent_cert).borrow().certificate.original;
+                let parent_certificate: &rfc5280::Certificate = parent_cert.as_ref().as_ref();
+                parent_certificate.tbs_certificate.subject.clone()
+            };
+
+            println!(
+                "Flattening {} out of its chain, its {} signees are re-signed by {}",
+                subject,
+                signees.len(),
+                signee::subject(&parent)
+            );
+
+            for signee in &signees {
+                if let Signee::CertKeyPair(cert_key_pair) = signee {
+                    let mut cert_key_pair = (**cert_key_pair).borrow_mut();
+                    cert_key_pair.signer = Some(Rc::clone(&parent));
+                    cert_key_pair.new_issuer = Some(parent_subject.clone());
+                }
+            }
+            (*parent).borrow_mut().signees.extend(signees);
+
+            self.flattened_intermediates.push(intermediate);
+        }
+
+        Ok(())
+    }
+
+    /// Have the given CA re-sign the roots instead of their own new keys, making them its
+    /// intermediates so that everything chains to it. Requires that signers have been filled, and
+    /// must come after anything moving pairs between signers (e.g. flatten_chain)
+    pub(crate) fn use_external_ca(&mut self, external_ca: ExternalCa) -> Result<()> {
+        let (subject, skid) = (external_ca.subject(), external_ca.skid()?);
+
+        for cert_key_pair in &self.cert_key_pairs {
+            let mut cert_key_pair = (**cert_key_pair).borrow_mut();
+            if cert_key_pair.signer.is_none() {
+                cert_key_pair.new_issuer = Some(subject.clone());
+                cert_key_pair.new_issuer_skid = Some(skid.clone());
+            }
+        }
+
+        self.external_ca = Some(external_ca);
+        Ok(())
+    }
+
+    /// Have the given CAs (and leaf certs, see CaGrafts::load_material_dir) take the place of the
+    /// cluster's certs of the same name (see ca_graft), rather than those being regenerated. A
+    /// grafted cert with a signer is issued by that signer's graft, so the signer has to be
+    /// grafted as well. Requires that signers and signees have
+    /// been filled.
+    pub(crate) fn graft_cas(&mut self, ca_grafts: &CaGrafts) -> Result<()> {
+        let mut grafted = HashSet::new();
+        for cert_key_pair in &self.cert_key_pairs {
+            let mut cert_key_pair = (**cert_key_pair).borrow_mut();
+            let certificate = (*cert_key_pair.distributed_cert).borrow().certificate.clone();
+            let Some(common_name) = certificate.original.subject_common_name() else {
+                continue;
+            };
+            let graft = if ca_graft::is_ca(&certificate)? {
+                ca_grafts.get(&common_name)
+            } else {
+                ca_grafts.get_leaf(&common_name)
+            };
+            let Some(graft) = graft else {
+                continue;
+            };
+
+            println!("- Grafting {} in place of {}", graft.certificate.subject, certificate.subject);
+            grafted.insert(ca_graft::graft_name(&common_name).to_string());
+            cert_key_pair.graft = Some(graft.clone());
+        }
+
+        for cert_key_pair in &self.cert_key_pairs {
+            let cert_key_pair = (**cert_key_pair).borrow();
+            let Some(graft) = &cert_key_pair.graft else {
+                continue;
+            };
+
+            // The graft's subject isn't necessarily the one of the CA it takes the place of
+            let graft_certificate: &rfc5280::Certificate = graft.certificate.original.as_ref().as_ref();
+            for signee in &cert_key_pair.signees {
+                if let Signee::CertKeyPair(signee) = signee {
+                    let mut signee = (**signee).borrow_mut();
+                    if signee.graft.is_none() {
+                        signee.new_issuer = Some(graft_certificate.tbs_certificate.subject.clone());
+                    }
+                }
+            }
+
+            let Some(signer) = &cert_key_pair.signer else {
+                continue;
+            };
+            let signer = (**signer).borrow();
+            let signer_graft = signer.graft.as_ref().with_context(|| {
+                format!(
+                    "{} is grafted, but its signer {} isn't",
+                    graft.certificate.subject,
+                    (*signer.distributed_cert).borrow().certificate.subject
+                )
+            })?;
+            ensure!(
+                graft.certificate.issuer == signer_graft.certificate.subject,
+                "grafted {} isn't issued by {}, which is grafted in place of its signer",
+                graft.certificate.subject,
+                signer_graft.certificate.subject
+            );
+        }
+
+        for name in ca_grafts.names().filter(|name| !grafted.contains(*name)) {
+            println!("WARNING: graft {} matches none of the cluster's certs", name);
+        }
+
+        Ok(())
+    }
+
+    /// CAs whose private key isn't anywhere in scope can't be re-signed as they are, so a brand new
+    /// CA with the same subject is minted in their place, and written to all of the locations
+    /// (i.e. trust bundles) the original was found in. As that replaces a trust anchor the user
+    /// might not expect us to touch, it's only done when explicitly allowed, except for the CAs
+    /// known to drop their keys (see DistributedCert::is_known_missing_private_key). Requires that signees have
+    /// been filled.
+    pub(crate) fn check_keyless_cas(&self, regenerate_keyless_cas: bool) -> Result<()> {
+        let keyless_cas = self
+            .cert_key_pairs
+            .iter()
+            .map(|cert_key_pair| (**cert_key_pair).borrow())
+            .filter(|cert_key_pair| {
+                let distributed_cert = (*cert_key_pair.distributed_cert).borrow();
+
+                cert_key_pair.distributed_private_key.is_none()
+                    && cert_key_pair.graft.is_none()
+                    && (!cert_key_pair.signees.is_empty() || distributed_cert.certificate.original.subject_is_issuer())
+                    && !distributed_cert.is_known_missing_private_key()
+            })
+            .map(|cert_key_pair| {
+                let distributed_cert = (*cert_key_pair.distributed_cert).borrow();
+                format!(
+                    "{} (with {} signees, found in {})",
+                    distributed_cert.certificate.subject,
+                    cert_key_pair.signees.len(),
+                    distributed_cert.locations
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if keyless_cas.is_empty() {
+            return Ok(());
+        }
+
+        if !regenerate_keyless_cas {
+            bail!(
+                "the private keys of these CAs weren't found, pass --regenerate-keyless-cas to replace them with new CAs: {}",
+                keyless_cas.join(", ")
+            );
+        }
+
+        for keyless_ca in keyless_cas {
+            println!("Minting a new CA in place of the keyless CA {}", keyless_ca);
+        }
+
+        Ok(())
+    }
+
+    /// Warn about certs whose signer chain doesn't satisfy the BasicConstraints of the CAs in it,
+    /// see CertKeyPair::basic_constraints_violations. Requires that signers have been filled.
+    pub(crate) fn check_basic_constraints(&self) -> Result<()> {
+        for cert_key_pair in &self.cert_key_pairs {
+            for violation in (**cert_key_pair).borrow().basic_constraints_violations()? {
+                println!("WARNING: basic constraints violated: {}", violation);
+            }
+        }
+
         Ok(())
     }
 
@@ -507,11 +1196,19 @@ impl ClusterCryptoObjects {
     fn register_discovered_crypto_object(&mut self, discovered_crypto_object: DiscoveredCryptoObect, location: locations::Location) {
         match discovered_crypto_object.crypto_object {
             crypto_objects::CryptoObject::PrivateKey(private_part, public_part) => {
-                self.register_discovered_private_key(public_part, private_part, &location)
+                self.register_discovered_private_key(public_part, *private_part, &location)
             }
             crypto_objects::CryptoObject::PublicKey(public_key) => self.register_discovered_public_key(public_key, &location),
             crypto_objects::CryptoObject::Certificate(hashable_cert) => self.register_discovered_certificate(hashable_cert, &location),
             crypto_objects::CryptoObject::Jwt(jwt) => self.register_discovered_jwt(jwt, location),
+            crypto_objects::CryptoObject::Crl(crl) => self.register_discovered_crl(crl, location),
+            crypto_objects::CryptoObject::Unsupported(description) => {
+                self.unsupported_objects
+                    .entry(description)
+                    .or_insert_with(|| Locations(Default::default()))
+                    .0
+                    .insert(location);
+            }
         }
     }
 
@@ -531,6 +1228,22 @@ impl ClusterCryptoObjects {
         }
     }
 
+    fn register_discovered_crl(&mut self, crl: crl::Crl, location: locations::Location) {
+        match self.distributed_crls.entry(crl.clone()) {
+            Vacant(distributed_crl) => {
+                distributed_crl.insert(Rc::new(RefCell::new(DistributedCrl {
+                    crl,
+                    locations: Locations(vec![location].into_iter().collect()),
+                    signer: None,
+                    regenerated: false,
+                })));
+            }
+            Occupied(distributed_crl) => {
+                (**distributed_crl.get()).borrow_mut().locations.0.insert(location);
+            }
+        }
+    }
+
     fn register_discovered_certificate(&mut self, hashable_cert: certificate::Certificate, location: &locations::Location) {
         match self.distributed_certs.entry(hashable_cert.clone()) {
             Vacant(distributed_cert) => {
@@ -593,3 +1306,907 @@ impl ClusterCryptoObjects {
         }
     }
 }
+
+/// How long regenerating everything under one root took, and how many keys it took
+struct ChainStats {
+    root: String,
+    duration: Duration,
+    usage: KeyPoolUsage,
+}
+
+/// Print the regeneration stats of all chains (slowest first), along with the pool sizes which
+/// would have covered this cluster without generating any keys on demand
+fn print_chain_stats(mut chain_stats: Vec<ChainStats>, total_usage: &KeyPoolUsage) {
+    chain_stats.sort_by_key(|chain| std::cmp::Reverse(chain.duration));
+
+    console::count("chains", chain_stats.len());
+    console::count("keys", total_usage.total());
+
+    let rows = chain_stats
+        .iter()
+        .map(|chain| {
+            vec![
+                chain.root.clone(),
+                format!("{:.3?}", chain.duration),
+                chain.usage.total().to_string(),
+                chain.usage.to_string(),
+            ]
+        })
+        .collect();
+    if !console::table("Chains processed, slowest first", &["ROOT", "DURATION", "KEYS", "KEY POOL"], rows) {
+        println!("- Regeneration per chain, slowest first:");
+        for chain in &chain_stats {
+            println!(
+                "  {:>10.3?} {:>4} keys ({}) {}",
+                chain.duration,
+                chain.usage.total(),
+                chain.usage,
+                chain.root
+            );
+        }
+    }
+
+    println!("- Total key usage: {}", total_usage);
+
+    let mut needed = total_usage.from_pool.clone();
+    for (key_size, count) in &total_usage.generated {
+        *needed.entry(*key_size).or_default() += count;
+    }
+    if !total_usage.generated.is_empty() {
+        println!(
+            "- The key pool ran out, this cluster needs {}",
+            needed
+                .iter()
+                .map(|(key_size, num_keys)| format!(
+                    "--rsa-key-pool-size {}",
+                    PoolSize {
+                        key_size: *key_size,
+                        num_keys: *num_keys
+                    }
+                ))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    }
+}
+
+fn describe_duplicate_ca(ca: &CertKeyPair) -> String {
+    let distributed_cert = (*ca.distributed_cert).borrow();
+    let mut signee_subjects = ca
+        .signees
+        .iter()
+        .map(|signee| match signee {
+            Signee::CertKeyPair(signee) => (*(**signee).borrow().distributed_cert).borrow().certificate.subject.clone(),
+            Signee::Jwt(_) => "a JWT".to_string(),
+            Signee::Crl(_) => "a CRL".to_string(),
+        })
+        .collect::<Vec<_>>();
+    signee_subjects.sort();
+
+    format!(
+        "{}, not after {}, found in {}, signs [{}]",
+        if ca.distributed_private_key.is_some() {
+            "with a private key"
+        } else {
+            "without a private key"
+        },
+        distributed_cert.certificate.not_after().to_rfc3339(),
+        distributed_cert.locations,
+        signee_subjects.join(", ")
+    )
+}
+
+/// Fold the duplicate CA into the kept one: its signees become signees of the kept CA, and its
+/// cert, key and public key locations get the kept CA's regenerated cert, key and public key
+fn merge_duplicate_ca(kept: &Rc<RefCell<CertKeyPair>>, duplicate: &Rc<RefCell<CertKeyPair>>) -> Result<()> {
+    let duplicate = (**duplicate).borrow();
+    let mut kept_mut = (**kept).borrow_mut();
+
+    for signee in &duplicate.signees {
+        match signee {
+            Signee::CertKeyPair(signee_pair) => (**signee_pair).borrow_mut().signer = Some(Rc::clone(kept)),
+            Signee::Jwt(jwt) => (**jwt).borrow_mut().signer = jwt::JwtSigner::CertKeyPair(Rc::clone(kept)),
+            Signee::Crl(crl) => (**crl).borrow_mut().signer = Some(Rc::clone(kept)),
+        }
+        kept_mut.signees.push(signee.clone());
+    }
+
+    // A duplicate intermediate is also a signee of its own signer, which shouldn't re-sign it
+    if let Some(signer) = &duplicate.signer {
+        (**signer)
+            .borrow_mut()
+            .signees
+            .retain(|signee| !matches!(signee, Signee::CertKeyPair(pair) if std::ptr::eq(pair.as_ptr(), &*duplicate)));
+    }
+
+    let duplicate_cert_locations = (*duplicate.distributed_cert).borrow().locations.0.clone();
+    (*kept_mut.distributed_cert)
+        .borrow_mut()
+        .locations
+        .0
+        .extend(duplicate_cert_locations);
+
+    if let (Some(kept_key), Some(duplicate_key)) = (&kept_mut.distributed_private_key, &duplicate.distributed_private_key) {
+        let duplicate_key_locations = (**duplicate_key).borrow().locations.0.clone();
+        (**kept_key).borrow_mut().locations.0.extend(duplicate_key_locations);
+    }
+
+    match (&kept_mut.associated_public_key, &duplicate.associated_public_key) {
+        (Some(kept_public_key), Some(duplicate_public_key)) => {
+            let duplicate_public_key_locations = (**duplicate_public_key).borrow().locations.0.clone();
+            (**kept_public_key).borrow_mut().locations.0.extend(duplicate_public_key_locations);
+        }
+        (None, Some(duplicate_public_key)) => kept_mut.associated_public_key = Some(Rc::clone(duplicate_public_key)),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn common_name(cert_key_pair: &CertKeyPair) -> Option<String> {
+    (*cert_key_pair.distributed_cert)
+        .borrow()
+        .certificate
+        .original
+        .subject_common_name()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use locations::{FileContentLocation, FileLocation, Location, LocationValueType};
+    use validity_policy::Validity;
+    use x509_certificate::{CapturedX509Certificate, EcdsaCurve, KeyAlgorithm, SignatureAlgorithm, X509CertificateBuilder};
+
+    fn keyless_pair(common_name: &str) -> Rc<RefCell<CertKeyPair>> {
+        let mut builder = X509CertificateBuilder::default();
+        builder.subject().append_organizational_unit_utf8_string("openshift").unwrap();
+        builder.subject().append_common_name_utf8_string(common_name).unwrap();
+        let (cert, _) = builder
+            .create_with_random_keypair(KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1))
+            .unwrap();
+
+        pair_from_cert(cert)
+    }
+
+    /// A self-signed cert made by openssl, which unlike X509CertificateBuilder can also make RSA
+    /// certs
+    fn openssl_keyless_pair(common_name: &str, newkey_args: &[&str]) -> Rc<RefCell<CertKeyPair>> {
+        let output = std::process::Command::new("openssl")
+            .args(["req", "-x509", "-nodes", "-keyout", "/dev/null", "-days", "1"])
+            .args(["-subj", &format!("/CN={}", common_name)])
+            .args(newkey_args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        pair_from_cert(CapturedX509Certificate::from_pem(output.stdout).unwrap())
+    }
+
+    fn pair_from_cert(cert: CapturedX509Certificate) -> Rc<RefCell<CertKeyPair>> {
+        Rc::new(RefCell::new(CertKeyPair {
+            distributed_private_key: None,
+            distributed_cert: Rc::new(RefCell::new(distributed_cert::DistributedCert {
+                certificate: certificate::Certificate::try_from(cert).unwrap(),
+                locations: Locations(Default::default()),
+            })),
+            signer: None,
+            signees: Vec::new(),
+            associated_public_key: None,
+            new_issuer: None,
+            new_issuer_skid: None,
+            graft: None,
+            regenerated: false,
+        }))
+    }
+
+    #[test]
+    fn test_unify_duplicate_cas() {
+        let kept = keyless_pair("duplicated-signer");
+        let duplicate = keyless_pair("duplicated-signer");
+        let leaf = keyless_pair("leaf");
+        (*leaf).borrow_mut().signer = Some(Rc::clone(&duplicate));
+        (*duplicate).borrow_mut().signees.push(Signee::CertKeyPair(Rc::clone(&leaf)));
+
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        cluster_crypto.cert_key_pairs = vec![Rc::clone(&kept), Rc::clone(&duplicate), Rc::clone(&leaf)];
+
+        cluster_crypto.handle_duplicate_cas(false).unwrap();
+        assert_eq!(cluster_crypto.cert_key_pairs.len(), 3);
+
+        cluster_crypto.handle_duplicate_cas(true).unwrap();
+        assert_eq!(cluster_crypto.cert_key_pairs.len(), 2);
+        let unified = cluster_crypto.cert_key_pairs.iter().find(|pair| !Rc::ptr_eq(pair, &leaf)).unwrap();
+        assert_eq!((**unified).borrow().signees, vec![Signee::CertKeyPair(Rc::clone(&leaf))]);
+        assert!(Rc::ptr_eq((*leaf).borrow().signer.as_ref().unwrap(), unified));
+    }
+
+    #[tokio::test]
+    async fn test_signer_cycle() {
+        let first = keyless_pair("first-cross-signed-ca");
+        let second = keyless_pair("second-cross-signed-ca");
+        let leaf = keyless_pair("leaf");
+        (*leaf).borrow_mut().signer = Some(Rc::clone(&first));
+        assert!((*leaf).borrow().check_signer_chain().is_ok());
+
+        (*first).borrow_mut().signer = Some(Rc::clone(&second));
+        (*second).borrow_mut().signer = Some(Rc::clone(&first));
+
+        assert_eq!((*leaf).borrow().num_parents(), signee::MAX_SIGNER_CHAIN_DEPTH);
+        let err = (*leaf).borrow().check_signer_chain().unwrap_err().to_string();
+        assert!(err.starts_with("signer cycle:"), "{}", err);
+        assert!(
+            err.contains("first-cross-signed-ca") && err.contains("second-cross-signed-ca"),
+            "{}",
+            err
+        );
+
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        cluster_crypto.cert_key_pairs = vec![first, second, leaf];
+        assert!(cluster_crypto
+            .regenerate_crypto(
+                RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                CnSanReplaceRules::try_from(vec![]).unwrap()
+            )
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_external_ca() {
+        let dir = tempfile::tempdir().unwrap();
+        let org_ca = crate::test_fixtures::CertFixture::ca("org-ca");
+        std::fs::write(dir.path().join("org-ca.crt"), &org_ca.cert_pem).unwrap();
+        std::fs::write(dir.path().join("org-ca.key"), &org_ca.key_pem).unwrap();
+        let external_ca = ExternalCa::load(&external_ca::ExternalCaSource {
+            cert: dir.path().join("org-ca.crt"),
+            key: dir.path().join("org-ca.key"),
+        })
+        .unwrap();
+
+        // A CA cert can't be loaded with the key of another
+        let other_ca = crate::test_fixtures::CertFixture::ca("other-ca");
+        std::fs::write(dir.path().join("other-ca.key"), &other_ca.key_pem).unwrap();
+        assert!(ExternalCa::load(&external_ca::ExternalCaSource {
+            cert: dir.path().join("org-ca.crt"),
+            key: dir.path().join("other-ca.key"),
+        })
+        .is_err());
+
+        let root = openssl_keyless_pair("root", &["-newkey", "rsa:2048"]);
+        let leaf = keyless_pair("leaf");
+        (*leaf).borrow_mut().signer = Some(Rc::clone(&root));
+        (*root).borrow_mut().signees.push(Signee::CertKeyPair(Rc::clone(&leaf)));
+
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        cluster_crypto.cert_key_pairs = vec![Rc::clone(&root), Rc::clone(&leaf)];
+        cluster_crypto.use_external_ca(external_ca.clone()).unwrap();
+        cluster_crypto
+            .regenerate_crypto(
+                RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                CnSanReplaceRules::try_from(vec![]).unwrap(),
+            )
+            .unwrap();
+
+        let cert = |pair: &Rc<RefCell<CertKeyPair>>| (*(**pair).borrow().distributed_cert).borrow().certificate.original.clone();
+        let (root, leaf) = (cert(&root), cert(&leaf));
+
+        // The root became an intermediate of the external CA, still signing its own signees
+        assert_eq!(root.subject_common_name().as_deref(), Some("root"));
+        assert_eq!(root.issuer_name(), external_ca.cert.subject_name());
+        crypto_utils::verify_signed_by_certificate(&root, &external_ca.cert).unwrap();
+        crypto_utils::verify_signed_by_certificate(&leaf, &root).unwrap();
+
+        // openssl also matches the AKID of the root against the SKID of the external CA
+        std::fs::write(dir.path().join("root.crt"), root.encode_pem()).unwrap();
+        let output = std::process::Command::new("openssl")
+            .args(["verify", "-CAfile", "org-ca.crt", "root.crt"])
+            .current_dir(dir.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    }
+
+    #[tokio::test]
+    async fn test_graft_cas() {
+        let dir = tempfile::tempdir().unwrap();
+        let grafted = crate::test_fixtures::CertFixture::ca("root");
+        std::fs::write(dir.path().join("root.pem"), format!("{}{}", grafted.cert_pem, grafted.key_pem)).unwrap();
+        let ca_grafts = CaGrafts::load(dir.path(), &PrivateKeyPolicy::default()).unwrap();
+
+        let root = openssl_keyless_pair("root@1700000000", &["-newkey", "rsa:2048"]);
+        // Unlike keyless_pair's, issued by the root, otherwise it would be taken for a keyless CA
+        let mut builder = X509CertificateBuilder::default();
+        builder.subject().append_common_name_utf8_string("leaf").unwrap();
+        builder.issuer().append_common_name_utf8_string("root@1700000000").unwrap();
+        let (leaf, _) = builder
+            .create_with_random_keypair(KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1))
+            .unwrap();
+        let leaf = pair_from_cert(leaf);
+        (*leaf).borrow_mut().signer = Some(Rc::clone(&root));
+        (*root).borrow_mut().signees.push(Signee::CertKeyPair(Rc::clone(&leaf)));
+
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        cluster_crypto.cert_key_pairs = vec![Rc::clone(&root), Rc::clone(&leaf)];
+        cluster_crypto.graft_cas(&ca_grafts).unwrap();
+        // The root's key is nowhere to be found, but it isn't needed with a graft
+        cluster_crypto.check_keyless_cas(false).unwrap();
+        cluster_crypto
+            .regenerate_crypto(
+                RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                CnSanReplaceRules::try_from(vec![]).unwrap(),
+            )
+            .unwrap();
+
+        let cert = |pair: &Rc<RefCell<CertKeyPair>>| (*(**pair).borrow().distributed_cert).borrow().certificate.original.clone();
+        let (root, leaf) = (cert(&root), cert(&leaf));
+        let grafted = CapturedX509Certificate::from_pem(&grafted.cert_pem).unwrap();
+
+        // The graft took the root's place as it is, and signs the root's signees
+        assert_eq!(root.encode_der().unwrap(), grafted.encode_der().unwrap());
+        assert_eq!(leaf.issuer_name(), grafted.subject_name());
+        crypto_utils::verify_signed_by_certificate(&leaf, &grafted).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mixed_chain() {
+        let rsa_root = openssl_keyless_pair("rsa-root", &["-newkey", "rsa:2048"]);
+        let ec_intermediate = openssl_keyless_pair("ec-intermediate", &["-newkey", "ec", "-pkeyopt", "ec_paramgen_curve:P-256"]);
+        let rsa_leaf = openssl_keyless_pair("rsa-leaf", &["-newkey", "rsa:2048"]);
+        for (signer, signee) in [(&rsa_root, &ec_intermediate), (&ec_intermediate, &rsa_leaf)] {
+            (**signee).borrow_mut().signer = Some(Rc::clone(signer));
+            (**signer).borrow_mut().signees.push(Signee::CertKeyPair(Rc::clone(signee)));
+        }
+
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        cluster_crypto.cert_key_pairs = vec![Rc::clone(&rsa_root), Rc::clone(&ec_intermediate), Rc::clone(&rsa_leaf)];
+        cluster_crypto
+            .regenerate_crypto(
+                RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                CnSanReplaceRules::try_from(vec![]).unwrap(),
+            )
+            .unwrap();
+
+        let cert = |pair: &Rc<RefCell<CertKeyPair>>| (*(**pair).borrow().distributed_cert).borrow().certificate.original.clone();
+        let (rsa_root, ec_intermediate, rsa_leaf) = (cert(&rsa_root), cert(&ec_intermediate), cert(&rsa_leaf));
+
+        // Each cert keeps the kind of key it had, and is signed with the algorithm of its signer's key
+        for (cert, signer, key_algorithm, signature_algorithm) in [
+            (&rsa_root, &rsa_root, KeyAlgorithm::Rsa, SignatureAlgorithm::RsaSha256),
+            (
+                &ec_intermediate,
+                &rsa_root,
+                KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1),
+                SignatureAlgorithm::RsaSha256,
+            ),
+            (&rsa_leaf, &ec_intermediate, KeyAlgorithm::Rsa, SignatureAlgorithm::EcdsaSha256),
+        ] {
+            assert_eq!(cert.key_algorithm(), Some(key_algorithm));
+            assert_eq!(cert.signature_algorithm(), Some(signature_algorithm));
+            crypto_utils::verify_signed_by_certificate(cert, signer).unwrap();
+        }
+        assert!(crypto_utils::verify_signed_by_certificate(&rsa_leaf, &rsa_root).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_basic_constraints_chain() {
+        let ec = |basic_constraints: &str| {
+            vec![
+                "-newkey".to_string(),
+                "ec".to_string(),
+                "-pkeyopt".to_string(),
+                "ec_paramgen_curve:P-256".to_string(),
+                "-addext".to_string(),
+                format!("basicConstraints=critical,{}", basic_constraints),
+            ]
+        };
+        let pair =
+            |common_name: &str, args: Vec<String>| openssl_keyless_pair(common_name, &args.iter().map(String::as_str).collect::<Vec<_>>());
+
+        let root = pair("root", ec("CA:TRUE,pathlen:1"));
+        let intermediate = pair("intermediate", ec("CA:TRUE,pathlen:0"));
+        let leaf = pair("leaf", ec("CA:FALSE"));
+        for (signer, signee) in [(&root, &intermediate), (&intermediate, &leaf)] {
+            (**signee).borrow_mut().signer = Some(Rc::clone(signer));
+            (**signer).borrow_mut().signees.push(Signee::CertKeyPair(Rc::clone(signee)));
+        }
+
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        cluster_crypto.cert_key_pairs = vec![Rc::clone(&root), Rc::clone(&intermediate), Rc::clone(&leaf)];
+        for cert_key_pair in &cluster_crypto.cert_key_pairs {
+            assert_eq!(
+                (**cert_key_pair).borrow().basic_constraints_violations().unwrap(),
+                Vec::<String>::new()
+            );
+        }
+
+        let cert = |pair: &Rc<RefCell<CertKeyPair>>| (*(**pair).borrow().distributed_cert).borrow().certificate.original.clone();
+        let extensions = |pair: &Rc<RefCell<CertKeyPair>>| {
+            let cert = cert(pair);
+            let original: &x509_certificate::rfc5280::Certificate = cert.as_ref().as_ref();
+            original.tbs_certificate.extensions.clone()
+        };
+        let original_extensions = [&root, &intermediate, &leaf].map(extensions);
+
+        cluster_crypto
+            .regenerate_crypto(
+                RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                CnSanReplaceRules::try_from(vec![]).unwrap(),
+            )
+            .unwrap();
+
+        // The intermediate stays a CA which can sign leaves, and the whole chain still verifies
+        for (pair, original_extensions) in [&root, &intermediate, &leaf].into_iter().zip(original_extensions) {
+            let basic_constraints_oid = bcder::Oid(&[85, 29, 19]);
+            let basic_constraints = |extensions: Option<x509_certificate::rfc5280::Extensions>| {
+                extensions
+                    .unwrap()
+                    .iter()
+                    .find(|ext| ext.id == basic_constraints_oid)
+                    .cloned()
+                    .unwrap()
+            };
+            assert_eq!(basic_constraints(extensions(pair)), basic_constraints(original_extensions));
+            assert_eq!((**pair).borrow().basic_constraints_violations().unwrap(), Vec::<String>::new());
+        }
+        crypto_utils::verify_signed_by_certificate(&cert(&root), &cert(&root)).unwrap();
+        crypto_utils::verify_signed_by_certificate(&cert(&intermediate), &cert(&root)).unwrap();
+        crypto_utils::verify_signed_by_certificate(&cert(&leaf), &cert(&intermediate)).unwrap();
+
+        // An intermediate under a root which allows no intermediates, a leaf signed by a non-CA,
+        // and a CA with a negative path length, which openssl happily makes
+        let strict_root = pair("strict-root", ec("CA:TRUE,pathlen:0"));
+        let not_a_ca = pair("not-a-ca", ec("CA:FALSE"));
+        let negative = pair("negative", ec("DER:30:06:01:01:ff:02:01:ff"));
+        let violations = |signer: &Rc<RefCell<CertKeyPair>>| {
+            let leaf = pair("leaf", ec("CA:FALSE"));
+            let intermediate = pair("intermediate", ec("CA:TRUE"));
+            (*leaf).borrow_mut().signer = Some(Rc::clone(&intermediate));
+            (*intermediate).borrow_mut().signer = Some(Rc::clone(signer));
+            let leaf_violations = (*leaf).borrow().basic_constraints_violations().unwrap();
+            leaf_violations
+        };
+
+        assert_eq!(
+            violations(&strict_root),
+            vec!["CN=strict-root (CA:TRUE, pathlen:0) has 1 intermediate CAs between it and CN=leaf".to_string()]
+        );
+        assert_eq!(
+            violations(&negative),
+            vec!["CN=negative (CA:TRUE, pathlen:-1) has 1 intermediate CAs between it and CN=leaf".to_string()]
+        );
+        let leaf_of_not_a_ca = pair("leaf", ec("CA:FALSE"));
+        (*leaf_of_not_a_ca).borrow_mut().signer = Some(Rc::clone(&not_a_ca));
+        assert_eq!(
+            (*leaf_of_not_a_ca).borrow().basic_constraints_violations().unwrap(),
+            vec!["CN=not-a-ca signs CN=leaf but isn't a CA (CA:FALSE)".to_string()]
+        );
+    }
+
+    /// A root, an intermediate and a leaf, each with its key, issued by openssl into dir along
+    /// with the bundles a cluster would typically have them in
+    fn openssl_three_level_chain(dir: &std::path::Path) {
+        let openssl = |args: &[&str]| {
+            let output = std::process::Command::new("openssl")
+                .current_dir(dir)
+                .args(["req", "-x509", "-nodes", "-days", "1"])
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        };
+
+        openssl(&[
+            "-newkey",
+            "rsa:2048",
+            "-subj",
+            "/CN=root",
+            "-keyout",
+            "root.key",
+            "-out",
+            "root.crt",
+            "-addext",
+            "basicConstraints=critical,CA:TRUE",
+        ]);
+        openssl(&[
+            "-newkey",
+            "ec",
+            "-pkeyopt",
+            "ec_paramgen_curve:P-256",
+            "-subj",
+            "/CN=intermediate",
+            "-keyout",
+            "intermediate.key",
+            "-out",
+            "intermediate.crt",
+            "-CA",
+            "root.crt",
+            "-CAkey",
+            "root.key",
+            "-addext",
+            "basicConstraints=critical,CA:TRUE,pathlen:0",
+        ]);
+        openssl(&[
+            "-newkey",
+            "rsa:2048",
+            "-subj",
+            "/CN=leaf",
+            "-keyout",
+            "tls.key",
+            "-out",
+            "tls.crt",
+            "-CA",
+            "intermediate.crt",
+            "-CAkey",
+            "intermediate.key",
+            "-addext",
+            "basicConstraints=critical,CA:FALSE",
+        ]);
+
+        // Cluster keys are PKCS#1 and SEC1 rather than openssl's default PKCS#8
+        for key in ["root.key", "intermediate.key", "tls.key"] {
+            let output = std::process::Command::new("openssl")
+                .current_dir(dir)
+                .args(["pkey", "-traditional", "-in", key, "-out", key])
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        }
+
+        let read = |file_name: &str| std::fs::read_to_string(dir.join(file_name)).unwrap();
+        std::fs::write(dir.join("ca-bundle.crt"), read("root.crt") + &read("intermediate.crt")).unwrap();
+        std::fs::write(dir.join("tls-chain.crt"), read("tls.crt") + &read("intermediate.crt")).unwrap();
+        std::fs::remove_file(dir.join("intermediate.crt")).unwrap();
+    }
+
+    const THREE_LEVEL_CHAIN_FILES: [&str; 7] = [
+        "root.crt",
+        "root.key",
+        "intermediate.key",
+        "tls.crt",
+        "tls.key",
+        "ca-bundle.crt",
+        "tls-chain.crt",
+    ];
+
+    /// Scan the given files, the same way the static dirs are scanned, and find the relationships
+    /// between the crypto objects in them
+    fn scan_files(dir: &std::path::Path, file_names: &[&str]) -> ClusterCryptoObjects {
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        for file_name in file_names {
+            let path = dir.join(file_name);
+            cluster_crypto.register_discovered_crypto_objects(
+                crypto_objects::process_pem_bundle(
+                    &std::fs::read_to_string(&path).unwrap(),
+                    &Location::Filesystem(FileLocation {
+                        path: path.to_string_lossy().to_string(),
+                        content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+                    }),
+                    &PrivateKeyPolicy::default(),
+                )
+                .unwrap(),
+            );
+        }
+        cluster_crypto.pair_certs_and_keys().unwrap();
+        cluster_crypto.fill_cert_key_signers().unwrap();
+        cluster_crypto.fill_signees().unwrap();
+        cluster_crypto
+    }
+
+    /// Like commit_to_etcd_and_disk, for certs which are all on disk
+    async fn commit_certs_to_disk(cluster_crypto: &ClusterCryptoObjects) {
+        let file_locations = |cert_key_pair: &CertKeyPair| {
+            (*cert_key_pair.distributed_cert)
+                .borrow()
+                .locations
+                .0
+                .iter()
+                .map(|location| match location {
+                    Location::Filesystem(file_location) => file_location.clone(),
+                    Location::K8s(_) => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for cert_key_pair in &cluster_crypto.cert_key_pairs {
+            let cert_key_pair = (**cert_key_pair).borrow().clone();
+            for file_location in file_locations(&cert_key_pair) {
+                cert_key_pair
+                    .commit_filesystem_cert(&file_location, &CryptoPolicies::default())
+                    .await
+                    .unwrap();
+            }
+        }
+        for flattened_intermediate in &cluster_crypto.flattened_intermediates {
+            let flattened_intermediate = (**flattened_intermediate).borrow().clone();
+            for file_location in file_locations(&flattened_intermediate) {
+                flattened_intermediate
+                    .remove_from_filesystem_bundle(&file_location, &CryptoPolicies::default())
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    fn openssl_verify(dir: &std::path::Path, args: &[&str]) {
+        let output = std::process::Command::new("openssl")
+            .current_dir(dir)
+            .arg("verify")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_three_level_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        openssl_three_level_chain(dir.path());
+        let file_names = THREE_LEVEL_CHAIN_FILES;
+        let read_pems = |file_name: &str| pem::parse_many(std::fs::read(dir.path().join(file_name)).unwrap()).unwrap();
+        let original_pems = file_names.map(read_pems);
+
+        let mut cluster_crypto = scan_files(dir.path(), &file_names);
+
+        // The intermediate is both a signee of the root and the signer of the leaf, and is
+        // regenerated between the two
+        assert_eq!(cluster_crypto.cert_key_pairs.len(), 3);
+        let pair = |common_name: &str| {
+            Rc::clone(
+                cluster_crypto
+                    .cert_key_pairs
+                    .iter()
+                    .find(|pair| signee::subject(pair) == format!("CN={}", common_name))
+                    .unwrap(),
+            )
+        };
+        let (root, intermediate, leaf) = (pair("root"), pair("intermediate"), pair("leaf"));
+        assert!(Rc::ptr_eq((*intermediate).borrow().signer.as_ref().unwrap(), &root));
+        assert!(Rc::ptr_eq((*leaf).borrow().signer.as_ref().unwrap(), &intermediate));
+        assert_eq!((*intermediate).borrow().num_parents(), 1);
+        assert_eq!((*leaf).borrow().num_parents(), 2);
+
+        cluster_crypto
+            .regenerate_crypto(
+                RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                CnSanReplaceRules::try_from(vec![]).unwrap(),
+            )
+            .unwrap();
+        commit_certs_to_disk(&cluster_crypto).await;
+
+        // Every copy of every cert, including the ones in bundles, got replaced
+        let new_pems = file_names.map(read_pems);
+        for (file_name, (original, new)) in file_names.iter().zip(original_pems.iter().zip(&new_pems)) {
+            if file_name.ends_with(".crt") {
+                assert_eq!(original.len(), new.len());
+                assert!(original.iter().zip(new).all(|(original, new)| original != new), "{}", file_name);
+            }
+        }
+        let [root_pems, _, _, leaf_pems, _, ca_bundle_pems, tls_chain_pems] = &new_pems;
+        assert_eq!(ca_bundle_pems[0], root_pems[0]);
+        assert_eq!(tls_chain_pems[0], leaf_pems[0]);
+        assert_eq!(ca_bundle_pems[1], tls_chain_pems[1]);
+
+        // openssl finds the regenerated intermediate through the AKID of the leaf, and the root
+        // through the AKID of the intermediate
+        openssl_verify(dir.path(), &["-CAfile", "root.crt", "-untrusted", "tls-chain.crt", "tls.crt"]);
+    }
+
+    #[tokio::test]
+    async fn test_flatten_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        openssl_three_level_chain(dir.path());
+        let mut cluster_crypto = scan_files(dir.path(), &THREE_LEVEL_CHAIN_FILES);
+
+        assert!(cluster_crypto.flatten_chain("nonexistent").is_err());
+        assert!(cluster_crypto.flatten_chain("root").is_err());
+        cluster_crypto.flatten_chain("intermediate").unwrap();
+
+        let pair = |common_name: &str| {
+            Rc::clone(
+                cluster_crypto
+                    .cert_key_pairs
+                    .iter()
+                    .find(|pair| signee::subject(pair) == format!("CN={}", common_name))
+                    .unwrap(),
+            )
+        };
+        let (root, intermediate, leaf) = (pair("root"), pair("intermediate"), pair("leaf"));
+        assert!(Rc::ptr_eq((*leaf).borrow().signer.as_ref().unwrap(), &root));
+        assert!((*intermediate).borrow().signees.is_empty());
+        assert_eq!((*root).borrow().signees.len(), 2);
+
+        cluster_crypto
+            .regenerate_crypto(
+                RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                CnSanReplaceRules::try_from(vec![]).unwrap(),
+            )
+            .unwrap();
+        commit_certs_to_disk(&cluster_crypto).await;
+
+        // The intermediate is gone from both bundles, and the leaf is issued by the root
+        let read_pems = |file_name: &str| pem::parse_many(std::fs::read(dir.path().join(file_name)).unwrap()).unwrap();
+        assert_eq!(read_pems("ca-bundle.crt"), read_pems("root.crt"));
+        assert_eq!(read_pems("tls-chain.crt"), read_pems("tls.crt"));
+        assert_eq!((*(*leaf).borrow().distributed_cert).borrow().certificate.issuer, "CN=root");
+        openssl_verify(dir.path(), &["-CAfile", "ca-bundle.crt", "tls-chain.crt"]);
+    }
+
+    #[test]
+    fn test_rotation_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        openssl_three_level_chain(dir.path());
+        let cluster_crypto = scan_files(dir.path(), &THREE_LEVEL_CHAIN_FILES);
+
+        let pair = |cluster_crypto: &ClusterCryptoObjects, common_name: &str| {
+            Rc::clone(
+                cluster_crypto
+                    .cert_key_pairs
+                    .iter()
+                    .find(|pair| signee::subject(pair) == format!("CN={}", common_name))
+                    .unwrap(),
+            )
+        };
+        let rotation_roots = |cluster_crypto: &ClusterCryptoObjects, common_names: &[&str]| {
+            cluster_crypto
+                .rotation_roots(|cert_key_pair| {
+                    let subject = (*cert_key_pair.distributed_cert).borrow().certificate.subject.clone();
+                    common_names.iter().any(|common_name| subject == format!("CN={}", common_name))
+                })
+                .iter()
+                .map(signee::subject)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(rotation_roots(&cluster_crypto, &["leaf"]), vec!["CN=leaf"]);
+        // The leaf is rotated along with the intermediate which signed it
+        assert_eq!(rotation_roots(&cluster_crypto, &["leaf", "intermediate"]), vec!["CN=intermediate"]);
+        assert!(rotation_roots(&cluster_crypto, &[]).is_empty());
+
+        // Without the intermediate's key, the leaf can only be re-signed by a new intermediate
+        let intermediate = pair(&cluster_crypto, "intermediate");
+        (*intermediate).borrow_mut().distributed_private_key = None;
+        assert_eq!(rotation_roots(&cluster_crypto, &["leaf"]), vec!["CN=intermediate"]);
+    }
+
+    #[test]
+    fn test_check_skipped_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        openssl_three_level_chain(dir.path());
+        let mut cluster_crypto = scan_files(dir.path(), &THREE_LEVEL_CHAIN_FILES);
+
+        let mut check = |skip: &[&str], only: &[&str]| {
+            let to_strings = |patterns: &[&str]| patterns.iter().map(|pattern| pattern.to_string()).collect::<Vec<_>>();
+            cluster_crypto.filter_by_cn(CnFilter::new(&to_strings(skip), &to_strings(only)).unwrap());
+            let walk_roots = cluster_crypto.rotation_roots(|cert_key_pair| cluster_crypto.selected_by_cn(cert_key_pair));
+            cluster_crypto
+                .check_skipped_untouched(&walk_roots)
+                .map(|()| walk_roots.iter().map(signee::subject).collect::<Vec<_>>())
+        };
+
+        // A skipped leaf would still be re-signed by its regenerated signer
+        assert!(check(&["leaf"], &[]).is_err());
+        assert_eq!(check(&["root"], &[]).unwrap(), vec!["CN=intermediate"]);
+        assert_eq!(check(&["root", "intermediate"], &[]).unwrap(), vec!["CN=leaf"]);
+        assert_eq!(check(&[], &["inter*"]).unwrap(), vec!["CN=intermediate"]);
+        assert!(check(&["leaf"], &["inter*"]).is_err());
+    }
+
+    #[test]
+    fn test_check_keyless_cas() {
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        cluster_crypto.cert_key_pairs.push(keyless_pair("admin-kubeconfig-signer"));
+        assert!(cluster_crypto.check_keyless_cas(false).is_ok());
+
+        // The root of a service mesh plugged-in CA is known by where it is rather than by its name
+        let mesh_root = keyless_pair("mesh-root-ca");
+        (*(*mesh_root).borrow().distributed_cert)
+            .borrow_mut()
+            .locations
+            .0
+            .insert(Location::k8s_yaml(
+                &locations::K8sResourceLocation::new(Some("istio-system"), "Secret", "cacerts", "v1"),
+                &locations::YamlLocation::new("/data", "root-cert.pem", locations::FieldEncoding::Base64),
+            ));
+        cluster_crypto.cert_key_pairs.push(mesh_root);
+        assert!(cluster_crypto.check_keyless_cas(false).is_ok());
+
+        cluster_crypto.cert_key_pairs.push(keyless_pair("some-keyless-ca"));
+        assert!(cluster_crypto.check_keyless_cas(false).is_err());
+        assert!(cluster_crypto.check_keyless_cas(true).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_sequential_serials() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = crate::test_fixtures::CertFixture::ca("root");
+        let mut file_names = vec!["ca.crt".to_string(), "ca.key".to_string()];
+        std::fs::write(dir.path().join("ca.crt"), &ca.cert_pem).unwrap();
+        std::fs::write(dir.path().join("ca.key"), &ca.key_pem).unwrap();
+        // Enough leaves for the signing to be spread over threads
+        for i in 0..8 {
+            let leaf = crate::test_fixtures::CertFixture::leaf(&format!("leaf-{}", i), &ca);
+            std::fs::write(dir.path().join(format!("leaf-{}.crt", i)), &leaf.cert_pem).unwrap();
+            std::fs::write(dir.path().join(format!("leaf-{}.key", i)), &leaf.key_pem).unwrap();
+            file_names.extend([format!("leaf-{}.crt", i), format!("leaf-{}.key", i)]);
+        }
+
+        let mut serials_of_runs = Vec::new();
+        for file_names in [file_names.clone(), file_names.into_iter().rev().collect()] {
+            let mut cluster_crypto = scan_files(dir.path(), &file_names.iter().map(String::as_str).collect::<Vec<_>>());
+            cluster_crypto.policies.serial = SerialPolicy::Sequential;
+            cluster_crypto
+                .regenerate_crypto(
+                    RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                    CnSanReplaceRules::try_from(vec![]).unwrap(),
+                )
+                .unwrap();
+
+            let mut serials = cluster_crypto
+                .cert_key_pairs
+                .iter()
+                .map(|pair| {
+                    let cert = (*(**pair).borrow().distributed_cert).borrow().certificate.clone();
+                    (cert.subject, cert.original.serial_number_asn1().as_slice().to_vec())
+                })
+                .collect::<Vec<_>>();
+            serials.sort();
+            serials_of_runs.push(serials);
+        }
+
+        // The same serial numbers, no matter in which order the certs were found or signed
+        assert_eq!(serials_of_runs[0], serials_of_runs[1]);
+        let serials = &serials_of_runs[0];
+        assert_eq!(serials.len(), 9);
+        assert!(serials.contains(&("CN=root".to_string(), vec![1])));
+        let mut numbers = serials.iter().map(|(_, serial)| serial.clone()).collect::<Vec<_>>();
+        numbers.sort();
+        assert_eq!(numbers, (1..=9).map(|serial| vec![serial]).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_validity_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = crate::test_fixtures::CertFixture::ca("root");
+        let leaf = crate::test_fixtures::CertFixture::leaf("leaf", &ca);
+        let files = [
+            ("ca.crt", &ca.cert_pem),
+            ("ca.key", &ca.key_pem),
+            ("leaf.crt", &leaf.cert_pem),
+            ("leaf.key", &leaf.key_pem),
+        ];
+        for (file_name, contents) in files {
+            std::fs::write(dir.path().join(file_name), contents).unwrap();
+        }
+
+        // Each run regenerates by its own policies, whatever other runs in the process use
+        let mut leaf_not_afters = Vec::new();
+        for cert_validity in [Validity::Preserve, Validity::Lifetime(chrono::Duration::days(30))] {
+            let mut cluster_crypto = scan_files(dir.path(), &files.map(|(file_name, _)| file_name));
+            cluster_crypto.policies.validity.cert = cert_validity;
+            cluster_crypto
+                .regenerate_crypto(
+                    RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                    CnSanReplaceRules::try_from(vec![]).unwrap(),
+                )
+                .unwrap();
+
+            let leaf = cluster_crypto
+                .cert_key_pairs
+                .iter()
+                .map(|pair| (*(**pair).borrow().distributed_cert).borrow().certificate.clone())
+                .find(|cert| cert.subject == "CN=leaf")
+                .unwrap();
+            leaf_not_afters.push(leaf.not_after());
+        }
+
+        // The fixtures are valid for a day
+        let now = chrono::Utc::now();
+        assert!(leaf_not_afters[0] < now + chrono::Duration::days(2));
+        assert!(leaf_not_afters[1] > now + chrono::Duration::days(29));
+    }
+}
@@ -0,0 +1,352 @@
+use super::{
+    cert_key_pair::basic_constraints::BasicConstraints,
+    certificate::Certificate,
+    crypto_objects::{self, CryptoObject},
+    crypto_utils,
+    keys::PrivateKey,
+    private_key_format::PrivateKeyPolicy,
+    resource_kinds::ResourceKindPolicy,
+    scanning, ClusterCryptoObjects, CryptoPolicies,
+};
+use crate::{
+    audit::{self, AuditAction},
+    capabilities::Capabilities,
+    file_utils,
+    k8s_etcd::{EtcdAccess, InMemoryK8sEtcd},
+    namespace_filter::NamespaceFilter,
+};
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use x509_certificate::rfc5280;
+use zeroize::Zeroizing;
+
+/// A CA cert and its private key from outside the cluster (exported from another cluster with
+/// export-cas, or issued centrally), which takes the place of the cluster's CA of the same name
+/// as it is, rather than that CA being regenerated with a new key. Its signees are still
+/// regenerated with new keys of their own, so clusters grafted with the same CAs share a trust
+/// hierarchy while none of them share a leaf key. See --graft-cas. Leaf certs issued by a grafted
+/// CA can be grafted the same way, see CaGrafts::load_material_dir
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GraftedCert {
+    pub(crate) certificate: Certificate,
+    pub(crate) private_key: PrivateKey,
+}
+
+impl GraftedCert {
+    /// PEM files holding the cert and its private key between them, in any order
+    fn load(paths: &[PathBuf], private_key_policy: &PrivateKeyPolicy) -> Result<Self> {
+        let (mut certificate, mut private_key) = (None, None);
+        for path in paths {
+            let contents = Zeroizing::new(std::fs::read(path).with_context(|| format!("reading {:?}", path))?);
+            for pem in pem::parse_many(contents.as_slice()).context("parsing pem")? {
+                match crypto_objects::process_single_pem(&pem, private_key_policy)? {
+                    Some(CryptoObject::Certificate(cert)) => ensure!(certificate.replace(cert).is_none(), "more than one cert"),
+                    Some(CryptoObject::PrivateKey(key, _)) => ensure!(private_key.replace(*key).is_none(), "more than one private key"),
+                    _ => bail!("{} is neither a cert nor a private key", pem.tag()),
+                }
+            }
+        }
+
+        let certificate = certificate.context("no cert")?;
+        let private_key = private_key.context("no private key")?;
+        ensure!(
+            crypto_utils::key_matches_cert(&private_key, &certificate)?,
+            "private key doesn't match the cert"
+        );
+
+        Ok(Self { certificate, private_key })
+    }
+
+    fn load_ca(paths: &[PathBuf], private_key_policy: &PrivateKeyPolicy) -> Result<Self> {
+        let graft = Self::load(paths, private_key_policy)?;
+        ensure!(is_ca(&graft.certificate)?, "{} isn't a CA", graft.certificate.subject);
+        Ok(graft)
+    }
+
+    fn name(&self) -> Result<String> {
+        Ok(graft_name(
+            &self
+                .certificate
+                .original
+                .subject_common_name()
+                .with_context(|| format!("cert {} has no CN", self.certificate.subject))?,
+        )
+        .to_string())
+    }
+}
+
+/// The certs to graft, by graft_name
+pub(crate) struct CaGrafts {
+    cas: HashMap<String, GraftedCert>,
+    /// Leaf certs issued by the grafted CAs, see load_material_dir
+    leaves: HashMap<String, GraftedCert>,
+}
+
+impl CaGrafts {
+    /// Every .pem file in the directory, each holding a CA cert and its private key, as written
+    /// by export-cas
+    pub(crate) fn load(dir: &Path, private_key_policy: &PrivateKeyPolicy) -> Result<Self> {
+        let mut cas = HashMap::new();
+        for path in file_utils::globvec(dir, "*.pem")? {
+            let graft = GraftedCert::load_ca(std::slice::from_ref(&path), private_key_policy)
+                .with_context(|| format!("loading CA from {:?}", path))?;
+            insert(&mut cas, graft, dir)?;
+        }
+
+        ensure!(!cas.is_empty(), "no CAs in {:?}", dir);
+
+        Ok(Self {
+            cas,
+            leaves: HashMap::new(),
+        })
+    }
+
+    /// A directory with a subdirectory per CA, named after its CN (less the @timestamp suffix,
+    /// and with the characters export-cas replaces in file names replaced), holding the CA as
+    /// ca.crt and ca.key. Next to them, any number of leaf certs issued by the CA, each as
+    /// NAME.crt and NAME.key, which take the place of the cluster's leaf certs of the same CN.
+    /// Intermediate CAs get a subdirectory of their own, like roots
+    pub(crate) fn load_material_dir(dir: &Path, private_key_policy: &PrivateKeyPolicy) -> Result<Self> {
+        let (mut cas, mut leaves) = (HashMap::new(), HashMap::new());
+        // globvec leaves out directories
+        let mut ca_dirs = std::fs::read_dir(dir)
+            .with_context(|| format!("reading {:?}", dir))?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        ca_dirs.retain(|path| path.is_dir() && !path.is_symlink());
+        ca_dirs.sort();
+        for ca_dir in ca_dirs {
+            let ca = GraftedCert::load_ca(&[ca_dir.join("ca.crt"), ca_dir.join("ca.key")], private_key_policy)
+                .with_context(|| format!("loading CA from {:?}", ca_dir))?;
+            let name = ca.name()?;
+            ensure!(
+                ca_dir
+                    .file_name()
+                    .is_some_and(|dir_name| dir_name.to_string_lossy() == file_name(&name)),
+                "{:?} holds CA {}, expected it to be named {:?}",
+                ca_dir,
+                name,
+                file_name(&name)
+            );
+
+            for cert_path in file_utils::globvec(&ca_dir, "*.crt")? {
+                if cert_path.file_name().is_some_and(|file_name| file_name == "ca.crt") {
+                    continue;
+                }
+
+                let leaf = GraftedCert::load(&[cert_path.clone(), cert_path.with_extension("key")], private_key_policy)
+                    .with_context(|| format!("loading leaf cert from {:?}", cert_path))?;
+                ensure!(
+                    !is_ca(&leaf.certificate)?,
+                    "{:?} is a CA, which needs a directory of its own",
+                    cert_path
+                );
+                ensure!(
+                    leaf.certificate.issuer == ca.certificate.subject
+                        && crypto_utils::verify_signed_by_certificate(&leaf.certificate.original, &ca.certificate.original).is_ok(),
+                    "{:?} isn't issued by {}",
+                    cert_path,
+                    ca.certificate.subject
+                );
+                insert(&mut leaves, leaf, &ca_dir)?;
+            }
+
+            insert(&mut cas, ca, dir)?;
+        }
+
+        ensure!(!cas.is_empty(), "no CA directories in {:?}", dir);
+
+        Ok(Self { cas, leaves })
+    }
+
+    /// The CA grafted in place of the cluster's CA with the given CN
+    pub(crate) fn get(&self, common_name: &str) -> Option<&GraftedCert> {
+        self.cas.get(graft_name(common_name))
+    }
+
+    /// The leaf cert grafted in place of the cluster's leaf cert with the given CN
+    pub(crate) fn get_leaf(&self, common_name: &str) -> Option<&GraftedCert> {
+        self.leaves.get(graft_name(common_name))
+    }
+
+    pub(crate) fn names(&self) -> impl Iterator<Item = &String> {
+        self.cas.keys().chain(self.leaves.keys())
+    }
+}
+
+fn insert(grafts: &mut HashMap<String, GraftedCert>, graft: GraftedCert, dir: &Path) -> Result<()> {
+    let name = graft.name()?;
+    ensure!(
+        grafts.insert(name.clone(), graft).is_none(),
+        "more than one cert named {} in {:?}",
+        name,
+        dir
+    );
+    Ok(())
+}
+
+/// CAs are matched by their CN, less the @<unix timestamp> suffix OpenShift's operators add to
+/// the CNs of the CAs they create, which is different in every cluster
+pub(crate) fn graft_name(common_name: &str) -> &str {
+    match common_name.rsplit_once('@') {
+        Some((name, timestamp)) if !timestamp.is_empty() && timestamp.bytes().all(|byte| byte.is_ascii_digit()) => name,
+        _ => common_name,
+    }
+}
+
+pub(crate) fn is_ca(certificate: &Certificate) -> Result<bool> {
+    let original: &rfc5280::Certificate = certificate.original.as_ref().as_ref();
+    Ok(BasicConstraints::from_tbs_certificate(&original.tbs_certificate)?.is_some_and(|basic_constraints| basic_constraints.ca))
+}
+
+/// Scan a cluster (without modifying anything) and write each of its CAs whose private key it
+/// has, along with the key, into its own .pem file in the directory (which must not exist yet or
+/// be empty), to be grafted into other clusters with --graft-cas. Of CAs with the same name
+/// (usually left over from past rotations), the one valid the longest is exported
+pub(crate) async fn export_cas(etcd_endpoint: &str, etcd_access: &EtcdAccess, static_dirs: Vec<PathBuf>, dir: &Path) -> Result<()> {
+    file_utils::create_empty_private_dir(dir, "export")?;
+
+    let in_memory_etcd_client = Arc::new(InMemoryK8sEtcd::new(
+        etcd_access.connect(etcd_endpoint).await?,
+        etcd_access,
+        NamespaceFilter::default(),
+        ResourceKindPolicy::default(),
+    ));
+    let capabilities = Capabilities::detect(&in_memory_etcd_client, None)
+        .await
+        .context("detecting cluster capabilities")?;
+    let discovered_crypto_objects = scanning::crypto_scan(in_memory_etcd_client, static_dirs, capabilities, CryptoPolicies::default())
+        .await
+        .context("scanning")?;
+
+    let mut cluster_crypto = ClusterCryptoObjects::new();
+    cluster_crypto.register_discovered_crypto_objects(discovered_crypto_objects);
+    cluster_crypto.pair_certs_and_keys().context("pairing certs and keys")?;
+
+    let mut cas = BTreeMap::new();
+    for cert_key_pair in &cluster_crypto.cert_key_pairs {
+        let cert_key_pair = (**cert_key_pair).borrow();
+        let Some(private_key) = &cert_key_pair.distributed_private_key else {
+            continue;
+        };
+        let certificate = (*cert_key_pair.distributed_cert).borrow().certificate.clone();
+        if !is_ca(&certificate)? {
+            continue;
+        }
+
+        let graft = GraftedCert {
+            certificate,
+            private_key: (**private_key).borrow().key.clone(),
+        };
+        let name = graft.name()?;
+        match cas.get(&name) {
+            Some(GraftedCert { certificate, .. }) if certificate.not_after() >= graft.certificate.not_after() => {}
+            _ => {
+                cas.insert(name, graft);
+            }
+        }
+    }
+
+    for (name, graft) in &cas {
+        let path = dir.join(format!("{}.pem", file_name(name)));
+        let contents = Zeroizing::new(format!(
+            "{}{}",
+            graft.certificate.original.encode_pem(),
+            pem::encode(&graft.private_key.pem()?)
+        ));
+        // Distinct names can still make the same file name
+        ensure!(!path.exists(), "more than one CA would be exported to {:?}", path);
+        file_utils::write_private(&path, contents.as_bytes())?;
+        audit::record(AuditAction::FileWrite, &path.to_string_lossy(), Some(contents.as_bytes()))?;
+    }
+
+    println!("Exported {} CAs to {:?}", cas.len(), dir);
+
+    Ok(())
+}
+
+/// CA names are only used as file names for telling the files apart, the CAs are matched by
+/// their certs when grafted
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|char| match char {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => char,
+            _ => '_',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::CertFixture;
+
+    #[test]
+    fn test_graft_name() {
+        assert_eq!(
+            graft_name("openshift-kube-apiserver-operator_kube-apiserver-to-kubelet-signer@1700000000"),
+            "openshift-kube-apiserver-operator_kube-apiserver-to-kubelet-signer"
+        );
+        assert_eq!(graft_name("kube-csr-signer_@1700000000"), "kube-csr-signer_");
+        assert_eq!(graft_name("etcd-signer"), "etcd-signer");
+        assert_eq!(graft_name("user@example.com"), "user@example.com");
+        assert_eq!(graft_name("trailing@"), "trailing@");
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = CertFixture::ca("etcd-signer@1700000000");
+        let leaf = CertFixture::leaf("etcd-peer", &ca);
+
+        std::fs::write(dir.path().join("etcd-signer.pem"), format!("{}{}", ca.key_pem, ca.cert_pem)).unwrap();
+        let grafts = CaGrafts::load(dir.path(), &PrivateKeyPolicy::default()).unwrap();
+        assert_eq!(grafts.names().collect::<Vec<_>>(), vec!["etcd-signer"]);
+        assert!(grafts.get("etcd-signer@1800000000").is_some());
+        assert!(grafts.get("etcd-metric-signer").is_none());
+
+        // Only CAs can be grafted, and only with their own key
+        std::fs::write(dir.path().join("etcd-peer.pem"), format!("{}{}", leaf.cert_pem, leaf.key_pem)).unwrap();
+        assert!(CaGrafts::load(dir.path(), &PrivateKeyPolicy::default()).is_err());
+        std::fs::write(dir.path().join("etcd-peer.pem"), format!("{}{}", ca.cert_pem, leaf.key_pem)).unwrap();
+        assert!(CaGrafts::load(dir.path(), &PrivateKeyPolicy::default()).is_err());
+    }
+
+    #[test]
+    fn test_load_material_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = CertFixture::ca("etcd-signer@1700000000");
+        let leaf = CertFixture::leaf("system:etcd-peer:master-0", &ca);
+        let other_ca = CertFixture::ca("other-signer");
+
+        let ca_dir = dir.path().join("etcd-signer");
+        std::fs::create_dir(&ca_dir).unwrap();
+        std::fs::write(ca_dir.join("ca.crt"), &ca.cert_pem).unwrap();
+        std::fs::write(ca_dir.join("ca.key"), &ca.key_pem).unwrap();
+        std::fs::write(ca_dir.join("peer.crt"), &leaf.cert_pem).unwrap();
+        std::fs::write(ca_dir.join("peer.key"), &leaf.key_pem).unwrap();
+
+        let grafts = CaGrafts::load_material_dir(dir.path(), &PrivateKeyPolicy::default()).unwrap();
+        assert!(grafts.get("etcd-signer@1800000000").is_some());
+        assert!(grafts.get_leaf("system:etcd-peer:master-0").is_some());
+        assert!(grafts.get("system:etcd-peer:master-0").is_none());
+
+        // Leafs have to be issued by the CA of their directory, and have their keys
+        std::fs::remove_file(ca_dir.join("peer.key")).unwrap();
+        assert!(CaGrafts::load_material_dir(dir.path(), &PrivateKeyPolicy::default()).is_err());
+        let other_leaf = CertFixture::leaf("system:etcd-peer:master-0", &other_ca);
+        std::fs::write(ca_dir.join("peer.crt"), &other_leaf.cert_pem).unwrap();
+        std::fs::write(ca_dir.join("peer.key"), &other_leaf.key_pem).unwrap();
+        assert!(CaGrafts::load_material_dir(dir.path(), &PrivateKeyPolicy::default()).is_err());
+        std::fs::remove_file(ca_dir.join("peer.crt")).unwrap();
+        std::fs::remove_file(ca_dir.join("peer.key")).unwrap();
+
+        // Directories are named after their CA
+        std::fs::rename(&ca_dir, dir.path().join("other-signer")).unwrap();
+        assert!(CaGrafts::load_material_dir(dir.path(), &PrivateKeyPolicy::default()).is_err());
+    }
+}
@@ -1,37 +1,44 @@
 use super::{
+    ca_graft::GraftedCert,
     certificate::Certificate,
-    crypto_utils::encode_tbs_cert_to_der,
+    crypto_utils::{self, encode_tbs_cert_to_der},
     distributed_cert::DistributedCert,
     distributed_private_key::DistributedPrivateKey,
     distributed_public_key::DistributedPublicKey,
     keys::PrivateKey,
-    locations::{FileContentLocation, FileLocation, K8sLocation, Location},
+    locations::{FileContentLocation, FileLocation, K8sLocation, Location, PemBundleRole},
     pem_utils,
-    signee::Signee,
+    serial_policy::SerialSequence,
+    signee::{self, Signee, MAX_SIGNER_CHAIN_DEPTH},
+    CryptoPolicies,
 };
 use crate::{
     cluster_crypto::locations::LocationValueType,
     cnsanreplace::CnSanReplaceRules,
-    file_utils::{get_filesystem_yaml, recreate_yaml_at_location_with_new_pem},
-    k8s_etcd::{get_etcd_yaml, InMemoryK8sEtcd},
+    deterministic,
+    file_utils::{
+        self, read_resource_data_entry, recreate_file_yaml_at_location_with_new_pem, recreate_json_at_location_with_new_pem,
+        remove_pem_from_file_yaml_at_location, remove_pem_from_json_at_location, write_if_changed, FileKind,
+    },
+    k8s_etcd::{get_etcd_document, get_etcd_yaml, put_etcd_document_if_changed, InMemoryK8sEtcd},
     rsa_key_pool::RsaKeyPool,
 };
 use anyhow::{bail, Context, Result};
 use bcder::BitString;
 use bytes::Bytes;
 use fn_error_context::context;
-use rsa::{signature::Signer, RsaPrivateKey};
-use std::{cell::RefCell, fmt::Display, rc::Rc};
-use tokio::{self, io::AsyncReadExt};
-use x509_certificate::{
-    rfc5280::{self, AlgorithmIdentifier},
-    CapturedX509Certificate, InMemorySigningKeyPair, KeyAlgorithm, Sign, X509Certificate,
-};
+use std::{cell::RefCell, fmt::Display, path::Path, rc::Rc};
+use x509_cert::ext::pkix::SubjectKeyIdentifier;
+use x509_certificate::{rfc3280, rfc5280, CapturedX509Certificate, InMemorySigningKeyPair, KeyAlgorithm, Sign, X509Certificate};
 
+mod akid;
+pub(crate) mod basic_constraints;
 mod cert_mutations;
-mod skid;
+pub(crate) mod skid;
+
+use basic_constraints::BasicConstraints;
 
-const SUBJECT_ALTERNATIVE_NAME_OID: [u8; 3] = [85, 29, 17];
+pub(crate) const SUBJECT_ALTERNATIVE_NAME_OID: [u8; 3] = [85, 29, 17];
 const SUBJECT_KEY_IDENTIFIER_OID: [u8; 3] = [85, 29, 14];
 const AUTHORITY_KEY_IDENTIFIER_OID: [u8; 3] = [85, 29, 35];
 
@@ -43,45 +50,254 @@ pub(crate) struct CertKeyPair {
     /// The signer is the cert that signed this cert. If this is a self-signed cert, then this will
     /// be None
     pub(crate) signer: Option<Rc<RefCell<CertKeyPair>>>,
-    /// The signees are the certs or jwts that this cert has signed
+    /// The signees are the certs, jwts or CRLs that this cert has signed
     pub(crate) signees: Vec<Signee>,
     /// Sometimes cert public keys also appear on their own, outside the cert, so we need to track
     /// them
     pub(crate) associated_public_key: Option<Rc<RefCell<DistributedPublicKey>>>,
+    /// When this pair was moved under a different signer than the one which originally signed it
+    /// (see ClusterCryptoObjects::flatten_chain), the subject of the original cert of its new
+    /// signer, which replaces its issuer when it's re-signed
+    pub(crate) new_issuer: Option<rfc3280::Name>,
+    /// The SKID of the new issuer of a root re-signed by an external CA (see
+    /// ClusterCryptoObjects::use_external_ca), which replaces its AKID. Pairs with a signer get
+    /// theirs from the signer
+    pub(crate) new_issuer_skid: Option<SubjectKeyIdentifier>,
+    /// The cert taking the place of this one rather than it being regenerated, see
+    /// ClusterCryptoObjects::graft_cas
+    pub(crate) graft: Option<GraftedCert>,
     pub(crate) regenerated: bool,
 }
 
+/// A cert-key pair halfway through being regenerated, see CertKeyPair::prepare_regeneration
+pub(crate) struct Regeneration {
+    new_key_pair: InMemorySigningKeyPair,
+    private_key: PrivateKey,
+    cert: RegeneratedCert,
+    signees_cn_san_replace_rules: CnSanReplaceRules,
+}
+
+impl Regeneration {
+    /// The bytes to sign, unless the new cert needs no signing (e.g. it's a grafted CA)
+    pub(crate) fn tbs_der(&self) -> Option<&[u8]> {
+        match &self.cert {
+            RegeneratedCert::Signed(_) => None,
+            RegeneratedCert::Unsigned(unsigned_cert) => Some(&unsigned_cert.tbs_der),
+        }
+    }
+}
+
+enum RegeneratedCert {
+    Signed(CapturedX509Certificate),
+    Unsigned(UnsignedCert),
+}
+
+struct UnsignedCert {
+    tbs_certificate: rfc5280::TbsCertificate,
+    signature_algorithm: rfc5280::AlgorithmIdentifier,
+    /// tbs_certificate encoded to DER
+    tbs_der: Vec<u8>,
+}
+
+impl UnsignedCert {
+    fn into_cert(self, signature: Vec<u8>) -> Result<CapturedX509Certificate> {
+        // Create a full certificate by combining the to-be-signed part with the signature itself
+        let cert = rfc5280::Certificate {
+            tbs_certificate: self.tbs_certificate,
+            signature_algorithm: self.signature_algorithm,
+            signature: BitString::new(0, Bytes::from(signature)),
+        };
+
+        // Encode the entire cert as DER and reload it into a CapturedX509Certificate which is the
+        // type we use in our structs
+        Ok(CapturedX509Certificate::from_der(X509Certificate::from(cert).encode_der()?)?)
+    }
+}
+
 impl CertKeyPair {
     pub(crate) fn num_parents(&self) -> usize {
-        if let Some(signer) = self.signer.as_ref() {
-            1 + signer.borrow().num_parents()
-        } else {
-            0
+        let mut num_parents = 0;
+        let mut signer = self.signer.clone();
+
+        // Bounded, as this is also used to display pairs whose signer chain turned out to be cyclic
+        while let Some(current) = signer {
+            num_parents += 1;
+            if num_parents >= MAX_SIGNER_CHAIN_DEPTH {
+                break;
+            }
+            signer = (*current).borrow().signer.clone();
+        }
+
+        num_parents
+    }
+
+    /// Make sure following the signers of this pair leads to a root, and not back to a signer
+    /// which was already seen
+    pub(crate) fn check_signer_chain(&self) -> Result<()> {
+        let mut signer_chain: Vec<Rc<RefCell<CertKeyPair>>> = Vec::new();
+        let mut signer = self.signer.clone();
+
+        while let Some(current) = signer {
+            let seen = std::ptr::eq(current.as_ptr(), self) || signer_chain.iter().any(|seen| Rc::ptr_eq(seen, &current));
+            if seen || signer_chain.len() >= MAX_SIGNER_CHAIN_DEPTH {
+                bail!(
+                    "{} {} -> {}",
+                    if seen { "signer cycle:" } else { "signer chain too deep:" },
+                    (*self.distributed_cert).borrow().certificate.subject,
+                    signer_chain
+                        .iter()
+                        .chain([&current])
+                        .map(signee::subject)
+                        .collect::<Vec<_>>()
+                        .join(" -> ")
+                );
+            }
+
+            signer = (*current).borrow().signer.clone();
+            signer_chain.push(current);
+        }
+
+        Ok(())
+    }
+
+    /// Check that the BasicConstraints of the certs up the signer chain of this pair allow them to
+    /// sign it: its direct signer has to be a CA, and each signer further up has to allow as many
+    /// intermediate CAs below it as there are between it and this pair. Regeneration keeps the
+    /// constraints as they are, so a chain violating them is broken before and after, which is
+    /// worth knowing about but not a reason to refuse to regenerate it. Returns a description of
+    /// each violation
+    pub(crate) fn basic_constraints_violations(&self) -> Result<Vec<String>> {
+        let subject = |cert_key_pair: &CertKeyPair| (*cert_key_pair.distributed_cert).borrow().certificate.subject.clone();
+        let basic_constraints = |cert_key_pair: &CertKeyPair| {
+            let distributed_cert = (*cert_key_pair.distributed_cert).borrow();
+            let original: &rfc5280::Certificate = distributed_cert.certificate.original.as_ref().as_ref();
+            BasicConstraints::from_tbs_certificate(&original.tbs_certificate)
+                .with_context(|| format!("reading basic constraints of {}", subject(cert_key_pair)))
+        };
+
+        let mut violations = vec![];
+        let mut signer = self.signer.clone();
+        let mut intermediates = 0;
+
+        // Bounded, as the signer chain is only checked for cycles right before regeneration
+        while let Some(current) = signer {
+            let current_pair = (*current).borrow();
+            match basic_constraints(&current_pair)? {
+                Some(constraints) if intermediates == 0 && !constraints.ca => violations.push(format!(
+                    "{} signs {} but isn't a CA ({})",
+                    subject(&current_pair),
+                    subject(self),
+                    constraints
+                )),
+                None if intermediates == 0 => violations.push(format!(
+                    "{} signs {} but has no basic constraints",
+                    subject(&current_pair),
+                    subject(self)
+                )),
+                Some(constraints) if !constraints.allows_intermediates(intermediates) => violations.push(format!(
+                    "{} ({}) has {} intermediate CAs between it and {}",
+                    subject(&current_pair),
+                    constraints,
+                    intermediates,
+                    subject(self)
+                )),
+                _ => {}
+            }
+
+            intermediates += 1;
+            if intermediates >= MAX_SIGNER_CHAIN_DEPTH {
+                break;
+            }
+            signer = current_pair.signer.clone();
         }
+
+        Ok(violations)
+    }
+
+    /// The SKID of the (current) cert of this pair, if it has one
+    pub(crate) fn skid(&self) -> Result<Option<SubjectKeyIdentifier>> {
+        let cert = Rc::clone(&self.distributed_cert);
+        let cert = &(*cert).borrow().certificate.original;
+        let certificate: &rfc5280::Certificate = cert.as_ref().as_ref();
+        skid::get_skid(&certificate.tbs_certificate)
     }
 
+    /// Re-sign the cert of this pair (with sign_with, or with its own new key if it's a root) and
+    /// replace its keys. Returns the new key, which its signees have to be re-signed with, along
+    /// with the CN/SAN rules which apply to them. See signee::SigneeWalk for regenerating them
     pub(crate) fn regenerate(
         &mut self,
         sign_with: Option<&InMemorySigningKeyPair>,
         rsa_key_pool: &mut RsaKeyPool,
+        serials: &mut SerialSequence,
         cn_san_replace_rules: &CnSanReplaceRules,
-    ) -> Result<()> {
-        let (new_cert_subject_key_pair, rsa_private_key, new_cert) = self.re_sign_cert(sign_with, rsa_key_pool, cn_san_replace_rules)?;
-        (*self.distributed_cert).borrow_mut().certificate = Certificate::try_from(new_cert)?;
+        policies: &CryptoPolicies,
+    ) -> Result<(InMemorySigningKeyPair, CnSanReplaceRules)> {
+        let regeneration = self.prepare_regeneration(sign_with, rsa_key_pool, serials, cn_san_replace_rules, policies)?;
+        let signature = match regeneration.tbs_der() {
+            Some(tbs_der) => Some(policies.signature.sign(sign_with.unwrap_or(&regeneration.new_key_pair), tbs_der)?),
+            None => None,
+        };
+        self.finish_regeneration(regeneration, signature)
+    }
 
-        for signee in &mut self.signees {
-            signee.regenerate(
-                &(*self.distributed_cert).borrow().certificate.public_key,
-                Some(&new_cert_subject_key_pair),
-                rsa_key_pool,
-                cn_san_replace_rules,
-            )?;
-        }
+    /// Everything regenerate does short of signing the new cert, which is left to the caller so
+    /// that it can sign many certs at once, see signee::SigneeWalk. The result must be passed to
+    /// finish_regeneration along with the signature of its tbs_der, made with sign_with (or with
+    /// the new key of this pair if it's a root)
+    pub(crate) fn prepare_regeneration(
+        &mut self,
+        sign_with: Option<&InMemorySigningKeyPair>,
+        rsa_key_pool: &mut RsaKeyPool,
+        serials: &mut SerialSequence,
+        cn_san_replace_rules: &CnSanReplaceRules,
+        policies: &CryptoPolicies,
+    ) -> Result<Regeneration> {
+        // Signer scoped rules are matched against the original CN of the signing CA, so grab it
+        // before the cert is re-signed (and possibly renamed)
+        let signees_cn_san_replace_rules = match (*self.distributed_cert).borrow().certificate.original.subject_common_name() {
+            Some(common_name) => cn_san_replace_rules.for_signer(&common_name),
+            None => cn_san_replace_rules.clone(),
+        };
+
+        let (new_key_pair, private_key, cert) = match &self.graft {
+            // Grafted CAs are taken as they are, CN/SAN rules included
+            Some(graft) => (
+                graft.private_key.signing_key_pair()?,
+                graft.private_key.clone(),
+                RegeneratedCert::Signed((*graft.certificate.original).clone()),
+            ),
+            None => {
+                let (new_key_pair, private_key, unsigned_cert) =
+                    self.prepare_re_sign(sign_with, rsa_key_pool, serials, cn_san_replace_rules, policies)?;
+                (new_key_pair, private_key, RegeneratedCert::Unsigned(unsigned_cert))
+            }
+        };
+
+        Ok(Regeneration {
+            new_key_pair,
+            private_key,
+            cert,
+            signees_cn_san_replace_rules,
+        })
+    }
+
+    /// Replace the cert and keys of this pair with the ones prepared by prepare_regeneration.
+    /// Returns the same as regenerate
+    pub(crate) fn finish_regeneration(
+        &mut self,
+        regeneration: Regeneration,
+        signature: Option<Vec<u8>>,
+    ) -> Result<(InMemorySigningKeyPair, CnSanReplaceRules)> {
+        let new_cert = match regeneration.cert {
+            RegeneratedCert::Signed(cert) => cert,
+            RegeneratedCert::Unsigned(unsigned_cert) => unsigned_cert.into_cert(signature.context("cert was never signed")?)?,
+        };
+        (*self.distributed_cert).borrow_mut().certificate = Certificate::try_from(new_cert)?;
 
+        let private_key = regeneration.private_key;
         if let Some(associated_public_key) = &mut self.associated_public_key {
-            (*associated_public_key)
-                .borrow_mut()
-                .regenerate(&PrivateKey::Rsa(rsa_private_key.clone()))?;
+            (*associated_public_key).borrow_mut().regenerate(&private_key)?;
         }
 
         // This condition exists because not all certs originally had a private key associated with
@@ -89,21 +305,25 @@ impl CertKeyPair {
         // regenerated private key only in case there was one there to begin with. Otherwise we
         // just discard it just like it was discarded during install time.
         if let Some(distributed_private_key) = &mut self.distributed_private_key {
-            (**distributed_private_key).borrow_mut().key = PrivateKey::Rsa(rsa_private_key)
+            (**distributed_private_key).borrow_mut().key = private_key
         }
 
         self.regenerated = true;
 
-        Ok(())
+        Ok((regeneration.new_key_pair, regeneration.signees_cn_san_replace_rules))
     }
 
+    /// The to-be-signed part of the re-signed cert, along with its new key. Signing it is left to
+    /// the caller, see prepare_regeneration
     #[context["re-signing cert with subject {}", self.distributed_cert.borrow().certificate.subject]]
-    pub(crate) fn re_sign_cert(
+    fn prepare_re_sign(
         &mut self,
         sign_with: Option<&InMemorySigningKeyPair>,
         rsa_key_pool: &mut RsaKeyPool,
+        serials: &mut SerialSequence,
         cn_san_rules: &CnSanReplaceRules,
-    ) -> Result<(InMemorySigningKeyPair, RsaPrivateKey, CapturedX509Certificate)> {
+        policies: &CryptoPolicies,
+    ) -> Result<(InMemorySigningKeyPair, PrivateKey, UnsignedCert)> {
         // Clone the to-be-signed part of the certificate from the original certificate
         let cert: &X509Certificate = &(*self.distributed_cert).borrow().certificate.original;
         let certificate: &rfc5280::Certificate = cert.as_ref();
@@ -113,32 +333,30 @@ impl CertKeyPair {
         // determining the method relies on the cert keys before they're regenerated
         let skid_method = skid::get_cert_key_skid_method(&mut tbs_certificate);
 
-        // TODO: Find a less hacky way to get the key size. It's ugly but if we get this wrong, the
-        // only thing that happens is that we don't get to enjoy the pool's cache or we generate a
-        // key too large
-        let rsa_key_size = tbs_certificate.subject_public_key_info.subject_public_key.bit_len() - 112;
+        // Generate a new key of the same kind for this cert
+        let (self_new_private_key, self_new_key_pair) =
+            rsa_key_pool.replacement_for(&(*self.distributed_cert).borrow().certificate.public_key)?;
 
-        // Generate a new RSA key for this cert
-        let (self_new_rsa_private_key, self_new_key_pair) = rsa_key_pool.get(rsa_key_size).context("getting rsa key")?;
-
-        // Replace just the public key info in the to-be-signed part with the newly generated RSA
-        // key
+        // Replace just the public key info in the to-be-signed part with the newly generated key
         tbs_certificate.subject_public_key_info = rfc5280::SubjectPublicKeyInfo {
             algorithm: KeyAlgorithm::from(&self_new_key_pair).into(),
             subject_public_key: BitString::new(0, self_new_key_pair.public_key_data()),
         };
 
         // If we weren't given a key to sign with, we use the new key we just generated
-        // as this is a root (self-signed) certificate
+        // as this is a root (self-signed) certificate. Roots re-signed by an external CA are
+        // given its key
         let signing_key = if let Some(key_pair) = &sign_with {
             key_pair
         } else {
             &self_new_key_pair
         };
 
-        // TODO: No need to change the signature algorithm once we know how to re-sign ECDSA,
-        // we're only forced to change this because we make all certs RSA
-        let signature_algorithm: AlgorithmIdentifier = signing_key.signature_algorithm()?.into();
+        // The signature algorithm is determined by the (new) signing key and the configured
+        // signature policy. The signer and the cert don't necessarily have the same kind of key,
+        // e.g. an RSA CA might sign an EC leaf, so this might not be the algorithm of the cert's
+        // own key nor the original one
+        let signature_algorithm = policies.signature.algorithm_identifier(signing_key)?;
         tbs_certificate.signature = signature_algorithm.clone();
 
         // Fix SKID
@@ -146,42 +364,107 @@ impl CertKeyPair {
             skid::fix_skid(&mut tbs_certificate, skid_method?)?;
         }
 
+        // Fix AKID. Signers are regenerated before their signees, so the signer's cert already
+        // has its new SKID by now. Roots identify themselves, with the SKID we just fixed, unless
+        // they're re-signed by an external CA
+        let signer_skid = match (&self.signer, &self.new_issuer_skid) {
+            (Some(signer), _) => (**signer).borrow().skid()?,
+            (None, Some(new_issuer_skid)) => Some(new_issuer_skid.clone()),
+            (None, None) => skid::get_skid(&tbs_certificate)?,
+        };
+        if let Some(signer_skid) = signer_skid {
+            akid::fix_akid(&mut tbs_certificate, &signer_skid).context("fixing AKID")?;
+        }
+
+        if let Some(new_issuer) = &self.new_issuer {
+            tbs_certificate.issuer = new_issuer.clone();
+        }
+
+        // Matched against the original CN, before any CN/SAN rules rename the cert
+        let is_ca = BasicConstraints::from_tbs_certificate(&tbs_certificate)?.is_some_and(|constraints| constraints.ca);
+        policies.validity.apply(
+            &mut tbs_certificate,
+            cert.subject_common_name().as_deref(),
+            is_ca,
+            deterministic::now(),
+        );
+        serials.apply(&mut tbs_certificate, cert)?;
+        policies
+            .extension
+            .apply(&mut tbs_certificate, cert.subject_common_name().as_deref())
+            .context("overriding extensions")?;
+
         // Perform all requested mutations on the certificate
         cert_mutations::mutate_cert(&mut tbs_certificate, cn_san_rules).context("mutating cert")?;
 
         // The to-be-signed ceritifcate, encoded to DER, is the bytes we sign
         let tbs_der = encode_tbs_cert_to_der(&tbs_certificate)?;
 
-        // Generate the actual signature
-        let signature = signing_key.try_sign(&tbs_der)?;
+        Ok((
+            self_new_key_pair,
+            self_new_private_key,
+            UnsignedCert {
+                tbs_certificate,
+                signature_algorithm,
+                tbs_der,
+            },
+        ))
+    }
 
-        // Create a full certificate by combining the to-be-signed part with the signature itself
-        let cert = rfc5280::Certificate {
-            tbs_certificate,
-            signature_algorithm,
-            signature: BitString::new(0, Bytes::copy_from_slice(signature.as_ref())),
-        };
+    pub(crate) async fn commit_to_etcd_and_disk(&self, etcd_client: &InMemoryK8sEtcd, policies: &CryptoPolicies) -> Result<()> {
+        self.commit_pair_certificate(etcd_client, policies).await?;
+        self.commit_pair_key(etcd_client, policies).await
+    }
 
-        // Encode the entire cert as DER and reload it into a CapturedX509Certificate which is the
-        // type we use in our structs
-        let cert = CapturedX509Certificate::from_der(X509Certificate::from(cert).encode_der()?)?;
+    // See ClusterCryptoObjects::commit_to_etcd_and_disk
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub(crate) async fn commit_pair_certificate(&self, etcd_client: &InMemoryK8sEtcd, policies: &CryptoPolicies) -> Result<()> {
+        for location in (*self.distributed_cert).borrow().locations.0.iter() {
+            match location {
+                Location::K8s(k8slocation) => {
+                    self.commit_k8s_cert(etcd_client, k8slocation, policies).await?;
+                }
+                Location::Filesystem(filelocation) => {
+                    self.commit_filesystem_cert(filelocation, policies).await?;
+                }
+            }
+        }
 
-        Ok((self_new_key_pair, self_new_rsa_private_key, cert))
+        Ok(())
     }
 
-    pub(crate) async fn commit_to_etcd_and_disk(&self, etcd_client: &InMemoryK8sEtcd) -> Result<()> {
-        self.commit_pair_certificate(etcd_client).await?;
-        self.commit_pair_key(etcd_client).await
+    pub(crate) async fn commit_k8s_cert(
+        &self,
+        etcd_client: &InMemoryK8sEtcd,
+        k8slocation: &K8sLocation,
+        policies: &CryptoPolicies,
+    ) -> Result<()> {
+        let document = get_etcd_document(etcd_client, &k8slocation.resource_location).await?;
+
+        let new_document = recreate_json_at_location_with_new_pem(
+            &document,
+            &k8slocation.yaml_location,
+            &pem::parse((*self.distributed_cert).borrow().certificate.original.encode_pem())?,
+            &policies.private_key,
+        )?;
+        put_etcd_document_if_changed(etcd_client, k8slocation, &document, new_document).await;
+
+        Ok(())
     }
 
-    pub(crate) async fn commit_pair_certificate(&self, etcd_client: &InMemoryK8sEtcd) -> Result<()> {
-        for location in (*self.distributed_cert).borrow().locations.0.iter() {
+    /// Remove the (regenerated) cert of this pair from all the bundles it's in, leaving the
+    /// locations it's alone in as they are. Used for intermediates which were flattened out of
+    /// their chain, so must run after everything else was committed, as it shifts the indices of
+    /// the PEMs following it in their bundles
+    pub(crate) async fn remove_from_bundles(&self, etcd_client: &InMemoryK8sEtcd, policies: &CryptoPolicies) -> Result<()> {
+        let locations = (*self.distributed_cert).borrow().locations.0.clone();
+        for location in &locations {
             match location {
                 Location::K8s(k8slocation) => {
-                    self.commit_k8s_cert(etcd_client, &k8slocation).await?;
+                    self.remove_from_k8s_bundle(etcd_client, k8slocation).await?;
                 }
                 Location::Filesystem(filelocation) => {
-                    self.commit_filesystem_cert(&filelocation).await?;
+                    self.remove_from_filesystem_bundle(filelocation, policies).await?;
                 }
             }
         }
@@ -189,70 +472,125 @@ impl CertKeyPair {
         Ok(())
     }
 
-    pub(crate) async fn commit_k8s_cert(&self, etcd_client: &InMemoryK8sEtcd, k8slocation: &K8sLocation) -> Result<()> {
-        let resource = get_etcd_yaml(etcd_client, &k8slocation.resource_location).await?;
+    pub(crate) async fn remove_from_k8s_bundle(&self, etcd_client: &InMemoryK8sEtcd, k8slocation: &K8sLocation) -> Result<()> {
+        let document = get_etcd_document(etcd_client, &k8slocation.resource_location).await?;
+
+        let new_document = remove_pem_from_json_at_location(
+            &document,
+            &k8slocation.yaml_location,
+            &pem::parse((*self.distributed_cert).borrow().certificate.original.encode_pem())?,
+        )?;
+        put_etcd_document_if_changed(etcd_client, k8slocation, &document, new_document).await;
+
+        Ok(())
+    }
+
+    pub(crate) async fn remove_from_filesystem_bundle(&self, filelocation: &FileLocation, policies: &CryptoPolicies) -> Result<()> {
+        let contents = file_utils::read_file(Path::new(&filelocation.path)).await?;
+
+        let pem = pem::parse((*self.distributed_cert).borrow().certificate.original.encode_pem())?;
+
+        let new_contents = match &filelocation.content_location {
+            FileContentLocation::Raw(LocationValueType::Pem(_)) => {
+                pem_utils::pem_bundle_remove_pem(String::from_utf8(contents.clone())?, &pem)?.into_bytes()
+            }
+            FileContentLocation::Yaml(yaml_location) => {
+                remove_pem_from_file_yaml_at_location(&String::from_utf8(contents.clone())?, filelocation, yaml_location, &pem)?
+                    .into_bytes()
+            }
+            // DER files only ever hold a single cert
+            _ => return Ok(()),
+        };
+
+        write_if_changed(
+            filelocation,
+            FileKind::Certificate,
+            policies.file_permissions,
+            &contents,
+            new_contents,
+        )
+        .await
+    }
 
-        etcd_client
-            .put(
-                &k8slocation.resource_location.as_etcd_key(),
-                recreate_yaml_at_location_with_new_pem(
-                    resource,
+    /// Make sure the chains the cert of this pair is the leaf of are still intact after the commit,
+    /// see crypto_utils::verify_pem_bundle_chain. The rest of each chain is committed by the pairs
+    /// it's made of, so this must run once everything was committed
+    pub(crate) async fn verify_committed_chains(&self, etcd_client: &InMemoryK8sEtcd) -> Result<()> {
+        let is_leaf = |value: &LocationValueType| matches!(value, LocationValueType::Pem(pem_location_info) if pem_location_info.role == PemBundleRole::Leaf);
+
+        let locations = (*self.distributed_cert).borrow().locations.0.clone();
+        for location in &locations {
+            let pem_bundle = match location {
+                Location::K8s(k8slocation) if is_leaf(&k8slocation.yaml_location.value) => read_resource_data_entry(
+                    &get_etcd_yaml(etcd_client, &k8slocation.resource_location).await?,
                     &k8slocation.yaml_location,
-                    &pem::parse((*self.distributed_cert).borrow().certificate.original.encode_pem())?,
-                    crate::file_utils::RecreateYamlEncoding::Json,
-                )?
-                .as_bytes()
-                .to_vec(),
-            )
-            .await;
+                )?,
+                Location::Filesystem(filelocation) => match &filelocation.content_location {
+                    FileContentLocation::Raw(value) if is_leaf(value) => {
+                        String::from_utf8(file_utils::read_file(Path::new(&filelocation.path)).await?)?
+                    }
+                    FileContentLocation::Yaml(yaml_location) if is_leaf(&yaml_location.value) => read_resource_data_entry(
+                        &serde_yaml::from_str(&String::from_utf8(file_utils::read_file(Path::new(&filelocation.path)).await?)?)?,
+                        yaml_location,
+                    )?,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            crypto_utils::verify_pem_bundle_chain(&pem_bundle).with_context(|| format!("verifying chain at {}", location))?;
+        }
 
         Ok(())
     }
 
-    pub(crate) async fn commit_pair_key(&self, etcd_client: &InMemoryK8sEtcd) -> Result<()> {
+    // See ClusterCryptoObjects::commit_to_etcd_and_disk
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub(crate) async fn commit_pair_key(&self, etcd_client: &InMemoryK8sEtcd, policies: &CryptoPolicies) -> Result<()> {
         if let Some(private_key) = &self.distributed_private_key {
-            (*private_key).borrow_mut().commit_to_etcd_and_disk(etcd_client).await?;
+            (*private_key).borrow_mut().commit_to_etcd_and_disk(etcd_client, policies).await?;
         }
 
         Ok(())
     }
 
-    pub(crate) async fn commit_filesystem_cert(&self, filelocation: &FileLocation) -> Result<()> {
-        let mut file = tokio::fs::File::open(&filelocation.path).await?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents).await?;
+    pub(crate) async fn commit_filesystem_cert(&self, filelocation: &FileLocation, policies: &CryptoPolicies) -> Result<()> {
+        let contents = file_utils::read_file(Path::new(&filelocation.path)).await?;
 
         let newpem = pem::parse((*self.distributed_cert).borrow().certificate.original.encode_pem())?;
 
-        Ok(tokio::fs::write(
-            &filelocation.path,
-            match &filelocation.content_location {
-                FileContentLocation::Raw(location_value_type) => match &location_value_type {
-                    LocationValueType::Pem(pem_location_info) => pem_utils::pem_bundle_replace_pem_at_index(
-                        String::from_utf8(contents)?,
-                        pem_location_info.pem_bundle_index,
-                        &newpem,
-                    )?,
-                    _ => {
-                        bail!("Cannot replace PEM in non-PEM file");
-                    }
-                },
-                FileContentLocation::Yaml(yaml_location) => {
-                    let resource = get_filesystem_yaml(filelocation).await?;
-                    recreate_yaml_at_location_with_new_pem(
-                        resource,
-                        yaml_location,
-                        &newpem,
-                        if filelocation.path.ends_with("currentconfig") {
-                            crate::file_utils::RecreateYamlEncoding::Json
-                        } else {
-                            crate::file_utils::RecreateYamlEncoding::Yaml
-                        },
-                    )?
+        let new_contents = match &filelocation.content_location {
+            FileContentLocation::Raw(location_value_type) => match &location_value_type {
+                LocationValueType::Pem(pem_location_info) => pem_utils::pem_bundle_replace_pem_at_index(
+                    String::from_utf8(contents.clone())?,
+                    pem_location_info.pem_bundle_index,
+                    &newpem,
+                    &policies.private_key,
+                )?
+                .into_bytes(),
+                _ => {
+                    bail!("Cannot replace PEM in non-PEM file");
                 }
             },
+            FileContentLocation::Der => newpem.contents().to_vec(),
+            FileContentLocation::Yaml(yaml_location) => recreate_file_yaml_at_location_with_new_pem(
+                &String::from_utf8(contents.clone())?,
+                filelocation,
+                yaml_location,
+                &newpem,
+                &policies.private_key,
+            )?
+            .into_bytes(),
+        };
+
+        write_if_changed(
+            filelocation,
+            FileKind::Certificate,
+            policies.file_permissions,
+            &contents,
+            new_contents,
         )
-        .await?)
+        .await
     }
 }
 
@@ -289,8 +627,8 @@ impl Display for CertKeyPair {
         )?;
         write!(f, " | {}", (*self.distributed_cert).borrow().certificate.subject,)?;
 
-        if self.signees.len() > 0 {
-            writeln!(f, "")?;
+        if !self.signees.is_empty() {
+            writeln!(f)?;
         }
 
         for signee in &self.signees {
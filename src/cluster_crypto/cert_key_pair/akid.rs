@@ -0,0 +1,31 @@
+use super::AUTHORITY_KEY_IDENTIFIER_OID;
+use anyhow::{Context, Result};
+use bcder::{OctetString, Oid};
+use der::{Decode, Encode};
+use x509_cert::ext::pkix::{AuthorityKeyIdentifier, SubjectKeyIdentifier};
+use x509_certificate::rfc5280;
+
+/// Point the AKID of a re-signed cert at the SKID of the cert that signed it. The signer's SKID
+/// changes along with its key, and verifiers like openssl use the AKID to find the issuer of a
+/// cert, so leaving the original one in place breaks chains with intermediates. Certs without an
+/// AKID (or with an AKID identifying the issuer only by name and serial) are left as they are
+pub(crate) fn fix_akid(tbs_certificate: &mut rfc5280::TbsCertificate, signer_skid: &SubjectKeyIdentifier) -> Result<()> {
+    let Some(extensions) = &mut tbs_certificate.extensions else {
+        return Ok(());
+    };
+
+    for extension in extensions.iter_mut().filter(|ext| ext.id == Oid(&AUTHORITY_KEY_IDENTIFIER_OID)) {
+        let mut akid = AuthorityKeyIdentifier::from_der(&extension.value.to_bytes())
+            .ok()
+            .context("failed to parse AKID extension")?;
+
+        if akid.key_identifier.is_none() {
+            continue;
+        }
+
+        akid.key_identifier = Some(signer_skid.0.clone());
+        extension.value = OctetString::new(bytes::Bytes::from(akid.to_der().ok().context("failed to encode AKID extension")?));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use anyhow::{bail, Context, Result};
+use bcder::{Integer, Mode, Oid, Tag};
+use std::fmt::Display;
+use x509_certificate::rfc5280;
+
+const BASIC_CONSTRAINTS_OID: [u8; 3] = [85, 29, 19];
+
+/// The BasicConstraints extension of a cert. Re-signing keeps the extensions of the original TBS
+/// certificate as they are, so these are only changed by regeneration when overridden (see
+/// extension_policy), they're otherwise only parsed to check that the signer chains we found
+/// actually satisfy them.
+///
+/// The path length is kept signed, as some encoders happily produce negative ones, which RFC 5280
+/// doesn't allow but which we still have to be able to read (and preserve)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BasicConstraints {
+    pub(crate) ca: bool,
+    pub(crate) path_len: Option<i64>,
+}
+
+impl BasicConstraints {
+    /// The BasicConstraints of the given cert, None if it doesn't have the extension at all (e.g.
+    /// v1 certs)
+    pub(crate) fn from_tbs_certificate(tbs_certificate: &rfc5280::TbsCertificate) -> Result<Option<Self>> {
+        let Some(extensions) = &tbs_certificate.extensions else {
+            return Ok(None);
+        };
+
+        let mut basic_constraints_extensions = extensions.iter().filter(|ext| ext.id == Oid(&BASIC_CONSTRAINTS_OID));
+        let Some(extension) = basic_constraints_extensions.next() else {
+            return Ok(None);
+        };
+        if basic_constraints_extensions.next().is_some() {
+            bail!("multiple BasicConstraints extensions found");
+        }
+
+        Self::from_der(&extension.value.to_bytes()).map(Some)
+    }
+
+    /// BasicConstraints ::= SEQUENCE { cA BOOLEAN DEFAULT FALSE, pathLenConstraint INTEGER OPTIONAL }
+    fn from_der(der_bytes: &[u8]) -> Result<Self> {
+        Mode::Ber
+            .decode(der_bytes, |cons| {
+                cons.take_sequence(|cons| {
+                    let ca = cons.take_opt_bool()?.unwrap_or(false);
+                    let path_len = cons.take_opt_primitive_if(Tag::INTEGER, Integer::i64_from_primitive)?;
+                    Ok(BasicConstraints { ca, path_len })
+                })
+            })
+            .ok()
+            .context("failed to parse BasicConstraints extension")
+    }
+
+    /// Whether this CA may sign a chain which has the given number of intermediate CAs below it
+    pub(crate) fn allows_intermediates(&self, intermediates: usize) -> bool {
+        match self.path_len {
+            Some(path_len) => i64::try_from(intermediates).is_ok_and(|intermediates| intermediates <= path_len),
+            None => true,
+        }
+    }
+}
+
+impl Display for BasicConstraints {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CA:{}", if self.ca { "TRUE" } else { "FALSE" })?;
+        if let Some(path_len) = self.path_len {
+            write!(f, ", pathlen:{}", path_len)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_der() {
+        for (der_bytes, expected) in [
+            // CA:TRUE, pathlen:0
+            (
+                &[0x30, 0x06, 0x01, 0x01, 0xff, 0x02, 0x01, 0x00][..],
+                BasicConstraints {
+                    ca: true,
+                    path_len: Some(0),
+                },
+            ),
+            // CA:TRUE, pathlen:-1
+            (
+                &[0x30, 0x06, 0x01, 0x01, 0xff, 0x02, 0x01, 0xff][..],
+                BasicConstraints {
+                    ca: true,
+                    path_len: Some(-1),
+                },
+            ),
+            // CA:TRUE
+            (&[0x30, 0x03, 0x01, 0x01, 0xff][..], BasicConstraints { ca: true, path_len: None }),
+            // CA:FALSE, the default, so an empty sequence
+            (&[0x30, 0x00][..], BasicConstraints { ca: false, path_len: None }),
+        ] {
+            assert_eq!(BasicConstraints::from_der(der_bytes).unwrap(), expected);
+        }
+
+        assert!(BasicConstraints::from_der(&[0x04, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_allows_intermediates() {
+        let unconstrained = BasicConstraints { ca: true, path_len: None };
+        let zero = BasicConstraints {
+            ca: true,
+            path_len: Some(0),
+        };
+        let negative = BasicConstraints {
+            ca: true,
+            path_len: Some(-1),
+        };
+
+        assert!(unconstrained.allows_intermediates(5));
+        assert!(zero.allows_intermediates(0));
+        assert!(!zero.allows_intermediates(1));
+        assert!(!negative.allows_intermediates(0));
+        assert_eq!(negative.to_string(), "CA:TRUE, pathlen:-1");
+    }
+}
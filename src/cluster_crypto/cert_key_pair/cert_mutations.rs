@@ -1,12 +1,12 @@
 use super::SUBJECT_ALTERNATIVE_NAME_OID;
 use crate::cnsanreplace::CnSanReplaceRules;
-use anyhow;
 use anyhow::{Context, Result};
 use bcder::OctetString;
 use bcder::Oid;
-use der::asn1::Ia5String;
+use der::asn1::{Ia5String, OctetString as DerOctetString};
 use der::{Decode, Encode};
-use x509_cert::ext::pkix::name::GeneralName::DnsName;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use x509_cert::ext::pkix::name::GeneralName::{DnsName, IpAddress};
 use x509_cert::ext::pkix::SubjectAltName;
 use x509_certificate::rfc3280::Name;
 use x509_certificate::{rfc3280, rfc4519::OID_COMMON_NAME, rfc5280::TbsCertificate};
@@ -58,13 +58,20 @@ pub(crate) fn mutate_cert_subject_alternative_name(
                         .iter()
                         .map(|san| {
                             Ok(match san {
-                                DnsName(name) => DnsName(Ia5String::new(&cn_san_replace_rules.replace(&name.to_string()))?),
+                                DnsName(name) => DnsName(Ia5String::new(&cn_san_replace_rules.replace(name.as_ref()))?),
+                                IpAddress(address) => IpAddress(mutate_ip_address(address, cn_san_replace_rules)?),
                                 san_name => san_name.clone(),
                             })
                         })
                         .collect::<Result<Vec<_>>>()?,
                 );
 
+                // Left untouched when no rule applies, re-encoding could change how the original
+                // encoder wrote it
+                if new_san_extension == san_extension {
+                    return Ok(());
+                }
+
                 ext.value = OctetString::new(bytes::Bytes::copy_from_slice(
                     new_san_extension
                         .to_der()
@@ -80,3 +87,24 @@ pub(crate) fn mutate_cert_subject_alternative_name(
     }
     Ok(())
 }
+
+/// IP SANs are matched against the rules in their canonical text form, and so have to be replaced
+/// with IP addresses, though not necessarily of the same family
+fn mutate_ip_address(address: &DerOctetString, cn_san_replace_rules: &CnSanReplaceRules) -> Result<DerOctetString> {
+    let ip_address = match address.as_bytes().len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(address.as_bytes())?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(address.as_bytes())?)),
+        // Malformed, left alone
+        _ => return Ok(address.clone()),
+    };
+
+    let replacement = cn_san_replace_rules.replace(&ip_address.to_string());
+    let new_ip_address = replacement
+        .parse::<IpAddr>()
+        .with_context(|| format!("IP SAN {} replaced with {:?}, which isn't an IP address", ip_address, replacement))?;
+
+    Ok(DerOctetString::new(match new_ip_address {
+        IpAddr::V4(new_ip_address) => new_ip_address.octets().to_vec(),
+        IpAddr::V6(new_ip_address) => new_ip_address.octets().to_vec(),
+    })?)
+}
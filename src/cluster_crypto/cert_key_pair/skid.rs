@@ -70,7 +70,7 @@ pub(crate) fn calculate_skid(
                                             _ => bail!("invalid modulus sign"),
                                         };
 
-                                        if bytes.len() == 0 {
+                                        if bytes.is_empty() {
                                             bail!("modulus is zero")
                                         }
 
@@ -110,7 +110,7 @@ pub(crate) fn get_cert_key_skid_method(tbs_certificate: &mut rfc5280::TbsCertifi
             .filter(|ext| ext.id == Oid(&SUBJECT_KEY_IDENTIFIER_OID))
             .collect::<Vec<_>>();
 
-        if skid_extensions.len() == 0 {
+        if skid_extensions.is_empty() {
             return None;
         }
 
@@ -118,7 +118,7 @@ pub(crate) fn get_cert_key_skid_method(tbs_certificate: &mut rfc5280::TbsCertifi
             return Some(Err(anyhow::anyhow!("multiple SKID extensions found")));
         }
 
-        let skid_slice = match (&skid_extensions[0]).value.as_slice() {
+        let skid_slice = match skid_extensions[0].value.as_slice() {
             Some(slice) => slice,
             None => return Some(Err(anyhow::anyhow!("SKID extension not octet string"))),
         };
@@ -147,6 +147,23 @@ pub(crate) fn get_cert_key_skid_method(tbs_certificate: &mut rfc5280::TbsCertifi
     Some(Err(anyhow::anyhow!("failed to find matching SKID method")))
 }
 
+/// The SKID of a cert, if it has one
+pub(crate) fn get_skid(tbs_certificate: &rfc5280::TbsCertificate) -> Result<Option<SubjectKeyIdentifier>> {
+    let Some(extension) = tbs_certificate
+        .extensions
+        .as_ref()
+        .and_then(|extensions| extensions.iter().find(|ext| ext.id == Oid(&SUBJECT_KEY_IDENTIFIER_OID)))
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(
+        SubjectKeyIdentifier::from_der(&extension.value.to_bytes())
+            .ok()
+            .context("failed to parse SKID extension")?,
+    ))
+}
+
 pub(crate) fn fix_skid(tbs_certificate: &mut rfc5280::TbsCertificate, method: SubjectKeyIdentifierMethod) -> Result<()> {
     let new_skid_extension = calculate_skid(tbs_certificate, method)?;
 
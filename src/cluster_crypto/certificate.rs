@@ -1,7 +1,18 @@
-use super::keys::PublicKey;
+use super::{cert_key_pair::SUBJECT_ALTERNATIVE_NAME_OID, keys::PublicKey};
 use anyhow::{bail, Context, Result};
+use bcder::Oid;
+use der::Decode;
 use p256::pkcs8::EncodePublicKey;
-use std::hash::{Hash, Hasher};
+use sha2::Digest;
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+use x509_cert::ext::pkix::{name::GeneralName::DnsName, SubjectAltName};
 use x509_certificate::{self, CapturedX509Certificate};
 
 #[derive(Clone, Debug)]
@@ -9,9 +20,15 @@ pub(crate) struct Certificate {
     pub(crate) issuer: String,
     pub(crate) subject: String,
     pub(crate) public_key: PublicKey,
-    pub(crate) original: CapturedX509Certificate,
+    /// Shared, as the same cert (e.g. a CA) can appear in hundreds of locations. See
+    /// Certificate::from_der_cached
+    pub(crate) original: Arc<CapturedX509Certificate>,
 }
 
+/// Parsed certificates by the SHA-256 of their DER, across the entire run
+static PARSED_CERTIFICATES: OnceLock<Mutex<HashMap<[u8; 32], Certificate>>> = OnceLock::new();
+static PARSED_CERTIFICATES_HITS: AtomicUsize = AtomicUsize::new(0);
+
 impl PartialEq for Certificate {
     fn eq(&self, other: &Self) -> bool {
         self.issuer == other.issuer && self.subject == other.subject && self.public_key == other.public_key
@@ -37,7 +54,7 @@ impl TryFrom<CapturedX509Certificate> for Certificate {
             subject: cert.subject_name().user_friendly_str().unwrap_or("undecodable".to_string()),
             public_key: match cert.key_algorithm().context("failed to get cert key algorithm")? {
                 x509_certificate::KeyAlgorithm::Rsa => PublicKey::from_rsa_bytes(&bytes::Bytes::copy_from_slice(
-                    &cert.to_public_key_der().context("parsing public key")?.as_bytes(),
+                    cert.to_public_key_der().context("parsing public key")?.as_bytes(),
                 )),
                 x509_certificate::KeyAlgorithm::Ecdsa(_) => {
                     PublicKey::from_ec_cert_bytes(&bytes::Bytes::copy_from_slice(cert.encode_pem().as_bytes()))
@@ -45,7 +62,114 @@ impl TryFrom<CapturedX509Certificate> for Certificate {
                 }
                 x509_certificate::KeyAlgorithm::Ed25519 => bail!("ed25519 not supported"),
             },
-            original: cert,
+            original: Arc::new(cert),
         })
     }
 }
+
+impl Certificate {
+    /// Parse a DER encoded certificate, or if the exact same DER has already been parsed during
+    /// this run, return a (cheap) copy of that instead
+    pub(crate) fn from_der_cached(der: &[u8]) -> Result<Self> {
+        let digest: [u8; 32] = sha2::Sha256::digest(der).into();
+        let cache = PARSED_CERTIFICATES.get_or_init(Default::default);
+
+        if let Some(certificate) = cache.lock().ok().context("certificate cache lock poisoned")?.get(&digest) {
+            PARSED_CERTIFICATES_HITS.fetch_add(1, Ordering::Relaxed);
+            return Ok(certificate.clone());
+        }
+
+        // Parse without holding the lock, if another thread raced us to it, no harm done
+        let certificate = Certificate::try_from(CapturedX509Certificate::from_der(der).context("parsing DER")?).context("parsing cert")?;
+
+        cache
+            .lock()
+            .ok()
+            .context("certificate cache lock poisoned")?
+            .insert(digest, certificate.clone());
+
+        Ok(certificate)
+    }
+
+    /// The number of distinct certificates parsed so far and the number of times parsing was
+    /// avoided because the certificate was already parsed
+    pub(crate) fn cache_stats() -> (usize, usize) {
+        let distinct = PARSED_CERTIFICATES
+            .get()
+            .and_then(|cache| cache.lock().ok().map(|cache| cache.len()))
+            .unwrap_or(0);
+
+        (distinct, PARSED_CERTIFICATES_HITS.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn not_after(&self) -> chrono::DateTime<chrono::Utc> {
+        let certificate: &x509_certificate::rfc5280::Certificate = self.original.as_ref().as_ref();
+        chrono_time(&certificate.tbs_certificate.validity.not_after)
+    }
+
+    pub(crate) fn not_before(&self) -> chrono::DateTime<chrono::Utc> {
+        let certificate: &x509_certificate::rfc5280::Certificate = self.original.as_ref().as_ref();
+        chrono_time(&certificate.tbs_certificate.validity.not_before)
+    }
+
+    /// The subject CN and DNS SANs of the certificate, i.e. the values CN/SAN replace rules are
+    /// matched against
+    pub(crate) fn cn_san_values(&self) -> Result<Vec<String>> {
+        let mut values = self.original.subject_common_name().into_iter().collect::<Vec<_>>();
+        values.extend(self.dns_sans()?);
+        Ok(values)
+    }
+
+    pub(crate) fn dns_sans(&self) -> Result<Vec<String>> {
+        let mut values = vec![];
+
+        for extension in self
+            .original
+            .iter_extensions()
+            .filter(|extension| extension.id == Oid(&SUBJECT_ALTERNATIVE_NAME_OID))
+        {
+            let san_extension = SubjectAltName::from_der(extension.value.as_slice().context("empty SAN extension")?)?;
+            values.extend(san_extension.0.iter().filter_map(|san| match san {
+                DnsName(name) => Some(name.to_string()),
+                _ => None,
+            }));
+        }
+
+        Ok(values)
+    }
+}
+
+pub(crate) fn chrono_time(time: &x509_certificate::asn1time::Time) -> chrono::DateTime<chrono::Utc> {
+    match time {
+        x509_certificate::asn1time::Time::UtcTime(utc_time) => **utc_time,
+        x509_certificate::asn1time::Time::GeneralTime(generalized_time) => generalized_time.clone().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_certificate::{KeyAlgorithm, X509CertificateBuilder};
+
+    #[test]
+    fn test_from_der_cached() {
+        let mut builder = X509CertificateBuilder::default();
+        builder.subject().append_common_name_utf8_string("test-from-der-cached").unwrap();
+        let (cert, _) = builder
+            .create_with_random_keypair(KeyAlgorithm::Ecdsa(x509_certificate::EcdsaCurve::Secp256r1))
+            .unwrap();
+        let der = cert.encode_der().unwrap();
+
+        let (_, hits_before) = Certificate::cache_stats();
+        let first = Certificate::from_der_cached(&der).unwrap();
+        let second = Certificate::from_der_cached(&der).unwrap();
+        let (_, hits_after) = Certificate::cache_stats();
+
+        assert_eq!(first, second);
+        assert!(Arc::ptr_eq(&first.original, &second.original));
+        // Other tests might be parsing certs concurrently
+        assert!(hits_after > hits_before);
+
+        assert!(Certificate::from_der_cached(b"not a cert").is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{Context, Result};
+
+/// Which cert chains to regenerate, by the CNs of their certs, see --skip-cn and --only-cn. A
+/// pattern is a glob matched against the whole CN (e.g. *.apps.example.com)
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CnFilter {
+    /// Certs which are left as they are, along with everything they signed
+    skip: Vec<glob::Pattern>,
+    /// If not empty, only these certs are regenerated, along with everything they signed
+    only: Vec<glob::Pattern>,
+}
+
+impl CnFilter {
+    pub(crate) fn new(skip: &[String], only: &[String]) -> Result<Self> {
+        let parse = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| glob::Pattern::new(pattern).with_context(|| format!("parsing CN pattern {:?}", pattern)))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            skip: parse(skip)?,
+            only: parse(only)?,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.skip.is_empty() && self.only.is_empty()
+    }
+
+    /// Only some chains are selected, everything else (including standalone keys, which have no
+    /// CN) is left as it is
+    pub(crate) fn is_allow_list(&self) -> bool {
+        !self.only.is_empty()
+    }
+
+    /// Certs without a CN are never skipped, but aren't selected by an allow list either
+    pub(crate) fn skips(&self, common_name: Option<&str>) -> bool {
+        common_name.is_some_and(|common_name| self.skip.iter().any(|pattern| pattern.matches(common_name)))
+    }
+
+    pub(crate) fn selects(&self, common_name: Option<&str>) -> bool {
+        if self.skips(common_name) {
+            return false;
+        }
+
+        self.only.is_empty() || common_name.is_some_and(|common_name| self.only.iter().any(|pattern| pattern.matches(common_name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cn_filter() {
+        let skip_only = CnFilter::new(&["*.apps.example.com".to_string()], &[]).unwrap();
+        assert!(skip_only.skips(Some("console.apps.example.com")));
+        assert!(!skip_only.selects(Some("console.apps.example.com")));
+        assert!(skip_only.selects(Some("api.example.com")));
+        assert!(skip_only.selects(None));
+        assert!(!skip_only.is_allow_list());
+
+        let allow_list = CnFilter::new(&["ingress-operator@*".to_string()], &["ingress-*".to_string()]).unwrap();
+        assert!(allow_list.selects(Some("ingress-ca")));
+        assert!(!allow_list.selects(Some("ingress-operator@1700000000")));
+        assert!(!allow_list.selects(Some("etcd-signer")));
+        assert!(!allow_list.selects(None));
+
+        assert!(CnFilter::default().is_empty());
+        assert!(CnFilter::new(&["[".to_string()], &[]).is_err());
+    }
+}
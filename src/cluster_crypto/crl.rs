@@ -0,0 +1,247 @@
+use super::signature_policy::{self, SignaturePolicy};
+use anyhow::{Context, Result};
+use bcder::{encode::Values, Mode};
+use bytes::Bytes;
+use der::{
+    asn1::{BitString, OctetString},
+    oid::AssociatedOid,
+    Decode, Encode,
+};
+use x509_cert::{
+    crl::CertificateList,
+    ext::pkix::{AuthorityKeyIdentifier, SubjectKeyIdentifier},
+    name::Name,
+    spki::AlgorithmIdentifierOwned,
+};
+use x509_certificate::{rfc5280, CapturedX509Certificate, InMemorySigningKeyPair, KeyAlgorithm};
+
+pub(crate) const CRL_PEM_TAG: &str = "X509 CRL";
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Crl {
+    pub(crate) issuer: String,
+    pub(crate) der: Bytes,
+}
+
+impl Crl {
+    pub(crate) fn from_der(der: &[u8]) -> Result<Self> {
+        let certificate_list = CertificateList::from_der(der).context("parsing CRL")?;
+
+        Ok(Crl {
+            issuer: certificate_list.tbs_cert_list.issuer.to_string(),
+            der: Bytes::copy_from_slice(der),
+        })
+    }
+
+    pub(crate) fn pem(&self) -> pem::Pem {
+        pem::Pem::new(CRL_PEM_TAG, self.der.as_ref())
+    }
+
+    fn certificate_list(&self) -> Result<CertificateList> {
+        CertificateList::from_der(&self.der).context("parsing CRL")
+    }
+
+    /// Whether the CRL was issued by the given cert, i.e. it names the cert's subject as its
+    /// issuer and is signed by the cert's key
+    pub(crate) fn is_issued_by(&self, cert: &CapturedX509Certificate) -> Result<bool> {
+        let certificate_list = self.certificate_list()?;
+        let certificate: &rfc5280::Certificate = cert.as_ref();
+
+        if certificate_list.tbs_cert_list.issuer != name_from_x509_certificate(&certificate.tbs_certificate.subject)? {
+            return Ok(false);
+        }
+
+        let Ok(signer_key_algorithm) = KeyAlgorithm::try_from(&certificate.tbs_certificate.subject_public_key_info.algorithm) else {
+            return Ok(false);
+        };
+        let Ok(verify_algorithm) = signature_policy::verification_algorithm(
+            &algorithm_identifier_from_x509_cert(&certificate_list.signature_algorithm)?,
+            signer_key_algorithm,
+        ) else {
+            return Ok(false);
+        };
+
+        let signed_data = certificate_list.tbs_cert_list.to_der().ok().context("encoding CRL TBS")?;
+        let signature = certificate_list.signature.as_bytes().context("unaligned CRL signature")?;
+
+        Ok(ring::signature::UnparsedPublicKey::new(
+            verify_algorithm,
+            certificate.tbs_certificate.subject_public_key_info.subject_public_key.octet_bytes(),
+        )
+        .verify(&signed_data, signature)
+        .is_ok())
+    }
+
+    /// Re-sign the CRL with the new key of its (regenerated) issuer. The revoked certs and the
+    /// update times are copied over as they are, re-signed certs keep their serial numbers (unless
+    /// the serial policy says otherwise) so their revocations still apply. The issuer name and AKID are taken from the new issuer
+    /// cert, as both might have changed along with it
+    pub(crate) fn re_sign(
+        &self,
+        new_issuer_cert: &CapturedX509Certificate,
+        new_issuer_skid: Option<&SubjectKeyIdentifier>,
+        signing_key: &InMemorySigningKeyPair,
+        signature_policy: &SignaturePolicy,
+    ) -> Result<Crl> {
+        let mut tbs_cert_list = self.certificate_list()?.tbs_cert_list;
+
+        let new_issuer_certificate: &rfc5280::Certificate = new_issuer_cert.as_ref();
+        tbs_cert_list.issuer = name_from_x509_certificate(&new_issuer_certificate.tbs_certificate.subject)?;
+
+        if let (Some(new_issuer_skid), Some(crl_extensions)) = (new_issuer_skid, &mut tbs_cert_list.crl_extensions) {
+            for extension in crl_extensions
+                .iter_mut()
+                .filter(|extension| extension.extn_id == AuthorityKeyIdentifier::OID)
+            {
+                let mut akid = AuthorityKeyIdentifier::from_der(extension.extn_value.as_bytes())
+                    .ok()
+                    .context("failed to parse CRL AKID extension")?;
+
+                if akid.key_identifier.is_none() {
+                    continue;
+                }
+
+                akid.key_identifier = Some(new_issuer_skid.0.clone());
+                extension.extn_value = OctetString::new(akid.to_der().ok().context("failed to encode CRL AKID extension")?)
+                    .ok()
+                    .context("failed to encode CRL AKID extension")?;
+            }
+        }
+
+        let signature_algorithm = algorithm_identifier_to_x509_cert(&signature_policy.algorithm_identifier(signing_key)?)?;
+        tbs_cert_list.signature = signature_algorithm.clone();
+
+        let tbs_der = tbs_cert_list.to_der().ok().context("encoding CRL TBS")?;
+        let signature = signature_policy.sign(signing_key, &tbs_der)?;
+
+        let certificate_list = CertificateList {
+            tbs_cert_list,
+            signature_algorithm,
+            signature: BitString::from_bytes(&signature).ok().context("encoding CRL signature")?,
+        };
+
+        Crl::from_der(&certificate_list.to_der().ok().context("encoding CRL")?)
+    }
+}
+
+// CRLs are handled with x509-cert, while certs and signing are done with x509-certificate, so the
+// names and algorithm identifiers shared between them are converted through their DER encoding
+
+fn name_from_x509_certificate(name: &x509_certificate::rfc3280::Name) -> Result<Name> {
+    Name::from_der(name.encode_ref().to_captured(Mode::Der).as_slice())
+        .ok()
+        .context("converting name")
+}
+
+fn algorithm_identifier_to_x509_cert(algorithm_identifier: &rfc5280::AlgorithmIdentifier) -> Result<AlgorithmIdentifierOwned> {
+    AlgorithmIdentifierOwned::from_der(algorithm_identifier.to_captured(Mode::Der).as_slice())
+        .ok()
+        .context("converting algorithm identifier")
+}
+
+fn algorithm_identifier_from_x509_cert(algorithm_identifier: &AlgorithmIdentifierOwned) -> Result<rfc5280::AlgorithmIdentifier> {
+    let der = algorithm_identifier.to_der().ok().context("encoding algorithm identifier")?;
+
+    bcder::decode::Constructed::decode(der.as_slice(), Mode::Der, rfc5280::AlgorithmIdentifier::take_from)
+        .ok()
+        .context("converting algorithm identifier")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use x509_cert::{
+        crl::{RevokedCert, TbsCertList},
+        ext::Extension,
+        serial_number::SerialNumber,
+        time::Time,
+        Version,
+    };
+    use x509_certificate::{EcdsaCurve, X509CertificateBuilder};
+
+    fn ca(common_name: &str) -> (CapturedX509Certificate, InMemorySigningKeyPair) {
+        let mut builder = X509CertificateBuilder::default();
+        builder.subject().append_common_name_utf8_string(common_name).unwrap();
+        let (cert, key_pair) = builder
+            .create_with_random_keypair(KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1))
+            .unwrap();
+        (cert, key_pair)
+    }
+
+    fn akid_key_identifier(crl: &Crl) -> Option<Vec<u8>> {
+        let extensions = crl.certificate_list().unwrap().tbs_cert_list.crl_extensions?;
+        let extension = extensions
+            .iter()
+            .find(|extension| extension.extn_id == AuthorityKeyIdentifier::OID)?;
+        let akid = AuthorityKeyIdentifier::from_der(extension.extn_value.as_bytes()).unwrap();
+        akid.key_identifier.map(|key_identifier| key_identifier.as_bytes().to_vec())
+    }
+
+    /// A CRL naming the given CA as its issuer, with a revoked cert and an AKID, but with a bogus
+    /// signature
+    fn unsigned_crl(issuer: &CapturedX509Certificate, issuer_key: &InMemorySigningKeyPair) -> Crl {
+        let issuer_certificate: &rfc5280::Certificate = issuer.as_ref();
+        let signature_algorithm =
+            algorithm_identifier_to_x509_cert(&SignaturePolicy::default().algorithm_identifier(issuer_key).unwrap()).unwrap();
+        let time = Time::UtcTime(der::asn1::UtcTime::from_unix_duration(Duration::from_secs(1_700_000_000)).unwrap());
+        let akid = AuthorityKeyIdentifier {
+            key_identifier: Some(OctetString::new(vec![1; 20]).unwrap()),
+            authority_cert_issuer: None,
+            authority_cert_serial_number: None,
+        };
+
+        let certificate_list = CertificateList {
+            tbs_cert_list: TbsCertList {
+                version: Version::V2,
+                signature: signature_algorithm.clone(),
+                issuer: name_from_x509_certificate(&issuer_certificate.tbs_certificate.subject).unwrap(),
+                this_update: time,
+                next_update: Some(time),
+                revoked_certificates: Some(vec![RevokedCert {
+                    serial_number: SerialNumber::new(&[0x42]).unwrap(),
+                    revocation_date: time,
+                    crl_entry_extensions: None,
+                }]),
+                crl_extensions: Some(vec![Extension {
+                    extn_id: AuthorityKeyIdentifier::OID,
+                    critical: false,
+                    extn_value: OctetString::new(akid.to_der().unwrap()).unwrap(),
+                }]),
+            },
+            signature_algorithm,
+            signature: BitString::from_bytes(&[0; 64]).unwrap(),
+        };
+
+        Crl::from_der(&certificate_list.to_der().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_re_sign() {
+        let (ca_cert, ca_key) = ca("ca");
+        let (other_ca_cert, other_ca_key) = ca("other-ca");
+        let crl = unsigned_crl(&ca_cert, &ca_key);
+        assert!(!crl.is_issued_by(&ca_cert).unwrap());
+
+        let new_skid = SubjectKeyIdentifier(OctetString::new(vec![2; 20]).unwrap());
+        let re_signed = crl
+            .re_sign(&ca_cert, Some(&new_skid), &ca_key, &SignaturePolicy::default())
+            .unwrap();
+        assert!(re_signed.is_issued_by(&ca_cert).unwrap());
+        assert!(!re_signed.is_issued_by(&other_ca_cert).unwrap());
+        assert_eq!(akid_key_identifier(&re_signed), Some(vec![2; 20]));
+        assert_eq!(
+            re_signed.certificate_list().unwrap().tbs_cert_list.revoked_certificates,
+            crl.certificate_list().unwrap().tbs_cert_list.revoked_certificates
+        );
+
+        // The issuer follows the new issuer cert, e.g. when the CA was renamed
+        let re_signed = crl
+            .re_sign(&other_ca_cert, None, &other_ca_key, &SignaturePolicy::default())
+            .unwrap();
+        assert!(re_signed.is_issued_by(&other_ca_cert).unwrap());
+        assert!(!re_signed.is_issued_by(&ca_cert).unwrap());
+        assert_eq!(re_signed.issuer, "CN=other-ca");
+        assert_eq!(akid_key_identifier(&re_signed), Some(vec![1; 20]));
+    }
+}
@@ -1,31 +1,39 @@
 use super::{
     certificate::{self, Certificate},
-    jwt,
+    crl::{self, Crl},
+    ini, jwt,
     keys::{PrivateKey, PublicKey},
-    locations::Location,
+    locations::{FieldEncoding, Location, PemBundleRole, YamlLocation},
+    private_key_format::{self, PrivateKeyPolicy},
+    ssh_keys::{self, OpenSshPrivateKey, OpenSshRsaPrivateKey, SshPublicKeyLine},
+    yaml_crawl, CryptoPolicies,
 };
 use crate::rules;
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{bail, Context, Result};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use bytes::Bytes;
-use p256::SecretKey;
 use pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePublicKey};
 use std::{
     io::Write,
     process::{Command, Stdio},
 };
 
 pub(crate) enum CryptoObject {
-    PrivateKey(PrivateKey, PublicKey),
+    PrivateKey(Box<PrivateKey>, PublicKey),
     PublicKey(PublicKey),
     Certificate(Certificate),
     Jwt(jwt::Jwt),
+    Crl(Crl),
+    /// Crypto recert found but doesn't know how to regenerate (DSA keys, CSRs, etc.), described
+    /// for the report. These are left untouched
+    Unsupported(String),
 }
 
 impl From<(PrivateKey, PublicKey)> for CryptoObject {
     fn from(keys: (PrivateKey, PublicKey)) -> Self {
         let (private_key, public_key) = keys;
-        CryptoObject::PrivateKey(private_key, public_key)
+        CryptoObject::PrivateKey(Box::new(private_key), public_key)
     }
 }
 
@@ -47,6 +55,12 @@ impl From<jwt::Jwt> for CryptoObject {
     }
 }
 
+impl From<Crl> for CryptoObject {
+    fn from(crl: Crl) -> Self {
+        CryptoObject::Crl(crl)
+    }
+}
+
 pub(crate) struct DiscoveredCryptoObect {
     pub(crate) crypto_object: CryptoObject,
     pub(crate) location: Location,
@@ -60,17 +74,133 @@ impl DiscoveredCryptoObect {
 
 /// Given a value taken from a YAML field, scan it for cryptographic keys and certificates and
 /// record them in the appropriate data structures.
-pub(crate) fn process_yaml_value(value: String, location: &Location) -> Result<Vec<DiscoveredCryptoObect>> {
-    let pem_bundle_objects = process_pem_bundle(&value, location).context("processing pem bundle")?;
-    if pem_bundle_objects.len() > 0 {
+pub(crate) fn process_yaml_value(value: String, location: &Location, policies: &CryptoPolicies) -> Result<Vec<DiscoveredCryptoObect>> {
+    let pem_bundle_objects = process_pem_bundle(&value, location, &policies.private_key).context("processing pem bundle")?;
+    if !pem_bundle_objects.is_empty() {
         return Ok(pem_bundle_objects);
     }
 
+    let ssh_public_keys = process_ssh_public_keys(&value, location).context("processing ssh public keys")?;
+    if !ssh_public_keys.is_empty() {
+        return Ok(ssh_public_keys);
+    }
+
     if let Some(jwt) = process_jwt(&value, location)? {
-        Ok(vec![jwt])
+        return Ok(vec![jwt]);
+    }
+
+    let kubeconfig_objects = process_embedded_kubeconfig(&value, location, policies).context("processing embedded kubeconfig")?;
+    if !kubeconfig_objects.is_empty() {
+        return Ok(kubeconfig_objects);
+    }
+
+    process_embedded_document(&value, location, policies).context("processing embedded document")
+}
+
+/// Kubeconfigs are sometimes stored whole in a secret/configmap value rather than in a file of
+/// their own, e.g. the admin kubeconfig secret. Their certs and keys are located within the
+/// embedded kubeconfig (see FieldEncoding::Yaml), so that they're regenerated along with their
+/// issuers and written back into the kubeconfig like those of kubeconfig files
+fn process_embedded_kubeconfig(value: &str, location: &Location, policies: &CryptoPolicies) -> Result<Vec<DiscoveredCryptoObect>> {
+    // Cheap check, this runs against every secret/configmap data entry
+    if !["certificate-authority-data", "client-certificate-data", "client-key-data"]
+        .iter()
+        .any(|field| value.contains(field))
+    {
+        return Ok(vec![]);
+    }
+
+    let Ok(kubeconfig @ serde_json::Value::Object(_)) = serde_yaml::from_str::<serde_json::Value>(value) else {
+        return Ok(vec![]);
+    };
+
+    // Anything which doesn't quite look like a kubeconfig is just some other YAML
+    let Ok(yaml_values) = yaml_crawl::scan_kubeconfig(&kubeconfig) else {
+        return Ok(vec![]);
+    };
+
+    let mut discovered = vec![];
+    for yaml_value in &yaml_values {
+        let Ok(Some((embedded_location, decoded))) = yaml_crawl::decode_yaml_value(yaml_value, &policies.scan) else {
+            continue;
+        };
+
+        discovered.extend(
+            process_pem_bundle(
+                &decoded,
+                &location.with_embedded(FieldEncoding::Yaml(
+                    embedded_location.json_pointer.clone(),
+                    Box::new(embedded_location.encoding.clone()),
+                ))?,
+                &policies.private_key,
+            )
+            .with_context(|| format!("processing pem bundle at {} of embedded kubeconfig", embedded_location.json_pointer))?,
+        );
+    }
+
+    Ok(discovered)
+}
+
+/// Besides kubeconfigs, secrets/configmaps hold other structured documents with certs in them,
+/// e.g. a JSON credentials file with a CA bundle in one of its fields, or a cloud provider config
+/// (INI) with base64 encoded CA data. Their PEMs are located at the field within the document
+/// (see FieldEncoding::Yaml and FieldEncoding::Ini) rather than at the whole value, so that only
+/// that field is rewritten and the rest of the document is left as it was
+fn process_embedded_document(value: &str, location: &Location, policies: &CryptoPolicies) -> Result<Vec<DiscoveredCryptoObect>> {
+    // Cheap check, the PEMs are JSON escaped or (once, possibly more) base64 encoded
+    if !value.contains("-----BEGIN ") && !value.contains("LS0tLS1CRUdJT") {
+        return Ok(vec![]);
+    }
+
+    // The embedded encoding locating each decoded value within the document, along with the value
+    let mut embedded_values = vec![];
+    // INI documents start with a [section] too
+    let json_document = value
+        .trim_start()
+        .starts_with(['{', '['])
+        .then(|| serde_json::from_str::<serde_json::Value>(value).ok())
+        .flatten();
+    if let Some(document) = json_document {
+        for yaml_value in yaml_crawl::scan_embedded_document(&document) {
+            let Ok(Some((embedded_location, decoded))) = yaml_crawl::decode_yaml_value(&yaml_value, &policies.scan) else {
+                continue;
+            };
+
+            embedded_values.push((
+                FieldEncoding::Yaml(embedded_location.json_pointer, Box::new(embedded_location.encoding)),
+                decoded,
+            ));
+        }
     } else {
-        Ok(vec![])
+        for entry in ini::entries(value) {
+            let yaml_value = yaml_crawl::YamlValue {
+                location: YamlLocation::new("", entry.key, FieldEncoding::None),
+                value: serde_json::Value::String(entry.value.to_string()),
+            };
+            let Ok(Some((embedded_location, decoded))) = yaml_crawl::decode_yaml_value(&yaml_value, &policies.scan) else {
+                continue;
+            };
+
+            embedded_values.push((
+                FieldEncoding::Ini(
+                    entry.section.to_string(),
+                    entry.key.to_string(),
+                    Box::new(embedded_location.encoding),
+                ),
+                decoded,
+            ));
+        }
+    }
+
+    let mut discovered = vec![];
+    for (embedded_encoding, decoded) in embedded_values {
+        discovered.extend(
+            process_pem_bundle(&decoded, &location.with_embedded(embedded_encoding.clone())?, &policies.private_key)
+                .with_context(|| format!("processing pem bundle at {:?} of embedded document", embedded_encoding))?,
+        );
     }
+
+    Ok(discovered)
 }
 
 /// Given a value taken from a YAML field, check if it looks like a JWT and record it in the
@@ -87,13 +217,13 @@ pub(crate) fn process_jwt(value: &str, location: &Location) -> Result<Option<Dis
     let payload = parts[1];
     let signature = parts[2];
 
-    if let Err(_) = URL_SAFE_NO_PAD.decode(header.as_bytes()) {
+    if URL_SAFE_NO_PAD.decode(header.as_bytes()).is_err() {
         return Ok(None);
     }
-    if let Err(_) = URL_SAFE_NO_PAD.decode(payload.as_bytes()) {
+    if URL_SAFE_NO_PAD.decode(payload.as_bytes()).is_err() {
         return Ok(None);
     }
-    if let Err(_) = URL_SAFE_NO_PAD.decode(signature.as_bytes()) {
+    if URL_SAFE_NO_PAD.decode(signature.as_bytes()).is_err() {
         return Ok(None);
     }
 
@@ -106,46 +236,191 @@ pub(crate) fn process_jwt(value: &str, location: &Location) -> Result<Option<Dis
 
 /// Given a PEM bundle, scan it for cryptographic keys and certificates and record them in the
 /// appropriate data structures.
-pub(crate) fn process_pem_bundle(value: &str, location: &Location) -> Result<Vec<DiscoveredCryptoObect>> {
-    let pems = pem::parse_many(value).context("parsing pem")?;
+pub(crate) fn process_pem_bundle(
+    value: &str,
+    location: &Location,
+    private_key_policy: &PrivateKeyPolicy,
+) -> Result<Vec<DiscoveredCryptoObect>> {
+    let pems = match pem::parse_many(value) {
+        Ok(pems) => pems,
+        // PGP armor looks like PEM, but has a checksum line which isn't part of the base64 data
+        Err(_) if value.contains("-----BEGIN PGP ") => {
+            return Ok(vec![DiscoveredCryptoObect::new(
+                CryptoObject::Unsupported("PGP armored block".to_string()),
+                location.clone(),
+            )])
+        }
+        Err(err) => return Err(err).context("parsing pem"),
+    };
 
-    pems.iter()
+    let crypto_objects = pems
+        .iter()
         .enumerate()
-        .map(|(pem_index, pem)| process_single_pem(pem).with_context(|| format!("processing pem at index {} in the bundle", pem_index)))
-        .collect::<Result<Vec<_>>>()?
+        .map(|(pem_index, pem)| {
+            process_single_pem(pem, private_key_policy).with_context(|| format!("processing pem at index {} in the bundle", pem_index))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let roles = pem_bundle_roles(&crypto_objects);
+
+    crypto_objects
         .into_iter()
+        .zip(roles)
         .enumerate()
-        .filter(|(_, crypto_object)| crypto_object.is_some())
-        .map(|(pem_index, crypto_object)| (pem_index, crypto_object.unwrap()))
-        .map(|(pem_index, crypto_object)| {
+        .filter(|(_, (crypto_object, _))| crypto_object.is_some())
+        .map(|(pem_index, (crypto_object, role))| (pem_index, crypto_object.unwrap(), role))
+        .map(|(pem_index, crypto_object, role)| {
             Ok(DiscoveredCryptoObect::new(
                 crypto_object,
-                location.with_pem_bundle_index(pem_index.try_into()?)?,
+                location.with_pem_bundle_index(pem_index.try_into()?, role)?,
             ))
         })
         .collect::<Result<Vec<_>>>()
 }
 
+/// The role of each PEM of a bundle. A bundle is a chain if it starts with a cert which isn't
+/// self-signed, followed by the cert which issued it, then by the one which issued that, and so on.
+/// Only names are compared here, signatures are checked once the chain has been committed, see
+/// CertKeyPair::verify_committed_chains
+fn pem_bundle_roles(crypto_objects: &[Option<CryptoObject>]) -> Vec<PemBundleRole> {
+    let certificate = |index: usize| match crypto_objects.get(index) {
+        Some(Some(CryptoObject::Certificate(certificate))) => Some(certificate),
+        _ => None,
+    };
+
+    let mut chain_length = 0;
+    while let (Some(signee), Some(signer)) = (certificate(chain_length), certificate(chain_length + 1)) {
+        if signee.issuer == signee.subject || signee.issuer != signer.subject {
+            break;
+        }
+        chain_length += 1;
+    }
+
+    (0..crypto_objects.len())
+        .map(|index| match index {
+            _ if chain_length == 0 || index > chain_length => PemBundleRole::Member,
+            0 => PemBundleRole::Leaf,
+            _ => PemBundleRole::Chain,
+        })
+        .collect()
+}
+
+/// Given an authorized_keys style list of SSH public keys, one per line, record the keys in the
+/// appropriate data structures.
+pub(crate) fn process_ssh_public_keys(value: &str, location: &Location) -> Result<Vec<DiscoveredCryptoObect>> {
+    value
+        .lines()
+        .enumerate()
+        .filter_map(|(line_index, line)| Some((line_index, SshPublicKeyLine::parse(line)?)))
+        .map(|(line_index, ssh_public_key)| {
+            let crypto_object = match ssh_public_key {
+                SshPublicKeyLine::Rsa(rsa_public_key) => {
+                    // Same encoding as the public parts of RSA private keys, so the two get paired
+                    PublicKey::from_rsa_bytes(&Bytes::copy_from_slice(rsa_public_key.to_public_key_der()?.as_bytes())).into()
+                }
+                SshPublicKeyLine::Unsupported(description) => CryptoObject::Unsupported(description),
+            };
+
+            Ok(DiscoveredCryptoObect::new(
+                crypto_object,
+                location.with_ssh_public_key_line(line_index.try_into()?)?,
+            ))
+        })
+        .collect()
+}
+
+/// Given the raw contents of a binary file, try to interpret it as a single DER encoded
+/// certificate, private key (PKCS#1, PKCS#8 or SEC1) or CRL. Files that are neither are silently
+/// ignored, as there's no reliable way to tell an unsupported DER object apart from an arbitrary
+/// binary file.
+pub(crate) fn process_der(value: &[u8], location: &Location, private_key_policy: &PrivateKeyPolicy) -> Result<Vec<DiscoveredCryptoObect>> {
+    let crypto_object = if x509_certificate::CapturedX509Certificate::from_der(value).is_ok() {
+        process_pem_cert(&pem::Pem::new("CERTIFICATE", value)).context("processing der cert")?
+    } else if let Some(tag) = der_private_key_tag(value) {
+        process_single_pem(&pem::Pem::new(tag, value), private_key_policy).context("processing der private key")?
+    } else if let Ok(crl) = Crl::from_der(value) {
+        Some(crl.into())
+    } else {
+        None
+    };
+
+    Ok(crypto_object
+        .into_iter()
+        .map(|crypto_object| DiscoveredCryptoObect::new(crypto_object, location.clone()))
+        .collect())
+}
+
+/// The tag the PEM of a DER encoded private key would have, None when it isn't one. Keys are
+/// written back to DER files in the encoding they were found in, just like to PEM bundles
+pub(crate) fn der_private_key_tag(der: &[u8]) -> Option<&'static str> {
+    if rsa::RsaPrivateKey::from_pkcs1_der(der).is_ok() {
+        Some("RSA PRIVATE KEY")
+    } else if rsa::pkcs8::PrivateKeyInfo::try_from(der).is_ok() {
+        Some(private_key_format::PKCS8_TAG)
+    } else if p256::SecretKey::from_sec1_der(der).is_ok() || p384::SecretKey::from_sec1_der(der).is_ok() {
+        Some("EC PRIVATE KEY")
+    } else {
+        None
+    }
+}
+
 /// Given a single PEM, scan it for cryptographic keys and certificates and record them in the
 /// appropriate data structures.
-pub(crate) fn process_single_pem(pem: &pem::Pem) -> Result<Option<CryptoObject>> {
+pub(crate) fn process_single_pem(pem: &pem::Pem, private_key_policy: &PrivateKeyPolicy) -> Result<Option<CryptoObject>> {
     match pem.tag() {
         "CERTIFICATE" => process_pem_cert(pem).context("processing pem cert"),
         "TRUSTED CERTIFICATE" => process_pem_cert(pem).context("processing trusted pem cert"), // TODO: we'll have to save it back as TRUSTED
         "RSA PRIVATE KEY" => process_pem_rsa_private_key(pem).context("processing pem rsa private key"),
         "EC PRIVATE KEY" => process_pem_ec_private_key(pem).context("processing pem ec private key"),
-        "PRIVATE KEY" => Err(anyhow!("private pkcs8 unsupported")),
-        "PUBLIC KEY" => Err(anyhow!("private pkcs8 unsupported")),
+        ssh_keys::OPENSSH_PRIVATE_KEY_TAG => process_pem_openssh_private_key(pem).context("processing pem openssh private key"),
+        private_key_format::PKCS8_TAG => process_pem_pkcs8_private_key(pem).context("processing pem pkcs8 private key"),
+        private_key_format::ENCRYPTED_PKCS8_TAG => match private_key_policy.decrypt(pem)? {
+            Some(decrypted) => process_pem_pkcs8_private_key(&decrypted).context("processing decrypted pem pkcs8 private key"),
+            None => Ok(Some(CryptoObject::Unsupported(describe_unsupported_pem(pem.tag())))),
+        },
+        "PUBLIC KEY" => process_pem_spki_public_key(pem).context("processing pem spki public key"),
         "RSA PUBLIC KEY" => Ok(process_pem_public_key(pem)),
-        "ENTITLEMENT DATA" | "RSA SIGNATURE" => Ok(None),
-        _ => Err(anyhow!("unknown pem tag {}", pem.tag())),
+        crl::CRL_PEM_TAG => Ok(Some(Crl::from_der(pem.contents())?.into())),
+        tag => Ok(Some(CryptoObject::Unsupported(describe_unsupported_pem(tag)))),
+    }
+}
+
+fn describe_unsupported_pem(tag: &str) -> String {
+    match tag {
+        "DSA PRIVATE KEY" => "DSA private key",
+        private_key_format::ENCRYPTED_PKCS8_TAG => "encrypted private key, see --private-key-passphrase-file",
+        "CERTIFICATE REQUEST" | "NEW CERTIFICATE REQUEST" => "certificate signing request",
+        "ENTITLEMENT DATA" | "RSA SIGNATURE" => "entitlement data",
+        _ if tag.starts_with("PGP ") => "PGP armored block",
+        _ => return format!("PEM with unknown tag {}", tag),
     }
+    .to_string()
 }
 
 pub(crate) fn process_pem_public_key(pem: &pem::Pem) -> Option<CryptoObject> {
     Some(PublicKey::from_rsa_bytes(&bytes::Bytes::copy_from_slice(pem.contents())).into())
 }
 
+/// Given a SubjectPublicKeyInfo PEM, record it in the appropriate data structures. EC keys are held
+/// in the same encoding as the public parts of EC private keys, so the two get paired. RSA keys are
+/// held as the contents of RSA PUBLIC KEY PEMs and would be written back as one, so they're only
+/// reported, as are keys of other algorithms
+pub(crate) fn process_pem_spki_public_key(pem: &pem::Pem) -> Result<Option<CryptoObject>> {
+    let public_key_pem = if let Ok(public_key) = p256::PublicKey::from_public_key_der(pem.contents()) {
+        public_key.to_string()
+    } else if let Ok(public_key) = p384::PublicKey::from_public_key_der(pem.contents()) {
+        public_key.to_string()
+    } else if rsa::RsaPublicKey::from_public_key_der(pem.contents()).is_ok() {
+        return Ok(Some(CryptoObject::Unsupported("RSA public key PEM tagged PUBLIC KEY".to_string())));
+    } else {
+        return Ok(Some(CryptoObject::Unsupported(
+            "public key of an unsupported algorithm".to_string(),
+        )));
+    };
+
+    Ok(Some(PublicKey::Ec(Bytes::from(public_key_pem.into_bytes())).into()))
+}
+
 /// Given an RSA private key PEM, record it in the appropriate data structures.
 pub(crate) fn process_pem_rsa_private_key(pem: &pem::Pem) -> Result<Option<CryptoObject>> {
     let rsa_private_key = rsa::RsaPrivateKey::from_pkcs1_pem(&pem.to_string())?;
@@ -156,6 +431,38 @@ pub(crate) fn process_pem_rsa_private_key(pem: &pem::Pem) -> Result<Option<Crypt
     Ok(Some((private_part, public_part).into()))
 }
 
+/// Given a PKCS#8 private key PEM, record it in the appropriate data structures. The keys are held
+/// just like the PKCS#1 / SEC1 keys of the same algorithm, see PrivateKeyPolicy::reencode_like for
+/// how they're written back
+pub(crate) fn process_pem_pkcs8_private_key(pem: &pem::Pem) -> Result<Option<CryptoObject>> {
+    if let Ok(rsa_private_key) = rsa::RsaPrivateKey::from_pkcs8_der(pem.contents()) {
+        let private_part = PrivateKey::Rsa(rsa_private_key);
+        let public_part = PublicKey::try_from(&private_part)?;
+
+        return Ok(Some((private_part, public_part).into()));
+    }
+
+    if p256::SecretKey::from_pkcs8_der(pem.contents()).is_ok() || p384::SecretKey::from_pkcs8_der(pem.contents()).is_ok() {
+        return process_pem_ec_private_key(pem);
+    }
+
+    Ok(Some(CryptoObject::Unsupported(
+        "PKCS#8 private key of an unsupported algorithm".to_string(),
+    )))
+}
+
+/// Given an OpenSSH private key PEM, record it in the appropriate data structures.
+pub(crate) fn process_pem_openssh_private_key(pem: &pem::Pem) -> Result<Option<CryptoObject>> {
+    Ok(Some(match OpenSshRsaPrivateKey::parse(pem)? {
+        OpenSshPrivateKey::Rsa(openssh_private_key) => {
+            let private_part = PrivateKey::Rsa(openssh_private_key.key);
+            let public_part = PublicKey::try_from(&private_part)?;
+            (private_part, public_part).into()
+        }
+        OpenSshPrivateKey::Unsupported(description) => CryptoObject::Unsupported(description),
+    }))
+}
+
 /// Given an EC private key PEM, record it in the appropriate data structures.
 pub(crate) fn process_pem_ec_private_key(pem: &pem::Pem) -> Result<Option<CryptoObject>> {
     // First convert to pkcs#8 by shelling out to openssl pkcs8 -topk8 -nocrypt:
@@ -176,20 +483,15 @@ pub(crate) fn process_pem_ec_private_key(pem: &pem::Pem) -> Result<Option<Crypto
     let output = command.wait_with_output()?;
     let pem = pem::parse(output.stdout)?;
 
-    let key = pem.to_string().parse::<SecretKey>()?;
-    let public_key = key.public_key();
-
-    let private_part = PrivateKey::Ec(Bytes::copy_from_slice(pem.contents()));
-    let public_part = PublicKey::Ec(Bytes::copy_from_slice(public_key.to_string().as_bytes()));
+    let private_part = PrivateKey::Ec(pem.contents().into());
+    let public_part = PublicKey::try_from(&private_part)?;
 
     Ok(Some((private_part, public_part).into()))
 }
 
 /// Given a certificate PEM, record it in the appropriate data structures.
 pub(crate) fn process_pem_cert(pem: &pem::Pem) -> Result<Option<CryptoObject>> {
-    let x509_certificate = &x509_certificate::CapturedX509Certificate::from_der(pem.contents()).context("parsing DER")?;
-
-    let hashable_cert = certificate::Certificate::try_from(x509_certificate.clone()).context("parsing cert")?;
+    let hashable_cert = certificate::Certificate::from_der_cached(pem.contents())?;
 
     if rules::EXTERNAL_CERTS.contains(&hashable_cert.subject) {
         return Ok(None);
@@ -205,3 +507,295 @@ pub(crate) fn process_pem_cert(pem: &pem::Pem) -> Result<Option<CryptoObject>> {
 
     Ok(Some(CryptoObject::from(hashable_cert)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster_crypto::{
+            crypto_utils::{generate_ec_key, generate_rsa_key},
+            keys::EcCurve,
+            locations::{FieldEncoding, FileContentLocation, FileLocation, K8sResourceLocation, LocationValueType, YamlLocation},
+        },
+        file_utils,
+    };
+    use base64::engine::general_purpose::STANDARD as base64_standard;
+    use pkcs1::EncodeRsaPrivateKey;
+    use rsa::pkcs8::EncodePrivateKey;
+    use x509_certificate::{EcdsaCurve, KeyAlgorithm, X509CertificateBuilder};
+
+    fn bundle_location() -> Location {
+        Location::Filesystem(FileLocation {
+            path: "/etc/bundle.pem".to_string(),
+            content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+        })
+    }
+
+    fn unsupported(value: &str) -> Vec<String> {
+        process_pem_bundle(value, &bundle_location(), &PrivateKeyPolicy::default())
+            .unwrap()
+            .into_iter()
+            .map(|discovered| match discovered.crypto_object {
+                CryptoObject::Unsupported(description) => description,
+                _ => panic!("expected only unsupported objects"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_unsupported_pems() {
+        let bundle = pem::encode_many(&[
+            pem::Pem::new("DSA PRIVATE KEY", vec![1; 16]),
+            pem::Pem::new("SOMETHING ELSE", vec![2; 16]),
+        ]);
+        assert_eq!(unsupported(&bundle), vec!["DSA private key", "PEM with unknown tag SOMETHING ELSE"]);
+
+        let pgp = "-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nmQINBGRhZGVmAAAA\n=AbCd\n-----END PGP PUBLIC KEY BLOCK-----\n";
+        assert_eq!(unsupported(pgp), vec!["PGP armored block"]);
+    }
+
+    #[test]
+    fn test_spki_public_keys() {
+        let (ec_private_key, _) = generate_ec_key(EcCurve::P384).unwrap();
+        let ec_public_key = PublicKey::try_from(&ec_private_key).unwrap();
+        let discovered = process_pem_bundle(
+            &pem::encode(&ec_public_key.pem().unwrap()),
+            &bundle_location(),
+            &PrivateKeyPolicy::default(),
+        )
+        .unwrap();
+        match &discovered[..] {
+            [DiscoveredCryptoObect {
+                crypto_object: CryptoObject::PublicKey(public_key),
+                ..
+            }] => assert_eq!(*public_key, ec_public_key),
+            _ => panic!("expected a single public key"),
+        }
+
+        let (rsa_private_key, _) = generate_rsa_key(2048).unwrap();
+        let rsa_spki = pem::Pem::new(
+            "PUBLIC KEY",
+            rsa_private_key.to_public_key().to_public_key_der().unwrap().as_bytes(),
+        );
+        assert_eq!(unsupported(&pem::encode(&rsa_spki)), vec!["RSA public key PEM tagged PUBLIC KEY"]);
+    }
+
+    fn cert_pem(subject: &str, issuer: &str) -> pem::Pem {
+        let mut builder = X509CertificateBuilder::default();
+        builder.subject().append_common_name_utf8_string(subject).unwrap();
+        builder.issuer().append_common_name_utf8_string(issuer).unwrap();
+        let (cert, _) = builder
+            .create_with_random_keypair(KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1))
+            .unwrap();
+
+        pem::parse(cert.encode_pem()).unwrap()
+    }
+
+    fn roles(pems: &[&pem::Pem]) -> Vec<PemBundleRole> {
+        let bundle = pem::encode_many(&pems.iter().map(|pem| (*pem).clone()).collect::<Vec<_>>());
+
+        process_pem_bundle(&bundle, &bundle_location(), &PrivateKeyPolicy::default())
+            .unwrap()
+            .into_iter()
+            .map(|discovered| match discovered.location {
+                Location::Filesystem(FileLocation {
+                    content_location: FileContentLocation::Raw(LocationValueType::Pem(pem_location_info)),
+                    ..
+                }) => pem_location_info.role,
+                location => panic!("unexpected location {}", location),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pem_bundle_roles() {
+        use PemBundleRole::{Chain, Leaf, Member};
+
+        let leaf = cert_pem("leaf", "intermediate");
+        let intermediate = cert_pem("intermediate", "root");
+        let root = cert_pem("root", "root");
+        let other = cert_pem("other", "other");
+
+        assert_eq!(roles(&[&leaf, &intermediate, &root]), vec![Leaf, Chain, Chain]);
+        assert_eq!(roles(&[&leaf, &intermediate, &other]), vec![Leaf, Chain, Member]);
+        assert_eq!(roles(&[&leaf, &root, &intermediate]), vec![Member, Member, Member]);
+        assert_eq!(roles(&[&leaf]), vec![Member]);
+        // CA bundles often hold certs which sign each other, but they don't start with a leaf
+        assert_eq!(roles(&[&root, &intermediate, &leaf]), vec![Member, Member, Member]);
+    }
+
+    #[test]
+    fn test_embedded_kubeconfig() {
+        let ca = cert_pem("ca", "ca");
+        let kubeconfig = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Config",
+            "clusters": [{"name": "cluster", "cluster": {
+                "server": "https://api.example.com:6443",
+                "certificate-authority-data": base64_standard.encode(pem::encode(&ca)),
+            }}],
+            "users": [],
+        });
+
+        let kubeconfig_yaml = serde_yaml::to_string(&kubeconfig).unwrap();
+        let entry = base64_standard.encode(&kubeconfig_yaml);
+        let location = Location::k8s_yaml(
+            &K8sResourceLocation::new(Some("openshift-kube-apiserver"), "Secret", "node-kubeconfigs", "v1"),
+            &YamlLocation::new("/data", "lb-ext.kubeconfig", FieldEncoding::Base64),
+        );
+
+        let embedded = process_embedded_kubeconfig(&kubeconfig_yaml, &location, &CryptoPolicies::default()).unwrap();
+        assert_eq!(embedded.len(), 1);
+        assert!(matches!(embedded[0].crypto_object, CryptoObject::Certificate(_)));
+        let Location::K8s(k8s_location) = &embedded[0].location else {
+            panic!("unexpected location {}", embedded[0].location);
+        };
+        assert_eq!(
+            k8s_location.yaml_location.to_string(),
+            ":/data/lb-ext.kubeconfig:/clusters/0/cluster/certificate-authority-data:pem0"
+        );
+
+        // The cert is read from, and written back into, the kubeconfig inside of the secret
+        let encoding = &k8s_location.yaml_location.encoding;
+        assert_eq!(file_utils::decode_field(encoding, &entry).unwrap(), pem::encode(&ca));
+        let new_ca = pem::encode(&cert_pem("new-ca", "new-ca"));
+        let new_entry = file_utils::reencode_field(encoding, &entry, &new_ca).unwrap();
+        let new_kubeconfig: serde_json::Value =
+            serde_yaml::from_str(&file_utils::decode_field(&FieldEncoding::Base64, &new_entry).unwrap()).unwrap();
+        assert_eq!(new_kubeconfig["clusters"][0]["cluster"]["server"], "https://api.example.com:6443");
+        assert_eq!(file_utils::decode_field(encoding, &new_entry).unwrap(), new_ca);
+        assert!(file_utils::encode_field(encoding, &new_ca).is_err());
+
+        // Kubeconfigs without crypto objects and other YAML are not interesting
+        let mut without_crypto = kubeconfig.clone();
+        without_crypto["clusters"][0]["cluster"]
+            .as_object_mut()
+            .unwrap()
+            .remove("certificate-authority-data");
+        assert!(process_embedded_kubeconfig(
+            &serde_yaml::to_string(&without_crypto).unwrap(),
+            &location,
+            &CryptoPolicies::default()
+        )
+        .unwrap()
+        .is_empty());
+        assert!(process_embedded_kubeconfig(
+            "users: not a list\nclusters: [{certificate-authority-data: x}]\n",
+            &location,
+            &CryptoPolicies::default()
+        )
+        .unwrap()
+        .is_empty());
+        assert!(process_embedded_kubeconfig("just a string", &location, &CryptoPolicies::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    fn embedded_yaml_location(value: &str, location: &Location) -> (String, FieldEncoding) {
+        let embedded = process_embedded_document(value, location, &CryptoPolicies::default()).unwrap();
+        assert_eq!(embedded.len(), 1);
+        assert!(matches!(embedded[0].crypto_object, CryptoObject::Certificate(_)));
+        let Location::K8s(k8s_location) = &embedded[0].location else {
+            panic!("unexpected location {}", embedded[0].location);
+        };
+
+        (k8s_location.yaml_location.to_string(), k8s_location.yaml_location.encoding.clone())
+    }
+
+    #[test]
+    fn test_embedded_documents() {
+        let ca = pem::encode(&cert_pem("ca", "ca"));
+        let new_ca = pem::encode(&cert_pem("new-ca", "new-ca"));
+        let location = |key: &str| {
+            Location::k8s_yaml(
+                &K8sResourceLocation::new(Some("openshift-config"), "ConfigMap", "cloud-provider-config", "v1"),
+                &YamlLocation::new("/data", key, FieldEncoding::None),
+            )
+        };
+
+        // Only the field holding the cert is rewritten, the rest of the JSON stays as it was
+        let credentials = serde_json::json!({"endpoint": "https://storage.example.com", "tls": {"ca": ca}}).to_string();
+        let (display, encoding) = embedded_yaml_location(&credentials, &location("credentials.json"));
+        assert_eq!(display, ":/data/credentials.json:/tls/ca:pem0");
+        assert_eq!(file_utils::decode_field(&encoding, &credentials).unwrap(), ca);
+        let new_credentials = file_utils::reencode_field(&encoding, &credentials, &new_ca).unwrap();
+        assert_eq!(
+            new_credentials,
+            serde_json::json!({"endpoint": "https://storage.example.com", "tls": {"ca": new_ca}}).to_string()
+        );
+
+        // Same for the (base64 encoded) value of an INI key, down to its spacing and quoting
+        let cloud_config = format!(
+            "[Global]\nsecret-name = \"vsphere-creds\"\nca-cert-data = \"{}\"\n",
+            base64_standard.encode(&ca)
+        );
+        let (display, encoding) = embedded_yaml_location(&cloud_config, &location("config"));
+        assert_eq!(display, ":/data/config:[Global]ca-cert-data:pem0");
+        assert_eq!(file_utils::decode_field(&encoding, &cloud_config).unwrap(), ca);
+        assert_eq!(
+            file_utils::reencode_field(&encoding, &cloud_config, &new_ca).unwrap(),
+            cloud_config.replace(&base64_standard.encode(&ca), &base64_standard.encode(&new_ca))
+        );
+
+        assert!(process_embedded_document(
+            "[Global]\nsecret-name = vsphere-creds\n",
+            &location("config"),
+            &CryptoPolicies::default()
+        )
+        .unwrap()
+        .is_empty());
+    }
+
+    #[test]
+    fn test_der() {
+        let location = Location::Filesystem(FileLocation {
+            path: "/etc/pki/tls.der".to_string(),
+            content_location: FileContentLocation::Der,
+        });
+        let discovered = |der: &[u8]| {
+            process_der(der, &location, &PrivateKeyPolicy::default())
+                .unwrap()
+                .into_iter()
+                .map(|discovered| discovered.crypto_object)
+                .collect::<Vec<_>>()
+        };
+        let is_private_key = |der: &[u8], expected_tag: &str| {
+            assert_eq!(der_private_key_tag(der), Some(expected_tag));
+            match &discovered(der)[..] {
+                [CryptoObject::PrivateKey(private_key, _)] => (**private_key).clone(),
+                _ => panic!("expected a single private key"),
+            }
+        };
+
+        let cert = cert_pem("der", "der");
+        assert!(matches!(&discovered(cert.contents())[..], [CryptoObject::Certificate(_)]));
+
+        let (rsa_private_key, _) = generate_rsa_key(2048).unwrap();
+        let pkcs1_der = rsa_private_key.to_pkcs1_der().unwrap();
+        assert!(matches!(
+            is_private_key(pkcs1_der.as_bytes(), "RSA PRIVATE KEY"),
+            PrivateKey::Rsa(private_key) if private_key == rsa_private_key
+        ));
+        let pkcs8_der = rsa_private_key.to_pkcs8_der().unwrap();
+        assert!(matches!(
+            is_private_key(pkcs8_der.as_bytes(), private_key_format::PKCS8_TAG),
+            PrivateKey::Rsa(private_key) if private_key == rsa_private_key
+        ));
+
+        let (ec_private_key, _) = generate_ec_key(EcCurve::P256).unwrap();
+        let PrivateKey::Ec(ec_pkcs8_der) = &ec_private_key else {
+            panic!("expected an EC private key");
+        };
+        assert!(matches!(
+            is_private_key(ec_pkcs8_der, private_key_format::PKCS8_TAG),
+            PrivateKey::Ec(_)
+        ));
+        let sec1_pem = ec_private_key.pem().unwrap();
+        assert!(matches!(is_private_key(sec1_pem.contents(), "EC PRIVATE KEY"), PrivateKey::Ec(_)));
+
+        // A PNG header, binary but not crypto
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR\x00\x00\x00\x01\x00\x00\x00\x01\x08\x06\x00\x00\x00";
+        assert_eq!(der_private_key_tag(png), None);
+        assert!(discovered(png).is_empty());
+    }
+}
@@ -1,17 +1,30 @@
-use super::{cert_key_pair::CertKeyPair, distributed_jwt, keys};
-use anyhow::{bail, Context, Result};
+use super::{
+    cert_key_pair::CertKeyPair,
+    certificate::Certificate,
+    distributed_jwt, entropy,
+    keys::{self, EcCurve, PrivateKey, PublicKey},
+    signature_policy,
+};
+use anyhow::{bail, ensure, Context, Result};
 use bcder::{encode::Values, Mode};
+use der::Decode;
 use jwt_simple::prelude::RSAPublicKeyLike;
+use rand::{CryptoRng, RngCore};
 use rsa::{
     self,
-    pkcs8::{DecodePrivateKey, EncodePrivateKey},
+    pkcs8::{spki::SubjectPublicKeyInfoRef, DecodePrivateKey, EncodePrivateKey},
     RsaPrivateKey,
 };
 use serde_json::{Map, Value};
+use sha2::Digest;
 use std::process::Command as StdCommand;
 use std::{cell::RefCell, io::Write, rc::Rc};
+use subtle::ConstantTimeEq;
 use tokio::process::Command;
-use x509_certificate::{rfc5280, InMemorySigningKeyPair};
+use x509_certificate::{
+    rfc5280, CapturedX509Certificate, InMemorySigningKeyPair, KeyAlgorithm, Sign, X509Certificate, X509CertificateError,
+};
+use zeroize::Zeroizing;
 
 /// Shell out to openssl to verify that a certificate is signed by a given signing certificate. We
 /// use this when our certificate lib doesn't support the signature algorithm used by the
@@ -37,7 +50,7 @@ pub(crate) fn openssl_is_signed(potential_signer: &Rc<RefCell<CertKeyPair>>, sig
 
     let mut signing_cert_file = tempfile::NamedTempFile::new()?;
     signing_cert_file.write_all(
-        &(*(**potential_signer).borrow().distributed_cert)
+        (*(**potential_signer).borrow().distributed_cert)
             .borrow()
             .certificate
             .original
@@ -46,7 +59,7 @@ pub(crate) fn openssl_is_signed(potential_signer: &Rc<RefCell<CertKeyPair>>, sig
     )?;
     let mut signed_cert_file = tempfile::NamedTempFile::new()?;
     signed_cert_file.write_all(
-        &(*(**signee).borrow().distributed_cert)
+        (*(**signee).borrow().distributed_cert)
             .borrow()
             .certificate
             .original
@@ -67,30 +80,109 @@ pub(crate) fn openssl_is_signed(potential_signer: &Rc<RefCell<CertKeyPair>>, sig
     Ok(openssl_verify_output.status.success())
 }
 
+/// Verify that a cert is signed by the key of another cert.
+/// X509Certificate::verify_signed_by_certificate picks the verification algorithm according to the
+/// kind of key of the signed cert rather than that of the signer, so it can't verify certs signed
+/// by a different kind of key than their own, e.g. an EC cert signed by an RSA CA
+pub(crate) fn verify_signed_by_certificate(
+    signee: &CapturedX509Certificate,
+    signer: &CapturedX509Certificate,
+) -> Result<(), X509CertificateError> {
+    let signer: &rfc5280::Certificate = signer.as_ref();
+    let signer_key_algorithm = KeyAlgorithm::try_from(&signer.tbs_certificate.subject_public_key_info.algorithm)?;
+
+    // Re-parse to get at the exact bytes that were signed
+    let signee = X509Certificate::from_der(signee.constructed_data())?;
+    let signee: &rfc5280::Certificate = signee.as_ref();
+    let Some(signed_data) = &signee.tbs_certificate.raw_data else {
+        return Err(X509CertificateError::CertificateSignatureVerificationFailed);
+    };
+
+    let verify_algorithm = signature_policy::verification_algorithm(&signee.signature_algorithm, signer_key_algorithm)?;
+
+    ring::signature::UnparsedPublicKey::new(
+        verify_algorithm,
+        signer.tbs_certificate.subject_public_key_info.subject_public_key.octet_bytes(),
+    )
+    .verify(signed_data, &signee.signature.octet_bytes())
+    .map_err(|_| X509CertificateError::CertificateSignatureVerificationFailed)
+}
+
+/// Check that the chain at the start of a PEM bundle (see locations::PemBundleRole) is intact:
+/// each cert of it must be signed by the cert following it, whenever that one is named as its
+/// issuer. Certs which aren't followed by their issuer end the chain
+pub(crate) fn verify_pem_bundle_chain(pem_bundle: &str) -> Result<()> {
+    let certs = pem::parse_many(pem_bundle)
+        .context("parsing pem bundle")?
+        .iter()
+        .map_while(|pem| (pem.tag() == "CERTIFICATE").then(|| CapturedX509Certificate::from_der(pem.contents())))
+        .collect::<Result<Vec<_>, _>>()
+        .context("parsing chain certs")?;
+
+    for (index, pair) in certs.windows(2).enumerate() {
+        let [signee, signer] = pair else {
+            unreachable!("windows of 2");
+        };
+
+        let issuer = signee.issuer_name().user_friendly_str().context("decoding issuer")?;
+        if signee.subject_is_issuer() || issuer != signer.subject_name().user_friendly_str().context("decoding subject")? {
+            break;
+        }
+
+        match verify_signed_by_certificate(signee, signer) {
+            Ok(()) => {}
+            // Nothing we can tell about these, just like when filling cert signers
+            Err(X509CertificateError::UnsupportedSignatureVerification(..) | X509CertificateError::UnknownSignatureAlgorithm(..)) => {}
+            Err(X509CertificateError::CertificateSignatureVerificationFailed) => {
+                bail!("cert {} of the chain ({}) isn't signed by the cert following it", index, issuer)
+            }
+            Err(err) => return Err(err).with_context(|| format!("verifying cert {} of the chain", index)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Verify the signature of the JWT, whatever its times. Expired tokens (such as bound service
+/// account tokens which nothing refreshed) are re-signed along with the rest, so only the signature
+/// is checked: the verifier's clock is set to the middle of the timestamps jwt_simple can represent
+/// (2^32 seconds since the epoch), with a tolerance covering all of them
 pub(crate) fn verify_jwt(
     public_key: &keys::PublicKey,
     distributed_jwt: &distributed_jwt::DistributedJwt,
 ) -> Result<jwt_simple::prelude::JWTClaims<Map<String, Value>>, jwt_simple::Error> {
+    let options = jwt_simple::prelude::VerificationOptions {
+        accept_future: true,
+        artificial_time: Some(jwt_simple::prelude::Duration::from_secs(1 << 31)),
+        time_tolerance: Some(jwt_simple::prelude::Duration::from_secs((1 << 31) - 1)),
+        ..Default::default()
+    };
+
     match &public_key {
         keys::PublicKey::Rsa(bytes) => jwt_simple::prelude::RS256PublicKey::from_der(bytes)?,
         keys::PublicKey::Ec(_) => bail!("EC public keys are not supported"),
     }
-    .verify_token::<Map<String, Value>>(&distributed_jwt.jwt.str, None)
+    .verify_token::<Map<String, Value>>(&distributed_jwt.jwt.str, Some(options))
 }
 
 pub(crate) async fn generate_rsa_key_async(key_size: usize) -> Result<(RsaPrivateKey, InMemorySigningKeyPair)> {
+    let output = Command::new("openssl")
+        .args(["genrsa", &key_size.to_string()])
+        .args(entropy::openssl_rand_args())
+        .output()
+        .await
+        .context("openssl genrsa")?;
+    ensure!(
+        output.status.success(),
+        "openssl genrsa {} failed: {}",
+        key_size,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
     let rsa_private_key = RsaPrivateKey::from_pkcs8_pem(
-        String::from_utf8(
-            Command::new("openssl")
-                .args(&["genrsa", &key_size.to_string()])
-                .output()
-                .await
-                .context("openssl genrsa")?
-                .stdout,
-        )
-        .context("converting openssl key to utf-8")?
-        .to_string()
-        .as_str(),
+        String::from_utf8(output.stdout)
+            .context("converting openssl key to utf-8")?
+            .as_str(),
     )
     .context("private from pem")?;
 
@@ -100,17 +192,22 @@ pub(crate) async fn generate_rsa_key_async(key_size: usize) -> Result<(RsaPrivat
 }
 
 pub(crate) fn generate_rsa_key(key_size: usize) -> Result<(RsaPrivateKey, InMemorySigningKeyPair)> {
+    let output = StdCommand::new("openssl")
+        .args(["genrsa", &key_size.to_string()])
+        .args(entropy::openssl_rand_args())
+        .output()
+        .context("openssl genrsa")?;
+    ensure!(
+        output.status.success(),
+        "openssl genrsa {} failed: {}",
+        key_size,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
     let rsa_private_key = RsaPrivateKey::from_pkcs8_pem(
-        String::from_utf8(
-            StdCommand::new("openssl")
-                .args(&["genrsa", &key_size.to_string()])
-                .output()
-                .context("openssl genrsa")?
-                .stdout,
-        )
-        .context("converting openssl key to utf-8")?
-        .to_string()
-        .as_str(),
+        String::from_utf8(output.stdout)
+            .context("converting openssl key to utf-8")?
+            .as_str(),
     )
     .context("private from pem")?;
 
@@ -119,8 +216,190 @@ pub(crate) fn generate_rsa_key(key_size: usize) -> Result<(RsaPrivateKey, InMemo
     Ok((rsa_private_key, key_pair))
 }
 
+/// The exact size in bits of the modulus of a DER encoded RSA public key, either a
+/// SubjectPublicKeyInfo or a bare PKCS#1 RSAPublicKey (as found in "RSA PUBLIC KEY" PEMs). The
+/// length of the encoding alone is not enough to tell, it also depends on the exponent and on
+/// whether the modulus is padded
+pub(crate) fn rsa_key_size(der_bytes: &[u8]) -> Result<usize> {
+    let pkcs1_der = match SubjectPublicKeyInfoRef::from_der(der_bytes) {
+        Ok(spki) => {
+            ensure!(
+                spki.algorithm.oid == pkcs1::ALGORITHM_OID,
+                "public key algorithm {} is not RSA",
+                spki.algorithm.oid
+            );
+            spki.subject_public_key.as_bytes().context("unaligned RSA public key bit string")?
+        }
+        Err(_) => der_bytes,
+    };
+
+    let modulus = pkcs1::RsaPublicKey::from_der(pkcs1_der)
+        .context("parsing RSA public key")?
+        .modulus
+        .as_bytes();
+    let most_significant_byte = modulus.first().context("empty RSA modulus")?;
+
+    Ok(modulus.len() * 8 - most_significant_byte.leading_zeros() as usize)
+}
+
+/// Generate a new EC key on the given curve, returned as PKCS#8
+pub(crate) fn generate_ec_key(curve: EcCurve) -> Result<(PrivateKey, InMemorySigningKeyPair)> {
+    if let Some(mut rng) = entropy::rng() {
+        return generate_ec_key_with_rng(curve, &mut rng);
+    }
+
+    let key_pair = InMemorySigningKeyPair::generate_random(KeyAlgorithm::Ecdsa(curve.ecdsa_curve()))
+        .with_context(|| format!("generating {} key", curve))?;
+    // The private key data of EC key pairs is their whole PKCS#8 document
+    let pkcs8_der = key_pair.private_key_data().context("EC key pair without private key data")?;
+
+    Ok((PrivateKey::Ec(pkcs8_der.as_slice().into()), key_pair))
+}
+
+/// An EC key generated with the given RNG rather than the OS RNG, see entropy::rng and
+/// deterministic::rng_for
+pub(crate) fn generate_ec_key_with_rng(
+    curve: EcCurve,
+    rng: &mut (impl RngCore + CryptoRng),
+) -> Result<(PrivateKey, InMemorySigningKeyPair)> {
+    let pkcs8_der = Zeroizing::new(
+        match curve {
+            EcCurve::P256 => p256::SecretKey::random(rng).to_pkcs8_der(),
+            EcCurve::P384 => p384::SecretKey::random(rng).to_pkcs8_der(),
+        }
+        .with_context(|| format!("encoding {} key", curve))?
+        .as_bytes()
+        .to_vec(),
+    );
+
+    let key_pair = InMemorySigningKeyPair::from_pkcs8_der(&pkcs8_der).with_context(|| format!("loading {} key", curve))?;
+    Ok((PrivateKey::Ec(pkcs8_der.as_slice().into()), key_pair))
+}
+
+/// Whether the two are equal, in time which only depends on their lengths, so that comparing key
+/// material doesn't reveal how much of it matched
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// SHA-256 fingerprint, as colon separated uppercase hex like openssl x509 -fingerprint prints
+pub(crate) fn fingerprint(der: &[u8]) -> String {
+    sha2::Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// The fingerprint of the DER encoded SubjectPublicKeyInfo of the key, which is the same for a
+/// cert's key and for its private key. This is how recert identifies keys, e.g. the key of a cert
+/// is the private key with the same public key fingerprint, so the following match for a cert
+/// and its key:
+///
+///   openssl x509 -pubkey -noout -in cert.pem | openssl pkey -pubin -outform DER | sha256sum
+///   openssl pkey -pubout -outform DER -in key.pem | sha256sum
+pub(crate) fn public_key_fingerprint(public_key: &PublicKey) -> Result<String> {
+    Ok(match public_key {
+        PublicKey::Rsa(der_bytes) => fingerprint(der_bytes),
+        PublicKey::Ec(pem_bytes) => fingerprint(pem::parse(pem_bytes).context("parsing EC public key")?.contents()),
+    })
+}
+
+/// Whether the two fingerprints are of the same object. Either can also be in the lowercase and
+/// colon-less form of e.g. sha256sum
+pub(crate) fn fingerprints_match(a: &str, b: &str) -> bool {
+    let normalize = |fingerprint: &str| fingerprint.replace(':', "").to_ascii_uppercase();
+    constant_time_eq(normalize(a).as_bytes(), normalize(b).as_bytes())
+}
+
+/// Whether the private key is the key of the cert, the same way certs are paired with their keys
+/// when scanning
+pub(crate) fn key_matches_cert(private_key: &PrivateKey, certificate: &Certificate) -> Result<bool> {
+    Ok(PublicKey::try_from(private_key)? == certificate.public_key)
+}
+
 pub(crate) fn encode_tbs_cert_to_der(tbs_certificate: &rfc5280::TbsCertificate) -> Result<Vec<u8>> {
     let mut tbs_der = Vec::<u8>::new();
     tbs_certificate.encode_ref().write_encoded(Mode::Der, &mut tbs_der)?;
     Ok(tbs_der)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_crypto::crypto_objects::{self, CryptoObject};
+    use rsa::{pkcs1::EncodeRsaPublicKey, pkcs8::EncodePublicKey};
+
+    fn openssl_genrsa(args: &[&str]) -> RsaPrivateKey {
+        let output = StdCommand::new("openssl").arg("genrsa").args(args).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        RsaPrivateKey::from_pkcs8_pem(std::str::from_utf8(&output.stdout).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_fingerprints() {
+        let fixture = crate::test_fixtures::CertFixture::ca("root");
+        let other = crate::test_fixtures::CertFixture::ca("other");
+        let certificate = |pem: &str| Certificate::try_from(CapturedX509Certificate::from_pem(pem).unwrap()).unwrap();
+        // The fixtures' keys are SEC1 EC keys
+        let private_key = |pem: &str| match crypto_objects::process_pem_ec_private_key(&pem::parse(pem).unwrap()) {
+            Ok(Some(CryptoObject::PrivateKey(private_key, _))) => *private_key,
+            _ => panic!("not an EC private key"),
+        };
+
+        assert!(key_matches_cert(&private_key(&fixture.key_pem), &certificate(&fixture.cert_pem)).unwrap());
+        assert!(!key_matches_cert(&private_key(&other.key_pem), &certificate(&fixture.cert_pem)).unwrap());
+
+        // The same fingerprint as sha256sum of the DER SubjectPublicKeyInfo
+        let key_fingerprint = public_key_fingerprint(&certificate(&fixture.cert_pem).public_key).unwrap();
+        let pubkey = StdCommand::new("openssl")
+            .args(["pkey", "-pubout", "-outform", "DER"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(fixture.key_pem.as_bytes())?;
+                child.wait_with_output()
+            })
+            .unwrap();
+        assert!(pubkey.status.success());
+        let sha256sum = sha2::Sha256::digest(&pubkey.stdout)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        assert!(fingerprints_match(&key_fingerprint, &sha256sum));
+        assert!(!fingerprints_match(&key_fingerprint, &sha256sum[1..]));
+        assert!(!fingerprints_match(
+            &key_fingerprint,
+            &public_key_fingerprint(&certificate(&other.cert_pem).public_key).unwrap()
+        ));
+
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+
+    #[test]
+    fn test_rsa_key_size() {
+        for (args, expected_size) in [
+            (vec!["2048"], 2048),
+            (vec!["3072"], 3072),
+            (vec!["4096"], 4096),
+            // Unusual sizes, including one which isn't a whole number of bytes
+            (vec!["2050"], 2050),
+            (vec!["2056"], 2056),
+            // An exponent shorter than the usual 65537 makes the encoding shorter too
+            (vec!["-3", "2048"], 2048),
+        ] {
+            let public_key = openssl_genrsa(&args).to_public_key();
+
+            let spki_der = public_key.to_public_key_der().unwrap();
+            assert_eq!(rsa_key_size(spki_der.as_bytes()).unwrap(), expected_size, "SPKI {:?}", args);
+
+            let pkcs1_der = public_key.to_pkcs1_der().unwrap();
+            assert_eq!(rsa_key_size(pkcs1_der.as_bytes()).unwrap(), expected_size, "PKCS#1 {:?}", args);
+        }
+
+        assert!(rsa_key_size(b"not a key").is_err());
+    }
+}
@@ -1,7 +1,31 @@
-use super::{certificate, locations::Locations};
+use super::{
+    certificate,
+    locations::{Location, Locations},
+};
+use crate::rules::{KNOWN_MISSING_PRIVATE_KEY_CERTS, KNOWN_MISSING_PRIVATE_KEY_SECRET_ENTRIES};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct DistributedCert {
     pub(crate) certificate: certificate::Certificate,
     pub(crate) locations: Locations,
 }
+
+impl DistributedCert {
+    /// Whether the cert is known to have no private key in the cluster, either by its subject or
+    /// by the secret entry it's in, see rules::KNOWN_MISSING_PRIVATE_KEY_CERTS
+    pub(crate) fn is_known_missing_private_key(&self) -> bool {
+        KNOWN_MISSING_PRIVATE_KEY_CERTS
+            .iter()
+            .any(|known_missing_private_key_cert| known_missing_private_key_cert.is_match(&self.certificate.subject))
+            || self.locations.0.iter().any(|location| match location {
+                Location::K8s(k8s_location) => {
+                    k8s_location.resource_location.kind == "Secret"
+                        && KNOWN_MISSING_PRIVATE_KEY_SECRET_ENTRIES.iter().any(|(name, key)| {
+                            k8s_location.resource_location.name == *name
+                                && k8s_location.yaml_location.json_pointer == format!("/data/{}", key)
+                        })
+                }
+                Location::Filesystem(_) => false,
+            })
+    }
+}
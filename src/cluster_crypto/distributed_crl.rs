@@ -0,0 +1,103 @@
+use super::{
+    cert_key_pair::CertKeyPair,
+    crl::Crl,
+    locations::{FileContentLocation, FileLocation, K8sLocation, Location, LocationValueType, Locations},
+    pem_utils,
+    signature_policy::SignaturePolicy,
+    CryptoPolicies,
+};
+use crate::{
+    file_utils::{self, recreate_file_yaml_at_location_with_new_pem, recreate_json_at_location_with_new_pem, write_if_changed, FileKind},
+    k8s_etcd::{get_etcd_document, put_etcd_document_if_changed, InMemoryK8sEtcd},
+};
+use anyhow::{bail, Context, Result};
+use std::{cell::RefCell, path::Path, rc::Rc};
+use x509_certificate::InMemorySigningKeyPair;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DistributedCrl {
+    pub(crate) crl: Crl,
+    pub(crate) locations: Locations,
+    /// The cert-key pair of the CA which issued this CRL. CRLs of CAs we don't have are left as
+    /// they are
+    pub(crate) signer: Option<Rc<RefCell<CertKeyPair>>>,
+    pub(crate) regenerated: bool,
+}
+
+impl DistributedCrl {
+    /// Re-sign the CRL with the new key of its issuer. Signers are regenerated before their
+    /// signees, so the issuer's cert is already the regenerated one by now
+    pub(crate) fn regenerate(&mut self, new_signing_key: &InMemorySigningKeyPair, signature_policy: &SignaturePolicy) -> Result<()> {
+        let signer = self.signer.as_ref().context("cannot regenerate CRL without an issuer")?;
+        let signer = (**signer).borrow();
+        let signer_cert = Rc::clone(&signer.distributed_cert);
+        let signer_cert = &(*signer_cert).borrow().certificate.original;
+
+        self.crl = self
+            .crl
+            .re_sign(signer_cert, signer.skid()?.as_ref(), new_signing_key, signature_policy)
+            .with_context(|| format!("re-signing CRL of {}", self.crl.issuer))?;
+        self.regenerated = true;
+
+        Ok(())
+    }
+
+    pub(crate) async fn commit_to_etcd_and_disk(&self, etcd_client: &InMemoryK8sEtcd, policies: &CryptoPolicies) -> Result<()> {
+        if !self.regenerated {
+            return Ok(());
+        }
+
+        for location in self.locations.0.iter() {
+            match location {
+                Location::K8s(k8slocation) => {
+                    self.commit_k8s_crl(etcd_client, k8slocation, policies).await?;
+                }
+                Location::Filesystem(filelocation) => {
+                    self.commit_filesystem_crl(filelocation, policies).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn commit_k8s_crl(&self, etcd_client: &InMemoryK8sEtcd, k8slocation: &K8sLocation, policies: &CryptoPolicies) -> Result<()> {
+        let document = get_etcd_document(etcd_client, &k8slocation.resource_location).await?;
+
+        let new_document =
+            recreate_json_at_location_with_new_pem(&document, &k8slocation.yaml_location, &self.crl.pem(), &policies.private_key)?;
+        put_etcd_document_if_changed(etcd_client, k8slocation, &document, new_document).await;
+
+        Ok(())
+    }
+
+    async fn commit_filesystem_crl(&self, filelocation: &FileLocation, policies: &CryptoPolicies) -> Result<()> {
+        let contents = file_utils::read_file(Path::new(&filelocation.path)).await?;
+
+        let crl_pem = self.crl.pem();
+
+        let new_contents = match &filelocation.content_location {
+            FileContentLocation::Raw(location_value_type) => match &location_value_type {
+                LocationValueType::Pem(pem_location_info) => pem_utils::pem_bundle_replace_pem_at_index(
+                    String::from_utf8(contents.clone())?,
+                    pem_location_info.pem_bundle_index,
+                    &crl_pem,
+                    &policies.private_key,
+                )?
+                .into_bytes(),
+                _ => bail!("cannot commit non-PEM CRL location to filesystem"),
+            },
+            FileContentLocation::Der => crl_pem.contents().to_vec(),
+            FileContentLocation::Yaml(yaml_location) => recreate_file_yaml_at_location_with_new_pem(
+                &String::from_utf8(contents.clone())?,
+                filelocation,
+                yaml_location,
+                &crl_pem,
+                &policies.private_key,
+            )?
+            .into_bytes(),
+        };
+
+        write_if_changed(filelocation, FileKind::Crl, policies.file_permissions, &contents, new_contents).await
+    }
+}
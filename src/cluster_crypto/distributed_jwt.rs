@@ -1,18 +1,26 @@
 use super::{
     crypto_utils::verify_jwt,
-    jwt::Jwt,
-    jwt::JwtSigner,
+    jwt::{Jwt, JwtSigner, TokenPolicy},
     keys::PublicKey,
     locations::{FileLocation, K8sLocation, Location, LocationValueType, Locations},
 };
 use crate::{
-    file_utils::encode_resource_data_entry,
+    deterministic,
+    file_utils::reencode_resource_data_entry,
     k8s_etcd::{get_etcd_yaml, InMemoryK8sEtcd},
 };
 use anyhow::{bail, Context, Result};
-use jwt_simple::prelude::RSAKeyPairLike;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as base64_url, Engine as _};
+use jwt_simple::prelude::{Clock, RS256KeyPair, RSAKeyPairLike, UnixTimeStamp};
 use serde_json::Value;
-use x509_certificate::InMemorySigningKeyPair;
+use sha2::Digest;
+use x509_certificate::{InMemorySigningKeyPair, Sign};
+
+/// The key ID the kube-apiserver gives the tokens it signs: the unpadded base64url SHA-256 of the
+/// DER encoded public key of the signing key
+fn key_id(public_key_der: &[u8]) -> String {
+    base64_url.encode(sha2::Sha256::digest(public_key_der))
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct DistributedJwt {
@@ -23,11 +31,16 @@ pub(crate) struct DistributedJwt {
 }
 
 impl DistributedJwt {
-    pub(crate) fn regenerate(&mut self, original_signing_key: &PublicKey, new_signing_key: &InMemorySigningKeyPair) -> Result<()> {
+    pub(crate) fn regenerate(
+        &mut self,
+        original_signing_key: &PublicKey,
+        new_signing_key: &InMemorySigningKeyPair,
+        token_policy: &TokenPolicy,
+    ) -> Result<()> {
         let new_key = match &self.signer {
             JwtSigner::Unknown => bail!("cannot regenerate jwt with unknown signer"),
-            JwtSigner::CertKeyPair(_cert_key_pair) => self.resign(original_signing_key, new_signing_key)?,
-            JwtSigner::PrivateKey(_private_key) => self.resign(&original_signing_key, new_signing_key)?,
+            JwtSigner::CertKeyPair(_cert_key_pair) => self.resign(original_signing_key, new_signing_key, token_policy)?,
+            JwtSigner::PrivateKey(_private_key) => self.resign(original_signing_key, new_signing_key, token_policy)?,
         };
         self.jwt.str = new_key;
         self.regenerated = true;
@@ -35,17 +48,35 @@ impl DistributedJwt {
         Ok(())
     }
 
-    fn resign(&self, original_public_key: &PublicKey, new_signing_key_pair: &InMemorySigningKeyPair) -> Result<String> {
+    fn resign(
+        &self,
+        original_public_key: &PublicKey,
+        new_signing_key_pair: &InMemorySigningKeyPair,
+        token_policy: &TokenPolicy,
+    ) -> Result<String> {
         match new_signing_key_pair {
-            InMemorySigningKeyPair::Ecdsa(_, _, _) => {
+            InMemorySigningKeyPair::Ecdsa(_) => {
                 bail!("ecdsa unsupported");
             }
             InMemorySigningKeyPair::Ed25519(_) => {
                 bail!("ed unsupported");
             }
-            InMemorySigningKeyPair::Rsa(_rsa_key_pair, bytes) => Ok(jwt_simple::prelude::RS256KeyPair::from_der(bytes)?
-                .sign(verify_jwt(&original_public_key, self)?)?
-                .to_string()),
+            InMemorySigningKeyPair::Rsa(_) => {
+                let mut claims = verify_jwt(original_public_key, self)?;
+                let now = match deterministic::fixed_time() {
+                    Some(fixed_time) => UnixTimeStamp::from_secs(fixed_time.timestamp().try_into().context("fixed time before epoch")?),
+                    None => Clock::now_since_epoch(),
+                };
+                token_policy.apply(&mut claims, now)?;
+
+                // The PKCS#1 RSAPrivateKey of the key pair
+                let private_key_data = new_signing_key_pair
+                    .private_key_data()
+                    .context("RSA key pair without private key data")?;
+                let key_pair = RS256KeyPair::from_der(&private_key_data)?;
+                let key_id = key_id(&key_pair.public_key().to_der()?);
+                Ok(key_pair.with_key_id(&key_id).sign(claims)?)
+            }
         }
     }
 
@@ -53,10 +84,10 @@ impl DistributedJwt {
         for location in &self.locations.0 {
             match location {
                 Location::K8s(k8slocation) => {
-                    self.commit_to_etcd(etcd_client, &k8slocation).await?;
+                    self.commit_to_etcd(etcd_client, k8slocation).await?;
                 }
                 Location::Filesystem(filelocation) => {
-                    self.commit_to_filesystem(&filelocation).await?;
+                    self.commit_to_filesystem(filelocation).await?;
                 }
             }
         }
@@ -76,11 +107,13 @@ impl DistributedJwt {
             }
             LocationValueType::Jwt => {
                 if let Value::String(value_at_json_pointer) = value_at_json_pointer {
-                    *value_at_json_pointer = encode_resource_data_entry(&k8slocation.yaml_location, &self.jwt.str);
+                    *value_at_json_pointer =
+                        reencode_resource_data_entry(&k8slocation.yaml_location, value_at_json_pointer, &self.jwt.str)?;
                 } else {
                     bail!("non-string value at json pointer")
                 }
             }
+            LocationValueType::SshPublicKey(_) => bail!("JWT cannot be an SSH public key"),
             LocationValueType::Unknown => bail!("cannot commit unknown value type to etcd"),
         }
 
@@ -1,20 +1,23 @@
 use super::{
+    crypto_objects,
     distributed_public_key::DistributedPublicKey,
-    k8s_etcd::get_etcd_yaml,
+    k8s_etcd::{get_etcd_document, put_etcd_document_if_changed},
     keys::{PrivateKey, PublicKey},
     locations::{FileContentLocation, FileLocation, K8sLocation, Location, LocationValueType, Locations},
     pem_utils,
-    signee::Signee,
+    serial_policy::SerialSequence,
+    signee::{Signee, SigneeWalk},
+    CryptoPolicies,
 };
 use crate::{
     cnsanreplace::CnSanReplaceRules,
-    file_utils::{get_filesystem_yaml, read_file_to_string, recreate_yaml_at_location_with_new_pem},
+    file_utils::{self, recreate_file_yaml_at_location_with_new_pem, recreate_json_at_location_with_new_pem, write_if_changed, FileKind},
     k8s_etcd::InMemoryK8sEtcd,
     rsa_key_pool::RsaKeyPool,
 };
 use anyhow::{bail, Context, Result};
-use pkcs1::EncodeRsaPrivateKey;
-use std::{self, cell::RefCell, fmt::Display, rc::Rc};
+use std::{self, cell::RefCell, fmt::Display, path::Path, rc::Rc};
+use zeroize::Zeroizing;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct DistributedPrivateKey {
@@ -35,8 +38,8 @@ impl Display for DistributedPrivateKey {
             // "<>",
         )?;
 
-        if self.signees.len() > 0 || self.associated_distributed_public_key.is_some() {
-            writeln!(f, "")?;
+        if !self.signees.is_empty() || self.associated_distributed_public_key.is_some() {
+            writeln!(f)?;
         }
 
         for signee in &self.signees {
@@ -52,26 +55,28 @@ impl Display for DistributedPrivateKey {
 }
 
 impl DistributedPrivateKey {
-    pub(crate) fn regenerate(&mut self, rsa_key_pool: &mut RsaKeyPool, cn_san_replace_rules: &CnSanReplaceRules) -> Result<()> {
+    pub(crate) fn regenerate(
+        &mut self,
+        rsa_key_pool: &mut RsaKeyPool,
+        serials: &mut SerialSequence,
+        cn_san_replace_rules: &CnSanReplaceRules,
+        policies: &CryptoPolicies,
+    ) -> Result<()> {
         let original_signing_public_key = PublicKey::try_from(&self.key)?;
 
-        let num_bits = match &original_signing_public_key {
-            PublicKey::Rsa(bytes) => bytes.len() * 8 - 304,
-            PublicKey::Ec(_) => 0,
-        };
-
-        let (self_new_rsa_private_key, self_new_key_pair) = rsa_key_pool.get(num_bits).context("RSA pool empty")?;
+        let (self_new_private_key, self_new_key_pair) = rsa_key_pool.replacement_for(&original_signing_public_key)?;
 
-        for signee in &mut self.signees {
-            signee.regenerate(
-                &original_signing_public_key,
-                Some(&self_new_key_pair),
-                rsa_key_pool,
-                cn_san_replace_rules,
-            )?;
-        }
+        let mut signee_walk = SigneeWalk::new(policies);
+        signee_walk.push_signees(
+            &self.signees,
+            &original_signing_public_key,
+            self_new_key_pair,
+            cn_san_replace_rules.clone(),
+            Vec::new(),
+        );
+        signee_walk.run(rsa_key_pool, serials)?;
 
-        self.key = PrivateKey::Rsa(self_new_rsa_private_key);
+        self.key = self_new_private_key;
         self.regenerated = true;
 
         if let Some(public_key) = &self.associated_distributed_public_key {
@@ -81,14 +86,14 @@ impl DistributedPrivateKey {
         Ok(())
     }
 
-    pub(crate) async fn commit_to_etcd_and_disk(&self, etcd_client: &InMemoryK8sEtcd) -> Result<()> {
+    pub(crate) async fn commit_to_etcd_and_disk(&self, etcd_client: &InMemoryK8sEtcd, policies: &CryptoPolicies) -> Result<()> {
         for location in self.locations.0.iter() {
             match location {
                 Location::K8s(k8slocation) => {
-                    self.commit_k8s_private_key(etcd_client, &k8slocation).await?;
+                    self.commit_k8s_private_key(etcd_client, k8slocation, policies).await?;
                 }
                 Location::Filesystem(filelocation) => {
-                    self.commit_filesystem_private_key(&filelocation).await?;
+                    self.commit_filesystem_private_key(filelocation, policies).await?;
                 }
             }
         }
@@ -96,56 +101,62 @@ impl DistributedPrivateKey {
         Ok(())
     }
 
-    async fn commit_k8s_private_key(&self, etcd_client: &InMemoryK8sEtcd, k8slocation: &K8sLocation) -> Result<()> {
-        let resource = get_etcd_yaml(etcd_client, &k8slocation.resource_location).await?;
-
-        etcd_client
-            .put(
-                &k8slocation.resource_location.as_etcd_key(),
-                recreate_yaml_at_location_with_new_pem(
-                    resource,
-                    &k8slocation.yaml_location,
-                    &self.key.pem()?,
-                    crate::file_utils::RecreateYamlEncoding::Json,
-                )?
-                .as_bytes()
-                .to_vec(),
-            )
-            .await;
+    async fn commit_k8s_private_key(
+        &self,
+        etcd_client: &InMemoryK8sEtcd,
+        k8slocation: &K8sLocation,
+        policies: &CryptoPolicies,
+    ) -> Result<()> {
+        let document = Zeroizing::new(get_etcd_document(etcd_client, &k8slocation.resource_location).await?);
+
+        let new_document =
+            recreate_json_at_location_with_new_pem(&document, &k8slocation.yaml_location, &self.key.pem()?, &policies.private_key)?;
+        put_etcd_document_if_changed(etcd_client, k8slocation, &document, new_document).await;
 
         Ok(())
     }
 
-    async fn commit_filesystem_private_key(&self, filelocation: &FileLocation) -> Result<()> {
-        let private_key_pem = match &self.key {
-            PrivateKey::Rsa(rsa_private_key) => pem::Pem::new("RSA PRIVATE KEY", rsa_private_key.to_pkcs1_der()?.as_bytes()),
-            PrivateKey::Ec(ec_bytes) => pem::Pem::new("EC PRIVATE KEY", ec_bytes.as_ref()),
-        };
+    async fn commit_filesystem_private_key(&self, filelocation: &FileLocation, policies: &CryptoPolicies) -> Result<()> {
+        let private_key_pem = self.key.pem()?;
 
-        tokio::fs::write(
-            &filelocation.path,
-            match &filelocation.content_location {
-                FileContentLocation::Raw(pem_location_info) => match &pem_location_info {
-                    LocationValueType::Pem(pem_location_info) => pem_utils::pem_bundle_replace_pem_at_index(
-                        String::from_utf8((read_file_to_string(filelocation.path.clone().into()).await)?.into_bytes())?,
-                        pem_location_info.pem_bundle_index,
-                        &private_key_pem,
-                    )?,
-                    _ => bail!("cannot commit non-PEM to filesystem"),
-                },
-                FileContentLocation::Yaml(yaml_location) => {
-                    let resource = get_filesystem_yaml(filelocation).await?;
-                    recreate_yaml_at_location_with_new_pem(
-                        resource,
-                        yaml_location,
-                        &private_key_pem,
-                        crate::file_utils::RecreateYamlEncoding::Yaml,
-                    )?
-                }
+        let contents = Zeroizing::new(file_utils::read_file(Path::new(&filelocation.path)).await?);
+
+        let new_contents = match &filelocation.content_location {
+            FileContentLocation::Raw(pem_location_info) => match &pem_location_info {
+                LocationValueType::Pem(pem_location_info) => pem_utils::pem_bundle_replace_pem_at_index(
+                    String::from_utf8(contents.to_vec())?,
+                    pem_location_info.pem_bundle_index,
+                    &private_key_pem,
+                    &policies.private_key,
+                )?
+                .into_bytes(),
+                _ => bail!("cannot commit non-PEM to filesystem"),
             },
-        )
-        .await?;
+            FileContentLocation::Der => {
+                let original_tag = crypto_objects::der_private_key_tag(&contents).context("DER file no longer holds a private key")?;
+                policies
+                    .private_key
+                    .reencode_like(&pem::Pem::new(original_tag, contents.to_vec()), &private_key_pem)?
+                    .contents()
+                    .to_vec()
+            }
+            FileContentLocation::Yaml(yaml_location) => recreate_file_yaml_at_location_with_new_pem(
+                &String::from_utf8(contents.to_vec())?,
+                filelocation,
+                yaml_location,
+                &private_key_pem,
+                &policies.private_key,
+            )?
+            .into_bytes(),
+        };
 
-        Ok(())
+        write_if_changed(
+            filelocation,
+            FileKind::PrivateKey,
+            policies.file_permissions,
+            &contents,
+            new_contents,
+        )
+        .await
     }
 }
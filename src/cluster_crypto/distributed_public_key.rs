@@ -3,13 +3,16 @@ use anyhow::{bail, Result};
 use super::{
     keys::{PrivateKey, PublicKey},
     locations::{FileContentLocation, FileLocation, K8sLocation, Location, LocationValueType, Locations},
-    pem_utils,
+    pem_utils, ssh_keys, CryptoPolicies,
 };
 use crate::{
-    file_utils::{get_filesystem_yaml, read_file_to_string, recreate_yaml_at_location_with_new_pem},
-    k8s_etcd::{get_etcd_yaml, InMemoryK8sEtcd},
+    file_utils::{
+        self, recreate_file_yaml_at_location_with_new_pem, recreate_file_yaml_at_location_with_new_ssh_public_key,
+        recreate_json_at_location_with_new_pem, recreate_json_at_location_with_new_ssh_public_key, write_if_changed, FileKind,
+    },
+    k8s_etcd::{get_etcd_document, put_etcd_document_if_changed, InMemoryK8sEtcd},
 };
-use std::fmt::Display;
+use std::{fmt::Display, path::Path};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct DistributedPublicKey {
@@ -41,14 +44,14 @@ impl DistributedPublicKey {
         Ok(())
     }
 
-    pub(crate) async fn commit_to_etcd_and_disk(&self, etcd_client: &InMemoryK8sEtcd) -> Result<()> {
+    pub(crate) async fn commit_to_etcd_and_disk(&self, etcd_client: &InMemoryK8sEtcd, policies: &CryptoPolicies) -> Result<()> {
         for location in self.locations.0.iter() {
             match location {
                 Location::K8s(k8slocation) => {
-                    self.commit_k8s_public_key(etcd_client, &k8slocation).await?;
+                    self.commit_k8s_public_key(etcd_client, k8slocation, policies).await?;
                 }
                 Location::Filesystem(filelocation) => {
-                    self.commit_filesystem_public_key(&filelocation).await?;
+                    self.commit_filesystem_public_key(filelocation, policies).await?;
                 }
             }
         }
@@ -56,56 +59,124 @@ impl DistributedPublicKey {
         Ok(())
     }
 
-    async fn commit_k8s_public_key(&self, etcd_client: &InMemoryK8sEtcd, k8slocation: &K8sLocation) -> Result<()> {
-        let resource = get_etcd_yaml(etcd_client, &k8slocation.resource_location).await?;
-
-        etcd_client
-            .put(
-                &k8slocation.resource_location.as_etcd_key(),
-                recreate_yaml_at_location_with_new_pem(
-                    resource,
-                    &k8slocation.yaml_location,
-                    &self.key.pem(),
-                    crate::file_utils::RecreateYamlEncoding::Json,
-                )?
-                .as_bytes()
-                .to_vec(),
-            )
-            .await;
+    async fn commit_k8s_public_key(
+        &self,
+        etcd_client: &InMemoryK8sEtcd,
+        k8slocation: &K8sLocation,
+        policies: &CryptoPolicies,
+    ) -> Result<()> {
+        let document = get_etcd_document(etcd_client, &k8slocation.resource_location).await?;
+
+        let new_document = match &k8slocation.yaml_location.value {
+            LocationValueType::SshPublicKey(_) => {
+                recreate_json_at_location_with_new_ssh_public_key(&document, &k8slocation.yaml_location, &self.key)?
+            }
+            _ => recreate_json_at_location_with_new_pem(&document, &k8slocation.yaml_location, &self.key.pem()?, &policies.private_key)?,
+        };
+        put_etcd_document_if_changed(etcd_client, k8slocation, &document, new_document).await;
 
         Ok(())
     }
 
-    async fn commit_filesystem_public_key(&self, filelocation: &FileLocation) -> Result<()> {
-        let public_key_pem = match &self.key {
-            PublicKey::Rsa(public_key_bytes) => pem::Pem::new("RSA PUBLIC KEY", public_key_bytes.as_ref()),
-            PublicKey::Ec(_) => bail!("ECDSA public key not yet supported for filesystem commit"),
-        };
+    async fn commit_filesystem_public_key(&self, filelocation: &FileLocation, policies: &CryptoPolicies) -> Result<()> {
+        let public_key_pem = self.key.pem()?;
 
-        tokio::fs::write(
-            &filelocation.path,
-            match &filelocation.content_location {
-                FileContentLocation::Raw(pem_location_info) => match &pem_location_info {
-                    LocationValueType::Pem(pem_location_info) => pem_utils::pem_bundle_replace_pem_at_index(
-                        String::from_utf8((read_file_to_string(filelocation.path.clone().into()).await?).into_bytes())?,
-                        pem_location_info.pem_bundle_index,
-                        &public_key_pem,
-                    )?,
-                    _ => bail!("cannot commit non-PEM location to filesystem"),
-                },
-                FileContentLocation::Yaml(yaml_location) => {
-                    let resource = get_filesystem_yaml(filelocation).await?;
-                    recreate_yaml_at_location_with_new_pem(
-                        resource,
-                        yaml_location,
-                        &public_key_pem,
-                        crate::file_utils::RecreateYamlEncoding::Yaml,
-                    )?
-                }
+        let contents = file_utils::read_file(Path::new(&filelocation.path)).await?;
+
+        let new_contents = match &filelocation.content_location {
+            FileContentLocation::Raw(pem_location_info) => match &pem_location_info {
+                LocationValueType::Pem(pem_location_info) => pem_utils::pem_bundle_replace_pem_at_index(
+                    String::from_utf8(contents.clone())?,
+                    pem_location_info.pem_bundle_index,
+                    &public_key_pem,
+                    &policies.private_key,
+                )?
+                .into_bytes(),
+                LocationValueType::SshPublicKey(ssh_public_key_location_info) => ssh_keys::replace_public_key_at_line(
+                    &String::from_utf8(contents.clone())?,
+                    ssh_public_key_location_info.line_index,
+                    &self.key,
+                )?
+                .into_bytes(),
+                _ => bail!("cannot commit non-PEM location to filesystem"),
             },
+            FileContentLocation::Der => public_key_pem.contents().to_vec(),
+            FileContentLocation::Yaml(yaml_location) if matches!(yaml_location.value, LocationValueType::SshPublicKey(_)) => {
+                recreate_file_yaml_at_location_with_new_ssh_public_key(
+                    &String::from_utf8(contents.clone())?,
+                    filelocation,
+                    yaml_location,
+                    &self.key,
+                )?
+                .into_bytes()
+            }
+            FileContentLocation::Yaml(yaml_location) => recreate_file_yaml_at_location_with_new_pem(
+                &String::from_utf8(contents.clone())?,
+                filelocation,
+                yaml_location,
+                &public_key_pem,
+                &policies.private_key,
+            )?
+            .into_bytes(),
+        };
+
+        write_if_changed(
+            filelocation,
+            FileKind::PublicKey,
+            policies.file_permissions,
+            &contents,
+            new_contents,
         )
-        .await?;
+        .await
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_crypto::{
+        crypto_utils::generate_ec_key,
+        keys::EcCurve,
+        locations::{PemBundleRole, PemLocationInfo},
+    };
+    use std::collections::HashSet;
+
+    #[tokio::test]
+    async fn test_regenerate_ec_public_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("service-account.pub");
+
+        for curve in EcCurve::ALL {
+            let (original_private_key, _) = generate_ec_key(curve).unwrap();
+            let original_public_key = PublicKey::try_from(&original_private_key).unwrap();
+            std::fs::write(&path, pem::encode(&original_public_key.pem().unwrap())).unwrap();
+
+            let mut distributed_public_key = DistributedPublicKey {
+                key: original_public_key,
+                locations: Locations(HashSet::from([Location::Filesystem(FileLocation {
+                    path: path.to_str().unwrap().to_string(),
+                    content_location: FileContentLocation::Raw(LocationValueType::Pem(PemLocationInfo::new(0, PemBundleRole::Member))),
+                })])),
+                regenerated: false,
+                associated: true,
+            };
+
+            let (new_private_key, _) = generate_ec_key(curve).unwrap();
+            distributed_public_key.regenerate(&new_private_key).unwrap();
+            for location in &distributed_public_key.locations.0 {
+                let Location::Filesystem(file_location) = location else {
+                    unreachable!()
+                };
+                distributed_public_key
+                    .commit_filesystem_public_key(file_location, &CryptoPolicies::default())
+                    .await
+                    .unwrap();
+            }
+
+            let written = pem::parse(std::fs::read(&path).unwrap()).unwrap();
+            assert_eq!(written.tag(), "PUBLIC KEY");
+            assert_eq!(written, PublicKey::try_from(&new_private_key).unwrap().pem().unwrap());
+            assert_eq!(pem::encode(&written).parse::<p256::PublicKey>().is_ok(), curve == EcCurve::P256,);
+        }
     }
 }
@@ -0,0 +1,114 @@
+use anyhow::{ensure, Context, Result};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use tempfile::NamedTempFile;
+use zeroize::Zeroizing;
+
+/// How much is read from the entropy source, the most openssl reads from a -rand file which
+/// isn't a regular file
+const SEED_SIZE: u64 = 256;
+
+/// The least a regular file has to hold to be worth mixing in
+const MIN_SEED_SIZE: usize = 32;
+
+/// Entropy from outside of the OS RNG (e.g. a hardware RNG, or a per-device file provisioned by
+/// the factory), mixed into the generation of every key. Devices cloned from the same image onto
+/// identical hardware generate their keys early in their first boot, when the OS RNG might not
+/// have gathered much that sets them apart yet. The OS RNG is still used, this only ever adds to it
+struct EntropySource {
+    path: PathBuf,
+    seed: Zeroizing<Vec<u8>>,
+    /// The seed, for openssl genrsa -rand. In the temp dir, which openssl is allowed to read from
+    /// when sandboxed
+    seed_file: NamedTempFile,
+}
+
+static ENTROPY_SOURCE: OnceLock<EntropySource> = OnceLock::new();
+
+/// Read the seed from the source. Has to be called before sandboxing, as the source is usually
+/// outside of what the sandbox allows reading
+pub(crate) fn init(source: Option<&Path>) -> Result<()> {
+    if let Some(path) = source {
+        let mut seed = Zeroizing::new(vec![]);
+        std::fs::File::open(path)
+            .and_then(|file| file.take(SEED_SIZE).read_to_end(&mut seed))
+            .with_context(|| format!("reading entropy source {:?}", path))?;
+        ensure!(
+            seed.len() >= MIN_SEED_SIZE,
+            "entropy source {:?} holds only {} bytes, at least {} are required",
+            path,
+            seed.len(),
+            MIN_SEED_SIZE
+        );
+
+        let mut seed_file = NamedTempFile::new().context("creating entropy seed file")?;
+        seed_file.write_all(&seed).context("writing entropy seed file")?;
+
+        ENTROPY_SOURCE
+            .set(EntropySource {
+                path: path.to_path_buf(),
+                seed,
+                seed_file,
+            })
+            .ok()
+            .context("entropy source already initialized")?;
+    }
+
+    println!("Key generation entropy: {}", description());
+
+    Ok(())
+}
+
+/// What the keys are generated from, for reporting
+pub(crate) fn description() -> String {
+    match ENTROPY_SOURCE.get() {
+        Some(source) => format!("OS RNG mixed with {} bytes from {}", source.seed.len(), source.path.display()),
+        None => "OS RNG".to_string(),
+    }
+}
+
+/// The extra arguments of openssl genrsa for mixing in the entropy source
+pub(crate) fn openssl_rand_args() -> Vec<String> {
+    match ENTROPY_SOURCE.get() {
+        Some(source) => vec!["-rand".to_string(), source.seed_file.path().display().to_string()],
+        None => vec![],
+    }
+}
+
+/// The OS RNG mixed with the entropy source, for generating EC keys. None if there's no entropy
+/// source, in which case keys are generated with the OS RNG alone
+pub(crate) fn rng() -> Option<StdRng> {
+    ENTROPY_SOURCE.get().map(|source| mixed_rng(&source.seed))
+}
+
+/// Seeded with fresh OS randomness on every call, so that no two keys are generated from the
+/// same state even though the seed stays the same
+fn mixed_rng(seed: &[u8]) -> StdRng {
+    let mut os_random = Zeroizing::new([0u8; 32]);
+    rand::rngs::OsRng.fill_bytes(os_random.as_mut());
+
+    let mut hasher = Sha256::new();
+    hasher.update(os_random.as_ref());
+    hasher.update(seed);
+    StdRng::from_seed(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_crypto::{crypto_utils::generate_ec_key_with_rng, keys::EcCurve};
+
+    #[test]
+    fn test_mixed_rng() {
+        let seed = [7u8; MIN_SEED_SIZE];
+        let (mut first, mut second) = (mixed_rng(&seed), mixed_rng(&seed));
+        assert_ne!(first.next_u64(), second.next_u64());
+
+        generate_ec_key_with_rng(EcCurve::P256, &mut mixed_rng(&seed)).unwrap();
+    }
+}
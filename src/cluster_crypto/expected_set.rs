@@ -0,0 +1,122 @@
+use super::{
+    crypto_objects::DiscoveredCryptoObect,
+    locations::{FieldEncoding, Location, YamlLocation},
+};
+use crate::{
+    capabilities::{Capabilities, ExpectedCryptoObject},
+    namespace_filter::NamespaceFilter,
+};
+use anyhow::{bail, Result};
+
+/// Report the crypto objects every cluster of this version has but which weren't found, and fail
+/// if strict is set. These are missing from broken seeds, which would otherwise only be noticed
+/// once the cluster fails to boot
+pub(crate) fn validate(
+    discovered: &[DiscoveredCryptoObect],
+    capabilities: &Capabilities,
+    namespace_filter: &NamespaceFilter,
+    strict: bool,
+) -> Result<()> {
+    let missing = missing(discovered, capabilities.behavior().expected_crypto_objects, |expected| {
+        expected.capability.is_none_or(|capability| capabilities.has(capability)) && namespace_filter.allows(Some(expected.namespace))
+    });
+
+    for expected in &missing {
+        println!(
+            "WARNING: expected crypto object missing: {} ({}/{} {})",
+            expected.description, expected.namespace, expected.secret, expected.data_key
+        );
+    }
+
+    if strict && !missing.is_empty() {
+        bail!("{} expected crypto object(s) missing", missing.len());
+    }
+
+    Ok(())
+}
+
+/// The expected objects which apply to the cluster but weren't discovered
+fn missing<'a>(
+    discovered: &[DiscoveredCryptoObect],
+    expected_crypto_objects: &'a [ExpectedCryptoObject],
+    applies: impl Fn(&ExpectedCryptoObject) -> bool,
+) -> Vec<&'a ExpectedCryptoObject> {
+    expected_crypto_objects
+        .iter()
+        .filter(|expected| applies(expected))
+        .filter(|expected| !discovered.iter().any(|discovered| is_at(expected, &discovered.location)))
+        .collect()
+}
+
+fn is_at(expected: &ExpectedCryptoObject, location: &Location) -> bool {
+    let Location::K8s(k8s_location) = location else {
+        return false;
+    };
+
+    let resource_location = &k8s_location.resource_location;
+    resource_location.kind == "Secret"
+        && resource_location.namespace.as_deref() == Some(expected.namespace)
+        && resource_location.name == expected.secret
+        && k8s_location.yaml_location.json_pointer == YamlLocation::new("/data", expected.data_key, FieldEncoding::None).json_pointer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_crypto::{crypto_objects::CryptoObject, locations::K8sResourceLocation};
+
+    #[test]
+    fn test_missing() {
+        let expected_crypto_objects = [
+            ExpectedCryptoObject {
+                description: "service CA",
+                namespace: "openshift-service-ca",
+                secret: "signing-key",
+                data_key: "tls.key",
+                capability: None,
+            },
+            ExpectedCryptoObject {
+                description: "etcd signer",
+                namespace: "openshift-config",
+                secret: "etcd-signer",
+                data_key: "tls.key",
+                capability: None,
+            },
+            ExpectedCryptoObject {
+                description: "ingress CA",
+                namespace: "openshift-ingress-operator",
+                secret: "router-ca",
+                data_key: "tls.key",
+                capability: None,
+            },
+        ];
+
+        let discovered_at = |namespace: &str, name: &str, key: &str| {
+            DiscoveredCryptoObect::new(
+                CryptoObject::Unsupported("test".to_string()),
+                Location::k8s_yaml(
+                    &K8sResourceLocation::new(Some(namespace), "Secret", name, "v1"),
+                    &YamlLocation::new("/data", key, FieldEncoding::Base64),
+                ),
+            )
+        };
+        let discovered = [
+            discovered_at("openshift-service-ca", "signing-key", "tls.key"),
+            // Only the cert, not the key
+            discovered_at("openshift-config", "etcd-signer", "tls.crt"),
+        ];
+
+        let missing_descriptions = |applies: fn(&ExpectedCryptoObject) -> bool| {
+            missing(&discovered, &expected_crypto_objects, applies)
+                .into_iter()
+                .map(|expected| expected.description)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(missing_descriptions(|_| true), vec!["etcd signer", "ingress CA"]);
+        assert_eq!(
+            missing_descriptions(|expected| expected.namespace != "openshift-ingress-operator"),
+            vec!["etcd signer"]
+        );
+    }
+}
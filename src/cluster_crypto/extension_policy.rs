@@ -0,0 +1,284 @@
+use anyhow::{bail, ensure, Context, Result};
+use bcder::{OctetString, Oid};
+use bytes::Bytes;
+use der::{asn1::ObjectIdentifier, flagset::FlagSet, oid::AssociatedOid, Encode};
+use std::str::FromStr;
+use x509_cert::ext::pkix::{BasicConstraints, ExtendedKeyUsage, KeyUsage, KeyUsages};
+use x509_certificate::rfc5280;
+
+/// The key usages by their openssl names
+const KEY_USAGES: [(&str, KeyUsages); 9] = [
+    ("digitalSignature", KeyUsages::DigitalSignature),
+    ("nonRepudiation", KeyUsages::NonRepudiation),
+    ("keyEncipherment", KeyUsages::KeyEncipherment),
+    ("dataEncipherment", KeyUsages::DataEncipherment),
+    ("keyAgreement", KeyUsages::KeyAgreement),
+    ("keyCertSign", KeyUsages::KeyCertSign),
+    ("cRLSign", KeyUsages::CRLSign),
+    ("encipherOnly", KeyUsages::EncipherOnly),
+    ("decipherOnly", KeyUsages::DecipherOnly),
+];
+
+/// The extended key usages by their openssl names, others can be given as dotted OIDs
+const EXTENDED_KEY_USAGES: [(&str, ObjectIdentifier); 5] = [
+    ("serverAuth", ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.3.1")),
+    ("clientAuth", ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.3.2")),
+    ("codeSigning", ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.3.3")),
+    ("emailProtection", ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.3.4")),
+    ("OCSPSigning", ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.3.9")),
+];
+
+/// The extensions which can be overridden
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExtensionKind {
+    KeyUsage,
+    ExtendedKeyUsage,
+    BasicConstraints,
+}
+
+impl ExtensionKind {
+    fn oid(&self) -> ObjectIdentifier {
+        match self {
+            ExtensionKind::KeyUsage => KeyUsage::OID,
+            ExtensionKind::ExtendedKeyUsage => ExtendedKeyUsage::OID,
+            ExtensionKind::BasicConstraints => BasicConstraints::OID,
+        }
+    }
+
+    /// Of extensions which are added rather than replaced. Replaced extensions keep the
+    /// criticality of the original
+    fn critical(&self) -> bool {
+        match self {
+            ExtensionKind::KeyUsage | ExtensionKind::BasicConstraints => true,
+            ExtensionKind::ExtendedKeyUsage => false,
+        }
+    }
+}
+
+impl FromStr for ExtensionKind {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Ok(match name {
+            "keyUsage" => ExtensionKind::KeyUsage,
+            "extendedKeyUsage" => ExtensionKind::ExtendedKeyUsage,
+            "basicConstraints" => ExtensionKind::BasicConstraints,
+            _ => bail!(
+                "unsupported extension {:?}, expected keyUsage, extendedKeyUsage or basicConstraints",
+                name
+            ),
+        })
+    }
+}
+
+/// An extension of the certs with a given CN, written as CN=EXTENSION:VALUE with the extension
+/// and its value written like openssl's (e.g. extendedKeyUsage:clientAuth,serverAuth or
+/// basicConstraints:CA:FALSE), or EXTENSION:none to remove it. A trailing * in the CN matches
+/// any CN with the given prefix
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ExtensionOverride {
+    common_name: String,
+    kind: ExtensionKind,
+    /// The DER encoded extension value, None to remove the extension
+    value: Option<Vec<u8>>,
+}
+
+impl FromStr for ExtensionOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (common_name, extension) = value.rsplit_once('=').context("expected CN=EXTENSION:VALUE")?;
+        ensure!(!common_name.is_empty(), "empty CN in extension override {:?}", value);
+        let (kind, extension_value) = extension.split_once(':').context("expected CN=EXTENSION:VALUE")?;
+        let kind = kind.parse()?;
+
+        Ok(Self {
+            common_name: common_name.to_string(),
+            kind,
+            value: match extension_value {
+                "none" => None,
+                extension_value => Some(encode(kind, extension_value).with_context(|| format!("parsing {:?}", extension))?),
+            },
+        })
+    }
+}
+
+impl ExtensionOverride {
+    fn matches(&self, common_name: &str) -> bool {
+        match self.common_name.strip_suffix('*') {
+            Some(prefix) => common_name.starts_with(prefix),
+            None => common_name == self.common_name,
+        }
+    }
+
+    fn apply(&self, extensions: &mut rfc5280::Extensions) {
+        let id = Oid(Bytes::copy_from_slice(self.kind.oid().as_bytes()));
+        let original = extensions.iter().find(|extension| extension.id == id).cloned();
+        extensions.retain(|extension| extension.id != id);
+
+        let Some(value) = &self.value else {
+            return;
+        };
+        extensions.push(rfc5280::Extension {
+            critical: match original {
+                Some(original) => original.critical,
+                None => self.kind.critical().then_some(true),
+            },
+            id,
+            value: OctetString::new(Bytes::copy_from_slice(value)),
+        });
+    }
+}
+
+fn encode(kind: ExtensionKind, value: &str) -> Result<Vec<u8>> {
+    let der = match kind {
+        ExtensionKind::KeyUsage => {
+            let mut key_usages = FlagSet::<KeyUsages>::default();
+            for name in value.split(',') {
+                let (_, key_usage) = KEY_USAGES
+                    .iter()
+                    .find(|(known, _)| *known == name)
+                    .with_context(|| format!("unknown key usage {:?}", name))?;
+                key_usages |= *key_usage;
+            }
+            KeyUsage(key_usages).to_der()
+        }
+        ExtensionKind::ExtendedKeyUsage => ExtendedKeyUsage(
+            value
+                .split(',')
+                .map(|name| match EXTENDED_KEY_USAGES.iter().find(|(known, _)| *known == name) {
+                    Some((_, oid)) => Ok(*oid),
+                    None => ObjectIdentifier::new(name)
+                        .ok()
+                        .with_context(|| format!("unknown extended key usage {:?}", name)),
+                })
+                .collect::<Result<Vec<_>>>()?,
+        )
+        .to_der(),
+        ExtensionKind::BasicConstraints => {
+            let mut parts = value.split(',');
+            let ca = match parts.next() {
+                Some("CA:TRUE") => true,
+                Some("CA:FALSE") => false,
+                _ => bail!("expected CA:TRUE or CA:FALSE, optionally followed by ,pathlen:N"),
+            };
+            let path_len_constraint = match parts.next() {
+                Some(path_len) => Some(
+                    path_len
+                        .strip_prefix("pathlen:")
+                        .context("expected pathlen:N")?
+                        .parse()
+                        .context("parsing path length")?,
+                ),
+                None => None,
+            };
+            ensure!(parts.next().is_none(), "trailing basicConstraints options");
+            ensure!(ca || path_len_constraint.is_none(), "only CAs can have a path length");
+            BasicConstraints { ca, path_len_constraint }.to_der()
+        }
+    };
+
+    der.context("encoding extension")
+}
+
+/// The changes to the extensions of regenerated certs. Extensions are otherwise carried over
+/// from the originals as they are, other than the SKID and AKID, which identify the new keys
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ExtensionPolicy {
+    /// Every matching one applies, in order
+    pub(crate) overrides: Vec<ExtensionOverride>,
+}
+
+impl ExtensionPolicy {
+    /// Override the extensions of a cert about to be re-signed
+    pub(crate) fn apply(&self, tbs_certificate: &mut rfc5280::TbsCertificate, common_name: Option<&str>) -> Result<()> {
+        let Some(common_name) = common_name else {
+            return Ok(());
+        };
+
+        for extension_override in self
+            .overrides
+            .iter()
+            .filter(|extension_override| extension_override.matches(common_name))
+        {
+            let extensions = tbs_certificate
+                .extensions
+                .as_mut()
+                .with_context(|| format!("{} is a v1 cert without extensions, which can't be overridden", common_name))?;
+            extension_override.apply(extensions);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cluster_crypto::cert_key_pair::basic_constraints, test_fixtures::CertFixture};
+    use x509_certificate::CapturedX509Certificate;
+
+    #[test]
+    fn test_parse() {
+        let extension_override = "system:node:*=extendedKeyUsage:clientAuth".parse::<ExtensionOverride>().unwrap();
+        assert_eq!(extension_override.kind, ExtensionKind::ExtendedKeyUsage);
+        // SEQUENCE { OID 1.3.6.1.5.5.7.3.2 }
+        assert_eq!(
+            extension_override.value.unwrap(),
+            [0x30, 0x0a, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02]
+        );
+
+        // BIT STRING with digitalSignature and keyEncipherment
+        let key_usage = "leaf=keyUsage:digitalSignature,keyEncipherment"
+            .parse::<ExtensionOverride>()
+            .unwrap();
+        assert_eq!(key_usage.value.unwrap(), [0x03, 0x02, 0x05, 0xa0]);
+
+        assert_eq!("leaf=basicConstraints:none".parse::<ExtensionOverride>().unwrap().value, None);
+        for invalid in [
+            "=keyUsage:digitalSignature",
+            "leaf",
+            "leaf=subjectAltName:DNS:example.com",
+            "leaf=keyUsage:signEverything",
+            "leaf=basicConstraints:CA:FALSE,pathlen:0",
+            "leaf=extendedKeyUsage:notAnOid",
+        ] {
+            assert!(invalid.parse::<ExtensionOverride>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let policy = ExtensionPolicy {
+            overrides: vec![
+                "leaf=basicConstraints:CA:TRUE,pathlen:0".parse().unwrap(),
+                "lea*=extendedKeyUsage:clientAuth".parse().unwrap(),
+            ],
+        };
+
+        let ca = CertFixture::ca("root");
+        let leaf = CertFixture::leaf("leaf", &ca);
+        let cert = CapturedX509Certificate::from_pem(&leaf.cert_pem).unwrap();
+        let certificate: &rfc5280::Certificate = cert.as_ref();
+
+        let mut tbs_certificate = certificate.tbs_certificate.clone();
+        policy.apply(&mut tbs_certificate, Some("root")).unwrap();
+        assert_eq!(tbs_certificate, certificate.tbs_certificate);
+
+        policy.apply(&mut tbs_certificate, Some("leaf")).unwrap();
+        let extensions = tbs_certificate.extensions.as_ref().unwrap();
+        let basic_constraints = basic_constraints::BasicConstraints::from_tbs_certificate(&tbs_certificate)
+            .unwrap()
+            .unwrap();
+        assert_eq!(basic_constraints.to_string(), "CA:TRUE, pathlen:0");
+        // Replaced extensions keep their criticality, added ones get the usual one
+        let find = |oid: ObjectIdentifier| {
+            extensions
+                .iter()
+                .find(|extension| extension.id == Oid(Bytes::copy_from_slice(oid.as_bytes())))
+                .unwrap()
+        };
+        assert_eq!(find(BasicConstraints::OID).critical, Some(true));
+        assert_eq!(find(ExtendedKeyUsage::OID).critical, None);
+    }
+}
@@ -0,0 +1,111 @@
+use super::{
+    cert_key_pair::{basic_constraints::BasicConstraints, skid},
+    crypto_utils,
+};
+use crate::signing;
+use anyhow::{ensure, Context, Result};
+use std::{path::PathBuf, str::FromStr};
+use x509_cert::ext::pkix::SubjectKeyIdentifier;
+use x509_certificate::{rfc3280, rfc5280, CapturedX509Certificate, InMemorySigningKeyPair, Sign, X509Certificate};
+use zeroize::Zeroizing;
+
+/// Where to load the external CA from, written as CERT,KEY (e.g. /etc/pki/org-ca.crt,env:ORG_CA_KEY).
+/// The key can come from any of the sources supported by file_utils::read_secret
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ExternalCaSource {
+    pub(crate) cert: PathBuf,
+    pub(crate) key: PathBuf,
+}
+
+impl FromStr for ExternalCaSource {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (cert, key) = value.split_once(',').context("expected CERT,KEY, e.g. ca.crt,ca.key")?;
+        ensure!(!cert.is_empty() && !key.is_empty(), "empty cert or key path in {:?}", value);
+
+        Ok(Self {
+            cert: PathBuf::from(cert),
+            key: PathBuf::from(key),
+        })
+    }
+}
+
+/// A CA from outside the cluster (e.g. of an organizational PKI) which re-signs the cluster's root
+/// CAs, instead of them being self-signed with their new keys, so that the whole cluster chains to
+/// it. The roots keep their subjects and become intermediates of this CA. The CA itself is never
+/// written anywhere, and its key is only used to sign the roots
+#[derive(Clone)]
+pub(crate) struct ExternalCa {
+    pub(crate) cert: CapturedX509Certificate,
+    pkcs8_der: Zeroizing<Vec<u8>>,
+}
+
+impl ExternalCa {
+    /// Load the CA and make sure it can actually sign the roots: it must be a CA, have a SKID
+    /// (which becomes the AKID of the roots) and its key must match its cert
+    pub(crate) fn load(source: &ExternalCaSource) -> Result<Self> {
+        let cert = CapturedX509Certificate::from_pem(std::fs::read(&source.cert).with_context(|| format!("reading {:?}", source.cert))?)
+            .context("parsing external CA cert")?;
+        let external_ca = Self {
+            pkcs8_der: signing::read_pkcs8_private_key(&source.key).context("reading external CA key")?,
+            cert,
+        };
+
+        let tbs_certificate = &external_ca.certificate().tbs_certificate;
+        ensure!(
+            BasicConstraints::from_tbs_certificate(tbs_certificate)?.is_some_and(|basic_constraints| basic_constraints.ca),
+            "external CA cert isn't a CA"
+        );
+        ensure!(
+            skid::get_skid(tbs_certificate)?.is_some(),
+            "external CA cert has no subject key identifier"
+        );
+        ensure!(
+            crypto_utils::constant_time_eq(
+                external_ca.key_pair()?.public_key_data().as_ref(),
+                external_ca.cert.public_key_data().as_ref()
+            ),
+            "external CA key doesn't match its cert"
+        );
+
+        Ok(external_ca)
+    }
+
+    fn certificate(&self) -> &rfc5280::Certificate {
+        let cert: &X509Certificate = &self.cert;
+        cert.as_ref()
+    }
+
+    pub(crate) fn key_pair(&self) -> Result<InMemorySigningKeyPair> {
+        InMemorySigningKeyPair::from_pkcs8_der(&self.pkcs8_der).context("loading external CA key")
+    }
+
+    /// The issuer of the roots once they're re-signed
+    pub(crate) fn subject(&self) -> rfc3280::Name {
+        self.certificate().tbs_certificate.subject.clone()
+    }
+
+    /// The AKID of the roots once they're re-signed
+    pub(crate) fn skid(&self) -> Result<SubjectKeyIdentifier> {
+        skid::get_skid(&self.certificate().tbs_certificate)?.context("external CA cert has no subject key identifier")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_external_ca_source() {
+        assert_eq!(
+            "ca.crt,env:CA_KEY".parse::<ExternalCaSource>().unwrap(),
+            ExternalCaSource {
+                cert: PathBuf::from("ca.crt"),
+                key: PathBuf::from("env:CA_KEY"),
+            }
+        );
+        assert!("ca.crt".parse::<ExternalCaSource>().is_err());
+        assert!(",ca.key".parse::<ExternalCaSource>().is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+
+/// A key = value line of an INI style document, such as a cloud provider config
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct IniEntry<'a> {
+    /// Empty for the keys before the first [section]
+    pub(crate) section: &'a str,
+    pub(crate) key: &'a str,
+    /// Without the quotes it may be written in
+    pub(crate) value: &'a str,
+    line_index: usize,
+}
+
+pub(crate) fn entries(document: &str) -> Vec<IniEntry<'_>> {
+    let mut section = "";
+    let mut entries = vec![];
+    for (line_index, line) in document.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with(['#', ';']) {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = name.trim();
+        } else if let Some((key, value)) = line.split_once('=') {
+            entries.push(IniEntry {
+                section,
+                key: key.trim(),
+                value: unquote(value.trim()),
+                line_index,
+            });
+        }
+    }
+
+    entries
+}
+
+pub(crate) fn get<'a>(document: &'a str, section: &str, key: &str) -> Option<&'a str> {
+    entries(document)
+        .into_iter()
+        .find(|entry| entry.section == section && entry.key == key)
+        .map(|entry| entry.value)
+}
+
+/// The document with the value of the key in the section replaced, keeping everything else (the
+/// spacing and quoting of the line included) as it was
+pub(crate) fn set(document: &str, section: &str, key: &str, value: &str) -> Result<String> {
+    let line_index = entries(document)
+        .into_iter()
+        .find(|entry| entry.section == section && entry.key == key)
+        .with_context(|| format!("key {} of section [{}] disappeared", key, section))?
+        .line_index;
+
+    Ok(document
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            if index != line_index {
+                return line.to_string();
+            }
+
+            let content = line.trim_end_matches(['\r', '\n']);
+            let ending = &line[content.len()..];
+            let (key_part, old_value) = content.split_once('=').unwrap_or((content, ""));
+            let spacing = &old_value[..old_value.len() - old_value.trim_start().len()];
+            let quote = if unquote(old_value.trim()) != old_value.trim() { "\"" } else { "" };
+            format!("{}={}{}{}{}{}", key_part, spacing, quote, value, quote, ending)
+        })
+        .collect())
+}
+
+fn unquote(value: &str) -> &str {
+    value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_and_set() {
+        let document =
+            "# comment\nglobal = 1\n[Global]\nsecret-name = \"vsphere-creds\"\nca-cert-data=AAAA\r\n\n[Workspace]\nca-cert-data = BBBB\n";
+
+        assert_eq!(
+            entries(document)
+                .iter()
+                .map(|entry| (entry.section, entry.key, entry.value))
+                .collect::<Vec<_>>(),
+            vec![
+                ("", "global", "1"),
+                ("Global", "secret-name", "vsphere-creds"),
+                ("Global", "ca-cert-data", "AAAA"),
+                ("Workspace", "ca-cert-data", "BBBB"),
+            ]
+        );
+        assert_eq!(get(document, "Workspace", "ca-cert-data"), Some("BBBB"));
+        assert_eq!(get(document, "Workspace", "secret-name"), None);
+
+        assert_eq!(
+            set(document, "Global", "ca-cert-data", "CCCC").unwrap(),
+            document.replace("ca-cert-data=AAAA\r\n", "ca-cert-data=CCCC\r\n")
+        );
+        assert_eq!(
+            set(document, "Global", "secret-name", "other-creds").unwrap(),
+            document.replace("\"vsphere-creds\"", "\"other-creds\"")
+        );
+        assert!(set(document, "Workspace", "secret-name", "other-creds").is_err());
+    }
+}
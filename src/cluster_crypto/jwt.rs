@@ -1,7 +1,12 @@
 use super::cert_key_pair::CertKeyPair;
 use super::distributed_private_key::DistributedPrivateKey;
+use anyhow::{bail, Context, Result};
+use jwt_simple::prelude::{Audiences, Duration, JWTClaims, UnixTimeStamp};
+use serde_json::{Map, Value};
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::rc::Rc;
+use std::str::FromStr;
 
 #[derive(Eq, PartialEq, Clone, Debug, Hash)]
 pub(crate) struct Jwt {
@@ -14,3 +19,114 @@ pub(crate) enum JwtSigner {
     CertKeyPair(Rc<RefCell<CertKeyPair>>),
     PrivateKey(Rc<RefCell<DistributedPrivateKey>>),
 }
+
+/// An audience of bound service account tokens and the one replacing it, written as OLD,NEW
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct AudienceReplace {
+    pub(crate) old: String,
+    pub(crate) new: String,
+}
+
+impl FromStr for AudienceReplace {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (old, new) = value.split_once(',').context("expected OLD,NEW")?;
+        let (old, new) = (old.trim(), new.trim());
+        if old.is_empty() || new.is_empty() {
+            bail!("empty audience in {:?}", value);
+        }
+
+        Ok(Self {
+            old: old.to_string(),
+            new: new.to_string(),
+        })
+    }
+}
+
+/// How the claims of re-signed tokens change. Legacy service account tokens (which never expire
+/// and have no audience) are re-signed with their claims as they are
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct TokenPolicy {
+    /// Tokens which expire (bound service account tokens) are re-issued to be valid from now for
+    /// this long, rather than keeping their original (possibly already passed) expiry
+    pub(crate) expiry: Option<chrono::Duration>,
+    pub(crate) audience_replace: Vec<AudienceReplace>,
+}
+
+impl TokenPolicy {
+    pub(crate) fn apply(&self, claims: &mut JWTClaims<Map<String, Value>>, now: UnixTimeStamp) -> Result<()> {
+        if let (Some(expiry), Some(_)) = (self.expiry, claims.expires_at) {
+            claims.issued_at = Some(now);
+            claims.invalid_before = Some(now);
+            claims.expires_at = Some(now + Duration::from_secs(expiry.num_seconds().try_into().context("negative token expiry")?));
+        }
+
+        let replace = |audience: String| match self.audience_replace.iter().find(|replace| replace.old == audience) {
+            Some(replace) => replace.new.clone(),
+            None => audience,
+        };
+        claims.audiences = match claims.audiences.take() {
+            Some(Audiences::AsString(audience)) => Some(Audiences::AsString(replace(audience))),
+            Some(Audiences::AsSet(audiences)) => Some(Audiences::AsSet(audiences.into_iter().map(replace).collect::<HashSet<_>>())),
+            None => None,
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_policy() {
+        let now = Duration::from_secs(1_700_000_000);
+        let bound = || JWTClaims {
+            issued_at: Some(Duration::from_secs(1_600_000_000)),
+            expires_at: Some(Duration::from_secs(1_600_003_600)),
+            invalid_before: Some(Duration::from_secs(1_600_000_000)),
+            issuer: Some("https://kubernetes.default.svc".to_string()),
+            subject: Some("system:serviceaccount:openshift-monitoring:prometheus-k8s".to_string()),
+            audiences: Some(Audiences::AsSet(HashSet::from([
+                "https://kubernetes.default.svc".to_string(),
+                "openshift".to_string(),
+            ]))),
+            jwt_id: None,
+            nonce: None,
+            custom: Map::new(),
+        };
+
+        // Nothing changes by default
+        let mut claims = bound();
+        TokenPolicy::default().apply(&mut claims, now).unwrap();
+        assert_eq!(claims.expires_at, bound().expires_at);
+        assert_eq!(claims.audiences.unwrap().into_set(), bound().audiences.unwrap().into_set());
+
+        let policy = TokenPolicy {
+            expiry: Some(chrono::Duration::hours(1)),
+            audience_replace: vec!["https://kubernetes.default.svc,https://kubernetes.default.svc.edge"
+                .parse()
+                .unwrap()],
+        };
+        let mut claims = bound();
+        policy.apply(&mut claims, now).unwrap();
+        assert_eq!(claims.issued_at, Some(now));
+        assert_eq!(claims.expires_at, Some(now + Duration::from_hours(1)));
+        assert_eq!(
+            claims.audiences.unwrap().into_set(),
+            HashSet::from(["https://kubernetes.default.svc.edge".to_string(), "openshift".to_string()])
+        );
+
+        // Legacy tokens never expire
+        let mut legacy = bound();
+        legacy.expires_at = None;
+        legacy.audiences = None;
+        policy.apply(&mut legacy, now).unwrap();
+        assert_eq!(legacy.expires_at, None);
+        assert_eq!(legacy.issued_at, bound().issued_at);
+
+        assert!("https://kubernetes.default.svc".parse::<AudienceReplace>().is_err());
+    }
+}
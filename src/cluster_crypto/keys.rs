@@ -1,23 +1,107 @@
+use super::crypto_utils;
 use anyhow::{bail, Context, Result};
 use base64::{engine::general_purpose::STANDARD as base64_standard, Engine as _};
 use bytes::Bytes;
-use p256::pkcs8::EncodePublicKey;
+use p256::{
+    elliptic_curve::sec1::ToEncodedPoint,
+    pkcs8::{AssociatedOid, DecodePrivateKey, EncodePublicKey},
+};
 use pkcs1::EncodeRsaPrivateKey;
-use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
-use rsa::RsaPrivateKey;
+use rsa::{pkcs8::EncodePrivateKey, RsaPrivateKey};
+use sec1::der::Encode;
 use std::{
     self,
-    fmt::Formatter,
+    fmt::{Display, Formatter},
+    hash::{Hash, Hasher},
     io::Write,
     process::{Command, Stdio},
 };
+use x509_certificate::{EcdsaCurve, InMemorySigningKeyPair};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-#[derive(Hash, Eq, PartialEq, Clone)]
+// Only a handful of these are ever alive at once, boxing the RSA key isn't worth it
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
 pub(crate) enum PrivateKey {
+    // RsaPrivateKey already zeroizes itself when dropped
     Rsa(RsaPrivateKey),
-    Ec(Bytes),
+    Ec(SecretBytes),
+}
+
+// Private keys are compared in constant time, see crypto_utils::constant_time_eq
+impl PartialEq for PrivateKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Rsa(key), Self::Rsa(other_key)) => match (key.to_pkcs1_der(), other_key.to_pkcs1_der()) {
+                (Ok(der), Ok(other_der)) => crypto_utils::constant_time_eq(der.as_bytes(), other_der.as_bytes()),
+                _ => false,
+            },
+            (Self::Ec(key), Self::Ec(other_key)) => key == other_key,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for PrivateKey {}
+
+impl Hash for PrivateKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            // Only hashes the public parts of the key
+            Self::Rsa(key) => key.hash(state),
+            Self::Ec(key) => key.hash(state),
+        }
+    }
+}
+
+/// Raw private key material which is zeroed out when dropped, so it doesn't linger in freed heap
+/// memory for the rest of the run
+#[derive(Clone)]
+pub(crate) struct SecretBytes(Vec<u8>);
+
+impl PartialEq for SecretBytes {
+    fn eq(&self, other: &Self) -> bool {
+        crypto_utils::constant_time_eq(&self.0, &other.0)
+    }
 }
 
+impl Eq for SecretBytes {}
+
+impl Hash for SecretBytes {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl From<&[u8]> for SecretBytes {
+    fn from(bytes: &[u8]) -> Self {
+        Self(bytes.to_vec())
+    }
+}
+
+impl std::ops::Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SecretBytes {}
+
 impl std::fmt::Debug for PrivateKey {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -28,20 +112,124 @@ impl std::fmt::Debug for PrivateKey {
 }
 
 impl PrivateKey {
+    /// For signing with this key, e.g. when re-signing certs with a CA's existing key
+    pub(crate) fn signing_key_pair(&self) -> Result<InMemorySigningKeyPair> {
+        match self {
+            PrivateKey::Rsa(rsa_private_key) => {
+                InMemorySigningKeyPair::from_pkcs8_der(rsa_private_key.to_pkcs8_der().context("encoding RSA private key")?.as_bytes())
+            }
+            PrivateKey::Ec(pkcs8_der) => InMemorySigningKeyPair::from_pkcs8_der(pkcs8_der),
+        }
+        .context("loading signing key pair")
+    }
+
     pub(crate) fn pem(&self) -> Result<pem::Pem> {
         Ok(match &self {
             PrivateKey::Rsa(rsa_private_key) => pem::Pem::new("RSA PRIVATE KEY", rsa_private_key.to_pkcs1_der()?.as_bytes()),
-            PrivateKey::Ec(ec_bytes) => pem::Pem::new("EC PRIVATE KEY", ec_bytes.as_ref()),
+            // We hold EC keys as PKCS#8, but "EC PRIVATE KEY" PEMs are SEC1
+            PrivateKey::Ec(ec_bytes) => pem::Pem::new("EC PRIVATE KEY", ec_sec1_der(ec_bytes)?.as_slice()),
         })
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Clone)]
+/// The curves of the EC keys we can regenerate like-for-like, keys on other curves are replaced by
+/// RSA keys
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum EcCurve {
+    P256,
+    P384,
+}
+
+impl EcCurve {
+    pub(crate) const ALL: [EcCurve; 2] = [EcCurve::P256, EcCurve::P384];
+
+    pub(crate) fn ecdsa_curve(&self) -> EcdsaCurve {
+        match self {
+            EcCurve::P256 => EcdsaCurve::Secp256r1,
+            EcCurve::P384 => EcdsaCurve::Secp384r1,
+        }
+    }
+}
+
+impl Display for EcCurve {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EcCurve::P256 => write!(f, "P-256"),
+            EcCurve::P384 => write!(f, "P-384"),
+        }
+    }
+}
+
+/// Convert a PKCS#8 EC private key to SEC1, the encoding of "EC PRIVATE KEY" PEMs
+fn ec_sec1_der(pkcs8_der: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let (private_key, public_key, curve) = if let Ok(secret_key) = p256::SecretKey::from_pkcs8_der(pkcs8_der) {
+        (
+            Zeroizing::new(secret_key.to_bytes().to_vec()),
+            secret_key.public_key().to_encoded_point(false).as_bytes().to_vec(),
+            p256::NistP256::OID,
+        )
+    } else {
+        let secret_key =
+            p384::SecretKey::from_pkcs8_der(pkcs8_der).context("parsing EC private key, only P-256 and P-384 are supported")?;
+        (
+            Zeroizing::new(secret_key.to_bytes().to_vec()),
+            secret_key.public_key().to_encoded_point(false).as_bytes().to_vec(),
+            p384::NistP384::OID,
+        )
+    };
+
+    // Unlike SecretKey::to_sec1_der, name the curve, without which neither openssl nor Go can
+    // load the key
+    Ok(Zeroizing::new(
+        sec1::EcPrivateKey {
+            private_key: &private_key,
+            parameters: Some(sec1::EcParameters::NamedCurve(curve)),
+            public_key: Some(&public_key),
+        }
+        .to_der()
+        .context("encoding EC private key")?,
+    ))
+}
+
+/// The public key of a PKCS#8 EC private key, as SubjectPublicKeyInfo PEM
+fn ec_public_key_pem(pkcs8_der: &[u8]) -> Result<String> {
+    if let Ok(secret_key) = p256::SecretKey::from_pkcs8_der(pkcs8_der) {
+        return Ok(secret_key.public_key().to_string());
+    }
+
+    Ok(p384::SecretKey::from_pkcs8_der(pkcs8_der)
+        .context("parsing EC private key, only P-256 and P-384 are supported")?
+        .public_key()
+        .to_string())
+}
+
+#[derive(Clone)]
 pub(crate) enum PublicKey {
     Rsa(Bytes),
     Ec(Bytes),
 }
 
+// Also compared in constant time, as this is how certs are paired with their private keys
+impl PartialEq for PublicKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Rsa(key), Self::Rsa(other_key)) | (Self::Ec(key), Self::Ec(other_key)) => crypto_utils::constant_time_eq(key, other_key),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for PublicKey {}
+
+impl Hash for PublicKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Rsa(key) | Self::Ec(key) => key.hash(state),
+        }
+    }
+}
+
 impl TryFrom<&PrivateKey> for PublicKey {
     type Error = anyhow::Error;
 
@@ -50,12 +238,9 @@ impl TryFrom<&PrivateKey> for PublicKey {
             PrivateKey::Rsa(private_key) => PublicKey::from_rsa_bytes(&bytes::Bytes::copy_from_slice(
                 private_key.to_public_key().to_public_key_der()?.as_bytes(),
             )),
-            PrivateKey::Ec(ec_bytes) => {
-                let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, ec_bytes)
-                    .ok()
-                    .context("failed to make pair from pkcs8")?;
-                PublicKey::Ec(Bytes::copy_from_slice(pair.public_key().as_ref()))
-            }
+            // Same encoding as the public keys of EC keys and certs we scan, so that regenerated
+            // keys can be matched against them just the same
+            PrivateKey::Ec(ec_bytes) => PublicKey::Ec(Bytes::copy_from_slice(ec_public_key_pem(ec_bytes)?.as_bytes())),
         })
     }
 }
@@ -105,10 +290,41 @@ impl PublicKey {
         Ok(PublicKey::Ec(output.stdout.into()))
     }
 
-    pub(crate) fn pem(&self) -> pem::Pem {
-        match &self {
-            PublicKey::Rsa(rsa_der_bytes) => pem::Pem::new("RSA PUBLIC KEY", rsa_der_bytes.as_ref()),
-            PublicKey::Ec(_) => todo!("Unsupported"),
+    /// The exact size in bits of an RSA key, or None for keys that aren't RSA
+    pub(crate) fn rsa_key_size(&self) -> Result<Option<usize>> {
+        match self {
+            PublicKey::Rsa(der_bytes) => Ok(Some(crypto_utils::rsa_key_size(der_bytes)?)),
+            PublicKey::Ec(_) => Ok(None),
         }
     }
+
+    /// The curve of an EC key, if it's one of the curves we generate keys for
+    pub(crate) fn ec_curve(&self) -> Option<EcCurve> {
+        let PublicKey::Ec(pem_bytes) = self else {
+            return None;
+        };
+        let pem = std::str::from_utf8(pem_bytes).ok()?;
+
+        if pem.parse::<p256::PublicKey>().is_ok() {
+            Some(EcCurve::P256)
+        } else if pem.parse::<p384::PublicKey>().is_ok() {
+            Some(EcCurve::P384)
+        } else {
+            None
+        }
+    }
+
+    /// EC keys are kept as the SPKI "PUBLIC KEY" PEM they were read as, and written back as one
+    pub(crate) fn pem(&self) -> Result<pem::Pem> {
+        Ok(match &self {
+            PublicKey::Rsa(rsa_der_bytes) => pem::Pem::new("RSA PUBLIC KEY", rsa_der_bytes.as_ref()),
+            PublicKey::Ec(pem_bytes) => {
+                let pem = pem::parse(pem_bytes.as_ref()).context("parsing EC public key PEM")?;
+                if pem.tag() != "PUBLIC KEY" {
+                    bail!("unexpected EC public key PEM tag {:?}", pem.tag());
+                }
+                pem
+            }
+        })
+    }
 }
@@ -1,3 +1,4 @@
+use super::resource_kinds::{CustomResourceKind, ResourceKindPolicy};
 use crate::json_tools;
 use anyhow::{bail, Context, Result};
 use lazy_static::lazy_static;
@@ -193,27 +194,29 @@ impl Debug for Location {
 }
 
 impl Location {
-    pub(crate) fn with_pem_bundle_index(&self, pem_bundle_index: u64) -> Result<Self> {
+    pub(crate) fn with_pem_bundle_index(&self, pem_bundle_index: u64, role: PemBundleRole) -> Result<Self> {
         Ok(match self {
             Self::K8s(k8s_location) => {
                 let mut new_k8s_location = k8s_location.clone();
-                new_k8s_location.yaml_location.value = LocationValueType::Pem(PemLocationInfo { pem_bundle_index });
+                new_k8s_location.yaml_location.value = LocationValueType::Pem(PemLocationInfo::new(pem_bundle_index, role));
                 Self::K8s(new_k8s_location)
             }
             Self::Filesystem(file_location) => match &file_location.content_location {
                 FileContentLocation::Raw(location_value_type) => match location_value_type {
                     LocationValueType::Pem(_) => bail!("already has PEM info"),
                     LocationValueType::Jwt => bail!("already has jwt info"),
+                    LocationValueType::SshPublicKey(_) => bail!("already has ssh public key info"),
                     LocationValueType::Unknown => {
                         let mut new_file_location = file_location.clone();
                         new_file_location.content_location =
-                            FileContentLocation::Raw(LocationValueType::Pem(PemLocationInfo::new(pem_bundle_index)));
+                            FileContentLocation::Raw(LocationValueType::Pem(PemLocationInfo::new(pem_bundle_index, role)));
                         Self::Filesystem(new_file_location)
                     }
                 },
+                FileContentLocation::Der => bail!("DER files cannot contain PEM bundles"),
                 FileContentLocation::Yaml(yaml_location) => {
                     let mut new_yaml_location = yaml_location.clone();
-                    new_yaml_location.value = LocationValueType::Pem(PemLocationInfo::new(pem_bundle_index));
+                    new_yaml_location.value = LocationValueType::Pem(PemLocationInfo::new(pem_bundle_index, role));
                     let mut new_file_location = file_location.clone();
                     new_file_location.content_location = FileContentLocation::Yaml(new_yaml_location);
                     Self::Filesystem(new_file_location)
@@ -233,12 +236,14 @@ impl Location {
                 FileContentLocation::Raw(location_value_type) => match location_value_type {
                     LocationValueType::Pem(_) => bail!("already has PEM info"),
                     LocationValueType::Jwt => bail!("already has jwt info"),
+                    LocationValueType::SshPublicKey(_) => bail!("already has ssh public key info"),
                     LocationValueType::Unknown => {
                         let mut new_file_location = file_location.clone();
                         new_file_location.content_location = FileContentLocation::Raw(LocationValueType::Jwt);
                         Self::Filesystem(new_file_location)
                     }
                 },
+                FileContentLocation::Der => bail!("DER files cannot contain jwts"),
                 FileContentLocation::Yaml(yaml_location) => {
                     let mut new_yaml_location = yaml_location.clone();
                     new_yaml_location.value = LocationValueType::Jwt;
@@ -249,25 +254,109 @@ impl Location {
             },
         })
     }
+
+    pub(crate) fn with_ssh_public_key_line(&self, line_index: u64) -> Result<Self> {
+        let value = LocationValueType::SshPublicKey(SshPublicKeyLocationInfo { line_index });
+
+        Ok(match self {
+            Self::K8s(k8s_location) => {
+                let mut new_k8s_location = k8s_location.clone();
+                new_k8s_location.yaml_location.value = value;
+                Self::K8s(new_k8s_location)
+            }
+            Self::Filesystem(file_location) => match &file_location.content_location {
+                FileContentLocation::Raw(LocationValueType::Unknown) => {
+                    let mut new_file_location = file_location.clone();
+                    new_file_location.content_location = FileContentLocation::Raw(value);
+                    Self::Filesystem(new_file_location)
+                }
+                FileContentLocation::Raw(location_value_type) => bail!("already has {} info", location_value_type),
+                FileContentLocation::Der => bail!("DER files cannot contain ssh public keys"),
+                FileContentLocation::Yaml(yaml_location) => {
+                    let mut new_yaml_location = yaml_location.clone();
+                    new_yaml_location.value = value;
+                    let mut new_file_location = file_location.clone();
+                    new_file_location.content_location = FileContentLocation::Yaml(new_yaml_location);
+                    Self::Filesystem(new_file_location)
+                }
+            },
+        })
+    }
+
+    /// The location of a value within a document (e.g. a kubeconfig or a cloud provider config)
+    /// which is itself stored whole in the field at this location. The embedded encoding is the
+    /// FieldEncoding::Yaml or FieldEncoding::Ini locating the value within that document
+    pub(crate) fn with_embedded(&self, embedded_encoding: FieldEncoding) -> Result<Self> {
+        let embed = |yaml_location: &YamlLocation| YamlLocation {
+            encoding: yaml_location.encoding.wrapping(embedded_encoding.clone()),
+            ..yaml_location.clone()
+        };
+
+        Ok(match self {
+            Self::K8s(k8s_location) => {
+                let mut new_k8s_location = k8s_location.clone();
+                new_k8s_location.yaml_location = embed(&k8s_location.yaml_location);
+                Self::K8s(new_k8s_location)
+            }
+            Self::Filesystem(file_location) => match &file_location.content_location {
+                FileContentLocation::Yaml(yaml_location) => {
+                    let mut new_file_location = file_location.clone();
+                    new_file_location.content_location = FileContentLocation::Yaml(embed(yaml_location));
+                    Self::Filesystem(new_file_location)
+                }
+                FileContentLocation::Raw(_) => bail!("raw files cannot embed documents"),
+                FileContentLocation::Der => bail!("DER files cannot embed documents"),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub(crate) struct SshPublicKeyLocationInfo {
+    pub(crate) line_index: u64,
+}
+
+impl std::fmt::Display for SshPublicKeyLocationInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, ":ssh{}", self.line_index)
+    }
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub(crate) struct PemLocationInfo {
     pub(crate) pem_bundle_index: u64,
+    pub(crate) role: PemBundleRole,
 }
 
 impl PemLocationInfo {
-    fn new(pem_bundle_index: u64) -> Self {
-        Self { pem_bundle_index }
+    pub(crate) fn new(pem_bundle_index: u64, role: PemBundleRole) -> Self {
+        Self { pem_bundle_index, role }
     }
 }
 
 impl std::fmt::Display for PemLocationInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, ":pem{}", self.pem_bundle_index)
+        write!(f, ":pem{}", self.pem_bundle_index)?;
+        match self.role {
+            PemBundleRole::Member => Ok(()),
+            PemBundleRole::Leaf => write!(f, "(leaf)"),
+            PemBundleRole::Chain => write!(f, "(chain)"),
+        }
     }
 }
 
+/// What a PEM is to the rest of its bundle. Most bundles are sets of unrelated PEMs (e.g. CA
+/// bundles), but some, like the tls.crt of kubernetes.io/tls secrets, are a leaf cert followed by
+/// the intermediates that (transitively) signed it, in order, which has to stay that way
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub(crate) enum PemBundleRole {
+    Member,
+    /// The first cert of a chain
+    Leaf,
+    /// A cert of a chain which signs the cert right before it
+    Chain,
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub(crate) struct FileLocation {
     pub(crate) path: String,
@@ -278,6 +367,8 @@ pub(crate) struct FileLocation {
 pub(crate) enum FileContentLocation {
     Raw(LocationValueType),
     Yaml(YamlLocation),
+    /// The entire file is a single DER encoded object, without any PEM armor
+    Der,
 }
 
 impl std::fmt::Display for FileContentLocation {
@@ -285,6 +376,7 @@ impl std::fmt::Display for FileContentLocation {
         match self {
             FileContentLocation::Raw(pem_location_info) => write!(f, "{}", pem_location_info),
             FileContentLocation::Yaml(yaml_location) => write!(f, "{}", yaml_location),
+            FileContentLocation::Der => write!(f, ":der"),
         }
     }
 }
@@ -293,6 +385,8 @@ impl std::fmt::Display for FileContentLocation {
 pub(crate) enum LocationValueType {
     Pem(PemLocationInfo),
     Jwt,
+    /// A line of an authorized_keys style list of SSH public keys
+    SshPublicKey(SshPublicKeyLocationInfo),
     Unknown,
 }
 
@@ -301,6 +395,7 @@ impl std::fmt::Display for LocationValueType {
         match self {
             LocationValueType::Pem(pem_location_info) => write!(f, "{}", pem_location_info),
             LocationValueType::Jwt => write!(f, ":jwt"),
+            LocationValueType::SshPublicKey(ssh_location_info) => write!(f, "{}", ssh_location_info),
             LocationValueType::Unknown => write!(f, ":unknown"),
         }
     }
@@ -310,7 +405,41 @@ impl std::fmt::Display for LocationValueType {
 pub(crate) enum FieldEncoding {
     None,
     Base64,
+    /// URL-safe base64 without padding, as used for the parts of JWTs
+    Base64Url,
     DataUrl,
+    /// Encoded with the second encoding and then again with the first one, e.g. a base64 field
+    /// holding a base64 encoded PEM bundle
+    Nested(Box<FieldEncoding>, Box<FieldEncoding>),
+    /// The field holds a whole YAML (or JSON) document, e.g. a kubeconfig stored in a secret, and
+    /// the value is the string at the JSON pointer within that document, encoded with the inner
+    /// encoding
+    Yaml(String, Box<FieldEncoding>),
+    /// The field holds a whole INI document, e.g. a cloud provider config stored in a configmap,
+    /// and the value is that of the key (second) in the section (first) of that document, encoded
+    /// with the inner encoding
+    Ini(String, String, Box<FieldEncoding>),
+}
+
+impl FieldEncoding {
+    /// The encoding of a value encoded with the inner encoding and then with this one
+    pub(crate) fn wrapping(&self, inner: FieldEncoding) -> FieldEncoding {
+        match self {
+            FieldEncoding::None => inner,
+            outer => FieldEncoding::Nested(Box::new(outer.clone()), Box::new(inner)),
+        }
+    }
+
+    /// Where the value is within the document embedded in the field, if there is one: a JSON
+    /// pointer for YAML documents and [section]key for INI ones
+    fn embedded_path(&self) -> Option<String> {
+        match self {
+            FieldEncoding::None | FieldEncoding::Base64 | FieldEncoding::Base64Url | FieldEncoding::DataUrl => None,
+            FieldEncoding::Nested(outer, inner) => outer.embedded_path().or_else(|| inner.embedded_path()),
+            FieldEncoding::Yaml(json_pointer, _) => Some(json_pointer.clone()),
+            FieldEncoding::Ini(section, key, _) => Some(format!("[{}]{}", section, key)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -323,7 +452,7 @@ pub(crate) struct YamlLocation {
 impl YamlLocation {
     pub fn new(prefix: &str, key: &str, encoding: FieldEncoding) -> Self {
         YamlLocation {
-            json_pointer: format!("{}/{}", prefix, key.to_string().replace("/", "~1")),
+            json_pointer: format!("{}/{}", prefix, key.replace('~', "~0").replace('/', "~1")),
             value: LocationValueType::Unknown,
             encoding,
         }
@@ -332,7 +461,10 @@ impl YamlLocation {
 
 impl std::fmt::Display for YamlLocation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, ":{}{}", self.json_pointer, self.value)
+        match self.encoding.embedded_path() {
+            Some(embedded_path) => write!(f, ":{}:{}{}", self.json_pointer, embedded_path, self.value),
+            None => write!(f, ":{}{}", self.json_pointer, self.value),
+        }
     }
 }
 
@@ -342,23 +474,44 @@ pub(crate) struct K8sResourceLocation {
     pub(crate) kind: String,
     pub(crate) apiversion: String,
     pub(crate) name: String,
+    /// Set for resources of a configured custom resource kind, which are stored differently, see
+    /// as_etcd_key
+    pub(crate) custom_resource_kind: Option<CustomResourceKind>,
 }
 
 impl K8sResourceLocation {
     pub(crate) fn new(namespace: Option<&str>, kind: &str, name: &str, apiversion: &str) -> Self {
         Self {
-            namespace: if let Some(namespace) = namespace {
-                Some(namespace.to_string())
-            } else {
-                None
-            },
+            namespace: namespace.map(|namespace| namespace.to_string()),
             kind: kind.to_string(),
             name: name.to_string(),
             apiversion: apiversion.to_string(),
+            custom_resource_kind: None,
+        }
+    }
+
+    /// Tell whether this resource is of one of the custom resource kinds being scanned
+    pub(crate) fn with_resource_kinds(self, resource_kinds: &ResourceKindPolicy) -> Self {
+        let custom_resource_kind = resource_kinds.find_custom(&self.apiversion, &self.kind).cloned();
+        Self {
+            custom_resource_kind,
+            ..self
         }
     }
 
     pub(crate) fn as_etcd_key(&self) -> String {
+        if let Some(custom_resource_kind) = &self.custom_resource_kind {
+            return format!(
+                "/kubernetes.io/{}/{}{}",
+                custom_resource_kind.etcd_resource(),
+                match &self.namespace {
+                    Some(namespace) => format!("{}/", namespace),
+                    None => "".to_string(),
+                },
+                self.name,
+            );
+        }
+
         let apiversion_first_component = self.apiversion.as_str().split('/').next();
 
         format!(
@@ -371,7 +524,11 @@ impl K8sResourceLocation {
             match apiversion_first_component {
                 Some(apiversion_first_component_value) => {
                     match apiversion_first_component_value {
-                        "apiregistration.k8s.io" | "machineconfiguration.openshift.io" | "config.openshift.io" | "console.openshift.io" => {
+                        "apiregistration.k8s.io"
+                        | "apiextensions.k8s.io"
+                        | "machineconfiguration.openshift.io"
+                        | "config.openshift.io"
+                        | "console.openshift.io" => {
                             format!("{}/", apiversion_first_component_value)
                         }
                         _ => "".to_string(),
@@ -399,6 +556,7 @@ impl TryFrom<&serde_json::Value> for K8sResourceLocation {
             kind: json_tools::read_string_field(value, "kind").context("missing kind field")?,
             name: json_tools::read_metadata_string_field(value, "name").context("missing name field")?,
             apiversion: json_tools::read_string_field(value, "apiVersion").context("missing apiversion field")?,
+            custom_resource_kind: None,
         })
     }
 }
@@ -0,0 +1,209 @@
+use crate::{cluster_crypto::ini, file_utils};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::PathBuf,
+};
+
+/// Config files which reference cert and key files by path rather than embedding them
+const CONFIG_GLOBS: [&str; 5] = [
+    "**/kubelet.conf",
+    "**/kubelet-config.yaml",
+    "**/kubeconfig",
+    "**/*kubeconfig",
+    "**/kubeConfig",
+];
+
+/// Cloud provider and CSI driver configs, which are INI-like (gcfg) rather than YAML
+const CLOUD_CONFIG_GLOBS: [&str; 4] = [
+    "**/cloud.conf",
+    "**/vsphere.conf",
+    "**/cloud-config/config",
+    "**/cloud-config/cloud.conf",
+];
+
+/// Cloud config keys holding a path, the CA bundle the cloud provider trusts its endpoint with
+const CLOUD_CONFIG_PATH_KEYS: [&str; 2] = ["ca-file", "ca_file"];
+
+/// Kubelet config fields holding a path
+const KUBELET_CONFIG_POINTERS: [&str; 3] = ["/tlsCertFile", "/tlsPrivateKeyFile", "/authentication/x509/clientCAFile"];
+
+/// Kubeconfig user fields holding a path
+const KUBECONFIG_USER_POINTERS: [&str; 2] = ["/user/client-certificate", "/user/client-key"];
+
+/// Files referenced by path from the known config files in the static dirs which the regular scan
+/// doesn't already cover, usually because their names don't match any of its globs. recert only
+/// modifies files in the static dirs, so references to files outside of them are only warned about
+pub(crate) async fn unscanned_referenced_files(static_dirs: &[PathBuf], scanned_files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let static_dirs = static_dirs
+        .iter()
+        .map(|dir| dir.canonicalize().with_context(|| format!("resolving static dir {:?}", dir)))
+        .collect::<Result<Vec<_>>>()?;
+    let scanned_files = scanned_files
+        .iter()
+        .filter_map(|file_path| file_path.canonicalize().ok())
+        .collect::<HashSet<_>>();
+
+    let mut config_paths = BTreeSet::new();
+    let mut cloud_config_paths = BTreeSet::new();
+    for dir in &static_dirs {
+        for glob in CONFIG_GLOBS {
+            config_paths.extend(file_utils::globvec(dir, glob)?);
+        }
+        for glob in CLOUD_CONFIG_GLOBS {
+            cloud_config_paths.extend(file_utils::globvec(dir, glob)?);
+        }
+    }
+
+    let mut references = vec![];
+    for config_path in config_paths {
+        // Not every file matching the globs is necessarily a config we understand
+        let Ok(config) = serde_yaml::from_slice::<Value>(&file_utils::read_file(&config_path).await?) else {
+            continue;
+        };
+        let paths = referenced_paths(&config).into_iter().map(str::to_string).collect::<Vec<_>>();
+        references.push((config_path, paths));
+    }
+    for config_path in cloud_config_paths {
+        let config = String::from_utf8_lossy(&file_utils::read_file(&config_path).await?).into_owned();
+        let paths = cloud_config_referenced_paths(&config)
+            .into_iter()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        references.push((config_path, paths));
+    }
+
+    let mut referenced_files = BTreeSet::new();
+    for (config_path, paths) in references {
+        for referenced_path in paths {
+            // Relative paths are relative to the config file, for both kubeconfigs and kubelet configs
+            let referenced_path = config_path.parent().context("config file without a parent")?.join(referenced_path);
+
+            let Ok(resolved_path) = referenced_path.canonicalize() else {
+                println!("WARNING: {:?} references {:?}, which doesn't exist", config_path, referenced_path);
+                continue;
+            };
+
+            if !static_dirs.iter().any(|dir| resolved_path.starts_with(dir)) {
+                println!(
+                    "WARNING: {:?} references {:?}, which is outside of the static dirs and will not be regenerated",
+                    config_path, referenced_path
+                );
+                continue;
+            }
+
+            if !scanned_files.contains(&resolved_path) && referenced_files.insert(resolved_path.clone()) {
+                println!("Following reference from {:?} to {:?}", config_path, resolved_path);
+            }
+        }
+    }
+
+    Ok(referenced_files.into_iter().collect())
+}
+
+/// The paths a kubelet config or kubeconfig references, as written in it
+fn referenced_paths(config: &Value) -> Vec<&str> {
+    let kubelet_config_paths = KUBELET_CONFIG_POINTERS.into_iter().filter_map(|pointer| config.pointer(pointer));
+
+    let kubeconfig_cluster_paths = config
+        .pointer("/clusters")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|cluster| cluster.pointer("/cluster/certificate-authority"));
+
+    let kubeconfig_user_paths = config
+        .pointer("/users")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .flat_map(|user| KUBECONFIG_USER_POINTERS.into_iter().filter_map(|pointer| user.pointer(pointer)));
+
+    kubelet_config_paths
+        .chain(kubeconfig_cluster_paths)
+        .chain(kubeconfig_user_paths)
+        .filter_map(Value::as_str)
+        .filter(|path| !path.is_empty())
+        .collect()
+}
+
+/// The paths a cloud config references, as written in it, e.g. ca-file = "/etc/kubernetes/ca.pem"
+fn cloud_config_referenced_paths(config: &str) -> Vec<&str> {
+    ini::entries(config)
+        .into_iter()
+        .filter(|entry| CLOUD_CONFIG_PATH_KEYS.contains(&entry.key))
+        .map(|entry| entry.value)
+        .filter(|path| !path.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_referenced_paths() {
+        let kubelet_config = serde_json::json!({
+            "kind": "KubeletConfiguration",
+            "tlsCertFile": "/etc/kubernetes/kubelet-server.crt",
+            "tlsPrivateKeyFile": "",
+            "authentication": {"x509": {"clientCAFile": "/etc/kubernetes/kubelet-ca.crt"}},
+        });
+        assert_eq!(
+            referenced_paths(&kubelet_config),
+            vec!["/etc/kubernetes/kubelet-server.crt", "/etc/kubernetes/kubelet-ca.crt"]
+        );
+
+        let kubeconfig = serde_json::json!({
+            "clusters": [{"cluster": {"certificate-authority": "ca.bundle", "server": "https://api-int.x:6443"}}],
+            "users": [{"user": {"client-certificate": "/var/lib/kubelet/pki/kubelet-client-current.pem", "client-key": "/var/lib/kubelet/pki/kubelet-client-current.pem"}}],
+        });
+        assert_eq!(
+            referenced_paths(&kubeconfig),
+            vec![
+                "ca.bundle",
+                "/var/lib/kubelet/pki/kubelet-client-current.pem",
+                "/var/lib/kubelet/pki/kubelet-client-current.pem"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cloud_config_referenced_paths() {
+        let vsphere_conf = r#"[Global]
+secret-name = "vsphere-creds"
+ca-file = "/etc/kubernetes/static-pod-resources/configmaps/cloud-config/ca-bundle.pem"
+
+[VirtualCenter "vcenter.example.com"]
+datacenters = "dc1"
+"#;
+        assert_eq!(
+            cloud_config_referenced_paths(vsphere_conf),
+            vec!["/etc/kubernetes/static-pod-resources/configmaps/cloud-config/ca-bundle.pem"]
+        );
+        assert!(cloud_config_referenced_paths("[Global]\nca-file = \"\"\n").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unscanned_referenced_files() {
+        let dir = std::env::temp_dir().join(format!("recert-path-references-test-{}", std::process::id()));
+        let static_dir = dir.join("kubernetes");
+        std::fs::create_dir_all(&static_dir).unwrap();
+        std::fs::write(static_dir.join("ca.bundle"), "").unwrap();
+        std::fs::write(static_dir.join("client.crt"), "").unwrap();
+        std::fs::write(dir.join("outside.crt"), "").unwrap();
+        std::fs::write(
+            static_dir.join("kubeconfig"),
+            "clusters: [{cluster: {certificate-authority: ca.bundle}}]\nusers: [{user: {client-certificate: client.crt, client-key: ../outside.crt}}]\n",
+        )
+        .unwrap();
+
+        let referenced_files = unscanned_referenced_files(std::slice::from_ref(&static_dir), &[static_dir.join("client.crt")])
+            .await
+            .unwrap();
+        assert_eq!(referenced_files, vec![static_dir.canonicalize().unwrap().join("ca.bundle")]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,19 +1,213 @@
-use anyhow::Result;
-
-pub(crate) fn pem_bundle_replace_pem_at_index(original_pem_bundle: String, pem_index: u64, newpem: &pem::Pem) -> Result<String> {
-    let pems = pem::parse_many(original_pem_bundle.clone())?;
-    let mut newpems = vec![];
-    for (i, pem) in pems.iter().enumerate() {
-        if i == usize::try_from(pem_index)? {
-            newpems.push(newpem.clone());
+use super::{private_key_format::PrivateKeyPolicy, ssh_keys};
+use anyhow::{bail, Context, Result};
+use std::{collections::BTreeMap, ops::Range};
+
+/// A PEM bundle indexed once into the byte ranges of its individual PEMs. Any number of PEMs can
+/// then be replaced, and the bundle is serialized in a single pass which copies everything that
+/// wasn't replaced (untouched PEMs, a leading byte order mark, separators, trailing whitespace,
+/// etc.) verbatim from the original, so consumers sensitive to such formatting details aren't
+/// affected. PEMs can also be removed, along with the line ending following them.
+pub(crate) struct PemBundle<'a> {
+    original: &'a str,
+    line_ending: pem::LineEnding,
+    spans: Vec<Range<usize>>,
+    /// None for removed PEMs
+    replacements: BTreeMap<usize, Option<pem::Pem>>,
+}
+
+impl<'a> PemBundle<'a> {
+    pub(crate) fn parse(original: &'a str) -> Result<Self> {
+        let mut spans = vec![];
+        let mut search_from = 0;
+
+        while let Some(begin) = original[search_from..].find("-----BEGIN ").map(|offset| search_from + offset) {
+            let end_marker = original[begin..].find("-----END ").context("pem missing end marker")? + begin;
+            let end = original[end_marker + "-----END ".len()..]
+                .find("-----")
+                .context("pem end marker not terminated")?
+                + end_marker
+                + "-----END ".len()
+                + "-----".len();
+
+            pem::parse(&original[begin..end]).with_context(|| format!("parsing pem at index {} in the bundle", spans.len()))?;
+
+            spans.push(begin..end);
+            search_from = end;
+        }
+
+        Ok(Self {
+            original,
+            line_ending: if original.contains("\r\n") {
+                pem::LineEnding::CRLF
+            } else {
+                pem::LineEnding::LF
+            },
+            spans,
+            replacements: BTreeMap::new(),
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub(crate) fn replace(&mut self, pem_index: u64, newpem: &pem::Pem, private_key_policy: &PrivateKeyPolicy) -> Result<()> {
+        let pem_index = usize::try_from(pem_index)?;
+        if pem_index >= self.len() {
+            bail!("pem index {} out of range for bundle of {} pems", pem_index, self.len());
+        }
+
+        let original_pem = pem::parse(&self.original[self.spans[pem_index].clone()])?;
+
+        // We hold all private keys as PKCS#1 / SEC1, those we found in other encodings are written back as such
+        let newpem = if original_pem.tag() == ssh_keys::OPENSSH_PRIVATE_KEY_TAG && newpem.tag() != original_pem.tag() {
+            ssh_keys::reencode_like_openssh_private_key(&original_pem, newpem).context("re-encoding as OpenSSH private key")?
         } else {
-            newpems.push(pem.clone());
+            private_key_policy
+                .reencode_like(&original_pem, newpem)
+                .context("re-encoding private key")?
+        };
+
+        // Don't bother re-encoding a PEM which is identical to the one it's replacing, so that
+        // the bundle stays byte-identical to the original
+        if original_pem == newpem {
+            self.replacements.remove(&pem_index);
+        } else {
+            self.replacements.insert(pem_index, Some(newpem));
         }
+
+        Ok(())
     }
-    Ok(pem::encode_many_config(
-        &newpems,
-        pem::EncodeConfig {
+
+    /// Remove all copies of the given PEM from the bundle, returns how many were removed
+    pub(crate) fn remove(&mut self, pem: &pem::Pem) -> Result<usize> {
+        let mut removed = 0;
+        for (pem_index, span) in self.spans.iter().enumerate() {
+            if pem::parse(&self.original[span.clone()])? == *pem {
+                self.replacements.insert(pem_index, None);
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    pub(crate) fn encode(&self) -> String {
+        let mut encoded = String::with_capacity(self.original.len());
+        let mut copied_until = 0;
+
+        for (pem_index, newpem) in &self.replacements {
+            let span = &self.spans[*pem_index];
+            encoded.push_str(&self.original[copied_until..span.start]);
+            copied_until = span.end;
+
+            match newpem {
+                Some(newpem) => encoded.push_str(
+                    pem::encode_config(
+                        newpem,
+                        pem::EncodeConfig {
+                            line_ending: self.line_ending,
+                        },
+                    )
+                    .trim_end(),
+                ),
+                // Removed PEMs take the whitespace separating them from the next PEM along with them,
+                // or from the previous one if they're last, so the bundle keeps its layout
+                None => match self.spans.get(pem_index + 1) {
+                    Some(next_span) if self.original[span.end..next_span.start].trim().is_empty() => copied_until = next_span.start,
+                    _ if *pem_index > 0 && self.original[self.spans[pem_index - 1].end..span.start].trim().is_empty() => {
+                        encoded.truncate(encoded.trim_end().len())
+                    }
+                    _ => {
+                        let rest = &self.original[copied_until..];
+                        copied_until += rest.len() - rest.strip_prefix("\r\n").or(rest.strip_prefix('\n')).unwrap_or(rest).len();
+                    }
+                },
+            }
+        }
+
+        encoded.push_str(&self.original[copied_until..]);
+        encoded
+    }
+}
+
+pub fn pem_bundle_replace_pem_at_index(
+    original_pem_bundle: String,
+    pem_index: u64,
+    newpem: &pem::Pem,
+    private_key_policy: &PrivateKeyPolicy,
+) -> Result<String> {
+    let mut pem_bundle = PemBundle::parse(&original_pem_bundle)?;
+    pem_bundle.replace(pem_index, newpem, private_key_policy)?;
+    Ok(pem_bundle.encode())
+}
+
+/// Remove the given PEM from a bundle, unless it's the only PEM in there. Certs which are alone in
+/// their location aren't part of a bundle, and removing them would leave nothing behind
+pub fn pem_bundle_remove_pem(original_pem_bundle: String, pem: &pem::Pem) -> Result<String> {
+    let mut pem_bundle = PemBundle::parse(&original_pem_bundle)?;
+    let removed = pem_bundle.remove(pem)?;
+    if removed == 0 || removed == pem_bundle.len() {
+        return Ok(original_pem_bundle);
+    }
+    Ok(pem_bundle.encode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pem_bundle_multiple_replacements() {
+        let pems = (0..5u8).map(|i| pem::Pem::new("CERTIFICATE", vec![i; 100])).collect::<Vec<_>>();
+        let original = format!(
+            "\u{feff}# comment\r\n{}\r\n\r\n",
+            pem::encode_many_config(
+                &pems,
+                pem::EncodeConfig {
+                    line_ending: pem::LineEnding::CRLF
+                }
+            )
+            .trim_end()
+        );
+
+        let mut bundle = PemBundle::parse(&original).unwrap();
+        assert_eq!(bundle.len(), 5);
+
+        let replacement = pem::Pem::new("CERTIFICATE", vec![0xff; 300]);
+        bundle.replace(1, &replacement, &PrivateKeyPolicy::default()).unwrap();
+        bundle.replace(3, &replacement, &PrivateKeyPolicy::default()).unwrap();
+        assert!(bundle.replace(5, &replacement, &PrivateKeyPolicy::default()).is_err());
+
+        let encoded = bundle.encode();
+        assert!(encoded.starts_with("\u{feff}# comment\r\n"));
+        assert!(encoded.ends_with("-----\r\n\r\n"));
+        assert!(!encoded.replace("\r\n", "").contains('\n'));
+        assert_eq!(
+            pem::parse_many(&encoded).unwrap(),
+            vec![pems[0].clone(), replacement.clone(), pems[2].clone(), replacement, pems[4].clone()]
+        );
+    }
+
+    #[test]
+    fn test_pem_bundle_remove_pem() {
+        let pems = (0..3u8).map(|i| pem::Pem::new("CERTIFICATE", vec![i; 100])).collect::<Vec<_>>();
+        let lf = pem::EncodeConfig {
             line_ending: pem::LineEnding::LF,
-        },
-    ))
+        };
+        let original = format!("# comment\n{}", pem::encode_many_config(&pems, lf));
+
+        for (removed, kept) in [(0, [1, 2]), (1, [0, 2]), (2, [0, 1])] {
+            let encoded = pem_bundle_remove_pem(original.clone(), &pems[removed]).unwrap();
+            assert!(encoded.starts_with("# comment\n-----BEGIN "));
+            assert!(encoded.ends_with("-----\n") && !encoded.ends_with("\n\n"));
+            assert_eq!(pem::parse_many(&encoded).unwrap(), kept.map(|i| pems[i].clone()));
+        }
+
+        // Not in the bundle, or the only PEM in it
+        let other = pem::Pem::new("CERTIFICATE", vec![0xff; 100]);
+        assert_eq!(pem_bundle_remove_pem(original.clone(), &other).unwrap(), original);
+        let single = pem::encode_config(&pems[0], lf);
+        assert_eq!(pem_bundle_remove_pem(single.clone(), &pems[0]).unwrap(), single);
+    }
 }
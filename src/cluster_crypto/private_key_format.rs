@@ -0,0 +1,183 @@
+use anyhow::{ensure, Context, Result};
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+use zeroize::Zeroizing;
+
+pub(crate) const PKCS8_TAG: &str = "PRIVATE KEY";
+pub(crate) const ENCRYPTED_PKCS8_TAG: &str = "ENCRYPTED PRIVATE KEY";
+
+/// The encoding of regenerated private keys. Keys found as (encrypted) PKCS#8 are always written
+/// back as such
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum PrivateKeyFormat {
+    /// Write keys back in the encoding they were found in
+    #[default]
+    Preserve,
+    /// Write PKCS#1 "RSA PRIVATE KEY" and SEC1 "EC PRIVATE KEY" keys as PKCS#8 "PRIVATE KEY"
+    Pkcs8,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct PrivateKeyPolicy {
+    pub(crate) format: PrivateKeyFormat,
+    /// The file holding the passphrase of encrypted PKCS#8 keys, which are otherwise left untouched
+    pub(crate) passphrase_file: Option<PathBuf>,
+}
+
+impl PrivateKeyPolicy {
+    /// Decrypt an "ENCRYPTED PRIVATE KEY" PEM into a "PRIVATE KEY" PEM, None when there's no
+    /// passphrase to decrypt it with
+    pub(crate) fn decrypt(&self, pem: &pem::Pem) -> Result<Option<pem::Pem>> {
+        let Some(passphrase_file) = &self.passphrase_file else {
+            return Ok(None);
+        };
+
+        openssl_pkcs8(pem, &["-passin", &format!("file:{}", passphrase_file.display())])
+            .context("decrypting PKCS#8 private key, is the passphrase right?")
+            .map(Some)
+    }
+
+    fn encrypt(&self, pem: &pem::Pem) -> Result<pem::Pem> {
+        let passphrase_file = self.passphrase_file.as_ref().context("no passphrase to encrypt private key with")?;
+
+        openssl_pkcs8(
+            pem,
+            &[
+                "-topk8",
+                "-v2",
+                "aes-256-cbc",
+                "-passout",
+                &format!("file:{}", passphrase_file.display()),
+            ],
+        )
+        .context("encrypting PKCS#8 private key")
+    }
+
+    /// Encode a new PKCS#1 / SEC1 private key PEM like the private key PEM it replaces, or as
+    /// PKCS#8 when that's forced. We hold all private keys as PKCS#1 / SEC1, so this is how keys
+    /// found as PKCS#8 get written back as such
+    pub(crate) fn reencode_like(&self, original: &pem::Pem, new_pem: &pem::Pem) -> Result<pem::Pem> {
+        if !matches!(new_pem.tag(), "RSA PRIVATE KEY" | "EC PRIVATE KEY") {
+            return Ok(new_pem.clone());
+        }
+
+        match original.tag() {
+            ENCRYPTED_PKCS8_TAG => {
+                let new_pem = to_pkcs8(new_pem)?;
+                // Encryption is salted, so only keys which actually changed are re-encrypted
+                if self.decrypt(original)?.as_ref() == Some(&new_pem) {
+                    Ok(original.clone())
+                } else {
+                    self.encrypt(&new_pem)
+                }
+            }
+            PKCS8_TAG => to_pkcs8(new_pem),
+            _ if self.format == PrivateKeyFormat::Pkcs8 => to_pkcs8(new_pem),
+            _ => Ok(new_pem.clone()),
+        }
+    }
+}
+
+/// Convert a PKCS#1 "RSA PRIVATE KEY" or SEC1 "EC PRIVATE KEY" PEM to a PKCS#8 "PRIVATE KEY" PEM
+pub(crate) fn to_pkcs8(pem: &pem::Pem) -> Result<pem::Pem> {
+    openssl_pkcs8(pem, &["-topk8", "-nocrypt"]).context("converting private key to PKCS#8")
+}
+
+fn openssl_pkcs8(pem: &pem::Pem, args: &[&str]) -> Result<pem::Pem> {
+    let mut command = Command::new("openssl")
+        .arg("pkcs8")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    command
+        .stdin
+        .take()
+        .context("failed to take openssl stdin pipe")?
+        .write_all(Zeroizing::new(pem::encode(pem)).as_bytes())?;
+
+    let output = command.wait_with_output()?;
+    ensure!(
+        output.status.success(),
+        "openssl pkcs8 failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    Ok(pem::parse(Zeroizing::new(output.stdout).as_slice())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_crypto::{
+        crypto_objects::{self, CryptoObject},
+        keys::PrivateKey,
+    };
+
+    fn openssl_pem(args: &[&str]) -> pem::Pem {
+        let output = Command::new("openssl").args(args).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        pem::parse(output.stdout).unwrap()
+    }
+
+    fn private_key(pem: &pem::Pem, policy: &PrivateKeyPolicy) -> PrivateKey {
+        let pem = match pem.tag() {
+            ENCRYPTED_PKCS8_TAG => policy.decrypt(pem).unwrap().unwrap(),
+            _ => pem.clone(),
+        };
+        match crypto_objects::process_single_pem(&pem, &PrivateKeyPolicy::default()).unwrap() {
+            Some(CryptoObject::PrivateKey(private_key, _)) => *private_key,
+            _ => panic!("expected a private key"),
+        }
+    }
+
+    #[test]
+    fn test_reencode_like() {
+        let dir = tempfile::tempdir().unwrap();
+        let passphrase_file = dir.path().join("passphrase");
+        std::fs::write(&passphrase_file, "hunter2\n").unwrap();
+        let preserve = PrivateKeyPolicy {
+            format: PrivateKeyFormat::Preserve,
+            passphrase_file: Some(passphrase_file.clone()),
+        };
+        let force_pkcs8 = PrivateKeyPolicy {
+            format: PrivateKeyFormat::Pkcs8,
+            ..preserve.clone()
+        };
+
+        let rsa_pkcs8 = openssl_pem(&["genpkey", "-algorithm", "RSA", "-pkeyopt", "rsa_keygen_bits:2048"]);
+        let ec_pkcs8 = openssl_pem(&["genpkey", "-algorithm", "EC", "-pkeyopt", "ec_paramgen_curve:P-256"]);
+        let encrypted = preserve.encrypt(&rsa_pkcs8).unwrap();
+        assert_eq!(encrypted.tag(), ENCRYPTED_PKCS8_TAG);
+        assert_eq!(private_key(&encrypted, &preserve), private_key(&rsa_pkcs8, &preserve));
+
+        for original in [&rsa_pkcs8, &ec_pkcs8] {
+            // Found as PKCS#8, held as PKCS#1 / SEC1, written back as PKCS#8
+            let new_pem = private_key(original, &preserve).pem().unwrap();
+            assert_ne!(new_pem.tag(), PKCS8_TAG);
+            let reencoded = preserve.reencode_like(original, &new_pem).unwrap();
+            assert_eq!(reencoded.tag(), PKCS8_TAG);
+            assert_eq!(private_key(&reencoded, &preserve), private_key(original, &preserve));
+
+            assert_eq!(preserve.reencode_like(&new_pem, &new_pem).unwrap(), new_pem);
+            assert_eq!(force_pkcs8.reencode_like(&new_pem, &new_pem).unwrap().tag(), PKCS8_TAG);
+        }
+
+        // Unchanged encrypted keys are left alone, changed ones are encrypted with the same passphrase
+        let unchanged = private_key(&encrypted, &preserve).pem().unwrap();
+        assert_eq!(preserve.reencode_like(&encrypted, &unchanged).unwrap(), encrypted);
+        let changed = private_key(&ec_pkcs8, &preserve).pem().unwrap();
+        let reencrypted = preserve.reencode_like(&encrypted, &changed).unwrap();
+        assert_eq!(reencrypted.tag(), ENCRYPTED_PKCS8_TAG);
+        assert_eq!(private_key(&reencrypted, &preserve), private_key(&ec_pkcs8, &preserve));
+
+        std::fs::write(&passphrase_file, "wrong\n").unwrap();
+        assert!(preserve.decrypt(&encrypted).is_err());
+        assert!(PrivateKeyPolicy::default().decrypt(&encrypted).unwrap().is_none());
+    }
+}
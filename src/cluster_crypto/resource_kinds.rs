@@ -0,0 +1,168 @@
+use anyhow::{ensure, Result};
+use std::str::FromStr;
+
+/// The resource kinds scanned by default, any of which can be skipped
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum BuiltinResourceKind {
+    Secret,
+    ConfigMap,
+    ValidatingWebhookConfiguration,
+    MutatingWebhookConfiguration,
+    ApiService,
+    CustomResourceDefinition,
+    MachineConfig,
+}
+
+impl BuiltinResourceKind {
+    /// The etcd key prefix (under /kubernetes.io/) of the resources of this kind
+    pub(crate) fn etcd_resource(&self) -> &'static str {
+        match self {
+            BuiltinResourceKind::Secret => "secrets",
+            BuiltinResourceKind::ConfigMap => "configmaps",
+            BuiltinResourceKind::ValidatingWebhookConfiguration => "validatingwebhookconfigurations",
+            BuiltinResourceKind::MutatingWebhookConfiguration => "mutatingwebhookconfigurations",
+            BuiltinResourceKind::ApiService => "apiregistration.k8s.io/apiservices",
+            BuiltinResourceKind::CustomResourceDefinition => "apiextensions.k8s.io/customresourcedefinitions",
+            BuiltinResourceKind::MachineConfig => "machineconfiguration.openshift.io/machineconfigs",
+        }
+    }
+}
+
+/// An additional (custom) resource kind to scan, written as GROUP/VERSION/KIND, optionally
+/// followed by =PLURAL when the plural isn't simply the lowercase kind with an s appended, e.g.
+/// camel.apache.org/v1/KameletBinding or networking.istio.io/v1beta1/DestinationRule
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct CustomResourceKind {
+    pub(crate) group: String,
+    pub(crate) version: String,
+    pub(crate) kind: String,
+    pub(crate) plural: String,
+}
+
+impl CustomResourceKind {
+    /// The etcd key prefix (under /kubernetes.io/) of the resources of this kind, custom resources
+    /// are always stored under their group
+    pub(crate) fn etcd_resource(&self) -> String {
+        format!("{}/{}", self.group, self.plural)
+    }
+
+    fn matches(&self, apiversion: &str, kind: &str) -> bool {
+        kind == self.kind && apiversion.split_once('/') == Some((self.group.as_str(), self.version.as_str()))
+    }
+}
+
+impl FromStr for CustomResourceKind {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (gvk, plural) = match value.split_once('=') {
+            Some((gvk, plural)) => (gvk, Some(plural)),
+            None => (value, None),
+        };
+
+        let mut split = gvk.split('/');
+        let (Some(group), Some(version), Some(kind), None) = (split.next(), split.next(), split.next(), split.next()) else {
+            anyhow::bail!("expected GROUP/VERSION/KIND[=PLURAL], e.g. camel.apache.org/v1/KameletBinding");
+        };
+
+        // Core kinds (e.g. v1/Secret) have no group, and aren't stored like custom resources
+        ensure!(
+            [group, version, kind].iter().all(|part| !part.is_empty()),
+            "empty group, version or kind in {:?}",
+            value
+        );
+
+        Ok(Self {
+            group: group.to_string(),
+            version: version.to_string(),
+            kind: kind.to_string(),
+            plural: plural.map_or_else(|| format!("{}s", kind.to_lowercase()), str::to_string),
+        })
+    }
+}
+
+/// Which resource kinds are scanned in etcd, see --skip-resource-kind and --scan-custom-resource
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ResourceKindPolicy {
+    pub(crate) skipped: Vec<BuiltinResourceKind>,
+    pub(crate) custom: Vec<CustomResourceKind>,
+}
+
+impl ResourceKindPolicy {
+    pub(crate) fn is_skipped(&self, builtin_resource_kind: BuiltinResourceKind) -> bool {
+        self.skipped.contains(&builtin_resource_kind)
+    }
+
+    /// The configured custom resource kind of a resource with the given apiVersion and kind, if any
+    pub(crate) fn find_custom(&self, apiversion: &str, kind: &str) -> Option<&CustomResourceKind> {
+        self.custom
+            .iter()
+            .find(|custom_resource_kind| custom_resource_kind.matches(apiversion, kind))
+    }
+
+    /// Whether the etcd key is that of a configured custom resource. Those are stored as plain JSON
+    /// rather than protobuf, so they don't go through ouger
+    pub(crate) fn is_custom_etcd_key(&self, key: &str) -> bool {
+        self.custom
+            .iter()
+            .any(|custom_resource_kind| key.starts_with(&format!("/kubernetes.io/{}/", custom_resource_kind.etcd_resource())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_crypto::locations::K8sResourceLocation;
+
+    #[test]
+    fn test_parse_custom_resource_kind() {
+        let kamelet_binding: CustomResourceKind = "camel.apache.org/v1/KameletBinding".parse().unwrap();
+        assert_eq!(kamelet_binding.etcd_resource(), "camel.apache.org/kameletbindings");
+        assert!(kamelet_binding.matches("camel.apache.org/v1", "KameletBinding"));
+        assert!(!kamelet_binding.matches("camel.apache.org/v1alpha1", "KameletBinding"));
+        assert!(!kamelet_binding.matches("camel.apache.org/v1", "Kamelet"));
+
+        let mesh: CustomResourceKind = "maistra.io/v2/ServiceMeshControlPlane=servicemeshcontrolplanes".parse().unwrap();
+        assert_eq!(mesh.etcd_resource(), "maistra.io/servicemeshcontrolplanes");
+
+        let policy: CustomResourceKind = "example.com/v1/Policy=policies".parse().unwrap();
+        assert_eq!(policy.plural, "policies");
+
+        assert!("v1/Secret".parse::<CustomResourceKind>().is_err());
+        assert!("/v1/Secret".parse::<CustomResourceKind>().is_err());
+        assert!("a/b/c/d".parse::<CustomResourceKind>().is_err());
+    }
+
+    #[test]
+    fn test_resource_kind_policy() {
+        let default = ResourceKindPolicy::default();
+        assert!(!default.is_skipped(BuiltinResourceKind::ConfigMap));
+        assert!(default.find_custom("camel.apache.org/v1", "KameletBinding").is_none());
+        assert!(!default.is_custom_etcd_key("/kubernetes.io/camel.apache.org/kameletbindings/ns/binding"));
+
+        let policy = ResourceKindPolicy {
+            skipped: vec![BuiltinResourceKind::ConfigMap],
+            custom: vec!["camel.apache.org/v1/KameletBinding".parse().unwrap()],
+        };
+        assert!(policy.is_skipped(BuiltinResourceKind::ConfigMap));
+        assert!(!policy.is_skipped(BuiltinResourceKind::Secret));
+        assert_eq!(
+            policy
+                .find_custom("camel.apache.org/v1", "KameletBinding")
+                .map(|kind| kind.plural.as_str()),
+            Some("kameletbindings")
+        );
+        assert!(policy.is_custom_etcd_key("/kubernetes.io/camel.apache.org/kameletbindings/ns/binding"));
+        assert!(!policy.is_custom_etcd_key("/kubernetes.io/secrets/ns/binding"));
+
+        let location = K8sResourceLocation::new(Some("ns"), "KameletBinding", "binding", "camel.apache.org/v1");
+        assert_eq!(
+            location.clone().with_resource_kinds(&policy).as_etcd_key(),
+            "/kubernetes.io/camel.apache.org/kameletbindings/ns/binding"
+        );
+        assert_ne!(
+            location.with_resource_kinds(&default).as_etcd_key(),
+            "/kubernetes.io/camel.apache.org/kameletbindings/ns/binding"
+        );
+    }
+}
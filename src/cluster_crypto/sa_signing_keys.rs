@@ -0,0 +1,177 @@
+use super::{
+    distributed_private_key::DistributedPrivateKey,
+    locations::{Location, Locations},
+};
+use anyhow::{bail, Result};
+use std::{cell::RefCell, rc::Rc};
+
+/// The secrets holding kube-controller-manager's service account token signing key, which signs
+/// the legacy service account tokens
+const KUBE_CONTROLLER_MANAGER_SECRETS: [&str; 1] = ["service-account-private-key"];
+
+/// The secrets holding the apiserver's bound service account token signing key, along with the
+/// operator's copy of the next one
+const APISERVER_SECRETS: [&str; 2] = ["bound-service-account-signing-key", "next-bound-service-account-signing-key"];
+
+/// The configmaps holding the public keys the apiserver verifies service account tokens with
+/// (--service-account-key-file), both of kube-controller-manager's signing keys and of its own
+const VERIFICATION_CONFIGMAPS: [&str; 2] = ["sa-token-signing-certs", "bound-sa-token-signing-certs"];
+
+/// The service account token signing keys. Some clusters keep kube-controller-manager's apart
+/// from the apiserver's, each of them verified through its public key in the apiserver's list
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SaSigningKey {
+    KubeControllerManager,
+    Apiserver,
+}
+
+impl SaSigningKey {
+    /// Which of the signing keys a standalone private key is, by where it was found
+    pub(crate) fn of(locations: &Locations) -> Option<Self> {
+        if located_in(locations, "Secret", &KUBE_CONTROLLER_MANAGER_SECRETS) {
+            Some(SaSigningKey::KubeControllerManager)
+        } else if located_in(locations, "Secret", &APISERVER_SECRETS) {
+            Some(SaSigningKey::Apiserver)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for SaSigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaSigningKey::KubeControllerManager => write!(f, "kube-controller-manager's service account signing key"),
+            SaSigningKey::Apiserver => write!(f, "the apiserver's bound service account signing key"),
+        }
+    }
+}
+
+/// Whether the service account token signing keys are regenerated together or only one of them.
+/// Other standalone private keys are regenerated either way
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum SaSigningKeyRegeneration {
+    /// Regenerate both signing keys
+    #[default]
+    Lockstep,
+    /// Only regenerate kube-controller-manager's signing key, leaving the apiserver's as it is
+    KubeControllerManager,
+    /// Only regenerate the apiserver's signing key, leaving kube-controller-manager's as it is
+    Apiserver,
+}
+
+impl SaSigningKeyRegeneration {
+    pub(crate) fn regenerates(&self, private_key: &DistributedPrivateKey) -> bool {
+        match (self, SaSigningKey::of(&private_key.locations)) {
+            (_, None) | (SaSigningKeyRegeneration::Lockstep, _) => true,
+            (SaSigningKeyRegeneration::KubeControllerManager, Some(signing_key)) => signing_key == SaSigningKey::KubeControllerManager,
+            (SaSigningKeyRegeneration::Apiserver, Some(signing_key)) => signing_key == SaSigningKey::Apiserver,
+        }
+    }
+}
+
+/// Tokens signed by a signing key whose public key isn't among the ones the apiserver verifies
+/// tokens with would be rejected, and regenerating the keys (together or not) only keeps the list
+/// covering them if it did to begin with. Only checked when the verification list was found at
+/// all, otherwise there's nothing to check against
+pub(crate) fn check_verification_keys<'a>(
+    private_keys: impl Iterator<Item = &'a Rc<RefCell<DistributedPrivateKey>>> + Clone,
+) -> Result<()> {
+    let verification_list_found = private_keys.clone().any(|private_key| {
+        (**private_key)
+            .borrow()
+            .associated_distributed_public_key
+            .as_ref()
+            .is_some_and(|public_key| located_in(&(**public_key).borrow().locations, "ConfigMap", &VERIFICATION_CONFIGMAPS))
+    });
+    if !verification_list_found {
+        return Ok(());
+    }
+
+    for private_key in private_keys {
+        let private_key = (**private_key).borrow();
+        let Some(signing_key) = SaSigningKey::of(&private_key.locations) else {
+            continue;
+        };
+
+        let verified = private_key
+            .associated_distributed_public_key
+            .as_ref()
+            .is_some_and(|public_key| located_in(&(**public_key).borrow().locations, "ConfigMap", &VERIFICATION_CONFIGMAPS));
+        if !verified {
+            bail!(
+                "the public key of {} at {} isn't among the keys the apiserver verifies service account tokens with",
+                signing_key,
+                private_key.locations
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// In a resource of the given kind and name, or in a static pod resources copy of one (e.g.
+/// .../secrets/service-account-private-key/service-account.key)
+fn located_in(locations: &Locations, kind: &str, names: &[&str]) -> bool {
+    let dir = match kind {
+        "Secret" => "secrets",
+        _ => "configmaps",
+    };
+
+    locations.0.iter().any(|location| match location {
+        Location::K8s(k8s_location) => {
+            k8s_location.resource_location.kind == kind && names.contains(&k8s_location.resource_location.name.as_str())
+        }
+        Location::Filesystem(file_location) => names.iter().any(|name| file_location.path.contains(&format!("/{}/{}/", dir, name))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_crypto::locations::{
+        FieldEncoding, FileContentLocation, FileLocation, K8sResourceLocation, LocationValueType, YamlLocation,
+    };
+    use std::collections::HashSet;
+
+    fn secret(name: &str) -> Locations {
+        Locations(HashSet::from([Location::k8s_yaml(
+            &K8sResourceLocation::new(Some("openshift-kube-controller-manager"), "Secret", name, "v1"),
+            &YamlLocation::new("/data", "service-account.key", FieldEncoding::Base64),
+        )]))
+    }
+
+    fn file(path: &str) -> Locations {
+        Locations(HashSet::from([Location::Filesystem(FileLocation {
+            path: path.to_string(),
+            content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+        })]))
+    }
+
+    #[test]
+    fn test_sa_signing_key() {
+        assert_eq!(
+            SaSigningKey::of(&secret("service-account-private-key")),
+            Some(SaSigningKey::KubeControllerManager)
+        );
+        assert_eq!(
+            SaSigningKey::of(&file(
+                "/etc/kubernetes/static-pod-resources/kube-apiserver-pod-5/secrets/bound-service-account-signing-key/service-account.key"
+            )),
+            Some(SaSigningKey::Apiserver)
+        );
+        assert_eq!(SaSigningKey::of(&secret("node-bootstrapper-token")), None);
+        // Public keys in the verification list aren't signing keys
+        assert_eq!(
+            SaSigningKey::of(&file(
+                "/etc/kubernetes/static-pod-resources/kube-apiserver-pod-5/configmaps/sa-token-signing-certs/service-account-001.pub"
+            )),
+            None
+        );
+        assert!(located_in(
+            &file("/etc/kubernetes/static-pod-resources/kube-apiserver-pod-5/configmaps/sa-token-signing-certs/service-account-001.pub"),
+            "ConfigMap",
+            &VERIFICATION_CONFIGMAPS
+        ));
+    }
+}
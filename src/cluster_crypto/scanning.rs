@@ -2,10 +2,14 @@ use self::crypto_objects::DiscoveredCryptoObect;
 use super::{
     crypto_objects,
     locations::{FileContentLocation, FileLocation, K8sResourceLocation, Location, LocationValueType},
+    path_references,
+    resource_kinds::BuiltinResourceKind,
+    CryptoPolicies,
 };
 use crate::{
+    capabilities::{Capabilities, Capability},
     cluster_crypto::{crypto_objects::process_yaml_value, yaml_crawl},
-    file_utils::{self, read_file_to_string},
+    concurrency, file_utils,
     k8s_etcd::InMemoryK8sEtcd,
 };
 use anyhow::{bail, Context, Result};
@@ -20,10 +24,21 @@ use std::{
 pub(crate) async fn crypto_scan(
     in_memory_etcd_client: Arc<InMemoryK8sEtcd>,
     static_dirs: Vec<PathBuf>,
+    capabilities: Capabilities,
+    policies: CryptoPolicies,
 ) -> Result<Vec<DiscoveredCryptoObect>> {
+    let policies = Arc::new(policies);
+
     // Launch separate paralllel long running background tasks
-    let discovered_etcd_objects = tokio::spawn(async move { scan_etcd_resources(in_memory_etcd_client).await.context("etcd resources") });
-    let discovered_filesystem_objects = scan_static_dirs(static_dirs);
+    let discovered_etcd_objects = tokio::spawn({
+        let policies = Arc::clone(&policies);
+        async move {
+            scan_etcd_resources(in_memory_etcd_client, &capabilities, policies)
+                .await
+                .context("etcd resources")
+        }
+    });
+    let discovered_filesystem_objects = scan_static_dirs(static_dirs, policies);
 
     // ... and join them
     let discovered_crypto_objects = discovered_etcd_objects.await??;
@@ -36,57 +51,72 @@ pub(crate) async fn crypto_scan(
         .collect::<Vec<_>>())
 }
 
-fn scan_static_dirs(static_dirs: Vec<PathBuf>) -> tokio::task::JoinHandle<std::result::Result<Vec<DiscoveredCryptoObect>, anyhow::Error>> {
+fn scan_static_dirs(
+    static_dirs: Vec<PathBuf>,
+    policies: Arc<CryptoPolicies>,
+) -> tokio::task::JoinHandle<std::result::Result<Vec<DiscoveredCryptoObect>, anyhow::Error>> {
     tokio::spawn(async move {
-        anyhow::Ok(
-            join_all(
-                static_dirs
-                    .into_iter()
-                    .map(|static_dir| {
-                        tokio::spawn(async move {
-                            scan_filesystem_directory(&static_dir)
-                                .await
-                                .with_context(|| format!("static dir {:?}", static_dir))
-                        })
-                    })
-                    .collect::<Vec<_>>(),
-            )
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()?
+        let file_paths = static_dirs
+            .iter()
+            .map(|static_dir| filesystem_scan_candidates(static_dir).with_context(|| format!("static dir {:?}", static_dir)))
+            .collect::<Result<Vec<_>>>()?
             .into_iter()
             .flatten()
-            .collect::<Vec<_>>(),
-        )
+            .collect::<Vec<_>>();
+
+        // Files referenced by path from configs, which the globs above might have missed
+        let referenced_file_paths = path_references::unscanned_referenced_files(&static_dirs, &file_paths)
+            .await
+            .context("following config path references")?;
+
+        scan_files(file_paths.into_iter().chain(referenced_file_paths).collect(), policies).await
     })
 }
 
 /// Read all relevant resources from etcd, scan them for cryptographic objects and record them
 /// in the appropriate data structures.
-pub(crate) async fn scan_etcd_resources(etcd_client: Arc<InMemoryK8sEtcd>) -> Result<Vec<DiscoveredCryptoObect>> {
-    let key_lists = {
-        let etcd_client = &etcd_client;
-        [
-            &(etcd_client.list_keys("secrets").await.context("listing secrets")?),
-            &(etcd_client.list_keys("configmaps").await.context("listing configmaps")?),
-            &(etcd_client
-                .list_keys("validatingwebhookconfigurations")
-                .await
-                .context("listing validatingwebhookconfigurations")?),
-            &(etcd_client
-                .list_keys("apiregistration.k8s.io/apiservices")
-                .await
-                .context("listing apiservices")?),
-            &(etcd_client
-                .list_keys("machineconfiguration.openshift.io/machineconfigs")
-                .await
-                .context("listing machineconfigs")?),
-        ]
-    };
+pub(crate) async fn scan_etcd_resources(
+    etcd_client: Arc<InMemoryK8sEtcd>,
+    capabilities: &Capabilities,
+    policies: Arc<CryptoPolicies>,
+) -> Result<Vec<DiscoveredCryptoObect>> {
+    let mut etcd_resources = vec![];
+    for builtin_resource_kind in [
+        BuiltinResourceKind::Secret,
+        BuiltinResourceKind::ConfigMap,
+        BuiltinResourceKind::ValidatingWebhookConfiguration,
+        BuiltinResourceKind::MutatingWebhookConfiguration,
+        BuiltinResourceKind::ApiService,
+        BuiltinResourceKind::CustomResourceDefinition,
+        BuiltinResourceKind::MachineConfig,
+    ] {
+        if policies.scan.resource_kinds.is_skipped(builtin_resource_kind)
+            || (builtin_resource_kind == BuiltinResourceKind::MachineConfig
+                && !capabilities.allows(Capability::MachineConfig, "scanning machineconfigs"))
+        {
+            continue;
+        }
 
-    let all_keys = key_lists.into_iter().flatten().collect::<Vec<_>>();
+        etcd_resources.push(builtin_resource_kind.etcd_resource().to_string());
+    }
+    etcd_resources.extend(
+        policies
+            .scan
+            .resource_kinds
+            .custom
+            .iter()
+            .map(|custom_resource_kind| custom_resource_kind.etcd_resource()),
+    );
+
+    let mut all_keys = vec![];
+    for etcd_resource in &etcd_resources {
+        all_keys.extend(
+            etcd_client
+                .list_keys(etcd_resource)
+                .await
+                .with_context(|| format!("listing {}", etcd_resource))?,
+        );
+    }
 
     if all_keys.is_empty() {
         bail!("No keys found in etcd - is the etcd database empty/corrupt?")
@@ -98,14 +128,19 @@ pub(crate) async fn scan_etcd_resources(etcd_client: Arc<InMemoryK8sEtcd>) -> Re
             .map(|key| {
                 let key = key.clone();
                 let etcd_client = Arc::clone(&etcd_client);
-                tokio::spawn(async move {
+                let policies = Arc::clone(&policies);
+                concurrency::spawn(async move {
                     let etcd_result = etcd_client
                         .get(key.clone())
                         .await
                         .with_context(|| format!("getting key {:?}", key))?;
                     let value: Value = serde_yaml::from_slice(etcd_result.value.as_slice())
                         .with_context(|| format!("deserializing value of key {:?}", key,))?;
-                    let k8s_resource_location = K8sResourceLocation::try_from(&value)?;
+                    let k8s_resource_location = K8sResourceLocation::try_from(&value)?.with_resource_kinds(&policies.scan.resource_kinds);
+
+                    if !etcd_client.namespace_filter().allows(k8s_resource_location.namespace.as_deref()) {
+                        return anyhow::Ok(vec![]);
+                    }
 
                     // Ensure our as_etcd_key function knows to generates the correct key, while we
                     // still have the key to compare to. TODO: Find a more robust way to generate
@@ -113,10 +148,10 @@ pub(crate) async fn scan_etcd_resources(etcd_client: Arc<InMemoryK8sEtcd>) -> Re
                     // complicated. Couldn't find documentation on how it should be done properly
                     assert_eq!(etcd_result.key, k8s_resource_location.as_etcd_key());
 
-                    let decoded_yaml_values = yaml_crawl::crawl_yaml(value)
+                    let decoded_yaml_values = yaml_crawl::crawl_yaml(value, &policies.scan)
                         .with_context(|| format!("crawling yaml of key {:?}", key))?
                         .iter()
-                        .map(|yaml| yaml_crawl::decode_yaml_value(yaml).context("decoding yaml"))
+                        .map(|yaml| yaml_crawl::decode_yaml_value(yaml, &policies.scan).context("decoding yaml"))
                         .collect::<Result<Vec<_>>>()?;
 
                     anyhow::Ok(
@@ -124,7 +159,7 @@ pub(crate) async fn scan_etcd_resources(etcd_client: Arc<InMemoryK8sEtcd>) -> Re
                             .into_iter()
                             .flatten()
                             .map(|(yaml_location, yaml_value)| {
-                                process_yaml_value(yaml_value, &Location::k8s_yaml(&k8s_resource_location, &yaml_location))
+                                process_yaml_value(yaml_value, &Location::k8s_yaml(&k8s_resource_location, &yaml_location), &policies)
                                     .with_context(|| format!("processing yaml value of key {:?} at location {:?}", key, yaml_location))
                             })
                             .collect::<Result<Vec<_>>>()?
@@ -146,47 +181,39 @@ pub(crate) async fn scan_etcd_resources(etcd_client: Arc<InMemoryK8sEtcd>) -> Re
     .collect::<Vec<_>>())
 }
 
-/// Recursively scans a directoy for files which exclusively contain a PEM bundle (as opposed
-/// to being embedded in a YAML file) and records them in the appropriate data structures.
-pub(crate) async fn scan_filesystem_directory(dir: &Path) -> Result<Vec<DiscoveredCryptoObect>> {
+/// All files in a directory (recursively) which might contain crypto objects, either as a PEM
+/// bundle on their own (as opposed to being embedded in a YAML file) or as a kubeconfig
+fn filesystem_scan_candidates(dir: &Path) -> Result<Vec<PathBuf>> {
+    Ok(file_utils::globvec(dir, "**/*.pem")?
+        .into_iter()
+        .chain(file_utils::globvec(dir, "**/*.crt")?)
+        .chain(file_utils::globvec(dir, "**/*.key")?)
+        .chain(file_utils::globvec(dir, "**/*.pub")?)
+        .chain(file_utils::globvec(dir, "**/*.der")?)
+        .chain(file_utils::globvec(dir, "**/*.crl")?)
+        // OVN / Open vSwitch name their certs and CA certs like this, e.g. vswitchd.cacert
+        .chain(file_utils::globvec(dir, "**/*.cert")?)
+        .chain(file_utils::globvec(dir, "**/*.cacert")?)
+        .chain(file_utils::globvec(dir, "**/authorized_keys")?)
+        .chain(file_utils::globvec(dir, "**/id_rsa")?)
+        // Also scan for the .mcdorig versions of the above files, which are sometimes created
+        // by machine-config-daemon
+        .chain(file_utils::globvec(dir, "**/*.crt.mcdorig")?)
+        .chain(file_utils::globvec(dir, "**/*.key.mcdorig")?)
+        .chain(file_utils::globvec(dir, "**/*.pub.mcdorig")?)
+        .chain(file_utils::globvec(dir, "**/currentconfig")?)
+        .chain(file_utils::globvec(dir, "**/*kubeconfig")?)
+        .chain(file_utils::globvec(dir, "**/kubeconfig")?)
+        .chain(file_utils::globvec(dir, "**/kubeConfig")?)
+        .collect())
+}
+
+/// Scans files for crypto objects and records them in the appropriate data structures.
+async fn scan_files(file_paths: Vec<PathBuf>, policies: Arc<CryptoPolicies>) -> Result<Vec<DiscoveredCryptoObect>> {
     Ok(join_all(
-        file_utils::globvec(dir, "**/*.pem")?
+        file_paths
             .into_iter()
-            .chain(file_utils::globvec(dir, "**/*.crt")?.into_iter())
-            .chain(file_utils::globvec(dir, "**/*.key")?.into_iter())
-            .chain(file_utils::globvec(dir, "**/*.pub")?.into_iter())
-            // Also scan for the .mcdorig versions of the above files, which are sometimes created
-            // by machine-config-daemon
-            .chain(file_utils::globvec(dir, "**/*.crt.mcdorig")?.into_iter())
-            .chain(file_utils::globvec(dir, "**/*.key.mcdorig")?.into_iter())
-            .chain(file_utils::globvec(dir, "**/*.pub.mcdorig")?.into_iter())
-            .chain(file_utils::globvec(dir, "**/currentconfig")?.into_iter())
-            .chain(file_utils::globvec(dir, "**/*kubeconfig")?.into_iter())
-            .chain(file_utils::globvec(dir, "**/kubeconfig")?.into_iter())
-            .chain(file_utils::globvec(dir, "**/kubeConfig")?.into_iter())
-            .map(|file_path| {
-                tokio::spawn(async move {
-                    let contents = read_file_to_string(file_path.clone()).await?;
-
-                    anyhow::Ok(
-                        if String::from_utf8(file_path.file_name().context("non-file")?.as_bytes().to_vec())?.ends_with("kubeconfig")
-                            || String::from_utf8(file_path.file_name().context("non-file")?.as_bytes().to_vec())? == "currentconfig"
-                        {
-                            process_static_resource_yaml(contents, &file_path)
-                                .with_context(|| format!("processing static resource yaml of file {:?}", file_path))?
-                        } else {
-                            crypto_objects::process_pem_bundle(
-                                &contents,
-                                &Location::Filesystem(FileLocation {
-                                    path: file_path.to_string_lossy().to_string(),
-                                    content_location: FileContentLocation::Raw(LocationValueType::Unknown),
-                                }),
-                            )
-                            .with_context(|| format!("processing pem bundle of file {:?}", file_path))?
-                        },
-                    )
-                })
-            })
+            .map(|file_path| concurrency::spawn(scan_file(file_path, Arc::clone(&policies))))
             .collect::<Vec<_>>(),
     )
     .await
@@ -199,11 +226,63 @@ pub(crate) async fn scan_filesystem_directory(dir: &Path) -> Result<Vec<Discover
     .collect::<Vec<_>>())
 }
 
-pub(crate) fn process_static_resource_yaml(contents: String, yaml_path: &PathBuf) -> Result<Vec<DiscoveredCryptoObect>> {
+async fn scan_file(file_path: PathBuf, policies: Arc<CryptoPolicies>) -> Result<Vec<DiscoveredCryptoObect>> {
+    let contents = file_utils::read_file(&file_path).await?;
+
+    anyhow::Ok(
+        if String::from_utf8(file_path.file_name().context("non-file")?.as_bytes().to_vec())?.ends_with("kubeconfig")
+            || String::from_utf8(file_path.file_name().context("non-file")?.as_bytes().to_vec())? == "currentconfig"
+        {
+            process_static_resource_yaml(String::from_utf8(contents)?, &file_path, &policies)
+                .with_context(|| format!("processing static resource yaml of file {:?}", file_path))?
+        } else {
+            let is_der = file_path.extension().is_some_and(|extension| extension == "der");
+            match String::from_utf8(contents) {
+                Ok(contents) if !is_der => {
+                    let location = Location::Filesystem(FileLocation {
+                        path: file_path.to_string_lossy().to_string(),
+                        content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+                    });
+
+                    let pem_bundle_objects = crypto_objects::process_pem_bundle(&contents, &location, &policies.private_key)
+                        .with_context(|| format!("processing pem bundle of file {:?}", file_path))?;
+
+                    // Files without any PEMs might be lists of SSH public keys (.pub, authorized_keys)
+                    if pem_bundle_objects.is_empty() {
+                        crypto_objects::process_ssh_public_keys(&contents, &location)
+                            .with_context(|| format!("processing ssh public keys of file {:?}", file_path))?
+                    } else {
+                        pem_bundle_objects
+                    }
+                }
+                // Files which are not valid UTF-8 (or explicitly named .der) can't
+                // be PEM, so they might be DER encoded
+                contents => crypto_objects::process_der(
+                    &match contents {
+                        Ok(contents) => contents.into_bytes(),
+                        Err(err) => err.into_bytes(),
+                    },
+                    &Location::Filesystem(FileLocation {
+                        path: file_path.to_string_lossy().to_string(),
+                        content_location: FileContentLocation::Der,
+                    }),
+                    &policies.private_key,
+                )
+                .with_context(|| format!("processing der of file {:?}", file_path))?,
+            }
+        },
+    )
+}
+
+pub(crate) fn process_static_resource_yaml(
+    contents: String,
+    yaml_path: &Path,
+    policies: &CryptoPolicies,
+) -> Result<Vec<DiscoveredCryptoObect>> {
     Ok(
-        yaml_crawl::crawl_yaml((&serde_yaml::from_str::<Value>(contents.as_str())?).clone())?
+        yaml_crawl::crawl_yaml(serde_yaml::from_str::<Value>(contents.as_str())?.clone(), &policies.scan)?
             .iter()
-            .map(yaml_crawl::decode_yaml_value)
+            .map(|yaml_value| yaml_crawl::decode_yaml_value(yaml_value, &policies.scan))
             .collect::<Result<Vec<_>>>()?
             .into_iter()
             .map(|opt| opt.context("failed to decode yaml"))
@@ -212,7 +291,8 @@ pub(crate) fn process_static_resource_yaml(contents: String, yaml_path: &PathBuf
             .map(|(yaml_location, decoded_yaml_value)| {
                 process_yaml_value(
                     decoded_yaml_value,
-                    &Location::file_yaml(&yaml_path.to_string_lossy().to_string(), &yaml_location),
+                    &Location::file_yaml(yaml_path.to_string_lossy().as_ref(), &yaml_location),
+                    policies,
                 )
             })
             .collect::<Result<Vec<_>>>()?
@@ -221,3 +301,90 @@ pub(crate) fn process_static_resource_yaml(contents: String, yaml_path: &PathBuf
             .collect::<Vec<_>>(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster_crypto::{ClusterCryptoObjects, CryptoPolicies},
+        cnsanreplace::CnSanReplaceRules,
+        rsa_key_pool::RsaKeyPool,
+        test_fixtures::CertFixture,
+    };
+
+    #[tokio::test]
+    async fn test_scan_openvswitch_pki() {
+        // Laid out like /etc/openvswitch and /var/lib/openvswitch/pki
+        let root = tempfile::tempdir().unwrap();
+        let (etc, pki) = (root.path().join("etc/openvswitch"), root.path().join("var/lib/openvswitch/pki"));
+        std::fs::create_dir_all(&etc).unwrap();
+        std::fs::create_dir_all(pki.join("switchca/private")).unwrap();
+
+        let ca = CertFixture::ca("ovs-switchca");
+        let client = CertFixture::leaf("ovsclient", &ca);
+        std::fs::write(pki.join("switchca/cacert.pem"), &ca.cert_pem).unwrap();
+        std::fs::write(pki.join("switchca/private/cakey.pem"), &ca.key_pem).unwrap();
+        std::fs::write(etc.join("vswitchd.cacert"), &ca.cert_pem).unwrap();
+        std::fs::write(etc.join("ovsclient-cert.pem"), &client.cert_pem).unwrap();
+        std::fs::write(etc.join("ovsclient-privkey.pem"), &client.key_pem).unwrap();
+
+        let discovered = scan_static_dirs(vec![etc.clone(), pki.clone()], Arc::default())
+            .await
+            .unwrap()
+            .unwrap();
+        for path in [
+            pki.join("switchca/cacert.pem"),
+            pki.join("switchca/private/cakey.pem"),
+            etc.join("vswitchd.cacert"),
+            etc.join("ovsclient-cert.pem"),
+            etc.join("ovsclient-privkey.pem"),
+        ] {
+            assert!(
+                discovered.iter().any(|discovered| matches!(
+                    &discovered.location,
+                    Location::Filesystem(file_location) if Path::new(&file_location.path) == path
+                )),
+                "{:?} not found",
+                path
+            );
+        }
+
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        cluster_crypto.register_discovered_crypto_objects(discovered);
+        cluster_crypto.pair_certs_and_keys().unwrap();
+        cluster_crypto.fill_cert_key_signers().unwrap();
+        cluster_crypto.fill_signees().unwrap();
+        cluster_crypto
+            .regenerate_crypto(
+                RsaKeyPool::fill(&[], Default::default()).await.unwrap(),
+                CnSanReplaceRules::try_from(vec![]).unwrap(),
+            )
+            .unwrap();
+        for cert_key_pair in &cluster_crypto.cert_key_pairs {
+            let cert_key_pair = (**cert_key_pair).borrow().clone();
+            let locations = (*cert_key_pair.distributed_cert).borrow().locations.0.clone();
+            for location in locations {
+                let Location::Filesystem(file_location) = location else {
+                    unreachable!();
+                };
+                cert_key_pair
+                    .commit_filesystem_cert(&file_location, &CryptoPolicies::default())
+                    .await
+                    .unwrap();
+            }
+        }
+
+        // Both copies of the CA cert were rewritten to the same new CA, which signed the new
+        // client cert
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_ne!(read(etc.join("vswitchd.cacert")), ca.cert_pem);
+        assert_ne!(read(etc.join("ovsclient-cert.pem")), client.cert_pem);
+        assert_eq!(read(etc.join("vswitchd.cacert")), read(pki.join("switchca/cacert.pem")));
+        let output = std::process::Command::new("openssl")
+            .current_dir(&etc)
+            .args(["verify", "-CAfile", "vswitchd.cacert", "ovsclient-cert.pem"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+}
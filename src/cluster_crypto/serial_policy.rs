@@ -0,0 +1,89 @@
+use crate::deterministic;
+use anyhow::{Context, Result};
+use bcder::{Integer, Mode};
+use rand::RngCore;
+use x509_certificate::{rfc5280, X509Certificate};
+
+/// The most RFC 5280 allows
+const RANDOM_SERIAL_SIZE: usize = 20;
+
+/// The serial numbers of regenerated certs. Applies to every regenerated cert alike, signers and
+/// signees. Certs which aren't regenerated always keep theirs. AKIDs which identify their issuer
+/// by name and serial rather than by key identifier aren't updated, so changing serials breaks
+/// those
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum SerialPolicy {
+    /// Keep the original serial numbers, which trust stores and monitoring might have pinned
+    #[default]
+    Preserve,
+    /// A new random 20 byte serial number for every cert, as RFC 5280 recommends
+    Random,
+    /// A new serial number for every cert counting up from 1, in the order the certs are
+    /// regenerated (signers before their signees). That order only depends on the certs, so the
+    /// same certs always get the same serial numbers
+    Sequential,
+}
+
+/// Hands out the serial numbers of a single regeneration, following its serial policy. Only ever
+/// used from the thread walking the cert graph, before the certs are signed, so that sequential
+/// serial numbers don't depend on how the signing is spread over threads, see signee::SigneeWalk
+pub(crate) struct SerialSequence {
+    policy: SerialPolicy,
+    next: u64,
+}
+
+impl SerialSequence {
+    pub(crate) fn new(policy: SerialPolicy) -> Self {
+        Self { policy, next: 1 }
+    }
+
+    /// Give a cert about to be re-signed its serial number. In deterministic runs, the random
+    /// serial number is derived from the original cert
+    pub(crate) fn apply(&mut self, tbs_certificate: &mut rfc5280::TbsCertificate, original: &X509Certificate) -> Result<()> {
+        match self.policy {
+            SerialPolicy::Preserve => {}
+            SerialPolicy::Random => {
+                let mut serial = [0u8; RANDOM_SERIAL_SIZE];
+                match deterministic::rng_for("serial number", &original.encode_der().context("encoding original cert")?) {
+                    Some(mut rng) => rng.fill_bytes(&mut serial),
+                    None => rand::rngs::OsRng.fill_bytes(&mut serial),
+                }
+                tbs_certificate.serial_number = random_serial(serial)?;
+            }
+            SerialPolicy::Sequential => {
+                tbs_certificate.serial_number = Integer::from(self.next);
+                self.next += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Positive and without leading zeros, so that it's encoded in exactly 20 bytes without a sign
+/// byte, which some verifiers count towards the limit
+fn random_serial(mut serial: [u8; RANDOM_SERIAL_SIZE]) -> Result<Integer> {
+    serial[0] = (serial[0] & 0x7f).max(1);
+
+    let mut der = vec![0x02, RANDOM_SERIAL_SIZE as u8];
+    der.extend_from_slice(&serial);
+    Mode::Der
+        .decode(der.as_slice(), Integer::take_from)
+        .ok()
+        .context("encoding serial number")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_serial() {
+        for (serial, first_byte) in [([0xff; RANDOM_SERIAL_SIZE], 0x7f), ([0x00; RANDOM_SERIAL_SIZE], 0x01)] {
+            let serial = random_serial(serial).unwrap();
+            assert!(serial.is_positive());
+            assert_eq!(serial.as_slice().len(), RANDOM_SERIAL_SIZE);
+            assert_eq!(serial.as_slice()[0], first_byte);
+        }
+    }
+}
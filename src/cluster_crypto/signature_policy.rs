@@ -0,0 +1,273 @@
+use crate::deterministic;
+use anyhow::{bail, Context, Result};
+use bcder::{encode, encode::PrimitiveContent, Captured, Mode, Oid, Tag};
+use bytes::Bytes;
+use ring::signature::{self as ringsig, RsaEncoding, VerificationAlgorithm};
+use rsa::signature::Signer;
+use std::fmt::Display;
+use x509_certificate::{
+    rfc5280::{AlgorithmIdentifier, AlgorithmParameter},
+    InMemorySigningKeyPair, KeyAlgorithm, Sign, SignatureAlgorithm, X509CertificateError,
+};
+
+/// 1.2.840.113549.1.1.10
+const RSASSA_PSS_OID: &[u8] = &[42, 134, 72, 134, 247, 13, 1, 1, 10];
+/// 1.2.840.113549.1.1.8
+const MGF1_OID: &[u8] = &[42, 134, 72, 134, 247, 13, 1, 1, 8];
+
+/// The digest of regenerated RSA signatures. ECDSA signatures always use the digest matching the
+/// curve of the signing key
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Digest {
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Digest {
+    const ALL: [Digest; 3] = [Digest::Sha256, Digest::Sha384, Digest::Sha512];
+
+    /// 2.16.840.1.101.3.4.2.1, 2.16.840.1.101.3.4.2.2 and 2.16.840.1.101.3.4.2.3
+    fn oid(&self) -> Oid {
+        let last = match self {
+            Digest::Sha256 => 1,
+            Digest::Sha384 => 2,
+            Digest::Sha512 => 3,
+        };
+        Oid(Bytes::copy_from_slice(&[96, 134, 72, 1, 101, 3, 4, 2, last]))
+    }
+
+    fn len(&self) -> u8 {
+        match self {
+            Digest::Sha256 => 32,
+            Digest::Sha384 => 48,
+            Digest::Sha512 => 64,
+        }
+    }
+}
+
+/// The padding of regenerated RSA signatures
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum RsaPadding {
+    /// PKCS#1 v1.5, what almost everything uses
+    #[default]
+    Pkcs1v15,
+    /// RSASSA-PSS, with MGF1 using the same digest as the signature and a salt as long as the digest
+    Pss,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SignaturePolicy {
+    pub(crate) rsa_digest: Digest,
+    pub(crate) rsa_padding: RsaPadding,
+}
+
+impl SignaturePolicy {
+    /// The algorithm identifier of signatures made with the given key under this policy
+    pub(crate) fn algorithm_identifier(&self, signing_key: &InMemorySigningKeyPair) -> Result<AlgorithmIdentifier> {
+        Ok(match (signing_key, self.rsa_padding) {
+            (InMemorySigningKeyPair::Rsa(..), RsaPadding::Pkcs1v15) => match self.rsa_digest {
+                Digest::Sha256 => SignatureAlgorithm::RsaSha256,
+                Digest::Sha384 => SignatureAlgorithm::RsaSha384,
+                Digest::Sha512 => SignatureAlgorithm::RsaSha512,
+            }
+            .into(),
+            (InMemorySigningKeyPair::Rsa(..), RsaPadding::Pss) => AlgorithmIdentifier {
+                algorithm: Oid(Bytes::from_static(RSASSA_PSS_OID)),
+                parameters: Some(pss_parameters(self.rsa_digest)),
+            },
+            _ => signing_key.signature_algorithm()?.into(),
+        })
+    }
+
+    pub(crate) fn sign(&self, signing_key: &InMemorySigningKeyPair, data: &[u8]) -> Result<Vec<u8>> {
+        let InMemorySigningKeyPair::Rsa(_) = signing_key else {
+            if let (InMemorySigningKeyPair::Ecdsa(_), true) = (signing_key, deterministic::enabled()) {
+                // The private key data of EC key pairs is their whole PKCS#8 document
+                let pkcs8_der = signing_key.private_key_data().context("EC key pair without private key data")?;
+                return deterministic_ecdsa_sign(&pkcs8_der, data);
+            }
+            return Ok(signing_key.try_sign(data).context("signing")?.into());
+        };
+
+        // The key pair only signs with PKCS#1 v1.5 and SHA-256, sign with its PKCS#1 RSAPrivateKey
+        // ourselves instead
+        let private_key_data = signing_key.private_key_data().context("RSA key pair without private key data")?;
+        let rsa_key_pair = ringsig::RsaKeyPair::from_der(&private_key_data)
+            .ok()
+            .context("loading RSA key pair")?;
+
+        let padding: &'static dyn RsaEncoding = match (self.rsa_padding, self.rsa_digest) {
+            (RsaPadding::Pkcs1v15, Digest::Sha256) => &ringsig::RSA_PKCS1_SHA256,
+            (RsaPadding::Pkcs1v15, Digest::Sha384) => &ringsig::RSA_PKCS1_SHA384,
+            (RsaPadding::Pkcs1v15, Digest::Sha512) => &ringsig::RSA_PKCS1_SHA512,
+            (RsaPadding::Pss, Digest::Sha256) => &ringsig::RSA_PSS_SHA256,
+            (RsaPadding::Pss, Digest::Sha384) => &ringsig::RSA_PSS_SHA384,
+            (RsaPadding::Pss, Digest::Sha512) => &ringsig::RSA_PSS_SHA512,
+        };
+
+        let mut signature = vec![0; rsa_key_pair.public().modulus_len()];
+        if rsa_key_pair
+            .sign(padding, &ring::rand::SystemRandom::new(), data, &mut signature)
+            .is_err()
+        {
+            bail!("RSA signing failed");
+        }
+
+        Ok(signature)
+    }
+}
+
+/// ECDSA signatures are randomized, unless their nonces are derived from the key and the data as
+/// in RFC 6979, which is how deterministic runs sign
+fn deterministic_ecdsa_sign(pkcs8_der: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    use p256::pkcs8::DecodePrivateKey;
+
+    if let Ok(signing_key) = p256::ecdsa::SigningKey::from_pkcs8_der(pkcs8_der) {
+        let signature: p256::ecdsa::Signature = p256::ecdsa::signature::Signer::sign(&signing_key, data);
+        return Ok(signature.to_der().as_bytes().to_vec());
+    }
+
+    let signing_key = p384::ecdsa::SigningKey::from_pkcs8_der(pkcs8_der)
+        .context("loading ECDSA key, only P-256 and P-384 keys can sign deterministically")?;
+    let signature: p384::ecdsa::Signature = p384::ecdsa::signature::Signer::sign(&signing_key, data);
+    Ok(signature.to_der().as_bytes().to_vec())
+}
+
+impl Display for SignaturePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digest = match self.rsa_digest {
+            Digest::Sha256 => "SHA-256",
+            Digest::Sha384 => "SHA-384",
+            Digest::Sha512 => "SHA-512",
+        };
+
+        match self.rsa_padding {
+            // The same names x509-certificate gives them
+            RsaPadding::Pkcs1v15 => write!(f, "{} with RSA encryption", digest),
+            RsaPadding::Pss => write!(f, "RSASSA-PSS with {}", digest),
+        }
+    }
+}
+
+/// The RSASSA-PSS-params of a signature with the given digest, as RFC 4055 recommends them: MGF1
+/// with the same digest and a salt as long as the digest
+fn pss_parameters(digest: Digest) -> AlgorithmParameter {
+    let hash_algorithm = || encode::sequence((digest.oid().encode(), ().encode()));
+
+    AlgorithmParameter::from_captured(Captured::from_values(
+        Mode::Der,
+        encode::sequence((
+            encode::sequence_as(Tag::CTX_0, hash_algorithm()),
+            encode::sequence_as(
+                Tag::CTX_1,
+                encode::sequence((Oid(Bytes::from_static(MGF1_OID)).encode(), hash_algorithm())),
+            ),
+            encode::sequence_as(Tag::CTX_2, digest.len().encode()),
+        )),
+    ))
+}
+
+/// The algorithm to verify a signature with, given its algorithm identifier and the kind of key
+/// of the signer. Unlike SignatureAlgorithm, this also understands RSASSA-PSS, as long as its
+/// parameters are the ones we sign with
+pub(crate) fn verification_algorithm(
+    algorithm_identifier: &AlgorithmIdentifier,
+    signer_key_algorithm: KeyAlgorithm,
+) -> Result<&'static dyn VerificationAlgorithm, X509CertificateError> {
+    if algorithm_identifier.algorithm.as_ref() != RSASSA_PSS_OID {
+        return SignatureAlgorithm::try_from(algorithm_identifier)?.resolve_verification_algorithm(signer_key_algorithm);
+    }
+
+    let digest = Digest::ALL
+        .into_iter()
+        .find(|digest| algorithm_identifier.parameters.as_ref() == Some(&pss_parameters(*digest)))
+        .ok_or_else(|| X509CertificateError::UnknownSignatureAlgorithm("RSASSA-PSS with unsupported parameters".to_string()))?;
+
+    Ok(match digest {
+        Digest::Sha256 => &ringsig::RSA_PSS_2048_8192_SHA256,
+        Digest::Sha384 => &ringsig::RSA_PSS_2048_8192_SHA384,
+        Digest::Sha512 => &ringsig::RSA_PSS_2048_8192_SHA512,
+    })
+}
+
+/// A human readable name of a cert's signature algorithm, for the summary
+pub(crate) fn describe(algorithm_identifier: &AlgorithmIdentifier) -> String {
+    if algorithm_identifier.algorithm.as_ref() == RSASSA_PSS_OID {
+        return match Digest::ALL
+            .into_iter()
+            .find(|digest| algorithm_identifier.parameters.as_ref() == Some(&pss_parameters(*digest)))
+        {
+            Some(digest) => SignaturePolicy {
+                rsa_digest: digest,
+                rsa_padding: RsaPadding::Pss,
+            }
+            .to_string(),
+            None => "RSASSA-PSS with other parameters".to_string(),
+        };
+    }
+
+    match SignatureAlgorithm::try_from(algorithm_identifier) {
+        Ok(signature_algorithm) => signature_algorithm.to_string(),
+        Err(_) => format!("unknown ({})", algorithm_identifier.algorithm),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_crypto::crypto_utils::{generate_rsa_key, verify_signed_by_certificate};
+    use x509_certificate::{rfc5280, CapturedX509Certificate};
+
+    #[test]
+    fn test_openssl_pss_cert() {
+        for (digest, description) in [("-sha256", "RSASSA-PSS with SHA-256"), ("-sha512", "RSASSA-PSS with SHA-512")] {
+            let output = std::process::Command::new("openssl")
+                .args(["req", "-x509", "-nodes", "-keyout", "/dev/null", "-days", "1", "-subj", "/CN=pss"])
+                .args(["-newkey", "rsa:2048", digest])
+                .args(["-sigopt", "rsa_padding_mode:pss", "-sigopt", "rsa_pss_saltlen:digest"])
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            let cert = CapturedX509Certificate::from_pem(output.stdout).unwrap();
+
+            // Our parameters are encoded exactly like openssl's, so we understand its certs and
+            // it understands ours
+            let certificate: &rfc5280::Certificate = cert.as_ref();
+            assert_eq!(describe(&certificate.signature_algorithm), description);
+            verify_signed_by_certificate(&cert, &cert).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (_, rsa_key_pair) = generate_rsa_key(2048).unwrap();
+        let data = b"to be signed";
+
+        for rsa_digest in Digest::ALL {
+            for rsa_padding in [RsaPadding::Pkcs1v15, RsaPadding::Pss] {
+                let policy = SignaturePolicy { rsa_digest, rsa_padding };
+                let algorithm_identifier = policy.algorithm_identifier(&rsa_key_pair).unwrap();
+                let signature = policy.sign(&rsa_key_pair, data).unwrap();
+
+                let verify_algorithm = verification_algorithm(&algorithm_identifier, KeyAlgorithm::Rsa).unwrap();
+                ringsig::UnparsedPublicKey::new(verify_algorithm, rsa_key_pair.public_key_data())
+                    .verify(data, &signature)
+                    .unwrap();
+                assert_eq!(describe(&algorithm_identifier), policy.to_string());
+            }
+        }
+
+        // ECDSA keys are unaffected by the RSA policy
+        let ec_key_pair = InMemorySigningKeyPair::generate_random(KeyAlgorithm::Ecdsa(x509_certificate::EcdsaCurve::Secp256r1)).unwrap();
+        let policy = SignaturePolicy {
+            rsa_digest: Digest::Sha512,
+            rsa_padding: RsaPadding::Pss,
+        };
+        assert_eq!(
+            describe(&policy.algorithm_identifier(&ec_key_pair).unwrap()),
+            SignatureAlgorithm::EcdsaSha256.to_string()
+        );
+    }
+}
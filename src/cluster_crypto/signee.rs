@@ -1,10 +1,19 @@
-use super::{cert_key_pair::CertKeyPair, distributed_jwt::DistributedJwt, keys};
+use super::{
+    cert_key_pair::{CertKeyPair, Regeneration},
+    distributed_crl::DistributedCrl,
+    distributed_jwt::DistributedJwt,
+    keys,
+    serial_policy::SerialSequence,
+    signature_policy::SignaturePolicy,
+    CryptoPolicies,
+};
 use crate::{cnsanreplace::CnSanReplaceRules, rsa_key_pool::RsaKeyPool};
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use std::{
     self,
     cell::RefCell,
     fmt::{Display, Formatter},
+    num::NonZeroUsize,
     rc::Rc,
 };
 use x509_certificate::InMemorySigningKeyPair;
@@ -13,6 +22,7 @@ use x509_certificate::InMemorySigningKeyPair;
 pub(crate) enum Signee {
     CertKeyPair(Rc<RefCell<CertKeyPair>>),
     Jwt(Rc<RefCell<DistributedJwt>>),
+    Crl(Rc<RefCell<DistributedCrl>>),
 }
 
 impl Display for Signee {
@@ -22,32 +32,232 @@ impl Display for Signee {
                 write!(f, "{}", (**cert_key_pair).borrow())
             }
             Signee::Jwt(jwt) => write!(f, "Jwt({})", (**jwt).borrow().locations),
+            Signee::Crl(crl) => write!(f, "Crl({})", (**crl).borrow().locations),
         }
     }
 }
 
-impl Signee {
-    pub(crate) fn regenerate(
+/// Signer chains are short in practice (a root, maybe an intermediate, and a leaf), anything much
+/// longer than that is certainly a bug in how the signers were calculated
+pub(crate) const MAX_SIGNER_CHAIN_DEPTH: usize = 32;
+
+struct PendingSignee {
+    signee: Signee,
+    original_signing_public_key: keys::PublicKey,
+    signing_key: Rc<InMemorySigningKeyPair>,
+    cn_san_replace_rules: Rc<CnSanReplaceRules>,
+    /// The cert-key pairs which (transitively) signed this signee, from the root down
+    signer_chain: Vec<Rc<RefCell<CertKeyPair>>>,
+}
+
+impl PendingSignee {
+    fn is_cert_key_pair(&self, cert_key_pair: &Rc<RefCell<CertKeyPair>>) -> bool {
+        matches!(&self.signee, Signee::CertKeyPair(signee) if Rc::ptr_eq(signee, cert_key_pair))
+    }
+}
+
+/// Regenerates signees along with everything they signed in turn. The signee graph is walked
+/// iteratively rather than recursively, so that a deep graph can't overflow the stack and a cycle
+/// (e.g. introduced by cross-signed CAs) is reported rather than walked forever
+pub(crate) struct SigneeWalk<'a> {
+    pending: Vec<PendingSignee>,
+    policies: &'a CryptoPolicies,
+}
+
+impl<'a> SigneeWalk<'a> {
+    pub(crate) fn new(policies: &'a CryptoPolicies) -> Self {
+        Self {
+            pending: Vec::new(),
+            policies,
+        }
+    }
+
+    /// Queue signees which have to be re-signed with signing_key, the replacement of the key
+    /// whose public part is original_signing_public_key
+    pub(crate) fn push_signees(
         &mut self,
+        signees: &[Signee],
         original_signing_public_key: &keys::PublicKey,
-        new_signing_key: Option<&InMemorySigningKeyPair>,
+        signing_key: InMemorySigningKeyPair,
+        cn_san_replace_rules: CnSanReplaceRules,
+        signer_chain: Vec<Rc<RefCell<CertKeyPair>>>,
+    ) {
+        let signing_key = Rc::new(signing_key);
+        let cn_san_replace_rules = Rc::new(cn_san_replace_rules);
+
+        for signee in signees {
+            self.pending.push(PendingSignee {
+                signee: signee.clone(),
+                original_signing_public_key: original_signing_public_key.clone(),
+                signing_key: Rc::clone(&signing_key),
+                cn_san_replace_rules: Rc::clone(&cn_san_replace_rules),
+                signer_chain: signer_chain.clone(),
+            });
+        }
+    }
+
+    /// Regenerate a cert-key pair, signing it with sign_with (or with its own new key if it's a
+    /// root), and queue its signees
+    pub(crate) fn regenerate_cert_key_pair(
+        &mut self,
+        cert_key_pair: &Rc<RefCell<CertKeyPair>>,
+        sign_with: Option<&InMemorySigningKeyPair>,
         rsa_key_pool: &mut RsaKeyPool,
+        serials: &mut SerialSequence,
         cn_san_replace_rules: &CnSanReplaceRules,
+        signer_chain: Vec<Rc<RefCell<CertKeyPair>>>,
     ) -> Result<()> {
-        match self {
-            Self::CertKeyPair(cert_key_pair) => {
-                (**cert_key_pair)
-                    .borrow_mut()
-                    .regenerate(new_signing_key, rsa_key_pool, cn_san_replace_rules)?;
-            }
-            Self::Jwt(jwt) => match new_signing_key {
-                Some(key_pair) => (**jwt).borrow_mut().regenerate(&original_signing_public_key, key_pair)?,
-                None => {
-                    bail!("Cannot regenerate a jwt without a signing key, regenerate may only be called on a signee that is a root cert-key-pair")
+        check_signer_chain(cert_key_pair, &signer_chain)?;
+
+        let (new_key_pair, signees_cn_san_replace_rules) =
+            (**cert_key_pair)
+                .borrow_mut()
+                .regenerate(sign_with, rsa_key_pool, serials, cn_san_replace_rules, self.policies)?;
+
+        self.push_signees_of(cert_key_pair, new_key_pair, signees_cn_san_replace_rules, signer_chain);
+
+        Ok(())
+    }
+
+    fn push_signees_of(
+        &mut self,
+        cert_key_pair: &Rc<RefCell<CertKeyPair>>,
+        new_key_pair: InMemorySigningKeyPair,
+        signees_cn_san_replace_rules: CnSanReplaceRules,
+        mut signer_chain: Vec<Rc<RefCell<CertKeyPair>>>,
+    ) {
+        let (new_public_key, signees) = {
+            let cert_key_pair = (**cert_key_pair).borrow();
+            let new_public_key = (*cert_key_pair.distributed_cert).borrow().certificate.public_key.clone();
+            (new_public_key, cert_key_pair.signees.clone())
+        };
+
+        signer_chain.push(Rc::clone(cert_key_pair));
+        self.push_signees(&signees, &new_public_key, new_key_pair, signees_cn_san_replace_rules, signer_chain);
+    }
+
+    /// Regenerate everything queued, until nothing is left. The graph is walked a generation at a
+    /// time: the signers of everything in a generation were all regenerated in the previous one,
+    /// so its certs can all be signed at once, see sign_all. Everything else (generating keys,
+    /// preparing the certs, updating the graph) is cheap in comparison and stays on this thread,
+    /// as the graph isn't Send. Keys and serial numbers in particular are handed out here, in the
+    /// order of the walk, never by the signing threads
+    pub(crate) fn run(mut self, rsa_key_pool: &mut RsaKeyPool, serials: &mut SerialSequence) -> Result<()> {
+        while !self.pending.is_empty() {
+            let mut generation: Vec<(PendingSignee, Regeneration)> = Vec::new();
+            for pending in std::mem::take(&mut self.pending) {
+                match &pending.signee {
+                    Signee::CertKeyPair(cert_key_pair) => {
+                        // A pair signed by more than one signer of this generation is regenerated
+                        // once for each of them, one generation after the other, as it would be
+                        // if they were regenerated one at a time
+                        if generation.iter().any(|(queued, _)| queued.is_cert_key_pair(cert_key_pair)) {
+                            self.pending.push(pending);
+                            continue;
+                        }
+
+                        check_signer_chain(cert_key_pair, &pending.signer_chain)?;
+                        let regeneration = (**cert_key_pair).borrow_mut().prepare_regeneration(
+                            Some(&pending.signing_key),
+                            rsa_key_pool,
+                            serials,
+                            &pending.cn_san_replace_rules,
+                            self.policies,
+                        )?;
+                        generation.push((pending, regeneration));
+                    }
+                    Signee::Jwt(jwt) => {
+                        (**jwt)
+                            .borrow_mut()
+                            .regenerate(&pending.original_signing_public_key, &pending.signing_key, &self.policies.token)?
+                    }
+                    Signee::Crl(crl) => (**crl).borrow_mut().regenerate(&pending.signing_key, &self.policies.signature)?,
                 }
-            },
+            }
+
+            let signatures = sign_all(
+                self.policies.signature,
+                &generation
+                    .iter()
+                    .filter_map(|(pending, regeneration)| Some((regeneration.tbs_der()?, pending.signing_key.as_ref())))
+                    .collect::<Vec<_>>(),
+            )?;
+
+            let mut signatures = signatures.into_iter();
+            for (pending, regeneration) in generation {
+                let Signee::CertKeyPair(cert_key_pair) = &pending.signee else {
+                    unreachable!("only cert-key pairs are signed in bulk");
+                };
+
+                let signature = match regeneration.tbs_der() {
+                    Some(_) => signatures.next(),
+                    None => None,
+                };
+                let (new_key_pair, signees_cn_san_replace_rules) =
+                    (**cert_key_pair).borrow_mut().finish_regeneration(regeneration, signature)?;
+
+                self.push_signees_of(cert_key_pair, new_key_pair, signees_cn_san_replace_rules, pending.signer_chain);
+            }
         }
 
         Ok(())
     }
 }
+
+fn check_signer_chain(cert_key_pair: &Rc<RefCell<CertKeyPair>>, signer_chain: &[Rc<RefCell<CertKeyPair>>]) -> Result<()> {
+    if let Some(cycle_start) = signer_chain.iter().position(|signer| Rc::ptr_eq(signer, cert_key_pair)) {
+        bail!(
+            "signer cycle: {} -> {}",
+            signer_chain[cycle_start..].iter().map(subject).collect::<Vec<_>>().join(" -> "),
+            subject(cert_key_pair)
+        );
+    }
+
+    if signer_chain.len() >= MAX_SIGNER_CHAIN_DEPTH {
+        bail!(
+            "signer chain of {} is deeper than {}: {}",
+            subject(cert_key_pair),
+            MAX_SIGNER_CHAIN_DEPTH,
+            signer_chain.iter().map(subject).collect::<Vec<_>>().join(" -> ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Sign each of the given DERs with its key, spread over as many threads as there are CPUs. The
+/// signatures come back in the order of the DERs, however the threads were scheduled.
+/// Signing (RSA signing in particular) is what regenerating big clusters spends most of its CPU
+/// time on, and unlike the rest of regeneration it only needs the bytes and the key
+fn sign_all(signature_policy: SignaturePolicy, to_sign: &[(&[u8], &InMemorySigningKeyPair)]) -> Result<Vec<Vec<u8>>> {
+    let threads = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    if to_sign.len() < 2 || threads < 2 {
+        return to_sign
+            .iter()
+            .map(|(tbs_der, signing_key)| signature_policy.sign(signing_key, tbs_der))
+            .collect();
+    }
+
+    let chunk_size = to_sign.len() / threads + 1;
+    std::thread::scope(|scope| {
+        to_sign
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(tbs_der, signing_key)| signature_policy.sign(signing_key, tbs_der))
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().map_err(|_| anyhow!("signing thread panicked"))?)
+            .collect::<Result<Vec<_>>>()
+    })
+    .map(|signatures| signatures.concat())
+}
+
+pub(crate) fn subject(cert_key_pair: &Rc<RefCell<CertKeyPair>>) -> String {
+    (*(**cert_key_pair).borrow().distributed_cert).borrow().certificate.subject.clone()
+}
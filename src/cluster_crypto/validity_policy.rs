@@ -0,0 +1,213 @@
+use super::certificate;
+use anyhow::{bail, Context, Result};
+use std::str::FromStr;
+use x509_certificate::{asn1time::Time, rfc5280};
+
+/// How far in the past the notBefore of certs given a new validity period is set, so that they're
+/// immediately valid on machines whose clocks are slightly behind
+fn not_before_backdate() -> chrono::Duration {
+    chrono::Duration::hours(1)
+}
+
+/// The validity period of a regenerated cert
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Validity {
+    /// Keep the original notBefore and notAfter
+    Preserve,
+    /// Valid from now for as long as the original cert had left. Expired certs keep their original
+    /// validity period
+    PreserveRemaining,
+    /// Valid from now for this long
+    Lifetime(chrono::Duration),
+}
+
+impl FromStr for Validity {
+    type Err = anyhow::Error;
+
+    /// preserve, preserve-remaining or a lifetime, see parse_duration
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "preserve" => return Ok(Validity::Preserve),
+            "preserve-remaining" => return Ok(Validity::PreserveRemaining),
+            _ => {}
+        }
+
+        Ok(Validity::Lifetime(
+            parse_duration(value).context("expected preserve, preserve-remaining or a lifetime")?,
+        ))
+    }
+}
+
+/// A positive number of hours, days or years (of 365 days), e.g. 72h, 30d or 10y
+pub(crate) fn parse_duration(value: &str) -> Result<chrono::Duration> {
+    let (count, unit) = value.split_at(value.len() - value.chars().last().context("empty duration")?.len_utf8());
+    let count = count
+        .parse::<i64>()
+        .with_context(|| format!("expected e.g. 72h, 30d or 10y, not {:?}", value))?;
+    if count <= 0 {
+        bail!("duration {:?} isn't positive", value);
+    }
+
+    Ok(match unit {
+        "h" => chrono::Duration::hours(count),
+        "d" => chrono::Duration::days(count),
+        "y" => chrono::Duration::days(count * 365),
+        _ => bail!("unknown duration unit {:?}, expected h, d or y", unit),
+    })
+}
+
+/// The validity of certs with a given CN, written as CN=VALIDITY. A trailing * in the CN matches
+/// any CN with the given prefix
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ValidityOverride {
+    common_name: String,
+    validity: Validity,
+}
+
+impl FromStr for ValidityOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (common_name, validity) = value.rsplit_once('=').context("expected CN=VALIDITY")?;
+        if common_name.is_empty() {
+            bail!("empty CN in validity override {:?}", value);
+        }
+
+        Ok(Self {
+            common_name: common_name.to_string(),
+            validity: validity.parse()?,
+        })
+    }
+}
+
+impl ValidityOverride {
+    fn matches(&self, common_name: &str) -> bool {
+        match self.common_name.strip_suffix('*') {
+            Some(prefix) => common_name.starts_with(prefix),
+            None => common_name == self.common_name,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ValidityPolicy {
+    pub(crate) cert: Validity,
+    pub(crate) ca: Validity,
+    /// Take precedence over the above, the first matching one applies
+    pub(crate) overrides: Vec<ValidityOverride>,
+}
+
+impl Default for ValidityPolicy {
+    fn default() -> Self {
+        Self {
+            cert: Validity::Preserve,
+            ca: Validity::Preserve,
+            overrides: vec![],
+        }
+    }
+}
+
+impl ValidityPolicy {
+    fn validity_for(&self, common_name: Option<&str>, is_ca: bool) -> Validity {
+        common_name
+            .and_then(|common_name| {
+                self.overrides
+                    .iter()
+                    .find(|validity_override| validity_override.matches(common_name))
+            })
+            .map(|validity_override| validity_override.validity)
+            .unwrap_or(if is_ca { self.ca } else { self.cert })
+    }
+
+    /// Set the validity period of a cert about to be re-signed, as of now
+    pub(crate) fn apply(
+        &self,
+        tbs_certificate: &mut rfc5280::TbsCertificate,
+        common_name: Option<&str>,
+        is_ca: bool,
+        now: chrono::DateTime<chrono::Utc>,
+    ) {
+        let lifetime = match self.validity_for(common_name, is_ca) {
+            Validity::Preserve => return,
+            Validity::PreserveRemaining => {
+                let remaining = certificate::chrono_time(&tbs_certificate.validity.not_after) - now;
+                if remaining <= chrono::Duration::zero() {
+                    return;
+                }
+                remaining
+            }
+            Validity::Lifetime(lifetime) => lifetime,
+        };
+
+        tbs_certificate.validity = rfc5280::Validity {
+            not_before: Time::from(now - not_before_backdate()),
+            not_after: Time::from(now + lifetime),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::CertFixture;
+    use x509_certificate::CapturedX509Certificate;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("preserve".parse::<Validity>().unwrap(), Validity::Preserve);
+        assert_eq!("preserve-remaining".parse::<Validity>().unwrap(), Validity::PreserveRemaining);
+        assert_eq!("72h".parse::<Validity>().unwrap(), Validity::Lifetime(chrono::Duration::hours(72)));
+        assert_eq!("365d".parse::<Validity>().unwrap(), Validity::Lifetime(chrono::Duration::days(365)));
+        assert_eq!("10y".parse::<Validity>().unwrap(), Validity::Lifetime(chrono::Duration::days(3650)));
+        for invalid in ["", "d", "0d", "-1d", "10w", "forever"] {
+            assert!(invalid.parse::<Validity>().is_err(), "{}", invalid);
+        }
+
+        assert_eq!(
+            "ingress-operator@*=preserve-remaining".parse::<ValidityOverride>().unwrap(),
+            ValidityOverride {
+                common_name: "ingress-operator@*".to_string(),
+                validity: Validity::PreserveRemaining,
+            }
+        );
+        assert!("=1y".parse::<ValidityOverride>().is_err());
+        assert!("admin-kubeconfig-signer".parse::<ValidityOverride>().is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let policy = ValidityPolicy {
+            cert: Validity::Lifetime(chrono::Duration::days(30)),
+            ca: Validity::Preserve,
+            overrides: vec!["ingress-operator@*=preserve-remaining".parse().unwrap()],
+        };
+
+        let ca = CertFixture::ca("root");
+        let cert = CapturedX509Certificate::from_pem(&ca.cert_pem).unwrap();
+        let certificate: &rfc5280::Certificate = cert.as_ref();
+        let original = certificate.tbs_certificate.clone();
+        let now = chrono::Utc::now();
+        let validity = |tbs_certificate: &rfc5280::TbsCertificate| {
+            (
+                certificate::chrono_time(&tbs_certificate.validity.not_before),
+                certificate::chrono_time(&tbs_certificate.validity.not_after),
+            )
+        };
+
+        let mut tbs_certificate = original.clone();
+        policy.apply(&mut tbs_certificate, Some("root"), true, now);
+        assert_eq!(validity(&tbs_certificate), validity(&original));
+
+        let mut tbs_certificate = original.clone();
+        policy.apply(&mut tbs_certificate, Some("leaf"), false, now);
+        let (not_before, not_after) = validity(&tbs_certificate);
+        // UTCTime has a resolution of seconds
+        assert_eq!(not_before.timestamp(), (now - not_before_backdate()).timestamp());
+        assert_eq!(not_after.timestamp(), (now + chrono::Duration::days(30)).timestamp());
+
+        let mut tbs_certificate = original.clone();
+        policy.apply(&mut tbs_certificate, Some("ingress-operator@1690000000"), true, now);
+        assert_eq!(validity(&tbs_certificate).1, validity(&original).1);
+        assert_eq!(validity(&tbs_certificate).0.timestamp(), (now - not_before_backdate()).timestamp());
+    }
+}
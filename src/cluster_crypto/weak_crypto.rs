@@ -0,0 +1,148 @@
+use super::{certificate::Certificate, keys::PublicKey, signature_policy::SignaturePolicy, ClusterCryptoObjects};
+use crate::rsa_key_pool::MIN_RSA_KEY_SIZE;
+use anyhow::{Context, Result};
+use std::fmt::Display;
+use x509_certificate::rfc5280;
+
+/// Signature algorithms with a broken digest, by OID
+const WEAK_SIGNATURE_ALGORITHMS: [(&[u8], &str); 5] = [
+    // 1.2.840.113549.1.1.2
+    (&[42, 134, 72, 134, 247, 13, 1, 1, 2], "MD2 with RSA encryption"),
+    // 1.2.840.113549.1.1.4
+    (&[42, 134, 72, 134, 247, 13, 1, 1, 4], "MD5 with RSA encryption"),
+    // 1.2.840.113549.1.1.5
+    (&[42, 134, 72, 134, 247, 13, 1, 1, 5], "SHA-1 with RSA encryption"),
+    // 1.2.840.10045.4.1
+    (&[42, 134, 72, 206, 61, 4, 1], "ECDSA with SHA-1"),
+    // 1.2.840.10040.4.3
+    (&[42, 134, 72, 206, 56, 4, 3], "DSA with SHA-1"),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Weakness {
+    SmallRsaKey(usize),
+    WeakSignature(&'static str),
+}
+
+impl Display for Weakness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Weakness::SmallRsaKey(key_size) => write!(f, "{}-bit RSA key", key_size),
+            Weakness::WeakSignature(signature_algorithm) => write!(f, "signed with {}", signature_algorithm),
+        }
+    }
+}
+
+/// A weakness of one of the crypto objects found during the scan, along with a description of
+/// the object for the report
+pub(crate) struct WeakCrypto {
+    pub(crate) object: String,
+    pub(crate) weakness: Weakness,
+}
+
+/// Look for keys smaller than 2048 bits and certs signed with MD5 or SHA-1 among the scanned
+/// objects. Must run before regeneration, which replaces all of them
+pub(crate) fn find_weak_crypto(cluster_crypto: &ClusterCryptoObjects) -> Result<Vec<WeakCrypto>> {
+    let mut findings = vec![];
+
+    for cert_key_pair in &cluster_crypto.cert_key_pairs {
+        let distributed_cert = &(*(**cert_key_pair).borrow().distributed_cert);
+        let distributed_cert = distributed_cert.borrow();
+        for weakness in cert_weaknesses(&distributed_cert.certificate)? {
+            findings.push(WeakCrypto {
+                object: format!("cert {:?} at {}", distributed_cert.certificate.subject, distributed_cert.locations),
+                weakness,
+            });
+        }
+    }
+
+    for distributed_private_key in cluster_crypto.distributed_private_keys.values() {
+        let distributed_private_key = (**distributed_private_key).borrow();
+        if let Some(weakness) = key_weakness(&PublicKey::try_from(&distributed_private_key.key)?)? {
+            findings.push(WeakCrypto {
+                object: format!("private key at {}", distributed_private_key.locations),
+                weakness,
+            });
+        }
+    }
+
+    Ok(findings)
+}
+
+fn cert_weaknesses(certificate: &Certificate) -> Result<Vec<Weakness>> {
+    let original: &rfc5280::Certificate = certificate.original.as_ref().as_ref();
+
+    let weak_signature = WEAK_SIGNATURE_ALGORITHMS
+        .into_iter()
+        .find(|(oid, _)| original.signature_algorithm.algorithm.as_ref() == *oid)
+        .map(|(_, name)| Weakness::WeakSignature(name));
+
+    Ok(key_weakness(&certificate.public_key)?.into_iter().chain(weak_signature).collect())
+}
+
+fn key_weakness(public_key: &PublicKey) -> Result<Option<Weakness>> {
+    Ok(match public_key.rsa_key_size().context("determining key size")? {
+        Some(key_size) if key_size < MIN_RSA_KEY_SIZE => Some(Weakness::SmallRsaKey(key_size)),
+        _ => None,
+    })
+}
+
+/// Print the weak crypto found, and what regeneration is going to do about it
+pub(crate) fn report(findings: &[WeakCrypto], upgrade_weak_crypto: bool, signature_policy: SignaturePolicy) {
+    if findings.is_empty() {
+        return;
+    }
+
+    println!("WARNING: found {} weaknesses in the scanned crypto objects:", findings.len());
+    for finding in findings {
+        println!("- {}: {}", finding.object, finding.weakness);
+    }
+
+    println!(
+        "Regenerated certs are signed with {} regardless of their original signature algorithm",
+        signature_policy
+    );
+    if findings.iter().any(|finding| matches!(finding.weakness, Weakness::SmallRsaKey(_))) {
+        if upgrade_weak_crypto {
+            println!(
+                "RSA keys smaller than {} bits are upgraded to {} bits",
+                MIN_RSA_KEY_SIZE, MIN_RSA_KEY_SIZE
+            );
+        } else {
+            println!(
+                "RSA keys smaller than {} bits can't be regenerated at their original size, use --upgrade-weak-crypto to upgrade them",
+                MIN_RSA_KEY_SIZE
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_certificate::CapturedX509Certificate;
+
+    fn openssl_cert(args: &[&str]) -> Certificate {
+        let output = std::process::Command::new("openssl")
+            .args(["req", "-x509", "-nodes", "-keyout", "/dev/null", "-days", "1", "-subj", "/CN=weak"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        Certificate::try_from(CapturedX509Certificate::from_pem(output.stdout).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_cert_weaknesses() {
+        assert_eq!(
+            cert_weaknesses(&openssl_cert(&["-newkey", "rsa:1024", "-sha1"])).unwrap(),
+            vec![Weakness::SmallRsaKey(1024), Weakness::WeakSignature("SHA-1 with RSA encryption")]
+        );
+        assert_eq!(cert_weaknesses(&openssl_cert(&["-newkey", "rsa:2048", "-sha256"])).unwrap(), vec![]);
+        assert_eq!(
+            cert_weaknesses(&openssl_cert(&["-newkey", "ec", "-pkeyopt", "ec_paramgen_curve:P-256", "-sha1"])).unwrap(),
+            vec![Weakness::WeakSignature("ECDSA with SHA-1")]
+        );
+    }
+}
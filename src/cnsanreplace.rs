@@ -1,19 +1,80 @@
 use anyhow::{self, Context, Result};
+use regex::Regex;
+use std::{cell::Cell, rc::Rc};
 
+#[derive(Clone)]
 pub(crate) struct CnSanReplace {
     pub(crate) old: String,
     pub(crate) new: String,
+    /// For regex rules, old compiled to match entire values, in which case new is a replacement
+    /// template which can refer to the capture groups of old, e.g. $1
+    regex: Option<Regex>,
+    /// When set, the rule only applies to certs in the chain of the CA with this CN, i.e. certs
+    /// signed directly or indirectly by it. A trailing * matches any CN with the given prefix, as
+    /// many CA CNs carry a timestamp (e.g. ingress-operator@1690000000)
+    pub(crate) signer: Option<String>,
+    /// How many times this rule replaced something. Shared between all copies of the rule (see
+    /// CnSanReplaceRules::for_signer) so it reflects the whole run
+    matches: Rc<Cell<usize>>,
 }
 
 impl std::fmt::Display for CnSanReplace {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Replacing all CN/SAN instances of {} with {}", self.old, self.new)
+        match self.regex {
+            Some(_) => write!(f, "Replacing all CN/SAN instances matching {} with {}", self.old, self.new)?,
+            None => write!(f, "Replacing all CN/SAN instances of {} with {}", self.old, self.new)?,
+        }
+
+        if let Some(signer) = &self.signer {
+            write!(f, " in certs signed by {}", signer)?;
+        }
+
+        Ok(())
     }
 }
 
 impl CnSanReplace {
-    pub(crate) fn new(old: String, new: String) -> Self {
-        Self { old, new }
+    pub(crate) fn new(old: String, new: String, signer: Option<String>) -> Self {
+        Self {
+            old,
+            new,
+            regex: None,
+            signer,
+            matches: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// A rule written as PATTERN:REPLACEMENT, where PATTERN is a regex which has to match the
+    /// entire CN/SAN value and REPLACEMENT can refer to its capture groups ($1, or ${1} when
+    /// followed by a letter or digit). The last : separates the two, so that the pattern can
+    /// contain non-capturing groups
+    pub(crate) fn parse_regex(value: &str) -> Result<Self> {
+        let (old, new) = value.rsplit_once(':').context("expected PATTERN:REPLACEMENT")?;
+        let regex = Regex::new(&format!("^(?:{})$", old)).with_context(|| format!("invalid regex {:?}", old))?;
+
+        Ok(Self {
+            regex: Some(regex),
+            ..Self::new(old.to_string(), new.to_string(), None)
+        })
+    }
+
+    /// The replacement of the value, if this rule applies to it
+    fn apply(&self, input: &str) -> Option<String> {
+        match &self.regex {
+            Some(regex) => regex.is_match(input).then(|| regex.replace(input, self.new.as_str()).into_owned()),
+            None => (self.old == input).then(|| self.new.clone()),
+        }
+    }
+
+    pub(crate) fn matches(&self) -> usize {
+        self.matches.get()
+    }
+
+    fn signer_matches(signer: &str, common_name: &str) -> bool {
+        match signer.strip_suffix('*') {
+            Some(prefix) => common_name.starts_with(prefix),
+            None => common_name == signer,
+        }
     }
 }
 
@@ -24,25 +85,71 @@ impl TryFrom<String> for CnSanReplace {
         let mut split = value.split_whitespace();
         let old = split.next().context("old value")?.to_string();
         let new = split.next().context("new value")?.to_string();
+        let signer = split.next().map(str::to_string);
 
-        Ok(Self::new(old, new))
+        if split.next().is_some() {
+            anyhow::bail!("too many values in {:?}, expected \"old new [signer]\"", value);
+        }
+
+        Ok(Self::new(old, new, signer))
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct CnSanReplaceRules(Vec<CnSanReplace>);
 
 impl CnSanReplaceRules {
     pub(crate) fn replace(&self, input: &str) -> String {
         let mut output = input.to_string();
 
-        for rule in &self.0 {
-            if rule.old == input {
-                output = rule.new.clone();
+        for rule in self.0.iter().filter(|rule| rule.signer.is_none()) {
+            if let Some(new) = rule.apply(input) {
+                output = new;
+                rule.matches.set(rule.matches.get() + 1);
             }
         }
 
         output
     }
+
+    pub(crate) fn extend(&mut self, rules: impl IntoIterator<Item = CnSanReplace>) {
+        self.0.extend(rules);
+    }
+
+    /// Rules which haven't replaced anything so far, most likely due to a typo
+    pub(crate) fn unmatched(&self) -> Vec<&CnSanReplace> {
+        self.0.iter().filter(|rule| rule.matches() == 0).collect()
+    }
+
+    /// Report rules which didn't match anything, and fail if strict is set
+    pub(crate) fn validate(&self, strict: bool) -> Result<()> {
+        let unmatched = self.unmatched();
+
+        for rule in &unmatched {
+            println!("WARNING: CN/SAN rule matched nothing: {}", rule);
+        }
+
+        if strict && !unmatched.is_empty() {
+            anyhow::bail!("{} CN/SAN rule(s) matched nothing", unmatched.len());
+        }
+
+        Ok(())
+    }
+
+    /// The rules that apply to certs signed by the CA with the given CN. Rules scoped to that CA
+    /// become unscoped, so they apply to the entire chain below it.
+    pub(crate) fn for_signer(&self, signer_common_name: &str) -> Self {
+        Self(
+            self.0
+                .iter()
+                .cloned()
+                .map(|rule| match &rule.signer {
+                    Some(signer) if CnSanReplace::signer_matches(signer, signer_common_name) => CnSanReplace { signer: None, ..rule },
+                    _ => rule,
+                })
+                .collect(),
+        )
+    }
 }
 
 impl TryFrom<Vec<String>> for CnSanReplaceRules {
@@ -52,7 +159,7 @@ impl TryFrom<Vec<String>> for CnSanReplaceRules {
         Ok(Self(
             value
                 .into_iter()
-                .map(|pair| CnSanReplace::try_from(pair))
+                .map(CnSanReplace::try_from)
                 .collect::<Result<Vec<_>>>()
                 .context("parsing cn-san-replace")?,
         ))
@@ -68,3 +175,74 @@ impl std::fmt::Display for CnSanReplaceRules {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signer_scoped_rules() {
+        let rules = CnSanReplaceRules::try_from(vec![
+            "api.old.com api.new.com".to_string(),
+            "*.apps.old.com *.apps.new.com ingress-operator@*".to_string(),
+        ])
+        .unwrap();
+
+        assert_eq!(rules.replace("api.old.com"), "api.new.com");
+        assert_eq!(rules.replace("*.apps.old.com"), "*.apps.old.com");
+
+        let unrelated = rules.for_signer("kube-apiserver-lb-signer");
+        assert_eq!(unrelated.replace("api.old.com"), "api.new.com");
+        assert_eq!(unrelated.replace("*.apps.old.com"), "*.apps.old.com");
+
+        let ingress = rules.for_signer("ingress-operator@1690000000");
+        assert_eq!(ingress.replace("api.old.com"), "api.new.com");
+        assert_eq!(ingress.replace("*.apps.old.com"), "*.apps.new.com");
+
+        // Once in scope, the rule applies to the rest of the chain
+        assert_eq!(ingress.for_signer("some-intermediate").replace("*.apps.old.com"), "*.apps.new.com");
+
+        assert!(CnSanReplace::try_from("a b c d".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_unmatched_rules() {
+        let rules = CnSanReplaceRules::try_from(vec![
+            "api.old.com api.new.com".to_string(),
+            "api.typo.com api.new.com".to_string(),
+            "*.apps.old.com *.apps.new.com ingress-operator@*".to_string(),
+        ])
+        .unwrap();
+
+        rules.replace("api.old.com");
+        // Matches recorded by scoped copies of the rules count too
+        rules.for_signer("ingress-operator@1690000000").replace("*.apps.old.com");
+
+        let unmatched = rules.unmatched();
+        assert_eq!(unmatched.len(), 1);
+        assert_eq!(unmatched[0].old, "api.typo.com");
+
+        assert!(rules.validate(false).is_ok());
+        assert!(rules.validate(true).is_err());
+    }
+
+    #[test]
+    fn test_regex_rules() {
+        let mut rules = CnSanReplaceRules::try_from(vec![]).unwrap();
+        rules.extend([
+            CnSanReplace::parse_regex(r"(.*)\.old\.base\.domain:$1.new.base.domain").unwrap(),
+            CnSanReplace::parse_regex(r"(?:master|worker)-(\d+):node-$1").unwrap(),
+        ]);
+
+        assert_eq!(rules.replace("*.apps.old.base.domain"), "*.apps.new.base.domain");
+        assert_eq!(rules.replace("api-int.old.base.domain"), "api-int.new.base.domain");
+        assert_eq!(rules.replace("master-2"), "node-2");
+        // Only entire values are matched
+        assert_eq!(rules.replace("api.old.base.domain.example.com"), "api.old.base.domain.example.com");
+        assert_eq!(rules.replace("master-2x"), "master-2x");
+        assert!(rules.unmatched().is_empty());
+
+        assert!(CnSanReplace::parse_regex("no-separator").is_err());
+        assert!(CnSanReplace::parse_regex("(unclosed:new").is_err());
+    }
+}
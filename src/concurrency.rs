@@ -0,0 +1,126 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+};
+use tokio::{sync::Semaphore, task::JoinError};
+
+/// Big clusters have thousands of etcd keys and files, and spawning a task for each of them all at
+/// once causes memory spikes and can overload etcd. Leaf tasks (ones that don't spawn further
+/// bounded tasks themselves, which could otherwise deadlock waiting for permits held by their
+/// parents) should be spawned through here to limit how many of them run at the same time.
+struct TaskLimiter {
+    semaphore: Arc<Semaphore>,
+    queued: AtomicUsize,
+    max_queued: AtomicUsize,
+    running: AtomicUsize,
+    max_running: AtomicUsize,
+}
+
+impl TaskLimiter {
+    fn new(max_concurrency: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrency.max(1))),
+            queued: AtomicUsize::new(0),
+            max_queued: AtomicUsize::new(0),
+            running: AtomicUsize::new(0),
+            max_running: AtomicUsize::new(0),
+        }
+    }
+
+    async fn spawn<F>(&'static self, future: F) -> Result<F::Output, JoinError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_queued.fetch_max(queued, Ordering::Relaxed);
+
+        // The semaphore is never closed
+        let permit = Arc::clone(&self.semaphore).acquire_owned().await.expect("semaphore closed");
+
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+
+        tokio::spawn(async move {
+            let running = self.running.fetch_add(1, Ordering::Relaxed) + 1;
+            self.max_running.fetch_max(running, Ordering::Relaxed);
+
+            let output = future.await;
+
+            self.running.fetch_sub(1, Ordering::Relaxed);
+            drop(permit);
+
+            output
+        })
+        .await
+    }
+}
+
+pub(crate) const DEFAULT_MAX_CONCURRENCY: usize = 100;
+
+static TASK_LIMITER: OnceLock<TaskLimiter> = OnceLock::new();
+
+fn task_limiter() -> &'static TaskLimiter {
+    TASK_LIMITER.get_or_init(|| TaskLimiter::new(DEFAULT_MAX_CONCURRENCY))
+}
+
+pub(crate) fn set_max_concurrency(max_concurrency: usize) -> anyhow::Result<()> {
+    TASK_LIMITER
+        .set(TaskLimiter::new(max_concurrency))
+        .ok()
+        .ok_or_else(|| anyhow::anyhow!("task limiter already initialized"))
+}
+
+/// Like tokio::spawn, but the task is only spawned once there's room for it to run. Nothing
+/// happens until the returned future is polled, so e.g. join_all-ing many of these only keeps
+/// max-concurrency tasks alive at once, while the rest wait in the queue.
+pub(crate) async fn spawn<F>(future: F) -> Result<F::Output, JoinError>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    task_limiter().spawn(future).await
+}
+
+/// The peak number of tasks that were waiting for a permit and that were running at the same time
+pub(crate) fn peak_queue_depth_and_concurrency() -> (usize, usize) {
+    let limiter = task_limiter();
+    (
+        limiter.max_queued.load(Ordering::Relaxed),
+        limiter.max_running.load(Ordering::Relaxed),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spawn_is_bounded() {
+        // A limiter of our own, so the global one (shared with other tests) is left alone
+        let limiter: &'static TaskLimiter = Box::leak(Box::new(TaskLimiter::new(3)));
+        let concurrent = Arc::new(AtomicUsize::new(0));
+        let max_concurrent = Arc::new(AtomicUsize::new(0));
+
+        let results = futures_util::future::join_all((0..20).map(|i| {
+            let concurrent = Arc::clone(&concurrent);
+            let max_concurrent = Arc::clone(&max_concurrent);
+            limiter.spawn(async move {
+                let now = concurrent.fetch_add(1, Ordering::SeqCst) + 1;
+                max_concurrent.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                concurrent.fetch_sub(1, Ordering::SeqCst);
+                i
+            })
+        }))
+        .await;
+
+        assert_eq!(results.into_iter().map(Result::unwrap).sum::<usize>(), (0..20).sum::<usize>());
+        assert_eq!(max_concurrent.load(Ordering::SeqCst), 3);
+        assert_eq!(limiter.max_running.load(Ordering::Relaxed), 3);
+        // join_all polls everything once up front, only the first 3 get a permit right away
+        assert_eq!(limiter.max_queued.load(Ordering::Relaxed), 17);
+    }
+}
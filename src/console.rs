@@ -0,0 +1,264 @@
+use anyhow::Result;
+use std::{
+    io::IsTerminal,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// How progress is printed to stdout
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Pretty when stdout is a terminal, plain otherwise
+    #[default]
+    Auto,
+    /// One line per step, for logs
+    Plain,
+    /// Colored per-phase summaries (counts and durations) and a table of the chains processed
+    /// at the end. Colors are left out when NO_COLOR is set
+    Pretty,
+}
+
+static PRETTY: OnceLock<bool> = OnceLock::new();
+
+pub(crate) fn init(format: OutputFormat) -> Result<()> {
+    let pretty = match format {
+        OutputFormat::Auto => std::io::stdout().is_terminal(),
+        OutputFormat::Plain => false,
+        OutputFormat::Pretty => true,
+    };
+
+    PRETTY.set(pretty).ok().ok_or_else(|| anyhow::anyhow!("output format already set"))
+}
+
+pub(crate) fn pretty() -> bool {
+    PRETTY.get().copied().unwrap_or(false)
+}
+
+fn colored() -> bool {
+    pretty() && std::env::var_os("NO_COLOR").is_none()
+}
+
+#[derive(Clone, Copy)]
+enum Style {
+    Heading,
+    Success,
+    Failure,
+    Dim,
+}
+
+fn paint(text: &str, style: Style) -> String {
+    if !colored() {
+        return text.to_string();
+    }
+
+    let code = match style {
+        Style::Heading => "1;34",
+        Style::Success => "32",
+        Style::Failure => "1;31",
+        Style::Dim => "2",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+struct Phase {
+    name: String,
+    started: Instant,
+    counts: Vec<(String, usize)>,
+}
+
+struct Table {
+    title: String,
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+struct Console {
+    phase: Option<Phase>,
+    /// Printed once the run is over, so that they don't scroll away among the phases
+    tables: Vec<Table>,
+}
+
+static CONSOLE: Mutex<Console> = Mutex::new(Console {
+    phase: None,
+    tables: Vec::new(),
+});
+
+fn with_console<T>(f: impl FnOnce(&mut Console) -> T) -> T {
+    // Printing progress is never worth failing the run over
+    let mut console = CONSOLE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(&mut console)
+}
+
+/// The previous phase is over, summarize it (when pretty) and start the next one
+pub(crate) fn phase(name: &str) {
+    with_console(|console| {
+        if let Some(finished) = console.phase.take() {
+            print_phase_summary(&finished, Style::Success);
+        }
+
+        if pretty() {
+            println!("{} {}", paint("==>", Style::Heading), paint(name, Style::Heading));
+        }
+
+        console.phase = Some(Phase {
+            name: name.to_string(),
+            started: Instant::now(),
+            counts: Vec::new(),
+        });
+    })
+}
+
+/// Something the current phase went through (e.g. crypto objects scanned), for its summary
+pub(crate) fn count(label: &str, count: usize) {
+    with_console(|console| {
+        if let Some(phase) = &mut console.phase {
+            phase.counts.push((label.to_string(), count));
+        }
+    })
+}
+
+/// A table to print at the end of the run when pretty, see finish. Returns false if the output
+/// isn't pretty, for the caller to print the same information plainly instead
+pub(crate) fn table(title: &str, headers: &[&str], rows: Vec<Vec<String>>) -> bool {
+    if !pretty() {
+        return false;
+    }
+
+    with_console(|console| {
+        console.tables.push(Table {
+            title: title.to_string(),
+            headers: headers.iter().map(|header| header.to_string()).collect(),
+            rows,
+        })
+    });
+
+    true
+}
+
+/// Summarize the last phase and print the tables, when pretty
+pub(crate) fn finish(result: &Result<()>) {
+    with_console(|console| {
+        if let Some(last) = console.phase.take() {
+            print_phase_summary(&last, if result.is_ok() { Style::Success } else { Style::Failure });
+        }
+
+        if pretty() {
+            for table in console.tables.drain(..) {
+                println!();
+                println!("{}", paint(&table.title, Style::Heading));
+                print!("{}", render_table(&table));
+            }
+        }
+    })
+}
+
+fn print_phase_summary(phase: &Phase, style: Style) {
+    if !pretty() {
+        return;
+    }
+
+    let mark = match style {
+        Style::Failure => "✗",
+        _ => "✓",
+    };
+    let counts = phase
+        .counts
+        .iter()
+        .map(|(label, count)| format!("{} {}", count, label))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    println!(
+        "{} {} {}{}",
+        paint(mark, style),
+        phase.name,
+        paint(&format!("in {}", format_duration(phase.started.elapsed())), Style::Dim),
+        if counts.is_empty() {
+            String::new()
+        } else {
+            format!(": {}", counts)
+        }
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    match duration.as_secs() {
+        0 => format!("{}ms", duration.as_millis()),
+        seconds if seconds < 60 => format!("{:.1}s", duration.as_secs_f64()),
+        seconds => format!("{}m{:02}s", seconds / 60, seconds % 60),
+    }
+}
+
+/// Columns padded to their widest cell, the first one left aligned (it's usually a name) and the
+/// rest right aligned (they're usually numbers)
+fn render_table(table: &Table) -> String {
+    let widths = table
+        .headers
+        .iter()
+        .enumerate()
+        .map(|(column, header)| {
+            table
+                .rows
+                .iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| cell.chars().count())
+                .chain([header.chars().count()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+
+    let render_row = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(column, (cell, width))| {
+                if column == 0 {
+                    format!("{:<width$}", cell, width = *width)
+                } else {
+                    format!("{:>width$}", cell, width = *width)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut rendered = format!("  {}\n", paint(&render_row(&table.headers), Style::Dim));
+    for row in &table.rows {
+        rendered.push_str(&format!("  {}\n", render_row(row)));
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_table() {
+        let table = Table {
+            title: "Chains".to_string(),
+            headers: vec!["ROOT".to_string(), "KEYS".to_string()],
+            rows: vec![
+                vec!["CN=etcd-signer".to_string(), "12".to_string()],
+                vec!["CN=root-ca".to_string(), "3".to_string()],
+            ],
+        };
+
+        // Never colored, as init is never called in tests
+        assert_eq!(
+            render_table(&table),
+            "  ROOT            KEYS\n  CN=etcd-signer    12\n  CN=root-ca         3\n"
+        );
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+        assert_eq!(format_duration(Duration::from_millis(12_340)), "12.3s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m05s");
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{
+    cluster_crypto::locations::K8sResourceLocation,
+    file_utils,
+    k8s_etcd::{get_etcd_yaml, InMemoryK8sEtcd},
+};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as base64_standard, Engine as _};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+/// The static pods whose resources the operators sync to disk, along with the namespace of the
+/// secrets and configmaps they're synced from
+const STATIC_POD_NAMESPACES: [(&str, &str); 4] = [
+    ("kube-apiserver", "openshift-kube-apiserver"),
+    ("kube-controller-manager", "openshift-kube-controller-manager"),
+    ("kube-scheduler", "openshift-kube-scheduler"),
+    ("etcd", "openshift-etcd"),
+];
+
+/// The etcd resource a file in static-pod-resources is a copy of, and the data key it's a copy of
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct EtcdCopy {
+    kind: &'static str,
+    namespace: &'static str,
+    name: String,
+    key: String,
+}
+
+/// The files of each static-pod-resources secret or configmap dir, keyed by the etcd key of the
+/// resource they're copies of and by the dir
+type DiskCopies = BTreeMap<(String, PathBuf), Vec<(EtcdCopy, Vec<u8>)>>;
+
+impl EtcdCopy {
+    fn location(&self) -> K8sResourceLocation {
+        K8sResourceLocation::new(Some(self.namespace), self.kind, &self.name, "v1")
+    }
+
+    fn plural(&self) -> &'static str {
+        match self.kind {
+            "Secret" => "secrets",
+            _ => "configmaps",
+        }
+    }
+}
+
+/// The operators sync many of the certs and keys in etcd to the static pod resources on disk,
+/// under static-pod-resources/<static pod>-certs (the latest copy) and
+/// static-pod-resources/<static pod>-pod-<revision> (a copy per revision). After committing, both
+/// copies of each of these must be the same, or else the static pods and the operators disagree on
+/// which certs are in use. Copies which differ fail the check. Copies which exist in only one
+/// place, which usually means the seed was only partially synced when it was taken, are reported
+pub(crate) async fn cross_check(etcd_client: &InMemoryK8sEtcd, static_dirs: &[PathBuf]) -> Result<()> {
+    let mut disk_copies = DiskCopies::new();
+    for dir in static_dirs {
+        for file_path in file_utils::globvec(dir, "**/static-pod-resources/*/*/*/*")? {
+            let Some(etcd_copy) = expected_etcd_copy(&file_path) else {
+                continue;
+            };
+
+            let resource_dir = file_path.parent().context("no parent")?.to_path_buf();
+            disk_copies
+                .entry((etcd_copy.location().as_etcd_key(), resource_dir))
+                .or_default()
+                .push((etcd_copy, file_utils::read_file(&file_path).await?));
+        }
+    }
+
+    let mut existing_keys: HashMap<String, BTreeSet<String>> = HashMap::new();
+    let mut mismatches = vec![];
+
+    for ((etcd_key, resource_dir), copies) in disk_copies {
+        let (etcd_copy, _) = copies.first().context("no copies")?;
+
+        let list_prefix = format!("{}/{}/", etcd_copy.plural(), etcd_copy.namespace);
+        if !existing_keys.contains_key(&list_prefix) {
+            let keys = etcd_client.list_keys(&list_prefix).await?.into_iter().collect();
+            existing_keys.insert(list_prefix.clone(), keys);
+        }
+
+        if !existing_keys[&list_prefix].contains(&etcd_key) {
+            if copies.iter().any(|(_, on_disk)| is_crypto(on_disk)) {
+                println!("WARNING: {:?} has no copy in etcd, expected {}", resource_dir, etcd_key);
+            }
+            continue;
+        }
+
+        let etcd_data = resource_data(&get_etcd_yaml(etcd_client, &etcd_copy.location()).await?, etcd_copy.kind)
+            .with_context(|| format!("reading data of {}", etcd_key))?;
+
+        for (etcd_copy, on_disk) in &copies {
+            match etcd_data.get(&etcd_copy.key) {
+                Some(in_etcd) if !is_crypto(in_etcd) && !is_crypto(on_disk) => {}
+                Some(in_etcd) if !same_pem(in_etcd, on_disk) => mismatches.push(format!(
+                    "{:?} and {} {:?}",
+                    resource_dir.join(&etcd_copy.key),
+                    etcd_key,
+                    etcd_copy.key
+                )),
+                Some(_) => {}
+                None if is_crypto(on_disk) => println!(
+                    "WARNING: {:?} has no copy in etcd, expected key {:?} in {}",
+                    resource_dir.join(&etcd_copy.key),
+                    etcd_copy.key,
+                    etcd_key
+                ),
+                None => {}
+            }
+        }
+
+        for (key, in_etcd) in &etcd_data {
+            if is_crypto(in_etcd) && !copies.iter().any(|(etcd_copy, _)| &etcd_copy.key == key) {
+                println!(
+                    "WARNING: {} {:?} has no copy on disk, expected {:?}",
+                    etcd_key,
+                    key,
+                    resource_dir.join(key)
+                );
+            }
+        }
+    }
+
+    if !mismatches.is_empty() {
+        bail!("etcd and disk copies differ after commit:\n{}", mismatches.join("\n"))
+    }
+
+    Ok(())
+}
+
+/// The etcd copy of a file at static-pod-resources/<dir>/<secrets|configmaps>/<name>/<key>, if
+/// it's in one of the dirs the operators sync
+fn expected_etcd_copy(file_path: &Path) -> Option<EtcdCopy> {
+    let components = file_path.iter().map(|component| component.to_str()).collect::<Option<Vec<_>>>()?;
+    let [.., "static-pod-resources", static_pod_dir, kind_dir, name, key] = components.as_slice() else {
+        return None;
+    };
+
+    let kind = match *kind_dir {
+        "secrets" => "Secret",
+        "configmaps" => "ConfigMap",
+        _ => return None,
+    };
+
+    STATIC_POD_NAMESPACES.into_iter().find_map(|(static_pod, namespace)| {
+        let suffix = static_pod_dir.strip_prefix(static_pod)?;
+
+        let name = if suffix == "-certs" {
+            name.to_string()
+        } else {
+            // Revisioned copies come from the revisioned resources, which have the revision as a suffix
+            let revision = suffix.strip_prefix("-pod-")?;
+            if revision.is_empty() || !revision.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            format!("{}-{}", name, revision)
+        };
+
+        Some(EtcdCopy {
+            kind,
+            namespace,
+            name,
+            key: key.to_string(),
+        })
+    })
+}
+
+/// All data of a secret or configmap, decoded
+fn resource_data(resource: &Value, kind: &str) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut data = BTreeMap::new();
+
+    if let Some(entries) = resource.pointer("/data").and_then(Value::as_object) {
+        for (key, value) in entries {
+            let value = value.as_str().context("data value not a string")?;
+            data.insert(
+                key.clone(),
+                if kind == "Secret" {
+                    base64_standard.decode(value).with_context(|| format!("decoding {:?}", key))?
+                } else {
+                    value.as_bytes().to_vec()
+                },
+            );
+        }
+    }
+
+    if let Some(entries) = resource.pointer("/binaryData").and_then(Value::as_object) {
+        for (key, value) in entries {
+            let value = value.as_str().context("binaryData value not a string")?;
+            data.insert(
+                key.clone(),
+                base64_standard.decode(value).with_context(|| format!("decoding {:?}", key))?,
+            );
+        }
+    }
+
+    Ok(data)
+}
+
+/// Only copies holding certs or keys are checked, other synced files (configs, scripts) are none
+/// of recert's business
+fn is_crypto(contents: &[u8]) -> bool {
+    contents.windows(b"-----BEGIN ".len()).any(|window| window == b"-----BEGIN ")
+}
+
+/// Whether both contain the same PEMs, regardless of formatting differences like trailing newlines
+fn same_pem(a: &[u8], b: &[u8]) -> bool {
+    match (pem::parse_many(a), pem::parse_many(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_etcd_copy() {
+        assert_eq!(
+            expected_etcd_copy(Path::new(
+                "/etc/kubernetes/static-pod-resources/kube-apiserver-certs/secrets/localhost-serving-cert-certkey/tls.crt"
+            )),
+            Some(EtcdCopy {
+                kind: "Secret",
+                namespace: "openshift-kube-apiserver",
+                name: "localhost-serving-cert-certkey".to_string(),
+                key: "tls.crt".to_string(),
+            })
+        );
+
+        assert_eq!(
+            expected_etcd_copy(Path::new(
+                "/etc/kubernetes/static-pod-resources/etcd-pod-3/configmaps/etcd-serving-ca/ca-bundle.crt"
+            )),
+            Some(EtcdCopy {
+                kind: "ConfigMap",
+                namespace: "openshift-etcd",
+                name: "etcd-serving-ca-3".to_string(),
+                key: "ca-bundle.crt".to_string(),
+            })
+        );
+
+        assert_eq!(
+            expected_etcd_copy(Path::new("/etc/kubernetes/static-pod-resources/etcd-pod-3/etcd-pod.yaml")),
+            None
+        );
+        assert_eq!(
+            expected_etcd_copy(Path::new("/etc/kubernetes/static-pod-resources/etcd-member/secrets/a/tls.crt")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_same_pem() {
+        let pem = b"-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n";
+        assert!(is_crypto(pem));
+        assert!(same_pem(pem, &pem[..pem.len() - 1]));
+        assert!(!same_pem(pem, b"-----BEGIN CERTIFICATE-----\nBBBB\n-----END CERTIFICATE-----\n"));
+        assert!(!is_crypto(b"apiVersion: v1"));
+    }
+}
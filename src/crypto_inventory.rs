@@ -0,0 +1,178 @@
+use crate::{
+    cluster_crypto::{crypto_utils::public_key_fingerprint, locations::Locations},
+    deterministic,
+    key_continuity::{cert_fingerprints, private_key_fingerprint, KeyContinuity},
+};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// An inventory of the crypto material in the cluster once recert is done, in the shape of a
+/// CycloneDX 1.6 cryptography bill of materials, for compliance tooling attesting which certs and
+/// keys ship in an image. Unlike the run summary, only the material as it ends up is listed, not
+/// what it replaced. Built from the objects recorded before regeneration, which by now hold their
+/// regenerated versions
+fn render(key_continuity: &KeyContinuity) -> Result<String> {
+    let occurrences = |locations: &Locations| {
+        let mut locations = locations.0.iter().map(|location| location.to_string()).collect::<Vec<_>>();
+        locations.sort();
+        serde_json::json!({
+            "occurrences": locations
+                .into_iter()
+                .map(|location| serde_json::json!({ "location": location }))
+                .collect::<Vec<_>>(),
+        })
+    };
+
+    let mut components = vec![];
+    for (cert_key_pair, _) in &key_continuity.cert_key_pairs {
+        let cert_key_pair = (**cert_key_pair).borrow();
+        let (fingerprint, key_fingerprint) = cert_fingerprints(&cert_key_pair)?;
+        let distributed_cert = (*cert_key_pair.distributed_cert).borrow();
+        let certificate = &distributed_cert.certificate;
+        components.push(serde_json::json!({
+            "type": "cryptographic-asset",
+            "bom-ref": format!("cert:{}", hex_digest(&fingerprint)),
+            "name": certificate.subject,
+            "hashes": [{ "alg": "SHA-256", "content": hex_digest(&fingerprint) }],
+            "cryptoProperties": {
+                "assetType": "certificate",
+                "certificateProperties": {
+                    "subjectName": certificate.subject,
+                    "issuerName": certificate.issuer,
+                    "notValidBefore": certificate.not_before().to_rfc3339(),
+                    "notValidAfter": certificate.not_after().to_rfc3339(),
+                    "certificateFormat": "X.509",
+                    "subjectPublicKeyRef": format!("key:{}", hex_digest(&key_fingerprint)),
+                },
+            },
+            "evidence": occurrences(&distributed_cert.locations),
+        }));
+
+        // Paired keys aren't among the standalone private keys
+        if let Some(private_key) = &cert_key_pair.distributed_private_key {
+            components.push(key_component(
+                "private-key",
+                &key_fingerprint,
+                occurrences(&(**private_key).borrow().locations),
+            ));
+        }
+    }
+
+    for (private_key, _) in &key_continuity.private_keys {
+        let private_key = (**private_key).borrow();
+        components.push(key_component(
+            "private-key",
+            &private_key_fingerprint(&private_key)?,
+            occurrences(&private_key.locations),
+        ));
+    }
+
+    for (public_key, _) in &key_continuity.public_keys {
+        let public_key = (**public_key).borrow();
+        components.push(key_component(
+            "public-key",
+            &public_key_fingerprint(&public_key.key)?,
+            occurrences(&public_key.locations),
+        ));
+    }
+
+    Ok(serde_json::to_string_pretty(&serde_json::json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.6",
+        "version": 1,
+        "metadata": {
+            "timestamp": deterministic::now().to_rfc3339(),
+            "tools": {
+                "components": [{ "type": "application", "name": "recert", "version": env!("CARGO_PKG_VERSION") }],
+            },
+        },
+        "components": components,
+    }))?)
+}
+
+/// Keys are identified by the fingerprint of their public key, so a private key and the cert
+/// (or public key) it belongs to share their reference
+fn key_component(kind: &str, key_fingerprint: &str, evidence: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "type": "cryptographic-asset",
+        "bom-ref": format!("{}:{}", kind, hex_digest(key_fingerprint)),
+        "name": kind,
+        "cryptoProperties": {
+            "assetType": "related-crypto-material",
+            "relatedCryptoMaterialProperties": {
+                "type": kind,
+                "id": format!("key:{}", hex_digest(key_fingerprint)),
+            },
+        },
+        "evidence": evidence,
+    })
+}
+
+/// CycloneDX hashes are plain lowercase hex, rather than the colon separated form we use elsewhere
+fn hex_digest(fingerprint: &str) -> String {
+    fingerprint.replace(':', "").to_ascii_lowercase()
+}
+
+pub(crate) fn write(path: &Path, key_continuity: &KeyContinuity) -> Result<()> {
+    std::fs::write(path, render(key_continuity)?).with_context(|| format!("writing crypto inventory to {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster_crypto::{
+            crypto_objects,
+            locations::{FileContentLocation, FileLocation, Location, LocationValueType},
+            private_key_format::PrivateKeyPolicy,
+            ClusterCryptoObjects,
+        },
+        test_fixtures::CertFixture,
+    };
+
+    #[test]
+    fn test_render() {
+        let ca = CertFixture::ca("root");
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        for (path, contents) in [("/ca.crt", &ca.cert_pem), ("/ca.key", &ca.key_pem)] {
+            cluster_crypto.register_discovered_crypto_objects(
+                crypto_objects::process_pem_bundle(
+                    contents,
+                    &Location::Filesystem(FileLocation {
+                        path: path.to_string(),
+                        content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+                    }),
+                    &PrivateKeyPolicy::default(),
+                )
+                .unwrap(),
+            );
+        }
+        cluster_crypto.pair_certs_and_keys().unwrap();
+
+        let key_continuity = KeyContinuity::record_originals(&cluster_crypto).unwrap();
+        let inventory: serde_json::Value = serde_json::from_str(&render(&key_continuity).unwrap()).unwrap();
+        assert_eq!(inventory["bomFormat"], "CycloneDX");
+
+        let components = inventory["components"].as_array().unwrap();
+        let cert = &components[0];
+        let certificate_properties = &cert["cryptoProperties"]["certificateProperties"];
+        assert_eq!(certificate_properties["subjectName"], ca.subject());
+        assert_eq!(certificate_properties["issuerName"], ca.subject());
+        assert_eq!(
+            cert["evidence"]["occurrences"],
+            serde_json::json!([{ "location": "file:/ca.crt::pem0" }])
+        );
+        assert_eq!(cert["hashes"][0]["content"].as_str().unwrap().len(), 64);
+
+        // The cert's private key is listed as well, referenced by the cert
+        assert_eq!(components.len(), 2);
+        assert_eq!(
+            components[1]["evidence"]["occurrences"],
+            serde_json::json!([{ "location": "file:/ca.key::pem0" }])
+        );
+        assert_eq!(
+            components[1]["cryptoProperties"]["relatedCryptoMaterialProperties"]["id"],
+            certificate_properties["subjectPublicKeyRef"]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use rand::{rngs::StdRng, SeedableRng};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+
+/// The clock of deterministic runs unless SOURCE_DATE_EPOCH says otherwise, 2024-01-01T00:00:00Z
+const DEFAULT_FIXED_TIME: i64 = 1_704_067_200;
+
+/// Everything which would otherwise differ between two runs with the same inputs (the new keys,
+/// the signatures made with them, the times regenerated certs and tokens are dated from and the
+/// random suffixes of renames) derived from a seed and a fixed clock instead, so that the runs
+/// produce byte-identical output. Serial numbers are kept as they are either way. Anyone who
+/// knows the seed can derive every new private key, so this is for integration tests only, see
+/// --deterministic-seed
+struct Deterministic {
+    seed: u64,
+    fixed_time: DateTime<Utc>,
+}
+
+static DETERMINISTIC: OnceLock<Deterministic> = OnceLock::new();
+
+pub(crate) fn init(seed: Option<u64>) -> Result<()> {
+    let Some(seed) = seed else {
+        return Ok(());
+    };
+
+    let fixed_time = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch.parse().context("parsing SOURCE_DATE_EPOCH")?,
+        Err(_) => DEFAULT_FIXED_TIME,
+    };
+    let fixed_time = Utc
+        .timestamp_opt(fixed_time, 0)
+        .single()
+        .context("SOURCE_DATE_EPOCH out of range")?;
+
+    DETERMINISTIC
+        .set(Deterministic { seed, fixed_time })
+        .ok()
+        .context("deterministic mode already initialized")?;
+
+    println!(
+        "WARNING: deterministic mode, every new key can be derived from the seed and the clock is fixed at {}. Never use this on a real cluster",
+        fixed_time.to_rfc3339()
+    );
+
+    Ok(())
+}
+
+pub(crate) fn enabled() -> bool {
+    DETERMINISTIC.get().is_some()
+}
+
+/// The fixed time of deterministic runs, None otherwise
+pub(crate) fn fixed_time() -> Option<DateTime<Utc>> {
+    DETERMINISTIC.get().map(|deterministic| deterministic.fixed_time)
+}
+
+/// The current time, or the fixed time of deterministic runs
+pub(crate) fn now() -> DateTime<Utc> {
+    fixed_time().unwrap_or_else(Utc::now)
+}
+
+/// A RNG for generating the value identified by purpose and identity (e.g. the replacement of
+/// the key with the given public key) in deterministic runs, None otherwise. Derived from the
+/// seed and what it's for rather than drawn from a single stream, so that every value comes out
+/// the same no matter in which order the values are generated, which depends on scheduling
+pub(crate) fn rng_for(purpose: &str, identity: &[u8]) -> Option<StdRng> {
+    let deterministic = DETERMINISTIC.get()?;
+    Some(derive_rng(deterministic.seed, purpose, identity))
+}
+
+fn derive_rng(seed: u64, purpose: &str, identity: &[u8]) -> StdRng {
+    let mut hasher = Sha256::new();
+    hasher.update(seed.to_be_bytes());
+    // Length prefixed, so that no purpose and identity can run into each other
+    hasher.update((purpose.len() as u64).to_be_bytes());
+    hasher.update(purpose.as_bytes());
+    hasher.update(identity);
+    StdRng::from_seed(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    #[test]
+    fn test_derive_rng() {
+        let next = |seed, purpose, identity: &[u8]| derive_rng(seed, purpose, identity).next_u64();
+
+        assert_eq!(next(1, "key", b"original"), next(1, "key", b"original"));
+        assert_ne!(next(1, "key", b"original"), next(2, "key", b"original"));
+        assert_ne!(next(1, "key", b"original"), next(1, "key", b"other"));
+        assert_ne!(next(1, "key", b"original"), next(1, "keyo", b"riginal"));
+    }
+}
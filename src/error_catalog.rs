@@ -0,0 +1,180 @@
+use crate::{cleanup::Panicked, rsa_key_pool::WeakKey};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use strum::IntoEnumIterator;
+
+/// A class of failure with a stable code, for integrators to map to guidance of their own (in the
+/// user's language) instead of showing recert's error chain. Codes are never reused or changed in
+/// meaning, new classes get new codes. Failures which don't fall into any class are Unclassified,
+/// and integrators are expected to fall back to the error chain for those
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum_macros::EnumIter)]
+pub(crate) enum ErrorCode {
+    Internal,
+    EtcdUnreachable,
+    EtcdRequestFailed,
+    PermissionDenied,
+    FileNotFound,
+    DiskFull,
+    WeakKey,
+    Unclassified,
+}
+
+impl ErrorCode {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::Internal => "RECERT-E0001",
+            ErrorCode::EtcdUnreachable => "RECERT-E0101",
+            ErrorCode::EtcdRequestFailed => "RECERT-E0102",
+            ErrorCode::PermissionDenied => "RECERT-E0201",
+            ErrorCode::FileNotFound => "RECERT-E0202",
+            ErrorCode::DiskFull => "RECERT-E0203",
+            ErrorCode::WeakKey => "RECERT-E0301",
+            ErrorCode::Unclassified => "RECERT-E9999",
+        }
+    }
+
+    /// The English message, with {placeholders} for the parameters of the failure. Every message
+    /// has {phase} (what recert was doing) and {detail} (the innermost cause), some have more
+    pub(crate) fn template(&self) -> &'static str {
+        match self {
+            ErrorCode::Internal => "recert hit an internal error while {phase}, please report it: {detail}",
+            ErrorCode::EtcdUnreachable => "recert couldn't reach etcd while {phase}, check that etcd is running and the endpoint and TLS credentials are right: {detail}",
+            ErrorCode::EtcdRequestFailed => "etcd rejected a request while {phase}: {detail}",
+            ErrorCode::PermissionDenied => "recert was denied access to a file while {phase}, check the permissions and the sandbox: {detail}",
+            ErrorCode::FileNotFound => "a file recert needs is missing, found while {phase}: {detail}",
+            ErrorCode::DiskFull => "the disk filled up while {phase}: {detail}",
+            ErrorCode::WeakKey => "the cluster has a weak {bits}-bit RSA key, which recert only replaces with --upgrade-weak-crypto",
+            ErrorCode::Unclassified => "recert failed while {phase}: {detail}",
+        }
+    }
+}
+
+/// A failure classified into the catalog, along with the parameters of its message
+#[derive(Debug)]
+pub(crate) struct CatalogedError {
+    pub(crate) code: ErrorCode,
+    pub(crate) params: BTreeMap<&'static str, String>,
+}
+
+impl CatalogedError {
+    /// The first cause in the chain which falls into a class decides the class, as the outer
+    /// layers only add context
+    pub(crate) fn classify(error: &anyhow::Error, phase: &str) -> Self {
+        let mut params = BTreeMap::from([("phase", phase.to_string()), ("detail", error.root_cause().to_string())]);
+
+        let code = error
+            .chain()
+            .find_map(|cause| {
+                if cause.is::<Panicked>() {
+                    Some(ErrorCode::Internal)
+                } else if let Some(weak_key) = cause.downcast_ref::<WeakKey>() {
+                    params.insert("bits", weak_key.size.to_string());
+                    Some(ErrorCode::WeakKey)
+                } else if let Some(etcd_error) = cause.downcast_ref::<etcd_client::Error>() {
+                    Some(classify_etcd(etcd_error))
+                } else {
+                    cause.downcast_ref::<std::io::Error>().and_then(classify_io)
+                }
+            })
+            .unwrap_or(ErrorCode::Unclassified);
+
+        Self { code, params }
+    }
+
+    pub(crate) fn message(&self) -> String {
+        render(self.code.template(), &self.params)
+    }
+}
+
+fn classify_etcd(error: &etcd_client::Error) -> ErrorCode {
+    match error {
+        etcd_client::Error::TransportError(_) => ErrorCode::EtcdUnreachable,
+        etcd_client::Error::GRpcStatus(status) if status.code() == tonic::Code::Unavailable => ErrorCode::EtcdUnreachable,
+        _ => ErrorCode::EtcdRequestFailed,
+    }
+}
+
+fn classify_io(error: &std::io::Error) -> Option<ErrorCode> {
+    match error.kind() {
+        std::io::ErrorKind::PermissionDenied => Some(ErrorCode::PermissionDenied),
+        std::io::ErrorKind::NotFound => Some(ErrorCode::FileNotFound),
+        _ if error.raw_os_error() == Some(libc::ENOSPC) => Some(ErrorCode::DiskFull),
+        _ => None,
+    }
+}
+
+/// Placeholders without a parameter are left as they are, so that a template never fails to render
+fn render(template: &str, params: &BTreeMap<&'static str, String>) -> String {
+    params.iter().fold(template.to_string(), |message, (name, value)| {
+        message.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+/// Print every code along with its English message, as JSON, for integrators to build their own
+/// translations from
+pub(crate) fn print_catalog() -> Result<()> {
+    let catalog = ErrorCode::iter()
+        .map(|code| {
+            serde_json::json!({
+                "code": code.code(),
+                "name": format!("{:?}", code),
+                "message": code.template(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    println!("{}", serde_json::to_string_pretty(&catalog)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_codes_unique() {
+        let codes = ErrorCode::iter().map(|code| code.code()).collect::<HashSet<_>>();
+        assert_eq!(codes.len(), ErrorCode::iter().count());
+    }
+
+    #[test]
+    fn test_classify() {
+        let io_error = |kind| {
+            Err::<(), _>(std::io::Error::from(kind))
+                .context("reading \"/etc/kubernetes\"")
+                .unwrap_err()
+        };
+
+        let cataloged = CatalogedError::classify(&io_error(std::io::ErrorKind::PermissionDenied), "scanning");
+        assert_eq!(cataloged.code, ErrorCode::PermissionDenied);
+        assert_eq!(
+            cataloged.message(),
+            "recert was denied access to a file while scanning, check the permissions and the sandbox: permission denied"
+        );
+
+        let cataloged = CatalogedError::classify(&io_error(std::io::ErrorKind::NotFound), "scanning");
+        assert_eq!(cataloged.code, ErrorCode::FileNotFound);
+
+        let weak_key = anyhow::Error::new(WeakKey { size: 1024 }).context("getting rsa key");
+        let cataloged = CatalogedError::classify(&weak_key, "regenerating");
+        assert_eq!(cataloged.code, ErrorCode::WeakKey);
+        assert_eq!(
+            cataloged.message(),
+            "the cluster has a weak 1024-bit RSA key, which recert only replaces with --upgrade-weak-crypto"
+        );
+
+        let panicked = anyhow::Error::new(Panicked("index out of bounds".to_string())).context("regeneration");
+        assert_eq!(CatalogedError::classify(&panicked, "regenerating").code, ErrorCode::Internal);
+
+        let other = anyhow::anyhow!("no cluster version").context("detecting cluster version");
+        let cataloged = CatalogedError::classify(&other, "detecting capabilities");
+        assert_eq!(cataloged.code, ErrorCode::Unclassified);
+        assert_eq!(
+            cataloged.message(),
+            "recert failed while detecting capabilities: no cluster version"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::cluster_crypto::{locations::Locations, ClusterCryptoObjects};
+use anyhow::{bail, Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    str::FromStr,
+};
+use tokio::{io::AsyncWriteExt, process::Command};
+use zeroize::Zeroizing;
+
+/// Who the escrow archive is encrypted to, written as age:RECIPIENT (an age or SSH public key) or
+/// gpg:KEY (a key ID, fingerprint or email address of a key in gpg's keyring)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum EscrowRecipient {
+    Age(String),
+    Gpg(String),
+}
+
+impl FromStr for EscrowRecipient {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (kind, recipient) = value.split_once(':').context("expected age:RECIPIENT or gpg:KEY")?;
+        if recipient.is_empty() {
+            bail!("empty escrow recipient in {:?}", value);
+        }
+
+        match kind {
+            "age" => Ok(EscrowRecipient::Age(recipient.to_string())),
+            "gpg" => Ok(EscrowRecipient::Gpg(recipient.to_string())),
+            _ => bail!("unknown escrow recipient kind {:?}, expected age:RECIPIENT or gpg:KEY", kind),
+        }
+    }
+}
+
+/// Where the original keys and certs are escrowed to, see escrow
+pub(crate) struct EscrowTarget {
+    pub(crate) archive: PathBuf,
+    pub(crate) recipient: EscrowRecipient,
+}
+
+impl EscrowRecipient {
+    /// The command encrypting its stdin to this recipient into the given file
+    fn encrypt_command(&self, archive: &Path) -> Command {
+        let mut command = match self {
+            EscrowRecipient::Age(recipient) => {
+                let mut command = Command::new("age");
+                command.arg("--encrypt").arg("--recipient").arg(recipient);
+                command
+            }
+            EscrowRecipient::Gpg(key) => {
+                let mut command = Command::new("gpg");
+                command
+                    .args(["--batch", "--yes", "--trust-model", "always", "--encrypt", "--recipient"])
+                    .arg(key);
+                command
+            }
+        };
+        command.arg("--output").arg(archive);
+        command
+    }
+}
+
+/// The original private keys and certs, along with where they were found, as a JSON document.
+/// Regeneration replaces them in place, so this has to be taken before anything is regenerated
+fn render_archive(cluster_crypto: &ClusterCryptoObjects) -> Result<Zeroizing<String>> {
+    let locations = |locations: &Locations| locations.0.iter().map(|location| location.to_string()).collect::<Vec<_>>();
+
+    let mut objects = vec![];
+
+    for cert_key_pair in &cluster_crypto.cert_key_pairs {
+        let cert_key_pair = (**cert_key_pair).borrow();
+        let distributed_cert = (*cert_key_pair.distributed_cert).borrow();
+        objects.push(serde_json::json!({
+            "kind": "cert",
+            "subject": distributed_cert.certificate.subject,
+            "locations": locations(&distributed_cert.locations),
+            "pem": distributed_cert.certificate.original.encode_pem(),
+        }));
+
+        if let Some(private_key) = &cert_key_pair.distributed_private_key {
+            let private_key = (**private_key).borrow();
+            objects.push(serde_json::json!({
+                "kind": "private-key",
+                "subject": distributed_cert.certificate.subject,
+                "locations": locations(&private_key.locations),
+                "pem": pem::encode(&private_key.key.pem()?),
+            }));
+        }
+    }
+
+    for private_key in cluster_crypto.distributed_private_keys.values() {
+        let private_key = (**private_key).borrow();
+        objects.push(serde_json::json!({
+            "kind": "private-key",
+            "locations": locations(&private_key.locations),
+            "pem": pem::encode(&private_key.key.pem()?),
+        }));
+    }
+
+    // The intermediate JSON values hold the keys as well, but serde_json::Value can't be zeroized
+    Ok(Zeroizing::new(serde_json::to_string_pretty(
+        &serde_json::json!({ "objects": objects }),
+    )?))
+}
+
+/// Export the original private keys and certs into an archive encrypted to the given recipient,
+/// for manually recovering from a botched run. The plaintext only ever goes to the encryption
+/// tool's stdin, it's never written to disk
+pub(crate) async fn escrow(cluster_crypto: &ClusterCryptoObjects, target: &EscrowTarget) -> Result<()> {
+    let contents = render_archive(cluster_crypto).context("rendering escrow archive")?;
+
+    let mut command = target
+        .recipient
+        .encrypt_command(&target.archive)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("running escrow encryption tool")?;
+
+    command
+        .stdin
+        .take()
+        .context("opening encryption tool's stdin pipe")?
+        .write_all(contents.as_bytes())
+        .await
+        .context("writing to encryption tool's stdin pipe")?;
+
+    let result = command.wait_with_output().await.context("waiting for encryption tool to finish")?;
+
+    if !result.status.success() {
+        bail!(
+            "encrypting escrow archive failed with exit code {:?} and stderr: {}",
+            result.status.code(),
+            String::from_utf8_lossy(&result.stderr)
+        );
+    }
+
+    println!("Escrowed the original keys and certs to {:?}", target.archive);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster_crypto::{
+            crypto_objects,
+            locations::{FileContentLocation, FileLocation, Location, LocationValueType},
+            private_key_format::PrivateKeyPolicy,
+        },
+        test_fixtures::CertFixture,
+    };
+
+    #[test]
+    fn test_escrow_recipient() {
+        assert_eq!(
+            "age:age1qyqszqgpqyqszqgp".parse::<EscrowRecipient>().unwrap(),
+            EscrowRecipient::Age("age1qyqszqgpqyqszqgp".to_string())
+        );
+        assert_eq!(
+            "gpg:ops@example.com".parse::<EscrowRecipient>().unwrap(),
+            EscrowRecipient::Gpg("ops@example.com".to_string())
+        );
+        assert!("age:".parse::<EscrowRecipient>().is_err());
+        assert!("pgp:ops@example.com".parse::<EscrowRecipient>().is_err());
+        assert!("age1qyqszqgpqyqszqgp".parse::<EscrowRecipient>().is_err());
+    }
+
+    #[test]
+    fn test_render_archive() {
+        let ca = CertFixture::ca("root");
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        for (path, contents) in [("/ca.crt", &ca.cert_pem), ("/ca.key", &ca.key_pem)] {
+            cluster_crypto.register_discovered_crypto_objects(
+                crypto_objects::process_pem_bundle(
+                    contents,
+                    &Location::Filesystem(FileLocation {
+                        path: path.to_string(),
+                        content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+                    }),
+                    &PrivateKeyPolicy::default(),
+                )
+                .unwrap(),
+            );
+        }
+        cluster_crypto.pair_certs_and_keys().unwrap();
+
+        let archive: serde_json::Value = serde_json::from_str(&render_archive(&cluster_crypto).unwrap()).unwrap();
+        let objects = archive["objects"].as_array().unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0]["kind"], "cert");
+        assert_eq!(objects[0]["locations"][0], "file:/ca.crt::pem0");
+        assert_eq!(objects[1]["kind"], "private-key");
+        assert_eq!(objects[1]["subject"], ca.subject());
+        let secret_key = |key_pem: &str| {
+            p256::SecretKey::from_sec1_der(pem::parse(key_pem).unwrap().contents())
+                .unwrap()
+                .to_bytes()
+        };
+        assert_eq!(secret_key(objects[1]["pem"].as_str().unwrap()), secret_key(&ca.key_pem));
+    }
+}
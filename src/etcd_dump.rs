@@ -0,0 +1,235 @@
+use crate::{
+    audit::{self, AuditAction},
+    file_utils,
+    k8s_etcd::{run_ouger, EtcdAccess},
+};
+use anyhow::{ensure, Context, Result};
+use etcd_client::GetOptions;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Kubernetes prefixes protobuf encoded values with this
+pub(crate) const PROTOBUF_MAGIC: &[u8] = b"k8s\x00";
+
+/// How a value is stored in etcd, and so how its dump file is named and read back
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DumpFormat {
+    /// Protobuf, decoded with ouger, dumped as KEY.yaml
+    Protobuf,
+    /// JSON, as custom resources are stored, dumped as KEY.json.yaml
+    Json,
+    /// Anything else, dumped verbatim as KEY.raw
+    Raw,
+}
+
+impl DumpFormat {
+    /// Checked in this order, so that .json.yaml comes before .yaml
+    const ALL: [DumpFormat; 3] = [DumpFormat::Json, DumpFormat::Protobuf, DumpFormat::Raw];
+
+    fn detect(value: &[u8]) -> Self {
+        if value.starts_with(PROTOBUF_MAGIC) {
+            DumpFormat::Protobuf
+        } else if serde_json::from_slice::<Value>(value).is_ok_and(|value| value.is_object()) {
+            DumpFormat::Json
+        } else {
+            DumpFormat::Raw
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            DumpFormat::Protobuf => ".yaml",
+            DumpFormat::Json => ".json.yaml",
+            DumpFormat::Raw => ".raw",
+        }
+    }
+
+    /// The dump file contents of a raw etcd value
+    async fn dump(&self, value: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            DumpFormat::Protobuf => to_yaml(&run_ouger("decode", value).await.context("decoding value with ouger")?)?,
+            DumpFormat::Json => to_yaml(value)?,
+            DumpFormat::Raw => value.to_vec(),
+        })
+    }
+
+    /// The raw etcd value of dump file contents
+    async fn load(&self, contents: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            DumpFormat::Protobuf => run_ouger("encode", &serde_json::to_vec(&from_yaml(contents)?)?)
+                .await
+                .context("encoding value with ouger")?,
+            DumpFormat::Json => serde_json::to_vec(&from_yaml(contents)?)?,
+            DumpFormat::Raw => contents.to_vec(),
+        })
+    }
+
+    /// Whether the dump file contents differ from the raw etcd value. Compared as documents rather
+    /// than as bytes, so that going through YAML (which doesn't keep e.g. the order of the fields)
+    /// doesn't make every key look modified
+    async fn differs(&self, contents: &[u8], value: &[u8]) -> Result<bool> {
+        Ok(match self {
+            DumpFormat::Raw => contents != value,
+            _ => from_yaml(contents)? != from_yaml(&self.dump(value).await?)?,
+        })
+    }
+}
+
+fn to_yaml(document: &[u8]) -> Result<Vec<u8>> {
+    Ok(serde_yaml::to_string(&from_yaml(document)?)?.into_bytes())
+}
+
+/// YAML being a superset of JSON, this also parses JSON documents
+fn from_yaml(document: &[u8]) -> Result<Value> {
+    serde_yaml::from_slice(document).context("parsing document")
+}
+
+fn dump_path(dir: &Path, key: &str, format: DumpFormat) -> Result<PathBuf> {
+    let path = dir.join(file_utils::relative_path(Path::new(key))?);
+    let file_name = path.file_name().context("empty key")?.to_string_lossy();
+    Ok(path.with_file_name(format!("{}{}", file_name, format.extension())))
+}
+
+/// The etcd key of a dump file, along with the format it's in. None for files which aren't part
+/// of the dump
+fn parse_dump_path(dir: &Path, path: &Path) -> Result<Option<(String, DumpFormat)>> {
+    let relative = path.strip_prefix(dir).context("dump file outside of the dump dir")?;
+    let relative = relative.to_str().with_context(|| format!("non-unicode dump file {:?}", path))?;
+
+    Ok(DumpFormat::ALL
+        .into_iter()
+        .find_map(|format| relative.strip_suffix(format.extension()).map(|key| (format!("/{}", key), format))))
+}
+
+/// Write every key under the prefix into its own file in the dir, decoded into YAML whenever
+/// possible, for inspecting, diffing and hand-editing the cluster's resources around a recert run.
+/// The dump holds all of the cluster's secrets, so it's only readable by its owner
+pub(crate) async fn dump(etcd_endpoint: &str, etcd_access: &EtcdAccess, dir: &Path, prefix: &str) -> Result<()> {
+    file_utils::create_empty_private_dir(dir, "dump")?;
+
+    let etcd_client = etcd_access.connect(etcd_endpoint).await?;
+    let response = etcd_client
+        .kv_client()
+        .get(prefix, Some(GetOptions::new().with_prefix()))
+        .await
+        .context("listing keys")?;
+
+    for kv in response.kvs() {
+        let key = kv.key_str()?;
+        audit::record(AuditAction::EtcdGet, key, Some(kv.value()))?;
+
+        let value = etcd_access
+            .decrypt(key, kv.value().to_vec())
+            .await
+            .with_context(|| format!("decrypting {}", key))?;
+        let format = DumpFormat::detect(&value);
+        let path = dump_path(dir, key, format)?;
+        let contents = format.dump(&value).await.with_context(|| format!("dumping {}", key))?;
+
+        file_utils::create_private_dir(path.parent().context("dump file without a parent")?)?;
+        file_utils::write_private(&path, &contents)?;
+        audit::record(AuditAction::FileWrite, &path.to_string_lossy(), Some(&contents))?;
+    }
+
+    println!("Dumped {} keys to {:?}", response.kvs().len(), dir);
+
+    Ok(())
+}
+
+/// Put the (possibly hand-edited) files of a dump made by dump back into etcd. Only keys whose
+/// files differ from what's in etcd are written. Keys missing from the dump are left alone. Dumps
+/// are decrypted, so the resources the encryption config covers are encrypted again
+pub(crate) async fn load(etcd_endpoint: &str, etcd_access: &EtcdAccess, dir: &Path) -> Result<()> {
+    let etcd_client = etcd_access.connect(etcd_endpoint).await?;
+
+    let mut loaded = 0;
+    let mut unchanged = 0;
+    for path in file_utils::globvec(dir, "**/*")? {
+        let Some((key, format)) = parse_dump_path(dir, &path)? else {
+            continue;
+        };
+
+        let contents = file_utils::read_file(&path).await?;
+        let response = etcd_client.kv_client().get(key.as_bytes(), None).await?;
+        if let Some(kv) = response.kvs().first() {
+            let current = etcd_access
+                .decrypt(&key, kv.value().to_vec())
+                .await
+                .with_context(|| format!("decrypting {}", key))?;
+            ensure!(
+                DumpFormat::detect(&current) == format,
+                "{:?} doesn't match the format {} is stored in",
+                path,
+                key
+            );
+
+            if !format
+                .differs(&contents, &current)
+                .await
+                .with_context(|| format!("comparing {:?}", path))?
+            {
+                unchanged += 1;
+                continue;
+            }
+        }
+
+        let value = format.load(&contents).await.with_context(|| format!("loading {:?}", path))?;
+        let value = etcd_access
+            .encrypt(&key, value)
+            .await
+            .with_context(|| format!("encrypting {}", key))?;
+        etcd_client.kv_client().put(key.as_bytes(), value.clone(), None).await?;
+        audit::record(AuditAction::EtcdPut, &key, Some(&value))?;
+        println!("Loaded {}", key);
+        loaded += 1;
+    }
+
+    println!("Loaded {} keys from {:?}, {} were unchanged", loaded, dir, unchanged);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dump_paths_and_formats() {
+        let dir = Path::new("/dump");
+        for (key, value, format) in [
+            (
+                "/kubernetes.io/secrets/openshift-config/etcd-client",
+                &b"k8s\x00\n\x0c\n\x02v1"[..],
+                DumpFormat::Protobuf,
+            ),
+            (
+                "/kubernetes.io/machineconfiguration.openshift.io/machineconfigs/00-master",
+                &br#"{"kind":"MachineConfig","metadata":{"name":"00-master"}}"#[..],
+                DumpFormat::Json,
+            ),
+            ("/kubernetes.io/masterleases/192.168.126.10", &b"\x0a\x0b"[..], DumpFormat::Raw),
+        ] {
+            assert_eq!(DumpFormat::detect(value), format);
+            let path = dump_path(dir, key, format).unwrap();
+            assert_eq!(parse_dump_path(dir, &path).unwrap(), Some((key.to_string(), format)));
+        }
+
+        assert_eq!(
+            dump_path(dir, "/kubernetes.io/configmaps/default/kube-root-ca.crt", DumpFormat::Protobuf).unwrap(),
+            Path::new("/dump/kubernetes.io/configmaps/default/kube-root-ca.crt.yaml")
+        );
+        assert!(dump_path(dir, "/kubernetes.io/../escape", DumpFormat::Raw).is_err());
+        assert_eq!(parse_dump_path(dir, Path::new("/dump/README")).unwrap(), None);
+
+        // Going through YAML reorders the fields, which doesn't count as a change
+        let value = br#"{"metadata":{"name":"00-master"},"kind":"MachineConfig"}"#;
+        let contents = DumpFormat::Json.dump(value).await.unwrap();
+        assert!(!DumpFormat::Json.differs(&contents, value).await.unwrap());
+        let edited = String::from_utf8(contents).unwrap().replace("00-master", "00-worker");
+        assert!(DumpFormat::Json.differs(edited.as_bytes(), value).await.unwrap());
+        assert_eq!(
+            from_yaml(&DumpFormat::Json.load(edited.as_bytes()).await.unwrap()).unwrap(),
+            serde_json::json!({"kind": "MachineConfig", "metadata": {"name": "00-worker"}})
+        );
+    }
+}
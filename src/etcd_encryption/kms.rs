@@ -0,0 +1,122 @@
+use anyhow::{ensure, Context, Result};
+use std::path::PathBuf;
+use tokio::net::UnixStream;
+use tonic::{
+    codec::ProstCodec,
+    codegen::http::uri::PathAndQuery,
+    transport::{Endpoint, Uri},
+};
+
+/// The version of the KMS v1 API the plugins implement
+const KMS_API_VERSION: &str = "v1beta1";
+
+/// The KeyManagementService messages of the KMS v1 API (k8s.io/kms/apis/v1beta1), only the ones
+/// needed to encrypt and decrypt data encryption keys
+#[derive(Clone, PartialEq, prost::Message)]
+struct DecryptRequest {
+    #[prost(string, tag = "1")]
+    version: String,
+    #[prost(bytes = "vec", tag = "2")]
+    cipher: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct DecryptResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    plain: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct EncryptRequest {
+    #[prost(string, tag = "1")]
+    version: String,
+    #[prost(bytes = "vec", tag = "2")]
+    plain: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct EncryptResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    cipher: Vec<u8>,
+}
+
+/// A KMS v1 plugin, listening on a unix socket of the node, which holds the key encrypting the
+/// per value data encryption keys (DEKs). The plugin has to be running for recert to read or write
+/// the resources it encrypts
+#[derive(Debug)]
+pub(crate) struct KmsPlugin {
+    pub(crate) name: String,
+    socket: PathBuf,
+}
+
+impl KmsPlugin {
+    /// The endpoint as it appears in the encryption config, e.g. unix:///var/run/kms-plugin.sock
+    pub(crate) fn new(name: &str, endpoint: &str) -> Result<Self> {
+        let socket = endpoint
+            .strip_prefix("unix://")
+            .with_context(|| format!("KMS plugin endpoint {} is not a unix socket", endpoint))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            socket: PathBuf::from(socket),
+        })
+    }
+
+    pub(crate) async fn decrypt(&self, cipher: Vec<u8>) -> Result<Vec<u8>> {
+        let response: DecryptResponse = self
+            .call(
+                "Decrypt",
+                DecryptRequest {
+                    version: KMS_API_VERSION.to_string(),
+                    cipher,
+                },
+            )
+            .await?;
+
+        Ok(response.plain)
+    }
+
+    pub(crate) async fn encrypt(&self, plain: Vec<u8>) -> Result<Vec<u8>> {
+        let response: EncryptResponse = self
+            .call(
+                "Encrypt",
+                EncryptRequest {
+                    version: KMS_API_VERSION.to_string(),
+                    plain,
+                },
+            )
+            .await?;
+
+        ensure!(!response.cipher.is_empty(), "KMS plugin {} returned an empty cipher", self.name);
+        Ok(response.cipher)
+    }
+
+    async fn call<Request, Response>(&self, method: &str, request: Request) -> Result<Response>
+    where
+        Request: prost::Message + Send + Sync + 'static,
+        Response: prost::Message + Default + Send + Sync + 'static,
+    {
+        let socket = self.socket.clone();
+        // The URI is required but unused, the connector always connects to the socket
+        let channel = Endpoint::from_static("http://localhost")
+            .connect_with_connector(tower::service_fn(move |_: Uri| UnixStream::connect(socket.clone())))
+            .await
+            .with_context(|| format!("connecting to KMS plugin {} at {:?}", self.name, self.socket))?;
+
+        let mut client = tonic::client::Grpc::new(channel);
+        client
+            .ready()
+            .await
+            .with_context(|| format!("waiting for KMS plugin {}", self.name))?;
+
+        Ok(client
+            .unary(
+                tonic::Request::new(request),
+                PathAndQuery::try_from(format!("/{}.KeyManagementService/{}", KMS_API_VERSION, method))?,
+                ProstCodec::default(),
+            )
+            .await
+            .with_context(|| format!("calling {} of KMS plugin {}", method, self.name))?
+            .into_inner())
+    }
+}
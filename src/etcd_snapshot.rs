@@ -0,0 +1,385 @@
+use anyhow::{bail, ensure, Context, Result};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, path::Path};
+
+mod bbolt;
+
+/// The bbolt bucket etcd keeps every revision of every key in
+const KEY_BUCKET: &[u8] = b"key";
+
+/// Snapshots taken with etcdctl snapshot save end with the SHA-256 of the database, which
+/// etcdutl snapshot restore checks. They're the only databases whose size isn't a multiple of
+/// 512, see etcd's snapshot restore
+const SNAPSHOT_HASH_SIZE: usize = 32;
+
+/// An etcd database (a snapshot, or the member/snap/db of a data dir) read whole into memory, so
+/// that recert can run fully offline without an etcd serving it. Writes are made the way etcd
+/// itself would make them, as new revisions (all in one transaction) of the keys they change
+pub(crate) struct EtcdSnapshot {
+    database: bbolt::Database,
+    had_hash: bool,
+    /// The revision key (see Revision::to_bytes) of the latest revision of every live key
+    latest: HashMap<String, Vec<u8>>,
+    /// The revision of the transaction the writes are made in
+    main_revision: i64,
+    sub_revision: i64,
+}
+
+/// An etcd MVCC revision, stored big-endian so that bbolt keeps the revisions in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Revision {
+    main: i64,
+    sub: i64,
+}
+
+impl Revision {
+    fn to_bytes(self, tombstone: bool) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(18);
+        bytes.extend(self.main.to_be_bytes());
+        bytes.push(b'_');
+        bytes.extend(self.sub.to_be_bytes());
+        if tombstone {
+            bytes.push(b't');
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, bool)> {
+        ensure!(
+            bytes.len() == 17 || bytes.len() == 18,
+            "revision key of unexpected length {}",
+            bytes.len()
+        );
+
+        Ok((
+            Self {
+                main: i64::from_be_bytes(bytes[0..8].try_into()?),
+                sub: i64::from_be_bytes(bytes[9..17].try_into()?),
+            },
+            bytes.get(17) == Some(&b't'),
+        ))
+    }
+}
+
+/// etcd's mvccpb.KeyValue
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct KeyValue {
+    key: Vec<u8>,
+    create_revision: i64,
+    mod_revision: i64,
+    version: i64,
+    value: Vec<u8>,
+    lease: i64,
+}
+
+fn read_varint(bytes: &[u8], offset: &mut usize) -> Result<u64> {
+    let mut varint = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*offset).context("truncated varint")?;
+        *offset += 1;
+        varint |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(varint);
+        }
+    }
+
+    bail!("varint too long")
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut varint: u64) {
+    while varint >= 0x80 {
+        bytes.push((varint as u8 & 0x7f) | 0x80);
+        varint >>= 7;
+    }
+    bytes.push(varint as u8);
+}
+
+impl KeyValue {
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut key_value = Self::default();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let tag = read_varint(bytes, &mut offset)?;
+            match (tag >> 3, tag & 0x7) {
+                (field @ (1 | 5), 2) => {
+                    let length = read_varint(bytes, &mut offset)? as usize;
+                    let data = bytes.get(offset..offset + length).context("truncated field")?.to_vec();
+                    offset += length;
+                    match field {
+                        1 => key_value.key = data,
+                        _ => key_value.value = data,
+                    }
+                }
+                (field @ (2 | 3 | 4 | 6), 0) => {
+                    let varint = read_varint(bytes, &mut offset)? as i64;
+                    match field {
+                        2 => key_value.create_revision = varint,
+                        3 => key_value.mod_revision = varint,
+                        4 => key_value.version = varint,
+                        _ => key_value.lease = varint,
+                    }
+                }
+                (field, wire_type) => bail!("unexpected KeyValue field {} of wire type {}", field, wire_type),
+            }
+        }
+
+        Ok(key_value)
+    }
+
+    /// Like protobuf, leaves out the fields with default values
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        let write_bytes = |bytes: &mut Vec<u8>, field: u64, data: &[u8]| {
+            if !data.is_empty() {
+                write_varint(bytes, field << 3 | 2);
+                write_varint(bytes, data.len() as u64);
+                bytes.extend(data);
+            }
+        };
+        let write_int = |bytes: &mut Vec<u8>, field: u64, varint: i64| {
+            if varint != 0 {
+                write_varint(bytes, field << 3);
+                write_varint(bytes, varint as u64);
+            }
+        };
+
+        write_bytes(&mut bytes, 1, &self.key);
+        write_int(&mut bytes, 2, self.create_revision);
+        write_int(&mut bytes, 3, self.mod_revision);
+        write_int(&mut bytes, 4, self.version);
+        write_bytes(&mut bytes, 5, &self.value);
+        write_int(&mut bytes, 6, self.lease);
+
+        bytes
+    }
+}
+
+impl EtcdSnapshot {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let mut bytes = std::fs::read(path).with_context(|| format!("reading {:?}", path))?;
+
+        let had_hash = bytes.len() % 512 == SNAPSHOT_HASH_SIZE;
+        if had_hash {
+            let hash = bytes.split_off(bytes.len() - SNAPSHOT_HASH_SIZE);
+            ensure!(Sha256::digest(&bytes).as_slice() == hash, "snapshot hash mismatch, is it corrupt?");
+        }
+
+        let database = bbolt::Database::parse(&bytes).context("parsing bbolt database")?;
+
+        let mut latest = HashMap::new();
+        let mut max_revision = Revision { main: 0, sub: 0 };
+        for (revision_bytes, value) in database
+            .root
+            .bucket(KEY_BUCKET)
+            .context("no key bucket, not an etcd database")?
+            .values()
+        {
+            let (revision, tombstone) = Revision::from_bytes(revision_bytes)?;
+            let key = String::from_utf8(KeyValue::decode(value).context("decoding KeyValue")?.key)?;
+            max_revision = revision;
+            if tombstone {
+                latest.remove(&key);
+            } else {
+                latest.insert(key, revision_bytes.clone());
+            }
+        }
+
+        Ok(Self {
+            database,
+            had_hash,
+            latest,
+            main_revision: max_revision.main + 1,
+            sub_revision: 0,
+        })
+    }
+
+    fn key_bucket(&self) -> Result<&bbolt::Bucket> {
+        self.database.root.bucket(KEY_BUCKET).context("key bucket missing")
+    }
+
+    fn latest(&self, key: &str) -> Result<Option<KeyValue>> {
+        let Some(revision_bytes) = self.latest.get(key) else {
+            return Ok(None);
+        };
+
+        let Some(bbolt::Entry::Value(value)) = self.key_bucket()?.entries.get(revision_bytes) else {
+            bail!("revision of {} missing", key);
+        };
+
+        Ok(Some(KeyValue::decode(value)?))
+    }
+
+    /// The value of the key, as etcd would return it
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.latest(key)?.map(|key_value| key_value.value))
+    }
+
+    pub(crate) fn list_keys(&self, prefix: &str) -> Vec<String> {
+        let mut keys = self
+            .latest
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    fn next_revision(&mut self) -> Revision {
+        let revision = Revision {
+            main: self.main_revision,
+            sub: self.sub_revision,
+        };
+        self.sub_revision += 1;
+        revision
+    }
+
+    fn write_revision(&mut self, revision_bytes: Vec<u8>, key_value: &KeyValue) -> Result<()> {
+        self.database
+            .root
+            .bucket_mut(KEY_BUCKET)
+            .context("key bucket missing")?
+            .entries
+            .insert(revision_bytes, bbolt::Entry::Value(key_value.encode()));
+        Ok(())
+    }
+
+    pub(crate) fn put(&mut self, key: &str, value: Vec<u8>) -> Result<()> {
+        let revision = self.next_revision();
+        let key_value = match self.latest(key)? {
+            Some(latest) => KeyValue {
+                mod_revision: revision.main,
+                version: latest.version + 1,
+                value,
+                ..latest
+            },
+            None => KeyValue {
+                key: key.as_bytes().to_vec(),
+                create_revision: revision.main,
+                mod_revision: revision.main,
+                version: 1,
+                value,
+                lease: 0,
+            },
+        };
+
+        self.write_revision(revision.to_bytes(false), &key_value)?;
+        self.latest.insert(key.to_string(), revision.to_bytes(false));
+        Ok(())
+    }
+
+    pub(crate) fn delete(&mut self, key: &str) -> Result<()> {
+        if !self.latest.contains_key(key) {
+            return Ok(());
+        }
+
+        let revision = self.next_revision();
+        self.write_revision(
+            revision.to_bytes(true),
+            &KeyValue {
+                key: key.as_bytes().to_vec(),
+                mod_revision: revision.main,
+                ..KeyValue::default()
+            },
+        )?;
+        self.latest.remove(key);
+        Ok(())
+    }
+
+    /// Write the database out, with a hash if the original snapshot had one
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let mut bytes = self.database.serialize();
+        if self.had_hash {
+            let hash = Sha256::digest(&bytes);
+            bytes.extend(hash);
+        }
+
+        std::fs::write(path, bytes).with_context(|| format!("writing {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(key_values: &[(Revision, bool, KeyValue)]) -> EtcdSnapshot {
+        let key_bucket = bbolt::Bucket {
+            sequence: 0,
+            entries: key_values
+                .iter()
+                .map(|(revision, tombstone, key_value)| (revision.to_bytes(*tombstone), bbolt::Entry::Value(key_value.encode())))
+                .collect(),
+        };
+        let database = bbolt::Database::new(bbolt::Bucket {
+            sequence: 0,
+            entries: [(KEY_BUCKET.to_vec(), bbolt::Entry::Bucket(key_bucket))].into(),
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.db");
+        let mut bytes = database.serialize();
+        bytes.extend(Sha256::digest(&bytes));
+        std::fs::write(&path, bytes).unwrap();
+        EtcdSnapshot::open(&path).unwrap()
+    }
+
+    fn key_value(key: &str, revision: i64, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.as_bytes().to_vec(),
+            create_revision: 2,
+            mod_revision: revision,
+            version: revision - 1,
+            value: value.as_bytes().to_vec(),
+            lease: 0,
+        }
+    }
+
+    #[test]
+    fn test_key_value_roundtrip() {
+        let key_value = KeyValue {
+            lease: 7,
+            ..key_value("/kubernetes.io/secrets/ns/name", 300, "\u{0}k8s\u{0}protobuf")
+        };
+        assert_eq!(KeyValue::decode(&key_value.encode()).unwrap(), key_value);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let revision = |main| Revision { main, sub: 0 };
+        let mut snapshot = snapshot(&[
+            (revision(2), false, key_value("/kubernetes.io/secrets/ns/a", 2, "a1")),
+            (revision(3), false, key_value("/kubernetes.io/secrets/ns/b", 3, "b1")),
+            (revision(4), false, key_value("/kubernetes.io/secrets/ns/a", 4, "a2")),
+            (revision(5), true, key_value("/kubernetes.io/secrets/ns/b", 5, "")),
+            (revision(6), false, key_value("/kubernetes.io/configmaps/ns/c", 6, "c1")),
+        ]);
+
+        assert_eq!(snapshot.get("/kubernetes.io/secrets/ns/a").unwrap(), Some(b"a2".to_vec()));
+        assert_eq!(snapshot.get("/kubernetes.io/secrets/ns/b").unwrap(), None);
+        assert_eq!(snapshot.list_keys("/kubernetes.io/secrets/"), vec!["/kubernetes.io/secrets/ns/a"]);
+
+        snapshot.put("/kubernetes.io/secrets/ns/a", b"a3".to_vec()).unwrap();
+        snapshot.put("/kubernetes.io/secrets/ns/d", b"d1".to_vec()).unwrap();
+        snapshot.delete("/kubernetes.io/configmaps/ns/c").unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.db");
+        snapshot.write(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len() % 512, SNAPSHOT_HASH_SIZE as u64);
+
+        let written = EtcdSnapshot::open(&path).unwrap();
+        assert_eq!(written.get("/kubernetes.io/secrets/ns/a").unwrap(), Some(b"a3".to_vec()));
+        assert_eq!(written.get("/kubernetes.io/configmaps/ns/c").unwrap(), None);
+        assert_eq!(
+            written.list_keys("/kubernetes.io/"),
+            vec!["/kubernetes.io/secrets/ns/a", "/kubernetes.io/secrets/ns/d"]
+        );
+
+        // The writes are new revisions of one transaction, like etcd would have made them
+        let a = written.latest("/kubernetes.io/secrets/ns/a").unwrap().unwrap();
+        assert_eq!((a.create_revision, a.mod_revision, a.version), (2, 7, 4));
+        let d = written.latest("/kubernetes.io/secrets/ns/d").unwrap().unwrap();
+        assert_eq!((d.create_revision, d.mod_revision, d.version), (7, 7, 1));
+        assert_eq!(written.main_revision, 8);
+    }
+}
@@ -0,0 +1,458 @@
+//! Just enough of the bbolt file format to read a whole database into memory and write it back
+//! out as a fresh, compact database. See https://github.com/etcd-io/bbolt for the format, all
+//! integers are little-endian, as on every platform etcd runs on
+
+use anyhow::{bail, ensure, Context, Result};
+use std::collections::BTreeMap;
+
+const MAGIC: u32 = 0xED0C_DAED;
+const VERSION: u32 = 2;
+
+const PAGE_HEADER_SIZE: usize = 16;
+const ELEMENT_SIZE: usize = 16;
+const BUCKET_HEADER_SIZE: usize = 16;
+const META_CHECKSUMMED_SIZE: usize = 56;
+
+const BRANCH_PAGE_FLAG: u16 = 0x01;
+const LEAF_PAGE_FLAG: u16 = 0x02;
+const META_PAGE_FLAG: u16 = 0x04;
+const FREELIST_PAGE_FLAG: u16 = 0x10;
+
+const BUCKET_LEAF_FLAG: u32 = 0x01;
+
+/// The page size bbolt uses when the OS doesn't say otherwise, for finding the second meta page
+/// when the first one is corrupt
+const DEFAULT_PAGE_SIZE: usize = 4096;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Bucket {
+    pub(crate) sequence: u64,
+    pub(crate) entries: BTreeMap<Vec<u8>, Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Entry {
+    Value(Vec<u8>),
+    Bucket(Bucket),
+}
+
+impl Bucket {
+    pub(crate) fn bucket(&self, name: &[u8]) -> Option<&Bucket> {
+        match self.entries.get(name) {
+            Some(Entry::Bucket(bucket)) => Some(bucket),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn bucket_mut(&mut self, name: &[u8]) -> Option<&mut Bucket> {
+        match self.entries.get_mut(name) {
+            Some(Entry::Bucket(bucket)) => Some(bucket),
+            _ => None,
+        }
+    }
+
+    /// The (non-bucket) values of this bucket
+    pub(crate) fn values(&self) -> impl Iterator<Item = (&Vec<u8>, &Vec<u8>)> {
+        self.entries.iter().filter_map(|(key, entry)| match entry {
+            Entry::Value(value) => Some((key, value)),
+            Entry::Bucket(_) => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Database {
+    page_size: usize,
+    txid: u64,
+    pub(crate) root: Bucket,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16> {
+    Ok(u16::from_le_bytes(bytes.get(offset..offset + 2).context("truncated")?.try_into()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(bytes.get(offset..offset + 4).context("truncated")?.try_into()?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    Ok(u64::from_le_bytes(bytes.get(offset..offset + 8).context("truncated")?.try_into()?))
+}
+
+fn fnv64a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+struct Meta {
+    page_size: usize,
+    root: u64,
+    root_sequence: u64,
+    txid: u64,
+}
+
+fn parse_meta(bytes: &[u8], page_offset: usize) -> Result<Meta> {
+    let page = bytes.get(page_offset..).context("meta page out of bounds")?;
+    ensure!(read_u16(page, 8)? & META_PAGE_FLAG != 0, "not a meta page");
+
+    let meta = page.get(PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 64).context("truncated meta page")?;
+    ensure!(read_u32(meta, 0)? == MAGIC, "not a bbolt database");
+    ensure!(read_u32(meta, 4)? == VERSION, "unsupported bbolt version {}", read_u32(meta, 4)?);
+    ensure!(
+        read_u64(meta, META_CHECKSUMMED_SIZE)? == fnv64a(&meta[..META_CHECKSUMMED_SIZE]),
+        "meta page checksum mismatch"
+    );
+
+    Ok(Meta {
+        page_size: read_u32(meta, 8)? as usize,
+        root: read_u64(meta, 16)?,
+        root_sequence: read_u64(meta, 24)?,
+        txid: read_u64(meta, 48)?,
+    })
+}
+
+impl Database {
+    /// A database holding the root bucket, as if it had just been created
+    #[cfg(test)]
+    pub(crate) fn new(root: Bucket) -> Self {
+        Self {
+            page_size: DEFAULT_PAGE_SIZE,
+            txid: 0,
+            root,
+        }
+    }
+
+    pub(crate) fn parse(bytes: &[u8]) -> Result<Self> {
+        let first = parse_meta(bytes, 0);
+        let second_offset = first.as_ref().map_or(DEFAULT_PAGE_SIZE, |meta| meta.page_size);
+        let second = parse_meta(bytes, second_offset);
+
+        // Like bbolt, use the meta page of the latest transaction, falling back to the other one
+        // if it's corrupt
+        let meta = match (first, second) {
+            (Ok(first), Ok(second)) => {
+                if second.txid > first.txid {
+                    second
+                } else {
+                    first
+                }
+            }
+            (Ok(meta), Err(_)) | (Err(_), Ok(meta)) => meta,
+            (Err(err), Err(_)) => return Err(err.context("reading meta pages")),
+        };
+
+        let reader = Reader {
+            bytes,
+            page_size: meta.page_size,
+        };
+
+        Ok(Self {
+            page_size: meta.page_size,
+            txid: meta.txid,
+            root: reader
+                .read_bucket(meta.root, meta.root_sequence, 0)
+                .context("reading root bucket")?,
+        })
+    }
+
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut writer = Writer {
+            page_size: self.page_size,
+            // The two meta pages and the freelist come first
+            pages: vec![0; 3 * self.page_size],
+        };
+
+        let root = writer.write_bucket(&self.root);
+
+        // A fresh database has nothing to free
+        let freelist_offset = 2 * self.page_size;
+        writer.pages[freelist_offset..freelist_offset + 8].copy_from_slice(&2u64.to_le_bytes());
+        writer.pages[freelist_offset + 8..freelist_offset + 10].copy_from_slice(&FREELIST_PAGE_FLAG.to_le_bytes());
+
+        let high_water_mark = (writer.pages.len() / self.page_size) as u64;
+        for page_id in 0..2 {
+            let mut meta = Vec::with_capacity(64);
+            meta.extend(MAGIC.to_le_bytes());
+            meta.extend(VERSION.to_le_bytes());
+            meta.extend((self.page_size as u32).to_le_bytes());
+            meta.extend(0u32.to_le_bytes());
+            meta.extend(root.to_le_bytes());
+            meta.extend(self.root.sequence.to_le_bytes());
+            meta.extend(2u64.to_le_bytes());
+            meta.extend(high_water_mark.to_le_bytes());
+            meta.extend((self.txid + 1).to_le_bytes());
+            meta.extend(fnv64a(&meta).to_le_bytes());
+
+            let offset = page_id * self.page_size;
+            writer.pages[offset..offset + 8].copy_from_slice(&(page_id as u64).to_le_bytes());
+            writer.pages[offset + 8..offset + 10].copy_from_slice(&META_PAGE_FLAG.to_le_bytes());
+            writer.pages[offset + PAGE_HEADER_SIZE..offset + PAGE_HEADER_SIZE + meta.len()].copy_from_slice(&meta);
+        }
+
+        writer.pages
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    page_size: usize,
+}
+
+impl Reader<'_> {
+    fn page(&self, page_id: u64) -> Result<&[u8]> {
+        let offset = usize::try_from(page_id)?
+            .checked_mul(self.page_size)
+            .context("page id out of bounds")?;
+        let header = self.bytes.get(offset..offset + PAGE_HEADER_SIZE).context("page out of bounds")?;
+        let overflow = read_u32(header, 12)? as usize;
+
+        self.bytes
+            .get(offset..offset + (overflow + 1) * self.page_size)
+            .context("page overflows the database")
+    }
+
+    fn read_bucket(&self, root: u64, sequence: u64, depth: usize) -> Result<Bucket> {
+        let mut bucket = Bucket {
+            sequence,
+            entries: BTreeMap::new(),
+        };
+        self.read_node(self.page(root)?, depth, &mut bucket)?;
+        Ok(bucket)
+    }
+
+    /// Read the entries of the B+tree under a page into the bucket
+    fn read_node(&self, page: &[u8], depth: usize, bucket: &mut Bucket) -> Result<()> {
+        ensure!(depth < 64, "database nested too deeply, is it corrupt?");
+
+        let flags = read_u16(page, 8)?;
+        let count = read_u16(page, 10)? as usize;
+
+        for index in 0..count {
+            let element_offset = PAGE_HEADER_SIZE + index * ELEMENT_SIZE;
+            if flags & BRANCH_PAGE_FLAG != 0 {
+                let child = read_u64(page, element_offset + 8)?;
+                self.read_node(self.page(child)?, depth + 1, bucket)?;
+            } else if flags & LEAF_PAGE_FLAG != 0 {
+                let element_flags = read_u32(page, element_offset)?;
+                let key_offset = element_offset + read_u32(page, element_offset + 4)? as usize;
+                let key_size = read_u32(page, element_offset + 8)? as usize;
+                let value_size = read_u32(page, element_offset + 12)? as usize;
+                let key = page.get(key_offset..key_offset + key_size).context("key out of bounds")?;
+                let value = page
+                    .get(key_offset + key_size..key_offset + key_size + value_size)
+                    .context("value out of bounds")?;
+
+                let entry = if element_flags & BUCKET_LEAF_FLAG != 0 {
+                    Entry::Bucket(self.read_bucket_value(value, depth + 1)?)
+                } else {
+                    Entry::Value(value.to_vec())
+                };
+                bucket.entries.insert(key.to_vec(), entry);
+            } else {
+                bail!("unexpected page flags {:#x}", flags);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Buckets small enough are stored inline, in the value of their parent bucket's entry,
+    /// rather than in pages of their own
+    fn read_bucket_value(&self, value: &[u8], depth: usize) -> Result<Bucket> {
+        let root = read_u64(value, 0)?;
+        let sequence = read_u64(value, 8)?;
+        if root != 0 {
+            return self.read_bucket(root, sequence, depth);
+        }
+
+        let mut bucket = Bucket {
+            sequence,
+            entries: BTreeMap::new(),
+        };
+        self.read_node(&value[BUCKET_HEADER_SIZE..], depth, &mut bucket)?;
+        Ok(bucket)
+    }
+}
+
+struct Writer {
+    page_size: usize,
+    pages: Vec<u8>,
+}
+
+impl Writer {
+    /// Write the bucket's B+tree (after those of its sub-buckets), returning its root page id
+    fn write_bucket(&mut self, bucket: &Bucket) -> u64 {
+        let mut leaf_elements = Vec::with_capacity(bucket.entries.len());
+        for (key, entry) in &bucket.entries {
+            leaf_elements.push(match entry {
+                Entry::Value(value) => (0, key.clone(), value.clone()),
+                Entry::Bucket(sub_bucket) => {
+                    let root = self.write_bucket(sub_bucket);
+                    let mut header = Vec::with_capacity(BUCKET_HEADER_SIZE);
+                    header.extend(root.to_le_bytes());
+                    header.extend(sub_bucket.sequence.to_le_bytes());
+                    (BUCKET_LEAF_FLAG, key.clone(), header)
+                }
+            });
+        }
+
+        // Nodes are (first key, page id)
+        let mut nodes = self.write_level(LEAF_PAGE_FLAG, &leaf_elements);
+        while nodes.len() > 1 {
+            let branch_elements = nodes
+                .into_iter()
+                .map(|(first_key, page_id)| (0, first_key, page_id.to_le_bytes().to_vec()))
+                .collect::<Vec<_>>();
+            nodes = self.write_level(BRANCH_PAGE_FLAG, &branch_elements);
+        }
+
+        nodes[0].1
+    }
+
+    /// Pack the elements into as few pages as fit them, returning the first key and page id of
+    /// each. Branch elements hold their page id in place of the value
+    fn write_level(&mut self, flags: u16, elements: &[(u32, Vec<u8>, Vec<u8>)]) -> Vec<(Vec<u8>, u64)> {
+        let element_size = |(_, key, value): &(u32, Vec<u8>, Vec<u8>)| {
+            ELEMENT_SIZE
+                + key.len()
+                + match flags {
+                    BRANCH_PAGE_FLAG => 0,
+                    _ => value.len(),
+                }
+        };
+
+        let mut pages = vec![];
+        let mut start = 0;
+        while start < elements.len() || pages.is_empty() {
+            let mut end = start;
+            let mut size = PAGE_HEADER_SIZE;
+            while end < elements.len() && (end == start || size + element_size(&elements[end]) <= self.page_size) {
+                size += element_size(&elements[end]);
+                end += 1;
+            }
+
+            let first_key = elements.get(start).map_or_else(Vec::new, |(_, key, _)| key.clone());
+            pages.push((first_key, self.write_page(flags, &elements[start..end])));
+            start = end;
+        }
+
+        pages
+    }
+
+    fn write_page(&mut self, flags: u16, elements: &[(u32, Vec<u8>, Vec<u8>)]) -> u64 {
+        let page_id = (self.pages.len() / self.page_size) as u64;
+
+        let mut page = Vec::with_capacity(self.page_size);
+        page.extend(page_id.to_le_bytes());
+        page.extend(flags.to_le_bytes());
+        page.extend((elements.len() as u16).to_le_bytes());
+        page.extend(0u32.to_le_bytes());
+
+        let mut data: Vec<u8> = vec![];
+        for (index, (element_flags, key, value)) in elements.iter().enumerate() {
+            // Relative to the element itself
+            let pos = ((elements.len() - index) * ELEMENT_SIZE + data.len()) as u32;
+            if flags == BRANCH_PAGE_FLAG {
+                page.extend(pos.to_le_bytes());
+                page.extend((key.len() as u32).to_le_bytes());
+                page.extend(value);
+                data.extend(key);
+            } else {
+                page.extend(element_flags.to_le_bytes());
+                page.extend(pos.to_le_bytes());
+                page.extend((key.len() as u32).to_le_bytes());
+                page.extend((value.len() as u32).to_le_bytes());
+                data.extend(key);
+                data.extend(value);
+            }
+        }
+        page.extend(data);
+
+        let page_count = page.len().div_ceil(self.page_size).max(1);
+        page[12..16].copy_from_slice(&((page_count - 1) as u32).to_le_bytes());
+        page.resize(page_count * self.page_size, 0);
+        self.pages.extend(page);
+
+        page_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(entries: impl IntoIterator<Item = (Vec<u8>, Entry)>) -> Bucket {
+        Bucket {
+            sequence: 7,
+            entries: entries.into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        // Enough values, some of them large, for the tree to need branch and overflow pages
+        let key_bucket = bucket((0..2000u32).map(|revision| {
+            let value = match revision % 100 {
+                0 => vec![b'x'; 10_000],
+                _ => format!("value {}", revision).into_bytes(),
+            };
+            (revision.to_be_bytes().to_vec(), Entry::Value(value))
+        }));
+        let database = Database {
+            page_size: 4096,
+            txid: 41,
+            root: bucket([
+                (b"key".to_vec(), Entry::Bucket(key_bucket)),
+                (
+                    b"meta".to_vec(),
+                    Entry::Bucket(bucket([(b"consistent_index".to_vec(), Entry::Value(vec![0, 0, 0, 9]))])),
+                ),
+                (b"empty".to_vec(), Entry::Bucket(Bucket::default())),
+            ]),
+        };
+
+        let serialized = database.serialize();
+        assert_eq!(serialized.len() % 4096, 0);
+
+        let parsed = Database::parse(&serialized).unwrap();
+        assert_eq!(parsed.txid, 42);
+        assert_eq!(parsed.root, database.root);
+
+        // The second meta page stands in for a corrupt first one
+        let mut corrupt = serialized.clone();
+        corrupt[PAGE_HEADER_SIZE] ^= 0xff;
+        assert_eq!(Database::parse(&corrupt).unwrap().root, database.root);
+        corrupt[4096 + PAGE_HEADER_SIZE] ^= 0xff;
+        assert!(Database::parse(&corrupt).is_err());
+    }
+
+    #[test]
+    fn test_inline_bucket() {
+        // A bucket inlined into its parent's leaf, as bbolt stores small buckets
+        let mut inline_page = vec![];
+        inline_page.extend(0u64.to_le_bytes());
+        inline_page.extend(3u64.to_le_bytes());
+        inline_page.extend(0u64.to_le_bytes());
+        inline_page.extend(LEAF_PAGE_FLAG.to_le_bytes());
+        inline_page.extend(1u16.to_le_bytes());
+        inline_page.extend(0u32.to_le_bytes());
+        inline_page.extend(0u32.to_le_bytes());
+        inline_page.extend((ELEMENT_SIZE as u32).to_le_bytes());
+        inline_page.extend(1u32.to_le_bytes());
+        inline_page.extend(2u32.to_le_bytes());
+        inline_page.extend(b"kvv");
+
+        let reader = Reader {
+            bytes: &[],
+            page_size: 4096,
+        };
+        assert_eq!(
+            reader.read_bucket_value(&inline_page, 0).unwrap(),
+            Bucket {
+                sequence: 3,
+                entries: BTreeMap::from([(b"k".to_vec(), Entry::Value(b"vv".to_vec()))]),
+            }
+        );
+    }
+}
@@ -1,18 +1,34 @@
-use crate::cluster_crypto::{
-    locations::{FileLocation, LocationValueType, YamlLocation},
-    pem_utils,
+use crate::{
+    audit::{self, AuditAction},
+    backup,
+    cluster_crypto::{
+        ini,
+        keys::PublicKey,
+        locations::{FieldEncoding, FileLocation, LocationValueType, YamlLocation},
+        pem_utils,
+        private_key_format::PrivateKeyPolicy,
+        ssh_keys,
+    },
+    json_tools, output_dir, yaml_tools,
 };
 use anyhow::{bail, Context, Result};
-use base64::{engine::general_purpose::STANDARD as base64_standard, Engine as _};
+use base64::{
+    engine::general_purpose::{STANDARD as base64_standard, URL_SAFE_NO_PAD as base64_url},
+    Engine as _,
+};
 use serde_json::Value;
-use std::path::{Path, PathBuf};
-use tokio::io::AsyncReadExt;
+use std::{
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt},
+    path::{Component, Path, PathBuf},
+};
+use tokio::io::AsyncWriteExt;
+use zeroize::Zeroizing;
 
 pub(crate) fn globvec(location: &Path, globstr: &str) -> Result<Vec<PathBuf>> {
     let mut globoptions = glob::MatchOptions::new();
     globoptions.require_literal_leading_dot = true;
 
-    Ok(glob::glob_with(
+    glob::glob_with(
         location
             .join(globstr)
             .to_str()
@@ -23,18 +39,113 @@ pub(crate) fn globvec(location: &Path, globstr: &str) -> Result<Vec<PathBuf>> {
     .into_iter()
     .filter(|path| !path.is_symlink())
     .filter(|path| !path.is_dir())
-    .collect::<Vec<_>>())
+    .filter_map(|path| match output_dir::is_removed(&path) {
+        Ok(true) => None,
+        Ok(false) => Some(Ok(path)),
+        Err(err) => Some(Err(err)),
+    })
+    .collect::<Result<Vec<_>>>()
 }
 
-pub(crate) async fn read_file_to_string(file_path: PathBuf) -> Result<String> {
-    let mut file = tokio::fs::File::open(file_path.clone()).await?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).await.context("failed to read file")?;
+/// The path without its root, for placing a file (or an etcd key) under another directory.
+/// Refuses anything which could point outside of that directory
+pub(crate) fn relative_path(path: &Path) -> Result<PathBuf> {
+    path.components()
+        .filter(|component| !matches!(component, Component::RootDir | Component::CurDir))
+        .map(|component| match component {
+            Component::Normal(component) => Ok(component),
+            _ => bail!("refusing to place {:?} under another directory", path),
+        })
+        .collect()
+}
+
+pub(crate) async fn read_file(file_path: &Path) -> Result<Vec<u8>> {
+    let contents = tokio::fs::read(output_dir::read_path(file_path)?)
+        .await
+        .context("failed to read file")?;
+    audit::record(AuditAction::FileRead, &file_path.to_string_lossy(), Some(&contents))?;
     Ok(contents)
 }
 
-pub(crate) async fn get_filesystem_yaml(file_location: &FileLocation) -> Result<Value> {
-    serde_yaml::from_str(read_file_to_string(file_location.path.clone().into()).await?.as_str()).context("failed to parse yaml")
+/// Read a secret (e.g. a key PEM) given on the command line. For pipeline use, where secrets
+/// shouldn't have to be written to intermediate files, the source can be "-" to read it from
+/// stdin or "env:NAME" to read it from the NAME environment variable. Anything else is a path.
+pub(crate) fn read_secret(source: &Path) -> Result<Zeroizing<String>> {
+    if source == Path::new("-") {
+        let mut secret = Zeroizing::new(String::new());
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut secret).context("reading stdin")?;
+        return Ok(secret);
+    }
+
+    if let Some(variable) = source.to_str().and_then(|source| source.strip_prefix("env:")) {
+        return Ok(Zeroizing::new(
+            std::env::var(variable).with_context(|| format!("reading environment variable {}", variable))?,
+        ));
+    }
+
+    Ok(Zeroizing::new(
+        std::fs::read_to_string(source).with_context(|| format!("reading {:?}", source))?,
+    ))
+}
+
+/// Create a directory (along with its parents) which only its owner gets to list
+pub(crate) fn create_private_dir(dir: &Path) -> Result<()> {
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("creating {:?}", dir))
+}
+
+/// Create the directory of a backup, dump, output or export, which hold private keys. It may
+/// already exist, but only empty, to never mix the files of different runs
+pub(crate) fn create_empty_private_dir(dir: &Path, kind: &str) -> Result<()> {
+    create_private_dir(dir).with_context(|| format!("creating {} dir", kind))?;
+    if std::fs::read_dir(dir)
+        .with_context(|| format!("listing {:?}", dir))?
+        .next()
+        .is_some()
+    {
+        bail!("{} dir {:?} is not empty, refusing to mix it with another {}", kind, dir, kind);
+    }
+
+    Ok(())
+}
+
+/// Write a file only its owner gets to read, for copies of private keys
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .with_context(|| format!("writing {:?}", path))
+}
+
+pub(crate) async fn read_file_to_string(file_path: PathBuf) -> Result<String> {
+    String::from_utf8(read_file(&file_path).await?).context("file is not valid utf-8")
+}
+
+pub(crate) async fn write_file(file_path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    backup::backup_file(file_path.as_ref())?;
+    let file_path = output_dir::write_path(file_path.as_ref())?;
+    tokio::fs::write(&file_path, contents.as_ref())
+        .await
+        .context("failed to write file")?;
+    audit::record(AuditAction::FileWrite, &file_path.to_string_lossy(), Some(contents.as_ref()))
+}
+
+/// Remove a file, or with --output-dir only record it as removed
+pub(crate) async fn remove_file(file_path: &Path) -> Result<()> {
+    backup::backup_file(file_path)?;
+    if !output_dir::remove_file(file_path)? {
+        tokio::fs::remove_file(file_path).await.context("failed to remove file")?;
+    }
+    audit::record(AuditAction::FileDelete, &file_path.to_string_lossy(), None)
 }
 
 pub(crate) enum RecreateYamlEncoding {
@@ -46,18 +157,20 @@ pub(crate) fn recreate_yaml_at_location_with_new_pem(
     mut resource: Value,
     yaml_location: &YamlLocation,
     new_pem: &pem::Pem,
+    private_key_policy: &PrivateKeyPolicy,
     encoding: RecreateYamlEncoding,
 ) -> Result<String> {
     let value_at_json_pointer = resource.pointer_mut(&yaml_location.json_pointer).context("value disappeared")?;
 
     match &yaml_location.value {
         LocationValueType::Pem(pem_location_info) => {
-            let newbundle = pem_utils::pem_bundle_replace_pem_at_index(
-                decode_resource_data_entry(yaml_location, value_at_json_pointer.as_str().context("value no longer string")?)?,
+            let encoded = replace_pem_in_resource_data_entry(
+                yaml_location,
+                value_at_json_pointer.as_str().context("value no longer string")?,
                 pem_location_info.pem_bundle_index,
-                &new_pem,
+                new_pem,
+                private_key_policy,
             )?;
-            let encoded = encode_resource_data_entry(&yaml_location, &newbundle);
 
             if let Value::String(value_at_json_pointer) = value_at_json_pointer {
                 *value_at_json_pointer = encoded;
@@ -68,32 +181,486 @@ pub(crate) fn recreate_yaml_at_location_with_new_pem(
         _ => bail!("called with non-pem location"),
     }
 
-    match encoding {
-        RecreateYamlEncoding::Json => serde_json::to_string(&resource).context("serializing json"),
-        RecreateYamlEncoding::Yaml => serde_yaml::to_string(&resource).context("serializing yaml"),
+    serialize_resource(&resource, encoding)
+}
+
+fn serialize_resource(resource: &Value, encoding: RecreateYamlEncoding) -> Result<String> {
+    let serialized = match encoding {
+        RecreateYamlEncoding::Json => serde_json::to_string(resource).context("serializing json")?,
+        RecreateYamlEncoding::Yaml => serde_yaml::to_string(resource).context("serializing yaml")?,
+    };
+
+    // Values containing unusual characters (CRLF line endings, BOMs, quotes, etc.) need escaping
+    // when serialized. Make sure that the serializer did that correctly and we're not about to
+    // write a document that parses into something other than what we intended.
+    let reparsed: Value = serde_yaml::from_str(&serialized).context("re-parsing serialized document")?;
+    if reparsed != *resource {
+        bail!("serialized document does not parse back to the intended structure");
+    }
+
+    Ok(serialized)
+}
+
+/// Rewrite the resource data entry at the given location of a serialized document with the given
+/// patch. Only the bytes of the entry's scalar are rewritten, in the style it was written in (see
+/// json_tools and yaml_tools), unless that's not possible and the whole document is re-serialized.
+/// The original document is returned untouched if the entry doesn't change.
+fn patch_serialized_entry(
+    document: &str,
+    yaml_location: &YamlLocation,
+    encoding: RecreateYamlEncoding,
+    patch: impl Fn(&str) -> Result<String>,
+) -> Result<String> {
+    let spliced = match encoding {
+        RecreateYamlEncoding::Json => json_tools::patch_string_at_pointer(document, &yaml_location.json_pointer, &patch)?,
+        RecreateYamlEncoding::Yaml => yaml_tools::patch_string_at_pointer(document, &yaml_location.json_pointer, &patch)?,
+    };
+    if let Some(spliced) = spliced {
+        return Ok(spliced);
+    }
+
+    let mut resource: Value = serde_yaml::from_str(document).context("parsing document")?;
+    let value_at_json_pointer = resource.pointer_mut(&yaml_location.json_pointer).context("value disappeared")?;
+    let Value::String(entry) = value_at_json_pointer else {
+        bail!("value not string");
+    };
+
+    let new_entry = patch(entry)?;
+    if new_entry == *entry {
+        return Ok(document.to_string());
+    }
+    *entry = new_entry;
+
+    serialize_resource(&resource, encoding)
+}
+
+/// Like recreate_yaml_at_location_with_new_pem, but operates directly on a serialized JSON
+/// document (as stored in etcd) and only rewrites the bytes of the single string scalar that
+/// changed, rather than parsing and re-serializing the entire document. Falls back to the full
+/// re-serialization if the scalar can't be located that way (e.g. the document is actually YAML).
+pub(crate) fn recreate_json_at_location_with_new_pem(
+    document: &str,
+    yaml_location: &YamlLocation,
+    new_pem: &pem::Pem,
+    private_key_policy: &PrivateKeyPolicy,
+) -> Result<String> {
+    let pem_location_info = match &yaml_location.value {
+        LocationValueType::Pem(pem_location_info) => pem_location_info,
+        _ => bail!("called with non-pem location"),
+    };
+
+    let patched = json_tools::patch_string_at_pointer(document, &yaml_location.json_pointer, |value_at_json_pointer| {
+        replace_pem_in_resource_data_entry(
+            yaml_location,
+            value_at_json_pointer,
+            pem_location_info.pem_bundle_index,
+            new_pem,
+            private_key_policy,
+        )
+    })?;
+
+    match patched {
+        Some(patched) => Ok(patched),
+        None => recreate_serialized_yaml_at_location_with_new_pem(
+            document,
+            yaml_location,
+            new_pem,
+            private_key_policy,
+            RecreateYamlEncoding::Json,
+        ),
+    }
+}
+
+/// Re-create a YAML file (or JSON, in the case of the MCD currentconfig) with a new PEM at the
+/// given location
+pub(crate) fn recreate_file_yaml_at_location_with_new_pem(
+    contents: &str,
+    filelocation: &FileLocation,
+    yaml_location: &YamlLocation,
+    new_pem: &pem::Pem,
+    private_key_policy: &PrivateKeyPolicy,
+) -> Result<String> {
+    recreate_serialized_yaml_at_location_with_new_pem(
+        contents,
+        yaml_location,
+        new_pem,
+        private_key_policy,
+        if filelocation.path.ends_with("currentconfig") {
+            RecreateYamlEncoding::Json
+        } else {
+            RecreateYamlEncoding::Yaml
+        },
+    )
+}
+
+/// Same as recreate_yaml_at_location_with_new_pem, but for a serialized document, of which only
+/// the PEM bundle is rewritten, see patch_serialized_entry
+fn recreate_serialized_yaml_at_location_with_new_pem(
+    document: &str,
+    yaml_location: &YamlLocation,
+    new_pem: &pem::Pem,
+    private_key_policy: &PrivateKeyPolicy,
+    encoding: RecreateYamlEncoding,
+) -> Result<String> {
+    let LocationValueType::Pem(pem_location_info) = &yaml_location.value else {
+        bail!("called with non-pem location");
+    };
+
+    patch_serialized_entry(document, yaml_location, encoding, |entry| {
+        replace_pem_in_resource_data_entry(
+            yaml_location,
+            entry,
+            pem_location_info.pem_bundle_index,
+            new_pem,
+            private_key_policy,
+        )
+    })
+}
+
+/// Replace a single PEM in the bundle contained in the given (encoded) resource data entry. If
+/// the bundle already contains that exact PEM, the original entry is returned as-is, as
+/// re-encoding it might not be byte-identical (e.g. data URLs with different media types).
+fn replace_pem_in_resource_data_entry(
+    yaml_location: &YamlLocation,
+    entry: &str,
+    pem_bundle_index: u64,
+    new_pem: &pem::Pem,
+    private_key_policy: &PrivateKeyPolicy,
+) -> Result<String> {
+    let original_bundle = decode_resource_data_entry(yaml_location, entry)?;
+    let newbundle = pem_utils::pem_bundle_replace_pem_at_index(original_bundle.clone(), pem_bundle_index, new_pem, private_key_policy)?;
+
+    if newbundle == original_bundle {
+        return Ok(entry.to_string());
+    }
+
+    reencode_resource_data_entry(yaml_location, entry, &newbundle)
+}
+
+/// Remove a PEM from the bundle in the resource data entry at the given location of a serialized
+/// JSON document (as stored in etcd), see pem_utils::pem_bundle_remove_pem
+pub(crate) fn remove_pem_from_json_at_location(document: &str, yaml_location: &YamlLocation, pem: &pem::Pem) -> Result<String> {
+    match json_tools::patch_string_at_pointer(document, &yaml_location.json_pointer, |entry| {
+        remove_pem_from_resource_data_entry(yaml_location, entry, pem)
+    })? {
+        Some(patched) => Ok(patched),
+        None => remove_pem_from_serialized_yaml_at_location(document, yaml_location, pem, RecreateYamlEncoding::Json),
+    }
+}
+
+/// Like remove_pem_from_json_at_location, but for YAML files (or JSON, in the case of the MCD
+/// currentconfig)
+pub(crate) fn remove_pem_from_file_yaml_at_location(
+    contents: &str,
+    filelocation: &FileLocation,
+    yaml_location: &YamlLocation,
+    pem: &pem::Pem,
+) -> Result<String> {
+    remove_pem_from_serialized_yaml_at_location(
+        contents,
+        yaml_location,
+        pem,
+        if filelocation.path.ends_with("currentconfig") {
+            RecreateYamlEncoding::Json
+        } else {
+            RecreateYamlEncoding::Yaml
+        },
+    )
+}
+
+fn remove_pem_from_serialized_yaml_at_location(
+    document: &str,
+    yaml_location: &YamlLocation,
+    pem: &pem::Pem,
+    encoding: RecreateYamlEncoding,
+) -> Result<String> {
+    patch_serialized_entry(document, yaml_location, encoding, |entry| {
+        remove_pem_from_resource_data_entry(yaml_location, entry, pem)
+    })
+}
+
+fn remove_pem_from_resource_data_entry(yaml_location: &YamlLocation, entry: &str, pem: &pem::Pem) -> Result<String> {
+    let original_bundle = decode_resource_data_entry(yaml_location, entry)?;
+    let newbundle = pem_utils::pem_bundle_remove_pem(original_bundle.clone(), pem)?;
+
+    if newbundle == original_bundle {
+        return Ok(entry.to_string());
+    }
+
+    reencode_resource_data_entry(yaml_location, entry, &newbundle)
+}
+
+/// Like recreate_json_at_location_with_new_pem, but replaces a single key in the authorized_keys
+/// style list of SSH public keys at the given location
+pub(crate) fn recreate_json_at_location_with_new_ssh_public_key(
+    document: &str,
+    yaml_location: &YamlLocation,
+    new_public_key: &PublicKey,
+) -> Result<String> {
+    match json_tools::patch_string_at_pointer(document, &yaml_location.json_pointer, |entry| {
+        replace_ssh_public_key_in_resource_data_entry(yaml_location, entry, new_public_key)
+    })? {
+        Some(patched) => Ok(patched),
+        None => recreate_serialized_yaml_at_location_with_new_ssh_public_key(
+            document,
+            yaml_location,
+            new_public_key,
+            RecreateYamlEncoding::Json,
+        ),
+    }
+}
+
+/// Like recreate_json_at_location_with_new_ssh_public_key, but for YAML files (or JSON, in the
+/// case of the MCD currentconfig)
+pub(crate) fn recreate_file_yaml_at_location_with_new_ssh_public_key(
+    contents: &str,
+    filelocation: &FileLocation,
+    yaml_location: &YamlLocation,
+    new_public_key: &PublicKey,
+) -> Result<String> {
+    recreate_serialized_yaml_at_location_with_new_ssh_public_key(
+        contents,
+        yaml_location,
+        new_public_key,
+        if filelocation.path.ends_with("currentconfig") {
+            RecreateYamlEncoding::Json
+        } else {
+            RecreateYamlEncoding::Yaml
+        },
+    )
+}
+
+fn recreate_serialized_yaml_at_location_with_new_ssh_public_key(
+    document: &str,
+    yaml_location: &YamlLocation,
+    new_public_key: &PublicKey,
+    encoding: RecreateYamlEncoding,
+) -> Result<String> {
+    patch_serialized_entry(document, yaml_location, encoding, |entry| {
+        replace_ssh_public_key_in_resource_data_entry(yaml_location, entry, new_public_key)
+    })
+}
+
+fn replace_ssh_public_key_in_resource_data_entry(yaml_location: &YamlLocation, entry: &str, new_public_key: &PublicKey) -> Result<String> {
+    let LocationValueType::SshPublicKey(ssh_public_key_location_info) = &yaml_location.value else {
+        bail!("called with non-ssh-public-key location");
+    };
+
+    let original_keys = decode_resource_data_entry(yaml_location, entry)?;
+    let new_keys = ssh_keys::replace_public_key_at_line(&original_keys, ssh_public_key_location_info.line_index, new_public_key)?;
+
+    if new_keys == original_keys {
+        return Ok(entry.to_string());
+    }
+
+    reencode_resource_data_entry(yaml_location, entry, &new_keys)
+}
+
+/// How the permissions of the files we rewrite are decided
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum PermissionPolicy {
+    /// Private key files end up 0600 and all other files 0644, but a file never becomes more
+    /// permissive than it already was (e.g. a 0600 file containing both a cert and a key)
+    #[default]
+    Strict,
+    /// Leave the permissions of existing files untouched
+    Preserve,
+}
+
+/// What kind of object is being written into a file, for the purpose of deciding its permissions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FileKind {
+    PrivateKey,
+    PublicKey,
+    Certificate,
+    Crl,
+}
+
+impl FileKind {
+    fn mode(&self) -> u32 {
+        match self {
+            FileKind::PrivateKey => 0o600,
+            FileKind::PublicKey | FileKind::Certificate | FileKind::Crl => 0o644,
+        }
+    }
+}
+
+pub(crate) fn file_mode(policy: PermissionPolicy, kind: FileKind, existing_mode: Option<u32>) -> u32 {
+    match (policy, existing_mode) {
+        (_, None) => kind.mode(),
+        (PermissionPolicy::Strict, Some(existing_mode)) => existing_mode & kind.mode(),
+        (PermissionPolicy::Preserve, Some(existing_mode)) => existing_mode & 0o7777,
+    }
+}
+
+/// Write the new contents of a file, unless they're identical to what it already contains, in
+/// which case only its permissions are fixed up so that its mtime doesn't change. The permissions
+/// of the file are decided by the PermissionPolicy, and are applied before any contents are written
+/// so that a private key is never momentarily readable with the old permissions.
+pub(crate) async fn write_if_changed(
+    filelocation: &FileLocation,
+    kind: FileKind,
+    policy: PermissionPolicy,
+    original_contents: &[u8],
+    new_contents: Vec<u8>,
+) -> Result<()> {
+    // These often contain private keys, don't leave them lying around in freed memory
+    let new_contents = Zeroizing::new(new_contents);
+
+    if original_contents == new_contents.as_slice() {
+        println!(
+            "Unchanged, not rewriting file:{}:{}",
+            filelocation.path, filelocation.content_location
+        );
+        return fix_permissions(Path::new(&filelocation.path), kind, policy, original_contents)
+            .await
+            .with_context(|| format!("fixing permissions of {}", filelocation.path));
+    }
+
+    write_with_permissions(Path::new(&filelocation.path), kind, policy, &new_contents)
+        .await
+        .with_context(|| format!("writing {}", filelocation.path))
+}
+
+/// Apply the PermissionPolicy to a file whose contents stay the same, e.g. a world readable private
+/// key which didn't need regenerating. With --output-dir the original is left alone, and only
+/// gets a copy when its permissions change
+async fn fix_permissions(path: &Path, kind: FileKind, policy: PermissionPolicy, contents: &[u8]) -> Result<()> {
+    let existing_mode = match tokio::fs::metadata(output_dir::read_path(path)?).await {
+        Ok(metadata) => metadata.permissions().mode() & 0o7777,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).context("reading existing permissions"),
+    };
+
+    let mode = file_mode(policy, kind, Some(existing_mode));
+    if mode == existing_mode {
+        return Ok(());
+    }
+
+    if output_dir::enabled() {
+        return write_with_permissions(path, kind, policy, contents).await;
+    }
+
+    backup::backup_file(path)?;
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .await
+        .context("setting permissions")?;
+
+    audit::record(AuditAction::FileWrite, &path.to_string_lossy(), Some(contents))
+}
+
+async fn write_with_permissions(path: &Path, kind: FileKind, policy: PermissionPolicy, contents: &[u8]) -> Result<()> {
+    backup::backup_file(path)?;
+
+    // With --output-dir, the copy takes the permissions the original would have been given
+    let existing_mode = match tokio::fs::metadata(output_dir::read_path(path)?).await {
+        Ok(metadata) => Some(metadata.permissions().mode()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).context("reading existing permissions"),
+    };
+
+    let mode = file_mode(policy, kind, existing_mode);
+
+    let path = &output_dir::write_path(path)?;
+    if existing_mode.is_some() && path.exists() {
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .await
+            .context("setting permissions")?;
     }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)
+        .await
+        .context("opening file")?;
+    file.write_all(contents).await.context("writing contents")?;
+    file.flush().await.context("flushing")?;
+
+    audit::record(AuditAction::FileWrite, &path.to_string_lossy(), Some(contents))
+}
+
+/// The new encoded resource data entry holding the value, given the original entry. The original is
+/// needed for values embedded in documents (see FieldEncoding::Yaml), which are written back into
+/// the rest of their document
+pub(crate) fn reencode_resource_data_entry(k8slocation: &YamlLocation, entry: &str, value: &str) -> Result<String> {
+    reencode_field(&k8slocation.encoding, entry, value)
 }
 
-pub(crate) fn encode_resource_data_entry(k8slocation: &YamlLocation, value: &String) -> String {
-    match k8slocation.encoding {
-        crate::cluster_crypto::locations::FieldEncoding::None => value.to_string(),
-        crate::cluster_crypto::locations::FieldEncoding::Base64 => base64_standard.encode(value.as_bytes()),
-        crate::cluster_crypto::locations::FieldEncoding::DataUrl => {
+/// Encode the value for a field of its own. Values embedded in documents can only be re-encoded
+/// into their original document, see reencode_field
+pub(crate) fn encode_field(encoding: &FieldEncoding, value: &str) -> Result<String> {
+    Ok(match encoding {
+        FieldEncoding::None => value.to_string(),
+        FieldEncoding::Base64 => base64_standard.encode(value.as_bytes()),
+        FieldEncoding::Base64Url => base64_url.encode(value.as_bytes()),
+        FieldEncoding::DataUrl => {
             let mut url = dataurl::DataUrl::new();
             url.set_data(value.as_bytes());
             url.to_string()
         }
+        FieldEncoding::Nested(outer, inner) => encode_field(outer, &encode_field(inner, value)?)?,
+        FieldEncoding::Yaml(_, _) | FieldEncoding::Ini(_, _, _) => {
+            bail!("cannot encode a value embedded in a document without the document")
+        }
+    })
+}
+
+/// Like encode_field, but values embedded in documents are patched into the document of the
+/// original (encoded) field
+pub(crate) fn reencode_field(encoding: &FieldEncoding, original: &str, value: &str) -> Result<String> {
+    match encoding {
+        FieldEncoding::Nested(outer, inner) => {
+            let original_inner = decode_field(outer, original)?;
+            reencode_field(outer, original, &reencode_field(inner, &original_inner, value)?)
+        }
+        FieldEncoding::Yaml(json_pointer, inner) => {
+            let mut document: Value = serde_yaml::from_str(original).context("parsing embedded document")?;
+            let Value::String(entry) = document
+                .pointer_mut(json_pointer)
+                .context("value disappeared from embedded document")?
+            else {
+                bail!("embedded value not string");
+            };
+            *entry = reencode_field(inner, entry, value)?;
+
+            // Documents which were JSON stay JSON, YAML being a superset of it both parse the same
+            Ok(if original.trim_start().starts_with('{') {
+                serde_json::to_string(&document).context("serializing embedded json")?
+            } else {
+                serde_yaml::to_string(&document).context("serializing embedded yaml")?
+            })
+        }
+        FieldEncoding::Ini(section, key, inner) => {
+            let entry = ini::get(original, section, key).context("value disappeared from embedded ini")?;
+            ini::set(original, section, key, &reencode_field(inner, entry, value)?)
+        }
+        _ => encode_field(encoding, value),
     }
 }
 
+/// The decoded value of the resource data entry at the given location of a parsed resource
+pub(crate) fn read_resource_data_entry(resource: &Value, yaml_location: &YamlLocation) -> Result<String> {
+    let Value::String(entry) = resource.pointer(&yaml_location.json_pointer).context("value disappeared")? else {
+        bail!("value not string");
+    };
+
+    decode_resource_data_entry(yaml_location, entry)
+}
+
 pub(crate) fn decode_resource_data_entry(yaml_location: &YamlLocation, value_at_json_pointer: &str) -> Result<String> {
-    Ok(match yaml_location.encoding {
-        crate::cluster_crypto::locations::FieldEncoding::None => value_at_json_pointer.to_string(),
-        crate::cluster_crypto::locations::FieldEncoding::Base64 => {
-            String::from_utf8(base64_standard.decode(value_at_json_pointer.as_bytes())?)?
-        }
-        crate::cluster_crypto::locations::FieldEncoding::DataUrl => {
-            let (decoded, _fragment) = data_url::DataUrl::process(value_at_json_pointer)
+    decode_field(&yaml_location.encoding, value_at_json_pointer)
+}
+
+pub(crate) fn decode_field(encoding: &FieldEncoding, value: &str) -> Result<String> {
+    Ok(match encoding {
+        FieldEncoding::None => value.to_string(),
+        FieldEncoding::Base64 => String::from_utf8(base64_standard.decode(value.as_bytes())?)?,
+        FieldEncoding::Base64Url => String::from_utf8(base64_url.decode(value.as_bytes())?)?,
+        FieldEncoding::DataUrl => {
+            let (decoded, _fragment) = data_url::DataUrl::process(value)
                 .ok()
                 .context("dataurl processing")?
                 .decode_to_vec()
@@ -101,6 +668,392 @@ pub(crate) fn decode_resource_data_entry(yaml_location: &YamlLocation, value_at_
                 .context("dataurl decoding")?;
             String::from_utf8(decoded)?
         }
+        FieldEncoding::Nested(outer, inner) => decode_field(inner, &decode_field(outer, value)?)?,
+        FieldEncoding::Yaml(json_pointer, inner) => {
+            let document: Value = serde_yaml::from_str(value).context("parsing embedded document")?;
+            let Value::String(entry) = document.pointer(json_pointer).context("value missing from embedded document")? else {
+                bail!("embedded value not string");
+            };
+            decode_field(inner, entry)?
+        }
+        FieldEncoding::Ini(section, key, inner) => {
+            decode_field(inner, ini::get(value, section, key).context("value missing from embedded ini")?)?
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster_crypto::locations::{FileContentLocation, PemBundleRole, PemLocationInfo},
+        fuzz_roundtrip::{random_pem, random_pem_bundle, random_string},
+    };
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    #[test]
+    fn test_recreate_yaml_roundtrip_with_nasty_values() {
+        let mut rng = StdRng::seed_from_u64(0x5ec7);
+
+        for _ in 0..500 {
+            let pems = (0..rng.gen_range(1..4)).map(|_| random_pem(&mut rng)).collect::<Vec<_>>();
+            let pem_bundle = random_pem_bundle(&mut rng, &pems);
+            let pem_bundle_index = rng.gen_range(0..pems.len());
+            let encoding = [
+                FieldEncoding::None,
+                FieldEncoding::Base64,
+                FieldEncoding::Base64Url,
+                FieldEncoding::DataUrl,
+                FieldEncoding::Nested(Box::new(FieldEncoding::Base64), Box::new(FieldEncoding::Base64)),
+            ]
+            .choose(&mut rng)
+            .unwrap()
+            .clone();
+
+            let mut yaml_location = YamlLocation::new("/data", "ca-bundle.crt", encoding);
+            let mut resource = serde_json::json!({
+                "kind": "ConfigMap",
+                "metadata": { "name": random_string(&mut rng), "annotations": { random_string(&mut rng): random_string(&mut rng) } },
+                "data": { random_string(&mut rng): random_string(&mut rng) },
+            });
+            resource["data"]["ca-bundle.crt"] = Value::String(encode_field(&yaml_location.encoding, &pem_bundle).unwrap());
+            yaml_location.value = LocationValueType::Pem(PemLocationInfo::new(pem_bundle_index as u64, PemBundleRole::Member));
+
+            let new_pem = random_pem(&mut rng);
+
+            for output_encoding in [RecreateYamlEncoding::Json, RecreateYamlEncoding::Yaml] {
+                let serialized = recreate_yaml_at_location_with_new_pem(
+                    resource.clone(),
+                    &yaml_location,
+                    &new_pem,
+                    &PrivateKeyPolicy::default(),
+                    output_encoding,
+                )
+                .unwrap();
+                let mut reparsed: Value = serde_yaml::from_str(&serialized).unwrap();
+
+                let new_bundle = decode_resource_data_entry(
+                    &yaml_location,
+                    reparsed.pointer(&yaml_location.json_pointer).unwrap().as_str().unwrap(),
+                )
+                .unwrap();
+                let new_pems = pem::parse_many(&new_bundle).unwrap();
+                assert_eq!(new_pems.len(), pems.len());
+                for (i, (original, new)) in pems.iter().zip(new_pems.iter()).enumerate() {
+                    assert_eq!(new, if i == pem_bundle_index { &new_pem } else { original });
+                }
+
+                // Formatting around the PEMs must be preserved
+                let leading = |bundle: &str| bundle[..bundle.find("-----BEGIN").unwrap()].to_string();
+                let trailing = |bundle: &str| bundle[bundle.trim_end().len()..].to_string();
+                assert_eq!(leading(&pem_bundle), leading(&new_bundle));
+                assert_eq!(trailing(&pem_bundle), trailing(&new_bundle));
+                assert_eq!(pem_bundle.contains("\r\n"), new_bundle.contains("\r\n"));
+
+                // Everything other than the changed scalar must be untouched
+                *reparsed.pointer_mut(&yaml_location.json_pointer).unwrap() =
+                    resource.pointer(&yaml_location.json_pointer).unwrap().clone();
+                assert_eq!(reparsed, resource);
+            }
+        }
+    }
+
+    #[test]
+    fn test_recreate_json_patch_matches_full_reserialization() {
+        let mut rng = StdRng::seed_from_u64(0x7a7c);
+
+        for _ in 0..500 {
+            let pems = (0..rng.gen_range(1..4)).map(|_| random_pem(&mut rng)).collect::<Vec<_>>();
+            let pem_bundle = random_pem_bundle(&mut rng, &pems);
+            let encoding = [FieldEncoding::None, FieldEncoding::Base64, FieldEncoding::DataUrl]
+                .choose(&mut rng)
+                .unwrap()
+                .clone();
+
+            // Keys which need escaping in JSON pointers and siblings on both sides of the target
+            let key = ["ca-bundle.crt", "a/b", "t~ls", "ca\"bundle"].choose(&mut rng).unwrap();
+            let mut yaml_location = YamlLocation::new("/data", key, encoding);
+            let mut resource = serde_json::json!({
+                "kind": "ConfigMap",
+                "metadata": { "name": random_string(&mut rng), "ownerReferences": [{ "name": random_string(&mut rng) }, []] },
+                "data": { "a": random_string(&mut rng), "z": [random_string(&mut rng), { "x": 1.5e3, "y": null, "z": true }] },
+            });
+            resource["data"][*key] = Value::String(encode_field(&yaml_location.encoding, &pem_bundle).unwrap());
+            yaml_location.value = LocationValueType::Pem(PemLocationInfo::new(rng.gen_range(0..pems.len()) as u64, PemBundleRole::Member));
+
+            let document = if rng.gen_bool(0.5) {
+                serde_json::to_string(&resource).unwrap()
+            } else {
+                serde_json::to_string_pretty(&resource).unwrap()
+            };
+
+            let new_pem = random_pem(&mut rng);
+
+            let patched =
+                recreate_json_at_location_with_new_pem(&document, &yaml_location, &new_pem, &PrivateKeyPolicy::default()).unwrap();
+            let full = recreate_yaml_at_location_with_new_pem(
+                resource.clone(),
+                &yaml_location,
+                &new_pem,
+                &PrivateKeyPolicy::default(),
+                RecreateYamlEncoding::Json,
+            )
+            .unwrap();
+
+            assert_eq!(
+                serde_json::from_str::<Value>(&patched).unwrap(),
+                serde_json::from_str::<Value>(&full).unwrap()
+            );
+
+            // Bytes before the patched scalar must be untouched
+            let span = json_tools::find_string_span(&document, &yaml_location.json_pointer).unwrap();
+            assert_eq!(document[..span.start], patched[..span.start]);
+        }
+    }
+
+    #[test]
+    fn test_unchanged_pem_leaves_document_untouched() {
+        let mut rng = StdRng::seed_from_u64(0x5a3e);
+
+        for encoding in [FieldEncoding::None, FieldEncoding::Base64, FieldEncoding::DataUrl] {
+            let pems = (0..3).map(|_| random_pem(&mut rng)).collect::<Vec<_>>();
+            let pem_bundle = random_pem_bundle(&mut rng, &pems);
+
+            let mut yaml_location = YamlLocation::new("/data", "ca-bundle.crt", encoding);
+            let mut resource = serde_json::json!({ "kind": "ConfigMap", "data": { "other": "value" } });
+            // Deliberately not the way we would encode it ourselves
+            resource["data"]["ca-bundle.crt"] = Value::String(match yaml_location.encoding {
+                FieldEncoding::DataUrl => format!("data:,{}", pem_bundle.replace('\n', "%0A").replace('\r', "%0D")),
+                _ => encode_field(&yaml_location.encoding, &pem_bundle).unwrap(),
+            });
+            yaml_location.value = LocationValueType::Pem(PemLocationInfo::new(1, PemBundleRole::Member));
+
+            let json = serde_json::to_string_pretty(&resource).unwrap();
+            assert_eq!(
+                recreate_json_at_location_with_new_pem(&json, &yaml_location, &pems[1], &PrivateKeyPolicy::default()).unwrap(),
+                json
+            );
+
+            // Comments and unusual (but valid) formatting which a re-serialization would not preserve
+            let yaml = format!("# comment\n{}", serde_yaml::to_string(&resource).unwrap().replace(": ", ":   "));
+            let file_location = FileLocation {
+                path: "/etc/kubernetes/kubeconfig".to_string(),
+                content_location: crate::cluster_crypto::locations::FileContentLocation::Yaml(yaml_location.clone()),
+            };
+            assert_eq!(
+                recreate_file_yaml_at_location_with_new_pem(&yaml, &file_location, &yaml_location, &pems[1], &PrivateKeyPolicy::default())
+                    .unwrap(),
+                yaml
+            );
+        }
+    }
+
+    #[test]
+    fn test_recreate_file_yaml_preserves_scalar_style() {
+        let mut rng = StdRng::seed_from_u64(0xb10c);
+        let pem_lines = |pem: &pem::Pem| {
+            pem::encode_config(
+                pem,
+                pem::EncodeConfig {
+                    line_ending: pem::LineEnding::LF,
+                },
+            )
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+        };
+        // A literal block scalar with strip chomping and a folded one, in which every line break
+        // of the PEM is written as an empty line, surrounded by things re-serializing would lose
+        let document =
+            |literal: &pem::Pem, folded: &pem::Pem| {
+                format!(
+                "# comment\napiVersion: v1\nkind:   ConfigMap\ndata:\n  literal: |-\n{}\n  folded: >\n{}\n\n  other: 'value'  # comment\n",
+                pem_lines(literal).iter().map(|line| format!("    {}", line)).collect::<Vec<_>>().join("\n"),
+                pem_lines(folded).iter().map(|line| format!("    {}", line)).collect::<Vec<_>>().join("\n\n"),
+            )
+            };
+
+        let (literal, folded) = (random_pem(&mut rng), random_pem(&mut rng));
+        let (new_literal, new_folded) = (random_pem(&mut rng), random_pem(&mut rng));
+        let original = document(&literal, &folded);
+        let resource: Value = serde_yaml::from_str(&original).unwrap();
+        assert_eq!(resource["data"]["literal"], pem_lines(&literal).join("\n"));
+        assert_eq!(resource["data"]["folded"], pem_lines(&folded).join("\n") + "\n");
+
+        let recreate = |document: &str, key: &str, new_pem: &pem::Pem| {
+            let mut yaml_location = YamlLocation::new("/data", key, FieldEncoding::None);
+            yaml_location.value = LocationValueType::Pem(PemLocationInfo::new(0, PemBundleRole::Member));
+            let file_location = FileLocation {
+                path: "/etc/kubernetes/manifests/configmap.yaml".to_string(),
+                content_location: FileContentLocation::Yaml(yaml_location.clone()),
+            };
+            recreate_file_yaml_at_location_with_new_pem(document, &file_location, &yaml_location, new_pem, &PrivateKeyPolicy::default())
+                .unwrap()
+        };
+
+        let recreated = recreate(&original, "literal", &new_literal);
+        assert_eq!(recreated, document(&new_literal, &folded));
+        let recreated = recreate(&recreated, "folded", &new_folded);
+        assert_eq!(recreated, document(&new_literal, &new_folded));
+    }
+
+    /// Not really a test, compares the targeted patching with full re-serialization on a large
+    /// document. Run with `cargo test --release -- --ignored --nocapture bench_`
+    #[test]
+    #[ignore]
+    fn bench_recreate_json_large_document() {
+        let mut rng = StdRng::seed_from_u64(0xbe7c);
+        let pems = (0..200).map(|_| random_pem(&mut rng)).collect::<Vec<_>>();
+        let pem_bundle = random_pem_bundle(&mut rng, &pems);
+        let mut yaml_location = YamlLocation::new("/data", "ca-bundle.crt", FieldEncoding::None);
+        let resource = serde_json::json!({ "kind": "ConfigMap", "data": { "ca-bundle.crt": pem_bundle } });
+        yaml_location.value = LocationValueType::Pem(PemLocationInfo::new(100, PemBundleRole::Member));
+        let document = serde_json::to_string(&resource).unwrap();
+        let new_pem = random_pem(&mut rng);
+
+        let iterations = 200;
+
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            recreate_json_at_location_with_new_pem(&document, &yaml_location, &new_pem, &PrivateKeyPolicy::default()).unwrap();
+        }
+        let patched = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            let resource: Value = serde_yaml::from_str(&document).unwrap();
+            recreate_yaml_at_location_with_new_pem(
+                resource,
+                &yaml_location,
+                &new_pem,
+                &PrivateKeyPolicy::default(),
+                RecreateYamlEncoding::Json,
+            )
+            .unwrap();
+        }
+        let full = start.elapsed();
+
+        println!(
+            "{} byte document: targeted patch {:?}/iter, full re-serialization {:?}/iter",
+            document.len(),
+            patched / iterations,
+            full / iterations
+        );
+    }
+
+    #[test]
+    fn test_read_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.pem");
+        std::fs::write(&path, "from file").unwrap();
+        assert_eq!(read_secret(&path).unwrap().as_str(), "from file");
+
+        std::env::set_var("RECERT_TEST_READ_SECRET", "from env");
+        assert_eq!(read_secret(Path::new("env:RECERT_TEST_READ_SECRET")).unwrap().as_str(), "from env");
+        assert!(read_secret(Path::new("env:RECERT_TEST_READ_SECRET_UNSET")).is_err());
+    }
+
+    #[test]
+    fn test_private_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let private_dir = dir.path().join("dump");
+        create_empty_private_dir(&private_dir, "dump").unwrap();
+        assert_eq!(std::fs::metadata(&private_dir).unwrap().permissions().mode() & 0o777, 0o700);
+
+        write_private(&private_dir.join("key"), b"secret").unwrap();
+        write_private(&private_dir.join("key"), b"new").unwrap();
+        assert_eq!(std::fs::read(private_dir.join("key")).unwrap(), b"new");
+        assert_eq!(
+            std::fs::metadata(private_dir.join("key")).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        // Empty dirs are taken as they are, non-empty ones are refused
+        assert!(create_empty_private_dir(&private_dir, "dump").is_err());
+        create_empty_private_dir(dir.path().join("empty").as_path(), "dump").unwrap();
+        create_empty_private_dir(dir.path().join("empty").as_path(), "dump").unwrap();
+    }
+
+    #[test]
+    fn test_file_mode() {
+        for (policy, kind, existing_mode, expected_mode) in [
+            (PermissionPolicy::Strict, FileKind::PrivateKey, Some(0o644), 0o600),
+            (PermissionPolicy::Strict, FileKind::PrivateKey, Some(0o777), 0o600),
+            (PermissionPolicy::Strict, FileKind::PrivateKey, Some(0o400), 0o400),
+            (PermissionPolicy::Strict, FileKind::PrivateKey, None, 0o600),
+            (PermissionPolicy::Strict, FileKind::Certificate, Some(0o666), 0o644),
+            (PermissionPolicy::Strict, FileKind::Certificate, Some(0o600), 0o600),
+            (PermissionPolicy::Strict, FileKind::Certificate, Some(0o100640), 0o640),
+            (PermissionPolicy::Strict, FileKind::PublicKey, None, 0o644),
+            (PermissionPolicy::Preserve, FileKind::PrivateKey, Some(0o100644), 0o644),
+            (PermissionPolicy::Preserve, FileKind::Certificate, Some(0o600), 0o600),
+            (PermissionPolicy::Preserve, FileKind::PrivateKey, None, 0o600),
+        ] {
+            assert_eq!(
+                file_mode(policy, kind, existing_mode),
+                expected_mode,
+                "{:?} {:?} {:?}",
+                policy,
+                kind,
+                existing_mode
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_with_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        let key_path = dir.path().join("tls.key");
+        std::fs::write(&key_path, "old").unwrap();
+        std::fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_with_permissions(&key_path, FileKind::PrivateKey, PermissionPolicy::Strict, b"new")
+            .await
+            .unwrap();
+        assert_eq!(mode(&key_path), 0o600);
+        assert_eq!(std::fs::read(&key_path).unwrap(), b"new");
+
+        let cert_path = dir.path().join("tls.crt");
+        std::fs::write(&cert_path, "old").unwrap();
+        std::fs::set_permissions(&cert_path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        write_with_permissions(&cert_path, FileKind::Certificate, PermissionPolicy::Strict, b"new")
+            .await
+            .unwrap();
+        assert_eq!(mode(&cert_path), 0o600);
+
+        let new_key_path = dir.path().join("new.key");
+        write_with_permissions(&new_key_path, FileKind::PrivateKey, PermissionPolicy::Strict, b"new")
+            .await
+            .unwrap();
+        assert_eq!(mode(&new_key_path), 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_write_if_changed_fixes_permissions_of_unchanged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("tls.key");
+        std::fs::write(&key_path, "key").unwrap();
+        std::fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let mtime = std::fs::metadata(&key_path).unwrap().modified().unwrap();
+
+        let file_location = FileLocation {
+            path: key_path.to_string_lossy().to_string(),
+            content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+        };
+        write_if_changed(
+            &file_location,
+            FileKind::PrivateKey,
+            PermissionPolicy::Strict,
+            b"key",
+            b"key".to_vec(),
+        )
+        .await
+        .unwrap();
+
+        let metadata = std::fs::metadata(&key_path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+        assert_eq!(metadata.modified().unwrap(), mtime);
+        assert_eq!(std::fs::read(&key_path).unwrap(), b"key");
     }
-    .clone())
 }
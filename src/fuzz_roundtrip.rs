@@ -0,0 +1,318 @@
+//! Randomized round-trips of the code rewriting crypto objects in resources. Each case generates
+//! a secret or configmap with awkward keys, values and PEM bundles (CRLF line endings, byte order
+//! marks, characters which need escaping in JSON pointers, JSON or YAML), crawls it to locate the
+//! bundle, replaces or removes one of its PEMs and serializes the result, checking that only the
+//! targeted PEM changed. Run by `recert fuzz-roundtrip`, by the tests and by the fuzz target in fuzz/.
+
+use crate::{
+    cluster_crypto::{
+        locations::{FieldEncoding, FileContentLocation, FileLocation, LocationValueType, PemBundleRole, PemLocationInfo, YamlLocation},
+        private_key_format::PrivateKeyPolicy,
+        yaml_crawl::{self, ScanPolicy},
+    },
+    file_utils::{self, RecreateYamlEncoding},
+    json_tools,
+};
+use anyhow::{ensure, Context, Result};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, RngCore, SeedableRng};
+use serde_json::Value;
+
+pub(crate) const NASTY_FRAGMENTS: &[&str] = &[
+    "\r\n", "\n", "\u{feff}", "\"", "'", ": ", "#", "\t", "\\", " ", "é", "€", "🔑", "- ", "|", ">", "&a", "*a", "!!str", "null", "0x1",
+];
+
+/// Keys which are easy to get wrong when building or following JSON pointers
+const NASTY_KEYS: &[&str] = &["ca-bundle.crt", "a/b", "t~ls", "a~1b", "~0", "ca\"bundle", "", "/"];
+
+pub(crate) fn random_string(rng: &mut impl Rng) -> String {
+    (0..rng.gen_range(0..12))
+        .map(|_| {
+            if rng.gen_bool(0.5) {
+                NASTY_FRAGMENTS.choose(rng).unwrap().to_string()
+            } else {
+                rng.gen_range('a'..='z').to_string()
+            }
+        })
+        .collect()
+}
+
+fn random_key(rng: &mut impl Rng) -> String {
+    if rng.gen_bool(0.5) {
+        NASTY_KEYS.choose(rng).unwrap().to_string()
+    } else {
+        random_string(rng)
+    }
+}
+
+/// A random JSON value which isn't scanned, to surround the values which are
+fn random_value(rng: &mut impl Rng, depth: usize) -> Value {
+    match rng.gen_range(0..if depth == 0 { 4 } else { 6 }) {
+        0 => Value::Null,
+        1 => Value::Bool(rng.gen()),
+        2 => Value::from(rng.gen_range(-1000..1000)),
+        3 => Value::String(random_string(rng)),
+        4 => Value::Array((0..rng.gen_range(0..3)).map(|_| random_value(rng, depth - 1)).collect()),
+        _ => Value::Object(
+            (0..rng.gen_range(0..3))
+                .map(|_| (random_key(rng), random_value(rng, depth - 1)))
+                .collect(),
+        ),
+    }
+}
+
+pub(crate) fn random_pem(rng: &mut impl Rng) -> pem::Pem {
+    pem::Pem::new(
+        "CERTIFICATE",
+        (0..rng.gen_range(1..200)).map(|_| rng.gen::<u8>()).collect::<Vec<_>>(),
+    )
+}
+
+pub(crate) fn random_pem_bundle(rng: &mut impl Rng, pems: &[pem::Pem]) -> String {
+    let line_ending = if rng.gen_bool(0.5) {
+        pem::LineEnding::CRLF
+    } else {
+        pem::LineEnding::LF
+    };
+    let encoded = pem::encode_many_config(pems, pem::EncodeConfig { line_ending });
+    let prefix = if rng.gen_bool(0.2) { "\u{feff}" } else { "" };
+    let suffix = ["", "\n", "\r\n", "\n\n", " \n"].choose(rng).unwrap();
+    format!("{}{}{}", prefix, encoded.trim_end(), suffix)
+}
+
+/// A secret or configmap holding the PEM bundle among other data, along with the location the
+/// bundle should be found at
+fn random_resource(rng: &mut impl Rng, pem_bundle: &str) -> Result<(Value, YamlLocation)> {
+    let is_secret = rng.gen_bool(0.5);
+    let encoding = if is_secret { FieldEncoding::Base64 } else { FieldEncoding::None };
+
+    let mut data = serde_json::Map::new();
+    for _ in 0..rng.gen_range(0..4) {
+        let value = random_string(rng);
+        data.insert(random_key(rng), Value::String(file_utils::encode_field(&encoding, &value)?));
+    }
+    let key = random_key(rng);
+    let yaml_location = YamlLocation::new("/data", &key, encoding.clone());
+    data.insert(key, Value::String(file_utils::encode_field(&encoding, pem_bundle)?));
+
+    let resource = serde_json::json!({
+        "apiVersion": "v1",
+        "kind": if is_secret { "Secret" } else { "ConfigMap" },
+        "metadata": {
+            "name": random_string(rng),
+            "namespace": random_string(rng),
+            "annotations": { random_key(rng): random_string(rng) },
+            "ownerReferences": random_value(rng, 3),
+        },
+        "data": data,
+        "extra": { random_key(rng): random_value(rng, 3) },
+    });
+
+    Ok((resource, yaml_location))
+}
+
+/// Crawl the resource the way the etcd scan does, and find the location of the PEM bundle
+fn locate(resource: &Value, pem_bundle: &str) -> Result<YamlLocation> {
+    let scan_policy = ScanPolicy::default();
+    let mut found = vec![];
+    for yaml_value in yaml_crawl::crawl_yaml(resource.clone(), &scan_policy).context("crawling")? {
+        if let Some((yaml_location, decoded)) = yaml_crawl::decode_yaml_value(&yaml_value, &scan_policy).context("decoding")? {
+            if decoded == pem_bundle {
+                found.push(yaml_location);
+            }
+        }
+    }
+
+    ensure!(found.len() == 1, "expected the bundle at exactly one location, found {:?}", found);
+    Ok(found.remove(0))
+}
+
+fn bundle_at(resource: &Value, yaml_location: &YamlLocation) -> Result<String> {
+    file_utils::read_resource_data_entry(resource, yaml_location).context("reading bundle back")
+}
+
+/// Everything but the value at the location must be untouched
+fn ensure_only_location_changed(original: &Value, mut rewritten: Value, yaml_location: &YamlLocation) -> Result<()> {
+    *rewritten.pointer_mut(&yaml_location.json_pointer).context("location disappeared")? = original
+        .pointer(&yaml_location.json_pointer)
+        .context("location missing from original")?
+        .clone();
+    ensure!(&rewritten == original, "values other than the bundle changed");
+    Ok(())
+}
+
+/// The bundle must hold the expected PEMs, formatted like the original bundle
+fn ensure_bundle(original_bundle: &str, bundle: &str, expected_pems: &[pem::Pem]) -> Result<()> {
+    ensure!(
+        pem::parse_many(bundle).context("parsing rewritten bundle")? == expected_pems,
+        "rewritten bundle doesn't hold the expected PEMs"
+    );
+
+    let leading = |bundle: &str| bundle.find("-----BEGIN").map(|begin| bundle[..begin].to_string());
+    let trailing = |bundle: &str| bundle[bundle.trim_end().len()..].to_string();
+    ensure!(leading(original_bundle) == leading(bundle), "text before the PEMs changed");
+    ensure!(trailing(original_bundle) == trailing(bundle), "text after the PEMs changed");
+    ensure!(original_bundle.contains("\r\n") == bundle.contains("\r\n"), "line endings changed");
+    Ok(())
+}
+
+/// Run the case generated from the given seed, failing if any invariant doesn't hold
+pub(crate) fn run_case(seed: u64) -> Result<()> {
+    roundtrip(&mut StdRng::seed_from_u64(seed))
+}
+
+/// Run the case generated from the given bytes, as given by a coverage guided fuzzer (see
+/// fuzz/fuzz_targets/fuzz_roundtrip.rs). Unlike with a seed, small changes to the bytes only
+/// change parts of the case, which is what lets the fuzzer work its way towards new code paths
+pub fn run_input(data: &[u8]) -> Result<()> {
+    roundtrip(&mut InputRng { data })
+}
+
+/// Draws its values from the given bytes, and zeroes once they run out
+struct InputRng<'a> {
+    data: &'a [u8],
+}
+
+impl RngCore for InputRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let available = dest.len().min(self.data.len());
+        dest[..available].copy_from_slice(&self.data[..available]);
+        dest[available..].fill(0);
+        self.data = &self.data[available..];
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+fn roundtrip(rng: &mut impl Rng) -> Result<()> {
+    let pems = (0..rng.gen_range(1..4)).map(|_| random_pem(rng)).collect::<Vec<_>>();
+    let pem_bundle = random_pem_bundle(rng, &pems);
+    let pem_bundle_index = rng.gen_range(0..pems.len());
+    let new_pem = random_pem(rng);
+    let private_key_policy = PrivateKeyPolicy::default();
+    let (resource, expected_location) = random_resource(rng, &pem_bundle)?;
+
+    let mut yaml_location = locate(&resource, &pem_bundle).context("locating")?;
+    ensure!(
+        yaml_location == expected_location,
+        "located {:?}, expected {:?}",
+        yaml_location,
+        expected_location
+    );
+    yaml_location.value = LocationValueType::Pem(PemLocationInfo::new(pem_bundle_index as u64, PemBundleRole::Member));
+
+    let mut expected_pems = pems.clone();
+    expected_pems[pem_bundle_index] = new_pem.clone();
+
+    // Full re-serialization, as JSON and as YAML
+    for (name, encoding) in [("json", RecreateYamlEncoding::Json), ("yaml", RecreateYamlEncoding::Yaml)] {
+        let serialized =
+            file_utils::recreate_yaml_at_location_with_new_pem(resource.clone(), &yaml_location, &new_pem, &private_key_policy, encoding)
+                .with_context(|| format!("re-serializing as {}", name))?;
+        let reparsed: Value = serde_yaml::from_str(&serialized).with_context(|| format!("re-parsing {}", name))?;
+
+        ensure_bundle(&pem_bundle, &bundle_at(&reparsed, &yaml_location)?, &expected_pems).with_context(|| name.to_string())?;
+        ensure_only_location_changed(&resource, reparsed, &yaml_location).with_context(|| name.to_string())?;
+    }
+
+    // Targeted patching of serialized JSON, as stored in etcd
+    let document = if rng.gen_bool(0.5) {
+        serde_json::to_string(&resource)?
+    } else {
+        serde_json::to_string_pretty(&resource)?
+    };
+    let patched = file_utils::recreate_json_at_location_with_new_pem(&document, &yaml_location, &new_pem, &private_key_policy)
+        .context("patching json")?;
+    let full = file_utils::recreate_yaml_at_location_with_new_pem(
+        resource.clone(),
+        &yaml_location,
+        &new_pem,
+        &private_key_policy,
+        RecreateYamlEncoding::Json,
+    )?;
+    ensure!(
+        serde_json::from_str::<Value>(&patched)? == serde_json::from_str::<Value>(&full)?,
+        "patched json differs from the re-serialized json"
+    );
+    let span = json_tools::find_string_span(&document, &yaml_location.json_pointer).context("bundle not found in json")?;
+    ensure!(document[..span.start] == patched[..span.start], "json before the bundle changed");
+
+    // Replacing a PEM with itself must not change a single byte
+    ensure!(
+        file_utils::recreate_json_at_location_with_new_pem(&document, &yaml_location, &pems[pem_bundle_index], &private_key_policy)?
+            == document,
+        "replacing a pem with itself changed the json"
+    );
+
+    // YAML files, as written by the re-serialization above, also go through the targeted rewrite
+    let yaml = serde_yaml::to_string(&resource)?;
+    let file_location = FileLocation {
+        path: "/etc/kubernetes/fuzz.yaml".to_string(),
+        content_location: FileContentLocation::Yaml(yaml_location.clone()),
+    };
+    let rewritten_yaml =
+        file_utils::recreate_file_yaml_at_location_with_new_pem(&yaml, &file_location, &yaml_location, &new_pem, &private_key_policy)
+            .context("rewriting yaml file")?;
+    let reparsed: Value = serde_yaml::from_str(&rewritten_yaml).context("re-parsing yaml file")?;
+    ensure_bundle(&pem_bundle, &bundle_at(&reparsed, &yaml_location)?, &expected_pems).context("yaml file")?;
+    ensure_only_location_changed(&resource, reparsed, &yaml_location).context("yaml file")?;
+
+    // Removing a PEM from a bundle keeps the others, in order
+    if pems.len() > 1 {
+        let removed =
+            file_utils::remove_pem_from_json_at_location(&document, &yaml_location, &pems[pem_bundle_index]).context("removing pem")?;
+        let reparsed: Value = serde_json::from_str(&removed).context("re-parsing json after removal")?;
+        let mut remaining_pems = pems.clone();
+        remaining_pems.remove(pem_bundle_index);
+        ensure!(
+            pem::parse_many(bundle_at(&reparsed, &yaml_location)?)? == remaining_pems,
+            "removal left the wrong pems behind"
+        );
+        ensure_only_location_changed(&resource, reparsed, &yaml_location).context("removal")?;
+    }
+
+    Ok(())
+}
+
+/// Run the given number of cases with consecutive seeds starting at the given one. Failures are
+/// reported along with the seed of the failing case, to be reproduced with --seed SEED
+/// --iterations 1
+pub(crate) fn fuzz_roundtrip(first_seed: u64, iterations: u64) -> Result<()> {
+    for seed in (0..iterations).map(|iteration| first_seed.wrapping_add(iteration)) {
+        run_case(seed).with_context(|| format!("round-trip case with seed {} failed", seed))?;
+    }
+
+    println!("{} round-trip cases starting at seed {} passed", iterations, first_seed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        fuzz_roundtrip(0x5eed, 300).unwrap();
+    }
+
+    #[test]
+    fn test_roundtrip_input() {
+        for data in [&[][..], &[0xff; 64], b"-----BEGIN CERTIFICATE-----\r\n"] {
+            run_input(data).unwrap();
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    etcd_dump::PROTOBUF_MAGIC,
+    file_utils,
+    k8s_etcd::{run_ouger, EtcdAccess},
+};
+use anyhow::{ensure, Context, Result};
+use base64::{
+    engine::general_purpose::{STANDARD as base64_standard, URL_SAFE_NO_PAD as base64_url},
+    Engine as _,
+};
+use etcd_client::GetOptions;
+use regex::Regex;
+use serde_json::Value;
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+
+/// How many layers of encoding deep values are decoded, e.g. a base64 encoded gzip of a
+/// kubeconfig is two layers deep
+const MAX_DECODE_DEPTH: usize = 3;
+
+/// Shorter strings are never tried as base64, practically every short word is valid base64
+const MIN_BASE64_LEN: usize = 16;
+
+/// How much of a matching line is printed on either side of the match
+const EXCERPT_CONTEXT: usize = 80;
+
+/// Search the etcd values under the prefix and the files of the static dirs for the pattern,
+/// printing the location of every match. Values are searched after decoding them, protobuf values
+/// with ouger, and then every string in them (and every file) through any layers of base64, data
+/// URL and gzip encoding, so that e.g. the old hostname is found inside of an ignition file
+pub(crate) async fn grep(
+    pattern: &Regex,
+    etcd_endpoint: Option<&str>,
+    etcd_access: &EtcdAccess,
+    prefix: &str,
+    static_dirs: Vec<PathBuf>,
+) -> Result<()> {
+    let mut matches = 0;
+
+    if let Some(etcd_endpoint) = etcd_endpoint {
+        let etcd_client = etcd_access.connect(etcd_endpoint).await?;
+        let response = etcd_client
+            .kv_client()
+            .get(prefix, Some(GetOptions::new().with_prefix()))
+            .await
+            .context("listing keys")?;
+
+        for kv in response.kvs() {
+            let key = kv.key_str()?;
+            let value = etcd_access
+                .decrypt(key, kv.value().to_vec())
+                .await
+                .with_context(|| format!("decrypting {}", key))?;
+            let lines = grep_etcd_value(pattern, key, &value)
+                .await
+                .with_context(|| format!("searching {}", key))?;
+            matches += lines.len();
+            lines.iter().for_each(|line| println!("{}", line));
+        }
+    }
+
+    for static_dir in static_dirs {
+        for path in file_utils::globvec(&static_dir, "**/*")? {
+            if !path.is_file() {
+                continue;
+            }
+
+            let contents = file_utils::read_file(&path).await?;
+            let lines = grep_bytes(pattern, &format!("file:{}", path.display()), &contents, true);
+            matches += lines.len();
+            lines.iter().for_each(|line| println!("{}", line));
+        }
+    }
+
+    println!("{} matches", matches);
+
+    Ok(())
+}
+
+async fn grep_etcd_value(pattern: &Regex, key: &str, value: &[u8]) -> Result<Vec<String>> {
+    let location = format!("etcd:{}", key);
+    let mut lines = vec![];
+    if pattern.is_match(key) {
+        lines.push(format!("{} (key): {}", location, key));
+    }
+
+    let decoded = if value.starts_with(PROTOBUF_MAGIC) {
+        run_ouger("decode", value).await.context("decoding value with ouger")?
+    } else {
+        value.to_vec()
+    };
+
+    match serde_yaml::from_slice::<Value>(&decoded) {
+        Ok(document @ (Value::Object(_) | Value::Array(_))) => grep_document(pattern, &location, "", &document, &mut lines),
+        _ => lines.extend(grep_bytes(pattern, &location, &decoded, true)),
+    }
+
+    Ok(lines)
+}
+
+/// Search every field name and string in the document, locating matches by their JSON pointer
+pub(crate) fn grep_document(pattern: &Regex, location: &str, pointer: &str, value: &Value, lines: &mut Vec<String>) {
+    match value {
+        Value::Object(object) => {
+            for (field, value) in object {
+                let pointer = format!("{}/{}", pointer, field.replace('~', "~0").replace('/', "~1"));
+                if pattern.is_match(field) {
+                    lines.push(format!("{}:{} (field name): {}", location, pointer, field));
+                }
+                grep_document(pattern, location, &pointer, value, lines);
+            }
+        }
+        Value::Array(array) => {
+            for (index, value) in array.iter().enumerate() {
+                grep_document(pattern, location, &format!("{}/{}", pointer, index), value, lines);
+            }
+        }
+        Value::String(string) => lines.extend(grep_bytes(pattern, &format!("{}:{}", location, pointer), string.as_bytes(), false)),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// Search the value and every decoding of it, line by line. Lines are only numbered in the
+/// location when asked to, e.g. for files but not for strings within a document
+fn grep_bytes(pattern: &Regex, location: &str, value: &[u8], number_lines: bool) -> Vec<String> {
+    let mut lines = vec![];
+    for (layers, decoded) in decodings(value, MAX_DECODE_DEPTH) {
+        let layers = match layers.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", layers.join(" > ")),
+        };
+
+        let text = String::from_utf8_lossy(&decoded);
+        for (line_index, line) in text.lines().enumerate() {
+            let Some(found) = pattern.find(line) else {
+                continue;
+            };
+
+            let line_number = match number_lines {
+                true => format!(":{}", line_index + 1),
+                false => String::new(),
+            };
+            lines.push(format!("{}{}{}: {}", location, line_number, layers, excerpt(line, found)));
+        }
+    }
+
+    lines
+}
+
+/// The value along with every decoding of it, through up to depth layers of encoding. Each comes
+/// with the encoding layers it was found in, outermost first
+fn decodings(value: &[u8], depth: usize) -> Vec<(Vec<&'static str>, Vec<u8>)> {
+    let mut decodings = vec![(vec![], value.to_vec())];
+    if depth == 0 {
+        return decodings;
+    }
+
+    let decoded = if value.starts_with(GZIP_MAGIC) {
+        gunzip(value).ok().map(|decoded| ("gzip", decoded))
+    } else {
+        std::str::from_utf8(value).ok().map(str::trim).and_then(|value| {
+            if value.starts_with("data:") {
+                data_url::DataUrl::process(value)
+                    .ok()
+                    .and_then(|data_url| data_url.decode_to_vec().ok())
+                    .map(|(decoded, _fragment)| ("data URL", decoded))
+            } else if value.len() >= MIN_BASE64_LEN {
+                base64_standard
+                    .decode(value)
+                    .map(|decoded| ("base64", decoded))
+                    .or_else(|_| base64_url.decode(value).map(|decoded| ("base64url", decoded)))
+                    .ok()
+                    // Random data which happens to be valid base64 doesn't decode to text
+                    .filter(|(_, decoded)| decoded.starts_with(GZIP_MAGIC) || std::str::from_utf8(decoded).is_ok())
+            } else {
+                None
+            }
+        })
+    };
+
+    if let Some((layer, decoded)) = decoded {
+        for (mut layers, decoded) in self::decodings(&decoded, depth - 1) {
+            layers.insert(0, layer);
+            decodings.push((layers, decoded));
+        }
+    }
+
+    decodings
+}
+
+/// There's no compression library among our dependencies, so like openssl and ouger, gzip is run
+fn gunzip(value: &[u8]) -> Result<Vec<u8>> {
+    let mut command = Command::new("gzip")
+        .arg("-dc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Written from another thread, as gzip may fill its stdout pipe before it's done reading
+    let mut stdin = command.stdin.take().context("failed to take gzip stdin pipe")?;
+    let value = value.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&value));
+
+    let output = command.wait_with_output()?;
+    ensure!(output.status.success(), "gzip failed: {}", String::from_utf8_lossy(&output.stderr));
+    writer.join().ok().context("gzip stdin writer panicked")??;
+
+    Ok(output.stdout)
+}
+
+/// The part of the line around the match, so that matches in e.g. a single line JSON document
+/// don't print the entire document
+fn excerpt(line: &str, found: regex::Match) -> String {
+    let mut start = found.start().saturating_sub(EXCERPT_CONTEXT);
+    while !line.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (found.end() + EXCERPT_CONTEXT).min(line.len());
+    while !line.is_char_boundary(end) {
+        end += 1;
+    }
+
+    format!(
+        "{}{}{}",
+        if start > 0 { "..." } else { "" },
+        line[start..end].trim(),
+        if end < line.len() { "..." } else { "" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grep_document() {
+        let kubeconfig = "clusters:\n- cluster:\n    server: https://api.seed.example.com:6443\n";
+        let document = serde_json::json!({
+            "metadata": {"name": "seed-kubeconfig", "annotations": {"seed.example.com/owner": "installer"}},
+            "data": {
+                "kubeconfig": base64_standard.encode(kubeconfig),
+                "short": "c2VlZA==",
+            },
+            "items": ["api.seed.example.com"],
+        });
+
+        let mut lines = vec![];
+        grep_document(
+            &Regex::new(r"seed\.example").unwrap(),
+            "etcd:/kubernetes.io/secrets/default/kc",
+            "",
+            &document,
+            &mut lines,
+        );
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                "etcd:/kubernetes.io/secrets/default/kc:/data/kubeconfig (base64): server: https://api.seed.example.com:6443",
+                "etcd:/kubernetes.io/secrets/default/kc:/items/0: api.seed.example.com",
+                "etcd:/kubernetes.io/secrets/default/kc:/metadata/annotations/seed.example.com~1owner (field name): seed.example.com/owner",
+            ]
+        );
+
+        // Too short to be worth trying as base64
+        let mut lines = vec![];
+        grep_document(&Regex::new("^seed$").unwrap(), "etcd:key", "", &document, &mut lines);
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn test_decodings() {
+        let data_url = format!("data:;base64,{}", base64_standard.encode("node-name: master-0\n"));
+        let lines = grep_bytes(&Regex::new("master-0").unwrap(), "file:/etc/config", data_url.as_bytes(), true);
+        assert_eq!(lines, ["file:/etc/config:1 (data URL): node-name: master-0"]);
+
+        let long_line = format!("{}master-0{}", "a".repeat(100), "b".repeat(100));
+        let lines = grep_bytes(&Regex::new("master-0").unwrap(), "file:/etc/config", long_line.as_bytes(), true);
+        assert_eq!(
+            lines,
+            [format!("file:/etc/config:1: ...{}master-0{}...", "a".repeat(80), "b".repeat(80))]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::file_utils;
+use anyhow::{ensure, Context, Result};
+use std::path::{Path, PathBuf};
+
+pub(crate) const DEFAULT_UNIT_NAME: &str = "recert.service";
+pub(crate) const DEFAULT_DONE_MARKER: &str = "/var/lib/recert/done";
+
+/// Everything needed to render the first boot units
+pub(crate) struct ServiceParameters {
+    pub(crate) unit_name: String,
+    /// recert is configured through its RECERT_* environment variables, loaded from this file
+    pub(crate) config: PathBuf,
+    pub(crate) executable: PathBuf,
+    /// Created once recert succeeds, so that it only ever runs on the first boot
+    pub(crate) done_marker: PathBuf,
+}
+
+/// Write a oneshot unit running recert at first boot, and a kubelet drop-in making sure the kubelet
+/// only starts once recert is done. recert runs after crio, which hosts the etcd it talks to
+pub(crate) async fn install_service(params: &ServiceParameters, unit_dir: &Path) -> Result<()> {
+    for path in [&params.config, &params.executable, &params.done_marker] {
+        ensure!(path.is_absolute(), "{:?} must be an absolute path, as systemd runs the unit", path);
+    }
+
+    let unit_path = unit_dir.join(&params.unit_name);
+    file_utils::write_file(&unit_path, render_unit(params))
+        .await
+        .with_context(|| format!("writing {:?}", unit_path))?;
+    println!("Wrote {:?}", unit_path);
+
+    let drop_in_dir = unit_dir.join("kubelet.service.d");
+    tokio::fs::create_dir_all(&drop_in_dir)
+        .await
+        .with_context(|| format!("creating {:?}", drop_in_dir))?;
+    let drop_in_path = drop_in_dir.join("10-recert.conf");
+    file_utils::write_file(&drop_in_path, render_kubelet_drop_in(params))
+        .await
+        .with_context(|| format!("writing {:?}", drop_in_path))?;
+    println!("Wrote {:?}", drop_in_path);
+
+    println!("Enable it with: systemctl enable {}", params.unit_name);
+
+    Ok(())
+}
+
+fn render_unit(params: &ServiceParameters) -> String {
+    format!(
+        "[Unit]
+Description=Regenerate the cluster's certificates, keys and tokens
+Wants=network-online.target crio.service
+After=network-online.target crio.service
+Before=kubelet.service
+ConditionPathExists=!{done_marker}
+
+[Service]
+Type=oneshot
+RemainAfterExit=yes
+EnvironmentFile={config}
+ExecStart={executable}
+ExecStartPost=/usr/bin/mkdir -p {done_marker_dir}
+ExecStartPost=/usr/bin/touch {done_marker}
+
+[Install]
+WantedBy=multi-user.target
+",
+        done_marker = params.done_marker.display(),
+        done_marker_dir = params.done_marker.parent().unwrap_or(Path::new("/")).display(),
+        config = params.config.display(),
+        executable = params.executable.display(),
+    )
+}
+
+fn render_kubelet_drop_in(params: &ServiceParameters) -> String {
+    format!(
+        "[Unit]
+Wants={unit_name}
+After={unit_name}
+",
+        unit_name = params.unit_name,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let params = ServiceParameters {
+            unit_name: DEFAULT_UNIT_NAME.to_string(),
+            config: PathBuf::from("/etc/recert/recert.env"),
+            executable: PathBuf::from("/usr/local/bin/recert"),
+            done_marker: PathBuf::from(DEFAULT_DONE_MARKER),
+        };
+
+        let unit = render_unit(&params);
+        assert!(unit.contains("EnvironmentFile=/etc/recert/recert.env\n"));
+        assert!(unit.contains("ExecStart=/usr/local/bin/recert\n"));
+        assert!(unit.contains("ConditionPathExists=!/var/lib/recert/done\n"));
+        assert!(unit.contains("ExecStartPost=/usr/bin/mkdir -p /var/lib/recert\n"));
+        assert!(unit.contains("Before=kubelet.service\n"));
+
+        assert_eq!(
+            render_kubelet_drop_in(&params),
+            "[Unit]\nWants=recert.service\nAfter=recert.service\n"
+        );
+    }
+}
@@ -1,4 +1,6 @@
+use anyhow::{Context, Result};
 use serde_json::Value;
+use std::ops::Range;
 
 pub(crate) fn read_string_field(value: &Value, field: &str) -> Option<String> {
     let get = value.as_object()?.get(field);
@@ -11,3 +13,129 @@ pub(crate) fn read_string_field(value: &Value, field: &str) -> Option<String> {
 pub(crate) fn read_metadata_string_field(value: &Value, field: &str) -> Option<String> {
     read_string_field(value.as_object()?.get("metadata")?, field)
 }
+
+/// Find the byte range (including the quotes) of the string value at the given JSON pointer in a
+/// serialized JSON document, without parsing the entire document. Returns None if the document is
+/// not JSON or there's no string at the pointer.
+pub fn find_string_span(document: &str, json_pointer: &str) -> Option<Range<usize>> {
+    let bytes = document.as_bytes();
+    let mut pos = skip_whitespace(bytes, 0);
+
+    for token in json_pointer.split('/').skip(1) {
+        let token = token.replace("~1", "/").replace("~0", "~");
+
+        match bytes.get(pos)? {
+            b'{' => {
+                pos = skip_whitespace(bytes, pos + 1);
+                loop {
+                    let key_end = string_end(bytes, pos)?;
+                    let key: String = serde_json::from_str(&document[pos..key_end]).ok()?;
+                    pos = skip_whitespace(bytes, key_end);
+                    if bytes.get(pos)? != &b':' {
+                        return None;
+                    }
+                    pos = skip_whitespace(bytes, pos + 1);
+
+                    if key == token {
+                        break;
+                    }
+
+                    pos = skip_separator(bytes, value_end(bytes, pos)?)?;
+                }
+            }
+            b'[' => {
+                pos = skip_whitespace(bytes, pos + 1);
+                for _ in 0..token.parse::<usize>().ok()? {
+                    pos = skip_separator(bytes, value_end(bytes, pos)?)?;
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    Some(pos..string_end(bytes, pos)?)
+}
+
+/// Replace the string value at the given JSON pointer of a serialized JSON document with the
+/// output of `patch`, leaving every other byte of the document untouched. Returns None if the
+/// string couldn't be located, in which case callers should fall back to fully parsing the
+/// document.
+pub fn patch_string_at_pointer(document: &str, json_pointer: &str, patch: impl FnOnce(&str) -> Result<String>) -> Result<Option<String>> {
+    let span = match find_string_span(document, json_pointer) {
+        Some(span) => span,
+        None => return Ok(None),
+    };
+
+    let original: String = serde_json::from_str(&document[span.clone()]).context("decoding original string")?;
+    let patched = patch(&original)?;
+    if patched == original {
+        return Ok(Some(document.to_string()));
+    }
+
+    let patched = serde_json::to_string(&patched).context("encoding patched string")?;
+
+    Ok(Some(format!("{}{}{}", &document[..span.start], patched, &document[span.end..])))
+}
+
+fn skip_whitespace(bytes: &[u8], mut pos: usize) -> usize {
+    while matches!(bytes.get(pos), Some(b' ' | b'\n' | b'\r' | b'\t')) {
+        pos += 1;
+    }
+    pos
+}
+
+/// Skips the comma following a value. Bails (None) when the enclosing object/array ends instead,
+/// as that means the key/index we were looking for doesn't exist.
+fn skip_separator(bytes: &[u8], pos: usize) -> Option<usize> {
+    let pos = skip_whitespace(bytes, pos);
+    (bytes.get(pos)? == &b',').then(|| skip_whitespace(bytes, pos + 1))
+}
+
+fn string_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    if bytes.get(pos)? != &b'"' {
+        return None;
+    }
+
+    let mut pos = pos + 1;
+    loop {
+        match bytes.get(pos)? {
+            b'\\' => pos += 2,
+            b'"' => return Some(pos + 1),
+            _ => pos += 1,
+        }
+    }
+}
+
+fn value_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    match bytes.get(pos)? {
+        b'"' => string_end(bytes, pos),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            let mut pos = pos;
+            loop {
+                match bytes.get(pos)? {
+                    b'"' => {
+                        pos = string_end(bytes, pos)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(pos + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                pos += 1;
+            }
+        }
+        _ => {
+            let mut pos = pos;
+            while !matches!(bytes.get(pos), None | Some(b',' | b'}' | b']' | b' ' | b'\n' | b'\r' | b'\t')) {
+                pos += 1;
+            }
+            Some(pos)
+        }
+    }
+}
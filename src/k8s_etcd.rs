@@ -1,24 +1,88 @@
-use crate::cluster_crypto::locations::K8sResourceLocation;
-use anyhow::{bail, Context, Result};
-use etcd_client::{Client as EtcdClient, GetOptions};
+use crate::{
+    audit::{self, AuditAction},
+    backup,
+    cluster_crypto::{
+        locations::{K8sLocation, K8sResourceLocation},
+        resource_kinds::ResourceKindPolicy,
+    },
+    concurrency,
+    etcd_encryption::{self, EncryptionConfig},
+    etcd_snapshot::EtcdSnapshot,
+    namespace_filter::NamespaceFilter,
+    output_dir,
+};
+use anyhow::{bail, ensure, Context, Result};
+use etcd_client::{Certificate, Client as EtcdClient, ConnectOptions, GetOptions, Identity, TlsOptions};
 use futures_util::future::join_all;
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 use tokio::sync::Mutex;
+use zeroize::Zeroize;
 
 pub(crate) struct EtcdResult {
     pub(crate) key: String,
     pub(crate) value: Vec<u8>,
 }
 
+/// Where the resources are read from and committed to
+enum EtcdBackend {
+    Etcd(Arc<EtcdClient>),
+    /// An etcd database file, written to the output path once committed, see --etcd-snapshot
+    Snapshot(Mutex<EtcdSnapshot>, PathBuf),
+}
+
 pub(crate) struct InMemoryK8sEtcd {
-    etcd_client: Arc<EtcdClient>,
+    backend: EtcdBackend,
     etcd_keyvalue_hashmap: Mutex<HashMap<String, Vec<u8>>>,
+    modified_keys: Mutex<HashSet<String>>,
     deleted_keys: Mutex<HashSet<String>>,
+    grouped_mutations: Mutex<Option<GroupedMutations>>,
+    etcd_access: EtcdAccess,
+    namespace_filter: NamespaceFilter,
+    resource_kinds: ResourceKindPolicy,
+}
+
+/// The edits made to etcd resources while mutations are grouped (see
+/// InMemoryK8sEtcd::group_mutations), merged per resource. A secret holding several crypto objects
+/// is edited once for each of them, but only the document the last edit left is put, and not even
+/// that if the edits cancelled each other out
+#[derive(Default)]
+struct GroupedMutations {
+    /// The original value (None for keys which didn't exist) and the current one of every edited key
+    resources: HashMap<String, (Option<Vec<u8>>, Vec<u8>)>,
+}
+
+impl GroupedMutations {
+    fn get(&self, key: &str) -> Option<&Vec<u8>> {
+        self.resources.get(key).map(|(_, current)| current)
+    }
+
+    fn put(&mut self, key: &str, original: Option<&Vec<u8>>, value: Vec<u8>) {
+        match self.resources.get_mut(key) {
+            Some((_, current)) => *current = value,
+            None => {
+                self.resources.insert(key.to_string(), (original.cloned(), value));
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.resources.remove(key);
+    }
+
+    /// The keys whose value differs from the original, with their merged value
+    fn into_changed(self) -> Vec<(String, Vec<u8>)> {
+        self.resources
+            .into_iter()
+            .filter(|(_, (original, current))| original.as_ref() != Some(current))
+            .map(|(key, (_, current))| (key, current))
+            .collect()
+    }
 }
 
 // An etcd client wrapper backed by an in-memory hashmap. All reads are served from memory, with
@@ -28,22 +92,159 @@ pub(crate) struct InMemoryK8sEtcd {
 // regeneration, as we we don't have to go through ouger and etcd for every single certificate and
 // key access.
 impl InMemoryK8sEtcd {
-    pub(crate) fn new(etcd_client: EtcdClient) -> Self {
+    pub(crate) fn new(
+        etcd_client: EtcdClient,
+        etcd_access: &EtcdAccess,
+        namespace_filter: NamespaceFilter,
+        resource_kinds: ResourceKindPolicy,
+    ) -> Self {
+        Self::with_backend(
+            EtcdBackend::Etcd(Arc::new(etcd_client)),
+            etcd_access,
+            namespace_filter,
+            resource_kinds,
+        )
+    }
+
+    /// Work on an etcd database file rather than a running etcd, writing the result to the output
+    /// path on commit
+    pub(crate) fn from_snapshot(
+        snapshot: EtcdSnapshot,
+        output: PathBuf,
+        etcd_access: &EtcdAccess,
+        namespace_filter: NamespaceFilter,
+        resource_kinds: ResourceKindPolicy,
+    ) -> Self {
+        Self::with_backend(
+            EtcdBackend::Snapshot(Mutex::new(snapshot), output),
+            etcd_access,
+            namespace_filter,
+            resource_kinds,
+        )
+    }
+
+    fn with_backend(
+        backend: EtcdBackend,
+        etcd_access: &EtcdAccess,
+        namespace_filter: NamespaceFilter,
+        resource_kinds: ResourceKindPolicy,
+    ) -> Self {
         Self {
-            etcd_client: Arc::new(etcd_client),
+            backend,
             etcd_keyvalue_hashmap: Mutex::new(HashMap::new()),
+            modified_keys: Mutex::new(HashSet::new()),
             deleted_keys: Mutex::new(HashSet::new()),
+            grouped_mutations: Mutex::new(None),
+            etcd_access: etcd_access.clone(),
+            namespace_filter,
+            resource_kinds,
         }
     }
 
+    pub(crate) fn etcd_access(&self) -> &EtcdAccess {
+        &self.etcd_access
+    }
+
+    pub(crate) fn namespace_filter(&self) -> &NamespaceFilter {
+        &self.namespace_filter
+    }
+
+    /// With --output-dir, the changes are written there instead, see output_dir
     pub(crate) async fn commit_to_actual_etcd(&self) -> Result<()> {
+        ensure!(
+            self.grouped_mutations.lock().await.is_none(),
+            "grouped mutations were never flushed"
+        );
+        self.ensure_namespace_filter_respected().await?;
+        self.backup_keys_to_be_committed().await.context("backing up etcd keys")?;
         self.commit_hashmap().await?;
         self.commit_deleted_keys().await?;
 
+        if let EtcdBackend::Snapshot(snapshot, output) = &self.backend {
+            snapshot.lock().await.write(output).context("writing etcd snapshot")?;
+        }
+
+        // The cache is full of secrets and is no longer needed once committed, zero it out rather
+        // than leaving it in memory for the rest of the run
+        let mut hashmap = self.etcd_keyvalue_hashmap.lock().await;
+        hashmap.values_mut().for_each(|value| value.zeroize());
+        hashmap.clear();
+
+        Ok(())
+    }
+
+    /// Scanning already skips the namespaces the filter excludes, but post-processing (e.g. a
+    /// cluster rename) might still have modified resources there. Refuse to commit anything at all
+    /// rather than touch them
+    async fn ensure_namespace_filter_respected(&self) -> Result<()> {
+        let hashmap = self.etcd_keyvalue_hashmap.lock().await;
+
+        for key in self.modified_keys.lock().await.iter() {
+            let value = hashmap.get(key).context("modified key missing from cache")?;
+            let namespace = NamespaceFilter::document_namespace(value);
+            if !self.namespace_filter.allows(namespace.as_deref()) {
+                bail!(
+                    "{} in namespace {} was modified, but the namespace is excluded by the namespace filter",
+                    key,
+                    namespace.unwrap_or_default()
+                );
+            }
+        }
+
         Ok(())
     }
 
+    /// With --backup-dir, save the values of all the keys about to be written or deleted, exactly
+    /// as they currently are in etcd
+    async fn backup_keys_to_be_committed(&self) -> Result<()> {
+        if !backup::enabled() {
+            return Ok(());
+        }
+
+        let keys = self
+            .modified_keys
+            .lock()
+            .await
+            .iter()
+            .chain(self.deleted_keys.lock().await.iter())
+            .cloned()
+            .collect::<HashSet<_>>();
+
+        let EtcdBackend::Etcd(etcd_client) = &self.backend else {
+            bail!("etcd snapshots cannot be backed up");
+        };
+
+        let mut originals = Vec::with_capacity(keys.len());
+        for key in keys {
+            let response = etcd_client.kv_client().get(key.as_bytes(), None).await?;
+            let value = response.kvs().first().map(|kv| kv.value().to_vec());
+            originals.push((key, value));
+        }
+
+        backup::backup_etcd_keys(originals)
+    }
+
+    /// The actual etcd client, bypassing the cache, unless working on a snapshot
+    pub(crate) fn etcd_client(&self) -> Option<Arc<EtcdClient>> {
+        match &self.backend {
+            EtcdBackend::Etcd(etcd_client) => Some(Arc::clone(etcd_client)),
+            EtcdBackend::Snapshot(_, _) => None,
+        }
+    }
+
     async fn commit_deleted_keys(&self) -> Result<(), anyhow::Error> {
+        let etcd_client = match &self.backend {
+            EtcdBackend::Etcd(etcd_client) => etcd_client,
+            EtcdBackend::Snapshot(snapshot, _) => {
+                let mut snapshot = snapshot.lock().await;
+                for key in self.deleted_keys.lock().await.iter() {
+                    snapshot.delete(key)?;
+                    audit::record(AuditAction::EtcdDelete, key, None)?;
+                }
+                return Ok(());
+            }
+        };
+
         join_all(
             self.deleted_keys
                 .lock()
@@ -51,9 +252,14 @@ impl InMemoryK8sEtcd {
                 .iter()
                 .map(|key| {
                     let key = key.clone();
-                    let etcd_client = Arc::clone(&self.etcd_client);
-                    tokio::spawn(async move {
+                    let etcd_client = Arc::clone(etcd_client);
+                    concurrency::spawn(async move {
+                        if output_dir::enabled() {
+                            return output_dir::write_etcd_key(&key, None);
+                        }
+
                         etcd_client.kv_client().delete(key.as_bytes(), None).await?;
+                        audit::record(AuditAction::EtcdDelete, &key, None)?;
                         anyhow::Ok(())
                     })
                 })
@@ -68,29 +274,98 @@ impl InMemoryK8sEtcd {
     }
 
     async fn commit_hashmap(&self) -> Result<(), anyhow::Error> {
-        for (key, value) in self.etcd_keyvalue_hashmap.lock().await.iter() {
-            let key = key.clone();
-            let value = value.clone();
-            let etcd_client = Arc::clone(&self.etcd_client);
-            // TODO: Find a fancier way to detect CRDs
-            let value = if key.starts_with("/kubernetes.io/machineconfiguration.openshift.io/machineconfigs/") {
-                value.to_vec()
-            } else {
-                run_ouger("encode", value.as_slice()).await.context("encoding value with ouger")?
-            };
-
-            etcd_client.kv_client().put(key.as_bytes(), value, None).await?;
+        // Keys which were only ever read don't need to be written back, that would only cause
+        // pointless etcd revisions
+        let mut modified = {
+            let hashmap = self.etcd_keyvalue_hashmap.lock().await;
+            self.modified_keys
+                .lock()
+                .await
+                .iter()
+                .map(|key| Ok((key.clone(), hashmap.get(key).context("modified key missing from cache")?.clone())))
+                .collect::<Result<Vec<_>>>()?
+        };
+        modified.sort_by(|(key, _), (other_key, _)| key.cmp(other_key));
+
+        // Encoding runs ouger once for every key, which is most of what committing a big cluster
+        // takes, so keys are encoded concurrently (as many at once as concurrency::spawn allows)
+        let encoded = join_all(
+            modified
+                .into_iter()
+                .map(|(key, value)| {
+                    let etcd_access = self.etcd_access.clone();
+                    let is_custom_resource = self.resource_kinds.is_custom_etcd_key(&key);
+                    concurrency::spawn(async move {
+                        let value = encode_for_etcd(&etcd_access, &key, value, is_custom_resource).await?;
+                        anyhow::Ok((key, value))
+                    })
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+        // Written one key at a time in key order, so that a failed commit leaves behind a
+        // predictable prefix of the keys, and the audit log lists them in order too
+        for (key, value) in encoded {
+            if output_dir::enabled() {
+                output_dir::write_etcd_key(&key, Some(&value))?;
+                continue;
+            }
+
+            match &self.backend {
+                EtcdBackend::Etcd(etcd_client) => {
+                    etcd_client
+                        .kv_client()
+                        .put(key.as_bytes(), value.clone(), None)
+                        .await
+                        .with_context(|| format!("putting {}", key))?;
+                }
+                EtcdBackend::Snapshot(snapshot, _) => snapshot.lock().await.put(&key, value.clone())?,
+            }
+            audit::record(AuditAction::EtcdPut, &key, Some(&value))?;
         }
 
         Ok(())
     }
 
+    /// From now on, puts are merged per resource (see GroupedMutations) rather than made one by
+    /// one, until flush_grouped_mutations
+    pub(crate) async fn group_mutations(&self) {
+        *self.grouped_mutations.lock().await = Some(GroupedMutations::default());
+    }
+
+    /// Put every resource edited since group_mutations, once each
+    pub(crate) async fn flush_grouped_mutations(&self) {
+        let Some(grouped_mutations) = self.grouped_mutations.lock().await.take() else {
+            return;
+        };
+
+        for (key, value) in grouped_mutations.into_changed() {
+            self.put(&key, value).await;
+        }
+    }
+
     pub(crate) async fn get(&self, key: String) -> Result<EtcdResult> {
         let mut result = EtcdResult {
             key: key.to_string(),
             value: vec![],
         };
 
+        if let Some(value) = self
+            .grouped_mutations
+            .lock()
+            .await
+            .as_ref()
+            .and_then(|grouped_mutations| grouped_mutations.get(&key))
+        {
+            result.value = value.clone();
+            return Ok(result);
+        }
+
         {
             let hashmap = self.etcd_keyvalue_hashmap.lock().await;
             if let Some(value) = hashmap.get(&key) {
@@ -99,15 +374,21 @@ impl InMemoryK8sEtcd {
             }
         }
 
-        let get_result = self
-            .etcd_client
-            .kv_client()
-            .get(key.clone(), None)
-            .await
-            .context("during etcd get")?;
-        let raw_etcd_value = get_result.kvs().first().context("key not found")?.value();
+        let raw_etcd_value = match &self.backend {
+            EtcdBackend::Etcd(etcd_client) => {
+                let get_result = etcd_client.kv_client().get(key.clone(), None).await.context("during etcd get")?;
+                get_result.kvs().first().context("key not found")?.value().to_vec()
+            }
+            EtcdBackend::Snapshot(snapshot, _) => snapshot.lock().await.get(&key)?.context("key not found")?,
+        };
+        audit::record(AuditAction::EtcdGet, &key, Some(&raw_etcd_value))?;
 
-        let decoded_value = run_ouger("decode", raw_etcd_value).await.context("decoding value with ouger")?;
+        let raw_etcd_value = self
+            .etcd_access
+            .decrypt(&key, raw_etcd_value)
+            .await
+            .with_context(|| format!("decrypting {}", key))?;
+        let decoded_value = run_ouger("decode", &raw_etcd_value).await.context("decoding value with ouger")?;
         self.etcd_keyvalue_hashmap
             .lock()
             .await
@@ -118,32 +399,124 @@ impl InMemoryK8sEtcd {
     }
 
     pub(crate) async fn put(&self, key: &str, value: Vec<u8>) {
-        self.etcd_keyvalue_hashmap.lock().await.insert(key.to_string(), value.clone());
+        if let Some(grouped_mutations) = self.grouped_mutations.lock().await.as_mut() {
+            grouped_mutations.put(key, self.etcd_keyvalue_hashmap.lock().await.get(key), value);
+            return;
+        }
+
+        let mut hashmap = self.etcd_keyvalue_hashmap.lock().await;
+        if hashmap.get(key) != Some(&value) {
+            hashmap.insert(key.to_string(), value.clone());
+            self.modified_keys.lock().await.insert(key.to_string());
+        }
         self.deleted_keys.lock().await.remove(key);
     }
 
     pub(crate) async fn list_keys(&self, resource_kind: &str) -> Result<Vec<String>> {
+        let etcd_client = match &self.backend {
+            EtcdBackend::Etcd(etcd_client) => etcd_client,
+            EtcdBackend::Snapshot(snapshot, _) => {
+                return Ok(snapshot.lock().await.list_keys(&format!("/kubernetes.io/{}", resource_kind)));
+            }
+        };
+
         let etcd_get_options = GetOptions::new().with_prefix().with_limit(0).with_keys_only();
-        let keys = self
-            .etcd_client
+        let keys = etcd_client
             .kv_client()
             .get(format!("/kubernetes.io/{}", resource_kind), Some(etcd_get_options.clone()))
             .await?;
 
         keys.kvs()
-            .into_iter()
+            .iter()
             .map(|k| Ok(k.key_str()?.to_string()))
             .collect::<Result<Vec<String>>>()
     }
 
     pub(crate) async fn delete(&self, key: &str) -> Result<()> {
+        if let Some(value) = self.etcd_keyvalue_hashmap.lock().await.get(key) {
+            let namespace = NamespaceFilter::document_namespace(value);
+            if !self.namespace_filter.allows(namespace.as_deref()) {
+                bail!("refusing to delete {}, its namespace is excluded by the namespace filter", key);
+            }
+        }
+
+        if let Some(grouped_mutations) = self.grouped_mutations.lock().await.as_mut() {
+            grouped_mutations.remove(key);
+        }
         self.etcd_keyvalue_hashmap.lock().await.remove(key);
+        self.modified_keys.lock().await.remove(key);
         self.deleted_keys.lock().await.insert(key.to_string());
         Ok(())
     }
 }
 
-async fn run_ouger(ouger_subcommand: &str, raw_etcd_value: &[u8]) -> Result<Vec<u8>> {
+/// How etcd is reached and read, see --etcd-cacert, --etcd-cert, --etcd-key and
+/// --etcd-encryption-config
+#[derive(Clone, Default)]
+pub(crate) struct EtcdAccess {
+    pub(crate) tls: Option<TlsOptions>,
+    pub(crate) encryption: Option<Arc<EncryptionConfig>>,
+}
+
+impl EtcdAccess {
+    /// Connect to the etcd at the endpoint, over TLS if it was given credentials. Endpoints
+    /// without a scheme are then reached over https
+    pub(crate) async fn connect(&self, etcd_endpoint: &str) -> Result<EtcdClient> {
+        let options = self.tls.as_ref().map(|tls| ConnectOptions::new().with_tls(tls.clone()));
+
+        EtcdClient::connect([etcd_endpoint], options)
+            .await
+            .with_context(|| format!("connecting to etcd at {}", etcd_endpoint))
+    }
+
+    /// The value as the kube-apiserver would see it, see etcd_encryption::decrypt
+    pub(crate) async fn decrypt(&self, etcd_key: &str, value: Vec<u8>) -> Result<Vec<u8>> {
+        etcd_encryption::decrypt(self.encryption.as_deref(), etcd_key, value).await
+    }
+
+    /// The value as the kube-apiserver would store it, see etcd_encryption::encrypt
+    pub(crate) async fn encrypt(&self, etcd_key: &str, value: Vec<u8>) -> Result<Vec<u8>> {
+        etcd_encryption::encrypt(self.encryption.as_deref(), etcd_key, value).await
+    }
+}
+
+/// The TLS options to connect to etcd with, for running against an etcd other than the node's own
+/// (e.g. on a backup restore host), if any credentials are given. The files are read right away,
+/// as they might be outside of what the sandbox allows reading
+pub(crate) fn load_tls(cacert: Option<&Path>, cert: Option<&Path>, key: Option<&Path>) -> Result<Option<TlsOptions>> {
+    if cacert.is_none() && cert.is_none() && key.is_none() {
+        return Ok(None);
+    }
+
+    let read = |path: &Path| std::fs::read(path).with_context(|| format!("reading {:?}", path));
+
+    let mut tls = TlsOptions::new();
+    if let Some(cacert) = cacert {
+        tls = tls.ca_certificate(Certificate::from_pem(read(cacert)?));
+    }
+    match (cert, key) {
+        (Some(cert), Some(key)) => tls = tls.identity(Identity::from_pem(read(cert)?, read(key)?)),
+        (None, None) => {}
+        _ => bail!("etcd client cert and key must be given together"),
+    }
+
+    Ok(Some(tls))
+}
+
+/// The value as it's stored in etcd. Custom resources are stored as plain JSON, see
+/// ResourceKindPolicy::is_custom_etcd_key
+async fn encode_for_etcd(etcd_access: &EtcdAccess, key: &str, value: Vec<u8>, is_custom_resource: bool) -> Result<Vec<u8>> {
+    // TODO: Find a fancier way to detect CRDs
+    let value = if key.starts_with("/kubernetes.io/machineconfiguration.openshift.io/machineconfigs/") || is_custom_resource {
+        value
+    } else {
+        run_ouger("encode", value.as_slice()).await.context("encoding value with ouger")?
+    };
+
+    etcd_access.encrypt(key, value).await.with_context(|| format!("encrypting {}", key))
+}
+
+pub(crate) async fn run_ouger(ouger_subcommand: &str, raw_etcd_value: &[u8]) -> Result<Vec<u8>> {
     let mut command = Command::new("ouger")
         .arg(ouger_subcommand)
         .stdin(Stdio::piped())
@@ -173,14 +546,30 @@ async fn run_ouger(ouger_subcommand: &str, raw_etcd_value: &[u8]) -> Result<Vec<
     Ok(result.stdout)
 }
 
-pub(crate) async fn get_etcd_yaml(client: &InMemoryK8sEtcd, k8slocation: &K8sResourceLocation) -> Result<Value> {
-    Ok(serde_yaml::from_str(&String::from_utf8(
+/// Get the raw serialized document stored at the given location, without parsing it
+pub(crate) async fn get_etcd_document(client: &InMemoryK8sEtcd, k8slocation: &K8sResourceLocation) -> Result<String> {
+    Ok(String::from_utf8(
         client
             .get(k8slocation.as_etcd_key())
             .await
             .with_context(|| format!("etcd get {}", k8slocation.as_etcd_key()))?
             .value,
-    )?)?)
+    )?)
+}
+
+pub(crate) async fn get_etcd_yaml(client: &InMemoryK8sEtcd, k8slocation: &K8sResourceLocation) -> Result<Value> {
+    Ok(serde_yaml::from_str(&get_etcd_document(client, k8slocation).await?)?)
+}
+
+/// Put a document derived from the original one, unless it's identical to it, in which case the
+/// write is skipped and reported as unchanged
+pub(crate) async fn put_etcd_document_if_changed(client: &InMemoryK8sEtcd, k8slocation: &K8sLocation, original: &str, new: String) {
+    if original == new {
+        println!("Unchanged, not rewriting k8s:{}", k8slocation);
+        return;
+    }
+
+    client.put(&k8slocation.resource_location.as_etcd_key(), new.into_bytes()).await;
 }
 
 pub(crate) async fn put_etcd_yaml(client: &InMemoryK8sEtcd, k8slocation: &K8sResourceLocation, value: Value) -> Result<()> {
@@ -189,3 +578,30 @@ pub(crate) async fn put_etcd_yaml(client: &InMemoryK8sEtcd, k8slocation: &K8sRes
         .await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grouped_mutations() {
+        let original = b"{\"data\":{\"tls.crt\":\"old\",\"tls.key\":\"old\"}}".to_vec();
+        let cert_edited = b"{\"data\":{\"tls.crt\":\"new\",\"tls.key\":\"old\"}}".to_vec();
+        let both_edited = b"{\"data\":{\"tls.crt\":\"new\",\"tls.key\":\"new\"}}".to_vec();
+        let mut grouped_mutations = GroupedMutations::default();
+
+        // Later edits build on the earlier ones, only the first edit's original counts
+        grouped_mutations.put("secret", Some(&original), cert_edited.clone());
+        assert_eq!(grouped_mutations.get("secret"), Some(&cert_edited));
+        grouped_mutations.put("secret", Some(&cert_edited), both_edited.clone());
+
+        // Edits which cancel each other out leave nothing to put
+        grouped_mutations.put("configmap", Some(&original), both_edited.clone());
+        grouped_mutations.put("configmap", Some(&both_edited), original.clone());
+
+        grouped_mutations.put("deleted", None, original.clone());
+        grouped_mutations.remove("deleted");
+
+        assert_eq!(grouped_mutations.into_changed(), vec![("secret".to_string(), both_edited)]);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::cluster_crypto::{
+    cert_key_pair::CertKeyPair,
+    crypto_utils::{fingerprint, public_key_fingerprint},
+    distributed_jwt::DistributedJwt,
+    distributed_private_key::DistributedPrivateKey,
+    distributed_public_key::DistributedPublicKey,
+    keys::PublicKey,
+    locations::Locations,
+    ClusterCryptoObjects,
+};
+use anyhow::{Context, Result};
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+/// The fingerprints of the cert and of its key
+pub(crate) fn cert_fingerprints(cert_key_pair: &CertKeyPair) -> Result<(String, String)> {
+    let distributed_cert = (*cert_key_pair.distributed_cert).borrow();
+    Ok((
+        // The DER as found, re-encoding it doesn't necessarily give back the same bytes
+        fingerprint(distributed_cert.certificate.original.constructed_data()),
+        public_key_fingerprint(&distributed_cert.certificate.public_key)?,
+    ))
+}
+
+pub(crate) fn private_key_fingerprint(distributed_private_key: &DistributedPrivateKey) -> Result<String> {
+    public_key_fingerprint(&PublicKey::try_from(&distributed_private_key.key)?)
+}
+
+pub(crate) fn jwt_fingerprint(distributed_jwt: &DistributedJwt) -> String {
+    fingerprint(distributed_jwt.jwt.str.as_bytes())
+}
+
+/// The fingerprints of a cert and of its private key
+type CertKeyFingerprints = (String, String);
+
+/// The fingerprints of the certs and keys as they were before regeneration, along with the objects
+/// themselves, whose regenerated versions are fingerprinted once the run is done to map each
+/// original cert / key to its replacement. For external systems keeping records of the cluster's
+/// certs (monitoring, cert inventories and the like) to follow along
+pub(crate) struct KeyContinuity {
+    pub(crate) cert_key_pairs: Vec<(Rc<RefCell<CertKeyPair>>, CertKeyFingerprints)>,
+    pub(crate) private_keys: Vec<(Rc<RefCell<DistributedPrivateKey>>, String)>,
+    pub(crate) public_keys: Vec<(Rc<RefCell<DistributedPublicKey>>, String)>,
+    /// Only in the run summary, see run_summary
+    pub(crate) jwts: Vec<(Rc<RefCell<DistributedJwt>>, String)>,
+}
+
+impl KeyContinuity {
+    /// Has to be called before anything is regenerated, as regeneration replaces the certs and
+    /// keys in place
+    pub(crate) fn record_originals(cluster_crypto: &ClusterCryptoObjects) -> Result<Self> {
+        Ok(Self {
+            cert_key_pairs: cluster_crypto
+                .cert_key_pairs
+                .iter()
+                .map(|cert_key_pair| Ok((Rc::clone(cert_key_pair), cert_fingerprints(&(**cert_key_pair).borrow())?)))
+                .collect::<Result<_>>()?,
+            private_keys: cluster_crypto
+                .distributed_private_keys
+                .values()
+                .map(|private_key| Ok((Rc::clone(private_key), private_key_fingerprint(&(**private_key).borrow())?)))
+                .collect::<Result<_>>()?,
+            public_keys: cluster_crypto
+                .distributed_public_keys
+                .values()
+                .map(|public_key| Ok((Rc::clone(public_key), public_key_fingerprint(&(**public_key).borrow().key)?)))
+                .collect::<Result<_>>()?,
+            jwts: cluster_crypto
+                .distributed_jwts
+                .values()
+                .map(|jwt| (Rc::clone(jwt), jwt_fingerprint(&(**jwt).borrow())))
+                .collect(),
+        })
+    }
+
+    fn render(&self) -> Result<String> {
+        let locations = |locations: &Locations| locations.0.iter().map(|location| location.to_string()).collect::<Vec<_>>();
+
+        let mut objects = vec![];
+
+        for (cert_key_pair, (old_fingerprint, old_key_fingerprint)) in &self.cert_key_pairs {
+            let cert_key_pair = (**cert_key_pair).borrow();
+            let (new_fingerprint, new_key_fingerprint) = cert_fingerprints(&cert_key_pair)?;
+            let distributed_cert = (*cert_key_pair.distributed_cert).borrow();
+            objects.push(serde_json::json!({
+                "kind": "cert",
+                "subject": distributed_cert.certificate.subject,
+                "old_fingerprint": old_fingerprint,
+                "new_fingerprint": new_fingerprint,
+                "old_key_fingerprint": old_key_fingerprint,
+                "new_key_fingerprint": new_key_fingerprint,
+                "locations": locations(&distributed_cert.locations),
+            }));
+        }
+
+        for (private_key, old_key_fingerprint) in &self.private_keys {
+            let private_key = (**private_key).borrow();
+            objects.push(serde_json::json!({
+                "kind": "private-key",
+                "old_key_fingerprint": old_key_fingerprint,
+                "new_key_fingerprint": private_key_fingerprint(&private_key)?,
+                "locations": locations(&private_key.locations),
+            }));
+        }
+
+        for (public_key, old_key_fingerprint) in &self.public_keys {
+            let public_key = (**public_key).borrow();
+            objects.push(serde_json::json!({
+                "kind": "public-key",
+                "old_key_fingerprint": old_key_fingerprint,
+                "new_key_fingerprint": public_key_fingerprint(&public_key.key)?,
+                "locations": locations(&public_key.locations),
+            }));
+        }
+
+        Ok(serde_json::to_string_pretty(&serde_json::json!({ "objects": objects }))?)
+    }
+
+    /// Write the mapping from the original fingerprints to the regenerated ones as JSON
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.render()?).with_context(|| format!("writing key continuity map to {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster_crypto::{
+            crypto_objects,
+            locations::{FileContentLocation, FileLocation, Location, LocationValueType},
+            private_key_format::PrivateKeyPolicy,
+        },
+        test_fixtures::CertFixture,
+    };
+
+    fn scan(cert_fixture: &CertFixture) -> ClusterCryptoObjects {
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        for (path, contents) in [("/tls.crt", &cert_fixture.cert_pem), ("/tls.key", &cert_fixture.key_pem)] {
+            cluster_crypto.register_discovered_crypto_objects(
+                crypto_objects::process_pem_bundle(
+                    contents,
+                    &Location::Filesystem(FileLocation {
+                        path: path.to_string(),
+                        content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+                    }),
+                    &PrivateKeyPolicy::default(),
+                )
+                .unwrap(),
+            );
+        }
+        cluster_crypto.pair_certs_and_keys().unwrap();
+        cluster_crypto
+    }
+
+    fn openssl_fingerprint(args: &[&str], input: &str) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("input.pem"), input).unwrap();
+        let output = std::process::Command::new("openssl")
+            .current_dir(dir.path())
+            .args(args)
+            .args(["-in", "input.pem", "-outform", "DER", "-out", "output.der"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        fingerprint(&std::fs::read(dir.path().join("output.der")).unwrap())
+    }
+
+    #[test]
+    fn test_key_continuity() {
+        let original = CertFixture::ca("root");
+        let replacement = CertFixture::ca("root");
+        let cluster_crypto = scan(&original);
+
+        let key_continuity = KeyContinuity::record_originals(&cluster_crypto).unwrap();
+
+        // Stand-in for regeneration, which replaces the cert and key in place
+        let replacement_crypto = scan(&replacement);
+        let replacement_pair = (*replacement_crypto.cert_key_pairs[0]).borrow();
+        let cert_key_pair = (*cluster_crypto.cert_key_pairs[0]).borrow();
+        (*cert_key_pair.distributed_cert).borrow_mut().certificate = (*replacement_pair.distributed_cert).borrow().certificate.clone();
+        (**cert_key_pair.distributed_private_key.as_ref().unwrap()).borrow_mut().key =
+            (**replacement_pair.distributed_private_key.as_ref().unwrap()).borrow().key.clone();
+
+        let rendered: serde_json::Value = serde_json::from_str(&key_continuity.render().unwrap()).unwrap();
+        let object = &rendered["objects"][0];
+        assert_eq!(object["kind"], "cert");
+        assert_eq!(object["old_fingerprint"], openssl_fingerprint(&["x509"], &original.cert_pem));
+        assert_eq!(object["new_fingerprint"], openssl_fingerprint(&["x509"], &replacement.cert_pem));
+        assert_eq!(
+            object["old_key_fingerprint"],
+            openssl_fingerprint(&["pkey", "-pubout"], &original.key_pem)
+        );
+        assert_eq!(
+            object["new_key_fingerprint"],
+            openssl_fingerprint(&["pkey", "-pubout"], &replacement.key_pem)
+        );
+        assert_eq!(object["locations"][0], "file:/tls.crt::pem0");
+    }
+}
@@ -0,0 +1,1970 @@
+use crate::{
+    cluster_crypto::{
+        ca_graft::{self, CaGrafts},
+        cn_filter::CnFilter,
+        entropy, expected_set,
+        extension_policy::{ExtensionOverride, ExtensionPolicy},
+        external_ca::{ExternalCa, ExternalCaSource},
+        jwt::{AudienceReplace, TokenPolicy},
+        private_key_format::{PrivateKeyFormat, PrivateKeyPolicy},
+        resource_kinds::{BuiltinResourceKind, CustomResourceKind, ResourceKindPolicy},
+        sa_signing_keys::SaSigningKeyRegeneration,
+        scanning,
+        serial_policy::SerialPolicy,
+        signature_policy::{Digest, RsaPadding, SignaturePolicy},
+        validity_policy::{self, Validity, ValidityOverride, ValidityPolicy},
+        weak_crypto,
+        yaml_crawl::{self, ScanPolicy},
+    },
+    ocp_postprocess::{
+        additional_trust_bundle::{self, AdditionalTrustBundle},
+        cloud_config_rename::params::CloudEndpointReplace,
+        cluster_domain_rename::params::ClusterRenameParameters,
+        ip_rename::params::{IpRenameParameters, IpReplace},
+        node_rename::params::NodeRenameParameters,
+    },
+};
+use anyhow::{ensure, Context, Result};
+use capabilities::{Capabilities, Capability, OcpVersion};
+use clap::{Parser, Subcommand};
+use cluster_crypto::{ClusterCryptoObjects, CryptoPolicies};
+use cnsanreplace::{CnSanReplace, CnSanReplaceRules};
+use console::OutputFormat;
+use escrow::{EscrowRecipient, EscrowTarget};
+use etcd_snapshot::EtcdSnapshot;
+use file_utils::PermissionPolicy;
+use futures_util::FutureExt;
+use k8s_etcd::{EtcdAccess, InMemoryK8sEtcd};
+use key_continuity::KeyContinuity;
+use namespace_filter::NamespaceFilter;
+use node_dirs::NodeDir;
+use regex::Regex;
+use rsa_key_pool::{KeySizePolicy, PoolSize};
+use std::{
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+mod audit;
+mod backup;
+mod batch;
+mod capabilities;
+mod change_plan;
+mod check_seed;
+mod cleanup;
+mod cluster_crypto;
+mod cnsanreplace;
+mod concurrency;
+mod console;
+mod cross_check;
+mod crypto_inventory;
+mod deterministic;
+mod error_catalog;
+mod escrow;
+mod etcd_dump;
+mod etcd_encryption;
+mod etcd_snapshot;
+mod file_utils;
+mod fuzz_roundtrip;
+mod grep;
+mod install_service;
+mod json_tools;
+mod k8s_etcd;
+mod key_continuity;
+mod list_sans;
+mod namespace_filter;
+mod node_dirs;
+mod ocp_postprocess;
+mod output_dir;
+mod rsa_key_pool;
+mod rules;
+mod run_summary;
+mod sandbox;
+mod signing;
+mod status;
+#[cfg(test)]
+mod test_fixtures;
+mod verify;
+mod watch;
+mod worker;
+mod yaml_tools;
+
+/// The resource rewriting round-trips, for the fuzz targets in fuzz/
+pub mod roundtrip {
+    pub use crate::{
+        cluster_crypto::pem_utils::pem_bundle_remove_pem,
+        fuzz_roundtrip::run_input,
+        json_tools::{find_string_span, patch_string_at_pointer},
+    };
+}
+
+/// A program to regenerate cluster certificates, keys and tokens
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // etcd endpoint to recertify
+    #[arg(long, env = "RECERT_ETCD_ENDPOINT", required_unless_present = "etcd_snapshot")]
+    etcd_endpoint: Option<String>,
+
+    /// Instead of a running etcd, recertify an etcd database file fully offline: a snapshot taken
+    /// with etcdctl snapshot save, or the member/snap/db of a stopped etcd's data dir. The result
+    /// is written to --etcd-snapshot-output, to be restored with etcdutl snapshot restore (or put
+    /// in place of the data dir's db)
+    #[arg(
+        long,
+        env = "RECERT_ETCD_SNAPSHOT",
+        conflicts_with_all = ["etcd_endpoint", "backup_dir", "rollback", "output_dir"],
+        requires = "etcd_snapshot_output"
+    )]
+    etcd_snapshot: Option<PathBuf>,
+
+    /// Where to write the etcd database recertified from --etcd-snapshot
+    #[arg(long, env = "RECERT_ETCD_SNAPSHOT_OUTPUT", requires = "etcd_snapshot")]
+    etcd_snapshot_output: Option<PathBuf>,
+
+    /// CA bundle to verify the etcd server's cert with, to connect to an etcd other than the node's
+    /// own over TLS, such as one on a backup restore host or in a remote maintenance environment.
+    /// An --etcd-endpoint without a scheme is then reached over https
+    #[arg(long, env = "RECERT_ETCD_CACERT")]
+    etcd_cacert: Option<PathBuf>,
+
+    /// Client cert to authenticate to etcd with over TLS, see --etcd-cacert
+    #[arg(long, env = "RECERT_ETCD_CERT", requires = "etcd_key")]
+    etcd_cert: Option<PathBuf>,
+
+    /// The private key of --etcd-cert
+    #[arg(long, env = "RECERT_ETCD_KEY", requires = "etcd_cert")]
+    etcd_key: Option<PathBuf>,
+
+    /// The kube-apiserver's encryption config (its --encryption-provider-config), for clusters
+    /// with etcd encryption at rest. The resources it covers are decrypted when read and encrypted
+    /// again with its write provider (aescbc, aesgcm or a KMS v1 plugin, which has to be running)
+    /// when written. Without it, encrypted resources fail the run
+    #[arg(long, env = "RECERT_ETCD_ENCRYPTION_CONFIG")]
+    etcd_encryption_config: Option<PathBuf>,
+
+    /// Generate a new key, add it to the aescbc / aesgcm providers of --etcd-encryption-config
+    /// that resources are written with, and encrypt everything recert writes with it. The config
+    /// is rewritten in place, copies of it (such as OpenShift's encryption-config secrets) have to
+    /// be updated separately
+    #[arg(long, requires = "etcd_encryption_config")]
+    etcd_encryption_rotate_key: bool,
+
+    /// Directory to recertify, such as /var/lib/kubelet, /etc/kubernetes and /etc/machine-config-daemon. Clusters using
+    /// OVN-Kubernetes also keep the ovnkube-node client certs in /var/lib/ovn-ic/etc (or /var/lib/ovn before OVN
+    /// interconnect) and /etc/ovn, and Open vSwitch keeps its PKI in /etc/openvswitch and /var/lib/openvswitch/pki. Can
+    /// specify multiple times
+    #[arg(long)]
+    static_dir: Vec<PathBuf>,
+
+    /// The filesystem root of a node of a multi-node cluster (e.g. its disk mounted on the host
+    /// running recert), written as NODE:ROOT where NODE is the node's current name. The node's
+    /// /etc/kubernetes, /var/lib/kubelet, /etc/machine-config-daemon and OVN / Open vSwitch dirs
+    /// under the root are recertified as if given with --static-dir. The node's hostname and IPs
+    /// can be changed with --node-config. Can specify multiple times, once per node. For example:
+    /// --node-dir master-0:/mnt/master-0 --node-dir master-1:/mnt/master-1
+    #[arg(long)]
+    node_dir: Vec<NodeDir>,
+
+    /// A list of strings to replace in the subject name of all certificates. Can specify multiple.
+    /// Must come in pairs of old and new values, separated by a space. For example:
+    /// --cn-san-replace "foo bar" --cn-san-replace "baz qux" will replace all instances of "foo"
+    /// with "bar" and all instances of "baz" with "qux" in the CN/SAN of all certificates.
+    /// An optional third value scopes the replacement to certs signed (directly or through
+    /// intermediates) by the CA with that CN, a trailing * matches CNs by prefix. For example:
+    /// --cn-san-replace "*.apps.foo.com *.apps.bar.com ingress-operator@*"
+    #[arg(long)]
+    cn_san_replace: Vec<String>,
+
+    /// Like --cn-san-replace, but with a regex which has to match entire CN/SAN values and a
+    /// replacement which can refer to its capture groups, separated by the last colon. Rewrites
+    /// e.g. wildcard certs and the per-node SANs of many hosts with a single rule. For example:
+    /// --cn-san-replace-regex '(.*)\.old\.base\.domain:$1.new.base.domain'
+    #[arg(long)]
+    cn_san_replace_regex: Vec<String>,
+
+    /// Fail (before anything is written) if any of the --cn-san-replace rules didn't match any
+    /// certificate. Without this, such rules only produce a warning
+    #[arg(long)]
+    strict_rules: bool,
+
+    /// Fail (before anything is written) if any of the crypto objects every cluster of its version
+    /// has (e.g. the etcd signer, the service CA, the service account signer) is missing, which is
+    /// a sign of a broken seed. Without this, missing objects only produce a warning
+    #[arg(long)]
+    strict_expected_set: bool,
+
+    /// Scan and regenerate everything as usual, but instead of committing anything (to etcd or to
+    /// disk) emit a JSON plan of every etcd key and file which would be rewritten, along with the
+    /// locations within them and the crypto objects going there. Postprocessing (e.g.
+    /// --cluster-rename) is skipped entirely
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Skip scanning and regenerating crypto objects entirely, and only apply the postprocessing
+    /// (e.g. --cluster-rename) to a cluster whose crypto was already regenerated by an earlier run,
+    /// or doesn't need to be
+    #[arg(
+        long,
+        conflicts_with_all = ["dry_run", "escrow_archive", "key_continuity_map", "summary_file", "crypto_inventory", "cn_san_replace", "cn_san_replace_regex", "use_ca", "graft_cas", "material_dir"]
+    )]
+    postprocess_only: bool,
+
+    /// Only regenerate the crypto objects, for periodic rotation of an otherwise unchanged
+    /// cluster. Refuses to be combined with anything changing the cluster's identity (its domain,
+    /// node names or the CN/SANs of its certs), even when set through environment variables. Only
+    /// the postprocessing required by the regenerated secrets themselves (the OLM secret hash
+    /// annotations) is still done
+    #[arg(
+        long,
+        env = "RECERT_CRYPTO_ONLY",
+        conflicts_with_all = ["postprocess_only", "cluster_rename", "node_config", "ip_replace", "cloud_endpoint_replace", "additional_trust_bundle", "cn_san_replace", "cn_san_replace_regex"]
+    )]
+    crypto_only: bool,
+
+    /// Write the --dry-run plan to this file rather than to stdout
+    #[arg(long, requires = "dry_run")]
+    change_plan: Option<PathBuf>,
+
+    /// Before regenerating anything, export the original private keys and certs (along with where
+    /// they were found) into this file as JSON encrypted to --escrow-recipient, for manually
+    /// recovering from a botched run. The archive holds every private key of the cluster, so only
+    /// enable this if it can be stored safely
+    #[arg(long, env = "RECERT_ESCROW_ARCHIVE", requires = "escrow_recipient")]
+    escrow_archive: Option<PathBuf>,
+
+    /// Who the escrow archive is encrypted to, either age:RECIPIENT (an age or SSH public key,
+    /// encrypted with the age CLI) or gpg:KEY (a key in gpg's keyring, encrypted with gpg)
+    #[arg(long, env = "RECERT_ESCROW_RECIPIENT", requires = "escrow_archive")]
+    escrow_recipient: Option<EscrowRecipient>,
+
+    /// Before the first write to each file and etcd key, save its original contents into this
+    /// directory (which must not exist yet or be empty). If committing fails, everything saved is
+    /// restored automatically, and it can be restored by hand later with --rollback. The backup
+    /// holds private keys, so it's only readable by its owner
+    #[arg(long, env = "RECERT_BACKUP_DIR", conflicts_with = "dry_run")]
+    backup_dir: Option<PathBuf>,
+
+    /// Instead of recertifying, restore all the files and etcd keys saved in this --backup-dir
+    /// directory to their original contents, deleting those recert created
+    #[arg(long, conflicts_with = "backup_dir")]
+    rollback: Option<PathBuf>,
+
+    /// Leave the files and etcd as they are, and instead write the files which would have been
+    /// modified into a parallel tree under this directory (which must not exist yet or be empty)
+    /// at their full original paths, and the etcd values which would have been put (still
+    /// encoded) at their keys, for build pipelines to inspect and package. Files and etcd keys
+    /// which would have been deleted are listed rather than deleted, see output_dir
+    #[arg(long, env = "RECERT_OUTPUT_DIR", conflicts_with_all = ["dry_run", "backup_dir", "rollback"])]
+    output_dir: Option<PathBuf>,
+
+    /// Once done, write a JSON mapping of the SHA-256 fingerprint of each original cert and key to
+    /// that of its replacement to this file, for external systems (monitoring, cert inventories)
+    /// keeping records of the cluster's certs to update them
+    #[arg(long, env = "RECERT_KEY_CONTINUITY_MAP", conflicts_with = "dry_run")]
+    key_continuity_map: Option<PathBuf>,
+
+    /// Once done, write a JSON summary of every cert, key and jwt found to this file, with their
+    /// locations, subjects, SANs, validity periods and the fingerprints from before and after
+    /// regeneration, for automation to audit and record what changed
+    #[arg(long, env = "RECERT_SUMMARY_FILE", conflicts_with = "dry_run")]
+    summary_file: Option<PathBuf>,
+
+    /// Once done, write an inventory of every cert and key in the cluster as it ends up to this
+    /// file, with their subjects, issuers, expiry, fingerprints and locations, in CycloneDX
+    /// cryptography BOM JSON, for compliance tooling attesting what ships in an image. Signed like
+    /// the audit log when --sign-key is given
+    #[arg(long, env = "RECERT_CRYPTO_INVENTORY", conflicts_with = "dry_run")]
+    crypto_inventory: Option<PathBuf>,
+
+    /// When the private key of a CA isn't found anywhere, mint a brand new CA with the same subject
+    /// in its place (updating all the trust bundles containing it) instead of failing. CAs known
+    /// to have their keys dropped by their creators are always replaced
+    #[arg(long)]
+    regenerate_keyless_cas: bool,
+
+    /// CAs with the same subject but different keys (usually left over from past rotations) are
+    /// always reported. With this, each such group is unified under a single regenerated CA, which
+    /// re-signs all of the group's signees and replaces all of the group's CAs
+    #[arg(long)]
+    unify_duplicate_cas: bool,
+
+    /// The CN of an intermediate CA to take out of its chain. Its signees are re-signed directly
+    /// by its own signer, and it's removed from all the bundles it's in. Can specify multiple
+    #[arg(long)]
+    flatten_chain: Vec<String>,
+
+    /// Leave the certs whose CN matches this glob (e.g. *.apps.example.com) as they are, along with
+    /// everything they signed, e.g. custom ingress certs or certs managed outside the cluster.
+    /// Everything else is still regenerated. Certs signed by a regenerated CA can't be skipped, as
+    /// they would no longer chain to it. Can specify multiple times
+    #[arg(long, env = "RECERT_SKIP_CN", value_delimiter = ',')]
+    skip_cn: Vec<String>,
+
+    /// Only regenerate the certs whose CN matches this glob, along with everything they signed
+    /// (and signers without keys, which can't re-sign them). Other certs and standalone keys are
+    /// left as they are. Can specify multiple times, and combine with --skip-cn
+    #[arg(long, env = "RECERT_ONLY_CN", value_delimiter = ',')]
+    only_cn: Vec<String>,
+
+    /// Where kube-controller-manager keeps a service account token signing key apart from the
+    /// apiserver's, "lockstep" regenerates both of them, while "kube-controller-manager" or
+    /// "apiserver" only regenerates that one, leaving the other as it is. Either way, the public
+    /// key of each signing key must be among the keys the apiserver verifies tokens with
+    #[arg(long, env = "RECERT_SA_SIGNING_KEY_REGENERATION", value_enum, default_value_t)]
+    sa_signing_key_regeneration: SaSigningKeyRegeneration,
+
+    /// Re-sign the root CAs with this CA (e.g. one of an organizational PKI) rather than with their
+    /// own new keys, so that the whole cluster chains to it. Written as CERT,KEY, where the key is
+    /// a PEM file or env:VAR. The roots keep their subjects and become intermediates of this CA,
+    /// which isn't added to any trust bundle
+    #[arg(long, env = "RECERT_USE_CA")]
+    use_ca: Option<ExternalCaSource>,
+
+    /// Directory of .pem files, each with a CA cert and its private key (e.g. as written by
+    /// export-cas), which take the place of the cluster's CAs of the same CN (less the @timestamp
+    /// suffix) as they are, instead of those being regenerated. Their signees still get new keys
+    /// of their own
+    #[arg(long, env = "RECERT_GRAFT_CAS", conflicts_with = "use_ca")]
+    graft_cas: Option<PathBuf>,
+
+    /// Directory of CAs from outside the cluster, and optionally leaf certs they issued, which take
+    /// the place of the cluster's certs of the same CN like --graft-cas, laid out as a
+    /// subdirectory per CA named after its CN (less the @timestamp suffix), holding ca.crt and
+    /// ca.key, along with NAME.crt and NAME.key for each leaf. Intermediate CAs get a subdirectory
+    /// of their own
+    #[arg(long, env = "RECERT_MATERIAL_DIR", conflicts_with_all = ["use_ca", "graft_cas"])]
+    material_dir: Option<PathBuf>,
+
+    /// A glob of the etcd namespaces to scan and modify, prefix with ! to exclude namespaces
+    /// instead. Can specify multiple, a namespace is included if it matches any of the include
+    /// globs (or there are none) and none of the exclude globs. For example:
+    /// --etcd-namespace-filter '!tenant-*' leaves all namespaces starting with tenant- untouched.
+    /// Cluster-scoped resources are not filtered. recert fails rather than commit changes to
+    /// excluded namespaces
+    #[arg(long)]
+    etcd_namespace_filter: Vec<String>,
+
+    /// A resource kind which is scanned by default, but which shouldn't be. Can specify multiple
+    #[arg(long, value_enum)]
+    skip_resource_kind: Vec<BuiltinResourceKind>,
+
+    /// An additional custom resource kind to scan, as GROUP/VERSION/KIND[=PLURAL], for operators
+    /// embedding crypto objects in their CRs. The plural defaults to the lowercase kind with an s
+    /// appended. All strings in such resources are scanned. Can specify multiple. For example:
+    /// --scan-custom-resource camel.apache.org/v1/KameletBinding
+    #[arg(long)]
+    scan_custom_resource: Vec<CustomResourceKind>,
+
+    /// Comma (or colon) separated cluster name and cluster base domain.
+    /// If given, many resources will be modified to use this new information, and the CNs / SANs
+    /// of certs carrying the original cluster domain (or one of its subdomains, e.g. api, api-int
+    /// and *.apps) are replaced to match
+    #[arg(long, env = "RECERT_CLUSTER_RENAME")]
+    cluster_rename: Option<String>,
+
+    /// YAML file with per-node parameters for multi-node (compact 3-node or standard HA)
+    /// clusters, a map from each node's current name to its parameters, e.g.:
+    /// {"nodes": {"master-0": {"hostname": "edge-master-0", "ips": ["192.168.126.10,10.1.2.10"]}, ...}}.
+    /// The per-node etcd certs, secrets and member names follow the new hostnames, and each node's
+    /// IPs are replaced as with --ip-replace, while the CAs remain shared by all nodes
+    #[arg(long, env = "RECERT_NODE_CONFIG")]
+    node_config: Option<PathBuf>,
+
+    /// Comma separated old and new IP address of a relocated node, either of which can be IPv4 or
+    /// IPv6. Can specify multiple, e.g. once per address family of dual-stack clusters. The IP
+    /// SANs (and IP valued CNs / DNS SANs) of regenerated certs are replaced, as are the IPs in
+    /// the etcd resources and static pod configs known to hold the node IP. For example:
+    /// --ip-replace 192.168.126.10,10.1.2.3
+    #[arg(long)]
+    ip_replace: Vec<IpReplace>,
+
+    /// Comma separated old and new endpoint (hostname or IP address) of the infrastructure the
+    /// cluster runs on, e.g. its vCenter. Can specify multiple. The endpoint is replaced in the
+    /// cloud provider and CSI driver configs in etcd and in the static dirs, along with the keys
+    /// of the vSphere credentials secrets. For example:
+    /// --cloud-endpoint-replace vcenter.dc1.example.com,vcenter.dc2.example.com
+    #[arg(long)]
+    cloud_endpoint_replace: Vec<CloudEndpointReplace>,
+
+    /// A PEM file of CA certs for the cluster to trust in addition to its own, e.g. the CA of a
+    /// TLS intercepting proxy in front of the network the cluster is relocated to. The CAs are
+    /// added to the user CA bundle (which is created and set as the proxy's trusted CA if the
+    /// cluster has none), the trusted-ca-bundle configmaps, the image registry CAs and their
+    /// copies in the static dirs, and to the system trust store of the nodes under /etc/pki
+    #[arg(long, env = "RECERT_ADDITIONAL_TRUST_BUNDLE")]
+    additional_trust_bundle: Option<PathBuf>,
+
+    /// Deprecated
+    #[arg(long)]
+    kubeconfig: Option<String>,
+
+    /// How progress is printed. "pretty" adds colored per-phase summaries and a table of the
+    /// chains processed at the end, "plain" prints one line per step. "auto" is pretty when
+    /// stdout is a terminal
+    #[arg(long, env = "RECERT_OUTPUT_FORMAT", value_enum, default_value_t)]
+    output_format: OutputFormat,
+
+    /// How to set the permissions of rewritten files. "strict" makes sure private key files are
+    /// 0600 and other files are at most 0644, "preserve" leaves permissions untouched
+    #[arg(long, env = "RECERT_FILE_PERMISSIONS", value_enum, default_value_t)]
+    file_permissions: PermissionPolicy,
+
+    /// The encoding of regenerated private keys. Keys found as PKCS#8 ("PRIVATE KEY") or
+    /// encrypted PKCS#8 ("ENCRYPTED PRIVATE KEY") are always written back as such, "pkcs8" also
+    /// writes PKCS#1 and SEC1 keys as PKCS#8
+    #[arg(long, env = "RECERT_PRIVATE_KEY_FORMAT", value_enum, default_value_t)]
+    private_key_format: PrivateKeyFormat,
+
+    /// File holding the passphrase of encrypted PKCS#8 private keys, which are regenerated and
+    /// re-encrypted with the same passphrase. Without it, encrypted keys are left untouched
+    #[arg(long, env = "RECERT_PRIVATE_KEY_PASSPHRASE_FILE")]
+    private_key_passphrase_file: Option<PathBuf>,
+
+    /// Append an audit trail of every file and etcd key read or written (along with the SHA-256
+    /// of the contents) to this file, as newline delimited JSON
+    #[arg(long, env = "RECERT_AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+
+    /// Also send the audit trail to journald, as structured journal entries
+    #[arg(long)]
+    audit_journald: bool,
+
+    /// A PEM encoded RSA private key used to sign the artifacts recert produces (the audit log and
+    /// the crypto inventory). Detached PKCS#1 v1.5 SHA-256 signatures are written next to each artifact with
+    /// a .sig suffix. Instead of a path, can be "-" to read the key from stdin or "env:NAME" to
+    /// read it from the NAME environment variable, so that it never has to be written to disk
+    #[arg(long, env = "RECERT_SIGN_KEY")]
+    sign_key: Option<PathBuf>,
+
+    /// A file or device (e.g. a hardware RNG such as /dev/hwrng) to mix into the generation of
+    /// every new key on top of the OS RNG, for devices cloned from the same image onto identical
+    /// hardware, which might not have gathered much entropy of their own that early in their first
+    /// boot. Up to 256 bytes are read from it, at least 32. The source used is printed and
+    /// recorded in the --summary-file
+    #[arg(long, env = "RECERT_ENTROPY_SOURCE")]
+    entropy_source: Option<PathBuf>,
+
+    /// FOR TESTING ONLY: derive every new key and the signatures made with them from this seed,
+    /// and date regenerated certs and tokens from a fixed clock (SOURCE_DATE_EPOCH, or
+    /// 2024-01-01T00:00:00Z), so that two runs over the same cluster produce byte-identical
+    /// output. Anyone who knows the seed can derive the new private keys. RSASSA-PSS signatures
+    /// and etcd encryption are randomized by design, so they can't be used with it
+    #[arg(
+        long,
+        env = "RECERT_DETERMINISTIC_SEED",
+        conflicts_with_all = ["entropy_source", "key_pool_file", "etcd_encryption_config"]
+    )]
+    deterministic_seed: Option<u64>,
+
+    /// Use landlock to restrict filesystem access to the static dirs (plus the system paths
+    /// required to run) and network access to the etcd endpoint port. Requires a kernel
+    /// supporting landlock ABI version 4 or later
+    #[arg(long)]
+    sandbox: bool,
+
+    /// Lock all of recert's memory (which holds private keys) into RAM so that it's never
+    /// swapped out, and prevent core dumps. May require raising RLIMIT_MEMLOCK
+    #[arg(long)]
+    lock_memory: bool,
+
+    /// Keep a small JSON status file (phase, rough percentage done and a heartbeat timestamp
+    /// refreshed every few seconds) at this path while running, for external watchdogs to detect
+    /// a hung recert
+    #[arg(long, env = "RECERT_STATUS_FILE")]
+    status_file: Option<PathBuf>,
+
+    /// If the run fails (or panics), write a JSON report with the phase it failed in and the full
+    /// error to this path
+    #[arg(long, env = "RECERT_FAILURE_REPORT")]
+    failure_report: Option<PathBuf>,
+
+    /// How many layers of base64 (standard or URL-safe) on top of a field's own encoding to decode
+    /// when looking for crypto objects, e.g. 1 to find a base64 encoded PEM in a secret's data
+    #[arg(long, env = "RECERT_MAX_DECODE_DEPTH", default_value_t = yaml_crawl::DEFAULT_MAX_DECODE_DEPTH)]
+    max_decode_depth: usize,
+
+    /// Sniff every secret and configmap value for crypto objects, including configmap binaryData
+    /// and annotations and kubeconfigs stored whole in a value (which are reported, as they can't
+    /// be regenerated in place). Values over 1MiB are skipped
+    #[arg(long, env = "RECERT_EXHAUSTIVE_SCAN")]
+    exhaustive_scan: bool,
+
+    /// How many RSA keys of a given size to generate ahead of time (in parallel with scanning), as
+    /// SIZE=COUNT. Can specify multiple times. Keys beyond these are generated on demand, which is
+    /// slower. The regeneration stats printed at the end suggest values for the scanned cluster.
+    /// Defaults to 2048=300 and 4096=20
+    #[arg(long, env = "RECERT_RSA_KEY_POOL_SIZE", value_delimiter = ',')]
+    rsa_key_pool_size: Vec<PoolSize>,
+
+    /// File of RSA keys generated ahead of time with the keygen subcommand, which the key pool is
+    /// filled with before generating any keys of its own. The file is removed once read, so that
+    /// its keys are never used by more than one run
+    #[arg(long, env = "RECERT_KEY_POOL_FILE")]
+    key_pool_file: Option<PathBuf>,
+
+    /// The size of regenerated RSA keys. "preserve" keeps the size of each original key,
+    /// "min:SIZE" upgrades smaller keys to SIZE bits (e.g. min:4096) and "exact:SIZE" makes all
+    /// keys SIZE bits. The key pool is sized accordingly
+    #[arg(long, env = "RECERT_RSA_KEY_SIZE_POLICY", default_value_t)]
+    rsa_key_size_policy: KeySizePolicy,
+
+    /// Replace RSA keys smaller than 2048 bits with 2048-bit keys. Without this, recert refuses
+    /// to regenerate them. Weak crypto found during the scan is reported either way
+    #[arg(long, env = "RECERT_UPGRADE_WEAK_CRYPTO")]
+    upgrade_weak_crypto: bool,
+
+    /// The digest of the RSA signatures of regenerated certs. ECDSA signatures always use the
+    /// digest that goes with the curve of the signing key
+    #[arg(long, env = "RECERT_RSA_SIGNATURE_DIGEST", value_enum, default_value_t)]
+    rsa_signature_digest: Digest,
+
+    /// The padding of the RSA signatures of regenerated certs, "pss" for RSASSA-PSS. Service
+    /// account tokens are always signed with RS256, which is the only algorithm the API server
+    /// accepts for them
+    #[arg(long, env = "RECERT_RSA_SIGNATURE_PADDING", value_enum, default_value_t)]
+    rsa_signature_padding: RsaPadding,
+
+    /// The serial numbers of regenerated certs: "preserve" keeps the original ones, which external
+    /// trust stores and monitoring might have pinned, "random" gives every cert a new random 20
+    /// byte one, and "sequential" numbers them from 1
+    #[arg(long, env = "RECERT_SERIAL_POLICY", value_enum, default_value_t)]
+    serial_policy: SerialPolicy,
+
+    /// The validity period of regenerated certs which aren't CAs. "preserve" keeps the original
+    /// notBefore / notAfter, "preserve-remaining" makes them valid from now for as long as the
+    /// originals had left, and a lifetime like 365d, 72h or 10y makes them valid from now for that
+    /// long
+    #[arg(long, env = "RECERT_CERT_VALIDITY", default_value = "preserve")]
+    cert_validity: Validity,
+
+    /// The validity period of regenerated CA certs, like --cert-validity
+    #[arg(long, env = "RECERT_CA_VALIDITY", default_value = "preserve")]
+    ca_validity: Validity,
+
+    /// The validity period of the regenerated certs with a given CN, as CN=VALIDITY, taking
+    /// precedence over --cert-validity and --ca-validity. A trailing * in the CN matches any CN
+    /// with the given prefix. Can specify multiple, the first matching one applies. For example:
+    /// --validity-override 'ingress-operator@*=2y'
+    #[arg(long)]
+    validity_override: Vec<ValidityOverride>,
+
+    /// Override an extension of the regenerated certs with a given CN, as CN=EXTENSION:VALUE,
+    /// where EXTENSION is keyUsage, extendedKeyUsage or basicConstraints, written like openssl's
+    /// (or none to remove it). Extensions are otherwise carried over from the original certs as
+    /// they are. A trailing * in the CN matches any CN with the given prefix. Can specify multiple,
+    /// every matching one applies. For example: --extension-override
+    /// 'system:node:*=extendedKeyUsage:clientAuth'
+    #[arg(long)]
+    extension_override: Vec<ExtensionOverride>,
+
+    /// Re-issue the bound service account tokens found to be valid from now for this long (e.g.
+    /// 24h or 365d) when re-signing them, rather than keeping their original expiry, which may have
+    /// long passed. Legacy service account tokens never expire and are left that way
+    #[arg(long, env = "RECERT_TOKEN_EXPIRY", value_parser = validity_policy::parse_duration)]
+    token_expiry: Option<chrono::Duration>,
+
+    /// Comma separated old and new audience of the bound service account tokens found, replaced
+    /// when re-signing them (e.g. along with a change of the service account issuer). Can specify
+    /// multiple. For example:
+    /// --token-audience-replace https://kubernetes.default.svc,https://api.edge.example.com
+    #[arg(long)]
+    token_audience_replace: Vec<AudienceReplace>,
+
+    /// Only regenerate the certs expiring within this window from now, e.g. 30d, for rotating what's
+    /// about to expire during a maintenance window. Everything the expiring certs signed is
+    /// regenerated with them, and signers without keys (which can't re-sign them) are regenerated
+    /// along with them. Other certs and standalone keys are left as they are
+    #[arg(
+        long,
+        env = "RECERT_ROTATE_EXPIRING_WITHIN",
+        value_parser = validity_policy::parse_duration,
+        conflicts_with = "postprocess_only"
+    )]
+    rotate_expiring_within: Option<chrono::Duration>,
+
+    /// Maximum number of etcd keys / files processed concurrently. Lower this if recert uses too
+    /// much memory or overloads etcd on big clusters
+    #[arg(long, env = "RECERT_MAX_CONCURRENCY", default_value_t = concurrency::DEFAULT_MAX_CONCURRENCY)]
+    max_concurrency: usize,
+
+    /// The OCP version (e.g. 4.13) of the cluster, which determines version specific behavior.
+    /// Detected from the ClusterVersion if not given
+    #[arg(long, env = "RECERT_OCP_VERSION")]
+    ocp_version: Option<OcpVersion>,
+}
+
+/// How the subcommands reading etcd connect to it over TLS and decrypt its values, like a run
+/// does with its own --etcd-cacert, --etcd-cert, --etcd-key and --etcd-encryption-config
+#[derive(clap::Args)]
+struct EtcdArgs {
+    /// CA bundle to verify the etcd server's cert with, to connect to etcd over TLS. An
+    /// --etcd-endpoint without a scheme is then reached over https
+    #[arg(long, env = "RECERT_ETCD_CACERT")]
+    etcd_cacert: Option<PathBuf>,
+
+    /// Client cert to authenticate to etcd with over TLS, see --etcd-cacert
+    #[arg(long, env = "RECERT_ETCD_CERT", requires = "etcd_key")]
+    etcd_cert: Option<PathBuf>,
+
+    /// The private key of --etcd-cert
+    #[arg(long, env = "RECERT_ETCD_KEY", requires = "etcd_cert")]
+    etcd_key: Option<PathBuf>,
+
+    /// The kube-apiserver's encryption config, for clusters with etcd encryption at rest. The
+    /// resources it covers are decrypted when read (and encrypted again when written)
+    #[arg(long, env = "RECERT_ETCD_ENCRYPTION_CONFIG")]
+    etcd_encryption_config: Option<PathBuf>,
+}
+
+impl EtcdArgs {
+    fn load(&self) -> Result<EtcdAccess> {
+        Ok(EtcdAccess {
+            tls: k8s_etcd::load_tls(self.etcd_cacert.as_deref(), self.etcd_cert.as_deref(), self.etcd_key.as_deref())
+                .context("loading etcd TLS credentials")?,
+            encryption: etcd_encryption::load(self.etcd_encryption_config.as_deref(), false)
+                .context("loading etcd encryption config")?
+                .map(Arc::new),
+        })
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Scan without modifying anything and list the unique CN/SAN values of all certificates,
+    /// along with the number of certificates carrying each of them
+    ListSans {
+        /// etcd endpoint to scan
+        #[arg(long)]
+        etcd_endpoint: String,
+
+        #[command(flatten)]
+        etcd: EtcdArgs,
+
+        /// Directory to scan. Can specify multiple times
+        #[arg(long)]
+        static_dir: Vec<PathBuf>,
+    },
+
+    /// Scan without modifying anything and export the CAs whose private keys are found, each
+    /// into its own .pem file, to be grafted into other clusters with --graft-cas
+    ExportCas {
+        /// etcd endpoint to scan
+        #[arg(long)]
+        etcd_endpoint: String,
+
+        #[command(flatten)]
+        etcd: EtcdArgs,
+
+        /// Directory to scan. Can specify multiple times
+        #[arg(long)]
+        static_dir: Vec<PathBuf>,
+
+        /// Directory to export the CAs to, which must not exist yet or be empty
+        #[arg(long)]
+        out: PathBuf,
+    },
+
+    /// Generate the RSA keys of the key pool ahead of time into a file, for --key-pool-file. Each
+    /// file is good for a single run, so it has to be generated for each node separately (e.g. on
+    /// the node, ahead of the downtime of its reconfiguration)
+    Keygen {
+        /// File to write the keys to, which must not exist yet
+        #[arg(long)]
+        out: PathBuf,
+
+        /// How many RSA keys of a given size to generate, as SIZE=COUNT. Can specify multiple
+        /// times. Defaults to 2048=300 and 4096=20
+        #[arg(long, value_delimiter = ',')]
+        rsa_key_pool_size: Vec<PoolSize>,
+
+        /// The --rsa-key-size-policy of the run the keys are for
+        #[arg(long, default_value_t)]
+        rsa_key_size_policy: KeySizePolicy,
+    },
+
+    /// Run multiple independent recert jobs (e.g. one per appliance being imaged) concurrently,
+    /// as described by a manifest. Each job's output and an aggregate summary are written to the
+    /// report dir
+    Batch {
+        /// YAML manifest with a list of jobs, each with a name, an etcd-endpoint and optionally
+        /// static-dirs, cn-san-replace, cluster-rename and extra-args (any other recert flags)
+        #[arg(long)]
+        manifest: PathBuf,
+
+        /// Directory for the per-job logs and the summary.json
+        #[arg(long, default_value = "batch-reports")]
+        report_dir: PathBuf,
+
+        /// Maximum number of jobs to run at the same time. Unlimited if not given
+        #[arg(long)]
+        max_parallel: Option<usize>,
+    },
+
+    /// Write a systemd unit running recert once at first boot (ordered after crio and before the
+    /// kubelet), along with a kubelet drop-in, so integrators don't have to write their own
+    InstallService {
+        /// File with the RECERT_* environment variables recert is configured with, e.g.
+        /// RECERT_ETCD_ENDPOINT=localhost:2379
+        #[arg(long)]
+        config: PathBuf,
+
+        /// Directory to write the units to
+        #[arg(long, default_value = "/etc/systemd/system")]
+        unit_dir: PathBuf,
+
+        /// Name of the recert unit
+        #[arg(long, default_value = install_service::DEFAULT_UNIT_NAME)]
+        unit_name: String,
+
+        /// The recert executable the unit runs. Defaults to the running executable
+        #[arg(long)]
+        executable: Option<PathBuf>,
+
+        /// File created once recert succeeded, which prevents it from running again on later boots
+        #[arg(long, default_value = install_service::DEFAULT_DONE_MARKER)]
+        done_marker: PathBuf,
+    },
+
+    /// Re-key a worker node's local materials against a control plane which has already been
+    /// recertified, without access to the cluster's etcd. The CA certs in the worker's CA bundles
+    /// and kubeconfigs are replaced by the control plane CAs with the same CN, and the kubelet's
+    /// client and serving certs are removed so that the kubelet bootstraps new ones
+    Worker {
+        /// Directory of the worker, such as /var/lib/kubelet and /etc/kubernetes. Can specify
+        /// multiple times
+        #[arg(long, required = true)]
+        static_dir: Vec<PathBuf>,
+
+        /// PEM bundle of the recertified control plane's CA certs
+        #[arg(long)]
+        control_plane_ca_bundle: PathBuf,
+    },
+
+    /// Re-scan etcd and the static dirs after a run (without modifying anything) and check that
+    /// every cert is signed by its issuer, every private key goes with the certs / public keys next
+    /// to it and every JWT verifies against one of the cluster's keys. Exits non-zero if anything
+    /// is inconsistent
+    Verify {
+        /// etcd endpoint to verify
+        #[arg(long)]
+        etcd_endpoint: String,
+
+        #[command(flatten)]
+        etcd: EtcdArgs,
+
+        /// Directory to verify. Can specify multiple times
+        #[arg(long)]
+        static_dir: Vec<PathBuf>,
+
+        /// The --key-continuity-map of the run, to also check that none of the original certs and
+        /// keys it replaced (e.g. the old CAs) are still around
+        #[arg(long)]
+        key_continuity_map: Option<PathBuf>,
+
+        /// Write a JSON report of every inconsistency found to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+
+        /// Write the results of every check, along with the unsupported objects which couldn't be
+        /// checked, to this file as JUnit XML, for CI pipelines to show as test results
+        #[arg(long)]
+        junit_report: Option<PathBuf>,
+    },
+
+    /// Watch the secrets and configmaps of a live cluster for a while after a run (e.g. once it
+    /// first boots) and alert on any written with one of the original certs / keys the run
+    /// replaced or with an old domain, catching operators which restore stale state from their
+    /// caches. Exits non-zero if anything was alerted on
+    Watch {
+        /// etcd endpoint to watch
+        #[arg(long)]
+        etcd_endpoint: String,
+
+        #[command(flatten)]
+        etcd: EtcdArgs,
+
+        /// The --key-continuity-map of the run, whose replaced originals must not come back
+        #[arg(long)]
+        key_continuity_map: Option<PathBuf>,
+
+        /// A domain the cluster no longer has (e.g. its cluster domain before a --cluster-rename),
+        /// which must not come back. Can specify multiple times
+        #[arg(long)]
+        old_domain: Vec<String>,
+
+        /// How long to watch for, e.g. 2h or 1d
+        #[arg(long, default_value = "1h", value_parser = validity_policy::parse_duration)]
+        duration: chrono::Duration,
+    },
+
+    /// Write every etcd key into its own file under a directory, decoded into YAML whenever
+    /// possible (KEY.yaml for protobuf values, KEY.json.yaml for JSON values such as custom
+    /// resources, KEY.raw for anything else), for inspecting, diffing and hand-editing the
+    /// cluster's resources around a recert run
+    EtcdDump {
+        /// etcd endpoint to dump
+        #[arg(long)]
+        etcd_endpoint: String,
+
+        #[command(flatten)]
+        etcd: EtcdArgs,
+
+        /// Directory to write the dump to, which must not exist yet or be empty
+        #[arg(long)]
+        out: PathBuf,
+
+        /// Only dump the keys with this prefix
+        #[arg(long, default_value = "/kubernetes.io/")]
+        prefix: String,
+    },
+
+    /// Put the (possibly hand-edited) files of an etcd-dump back into etcd. Only the keys whose
+    /// files differ from what's in etcd are written, keys missing from the dump are left alone
+    EtcdLoad {
+        /// etcd endpoint to load into
+        #[arg(long)]
+        etcd_endpoint: String,
+
+        #[command(flatten)]
+        etcd: EtcdArgs,
+
+        /// Directory of the etcd-dump to load
+        #[arg(long)]
+        from: PathBuf,
+    },
+
+    /// Search etcd values (decoded from protobuf) and the files of the static dirs for a regex,
+    /// looking through any base64, data URL and gzip encoding along the way, and print where it
+    /// matched. For writing rename rules, or chasing what's left of the old identity after a run
+    Grep {
+        /// Regex to search for
+        pattern: Regex,
+
+        /// etcd endpoint to search. Only the static dirs are searched if not given
+        #[arg(long)]
+        etcd_endpoint: Option<String>,
+
+        #[command(flatten)]
+        etcd: EtcdArgs,
+
+        /// Only search the keys with this prefix
+        #[arg(long, default_value = "/kubernetes.io/")]
+        prefix: String,
+
+        /// Directory to search. Can specify multiple times
+        #[arg(long)]
+        static_dir: Vec<PathBuf>,
+    },
+
+    /// Development aid: run randomized round-trips of locating a PEM bundle in a resource,
+    /// replacing one of its PEMs and serializing the resource again, failing on the first case
+    /// which corrupts the resource
+    #[command(hide = true)]
+    FuzzRoundtrip {
+        /// Number of cases to run
+        #[arg(long, default_value_t = 10000)]
+        iterations: u64,
+
+        /// Seed of the first case, cases use consecutive seeds. Random if not given
+        #[arg(long, conflicts_with = "input")]
+        seed: Option<u64>,
+
+        /// Run the single case generated from this file instead, such as an input the fuzz target
+        /// in fuzz/ found a failure with
+        #[arg(long)]
+        input: Option<PathBuf>,
+    },
+
+    /// Inspect a seed (its etcd or etcd snapshot and its static dirs) without modifying anything:
+    /// its OCP version, how its etcd values are stored and which kinds of keys it has. Reports
+    /// what a run over it would require (e.g. --etcd-encryption-config for encryption at rest)
+    /// and exits non-zero if this build of recert can't recertify it
+    CheckSeed {
+        /// etcd endpoint of the seed
+        #[arg(long, conflicts_with = "etcd_snapshot", required_unless_present = "etcd_snapshot")]
+        etcd_endpoint: Option<String>,
+
+        #[command(flatten)]
+        etcd: EtcdArgs,
+
+        /// etcd database file of the seed, instead of a running etcd
+        #[arg(long)]
+        etcd_snapshot: Option<PathBuf>,
+
+        /// Directory of the seed. Can specify multiple times
+        #[arg(long)]
+        static_dir: Vec<PathBuf>,
+
+        /// The OCP version of the seed, if it can't be detected
+        #[arg(long)]
+        ocp_version: Option<OcpVersion>,
+    },
+
+    /// Print the error catalog as JSON: the stable code of every class of failure along with its
+    /// English message template, for integrators to map the code in the --failure-report (or the
+    /// --status-file) to guidance of their own
+    ErrorCodes,
+}
+
+impl Command {
+    /// How the subcommands connecting to etcd connect to it
+    fn etcd(&self) -> Option<&EtcdArgs> {
+        match self {
+            Command::ListSans { etcd, .. }
+            | Command::ExportCas { etcd, .. }
+            | Command::Verify { etcd, .. }
+            | Command::Watch { etcd, .. }
+            | Command::EtcdDump { etcd, .. }
+            | Command::EtcdLoad { etcd, .. }
+            | Command::Grep { etcd, .. }
+            | Command::CheckSeed { etcd, .. } => Some(etcd),
+            Command::Keygen { .. }
+            | Command::Batch { .. }
+            | Command::InstallService { .. }
+            | Command::Worker { .. }
+            | Command::FuzzRoundtrip { .. }
+            | Command::ErrorCodes => None,
+        }
+    }
+}
+
+/// The recert CLI, see main.rs. recert is a library only so that the fuzz targets in fuzz/ can
+/// build against it, see roundtrip
+pub fn main() -> Result<()> {
+    let args = Cli::parse();
+
+    if let Some(command) = args.command {
+        // The credentials and encryption config might be outside of what the sandbox allows
+        // reading
+        let etcd_access = match command.etcd() {
+            Some(etcd) => etcd.load()?,
+            None => EtcdAccess::default(),
+        };
+
+        return match command {
+            Command::ListSans {
+                etcd_endpoint, static_dir, ..
+            } => tokio::runtime::Runtime::new()?.block_on(list_sans::list_sans(&etcd_endpoint, &etcd_access, static_dir)),
+            Command::ExportCas {
+                etcd_endpoint,
+                static_dir,
+                out,
+                ..
+            } => tokio::runtime::Runtime::new()?.block_on(ca_graft::export_cas(&etcd_endpoint, &etcd_access, static_dir, &out)),
+            Command::Keygen {
+                out,
+                rsa_key_pool_size,
+                rsa_key_size_policy,
+            } => {
+                let pool_sizes = if rsa_key_pool_size.is_empty() {
+                    rsa_key_pool::DEFAULT_POOL_SIZES.to_vec()
+                } else {
+                    rsa_key_pool_size
+                };
+                tokio::runtime::Runtime::new()?.block_on(rsa_key_pool::keygen(&out, &pool_sizes, rsa_key_size_policy))
+            }
+            Command::Batch {
+                manifest,
+                report_dir,
+                max_parallel,
+            } => {
+                let manifest = batch::BatchManifest::load(&manifest).context("loading batch manifest")?;
+                tokio::runtime::Runtime::new()?.block_on(batch::run(manifest, &report_dir, max_parallel))
+            }
+            Command::InstallService {
+                config,
+                unit_dir,
+                unit_name,
+                executable,
+                done_marker,
+            } => {
+                let params = install_service::ServiceParameters {
+                    unit_name,
+                    config,
+                    executable: match executable {
+                        Some(executable) => executable,
+                        None => std::env::current_exe().context("finding recert executable")?,
+                    },
+                    done_marker,
+                };
+                tokio::runtime::Runtime::new()?.block_on(install_service::install_service(&params, &unit_dir))
+            }
+            Command::Worker {
+                static_dir,
+                control_plane_ca_bundle,
+            } => tokio::runtime::Runtime::new()?.block_on(worker::worker(static_dir, &control_plane_ca_bundle)),
+            Command::Verify {
+                etcd_endpoint,
+                static_dir,
+                key_continuity_map,
+                report,
+                junit_report,
+                ..
+            } => tokio::runtime::Runtime::new()?.block_on(verify::verify(
+                &etcd_endpoint,
+                &etcd_access,
+                static_dir,
+                key_continuity_map.as_deref(),
+                report.as_deref(),
+                junit_report.as_deref(),
+            )),
+            Command::Watch {
+                etcd_endpoint,
+                key_continuity_map,
+                old_domain,
+                duration,
+                ..
+            } => tokio::runtime::Runtime::new()?.block_on(watch::watch(
+                &etcd_endpoint,
+                &etcd_access,
+                key_continuity_map.as_deref(),
+                &old_domain,
+                duration,
+            )),
+            Command::EtcdDump {
+                etcd_endpoint,
+                out,
+                prefix,
+                ..
+            } => tokio::runtime::Runtime::new()?.block_on(etcd_dump::dump(&etcd_endpoint, &etcd_access, &out, &prefix)),
+            Command::EtcdLoad { etcd_endpoint, from, .. } => {
+                tokio::runtime::Runtime::new()?.block_on(etcd_dump::load(&etcd_endpoint, &etcd_access, &from))
+            }
+            Command::Grep {
+                pattern,
+                etcd_endpoint,
+                prefix,
+                static_dir,
+                ..
+            } => {
+                tokio::runtime::Runtime::new()?.block_on(grep::grep(&pattern, etcd_endpoint.as_deref(), &etcd_access, &prefix, static_dir))
+            }
+            Command::FuzzRoundtrip { input: Some(input), .. } => {
+                fuzz_roundtrip::run_input(&std::fs::read(&input).with_context(|| format!("reading {:?}", input))?)?;
+                println!("round-trip case of {:?} passed", input);
+                Ok(())
+            }
+            Command::FuzzRoundtrip { iterations, seed, .. } => {
+                fuzz_roundtrip::fuzz_roundtrip(seed.unwrap_or_else(rand::random), iterations)
+            }
+            Command::CheckSeed {
+                etcd_endpoint,
+                etcd_snapshot,
+                static_dir,
+                ocp_version,
+                ..
+            } => tokio::runtime::Runtime::new()?.block_on(check_seed::check_seed(
+                etcd_endpoint.as_deref(),
+                &etcd_access,
+                etcd_snapshot.as_deref(),
+                static_dir,
+                ocp_version,
+            )),
+            Command::ErrorCodes => error_catalog::print_catalog(),
+        };
+    }
+
+    // The credentials might be outside of what the sandbox allows reading
+    let etcd_access = EtcdAccess {
+        tls: k8s_etcd::load_tls(args.etcd_cacert.as_deref(), args.etcd_cert.as_deref(), args.etcd_key.as_deref())
+            .context("loading etcd TLS credentials")?,
+        // Same for the encryption config
+        encryption: etcd_encryption::load(args.etcd_encryption_config.as_deref(), args.etcd_encryption_rotate_key)
+            .context("loading etcd encryption config")?
+            .map(Arc::new),
+    };
+
+    if let Some(backup_dir) = args.rollback {
+        let etcd_endpoint = args.etcd_endpoint.context("missing etcd endpoint")?;
+        return tokio::runtime::Runtime::new()?.block_on(async {
+            backup::rollback(&backup_dir, &etcd_access.connect(&etcd_endpoint).await?)
+                .await
+                .context("rolling back")
+        });
+    }
+
+    // The key might be outside of what the sandbox allows reading
+    signing::init(args.sign_key.clone()).context("loading signing key")?;
+    // Same for the additional trust bundle
+    let additional_trust_bundle = args
+        .additional_trust_bundle
+        .as_deref()
+        .map(AdditionalTrustBundle::load)
+        .transpose()
+        .context("loading additional trust bundle")?;
+    // Same for the entropy source
+    entropy::init(args.entropy_source.as_deref()).context("loading entropy source")?;
+    // Same for the key pool file, which is also removed
+    rsa_key_pool::load_pool_file(args.key_pool_file.as_deref()).context("loading key pool file")?;
+    ensure!(
+        args.deterministic_seed.is_none() || args.rsa_signature_padding != RsaPadding::Pss,
+        "RSASSA-PSS signatures are randomized, they can't be used with --deterministic-seed"
+    );
+    deterministic::init(args.deterministic_seed).context("initializing deterministic mode")?;
+
+    // Has to happen before the runtime spawns its worker threads, as landlock only restricts the
+    // calling thread and threads created after it
+    if args.sandbox {
+        sandbox::restrict(&sandbox_policy(&args)?).context("sandboxing")?;
+    }
+
+    if args.lock_memory {
+        sandbox::lock_memory().context("locking memory")?;
+    }
+
+    concurrency::set_max_concurrency(args.max_concurrency)?;
+    console::init(args.output_format)?;
+
+    tokio::runtime::Runtime::new()?.block_on(main_internal(args, etcd_access, additional_trust_bundle))
+}
+
+fn sandbox_policy(cli: &Cli) -> Result<sandbox::SandboxPolicy> {
+    let connect_ports = match cli.etcd_endpoint.as_deref() {
+        Some(etcd_endpoint) => {
+            let etcd_endpoint = if etcd_endpoint.contains("://") {
+                url::Url::parse(etcd_endpoint)
+            } else if cli.etcd_cacert.is_some() || cli.etcd_cert.is_some() {
+                url::Url::parse(&format!("https://{}", etcd_endpoint))
+            } else {
+                url::Url::parse(&format!("http://{}", etcd_endpoint))
+            }
+            .context("parsing etcd endpoint")?;
+
+            vec![etcd_endpoint.port_or_known_default().context("etcd endpoint has no port")?]
+        }
+        // Snapshots are worked on offline
+        None => vec![],
+    };
+
+    Ok(sandbox::SandboxPolicy {
+        read_write_paths: cli
+            .static_dir
+            .iter()
+            .cloned()
+            .chain(node_dirs::static_dirs(&cli.node_dir)?)
+            // The system trust stores the additional trust bundle is added to
+            .chain(if cli.additional_trust_bundle.is_some() {
+                additional_trust_bundle::system_trust_dirs(&node_dirs::roots(&cli.node_dir))
+            } else {
+                vec![]
+            })
+            // The audit log (and its signature), the status file, the failure report, the change
+            // plan, the escrow archive, the key continuity map, the summary file, the crypto
+            // inventory (and its signature), the backup dir and the output dir might not exist
+            // yet, so we need to be able to create files next to them
+            .chain(
+                cli.audit_log
+                    .iter()
+                    .chain(&cli.status_file)
+                    .chain(&cli.failure_report)
+                    .chain(&cli.change_plan)
+                    .chain(&cli.escrow_archive)
+                    .chain(&cli.key_continuity_map)
+                    .chain(&cli.summary_file)
+                    .chain(&cli.crypto_inventory)
+                    .chain(&cli.backup_dir)
+                    .chain(&cli.output_dir)
+                    .chain(&cli.etcd_snapshot)
+                    .chain(&cli.etcd_snapshot_output)
+                    .chain(&cli.etcd_encryption_config)
+                    .map(|path| match path.parent() {
+                        Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+                        _ => PathBuf::from("."),
+                    }),
+            )
+            .collect(),
+        connect_ports,
+    })
+}
+
+fn crypto_policies(cli: &Cli) -> CryptoPolicies {
+    CryptoPolicies {
+        file_permissions: cli.file_permissions,
+        serial: cli.serial_policy,
+        signature: SignaturePolicy {
+            rsa_digest: cli.rsa_signature_digest,
+            rsa_padding: cli.rsa_signature_padding,
+        },
+        validity: ValidityPolicy {
+            cert: cli.cert_validity,
+            ca: cli.ca_validity,
+            overrides: cli.validity_override.clone(),
+        },
+        extension: ExtensionPolicy {
+            overrides: cli.extension_override.clone(),
+        },
+        token: TokenPolicy {
+            expiry: cli.token_expiry,
+            audience_replace: cli.token_audience_replace.clone(),
+        },
+        private_key: PrivateKeyPolicy {
+            format: cli.private_key_format,
+            passphrase_file: cli.private_key_passphrase_file.clone(),
+        },
+        scan: ScanPolicy {
+            max_decode_depth: cli.max_decode_depth,
+            exhaustive: cli.exhaustive_scan,
+            resource_kinds: ResourceKindPolicy {
+                skipped: cli.skip_resource_kind.clone(),
+                custom: cli.scan_custom_resource.clone(),
+            },
+        },
+    }
+}
+
+async fn main_internal(args: Cli, etcd_access: EtcdAccess, additional_trust_bundle: Option<AdditionalTrustBundle>) -> Result<()> {
+    let failure_report = args.failure_report.clone();
+
+    status::init(args.status_file.clone()).context("initializing status file")?;
+    let heartbeat = status::spawn_heartbeat();
+
+    // Panics are handled like any other failure, so that they don't leave temporary files and a
+    // stale status file behind
+    let result = match AssertUnwindSafe(run(args, etcd_access, additional_trust_bundle))
+        .catch_unwind()
+        .await
+    {
+        Ok(result) => result,
+        Err(payload) => Err(cleanup::Panicked::from_payload(payload).into()),
+    };
+
+    heartbeat.abort();
+
+    if let Err(err) = &result {
+        cleanup::remove_temporaries();
+
+        if let Some(failure_report) = failure_report {
+            cleanup::write_failure_report(&failure_report, err).context("writing failure report")?;
+        }
+    }
+
+    console::finish(&result);
+    status::finish(&result).context("writing final status")?;
+
+    result
+}
+
+async fn run(args: Cli, etcd_access: EtcdAccess, additional_trust_bundle: Option<AdditionalTrustBundle>) -> Result<()> {
+    let audit_log = args.audit_log.clone();
+
+    let strict_rules = args.strict_rules;
+    let strict_expected_set = args.strict_expected_set;
+    let dry_run = args.dry_run;
+    let postprocess_only = args.postprocess_only;
+    let crypto_only = args.crypto_only;
+    if crypto_only {
+        println!("Crypto only, the cluster's identity is left untouched");
+    }
+    let change_plan = args.change_plan.clone();
+    let key_continuity_map = args.key_continuity_map.clone();
+    let summary_file = args.summary_file.clone();
+    let crypto_inventory = args.crypto_inventory.clone();
+    let crypto_policies = crypto_policies(&args);
+    let regeneration_policy = RegenerationPolicy {
+        regenerate_keyless_cas: args.regenerate_keyless_cas,
+        unify_duplicate_cas: args.unify_duplicate_cas,
+        flatten_chains: args.flatten_chain.clone(),
+        rsa_key_size_policy: if args.upgrade_weak_crypto {
+            args.rsa_key_size_policy.with_minimum(rsa_key_pool::MIN_RSA_KEY_SIZE)
+        } else {
+            args.rsa_key_size_policy
+        },
+        upgrade_weak_crypto: args.upgrade_weak_crypto,
+        rsa_key_pool_sizes: if args.rsa_key_pool_size.is_empty() {
+            rsa_key_pool::DEFAULT_POOL_SIZES.to_vec()
+        } else {
+            args.rsa_key_pool_size.clone()
+        },
+        external_ca: args
+            .use_ca
+            .as_ref()
+            .map(ExternalCa::load)
+            .transpose()
+            .context("loading external CA")?,
+        ca_grafts: match (&args.graft_cas, &args.material_dir) {
+            (Some(dir), _) => Some(CaGrafts::load(dir, &crypto_policies.private_key).context("loading grafted CAs")?),
+            (None, Some(dir)) => Some(CaGrafts::load_material_dir(dir, &crypto_policies.private_key).context("loading material dir")?),
+            (None, None) => None,
+        },
+        rotate_expiring_within: args.rotate_expiring_within,
+        cn_filter: CnFilter::new(&args.skip_cn, &args.only_cn).context("parsing CN filters")?,
+        sa_signing_key_regeneration: args.sa_signing_key_regeneration,
+        escrow: args
+            .escrow_archive
+            .clone()
+            .zip(args.escrow_recipient.clone())
+            .map(|(archive, recipient)| EscrowTarget { archive, recipient }),
+    };
+    let ocp_version = args.ocp_version;
+    let cloud_endpoint_replace = args.cloud_endpoint_replace.clone();
+    let system_trust_dirs = additional_trust_bundle::system_trust_dirs(&node_dirs::roots(&args.node_dir));
+
+    status::phase("initializing", 0)?;
+    let (static_dirs, mut cluster_crypto, memory_etcd, cn_san_replace_rules, cluster_rename, node_rename, ip_rename) =
+        init(args, &etcd_access, crypto_policies).await.context("initializing")?;
+
+    let postprocessing = Postprocessing {
+        cluster_rename,
+        node_rename,
+        ip_rename,
+        cloud_endpoint_replace,
+        additional_trust_bundle,
+        system_trust_dirs,
+        crypto_only,
+    };
+    // Before anything is regenerated
+    postprocessing.check_crypto_only()?;
+
+    status::phase("detecting capabilities", 5)?;
+    let capabilities = Capabilities::detect(&memory_etcd, ocp_version)
+        .await
+        .context("detecting cluster capabilities")?;
+    println!("Detected cluster capabilities:");
+    capabilities.report();
+
+    // Scanning and recertification
+    let key_continuity = if postprocess_only {
+        println!("Postprocessing only, skipping scanning and regeneration");
+        None
+    } else {
+        Some(
+            recertify(
+                Arc::clone(&memory_etcd),
+                &mut cluster_crypto,
+                static_dirs.clone(),
+                cn_san_replace_rules,
+                strict_rules,
+                strict_expected_set,
+                &regeneration_policy,
+                &capabilities,
+            )
+            .await
+            .context("recertification")?,
+        )
+    };
+
+    if dry_run {
+        status::phase("planning", 70)?;
+        change_plan::ChangePlan::new(&cluster_crypto)
+            .emit(change_plan.as_deref())
+            .context("emitting change plan")?;
+    } else {
+        // Apply changes
+        let etcd_client = memory_etcd.etcd_client();
+        let finalized = finalize(
+            memory_etcd,
+            (!postprocess_only).then_some(&mut cluster_crypto),
+            postprocessing,
+            static_dirs,
+            &capabilities,
+        )
+        .await
+        .context("finalization");
+
+        // Don't leave the cluster half recertified
+        if finalized.is_err() && backup::enabled() {
+            status::phase("rolling back", 90)?;
+            println!("Finalization failed, rolling back...");
+            let etcd_client = etcd_client.context("backups need a running etcd")?;
+            backup::rollback_current(&etcd_client)
+                .await
+                .context("rolling back failed finalization")?;
+        }
+        finalized?;
+
+        if let Some(key_continuity) = &key_continuity {
+            if let Some(key_continuity_map) = key_continuity_map {
+                key_continuity.write(&key_continuity_map).context("writing key continuity map")?;
+            }
+
+            if let Some(summary_file) = summary_file {
+                run_summary::write(&summary_file, key_continuity).context("writing run summary")?;
+            }
+
+            if let Some(crypto_inventory) = crypto_inventory {
+                crypto_inventory::write(&crypto_inventory, key_continuity).context("writing crypto inventory")?;
+                signing::sign_artifact(&crypto_inventory)
+                    .await
+                    .context("signing crypto inventory")?;
+            }
+        }
+    }
+
+    // Log
+    if !postprocess_only {
+        status::phase("summarizing", 95)?;
+        print_summary(cluster_crypto).await;
+    }
+
+    if let Some(audit_log) = audit_log {
+        signing::sign_artifact(&audit_log).await.context("signing audit log")?;
+    }
+
+    Ok(())
+}
+
+async fn init(
+    cli: Cli,
+    etcd_access: &EtcdAccess,
+    crypto_policies: CryptoPolicies,
+) -> Result<(
+    Vec<PathBuf>,
+    ClusterCryptoObjects,
+    Arc<InMemoryK8sEtcd>,
+    CnSanReplaceRules,
+    Option<ClusterRenameParameters>,
+    Option<NodeRenameParameters>,
+    Option<IpRenameParameters>,
+)> {
+    audit::init(cli.audit_log, cli.audit_journald).context("initializing audit log")?;
+    backup::init(cli.backup_dir).context("initializing backup")?;
+    output_dir::init(cli.output_dir).context("initializing output dir")?;
+
+    let mut cluster_crypto = ClusterCryptoObjects::new();
+    cluster_crypto.policies = crypto_policies;
+    let namespace_filter = NamespaceFilter::try_from(cli.etcd_namespace_filter).context("parsing cli etcd-namespace-filter")?;
+    let in_memory_etcd_client = Arc::new(match cli.etcd_snapshot {
+        Some(etcd_snapshot) => InMemoryK8sEtcd::from_snapshot(
+            EtcdSnapshot::open(&etcd_snapshot).context("opening etcd snapshot")?,
+            cli.etcd_snapshot_output.context("missing etcd snapshot output")?,
+            etcd_access,
+            namespace_filter,
+            cluster_crypto.policies.scan.resource_kinds.clone(),
+        ),
+        None => InMemoryK8sEtcd::new(
+            etcd_access.connect(&cli.etcd_endpoint.context("missing etcd endpoint")?).await?,
+            etcd_access,
+            namespace_filter,
+            cluster_crypto.policies.scan.resource_kinds.clone(),
+        ),
+    });
+
+    let mut cn_san_replace_rules = CnSanReplaceRules::try_from(cli.cn_san_replace).context("parsing cli cn-san-replace")?;
+    cn_san_replace_rules.extend(
+        cli.cn_san_replace_regex
+            .iter()
+            .map(|rule| CnSanReplace::parse_regex(rule))
+            .collect::<Result<Vec<_>>>()
+            .context("parsing cli cn-san-replace-regex")?,
+    );
+
+    let node_rename = match cli.node_config {
+        Some(node_config) => Some(NodeRenameParameters::load(&node_config).context("loading node config")?),
+        None => None,
+    };
+    if let Some(node_rename) = &node_rename {
+        cn_san_replace_rules.extend(node_rename.cn_san_replace_rules());
+    }
+
+    let mut static_dirs = cli.static_dir;
+    if !cli.node_dir.is_empty() {
+        if let Some(node_rename) = &node_rename {
+            for node_dir in &cli.node_dir {
+                ensure!(
+                    node_rename.contains(&node_dir.node),
+                    "node {} is not in the node config",
+                    node_dir.node
+                );
+            }
+        }
+        static_dirs.extend(node_dirs::static_dirs(&cli.node_dir).context("finding node dirs")?);
+    }
+
+    let ip_replace = cli
+        .ip_replace
+        .into_iter()
+        .chain(node_rename.iter().flat_map(|node_rename| node_rename.ip_replacements().cloned()))
+        .collect::<Vec<_>>();
+    let ip_rename = match ip_replace.is_empty() {
+        true => None,
+        false => Some(IpRenameParameters::new(ip_replace).context("parsing cli ip-replace and node config IPs")?),
+    };
+    if let Some(ip_rename) = &ip_rename {
+        cn_san_replace_rules.extend(ip_rename.cn_san_replace_rules());
+    }
+
+    let cluster_rename = match cli.cluster_rename {
+        Some(cluster_rename) => Some(ClusterRenameParameters::try_from(cluster_rename).context("parsing cli cluster-rename")?),
+        None => None,
+    };
+    if let Some(cluster_rename) = &cluster_rename {
+        let original_cluster_domain = ocp_postprocess::cluster_domain_rename::original_cluster_domain(&in_memory_etcd_client)
+            .await
+            .context("finding original cluster domain")?;
+        cn_san_replace_rules.extend(cluster_rename.cn_san_replace_rules(&original_cluster_domain)?);
+    }
+
+    Ok((
+        static_dirs,
+        cluster_crypto,
+        in_memory_etcd_client,
+        cn_san_replace_rules,
+        cluster_rename,
+        node_rename,
+        ip_rename,
+    ))
+}
+
+/// How crypto objects are regenerated, including CAs which can't simply be regenerated in place
+struct RegenerationPolicy {
+    regenerate_keyless_cas: bool,
+    unify_duplicate_cas: bool,
+    flatten_chains: Vec<String>,
+    rsa_key_pool_sizes: Vec<PoolSize>,
+    rsa_key_size_policy: KeySizePolicy,
+    upgrade_weak_crypto: bool,
+    external_ca: Option<ExternalCa>,
+    /// The certs taking the place of the cluster's own, see ClusterCryptoObjects::graft_cas
+    ca_grafts: Option<CaGrafts>,
+    /// Only regenerate the certs expiring within this window
+    rotate_expiring_within: Option<chrono::Duration>,
+    /// Which chains to regenerate by CN, see ClusterCryptoObjects::filter_by_cn
+    cn_filter: CnFilter,
+    /// See ClusterCryptoObjects::regenerate_sa_signing_keys
+    sa_signing_key_regeneration: SaSigningKeyRegeneration,
+    /// Where to export the original keys and certs to before they're replaced
+    escrow: Option<EscrowTarget>,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn recertify(
+    in_memory_etcd_client: Arc<InMemoryK8sEtcd>,
+    cluster_crypto: &mut ClusterCryptoObjects,
+    static_dirs: Vec<PathBuf>,
+    cn_san_replace_rules: CnSanReplaceRules,
+    strict_rules: bool,
+    strict_expected_set: bool,
+    regeneration_policy: &RegenerationPolicy,
+    capabilities: &Capabilities,
+) -> Result<KeyContinuity> {
+    // Perform parallelizable tasks like generating raw RSA keys to be used later and scanning for
+    // crypto objects
+    status::phase("scanning", 10)?;
+    println!("Scanning etcd/filesystem... This might take a while");
+    let all_discovered_crypto_objects = tokio::spawn(scanning::crypto_scan(
+        Arc::clone(&in_memory_etcd_client),
+        static_dirs,
+        capabilities.clone(),
+        cluster_crypto.policies.clone(),
+    ));
+    let rsa_key_pool_sizes = regeneration_policy.rsa_key_pool_sizes.clone();
+    let rsa_key_size_policy = regeneration_policy.rsa_key_size_policy;
+    let rsa_keys = tokio::spawn(async move { rsa_key_pool::RsaKeyPool::fill(&rsa_key_pool_sizes, rsa_key_size_policy).await });
+
+    // Wait for the parallelizable tasks to finish and get their results
+    let all_discovered_crypto_objects = all_discovered_crypto_objects.await?.context("scanning")?;
+    let (distinct_certificates, certificate_cache_hits) = cluster_crypto::certificate::Certificate::cache_stats();
+    console::count("crypto objects", all_discovered_crypto_objects.len());
+    console::count("distinct certificates", distinct_certificates);
+    println!(
+        "Scanning complete ({} distinct certificates parsed, {} repeated certificates served from cache), waiting for random key generation to complete...",
+        distinct_certificates, certificate_cache_hits
+    );
+    let rsa_pool = rsa_keys.await?.context("rsa key generation")?;
+    println!("Key generation complete");
+
+    expected_set::validate(
+        &all_discovered_crypto_objects,
+        capabilities,
+        in_memory_etcd_client.namespace_filter(),
+        strict_expected_set,
+    )
+    .context("validating expected crypto objects")?;
+
+    println!("Registering discovered crypto objects...");
+    cluster_crypto.register_discovered_crypto_objects(all_discovered_crypto_objects);
+
+    status::phase("establishing relationships", 40)?;
+    println!("Establishing relationships...");
+    establish_relationships(cluster_crypto, regeneration_policy)
+        .await
+        .context("relationships")?;
+    console::count("cert-key pairs", cluster_crypto.cert_key_pairs.len());
+
+    weak_crypto::report(
+        &weak_crypto::find_weak_crypto(cluster_crypto).context("looking for weak crypto")?,
+        regeneration_policy.upgrade_weak_crypto,
+        cluster_crypto.policies.signature,
+    );
+
+    if let Some(escrow_target) = &regeneration_policy.escrow {
+        status::phase("escrowing", 45)?;
+        println!("Escrowing original keys and certs...");
+        escrow::escrow(cluster_crypto, escrow_target)
+            .await
+            .context("escrowing original keys and certs")?;
+    }
+
+    let key_continuity = KeyContinuity::record_originals(cluster_crypto).context("recording original fingerprints")?;
+
+    status::phase("regenerating", 50)?;
+    println!("Regenerating cryptographic objects...");
+    cluster_crypto
+        .regenerate_crypto(rsa_pool, cn_san_replace_rules.clone())
+        .context("regeneration")?;
+
+    cn_san_replace_rules.validate(strict_rules).context("validating CN/SAN rules")?;
+
+    Ok(key_continuity)
+}
+
+/// Without regenerated crypto objects (with --postprocess-only) only postprocessing is applied
+async fn finalize(
+    in_memory_etcd_client: Arc<InMemoryK8sEtcd>,
+    cluster_crypto: Option<&mut ClusterCryptoObjects>,
+    postprocessing: Postprocessing,
+    static_dirs: Vec<PathBuf>,
+    capabilities: &Capabilities,
+) -> Result<()> {
+    if let Some(cluster_crypto) = cluster_crypto {
+        // Commit the cryptographic objects back to memory etcd and to disk
+        status::phase("committing", 70)?;
+        commit_cryptographic_objects_back(&in_memory_etcd_client, cluster_crypto).await?;
+        println!("Cross-checking etcd and disk copies...");
+        cross_check::cross_check(&in_memory_etcd_client, &static_dirs)
+            .await
+            .context("cross-checking etcd and disk copies")?;
+    }
+    status::phase("postprocessing", 80)?;
+    ocp_postprocess(&in_memory_etcd_client, postprocessing, static_dirs, capabilities).await?;
+
+    // Since we're using an in-memory fake etcd, we need to also commit the changes to the real
+    // etcd after we're done
+    status::phase("committing to etcd", 90)?;
+    etcd_encryption::commit_rotated_config(in_memory_etcd_client.etcd_access().encryption.as_deref())
+        .await
+        .context("writing rotated etcd encryption config")?;
+    println!("Committing to etcd...");
+    in_memory_etcd_client.commit_to_actual_etcd().await
+}
+
+async fn print_summary(cluster_crypto: ClusterCryptoObjects) {
+    println!("Crypto graph...");
+    cluster_crypto.display();
+    cluster_crypto.display_unsupported();
+
+    println!(
+        "Signature algorithms (RSA signatures of regenerated certs use {}):",
+        cluster_crypto.policies.signature
+    );
+    for (signature_algorithm, count) in cluster_crypto.signature_algorithm_counts() {
+        println!("{:>6} {}", count, signature_algorithm);
+    }
+
+    let (peak_queue_depth, peak_concurrency) = concurrency::peak_queue_depth_and_concurrency();
+    println!(
+        "Peak of {} concurrent tasks, with up to {} more queued",
+        peak_concurrency, peak_queue_depth
+    );
+}
+
+async fn commit_cryptographic_objects_back(
+    in_memory_etcd_client: &Arc<InMemoryK8sEtcd>,
+    cluster_crypto: &mut ClusterCryptoObjects,
+) -> Result<()> {
+    println!("Committing changes...");
+    let etcd_client = in_memory_etcd_client;
+    cluster_crypto.commit_to_etcd_and_disk(etcd_client).await
+}
+
+/// The OCP postprocessing to apply once the crypto objects are committed, see ocp_postprocess
+struct Postprocessing {
+    cluster_rename: Option<ClusterRenameParameters>,
+    node_rename: Option<NodeRenameParameters>,
+    ip_rename: Option<IpRenameParameters>,
+    cloud_endpoint_replace: Vec<CloudEndpointReplace>,
+    additional_trust_bundle: Option<AdditionalTrustBundle>,
+    system_trust_dirs: Vec<PathBuf>,
+    /// Only the postprocessing required by the regenerated secrets themselves (the OLM secret hash
+    /// annotations) is allowed, see Cli::crypto_only
+    crypto_only: bool,
+}
+
+impl Postprocessing {
+    /// With --crypto-only, refuse any postprocessing changing more than the crypto objects. The
+    /// CLI already rejects most of it, but the node config, for one, brings its own IP replacements
+    fn check_crypto_only(&self) -> Result<()> {
+        if !self.crypto_only {
+            return Ok(());
+        }
+
+        let requested = [
+            (self.cluster_rename.is_some(), "renaming the cluster"),
+            (self.node_rename.is_some(), "renaming nodes"),
+            (self.ip_rename.is_some(), "replacing IPs"),
+            (!self.cloud_endpoint_replace.is_empty(), "replacing cloud endpoints"),
+            (self.additional_trust_bundle.is_some(), "adding an additional trust bundle"),
+        ]
+        .into_iter()
+        .filter_map(|(requested, stage)| requested.then_some(stage))
+        .collect::<Vec<_>>();
+        ensure!(
+            requested.is_empty(),
+            "--crypto-only only postprocesses the OLM secret hash annotations, refusing {}",
+            requested.join(", ")
+        );
+
+        Ok(())
+    }
+}
+
+/// Perform some OCP-related post-processing to make some OCP operators happy
+async fn ocp_postprocess(
+    in_memory_etcd_client: &Arc<InMemoryK8sEtcd>,
+    postprocessing: Postprocessing,
+    static_dirs: Vec<PathBuf>,
+    capabilities: &Capabilities,
+) -> Result<()> {
+    println!("OCP postprocessing...");
+    if capabilities.allows(Capability::OperatorLifecycleManager, "fixing olm secret hash annotation") {
+        ocp_postprocess::fix_olm_secret_hash_annotation(in_memory_etcd_client)
+            .await
+            .context("fixing olm secret hash annotation")?;
+    }
+
+    let Postprocessing {
+        cluster_rename,
+        node_rename,
+        ip_rename,
+        cloud_endpoint_replace,
+        additional_trust_bundle,
+        system_trust_dirs,
+        ..
+    } = postprocessing;
+
+    if let Some(node_rename) = node_rename {
+        ocp_postprocess::node_rename(in_memory_etcd_client, &node_rename, &static_dirs)
+            .await
+            .context("renaming nodes")?;
+    }
+
+    if let Some(ip_rename) = ip_rename {
+        ocp_postprocess::ip_rename(in_memory_etcd_client, &ip_rename, &static_dirs)
+            .await
+            .context("replacing IPs")?;
+    }
+
+    if !cloud_endpoint_replace.is_empty() {
+        ocp_postprocess::cloud_config_rename(in_memory_etcd_client, &cloud_endpoint_replace, &static_dirs)
+            .await
+            .context("replacing cloud endpoints")?;
+    }
+
+    if let Some(additional_trust_bundle) = additional_trust_bundle {
+        ocp_postprocess::additional_trust_bundle(in_memory_etcd_client, &additional_trust_bundle, &static_dirs, &system_trust_dirs)
+            .await
+            .context("adding additional trust bundle")?;
+    }
+
+    if let Some(cluster_rename) = cluster_rename {
+        ocp_postprocess::cluster_rename(in_memory_etcd_client, cluster_rename, static_dirs, capabilities)
+            .await
+            .context("renaming cluster")?;
+    }
+
+    Ok(())
+}
+
+async fn establish_relationships(cluster_crypto: &mut ClusterCryptoObjects, regeneration_policy: &RegenerationPolicy) -> Result<()> {
+    println!("- Pairing certs and keys...");
+    cluster_crypto.pair_certs_and_keys()?;
+    println!("- Calculating cert signers...");
+    cluster_crypto.fill_cert_key_signers()?;
+    println!("- Calculating jwt signers...");
+    cluster_crypto.fill_jwt_signers()?;
+    println!("- Calculating CRL signers...");
+    cluster_crypto.fill_crl_signers()?;
+    println!("- Calculating signees...");
+    cluster_crypto.fill_signees()?;
+    println!("- Checking for duplicate CAs...");
+    cluster_crypto.handle_duplicate_cas(regeneration_policy.unify_duplicate_cas)?;
+    if !regeneration_policy.flatten_chains.is_empty() {
+        println!("- Flattening chains...");
+        for common_name in &regeneration_policy.flatten_chains {
+            cluster_crypto.flatten_chain(common_name)?;
+        }
+    }
+    if let Some(ca_grafts) = &regeneration_policy.ca_grafts {
+        println!("- Grafting CAs...");
+        cluster_crypto.graft_cas(ca_grafts)?;
+    }
+    println!("- Checking for keyless CAs...");
+    cluster_crypto.check_keyless_cas(regeneration_policy.regenerate_keyless_cas)?;
+    if let Some(external_ca) = &regeneration_policy.external_ca {
+        println!("- Placing roots under the external CA...");
+        cluster_crypto.use_external_ca(external_ca.clone())?;
+    }
+    if let Some(window) = regeneration_policy.rotate_expiring_within {
+        cluster_crypto.rotate_only_expiring_within(window);
+    }
+    if !regeneration_policy.cn_filter.is_empty() {
+        cluster_crypto.filter_by_cn(regeneration_policy.cn_filter.clone());
+    }
+    cluster_crypto.regenerate_sa_signing_keys(regeneration_policy.sa_signing_key_regeneration);
+    println!("- Checking basic constraints...");
+    cluster_crypto.check_basic_constraints()?;
+    println!("- Associating standalone public keys...");
+    cluster_crypto.associate_public_keys()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cli, *};
+
+    #[test]
+    fn test_subcommand_etcd_args() {
+        let cli = Cli::try_parse_from([
+            "recert",
+            "etcd-dump",
+            "--etcd-endpoint",
+            "etcd.example.com:2379",
+            "--etcd-cacert",
+            "ca.crt",
+            "--out",
+            "dump",
+        ])
+        .unwrap();
+        let etcd = cli.command.as_ref().and_then(Command::etcd).unwrap();
+        assert_eq!(etcd.etcd_cacert.as_deref(), Some(Path::new("ca.crt")));
+
+        // Client certs come with their keys
+        assert!(Cli::try_parse_from(["recert", "verify", "--etcd-endpoint", "localhost:2379", "--etcd-cert", "client.crt"]).is_err());
+    }
+
+    #[test]
+    fn test_crypto_only_refuses_postprocessing() {
+        let postprocessing = |crypto_only: bool| Postprocessing {
+            cluster_rename: None,
+            node_rename: None,
+            ip_rename: None,
+            cloud_endpoint_replace: vec![],
+            additional_trust_bundle: None,
+            system_trust_dirs: vec![],
+            crypto_only,
+        };
+        postprocessing(true).check_crypto_only().unwrap();
+
+        // E.g. the IPs of a node config
+        let ip_rename = Some(IpRenameParameters::new(vec!["192.168.1.10,192.168.2.10".parse().unwrap()]).unwrap());
+        let cloud_endpoint_replace = vec!["vcenter.example.com,vcenter.new.example.com".parse().unwrap()];
+        let err = Postprocessing {
+            ip_rename,
+            cloud_endpoint_replace,
+            ..postprocessing(true)
+        }
+        .check_crypto_only()
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--crypto-only only postprocesses the OLM secret hash annotations, refusing replacing IPs, replacing cloud endpoints"
+        );
+
+        Postprocessing {
+            cloud_endpoint_replace: vec!["vcenter.example.com,vcenter.new.example.com".parse().unwrap()],
+            ..postprocessing(false)
+        }
+        .check_crypto_only()
+        .unwrap();
+    }
+
+    /// Runs recert end to end, against the etcd at localhost:2379 and the files of the cluster in
+    /// ./cluster-files: cargo test -- --ignored test_init
+    #[tokio::test]
+    #[ignore]
+    async fn test_init() -> Result<()> {
+        let args = Cli {
+            command: None,
+            etcd_endpoint: Some("http://localhost:2379".to_string()),
+            etcd_snapshot: None,
+            etcd_snapshot_output: None,
+            etcd_cacert: None,
+            etcd_cert: None,
+            etcd_key: None,
+            etcd_encryption_config: None,
+            etcd_encryption_rotate_key: false,
+            static_dir: vec![
+                PathBuf::from("./cluster-files/kubernetes"),
+                PathBuf::from("./cluster-files/machine-config-daemon"),
+                PathBuf::from("./cluster-files/kubelet"),
+            ],
+            node_dir: vec![],
+            cn_san_replace: vec![
+                "api-int.test-cluster.redhat.com api-int.new-name.foo.com".to_string(),
+                "api.test-cluster.redhat.com api.new-name.foo.com".to_string(),
+                "*.apps.test-cluster.redhat.com *.apps.new-name.foo.com".to_string(),
+            ],
+            cn_san_replace_regex: vec![],
+            strict_rules: false,
+            strict_expected_set: false,
+            dry_run: false,
+            postprocess_only: false,
+            crypto_only: false,
+            change_plan: None,
+            escrow_archive: None,
+            escrow_recipient: None,
+            key_continuity_map: None,
+            summary_file: None,
+            crypto_inventory: None,
+            backup_dir: None,
+            output_dir: None,
+            rollback: None,
+            regenerate_keyless_cas: false,
+            unify_duplicate_cas: false,
+            flatten_chain: vec![],
+            skip_cn: vec![],
+            only_cn: vec![],
+            sa_signing_key_regeneration: SaSigningKeyRegeneration::Lockstep,
+            use_ca: None,
+            graft_cas: None,
+            material_dir: None,
+            etcd_namespace_filter: vec![],
+            skip_resource_kind: vec![],
+            scan_custom_resource: vec![],
+            cluster_rename: Some("test-cluster,new-name".to_string()),
+            node_config: None,
+            ip_replace: vec![],
+            cloud_endpoint_replace: vec![],
+            additional_trust_bundle: None,
+            kubeconfig: None,
+            output_format: OutputFormat::Auto,
+            file_permissions: PermissionPolicy::Strict,
+            private_key_format: PrivateKeyFormat::Preserve,
+            private_key_passphrase_file: None,
+            audit_log: None,
+            audit_journald: false,
+            status_file: None,
+            failure_report: None,
+            sandbox: false,
+            sign_key: None,
+            entropy_source: None,
+            deterministic_seed: None,
+            lock_memory: false,
+            max_decode_depth: yaml_crawl::DEFAULT_MAX_DECODE_DEPTH,
+            exhaustive_scan: false,
+            rsa_key_pool_size: vec![],
+            key_pool_file: None,
+            rsa_key_size_policy: KeySizePolicy::Preserve,
+            upgrade_weak_crypto: false,
+            rsa_signature_digest: Digest::Sha256,
+            rsa_signature_padding: RsaPadding::Pkcs1v15,
+            serial_policy: SerialPolicy::Preserve,
+            cert_validity: Validity::Preserve,
+            ca_validity: Validity::Preserve,
+            validity_override: vec![],
+            extension_override: vec![],
+            token_expiry: None,
+            token_audience_replace: vec![],
+            rotate_expiring_within: None,
+            max_concurrency: concurrency::DEFAULT_MAX_CONCURRENCY,
+            ocp_version: None,
+        };
+
+        main_internal(args, EtcdAccess::default(), None).await
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{
+    capabilities::Capabilities,
+    cluster_crypto::{
+        crypto_objects::{CryptoObject, DiscoveredCryptoObect},
+        resource_kinds::ResourceKindPolicy,
+        scanning, CryptoPolicies,
+    },
+    k8s_etcd::{EtcdAccess, InMemoryK8sEtcd},
+    namespace_filter::NamespaceFilter,
+};
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Scan etcd and the static dirs (without modifying anything) and print every unique CN/SAN
+/// value along with the number of certs carrying it, as a starting point for authoring
+/// --cn-san-replace rules
+pub(crate) async fn list_sans(etcd_endpoint: &str, etcd_access: &EtcdAccess, static_dirs: Vec<PathBuf>) -> Result<()> {
+    let etcd_client = etcd_access.connect(etcd_endpoint).await?;
+    let in_memory_etcd_client = Arc::new(InMemoryK8sEtcd::new(
+        etcd_client,
+        etcd_access,
+        NamespaceFilter::default(),
+        ResourceKindPolicy::default(),
+    ));
+
+    let capabilities = Capabilities::detect(&in_memory_etcd_client, None)
+        .await
+        .context("detecting cluster capabilities")?;
+    let discovered_crypto_objects = scanning::crypto_scan(in_memory_etcd_client, static_dirs, capabilities, CryptoPolicies::default())
+        .await
+        .context("scanning")?;
+
+    let mut counts = count_cn_san_values(&discovered_crypto_objects)?.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|(value_a, count_a), (value_b, count_b)| count_b.cmp(count_a).then(value_a.cmp(value_b)));
+
+    for (value, count) in counts {
+        println!("{:>6} {}", count, value);
+    }
+
+    Ok(())
+}
+
+/// The same cert usually appears in many locations, so each unique cert is only counted once
+fn count_cn_san_values(discovered_crypto_objects: &[DiscoveredCryptoObect]) -> Result<BTreeMap<String, usize>> {
+    let certificates = discovered_crypto_objects
+        .iter()
+        .filter_map(|discovered| match &discovered.crypto_object {
+            CryptoObject::Certificate(certificate) => Some(certificate),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    let mut counts = BTreeMap::new();
+    for certificate in certificates {
+        // A value appearing both as the CN and a SAN of the same cert is still one cert
+        for value in certificate.cn_san_values()?.into_iter().collect::<HashSet<_>>() {
+            *counts.entry(value).or_insert(0) += 1;
+        }
+    }
+
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_crypto::{
+        cert_key_pair::SUBJECT_ALTERNATIVE_NAME_OID,
+        certificate::Certificate,
+        keys::PublicKey,
+        locations::{FileContentLocation, FileLocation, Location, LocationValueType},
+    };
+    use bcder::Oid;
+    use der::{asn1::Ia5String, Encode};
+    use x509_cert::ext::pkix::{name::GeneralName::DnsName, SubjectAltName};
+    use x509_certificate::{KeyAlgorithm, X509CertificateBuilder};
+
+    fn certificate(common_name: &str, sans: &[&str]) -> Certificate {
+        let mut builder = X509CertificateBuilder::default();
+        builder.subject().append_common_name_utf8_string(common_name).unwrap();
+        builder.add_extension_der_data(
+            Oid(SUBJECT_ALTERNATIVE_NAME_OID.as_ref().into()),
+            false,
+            SubjectAltName(sans.iter().map(|san| DnsName(Ia5String::new(san).unwrap())).collect())
+                .to_der()
+                .unwrap(),
+        );
+        let (cert, _) = builder.create_with_random_keypair(KeyAlgorithm::Ed25519).unwrap();
+
+        Certificate {
+            issuer: common_name.to_string(),
+            subject: common_name.to_string(),
+            public_key: PublicKey::from_rsa_bytes(&cert.public_key_data()),
+            original: std::sync::Arc::new(cert),
+        }
+    }
+
+    fn discovered(certificate: Certificate, path: &str) -> DiscoveredCryptoObect {
+        DiscoveredCryptoObect::new(
+            CryptoObject::Certificate(certificate),
+            Location::Filesystem(FileLocation {
+                path: path.to_string(),
+                content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+            }),
+        )
+    }
+
+    #[test]
+    fn test_count_cn_san_values() {
+        let api = certificate("api.old.com", &["api.old.com", "api-int.old.com"]);
+        let ingress = certificate("ingress", &["*.apps.old.com", "api.old.com"]);
+
+        let counts = count_cn_san_values(&[
+            discovered(api.clone(), "/a"),
+            // Same cert in another location
+            discovered(api, "/b"),
+            discovered(ingress, "/c"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            counts.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("*.apps.old.com".to_string(), 1),
+                ("api-int.old.com".to_string(), 1),
+                ("api.old.com".to_string(), 2),
+                ("ingress".to_string(), 1),
+            ]
+        );
+    }
+}
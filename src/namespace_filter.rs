@@ -0,0 +1,89 @@
+use anyhow::{Context, Result};
+
+/// Which namespaces recert is allowed to scan and modify in etcd, built from the
+/// --etcd-namespace-filter globs. Cluster-scoped resources are not affected by it
+#[derive(Clone, Debug, Default)]
+pub(crate) struct NamespaceFilter {
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl NamespaceFilter {
+    /// A namespace is allowed if it matches any of the include globs (or there are none) and none
+    /// of the exclude globs
+    pub(crate) fn allows(&self, namespace: Option<&str>) -> bool {
+        let Some(namespace) = namespace else {
+            return true;
+        };
+
+        (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(namespace)))
+            && !self.exclude.iter().any(|pattern| pattern.matches(namespace))
+    }
+
+    /// The namespace of a resource as stored in etcd, if it's namespaced
+    pub(crate) fn document_namespace(document: &[u8]) -> Option<String> {
+        let resource: serde_json::Value = serde_json::from_slice(document).ok()?;
+        Some(resource.pointer("/metadata/namespace")?.as_str()?.to_string())
+    }
+}
+
+impl TryFrom<Vec<String>> for NamespaceFilter {
+    type Error = anyhow::Error;
+
+    fn try_from(filters: Vec<String>) -> Result<Self> {
+        let mut namespace_filter = Self::default();
+
+        for filter in filters {
+            let (patterns, glob) = match filter.strip_prefix('!') {
+                Some(glob) => (&mut namespace_filter.exclude, glob),
+                None => (&mut namespace_filter.include, filter.as_str()),
+            };
+
+            patterns.push(glob::Pattern::new(glob).with_context(|| format!("invalid namespace glob {:?}", glob))?);
+        }
+
+        Ok(namespace_filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(filters: &[&str]) -> NamespaceFilter {
+        NamespaceFilter::try_from(filters.iter().map(|filter| filter.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_allows() {
+        let allow_all = filter(&[]);
+        assert!(allow_all.allows(Some("openshift-etcd")));
+        assert!(allow_all.allows(None));
+
+        let exclude_tenant = filter(&["!tenant-*"]);
+        assert!(exclude_tenant.allows(Some("openshift-etcd")));
+        assert!(!exclude_tenant.allows(Some("tenant-a")));
+        assert!(exclude_tenant.allows(None));
+
+        let only_openshift = filter(&["openshift-*", "kube-system", "!openshift-keep"]);
+        assert!(only_openshift.allows(Some("openshift-etcd")));
+        assert!(only_openshift.allows(Some("kube-system")));
+        assert!(!only_openshift.allows(Some("openshift-keep")));
+        assert!(!only_openshift.allows(Some("default")));
+        assert!(only_openshift.allows(None));
+
+        assert!(NamespaceFilter::try_from(vec!["[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_document_namespace() {
+        assert_eq!(
+            NamespaceFilter::document_namespace(br#"{"kind":"Secret","metadata":{"name":"a","namespace":"b"}}"#),
+            Some("b".to_string())
+        );
+        assert_eq!(
+            NamespaceFilter::document_namespace(br#"{"kind":"APIService","metadata":{"name":"a"}}"#),
+            None
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{ensure, Context, Result};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The directories of a node holding its certs, keys and kubeconfigs, relative to its filesystem
+/// root. The same ones --static-dir is usually given on single-node clusters
+const NODE_STATIC_DIRS: [&str; 8] = [
+    "etc/kubernetes",
+    "var/lib/kubelet",
+    "etc/machine-config-daemon",
+    "var/lib/ovn-ic/etc",
+    "var/lib/ovn/etc",
+    "etc/ovn",
+    "etc/openvswitch",
+    "var/lib/openvswitch/pki",
+];
+
+/// The filesystem root of one of the nodes of a multi-node cluster (e.g. mounted from its disk),
+/// written as NODE:ROOT, where NODE is the node's current name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct NodeDir {
+    pub(crate) node: String,
+    pub(crate) root: PathBuf,
+}
+
+impl FromStr for NodeDir {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (node, root) = value.split_once(':').context("expected NODE:ROOT")?;
+        ensure!(!node.is_empty(), "empty node name in {:?}", value);
+        ensure!(!root.is_empty(), "empty root in {:?}", value);
+
+        Ok(Self {
+            node: node.to_string(),
+            root: PathBuf::from(root),
+        })
+    }
+}
+
+impl NodeDir {
+    /// The static dirs of the node which exist, not every node has all of them (e.g. only
+    /// clusters using OVN-Kubernetes have the OVN ones)
+    pub(crate) fn static_dirs(&self) -> Result<Vec<PathBuf>> {
+        ensure!(self.root.is_dir(), "root {:?} of node {} is not a directory", self.root, self.node);
+
+        Ok(NODE_STATIC_DIRS
+            .iter()
+            .map(|dir| self.root.join(dir))
+            .filter(|dir| dir.is_dir())
+            .collect())
+    }
+}
+
+/// The static dirs of all of the nodes, after making sure no node or root is given twice, which
+/// would have the same files scanned and rewritten twice
+pub(crate) fn static_dirs(node_dirs: &[NodeDir]) -> Result<Vec<PathBuf>> {
+    let (mut nodes, mut roots) = (HashSet::new(), HashSet::<&Path>::new());
+    for node_dir in node_dirs {
+        ensure!(nodes.insert(&node_dir.node), "node {} given more than once", node_dir.node);
+        ensure!(
+            roots.insert(node_dir.root.as_path()),
+            "root {:?} given to more than one node",
+            node_dir.root
+        );
+    }
+
+    node_dirs
+        .iter()
+        .map(NodeDir::static_dirs)
+        .collect::<Result<Vec<_>>>()
+        .map(|dirs| dirs.concat())
+}
+
+/// The filesystem roots of the nodes, / when no node dirs are given, as recert then runs on the
+/// (single) node itself
+pub(crate) fn roots(node_dirs: &[NodeDir]) -> Vec<PathBuf> {
+    if node_dirs.is_empty() {
+        return vec![PathBuf::from("/")];
+    }
+
+    node_dirs.iter().map(|node_dir| node_dir.root.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_dirs() {
+        let (master_0, master_1) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        std::fs::create_dir_all(master_0.path().join("etc/kubernetes")).unwrap();
+        std::fs::create_dir_all(master_0.path().join("var/lib/kubelet")).unwrap();
+        std::fs::create_dir_all(master_1.path().join("etc/kubernetes")).unwrap();
+
+        let node_dir = |node: &str, root: &Path| format!("{}:{}", node, root.display()).parse::<NodeDir>().unwrap();
+        let node_dirs = [node_dir("master-0", master_0.path()), node_dir("master-1", master_1.path())];
+        assert_eq!(node_dirs[0].node, "master-0");
+
+        assert_eq!(
+            static_dirs(&node_dirs).unwrap(),
+            vec![
+                master_0.path().join("etc/kubernetes"),
+                master_0.path().join("var/lib/kubelet"),
+                master_1.path().join("etc/kubernetes"),
+            ]
+        );
+
+        assert!(static_dirs(&[node_dirs[0].clone(), node_dir("master-0", master_1.path())]).is_err());
+        assert!(static_dirs(&[node_dirs[0].clone(), node_dir("master-1", master_0.path())]).is_err());
+        assert!(static_dirs(&[node_dir("master-2", &master_0.path().join("missing"))]).is_err());
+
+        assert!("/mnt/master-0".parse::<NodeDir>().is_err());
+        assert!(":/mnt/master-0".parse::<NodeDir>().is_err());
+    }
+}
@@ -1,5 +1,10 @@
-use self::cluster_domain_rename::params::ClusterRenameParameters;
+use self::{
+    additional_trust_bundle::AdditionalTrustBundle, cloud_config_rename::params::CloudEndpointReplace,
+    cluster_domain_rename::params::ClusterRenameParameters, ip_rename::params::IpRenameParameters,
+    node_rename::params::NodeRenameParameters,
+};
 use crate::{
+    capabilities::Capabilities,
     cluster_crypto::locations::K8sResourceLocation,
     k8s_etcd::{self, get_etcd_yaml, put_etcd_yaml},
 };
@@ -9,20 +14,24 @@ use k8s_etcd::InMemoryK8sEtcd;
 use sha2::Digest;
 use std::{path::PathBuf, sync::Arc};
 
+pub(crate) mod additional_trust_bundle;
+pub(crate) mod cloud_config_rename;
 pub(crate) mod cluster_domain_rename;
+pub(crate) mod ip_rename;
+pub(crate) mod node_rename;
 
 /// The OLM packageserver operator requires that its secret's olmcahash sha256 hash annotation be
 /// set to the sha256 hash of its APIServer's CA cert. Otherwise it makes no effort to reconcile
 /// it. This method does that. Ideally we should get OLM to be more tolerant of this and remove
 /// this post-processing step.
 pub(crate) async fn fix_olm_secret_hash_annotation(in_memory_etcd_client: &Arc<InMemoryK8sEtcd>) -> Result<()> {
-    let mut etcd_client = in_memory_etcd_client;
+    let etcd_client = in_memory_etcd_client;
     let mut hasher = sha2::Sha256::new();
 
     hasher.update(
         base64_standard.decode(
             get_etcd_yaml(
-                &mut etcd_client,
+                etcd_client,
                 &K8sResourceLocation::new(None, "APIService", "v1.packages.operators.coreos.com", "apiregistration.k8s.io/v1"),
             )
             .await?
@@ -41,7 +50,7 @@ pub(crate) async fn fix_olm_secret_hash_annotation(in_memory_etcd_client: &Arc<I
         "v1",
     );
 
-    let mut packageserver_serving_cert_secret = get_etcd_yaml(&mut etcd_client, &package_serving_cert_secret_k8s_resource_location).await?;
+    let mut packageserver_serving_cert_secret = get_etcd_yaml(etcd_client, &package_serving_cert_secret_k8s_resource_location).await?;
     packageserver_serving_cert_secret
         .pointer_mut("/metadata/annotations")
         .context("no .metadata.annotations")?
@@ -50,7 +59,7 @@ pub(crate) async fn fix_olm_secret_hash_annotation(in_memory_etcd_client: &Arc<I
         .insert("olmcahash".to_string(), serde_json::Value::String(format!("{:x}", hash)));
 
     put_etcd_yaml(
-        &etcd_client,
+        etcd_client,
         &package_serving_cert_secret_k8s_resource_location,
         packageserver_serving_cert_secret,
     )
@@ -59,16 +68,94 @@ pub(crate) async fn fix_olm_secret_hash_annotation(in_memory_etcd_client: &Arc<I
     Ok(())
 }
 
+/// Replace every string of the document with its replacement, including documents serialized into
+/// strings (e.g. the pod.yaml of static pod configmaps). Returns whether anything was replaced
+pub(crate) fn replace_in_strings(value: &mut serde_json::Value, replace: &dyn Fn(&str) -> String) -> bool {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(object) => object
+            .values_mut()
+            .fold(false, |replaced, value| replace_in_strings(value, replace) | replaced),
+        Value::Array(array) => array
+            .iter_mut()
+            .fold(false, |replaced, value| replace_in_strings(value, replace) | replaced),
+        Value::String(string) => {
+            let new_string = replace(string);
+            let replaced = new_string != *string;
+            *string = new_string;
+            replaced
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => false,
+    }
+}
+
 /// kubeconfigs have a server URL that we should change to the new cluster's API server URL.
 pub(crate) async fn cluster_rename(
     in_memory_etcd_client: &Arc<InMemoryK8sEtcd>,
     cluster_rename: ClusterRenameParameters,
     static_dirs: Vec<PathBuf>,
+    capabilities: &Capabilities,
 ) -> Result<()> {
     let etcd_client = in_memory_etcd_client;
-    cluster_domain_rename::rename_all(etcd_client, cluster_rename, static_dirs)
+    cluster_domain_rename::rename_all(etcd_client, cluster_rename, static_dirs, capabilities)
         .await
         .context("renaming all")?;
 
     Ok(())
 }
+
+/// Multi-node clusters have per-node resources named after the nodes, which have to follow the
+/// nodes' new names
+pub(crate) async fn node_rename(
+    in_memory_etcd_client: &Arc<InMemoryK8sEtcd>,
+    node_rename: &NodeRenameParameters,
+    static_dirs: &[PathBuf],
+) -> Result<()> {
+    node_rename::rename_all(in_memory_etcd_client, node_rename, static_dirs)
+        .await
+        .context("renaming nodes")?;
+
+    Ok(())
+}
+
+/// Relocated clusters have new node IPs, which etcd resources and static pod configs refer to
+pub(crate) async fn ip_rename(
+    in_memory_etcd_client: &Arc<InMemoryK8sEtcd>,
+    ip_rename: &IpRenameParameters,
+    static_dirs: &[PathBuf],
+) -> Result<()> {
+    ip_rename::rename_all(in_memory_etcd_client, ip_rename, static_dirs)
+        .await
+        .context("replacing IPs")?;
+
+    Ok(())
+}
+
+/// Relocated clusters may run on new infrastructure, which the cloud provider and CSI driver
+/// configs point at
+pub(crate) async fn cloud_config_rename(
+    in_memory_etcd_client: &Arc<InMemoryK8sEtcd>,
+    replacements: &[CloudEndpointReplace],
+    static_dirs: &[PathBuf],
+) -> Result<()> {
+    cloud_config_rename::rename_all(in_memory_etcd_client, replacements, static_dirs)
+        .await
+        .context("replacing cloud endpoints")?;
+
+    Ok(())
+}
+
+/// Relocated clusters may sit behind a TLS intercepting proxy, whose CA they have to trust
+pub(crate) async fn additional_trust_bundle(
+    in_memory_etcd_client: &Arc<InMemoryK8sEtcd>,
+    additional_trust_bundle: &AdditionalTrustBundle,
+    static_dirs: &[PathBuf],
+    system_trust_dirs: &[PathBuf],
+) -> Result<()> {
+    additional_trust_bundle::add_all(in_memory_etcd_client, additional_trust_bundle, static_dirs, system_trust_dirs)
+        .await
+        .context("adding additional trust bundle")?;
+
+    Ok(())
+}
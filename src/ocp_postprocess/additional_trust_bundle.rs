@@ -0,0 +1,352 @@
+use crate::{
+    cluster_crypto::cert_key_pair::basic_constraints::BasicConstraints,
+    file_utils::{self, read_file_to_string},
+    k8s_etcd::InMemoryK8sEtcd,
+};
+use anyhow::{ensure, Context, Result};
+use serde_json::Value;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use x509_certificate::{rfc5280, X509Certificate};
+
+/// The configmap of the user's CAs, which the proxy config points the cluster at and which the
+/// network operator merges into the trusted-ca-bundle configmaps
+const USER_CA_BUNDLE_KEY: &str = "/kubernetes.io/configmaps/openshift-config/user-ca-bundle";
+const CA_BUNDLE_DATA_KEY: &str = "ca-bundle.crt";
+
+const PROXY_KEY: &str = "/kubernetes.io/config.openshift.io/proxies/cluster";
+const IMAGE_CONFIG_KEY: &str = "/kubernetes.io/config.openshift.io/images/cluster";
+
+/// The merged bundle, and the configmaps it's injected into, of any namespace
+const TRUSTED_CA_BUNDLE_KEY: &str = "/kubernetes.io/configmaps/openshift-config-managed/trusted-ca-bundle";
+const INJECT_TRUSTED_CA_BUNDLE_LABEL: &str = "config.openshift.io/inject-trusted-cabundle";
+
+/// The copies of the trusted-ca-bundle configmaps in the static pod resources
+const FILE_GLOB: &str = "**/configmaps/trusted-ca-bundle/ca-bundle.crt";
+
+/// The system trust store of the nodes, relative to their filesystem root. The anchors are where
+/// the MCO writes the user CA bundle, and the extracted bundle is what update-ca-trust would make
+/// of them, which nothing runs before the cluster comes up
+const SYSTEM_TRUST_DIR: &str = "etc/pki/ca-trust";
+const SYSTEM_TRUST_ANCHOR: &str = "source/anchors/recert-additional-trust-bundle.crt";
+const SYSTEM_TRUST_EXTRACTED_BUNDLE: &str = "extracted/pem/tls-ca-bundle.pem";
+
+/// CAs to trust in addition to the cluster's own (e.g. the CA of a TLS intercepting proxy in
+/// front of the network a cluster is relocated to), see --additional-trust-bundle
+pub(crate) struct AdditionalTrustBundle {
+    certs: Vec<pem::Pem>,
+}
+
+/// The directories of the system trust stores of the nodes with the given filesystem roots, those
+/// which exist
+pub(crate) fn system_trust_dirs(roots: &[PathBuf]) -> Vec<PathBuf> {
+    roots
+        .iter()
+        .map(|root| root.join(SYSTEM_TRUST_DIR))
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+impl AdditionalTrustBundle {
+    /// A PEM file of CA certs, nothing else. Has to be read before sandboxing, as the bundle is
+    /// usually outside of what the sandbox allows reading
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path).with_context(|| format!("reading additional trust bundle {:?}", path))?;
+        let certs = pem::parse_many(contents).context("parsing additional trust bundle")?;
+        ensure!(!certs.is_empty(), "additional trust bundle {:?} holds no certs", path);
+
+        for cert in &certs {
+            ensure!(
+                cert.tag() == "CERTIFICATE",
+                "additional trust bundle holds a {}, expected only certs",
+                cert.tag()
+            );
+            let cert = X509Certificate::from_der(cert.contents()).context("parsing additional trust bundle cert")?;
+            let certificate: &rfc5280::Certificate = cert.as_ref();
+            ensure!(
+                BasicConstraints::from_tbs_certificate(&certificate.tbs_certificate)?.is_some_and(|basic_constraints| basic_constraints.ca),
+                "{} of the additional trust bundle isn't a CA",
+                cert.subject_common_name().unwrap_or_default()
+            );
+        }
+
+        Ok(Self { certs })
+    }
+
+    /// The bundle with the certs it doesn't hold yet appended, None if it holds all of them. Re-runs
+    /// over an already extended bundle leave it as it is
+    fn extend(&self, bundle: &str) -> Result<Option<String>> {
+        let present = pem::parse_many(bundle)
+            .context("parsing bundle")?
+            .into_iter()
+            .map(|pem| pem.into_contents())
+            .collect::<BTreeSet<_>>();
+
+        let missing = self
+            .certs
+            .iter()
+            .filter(|cert| !present.contains(cert.contents()))
+            .map(pem::encode)
+            .collect::<String>();
+        if missing.is_empty() {
+            return Ok(None);
+        }
+
+        let mut extended = bundle.to_string();
+        if !extended.is_empty() && !extended.ends_with('\n') {
+            extended.push('\n');
+        }
+        extended.push_str(&missing);
+
+        Ok(Some(extended))
+    }
+
+    fn pem(&self) -> String {
+        self.certs.iter().map(pem::encode).collect()
+    }
+}
+
+/// Add the CAs to the cluster's trust bundles: the user CA bundle (created, and pointed at by the
+/// proxy config, if the cluster has none), the trusted-ca-bundle configmaps the network operator
+/// merges it into, the image registry CAs, the static pod resource copies of the bundles and the
+/// system trust stores of the nodes, so that the cluster trusts them right away rather than once
+/// the operators catch up
+pub(crate) async fn add_all(
+    etcd_client: &Arc<InMemoryK8sEtcd>,
+    additional_trust_bundle: &AdditionalTrustBundle,
+    static_dirs: &[PathBuf],
+    system_trust_dirs: &[PathBuf],
+) -> Result<()> {
+    fix_user_ca_bundle(etcd_client, additional_trust_bundle)
+        .await
+        .context("adding to the user CA bundle")?;
+    fix_trusted_ca_bundles(etcd_client, additional_trust_bundle)
+        .await
+        .context("adding to the trusted CA bundles")?;
+    fix_image_registry_cas(etcd_client, additional_trust_bundle)
+        .await
+        .context("adding to the image registry CAs")?;
+
+    for dir in static_dirs {
+        fix_dir_resources(dir, additional_trust_bundle)
+            .await
+            .with_context(|| format!("adding to the trusted CA bundles in {:?}", dir))?;
+    }
+
+    for dir in system_trust_dirs {
+        fix_system_trust(dir, additional_trust_bundle)
+            .await
+            .with_context(|| format!("adding to the system trust store in {:?}", dir))?;
+    }
+
+    Ok(())
+}
+
+async fn exists(etcd_client: &InMemoryK8sEtcd, key: &str) -> Result<bool> {
+    let prefix = key.strip_prefix("/kubernetes.io/").context("not a k8s key")?;
+    Ok(etcd_client.list_keys(prefix).await?.iter().any(|listed| listed == key))
+}
+
+async fn get_value(etcd_client: &InMemoryK8sEtcd, key: &str) -> Result<Value> {
+    let etcd_result = etcd_client
+        .get(key.to_string())
+        .await
+        .with_context(|| format!("getting key {:?}", key))?;
+    serde_yaml::from_slice(etcd_result.value.as_slice()).with_context(|| format!("deserializing value of key {:?}", key))
+}
+
+/// Extend the given data entry of the configmap, or every one if None. Returns whether anything was
+/// added
+fn extend_configmap(configmap: &mut Value, additional_trust_bundle: &AdditionalTrustBundle, entries: Option<&str>) -> Result<bool> {
+    let Some(data) = configmap.pointer_mut("/data").and_then(Value::as_object_mut) else {
+        return Ok(false);
+    };
+
+    let mut extended_any = false;
+    for (name, value) in data.iter_mut().filter(|(name, _)| match entries {
+        Some(entry) => entry == name.as_str(),
+        None => true,
+    }) {
+        let Some(bundle) = value.as_str() else {
+            continue;
+        };
+        if let Some(extended) = additional_trust_bundle
+            .extend(bundle)
+            .with_context(|| format!("extending {}", name))?
+        {
+            *value = Value::String(extended);
+            extended_any = true;
+        }
+    }
+
+    Ok(extended_any)
+}
+
+async fn fix_user_ca_bundle(etcd_client: &Arc<InMemoryK8sEtcd>, additional_trust_bundle: &AdditionalTrustBundle) -> Result<()> {
+    if !exists(etcd_client, USER_CA_BUNDLE_KEY).await? {
+        println!("Creating k8s:{} with the additional trust bundle", USER_CA_BUNDLE_KEY);
+        let configmap = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {
+                "name": "user-ca-bundle",
+                "namespace": "openshift-config",
+            },
+            "data": { CA_BUNDLE_DATA_KEY: additional_trust_bundle.pem() },
+        });
+        etcd_client.put(USER_CA_BUNDLE_KEY, serde_json::to_vec(&configmap)?).await;
+    } else {
+        let mut configmap = get_value(etcd_client, USER_CA_BUNDLE_KEY).await?;
+        if extend_configmap(&mut configmap, additional_trust_bundle, Some(CA_BUNDLE_DATA_KEY))? {
+            println!("Adding the additional trust bundle to k8s:{}", USER_CA_BUNDLE_KEY);
+            etcd_client.put(USER_CA_BUNDLE_KEY, serde_json::to_vec(&configmap)?).await;
+        }
+    }
+
+    // The proxy config is what has the cluster trust the user CA bundle
+    let mut proxy = get_value(etcd_client, PROXY_KEY).await?;
+    let spec = proxy
+        .as_object_mut()
+        .context("proxy config not an object")?
+        .entry("spec")
+        .or_insert_with(|| serde_json::json!({}))
+        .as_object_mut()
+        .context("proxy spec not an object")?;
+    match spec
+        .get("trustedCA")
+        .and_then(|trusted_ca| trusted_ca.pointer("/name"))
+        .and_then(Value::as_str)
+    {
+        Some("user-ca-bundle") => {}
+        Some(name) if !name.is_empty() => {
+            println!(
+                "WARNING: the proxy config trusts configmap openshift-config/{} rather than user-ca-bundle, which the additional trust bundle was added to",
+                name
+            );
+        }
+        _ => {
+            println!("Pointing k8s:{} at the user CA bundle", PROXY_KEY);
+            spec.insert("trustedCA".to_string(), serde_json::json!({ "name": "user-ca-bundle" }));
+            etcd_client.put(PROXY_KEY, serde_json::to_vec(&proxy)?).await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn fix_trusted_ca_bundles(etcd_client: &Arc<InMemoryK8sEtcd>, additional_trust_bundle: &AdditionalTrustBundle) -> Result<()> {
+    for key in etcd_client.list_keys("configmaps/").await? {
+        let mut configmap = get_value(etcd_client, &key).await?;
+        let injected = configmap
+            .pointer(&format!("/metadata/labels/{}", INJECT_TRUSTED_CA_BUNDLE_LABEL.replace('/', "~1")))
+            .and_then(Value::as_str)
+            == Some("true");
+        if key != TRUSTED_CA_BUNDLE_KEY && !injected {
+            continue;
+        }
+
+        if extend_configmap(&mut configmap, additional_trust_bundle, Some(CA_BUNDLE_DATA_KEY))? {
+            println!("Adding the additional trust bundle to k8s:{}", key);
+            etcd_client.put(&key, serde_json::to_vec(&configmap)?).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// The image config names a configmap of openshift-config with a CA bundle for every registry
+/// (keyed by its hostname), a proxy in front of the registries intercepts all of them
+async fn fix_image_registry_cas(etcd_client: &Arc<InMemoryK8sEtcd>, additional_trust_bundle: &AdditionalTrustBundle) -> Result<()> {
+    if !exists(etcd_client, IMAGE_CONFIG_KEY).await? {
+        return Ok(());
+    }
+
+    let image_config = get_value(etcd_client, IMAGE_CONFIG_KEY).await?;
+    let Some(name) = image_config
+        .pointer("/spec/additionalTrustedCA/name")
+        .and_then(Value::as_str)
+        .filter(|name| !name.is_empty())
+    else {
+        return Ok(());
+    };
+
+    let key = format!("/kubernetes.io/configmaps/openshift-config/{}", name);
+    let mut configmap = get_value(etcd_client, &key).await?;
+    if extend_configmap(&mut configmap, additional_trust_bundle, None)? {
+        println!("Adding the additional trust bundle to k8s:{}", key);
+        etcd_client.put(&key, serde_json::to_vec(&configmap)?).await;
+    }
+
+    Ok(())
+}
+
+async fn fix_dir_resources(dir: &Path, additional_trust_bundle: &AdditionalTrustBundle) -> Result<()> {
+    for file_path in file_utils::globvec(dir, FILE_GLOB)? {
+        fix_file(&file_path, additional_trust_bundle).await?;
+    }
+
+    Ok(())
+}
+
+async fn fix_system_trust(dir: &Path, additional_trust_bundle: &AdditionalTrustBundle) -> Result<()> {
+    let anchor = dir.join(SYSTEM_TRUST_ANCHOR);
+    if anchor.parent().is_some_and(Path::is_dir) {
+        println!("Writing the additional trust bundle to file:{}", anchor.display());
+        file_utils::write_file(&anchor, additional_trust_bundle.pem())
+            .await
+            .with_context(|| format!("writing {:?}", anchor))?;
+    }
+
+    let extracted_bundle = dir.join(SYSTEM_TRUST_EXTRACTED_BUNDLE);
+    if extracted_bundle.is_file() {
+        fix_file(&extracted_bundle, additional_trust_bundle).await?;
+    }
+
+    Ok(())
+}
+
+async fn fix_file(file_path: &Path, additional_trust_bundle: &AdditionalTrustBundle) -> Result<()> {
+    let contents = read_file_to_string(file_path.to_path_buf())
+        .await
+        .with_context(|| format!("reading {:?}", file_path))?;
+
+    if let Some(new_contents) = additional_trust_bundle.extend(&contents)? {
+        println!("Adding the additional trust bundle to file:{}", file_path.display());
+        file_utils::write_file(file_path, new_contents)
+            .await
+            .with_context(|| format!("writing {:?}", file_path))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::CertFixture;
+
+    #[test]
+    fn test_load_and_extend() {
+        let dir = tempfile::tempdir().unwrap();
+        let (proxy_ca, cluster_ca) = (CertFixture::ca("proxy-ca"), CertFixture::ca("cluster-ca"));
+
+        let path = dir.path().join("bundle.crt");
+        std::fs::write(&path, &proxy_ca.cert_pem).unwrap();
+        let additional_trust_bundle = AdditionalTrustBundle::load(&path).unwrap();
+
+        let extended = additional_trust_bundle.extend(cluster_ca.cert_pem.trim_end()).unwrap().unwrap();
+        assert_eq!(pem::parse_many(&extended).unwrap().len(), 2);
+        assert!(extended.starts_with(cluster_ca.cert_pem.trim_end()));
+        // Already there
+        assert_eq!(additional_trust_bundle.extend(&extended).unwrap(), None);
+
+        // Only CA certs
+        std::fs::write(&path, format!("{}{}", proxy_ca.cert_pem, proxy_ca.key_pem)).unwrap();
+        assert!(AdditionalTrustBundle::load(&path).is_err());
+        std::fs::write(&path, CertFixture::leaf("proxy", &proxy_ca).cert_pem).unwrap();
+        assert!(AdditionalTrustBundle::load(&path).is_err());
+    }
+}
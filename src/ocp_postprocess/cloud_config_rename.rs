@@ -0,0 +1,235 @@
+use self::params::CloudEndpointReplace;
+use super::replace_in_strings;
+use crate::{
+    file_utils::{self, read_file_to_string},
+    k8s_etcd::InMemoryK8sEtcd,
+};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub(crate) mod params;
+
+/// The etcd resources (key prefixes under /kubernetes.io/) holding the cloud provider and CSI
+/// driver configs (along with the copies the operators make of them for their operands), the
+/// platform spec of the infrastructure config and the cloud credentials, whose data is keyed by
+/// the endpoint for vSphere
+const ETCD_KEY_PREFIXES: [&str; 10] = [
+    "configmaps/openshift-config/cloud-provider-config",
+    "configmaps/openshift-config-managed/kube-cloud-config",
+    "configmaps/openshift-kube-controller-manager/cloud-config",
+    "configmaps/openshift-kube-apiserver/cloud-config",
+    "configmaps/openshift-cloud-controller-manager/cloud-conf",
+    "configmaps/openshift-cluster-csi-drivers/",
+    "config.openshift.io/infrastructures/cluster",
+    "secrets/kube-system/vsphere-creds",
+    "secrets/openshift-cluster-csi-drivers/",
+    "secrets/openshift-cloud-controller-manager/",
+];
+
+const CREDENTIAL_KEY_SUFFIXES: [&str; 2] = [".username", ".password"];
+
+/// The cloud provider and CSI driver config files, as rendered by the MCO and as copied into the
+/// static pod resources
+const FILE_GLOBS: [&str; 4] = [
+    "**/cloud.conf",
+    "**/vsphere.conf",
+    "**/cloud-config/config",
+    "**/cloud-config/cloud.conf",
+];
+
+/// Point the cloud provider and CSI driver configs of a relocated cluster at its new
+/// infrastructure endpoints. The CA files these configs reference are regenerated like any other
+/// file (see path_references)
+pub(crate) async fn rename_all(
+    etcd_client: &Arc<InMemoryK8sEtcd>,
+    replacements: &[CloudEndpointReplace],
+    static_dirs: &[PathBuf],
+) -> Result<()> {
+    fix_etcd_resources(etcd_client, replacements)
+        .await
+        .context("replacing endpoints in etcd resources")?;
+
+    for dir in static_dirs {
+        fix_dir_resources(dir, replacements)
+            .await
+            .with_context(|| format!("replacing endpoints in filesystem resources in {:?}", dir))?;
+    }
+
+    Ok(())
+}
+
+async fn fix_etcd_resources(etcd_client: &Arc<InMemoryK8sEtcd>, replacements: &[CloudEndpointReplace]) -> Result<()> {
+    for prefix in ETCD_KEY_PREFIXES {
+        for key in etcd_client.list_keys(prefix).await? {
+            let etcd_result = etcd_client
+                .get(key.clone())
+                .await
+                .with_context(|| format!("getting key {:?}", key))?;
+            let mut value: Value =
+                serde_yaml::from_slice(etcd_result.value.as_slice()).with_context(|| format!("deserializing value of key {:?}", key))?;
+
+            let renamed_data = rename_data_keys(&mut value, replacements);
+            if replace_in_strings(&mut value, &|string| replace_endpoints(string, replacements)) || renamed_data {
+                println!("Replacing cloud endpoints in k8s:{}", key);
+                etcd_client.put(&key, serde_json::to_vec(&value)?).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn fix_dir_resources(dir: &Path, replacements: &[CloudEndpointReplace]) -> Result<()> {
+    let mut file_paths = BTreeSet::new();
+    for glob in FILE_GLOBS {
+        file_paths.extend(file_utils::globvec(dir, glob)?);
+    }
+
+    for file_path in file_paths {
+        let contents = read_file_to_string(file_path.clone())
+            .await
+            .with_context(|| format!("reading {:?}", file_path))?;
+        let new_contents = replace_endpoints(&contents, replacements);
+
+        if new_contents != contents {
+            println!("Replacing cloud endpoints in file:{}", file_path.display());
+            file_utils::write_file(&file_path, new_contents)
+                .await
+                .with_context(|| format!("writing {:?}", file_path))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// vSphere credentials secrets hold a VCENTER.username and VCENTER.password for every vCenter.
+/// Returns whether any key was renamed
+fn rename_data_keys(value: &mut Value, replacements: &[CloudEndpointReplace]) -> bool {
+    let Some(data) = value.pointer_mut("/data").and_then(Value::as_object_mut) else {
+        return false;
+    };
+
+    let renames = data
+        .keys()
+        .filter_map(|key| {
+            CREDENTIAL_KEY_SUFFIXES.into_iter().find_map(|suffix| {
+                let endpoint = key.strip_suffix(suffix)?;
+                let replacement = replacements.iter().find(|replacement| replacement.old == endpoint)?;
+                Some((key.clone(), format!("{}{}", replacement.new, suffix)))
+            })
+        })
+        .collect::<Vec<_>>();
+    for (key, new_key) in &renames {
+        if let Some(value) = data.remove(key) {
+            data.insert(new_key.clone(), value);
+        }
+    }
+
+    !renames.is_empty()
+}
+
+fn replace_endpoints(text: &str, replacements: &[CloudEndpointReplace]) -> String {
+    replacements.iter().fold(text.to_string(), |text, replacement| {
+        replace_endpoint(&text, &replacement.old, &replacement.new)
+    })
+}
+
+/// Replace the endpoint wherever it appears on its own, rather than as part of a longer hostname
+/// (vcenter.example.com in old-vcenter.example.com or vcenter.example.com.au)
+fn replace_endpoint(text: &str, old: &str, new: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, _) in text.match_indices(old) {
+        let end = start + old.len();
+        let continues_before = text[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-'));
+        let mut after = text[end..].chars();
+        let continues_after = match after.next() {
+            Some(c) if c.is_ascii_alphanumeric() || c == '-' => true,
+            Some('.') => after.next().is_some_and(|c| c.is_ascii_alphanumeric()),
+            _ => false,
+        };
+        if start < copied || continues_before || continues_after {
+            continue;
+        }
+
+        result.push_str(&text[copied..start]);
+        result.push_str(new);
+        copied = end;
+    }
+    result.push_str(&text[copied..]);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn replacements() -> [CloudEndpointReplace; 1] {
+        ["vcenter.example.com,vcenter.dc2.example.com".parse().unwrap()]
+    }
+
+    #[test]
+    fn test_replace_endpoints() {
+        let vsphere_conf = r#"[Global]
+secret-name = "vsphere-creds"
+secret-namespace = "kube-system"
+ca-file = /etc/kubernetes/static-pod-resources/configmaps/cloud-config/ca-bundle.pem
+
+[Workspace]
+server = "vcenter.example.com"
+datacenter = "dc1"
+
+[VirtualCenter "vcenter.example.com"]
+datacenters = "dc1"
+
+[VirtualCenter "old-vcenter.example.com"]
+datacenters = "vcenter.example.com.au"
+"#;
+
+        assert_eq!(
+            replace_endpoints(vsphere_conf, &replacements()),
+            vsphere_conf
+                .replace(r#"server = "vcenter.example.com""#, r#"server = "vcenter.dc2.example.com""#)
+                .replace(
+                    r#"[VirtualCenter "vcenter.example.com"]"#,
+                    r#"[VirtualCenter "vcenter.dc2.example.com"]"#
+                )
+        );
+        assert_eq!(
+            replace_endpoints("https://vcenter.example.com/sdk vcenter.example.com.", &replacements()),
+            "https://vcenter.dc2.example.com/sdk vcenter.dc2.example.com."
+        );
+    }
+
+    #[test]
+    fn test_rename_data_keys() {
+        let mut secret = json!({
+            "data": {
+                "vcenter.example.com.username": "YWRtaW4=",
+                "vcenter.example.com.password": "aHVudGVyMg==",
+                "other-vcenter.example.com.username": "cm9vdA==",
+            },
+        });
+
+        assert!(rename_data_keys(&mut secret, &replacements()));
+        assert_eq!(
+            secret["data"],
+            json!({
+                "vcenter.dc2.example.com.username": "YWRtaW4=",
+                "vcenter.dc2.example.com.password": "aHVudGVyMg==",
+                "other-vcenter.example.com.username": "cm9vdA==",
+            })
+        );
+        assert!(!rename_data_keys(&mut secret, &replacements()));
+    }
+}
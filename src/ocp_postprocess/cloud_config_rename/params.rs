@@ -0,0 +1,53 @@
+use anyhow::{bail, Context, Result};
+use std::str::FromStr;
+
+/// The endpoint (hostname or IP) of the infrastructure the cluster runs on, such as a vCenter or
+/// an OpenStack auth URL host, and the one replacing it, written as OLD,NEW
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct CloudEndpointReplace {
+    pub(crate) old: String,
+    pub(crate) new: String,
+}
+
+impl FromStr for CloudEndpointReplace {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (old, new) = value.split_once(',').context("expected OLD,NEW")?;
+        let (old, new) = (old.trim(), new.trim());
+
+        for endpoint in [old, new] {
+            if endpoint.is_empty() || !endpoint.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':')) {
+                bail!("invalid endpoint {:?}, expected a hostname or an IP address", endpoint);
+            }
+        }
+
+        Ok(Self {
+            old: old.to_string(),
+            new: new.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "vcenter.old.example.com, vcenter.new.example.com"
+                .parse::<CloudEndpointReplace>()
+                .unwrap(),
+            CloudEndpointReplace {
+                old: "vcenter.old.example.com".to_string(),
+                new: "vcenter.new.example.com".to_string(),
+            }
+        );
+        assert!("vcenter.old.example.com".parse::<CloudEndpointReplace>().is_err());
+        assert!(",vcenter.new.example.com".parse::<CloudEndpointReplace>().is_err());
+        assert!("vcenter.old.example.com,https://vcenter.new.example.com/sdk"
+            .parse::<CloudEndpointReplace>()
+            .is_err());
+    }
+}
@@ -1,24 +1,51 @@
 use self::params::ClusterRenameParameters;
-use crate::{cluster_crypto::locations::K8sResourceLocation, k8s_etcd::InMemoryK8sEtcd};
+use crate::{
+    capabilities::{Capabilities, Capability},
+    cluster_crypto::locations::K8sResourceLocation,
+    k8s_etcd::{get_etcd_yaml, InMemoryK8sEtcd},
+};
 use anyhow::{Context, Result};
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 mod etcd_rename;
 mod filesystem_rename;
 pub(crate) mod params;
 mod rename_utils;
 
+/// The cluster domain before the rename, i.e. the base domain of the cluster's DNS config (which
+/// OpenShift sets to the cluster name followed by the base domain)
+pub(crate) async fn original_cluster_domain(etcd_client: &Arc<InMemoryK8sEtcd>) -> Result<String> {
+    let k8s_resource_location = K8sResourceLocation::new(None, "Dns", "cluster", "config.openshift.io");
+    Ok(get_etcd_yaml(etcd_client, &k8s_resource_location)
+        .await?
+        .pointer("/spec/baseDomain")
+        .context("no /spec/baseDomain")?
+        .as_str()
+        .context("baseDomain not a string")?
+        .to_string())
+}
+
 pub(crate) async fn rename_all(
     etcd_client: &Arc<InMemoryK8sEtcd>,
     cluster_rename: ClusterRenameParameters,
     static_dirs: Vec<PathBuf>,
+    capabilities: &Capabilities,
 ) -> Result<(), anyhow::Error> {
     let cluster_domain = cluster_rename.cluster_domain();
     let generated_infra_id = rename_utils::generate_infra_id(cluster_rename.cluster_name.to_string())?;
 
-    fix_etcd_resources(etcd_client, &cluster_domain, generated_infra_id.clone(), &cluster_rename)
-        .await
-        .context("renaming etcd resources")?;
+    fix_etcd_resources(
+        etcd_client,
+        &cluster_domain,
+        generated_infra_id.clone(),
+        &cluster_rename,
+        capabilities,
+    )
+    .await
+    .context("renaming etcd resources")?;
 
     fix_filesystem_resources(&cluster_domain, static_dirs, generated_infra_id.clone())
         .await
@@ -39,123 +66,143 @@ async fn fix_filesystem_resources(
     Ok(())
 }
 
-async fn fix_dir_resources(cluster_domain: &str, dir: &PathBuf, generated_infra_id: &String) -> Result<(), anyhow::Error> {
-    filesystem_rename::fix_filesystem_kubeconfigs(&cluster_domain, &dir)
+async fn fix_dir_resources(cluster_domain: &str, dir: &Path, generated_infra_id: &str) -> Result<(), anyhow::Error> {
+    filesystem_rename::fix_filesystem_kubeconfigs(cluster_domain, dir)
         .await
         .context("renaming kubeconfigs")?;
-    filesystem_rename::fix_filesystem_apiserver_url_env_files(&cluster_domain, &dir)
+    filesystem_rename::fix_filesystem_apiserver_url_env_files(cluster_domain, dir)
         .await
         .context("renaming apiserver-url.env")?;
-    filesystem_rename::fix_filesystem_kcm_pods(generated_infra_id, &dir)
+    filesystem_rename::fix_filesystem_kcm_pods(generated_infra_id, dir)
         .await
         .context("renaming apiserver-url.env")?;
-    filesystem_rename::fix_filesystem_kcm_configs(generated_infra_id, &dir)
+    filesystem_rename::fix_filesystem_kcm_configs(generated_infra_id, dir)
         .await
         .context("renaming apiserver-url.env")?;
-    filesystem_rename::fix_filesystem_kube_apiserver_configs(cluster_domain, &dir)
+    filesystem_rename::fix_filesystem_kube_apiserver_configs(cluster_domain, dir)
         .await
         .context("renaming apiserver-url.env")?;
-    filesystem_rename::fix_filesystem_kube_apiserver_oauth_metadata(cluster_domain, &dir)
+    filesystem_rename::fix_filesystem_kube_apiserver_oauth_metadata(cluster_domain, dir)
         .await
         .context("renaming apiserver-url.env")?;
     Ok(())
 }
 
 async fn fix_etcd_resources(
-    mut etcd_client: &Arc<InMemoryK8sEtcd>,
+    etcd_client: &Arc<InMemoryK8sEtcd>,
     cluster_domain: &str,
     generated_infra_id: String,
     cluster_rename: &ClusterRenameParameters,
+    capabilities: &Capabilities,
 ) -> Result<(), anyhow::Error> {
-    etcd_rename::fix_router_certs(
-        &mut etcd_client,
-        &cluster_domain,
-        K8sResourceLocation::new(Some("openshift-authentication"), "Secret", "v4-0-config-system-router-certs", "v1"),
-    )
-    .await
-    .context("fixing v4-0-config-system-router-certs")?;
-    etcd_rename::fix_router_certs(
-        &mut etcd_client,
-        &cluster_domain,
-        K8sResourceLocation::new(Some("openshift-config-managed"), "Secret", "router-certs", "v1"),
-    )
-    .await
-    .context("fixing router-certs")?;
-    etcd_rename::fix_loadbalancer_serving_certkey(&mut etcd_client, &cluster_domain, "api", "external-loadbalancer-serving-certkey")
+    if capabilities.allows(Capability::Authentication, "fixing v4-0-config-system-router-certs") {
+        etcd_rename::fix_router_certs(
+            etcd_client,
+            cluster_domain,
+            K8sResourceLocation::new(Some("openshift-authentication"), "Secret", "v4-0-config-system-router-certs", "v1"),
+        )
+        .await
+        .context("fixing v4-0-config-system-router-certs")?;
+    }
+    if capabilities.allows(Capability::Ingress, "fixing router-certs") {
+        etcd_rename::fix_router_certs(
+            etcd_client,
+            cluster_domain,
+            K8sResourceLocation::new(Some("openshift-config-managed"), "Secret", "router-certs", "v1"),
+        )
+        .await
+        .context("fixing router-certs")?;
+    }
+    etcd_rename::fix_loadbalancer_serving_certkey(etcd_client, cluster_domain, "api", "external-loadbalancer-serving-certkey")
         .await
         .context("fixing external-loadbalancer-serving-certkey")?;
-    etcd_rename::fix_loadbalancer_serving_certkey(
-        &mut etcd_client,
-        &cluster_domain,
-        "api-int",
-        "internal-loadbalancer-serving-certkey",
-    )
-    .await
-    .context("fixing internal-loadbalancer-serving-certkey")?;
-    etcd_rename::fix_machineconfigs(&mut etcd_client, &cluster_domain)
+    etcd_rename::fix_loadbalancer_serving_certkey(etcd_client, cluster_domain, "api-int", "internal-loadbalancer-serving-certkey")
         .await
-        .context("fixing machineconfigs")?;
-    etcd_rename::fix_apiserver_config(&mut etcd_client, &cluster_domain)
+        .context("fixing internal-loadbalancer-serving-certkey")?;
+    if capabilities.allows(Capability::MachineConfig, "fixing machineconfigs") {
+        etcd_rename::fix_machineconfigs(etcd_client, cluster_domain)
+            .await
+            .context("fixing machineconfigs")?;
+    }
+    etcd_rename::fix_apiserver_config(etcd_client, cluster_domain)
         .await
         .context("fixing apiserver config")?;
-    etcd_rename::fix_authentication_config(&mut etcd_client, &cluster_domain)
-        .await
-        .context("fixing authentication config")?;
-    etcd_rename::fix_authentication_system_metadata(
-        &mut etcd_client,
-        &cluster_domain,
-        K8sResourceLocation::new(Some("openshift-authentication"), "Configmap", "v4-0-config-system-metadata", "v1"),
-    )
-    .await
-    .context("fixing authentication system metadata")?;
-    etcd_rename::fix_authentication_system_metadata(
-        &mut etcd_client,
-        &cluster_domain,
-        K8sResourceLocation::new(Some("openshift-config-managed"), "Configmap", "oauth-openshift", "v1"),
-    )
-    .await
-    .context("fixing authentication system metadata (config managed)")?;
-    etcd_rename::fix_console_public_config(&mut etcd_client, &cluster_domain)
-        .await
-        .context("fixing console public config")?;
-    etcd_rename::fix_console_cluster_config(&mut etcd_client, &cluster_domain)
-        .await
-        .context("fixing console cluster config")?;
-    etcd_rename::fix_dns_cluster_config(&mut etcd_client, &cluster_domain)
+    if capabilities.allows(Capability::Authentication, "fixing authentication config") {
+        etcd_rename::fix_authentication_config(etcd_client, cluster_domain)
+            .await
+            .context("fixing authentication config")?;
+    }
+    if capabilities.allows(Capability::Authentication, "fixing authentication system metadata") {
+        etcd_rename::fix_authentication_system_metadata(
+            etcd_client,
+            cluster_domain,
+            K8sResourceLocation::new(Some("openshift-authentication"), "Configmap", "v4-0-config-system-metadata", "v1"),
+        )
+        .await
+        .context("fixing authentication system metadata")?;
+    }
+    if capabilities.allows(Capability::Authentication, "fixing authentication system metadata (config managed)") {
+        etcd_rename::fix_authentication_system_metadata(
+            etcd_client,
+            cluster_domain,
+            K8sResourceLocation::new(Some("openshift-config-managed"), "Configmap", "oauth-openshift", "v1"),
+        )
+        .await
+        .context("fixing authentication system metadata (config managed)")?;
+    }
+    if capabilities.allows(Capability::Console, "fixing console public config") {
+        etcd_rename::fix_console_public_config(etcd_client, cluster_domain)
+            .await
+            .context("fixing console public config")?;
+    }
+    if capabilities.allows(Capability::Console, "fixing console cluster config") {
+        etcd_rename::fix_console_cluster_config(etcd_client, cluster_domain)
+            .await
+            .context("fixing console cluster config")?;
+    }
+    etcd_rename::fix_dns_cluster_config(etcd_client, cluster_domain)
         .await
         .context("fixing dns cluster config")?;
-    etcd_rename::fix_infrastructure_cluster_config(&mut etcd_client, &cluster_domain, &generated_infra_id)
+    etcd_rename::fix_infrastructure_cluster_config(etcd_client, cluster_domain, &generated_infra_id)
         .await
         .context("fixing infrastructure cluster config")?;
-    etcd_rename::fix_ingresses_cluster_config(&mut etcd_client, &cluster_domain)
+    etcd_rename::fix_ingresses_cluster_config(etcd_client, cluster_domain)
         .await
         .context("fixing ingresses cluster config")?;
-    etcd_rename::fix_console_cli_downloads(&mut etcd_client, &cluster_domain)
-        .await
-        .context("fixing console cli downloads")?;
-    etcd_rename::fix_monitoring_config(&mut etcd_client, &cluster_domain)
-        .await
-        .context("fixing monitoring config")?;
-    etcd_rename::fix_console_config(&mut etcd_client, &cluster_domain)
-        .await
-        .context("fixing console config")?;
-    etcd_rename::fix_kube_apiserver_configs(&mut etcd_client, &cluster_domain)
+    if capabilities.allows(Capability::Console, "fixing console cli downloads") {
+        etcd_rename::fix_console_cli_downloads(etcd_client, cluster_domain)
+            .await
+            .context("fixing console cli downloads")?;
+    }
+    if capabilities.allows(Capability::Monitoring, "fixing monitoring config") {
+        etcd_rename::fix_monitoring_config(etcd_client, cluster_domain)
+            .await
+            .context("fixing monitoring config")?;
+    }
+    if capabilities.allows(Capability::Console, "fixing console config") {
+        etcd_rename::fix_console_config(etcd_client, cluster_domain)
+            .await
+            .context("fixing console config")?;
+    }
+    etcd_rename::fix_kube_apiserver_configs(etcd_client, cluster_domain)
         .await
         .context("fixing kube apiserver system metadata")?;
-    etcd_rename::fix_oauth_metadata_configmap(&mut etcd_client, &cluster_domain)
+    etcd_rename::fix_oauth_metadata_configmap(etcd_client, cluster_domain)
         .await
         .context("fixing oauth metadata")?;
-    etcd_rename::fix_kcm_config(&mut etcd_client, &generated_infra_id)
+    etcd_rename::fix_kcm_config(etcd_client, &generated_infra_id)
         .await
         .context("fixing kcm config")?;
-    etcd_rename::fix_kcm_kubeconfig(&mut etcd_client, &cluster_domain)
+    etcd_rename::fix_kcm_kubeconfig(etcd_client, cluster_domain)
         .await
         .context("fixing kcm kubeconfig")?;
-    etcd_rename::fix_ovnkube_config(&mut etcd_client, &cluster_domain)
-        .await
-        .context("fixing ovnkube config")?;
+    if capabilities.allows(Capability::OvnKubernetes, "fixing ovnkube config") {
+        etcd_rename::fix_ovnkube_config(etcd_client, cluster_domain)
+            .await
+            .context("fixing ovnkube config")?;
+    }
     etcd_rename::fix_install_config(
-        &mut etcd_client,
+        etcd_client,
         &cluster_rename.cluster_name,
         &cluster_rename.cluster_base_domain,
         K8sResourceLocation::new(Some("kube-system"), "Configmap", "cluster-config-v1", "v1"),
@@ -163,31 +210,37 @@ async fn fix_etcd_resources(
     .await
     .context("fixing kube-system install-config")?;
     etcd_rename::fix_install_config(
-        &mut etcd_client,
+        etcd_client,
         &cluster_rename.cluster_name,
         &cluster_rename.cluster_base_domain,
         K8sResourceLocation::new(Some("openshift-etcd"), "Configmap", "cluster-config-v1", "v1"),
     )
     .await
     .context("fixing etc install-config")?;
-    etcd_rename::fix_kcm_pods(&mut etcd_client, &generated_infra_id)
+    etcd_rename::fix_kcm_pods(etcd_client, &generated_infra_id)
         .await
         .context("fixing kcm pods")?;
-    etcd_rename::fix_cvo_deployment(&mut etcd_client, &cluster_domain)
+    etcd_rename::fix_cvo_deployment(etcd_client, cluster_domain)
         .await
         .context("fixing cvo deployment")?;
-    etcd_rename::fix_multus_daemonsets(&mut etcd_client, &cluster_domain)
-        .await
-        .context("fixing multus daemonsets")?;
-    etcd_rename::fix_ovn_daemonset(&mut etcd_client, &cluster_domain)
-        .await
-        .context("fixing ovn daemonset")?;
-    etcd_rename::fix_router_default(&mut etcd_client, &cluster_domain)
-        .await
-        .context("fixing router default")?;
-    etcd_rename::fix_routes(&mut etcd_client, &cluster_domain)
+    if capabilities.allows(Capability::Multus, "fixing multus daemonsets") {
+        etcd_rename::fix_multus_daemonsets(etcd_client, cluster_domain)
+            .await
+            .context("fixing multus daemonsets")?;
+    }
+    if capabilities.allows(Capability::OvnKubernetes, "fixing ovn daemonset") {
+        etcd_rename::fix_ovn_daemonsets(etcd_client, cluster_domain, capabilities.behavior().ovn_kubernetes_daemonsets)
+            .await
+            .context("fixing ovn daemonsets")?;
+    }
+    if capabilities.allows(Capability::Ingress, "fixing router default") {
+        etcd_rename::fix_router_default(etcd_client, cluster_domain)
+            .await
+            .context("fixing router default")?;
+    }
+    etcd_rename::fix_routes(etcd_client, cluster_domain, capabilities)
         .await
         .context("fixing routes")?;
-    etcd_rename::delete_resources(&mut etcd_client).await.context("fixing kcm pods")?;
+    etcd_rename::delete_resources(etcd_client).await.context("fixing kcm pods")?;
     Ok(())
 }
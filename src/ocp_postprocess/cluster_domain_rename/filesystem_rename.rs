@@ -2,21 +2,24 @@ use super::{
     rename_utils::fix_api_server_arguments, rename_utils::fix_apiserver_url_file, rename_utils::fix_kcm_extended_args,
     rename_utils::fix_kcm_pod, rename_utils::fix_kubeconfig, rename_utils::fix_oauth_metadata,
 };
-use crate::file_utils::{self, read_file_to_string};
+use crate::{
+    concurrency,
+    file_utils::{self, read_file_to_string},
+};
 use anyhow::{self, Context, Result};
 use futures_util::future::join_all;
 use serde_json::Value;
-use std::{collections::HashSet, path::PathBuf};
+use std::{collections::HashSet, path::Path};
 
-pub(crate) async fn fix_filesystem_kcm_pods(generated_infra_id: &str, dir: &PathBuf) -> Result<()> {
+pub(crate) async fn fix_filesystem_kcm_pods(generated_infra_id: &str, dir: &Path) -> Result<()> {
     join_all(
         file_utils::globvec(dir, "**/kube-controller-manager-pod.yaml")?
             .into_iter()
-            .chain(file_utils::globvec(dir, "**/kube-controller-manager-pod/pod.yaml")?.into_iter())
+            .chain(file_utils::globvec(dir, "**/kube-controller-manager-pod/pod.yaml")?)
             .map(|file_path| {
                 let kcm_pod_path = file_path.clone();
                 let generated_infra_id = generated_infra_id.to_string();
-                tokio::spawn(async move {
+                concurrency::spawn(async move {
                     async move {
                         let contents = read_file_to_string(file_path.clone())
                             .await
@@ -25,7 +28,7 @@ pub(crate) async fn fix_filesystem_kcm_pods(generated_infra_id: &str, dir: &Path
 
                         fix_kcm_pod(&mut pod, &generated_infra_id)?;
 
-                        tokio::fs::write(
+                        file_utils::write_file(
                             file_path,
                             serde_json::to_string(&pod).context("serializing kube-controller-manager-pod.yaml")?,
                         )
@@ -48,14 +51,14 @@ pub(crate) async fn fix_filesystem_kcm_pods(generated_infra_id: &str, dir: &Path
     Ok(())
 }
 
-pub(crate) async fn fix_filesystem_kcm_configs(generated_infra_id: &str, dir: &PathBuf) -> Result<()> {
+pub(crate) async fn fix_filesystem_kcm_configs(generated_infra_id: &str, dir: &Path) -> Result<()> {
     join_all(
         file_utils::globvec(dir, "**/kube-controller-manager-pod*/configmaps/config/config.yaml")?
             .into_iter()
             .map(|file_path| {
                 let kcm_config_path = file_path.clone();
                 let generated_infra_id = generated_infra_id.to_string();
-                tokio::spawn(async move {
+                concurrency::spawn(async move {
                     async move {
                         let contents = read_file_to_string(file_path.clone())
                             .await
@@ -64,7 +67,7 @@ pub(crate) async fn fix_filesystem_kcm_configs(generated_infra_id: &str, dir: &P
 
                         fix_kcm_extended_args(&mut config, &generated_infra_id)?;
 
-                        tokio::fs::write(
+                        file_utils::write_file(
                             file_path,
                             serde_json::to_string(&config).context("serializing kube-controller-manager config.yaml")?,
                         )
@@ -87,14 +90,14 @@ pub(crate) async fn fix_filesystem_kcm_configs(generated_infra_id: &str, dir: &P
     Ok(())
 }
 
-pub(crate) async fn fix_filesystem_kube_apiserver_configs(cluster_domain: &str, dir: &PathBuf) -> Result<()> {
+pub(crate) async fn fix_filesystem_kube_apiserver_configs(cluster_domain: &str, dir: &Path) -> Result<()> {
     join_all(
         file_utils::globvec(dir, "**/kube-apiserver-pod*/configmaps/config/config.yaml")?
             .into_iter()
             .map(|file_path| {
                 let kcm_config_path = file_path.clone();
                 let cluster_domain = cluster_domain.to_string();
-                tokio::spawn(async move {
+                concurrency::spawn(async move {
                     async move {
                         let contents = read_file_to_string(file_path.clone())
                             .await
@@ -103,7 +106,7 @@ pub(crate) async fn fix_filesystem_kube_apiserver_configs(cluster_domain: &str,
 
                         fix_api_server_arguments(&mut config, &cluster_domain)?;
 
-                        tokio::fs::write(
+                        file_utils::write_file(
                             file_path,
                             serde_json::to_string(&config).context("serializing kube-apiserver config.yaml")?,
                         )
@@ -126,14 +129,14 @@ pub(crate) async fn fix_filesystem_kube_apiserver_configs(cluster_domain: &str,
     Ok(())
 }
 
-pub(crate) async fn fix_filesystem_kube_apiserver_oauth_metadata(cluster_domain: &str, dir: &PathBuf) -> Result<()> {
+pub(crate) async fn fix_filesystem_kube_apiserver_oauth_metadata(cluster_domain: &str, dir: &Path) -> Result<()> {
     join_all(
         file_utils::globvec(dir, "**/kube-apiserver-pod*/configmaps/oauth-metadata/oauthMetadata")?
             .into_iter()
             .map(|file_path| {
                 let kcm_config_path = file_path.clone();
                 let cluster_domain = cluster_domain.to_string();
-                tokio::spawn(async move {
+                concurrency::spawn(async move {
                     async move {
                         let contents = read_file_to_string(file_path.clone())
                             .await
@@ -142,7 +145,7 @@ pub(crate) async fn fix_filesystem_kube_apiserver_oauth_metadata(cluster_domain:
 
                         fix_oauth_metadata(&mut config, &cluster_domain)?;
 
-                        tokio::fs::write(
+                        file_utils::write_file(
                             file_path,
                             serde_json::to_string(&config).context("serializing kube-apiserver oauthMetadata")?,
                         )
@@ -165,16 +168,16 @@ pub(crate) async fn fix_filesystem_kube_apiserver_oauth_metadata(cluster_domain:
     Ok(())
 }
 
-pub(crate) async fn fix_filesystem_apiserver_url_env_files(cluster_domain: &str, dir: &PathBuf) -> Result<()> {
+pub(crate) async fn fix_filesystem_apiserver_url_env_files(cluster_domain: &str, dir: &Path) -> Result<()> {
     join_all(file_utils::globvec(dir, "**/apiserver-url.env")?.into_iter().map(|file_path| {
         let cluster_domain = cluster_domain.to_string();
         let kubeconfig_path = file_path.clone();
-        tokio::spawn(async move {
+        concurrency::spawn(async move {
             async move {
                 let contents = read_file_to_string(file_path.clone()).await.context("reading apiserver-url.env")?;
 
                 // write back to disk
-                tokio::fs::write(file_path, fix_apiserver_url_file(contents.as_bytes().into(), &cluster_domain)?)
+                file_utils::write_file(file_path, fix_apiserver_url_file(contents.as_bytes().into(), &cluster_domain)?)
                     .await
                     .context("writing kubeconfig to disk")?;
 
@@ -193,19 +196,19 @@ pub(crate) async fn fix_filesystem_apiserver_url_env_files(cluster_domain: &str,
     Ok(())
 }
 
-pub(crate) async fn fix_filesystem_kubeconfigs(cluster_domain: &str, dir: &PathBuf) -> Result<()> {
+pub(crate) async fn fix_filesystem_kubeconfigs(cluster_domain: &str, dir: &Path) -> Result<()> {
     join_all(
         file_utils::globvec(dir, "**/*kubeconfig")?
             .into_iter()
-            .chain(file_utils::globvec(dir, "**/kubeconfig")?.into_iter())
-            .chain(file_utils::globvec(dir, "**/kubeConfig")?.into_iter())
+            .chain(file_utils::globvec(dir, "**/kubeconfig")?)
+            .chain(file_utils::globvec(dir, "**/kubeConfig")?)
             // dedup to avoid races
             .collect::<HashSet<_>>()
             .into_iter()
             .map(|file_path| {
                 let cluster_domain = cluster_domain.to_string();
                 let kubeconfig_path = file_path.clone();
-                tokio::spawn(async move {
+                concurrency::spawn(async move {
                     async move {
                         let contents = read_file_to_string(file_path.clone()).await.context("reading kubeconfig")?;
                         let mut yaml_value = serde_yaml::from_str::<Value>(contents.as_str())
@@ -216,7 +219,7 @@ pub(crate) async fn fix_filesystem_kubeconfigs(cluster_domain: &str, dir: &PathB
                             .context("fixing kubeconfig")?;
 
                         // write back to disk
-                        tokio::fs::write(file_path, serde_yaml::to_string(&yaml_value).context("serializing kubeconfig")?)
+                        file_utils::write_file(file_path, serde_yaml::to_string(&yaml_value).context("serializing kubeconfig")?)
                             .await
                             .context("writing kubeconfig to disk")?;
 
@@ -1,4 +1,5 @@
-use anyhow::{self, bail, Result};
+use crate::cnsanreplace::CnSanReplace;
+use anyhow::{self, bail, Context, Result};
 
 #[derive(Clone)]
 pub(crate) struct ClusterRenameParameters {
@@ -17,21 +18,75 @@ impl ClusterRenameParameters {
     pub(crate) fn cluster_domain(&self) -> String {
         format!("{}.{}", self.cluster_name, self.cluster_base_domain)
     }
+
+    /// The certs of the API server, the ingress and the oauth server carry the cluster domain in
+    /// their SANs, as the cluster domain itself or as one of its subdomains (api, api-int, the
+    /// *.apps wildcard, ...). These follow the cluster domain along with the rest of the cluster
+    pub(crate) fn cn_san_replace_rules(&self, original_cluster_domain: &str) -> Result<Vec<CnSanReplace>> {
+        let cluster_domain = self.cluster_domain();
+        if original_cluster_domain == cluster_domain {
+            return Ok(vec![]);
+        }
+
+        // A single rule for the domain and all of its subdomains, so that --strict-rules doesn't
+        // fail on clusters without a cert for the cluster domain itself
+        Ok(vec![CnSanReplace::parse_regex(&format!(
+            r"(.+\.)?{}:${{1}}{}",
+            regex::escape(original_cluster_domain),
+            cluster_domain
+        ))
+        .context("generating cluster domain rule")?])
+    }
 }
 
 impl TryFrom<String> for ClusterRenameParameters {
     type Error = anyhow::Error;
 
+    /// NAME,BASE_DOMAIN or NAME:BASE_DOMAIN
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let mut parts = value.split(",");
+        let Some((cluster_name, cluster_base_domain)) = value.split_once([',', ':']) else {
+            bail!("cluster rename must be comma (or colon) separated cluster name and cluster base domain");
+        };
+
+        if cluster_name.is_empty() || cluster_base_domain.is_empty() || cluster_base_domain.contains([',', ':']) {
+            bail!("invalid cluster rename {:?}, expected e.g. new-name,new.base.domain", value);
+        }
+        if cluster_name.contains('.') {
+            bail!(
+                "cluster name {:?} can't contain dots, everything after the first dot is the base domain",
+                cluster_name
+            );
+        }
+
+        Ok(Self::new(cluster_name.to_string(), cluster_base_domain.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnsanreplace::CnSanReplaceRules;
 
-        if parts.clone().count() != 2 {
-            bail!("cluster rename must be comma seperated cluster name and cluster base domain");
+    #[test]
+    fn test_cluster_rename_parameters() {
+        for value in ["edge-1,new.example.com", "edge-1:new.example.com"] {
+            let params = ClusterRenameParameters::try_from(value.to_string()).unwrap();
+            assert_eq!(params.cluster_name, "edge-1");
+            assert_eq!(params.cluster_domain(), "edge-1.new.example.com");
+        }
+        for invalid in ["edge-1", ",new.example.com", "edge-1,", "edge-1,new,example", "edge.1,example.com"] {
+            assert!(ClusterRenameParameters::try_from(invalid.to_string()).is_err(), "{}", invalid);
         }
 
-        let cluster_name = parts.next().unwrap().to_string();
-        let cluster_base_domain = parts.next().unwrap().to_string();
+        let params = ClusterRenameParameters::try_from("edge-1,new.example.com".to_string()).unwrap();
+        assert!(params.cn_san_replace_rules("edge-1.new.example.com").unwrap().is_empty());
 
-        Ok(Self::new(cluster_name, cluster_base_domain))
+        let mut rules = CnSanReplaceRules::try_from(vec![]).unwrap();
+        rules.extend(params.cn_san_replace_rules("seed.old.example.com").unwrap());
+        assert_eq!(rules.replace("seed.old.example.com"), "edge-1.new.example.com");
+        assert_eq!(rules.replace("api-int.seed.old.example.com"), "api-int.edge-1.new.example.com");
+        assert_eq!(rules.replace("*.apps.seed.old.example.com"), "*.apps.edge-1.new.example.com");
+        assert_eq!(rules.replace("seedXold.example.com"), "seedXold.example.com");
+        assert_eq!(rules.replace("api.other-seed.old.example.com"), "api.other-seed.old.example.com");
     }
 }
@@ -0,0 +1,228 @@
+use self::params::{IpRenameParameters, IpReplace};
+use super::replace_in_strings;
+use crate::{
+    file_utils::{self, read_file_to_string},
+    k8s_etcd::InMemoryK8sEtcd,
+};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::{
+    collections::BTreeSet,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub(crate) mod params;
+
+/// The etcd resources (key prefixes under /kubernetes.io/) known to hold the node IP: the etcd
+/// and kube-apiserver static pod configs and their revisions, the etcd endpoints, the nodes'
+/// addresses and the endpoints of the kubernetes service
+const ETCD_KEY_PREFIXES: [&str; 9] = [
+    "configmaps/openshift-etcd/",
+    "configmaps/openshift-kube-apiserver/",
+    "configmaps/openshift-apiserver/config",
+    "deployments/openshift-oauth-apiserver/apiserver",
+    "minions/",
+    "services/endpoints/default/kubernetes",
+    "endpointslices/default/kubernetes",
+    "config.openshift.io/infrastructures/cluster",
+    "operator.openshift.io/etcds/cluster",
+];
+
+/// The files known to hold the node IP: the static pod manifests, the static pod resources of
+/// etcd and kube-apiserver and the kubelet's node IP hint
+const FILE_GLOBS: [&str; 4] = [
+    "**/manifests/*.yaml",
+    "**/static-pod-resources/etcd-*/**/*.yaml",
+    "**/static-pod-resources/kube-apiserver-*/**/*.yaml",
+    "**/nodeip-configuration",
+];
+
+/// Replace the IP literals in the known etcd resources and files. The certs carrying the IPs in
+/// their SANs have already been regenerated (see IpRenameParameters::cn_san_replace_rules)
+pub(crate) async fn rename_all(etcd_client: &Arc<InMemoryK8sEtcd>, ip_rename: &IpRenameParameters, static_dirs: &[PathBuf]) -> Result<()> {
+    fix_etcd_resources(etcd_client, ip_rename.replacements())
+        .await
+        .context("replacing IPs in etcd resources")?;
+
+    for dir in static_dirs {
+        fix_dir_resources(dir, ip_rename.replacements())
+            .await
+            .with_context(|| format!("replacing IPs in filesystem resources in {:?}", dir))?;
+    }
+
+    Ok(())
+}
+
+async fn fix_etcd_resources(etcd_client: &Arc<InMemoryK8sEtcd>, replacements: &[IpReplace]) -> Result<()> {
+    for prefix in ETCD_KEY_PREFIXES {
+        for key in etcd_client.list_keys(prefix).await? {
+            let etcd_result = etcd_client
+                .get(key.clone())
+                .await
+                .with_context(|| format!("getting key {:?}", key))?;
+            let mut value: Value =
+                serde_yaml::from_slice(etcd_result.value.as_slice()).with_context(|| format!("deserializing value of key {:?}", key))?;
+
+            if replace_in_strings(&mut value, &|string| replace_ips(string, replacements)) {
+                println!("Replacing IPs in k8s:{}", key);
+                etcd_client.put(&key, serde_json::to_vec(&value)?).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn fix_dir_resources(dir: &Path, replacements: &[IpReplace]) -> Result<()> {
+    let mut file_paths = BTreeSet::new();
+    for glob in FILE_GLOBS {
+        file_paths.extend(file_utils::globvec(dir, glob)?);
+    }
+
+    for file_path in file_paths {
+        let contents = read_file_to_string(file_path.clone())
+            .await
+            .with_context(|| format!("reading {:?}", file_path))?;
+        let new_contents = replace_ips(&contents, replacements);
+
+        if new_contents != contents {
+            println!("Replacing IPs in file:{}", file_path.display());
+            file_utils::write_file(&file_path, new_contents)
+                .await
+                .with_context(|| format!("writing {:?}", file_path))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn replace_ips(text: &str, replacements: &[IpReplace]) -> String {
+    replacements.iter().fold(text.to_string(), |text, replacement| {
+        replace_ip(&text, replacement.old, replacement.new)
+    })
+}
+
+/// Replace the (canonically written) IP wherever it appears on its own, rather than as part of a
+/// longer address (10.0.0.1 in 10.0.0.10). When switching address families, the brackets IPv6
+/// addresses need in URLs and next to ports are added or removed
+fn replace_ip(text: &str, old: IpAddr, new: IpAddr) -> String {
+    let old_text = old.to_string();
+    let new_text = new.to_string();
+
+    let mut result = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, _) in text.match_indices(&old_text) {
+        let end = start + old_text.len();
+        let before = &text[..start];
+        let after = &text[end..];
+        if start < copied || !stands_alone(old, before, after) {
+            continue;
+        }
+
+        result.push_str(&text[copied..start]);
+        copied = end;
+        match (old, new) {
+            (IpAddr::V4(_), IpAddr::V6(_)) if before.ends_with("//") || before.ends_with('@') || starts_with_port(after) => {
+                result.push_str(&format!("[{}]", new_text));
+            }
+            (IpAddr::V6(_), IpAddr::V4(_)) if before.ends_with('[') && after.starts_with(']') => {
+                result.pop();
+                result.push_str(&new_text);
+                copied += 1;
+            }
+            _ => result.push_str(&new_text),
+        }
+    }
+    result.push_str(&text[copied..]);
+
+    result
+}
+
+fn stands_alone(ip: IpAddr, before: &str, after: &str) -> bool {
+    let mut before_chars = before.chars().rev();
+    let mut after_chars = after.chars();
+    match ip {
+        IpAddr::V4(_) => {
+            !before_chars.next().is_some_and(|c| c.is_ascii_digit() || c == '.')
+                && match after_chars.next() {
+                    Some(c) if c.is_ascii_digit() => false,
+                    Some('.') => !after_chars.next().is_some_and(|c| c.is_ascii_digit()),
+                    _ => true,
+                }
+        }
+        IpAddr::V6(_) => {
+            !before_chars.next().is_some_and(|c| c.is_ascii_hexdigit() || c == ':')
+                && match after_chars.next() {
+                    Some(c) if c.is_ascii_hexdigit() => false,
+                    Some(':') => !after_chars.next().is_some_and(|c| c.is_ascii_hexdigit() || c == ':'),
+                    _ => true,
+                }
+        }
+    }
+}
+
+fn starts_with_port(text: &str) -> bool {
+    text.strip_prefix(':')
+        .is_some_and(|port| port.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn replacement(value: &str) -> IpReplace {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_replace_ip() {
+        let v4 = [replacement("192.168.1.10,10.0.0.5")];
+        assert_eq!(
+            replace_ips("--advertise-client-urls=https://192.168.1.10:2379,https://192.168.1.100:2379", &v4),
+            "--advertise-client-urls=https://10.0.0.5:2379,https://192.168.1.100:2379"
+        );
+        assert_eq!(replace_ips("KUBELET_NODEIP=192.168.1.10\n", &v4), "KUBELET_NODEIP=10.0.0.5\n");
+        assert_eq!(
+            replace_ips("1192.168.1.10 192.168.1.10.5 192.168.1.10.", &v4),
+            "1192.168.1.10 192.168.1.10.5 10.0.0.5."
+        );
+
+        let v4_to_v6 = [replacement("192.168.1.10,fd00::5")];
+        assert_eq!(
+            replace_ips("https://192.168.1.10:2379 192.168.1.10:2380 192.168.1.10", &v4_to_v6),
+            "https://[fd00::5]:2379 [fd00::5]:2380 fd00::5"
+        );
+
+        let v6_to_v4 = [replacement("fd00::10,10.0.0.5")];
+        assert_eq!(
+            replace_ips("https://[fd00::10]:2379 fd00::10 fd00::10:5 fd00::100", &v6_to_v4),
+            "https://10.0.0.5:2379 10.0.0.5 fd00::10:5 fd00::100"
+        );
+    }
+
+    #[test]
+    fn test_replace_in_strings() {
+        let mut configmap = json!({
+            "metadata": {"name": "etcd-endpoints", "generation": 3},
+            "data": {
+                "b3c1a9f0": "192.168.1.10",
+                "pod.yaml": "{\"env\":[{\"name\":\"NODE_master_0_IP\",\"value\":\"192.168.1.10\"}]}",
+            },
+        });
+
+        let replacements = [replacement("192.168.1.10,10.0.0.5")];
+        let replace = |string: &str| replace_ips(string, &replacements);
+        assert!(replace_in_strings(&mut configmap, &replace));
+        assert_eq!(
+            configmap["data"],
+            json!({
+                "b3c1a9f0": "10.0.0.5",
+                "pod.yaml": "{\"env\":[{\"name\":\"NODE_master_0_IP\",\"value\":\"10.0.0.5\"}]}",
+            })
+        );
+        assert!(!replace_in_strings(&mut configmap, &replace));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::cnsanreplace::CnSanReplace;
+use anyhow::{ensure, Context, Result};
+use serde::Deserialize;
+use std::{collections::HashSet, net::IpAddr, str::FromStr};
+
+/// An IP address of the cluster and the one replacing it, written as OLD,NEW. Either can be IPv4
+/// or IPv6, so that a cluster can also move between the two
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct IpReplace {
+    pub(crate) old: IpAddr,
+    pub(crate) new: IpAddr,
+}
+
+impl FromStr for IpReplace {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (old, new) = value.split_once(',').context("expected OLD,NEW")?;
+
+        Ok(Self {
+            old: old.trim().parse().with_context(|| format!("invalid IP address {:?}", old))?,
+            new: new.trim().parse().with_context(|| format!("invalid IP address {:?}", new))?,
+        })
+    }
+}
+
+impl TryFrom<String> for IpReplace {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+/// The IP addresses replaced, one per address family of dual-stack clusters
+#[derive(Clone)]
+pub(crate) struct IpRenameParameters {
+    replacements: Vec<IpReplace>,
+}
+
+impl IpRenameParameters {
+    pub(crate) fn new(replacements: Vec<IpReplace>) -> Result<Self> {
+        let olds = replacements.iter().map(|replacement| replacement.old).collect::<HashSet<_>>();
+        ensure!(olds.len() == replacements.len(), "IP address replaced more than once");
+        // Replacements are applied one after the other, so a swap would undo itself
+        for replacement in &replacements {
+            ensure!(
+                !olds.contains(&replacement.new),
+                "{} replaces {}, which is itself replaced",
+                replacement.new,
+                replacement.old
+            );
+        }
+
+        Ok(Self { replacements })
+    }
+
+    pub(crate) fn replacements(&self) -> &[IpReplace] {
+        &self.replacements
+    }
+
+    /// Certs carry the IPs in their IP SANs (which CN/SAN rules are matched against in their
+    /// canonical text form) and occasionally in their CN or DNS SANs
+    pub(crate) fn cn_san_replace_rules(&self) -> Vec<CnSanReplace> {
+        self.replacements
+            .iter()
+            .map(|replacement| CnSanReplace::new(replacement.old.to_string(), replacement.new.to_string(), None))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip_rename_parameters() {
+        let v4 = "192.168.126.10,10.1.2.3".parse::<IpReplace>().unwrap();
+        let v6 = "fd00:0::10, fd01::10".parse::<IpReplace>().unwrap();
+        assert_eq!(v6.old, "fd00::10".parse::<IpAddr>().unwrap());
+
+        let params = IpRenameParameters::new(vec![v4.clone(), v6]).unwrap();
+        let rules = params.cn_san_replace_rules();
+        assert!(rules.iter().any(|rule| rule.old == "192.168.126.10" && rule.new == "10.1.2.3"));
+        assert!(rules.iter().any(|rule| rule.old == "fd00::10" && rule.new == "fd01::10"));
+
+        assert!("192.168.126.10".parse::<IpReplace>().is_err());
+        assert!("192.168.126.10,master-0".parse::<IpReplace>().is_err());
+        assert!(IpRenameParameters::new(vec![v4.clone(), "192.168.126.10,10.1.2.4".parse().unwrap()]).is_err());
+        assert!(IpRenameParameters::new(vec![v4, "10.1.2.3,10.1.2.4".parse().unwrap()]).is_err());
+    }
+}
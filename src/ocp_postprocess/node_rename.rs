@@ -0,0 +1,265 @@
+use self::params::NodeRenameParameters;
+use crate::{
+    cluster_crypto::locations::K8sResourceLocation,
+    file_utils::{self, read_file_to_string},
+    k8s_etcd::{get_etcd_yaml, put_etcd_yaml, InMemoryK8sEtcd},
+};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub(crate) mod params;
+
+/// Each node has its own etcd peer, serving and metrics secrets, named after the node
+const PER_NODE_SECRET_PREFIXES: [&str; 3] = ["etcd-peer-", "etcd-serving-", "etcd-serving-metrics-"];
+
+/// Rename the per-node resources of all renamed nodes. The certs inside them have already been
+/// regenerated with the new node names (see NodeRenameParameters::cn_san_replace_rules), this only
+/// moves them to where the etcd operator and the etcd static pods expect to find them
+pub(crate) async fn rename_all(
+    etcd_client: &Arc<InMemoryK8sEtcd>,
+    node_rename: &NodeRenameParameters,
+    static_dirs: &[PathBuf],
+) -> Result<()> {
+    for (old, new) in node_rename.renames() {
+        fix_etcd_resources(etcd_client, old, new)
+            .await
+            .with_context(|| format!("renaming etcd resources of node {}", old))?;
+
+        for dir in static_dirs {
+            fix_dir_resources(dir, old, new)
+                .await
+                .with_context(|| format!("renaming filesystem resources of node {} in {:?}", old, dir))?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn fix_etcd_resources(etcd_client: &Arc<InMemoryK8sEtcd>, old: &str, new: &str) -> Result<()> {
+    let existing_secrets = etcd_client.list_keys("secrets/openshift-etcd/").await?;
+
+    for prefix in PER_NODE_SECRET_PREFIXES {
+        let old_location = K8sResourceLocation::new(Some("openshift-etcd"), "Secret", &format!("{}{}", prefix, old), "v1");
+        if !existing_secrets.contains(&old_location.as_etcd_key()) {
+            println!("No {}, not renaming it", old_location.as_etcd_key());
+            continue;
+        }
+
+        let new_location = K8sResourceLocation::new(Some("openshift-etcd"), "Secret", &format!("{}{}", prefix, new), "v1");
+        let mut secret = get_etcd_yaml(etcd_client, &old_location).await?;
+        secret
+            .pointer_mut("/metadata")
+            .context("no /metadata")?
+            .as_object_mut()
+            .context("metadata not an object")?
+            .insert("name".to_string(), Value::String(new_location.name.clone()));
+        put_etcd_yaml(etcd_client, &new_location, secret).await?;
+        etcd_client.delete(&old_location.as_etcd_key()).await?;
+    }
+
+    // The shared etcd-all-certs secret (and its revisions) bundles the per-node certs of all
+    // nodes, keyed by file name
+    for key in existing_secrets.iter().filter(|key| key.contains("/openshift-etcd/etcd-all-certs")) {
+        let etcd_result = etcd_client
+            .get(key.clone())
+            .await
+            .with_context(|| format!("getting key {:?}", key))?;
+        let value: Value =
+            serde_yaml::from_slice(etcd_result.value.as_slice()).with_context(|| format!("deserializing value of key {:?}", key))?;
+        let k8s_resource_location = K8sResourceLocation::try_from(&value)?;
+
+        let mut secret = get_etcd_yaml(etcd_client, &k8s_resource_location).await?;
+        fix_all_certs_data(&mut secret, old, new).with_context(|| format!("fixing {}", key))?;
+        put_etcd_yaml(etcd_client, &k8s_resource_location, secret).await?;
+    }
+
+    // The etcd member names come from the per-node env vars of the etcd pod template
+    for key in etcd_client.list_keys("configmaps/openshift-etcd/etcd-pod").await? {
+        let etcd_result = etcd_client
+            .get(key.clone())
+            .await
+            .with_context(|| format!("getting key {:?}", key))?;
+        let value: Value =
+            serde_yaml::from_slice(etcd_result.value.as_slice()).with_context(|| format!("deserializing value of key {:?}", key))?;
+        let k8s_resource_location = K8sResourceLocation::try_from(&value)?;
+
+        let mut configmap = get_etcd_yaml(etcd_client, &k8s_resource_location).await?;
+        let data = configmap.pointer_mut("/data").context("no /data")?;
+
+        let mut pod: Value = serde_yaml::from_str(
+            data.pointer("/pod.yaml")
+                .context("pod.yaml not found")?
+                .as_str()
+                .context("pod.yaml not a string")?,
+        )
+        .context("deserializing pod.yaml")?;
+
+        fix_etcd_pod(&mut pod, old, new).context("fixing etcd pod")?;
+
+        data.as_object_mut()
+            .context("data not an object")?
+            .insert(
+                "pod.yaml".to_string(),
+                Value::String(serde_json::to_string(&pod).context("serializing pod.yaml")?),
+            )
+            .context("could not find original pod.yaml")?;
+
+        put_etcd_yaml(etcd_client, &k8s_resource_location, configmap).await?;
+    }
+
+    Ok(())
+}
+
+async fn fix_dir_resources(dir: &Path, old: &str, new: &str) -> Result<()> {
+    for prefix in PER_NODE_SECRET_PREFIXES {
+        for extension in ["crt", "key"] {
+            for file_path in file_utils::globvec(dir, &format!("**/{}{}.{}", prefix, old, extension))? {
+                let new_file_path = file_path.with_file_name(format!("{}{}.{}", prefix, new, extension));
+                file_utils::write_file(&new_file_path, file_utils::read_file(&file_path).await?)
+                    .await
+                    .with_context(|| format!("writing {:?}", new_file_path))?;
+                file_utils::remove_file(&file_path)
+                    .await
+                    .with_context(|| format!("removing {:?}", file_path))?;
+            }
+        }
+    }
+
+    for file_path in file_utils::globvec(dir, "**/etcd-pod.yaml")?
+        .into_iter()
+        .chain(file_utils::globvec(dir, "**/etcd-pod-*/pod.yaml")?)
+    {
+        let contents = read_file_to_string(file_path.clone()).await.context("reading etcd pod")?;
+        let mut pod: Value = serde_yaml::from_str(&contents).with_context(|| format!("parsing {:?}", file_path))?;
+
+        fix_etcd_pod(&mut pod, old, new).with_context(|| format!("fixing {:?}", file_path))?;
+
+        file_utils::write_file(&file_path, serde_json::to_string(&pod).context("serializing etcd pod")?)
+            .await
+            .with_context(|| format!("writing {:?}", file_path))?;
+    }
+
+    Ok(())
+}
+
+fn fix_all_certs_data(secret: &mut Value, old: &str, new: &str) -> Result<()> {
+    let data = secret
+        .pointer_mut("/data")
+        .context("no /data")?
+        .as_object_mut()
+        .context("data not an object")?;
+
+    for prefix in PER_NODE_SECRET_PREFIXES {
+        for extension in ["crt", "key"] {
+            if let Some(value) = data.remove(&format!("{}{}.{}", prefix, old, extension)) {
+                data.insert(format!("{}{}.{}", prefix, new, extension), value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The etcd pod has env vars named NODE_<node>_ETCD_NAME, NODE_<node>_IP etc. for every node (with
+/// the node name mangled into an env var name), the ETCD_NAME one being the etcd member name
+fn fix_etcd_pod(pod: &mut Value, old: &str, new: &str) -> Result<()> {
+    let old_prefix = format!("NODE_{}_", env_var_node_name(old));
+    let new_prefix = format!("NODE_{}_", env_var_node_name(new));
+
+    for containers_pointer in ["/spec/containers", "/spec/initContainers"] {
+        let Some(containers) = pod.pointer_mut(containers_pointer) else {
+            continue;
+        };
+
+        for container in containers.as_array_mut().context("containers not an array")? {
+            let Some(env) = container.pointer_mut("/env") else {
+                continue;
+            };
+
+            for env_var in env.as_array_mut().context("env not an array")? {
+                let env_var = env_var.as_object_mut().context("env var not an object")?;
+                let Some(suffix) = env_var
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .and_then(|name| name.strip_prefix(&old_prefix))
+                    .map(str::to_string)
+                else {
+                    continue;
+                };
+
+                if suffix == "ETCD_NAME" {
+                    env_var.insert("value".to_string(), Value::String(new.to_string()));
+                }
+                env_var.insert("name".to_string(), Value::String(format!("{}{}", new_prefix, suffix)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn env_var_node_name(node: &str) -> String {
+    node.replace(['-', '.'], "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_fix_etcd_pod() {
+        let mut pod = json!({
+            "spec": {
+                "initContainers": [{"name": "etcd-ensure-env-vars"}],
+                "containers": [{
+                    "name": "etcd",
+                    "env": [
+                        {"name": "NODE_master_0_ETCD_NAME", "value": "master-0"},
+                        {"name": "NODE_master_0_IP", "value": "192.168.1.10"},
+                        {"name": "NODE_master_1_ETCD_NAME", "value": "master-1"},
+                        {"name": "ETCDCTL_API", "value": "3"},
+                    ],
+                }],
+            },
+        });
+
+        fix_etcd_pod(&mut pod, "master-0", "edge.master-0").unwrap();
+
+        assert_eq!(
+            pod["spec"]["containers"][0]["env"],
+            json!([
+                {"name": "NODE_edge_master_0_ETCD_NAME", "value": "edge.master-0"},
+                {"name": "NODE_edge_master_0_IP", "value": "192.168.1.10"},
+                {"name": "NODE_master_1_ETCD_NAME", "value": "master-1"},
+                {"name": "ETCDCTL_API", "value": "3"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_fix_all_certs_data() {
+        let mut secret = json!({
+            "data": {
+                "etcd-peer-master-0.crt": "peer0",
+                "etcd-serving-metrics-master-0.key": "metrics0",
+                "etcd-peer-master-1.crt": "peer1",
+            },
+        });
+
+        fix_all_certs_data(&mut secret, "master-0", "edge-0").unwrap();
+
+        assert_eq!(
+            secret["data"],
+            json!({
+                "etcd-peer-edge-0.crt": "peer0",
+                "etcd-serving-metrics-edge-0.key": "metrics0",
+                "etcd-peer-master-1.crt": "peer1",
+            })
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{cnsanreplace::CnSanReplace, ocp_postprocess::ip_rename::params::IpReplace};
+use anyhow::{ensure, Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+};
+
+/// The CNs of the per-node etcd certs are these prefixes followed by the node name
+const PER_NODE_CERT_CN_PREFIXES: [&str; 3] = ["system:etcd-peer:", "system:etcd-server:", "system:etcd-metric:"];
+
+/// Per-node parameters of a multi-node (compact 3-node or standard HA) cluster, keyed by the
+/// current name of the node:
+///
+///   nodes:
+///     master-0:
+///       hostname: edge-a-master-0
+///       ips: ["192.168.126.10,10.1.2.10"]
+///     master-1:
+///       hostname: edge-a-master-1
+///       ips: ["192.168.126.11,10.1.2.11"]
+///     master-2:
+///       hostname: edge-a-master-2
+///       ips: ["192.168.126.12,10.1.2.12"]
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NodeRenameParameters {
+    nodes: BTreeMap<String, NodeParameters>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NodeParameters {
+    /// The new hostname (and so node and etcd member name) of the node, it keeps its current
+    /// name if not given
+    #[serde(default)]
+    pub(crate) hostname: Option<String>,
+    /// The node's IPs along with the ones replacing them (OLD,NEW as in --ip-replace), one per
+    /// address family of dual-stack clusters
+    #[serde(default)]
+    pub(crate) ips: Vec<IpReplace>,
+}
+
+impl NodeRenameParameters {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?)
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let params: Self = serde_yaml::from_str(contents).context("parsing node config")?;
+
+        let mut hostnames = HashSet::new();
+        for (node, node_params) in &params.nodes {
+            let Some(hostname) = &node_params.hostname else {
+                continue;
+            };
+            ensure!(!hostname.is_empty(), "empty hostname for node {:?}", node);
+            ensure!(hostnames.insert(hostname), "hostname {:?} given to more than one node", hostname);
+            // The per-node resources of the two nodes would end up with the same names
+            ensure!(
+                hostname == node || !params.nodes.contains_key(hostname),
+                "node {:?} can't be renamed to {:?}, which is the current name of another node",
+                node,
+                hostname
+            );
+        }
+
+        Ok(params)
+    }
+
+    /// The (current name, new name) of every node whose name changes
+    pub(crate) fn renames(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.nodes.iter().filter_map(|(node, node_params)| match &node_params.hostname {
+            Some(hostname) if hostname != node => Some((node.as_str(), hostname.as_str())),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn contains(&self, node: &str) -> bool {
+        self.nodes.contains_key(node)
+    }
+
+    /// The IP replacements of all of the nodes. Each node's IPs are its own, so they're replaced
+    /// wherever they appear (the nodes refer to each other's IPs, e.g. in the etcd static pod),
+    /// which is only right as long as no two nodes claim the same IP (see IpRenameParameters::new)
+    pub(crate) fn ip_replacements(&self) -> impl Iterator<Item = &IpReplace> {
+        self.nodes.values().flat_map(|node_params| &node_params.ips)
+    }
+
+    /// The per-node certs carry the node name in their CN and SANs. The CAs signing them are
+    /// shared by all nodes and are left alone, so the certs of all nodes end up signed by the
+    /// same new CAs
+    pub(crate) fn cn_san_replace_rules(&self) -> Vec<CnSanReplace> {
+        self.renames()
+            .flat_map(|(old, new)| {
+                PER_NODE_CERT_CN_PREFIXES
+                    .iter()
+                    .map(move |prefix| CnSanReplace::new(format!("{}{}", prefix, old), format!("{}{}", prefix, new), None))
+                    .chain(std::iter::once(CnSanReplace::new(old.to_string(), new.to_string(), None)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_rename_parameters() {
+        let params = NodeRenameParameters::parse(
+            "nodes:
+  master-0:
+    hostname: edge-master-0
+  master-1:
+    hostname: master-1
+  master-2:
+    hostname: edge-master-2
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            params.renames().collect::<Vec<_>>(),
+            vec![("master-0", "edge-master-0"), ("master-2", "edge-master-2")]
+        );
+
+        let rules = params.cn_san_replace_rules();
+        assert_eq!(rules.len(), 8);
+        assert!(rules
+            .iter()
+            .any(|rule| rule.old == "system:etcd-peer:master-0" && rule.new == "system:etcd-peer:edge-master-0"));
+        assert!(rules.iter().any(|rule| rule.old == "master-2" && rule.new == "edge-master-2"));
+
+        assert!(NodeRenameParameters::parse("nodes:\n  master-0:\n    hostname: a\n  master-1:\n    hostname: a\n").is_err());
+        assert!(NodeRenameParameters::parse("nodes:\n  master-0:\n    hostname: master-1\n  master-1:\n    hostname: b\n").is_err());
+        assert!(NodeRenameParameters::parse("nodes:\n  master-0:\n    hostname: a\n    ip: 10.0.0.1\n").is_err());
+    }
+
+    #[test]
+    fn test_node_ips() {
+        let params = NodeRenameParameters::parse(
+            "nodes:
+  master-0:
+    hostname: edge-master-0
+    ips: [\"192.168.126.10,10.1.2.10\", \"fd00::10,fd01::10\"]
+  master-1:
+    ips: [\"192.168.126.11,10.1.2.11\"]
+",
+        )
+        .unwrap();
+
+        // Nodes without a hostname keep their name
+        assert_eq!(params.renames().collect::<Vec<_>>(), vec![("master-0", "edge-master-0")]);
+        assert!(params.contains("master-1"));
+        assert!(!params.contains("edge-master-0"));
+        assert_eq!(
+            params.ip_replacements().cloned().collect::<Vec<_>>(),
+            vec![
+                "192.168.126.10,10.1.2.10".parse().unwrap(),
+                "fd00::10,fd01::10".parse().unwrap(),
+                "192.168.126.11,10.1.2.11".parse().unwrap(),
+            ]
+        );
+
+        assert!(NodeRenameParameters::parse("nodes:\n  master-0:\n    ips: [\"192.168.126.10\"]\n").is_err());
+    }
+}
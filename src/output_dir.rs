@@ -0,0 +1,200 @@
+use crate::{
+    audit::{self, AuditAction},
+    file_utils,
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+const FILES_DIR_NAME: &str = "files";
+const ETCD_DIR_NAME: &str = "etcd";
+const REMOVED_FILES_FILE_NAME: &str = "removed-files";
+const REMOVED_ETCD_KEYS_FILE_NAME: &str = "removed-etcd-keys";
+
+/// Where everything recert would have modified in place goes instead, leaving the input seed data
+/// untouched. Files are written to files/ at their full original path, etcd values (exactly as
+/// they would have been put, so still encoded) to etcd/ at their key. Files and keys which would
+/// have been deleted are listed, one per line, in removed-files and removed-etcd-keys
+struct OutputDir {
+    dir: PathBuf,
+    removed_files: BTreeSet<PathBuf>,
+    removed_etcd_keys: BTreeSet<String>,
+}
+
+static OUTPUT_DIR: OnceLock<Mutex<OutputDir>> = OnceLock::new();
+
+pub(crate) fn init(dir: Option<PathBuf>) -> Result<()> {
+    if let Some(dir) = dir {
+        OUTPUT_DIR
+            .set(Mutex::new(OutputDir::create(&dir)?))
+            .ok()
+            .context("output dir already initialized")?;
+        println!("Writing everything modified to {:?} rather than in place", dir);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn enabled() -> bool {
+    OUTPUT_DIR.get().is_some()
+}
+
+fn output_dir() -> Result<Option<std::sync::MutexGuard<'static, OutputDir>>> {
+    OUTPUT_DIR
+        .get()
+        .map(|output_dir| output_dir.lock().ok().context("output dir lock poisoned"))
+        .transpose()
+}
+
+/// Where a file should be written to, creating the directories leading to it in the output dir
+pub(crate) fn write_path(path: &Path) -> Result<PathBuf> {
+    let Some(mut output_dir) = output_dir()? else {
+        return Ok(path.to_path_buf());
+    };
+
+    let output_path = output_dir.file_path(path)?;
+    file_utils::create_private_dir(output_path.parent().context("output path without a parent")?)?;
+    output_dir.removed_files.remove(path);
+    output_dir.write_removed_files()?;
+
+    Ok(output_path)
+}
+
+/// Where a file should be read from, its copy in the output dir if it was already written earlier
+/// in the run, so that later modifications build on earlier ones
+pub(crate) fn read_path(path: &Path) -> Result<PathBuf> {
+    let Some(output_dir) = output_dir()? else {
+        return Ok(path.to_path_buf());
+    };
+
+    let output_path = output_dir.file_path(path)?;
+    Ok(if output_path.exists() { output_path } else { path.to_path_buf() })
+}
+
+/// Whether the file was removed earlier in the run, when it's only recorded as removed
+pub(crate) fn is_removed(path: &Path) -> Result<bool> {
+    Ok(output_dir()?.is_some_and(|output_dir| output_dir.removed_files.contains(path)))
+}
+
+/// Record a file as removed, along with removing its copy in the output dir. Returns false when
+/// not writing to an output dir, in which case the file should be removed in place
+pub(crate) fn remove_file(path: &Path) -> Result<bool> {
+    let Some(mut output_dir) = output_dir()? else {
+        return Ok(false);
+    };
+
+    let output_path = output_dir.file_path(path)?;
+    match std::fs::remove_file(&output_path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).with_context(|| format!("removing {:?}", output_path)),
+    }
+    output_dir.removed_files.insert(path.to_path_buf());
+    output_dir.write_removed_files()?;
+
+    Ok(true)
+}
+
+/// Write the value of an etcd key to the output dir, or record it as removed when None
+pub(crate) fn write_etcd_key(key: &str, value: Option<&[u8]>) -> Result<()> {
+    let Some(mut output_dir) = output_dir()? else {
+        bail!("not writing to an output dir");
+    };
+
+    let output_path = output_dir.etcd_key_path(key)?;
+    match value {
+        Some(value) => {
+            file_utils::create_private_dir(output_path.parent().context("output path without a parent")?)?;
+            file_utils::write_private(&output_path, value)?;
+            output_dir.removed_etcd_keys.remove(key);
+            audit::record(AuditAction::FileWrite, &output_path.to_string_lossy(), Some(value))?;
+        }
+        None => {
+            match std::fs::remove_file(&output_path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err).with_context(|| format!("removing {:?}", output_path)),
+            }
+            output_dir.removed_etcd_keys.insert(key.to_string());
+        }
+    }
+
+    output_dir.write_removed_etcd_keys()
+}
+
+impl OutputDir {
+    /// The output holds private keys, so only its owner gets to read it
+    fn create(dir: &Path) -> Result<Self> {
+        file_utils::create_empty_private_dir(dir, "output")?;
+
+        let output_dir = Self {
+            dir: dir.to_path_buf(),
+            removed_files: BTreeSet::new(),
+            removed_etcd_keys: BTreeSet::new(),
+        };
+        output_dir.write_removed_files()?;
+        output_dir.write_removed_etcd_keys()?;
+
+        Ok(output_dir)
+    }
+
+    fn file_path(&self, path: &Path) -> Result<PathBuf> {
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir().context("getting current dir")?.join(path)
+        };
+
+        Ok(self.dir.join(FILES_DIR_NAME).join(file_utils::relative_path(&path)?))
+    }
+
+    fn etcd_key_path(&self, key: &str) -> Result<PathBuf> {
+        Ok(self.dir.join(ETCD_DIR_NAME).join(file_utils::relative_path(Path::new(key))?))
+    }
+
+    fn write_removed_files(&self) -> Result<()> {
+        let removed_files = self
+            .removed_files
+            .iter()
+            .map(|path| format!("{}\n", path.display()))
+            .collect::<String>();
+        file_utils::write_private(&self.dir.join(REMOVED_FILES_FILE_NAME), removed_files.as_bytes())
+    }
+
+    fn write_removed_etcd_keys(&self) -> Result<()> {
+        let removed_etcd_keys = self.removed_etcd_keys.iter().map(|key| format!("{}\n", key)).collect::<String>();
+        file_utils::write_private(&self.dir.join(REMOVED_ETCD_KEYS_FILE_NAME), removed_etcd_keys.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = OutputDir::create(&dir.path().join("output")).unwrap();
+
+        assert_eq!(
+            output_dir
+                .file_path(Path::new("/etc/kubernetes/./static-pod-resources/tls.key"))
+                .unwrap(),
+            dir.path().join("output/files/etc/kubernetes/static-pod-resources/tls.key")
+        );
+        assert_eq!(
+            output_dir
+                .etcd_key_path("/kubernetes.io/secrets/openshift-config/etcd-client")
+                .unwrap(),
+            dir.path().join("output/etcd/kubernetes.io/secrets/openshift-config/etcd-client")
+        );
+        assert!(output_dir.file_path(Path::new("/etc/kubernetes/../shadow")).is_err());
+        assert!(output_dir.etcd_key_path("/kubernetes.io/../../escape").is_err());
+
+        // Refuses to mix with the outputs of another run
+        assert!(OutputDir::create(&dir.path().join("output")).is_err());
+    }
+}
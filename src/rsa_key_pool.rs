@@ -1,62 +1,599 @@
-use super::cluster_crypto::crypto_utils::{generate_rsa_key, generate_rsa_key_async};
-use anyhow::Result;
+use super::{
+    cluster_crypto::{
+        crypto_utils::{generate_ec_key, generate_ec_key_with_rng, generate_rsa_key, generate_rsa_key_async},
+        keys::{EcCurve, PrivateKey, PublicKey},
+    },
+    deterministic,
+};
+use anyhow::{bail, ensure, Context, Result};
 use futures_util::future::join_all;
-use rsa::RsaPrivateKey;
+use rsa::{
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding},
+    traits::PublicKeyParts,
+    RsaPrivateKey,
+};
+use std::{collections::BTreeMap, fmt::Display, io::Write, os::unix::fs::OpenOptionsExt, path::Path, str::FromStr, sync::Mutex};
 use x509_certificate::InMemorySigningKeyPair;
+use zeroize::Zeroizing;
 
-pub struct RsaKeyPool {
-    pub(crate) keys_2048: Vec<(RsaPrivateKey, InMemorySigningKeyPair)>,
-    pub(crate) keys_4096: Vec<(RsaPrivateKey, InMemorySigningKeyPair)>,
+/// How many keys of each size are generated ahead of time, in parallel with scanning, unless
+/// configured otherwise
+pub(crate) const DEFAULT_POOL_SIZES: [PoolSize; 2] = [
+    PoolSize {
+        key_size: 2048,
+        num_keys: 300,
+    },
+    PoolSize {
+        key_size: 4096,
+        num_keys: 20,
+    },
+];
+
+/// RSA keys smaller than this are weak, and ring won't sign with them anyway
+pub(crate) const MIN_RSA_KEY_SIZE: usize = 2048;
+
+/// ring won't sign with RSA keys larger than this
+pub(crate) const MAX_RSA_KEY_SIZE: usize = 4096;
+
+/// The size of the RSA keys replacing keys of a kind we can't generate a like-for-like replacement
+/// for, e.g. EC keys on curves other than P-256 and P-384
+const NON_RSA_REPLACEMENT_KEY_SIZE: usize = 4096;
+
+/// How many EC keys of each curve are generated ahead of time. They're quick to generate and
+/// clusters hold few of them, so this is rarely exhausted
+const EC_POOL_SIZE: usize = 10;
+
+/// The keys of the --key-pool-file, until the pool is filled with them
+static POOL_FILE_KEYS: Mutex<Option<BTreeMap<usize, Vec<RsaPrivateKey>>>> = Mutex::new(None);
+
+/// How many keys of a given size to generate ahead of time, written as SIZE=COUNT (e.g. 2048=300)
+/// A key too weak to be replaced by a key of the same size, without --upgrade-weak-crypto. A type
+/// of its own so that the error catalog can tell it apart
+#[derive(Debug)]
+pub(crate) struct WeakKey {
+    pub(crate) size: usize,
 }
 
-impl RsaKeyPool {
-    pub async fn fill(num_keys_2048: usize, num_keys_4096: usize) -> Result<Self> {
+impl Display for WeakKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "refusing to replace a key with a weak {}-bit RSA key, use --upgrade-weak-crypto to replace it with a {}-bit key instead",
+            self.size, MIN_RSA_KEY_SIZE
+        )
+    }
+}
+
+impl std::error::Error for WeakKey {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PoolSize {
+    pub(crate) key_size: usize,
+    pub(crate) num_keys: usize,
+}
+
+impl FromStr for PoolSize {
+    type Err = anyhow::Error;
+
+    fn from_str(pool_size: &str) -> Result<Self> {
+        let (key_size, num_keys) = pool_size.split_once('=').context("expected SIZE=COUNT, e.g. 2048=300")?;
+
         Ok(Self {
-            keys_2048: join_all(
-                (0..num_keys_2048)
-                    .map(|_| tokio::spawn(async move { generate_rsa_key_async(2048).await }))
-                    .collect::<Vec<_>>(),
-            )
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()?
+            key_size: key_size.parse().context("parsing key size")?,
+            num_keys: num_keys.parse().context("parsing key count")?,
+        })
+    }
+}
+
+impl Display for PoolSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.key_size, self.num_keys)
+    }
+}
+
+/// Which size regenerated RSA keys have. By default each key is replaced by one of the same size
+/// as the original, security policies might require a minimum (e.g. min:4096, upgrading only the
+/// 2048-bit keys) or a single size everywhere (e.g. exact:4096)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum KeySizePolicy {
+    #[default]
+    Preserve,
+    Minimum(usize),
+    Exact(usize),
+}
+
+impl KeySizePolicy {
+    /// The size of the key replacing a key of the given size
+    pub(crate) fn apply(&self, key_size: usize) -> usize {
+        match self {
+            KeySizePolicy::Preserve => key_size,
+            KeySizePolicy::Minimum(minimum) => key_size.max(*minimum),
+            KeySizePolicy::Exact(exact) => *exact,
+        }
+    }
+
+    /// The same policy, but never producing keys smaller than the given size
+    pub(crate) fn with_minimum(&self, minimum: usize) -> KeySizePolicy {
+        match self {
+            KeySizePolicy::Preserve => KeySizePolicy::Minimum(minimum),
+            KeySizePolicy::Minimum(existing) => KeySizePolicy::Minimum(minimum.max(*existing)),
+            KeySizePolicy::Exact(exact) => KeySizePolicy::Exact(minimum.max(*exact)),
+        }
+    }
+
+    /// The pool sizes for the sizes the policy actually produces, e.g. with min:4096 the 2048-bit
+    /// part of the pool becomes 4096-bit keys instead
+    pub(crate) fn apply_to_pool_sizes(&self, pool_sizes: &[PoolSize]) -> Vec<PoolSize> {
+        let mut num_keys_by_size: BTreeMap<usize, usize> = BTreeMap::new();
+        for pool_size in pool_sizes {
+            *num_keys_by_size.entry(self.apply(pool_size.key_size)).or_default() += pool_size.num_keys;
+        }
+
+        num_keys_by_size
             .into_iter()
-            .collect::<Result<Vec<_>, _>>()?,
-            // Also a few 4096 keys
-            keys_4096: join_all(
-                (0..num_keys_4096)
-                    .map(|_| tokio::spawn(async move { generate_rsa_key_async(4096).await }))
-                    .collect::<Vec<_>>(),
-            )
-            .await
+            .map(|(key_size, num_keys)| PoolSize { key_size, num_keys })
+            .collect()
+    }
+}
+
+impl FromStr for KeySizePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> Result<Self> {
+        if policy == "preserve" {
+            return Ok(KeySizePolicy::Preserve);
+        }
+
+        let (kind, key_size) = policy.split_once(':').context("expected preserve, min:SIZE or exact:SIZE")?;
+        let key_size = key_size.parse().context("parsing key size")?;
+        ensure!(
+            key_size >= MIN_RSA_KEY_SIZE,
+            "refusing to generate RSA keys smaller than {} bits",
+            MIN_RSA_KEY_SIZE
+        );
+
+        match kind {
+            "min" => Ok(KeySizePolicy::Minimum(key_size)),
+            "exact" => Ok(KeySizePolicy::Exact(key_size)),
+            _ => bail!("unknown key size policy {:?}, expected preserve, min:SIZE or exact:SIZE", kind),
+        }
+    }
+}
+
+impl Display for KeySizePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeySizePolicy::Preserve => write!(f, "preserve"),
+            KeySizePolicy::Minimum(minimum) => write!(f, "min:{}", minimum),
+            KeySizePolicy::Exact(exact) => write!(f, "exact:{}", exact),
+        }
+    }
+}
+
+/// How many keys of each size were handed out, either from the pool or generated on the spot
+/// because the pool had none left
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct KeyPoolUsage {
+    pub(crate) from_pool: BTreeMap<usize, usize>,
+    pub(crate) generated: BTreeMap<usize, usize>,
+}
+
+impl KeyPoolUsage {
+    /// The usage since an earlier snapshot of the same pool's usage
+    pub(crate) fn since(&self, earlier: &KeyPoolUsage) -> KeyPoolUsage {
+        let difference = |now: &BTreeMap<usize, usize>, earlier: &BTreeMap<usize, usize>| {
+            now.iter()
+                .map(|(key_size, count)| (*key_size, count - earlier.get(key_size).unwrap_or(&0)))
+                .filter(|(_, count)| *count > 0)
+                .collect()
+        };
+
+        KeyPoolUsage {
+            from_pool: difference(&self.from_pool, &earlier.from_pool),
+            generated: difference(&self.generated, &earlier.generated),
+        }
+    }
+
+    pub(crate) fn total(&self) -> usize {
+        self.from_pool.values().chain(self.generated.values()).sum()
+    }
+}
+
+impl Display for KeyPoolUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |counts: &BTreeMap<usize, usize>| {
+            if counts.is_empty() {
+                "none".to_string()
+            } else {
+                counts
+                    .iter()
+                    .map(|(key_size, count)| format!("{}x{}", count, key_size))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        };
+
+        write!(
+            f,
+            "from pool: {}, generated on demand: {}",
+            describe(&self.from_pool),
+            describe(&self.generated)
+        )
+    }
+}
+
+/// Keys generated ahead of time to replace the keys found in the cluster, RSA keys keyed by size
+/// and EC keys keyed by curve
+pub struct RsaKeyPool {
+    pub(crate) keys: BTreeMap<usize, Vec<(RsaPrivateKey, InMemorySigningKeyPair)>>,
+    ec_keys: BTreeMap<EcCurve, Vec<(PrivateKey, InMemorySigningKeyPair)>>,
+    key_size_policy: KeySizePolicy,
+    usage: KeyPoolUsage,
+}
+
+impl RsaKeyPool {
+    /// Filled with the keys of the --key-pool-file first, if there is one, then with newly
+    /// generated keys for whatever the file is short of
+    pub async fn fill(pool_sizes: &[PoolSize], key_size_policy: KeySizePolicy) -> Result<Self> {
+        // Deterministic runs derive every key from the key it replaces, see replacement_for
+        if deterministic::enabled() {
+            return Ok(Self {
+                keys: BTreeMap::new(),
+                ec_keys: BTreeMap::new(),
+                key_size_policy,
+                usage: KeyPoolUsage::default(),
+            });
+        }
+
+        let mut keys = BTreeMap::new();
+        let pool_file_keys = POOL_FILE_KEYS.lock().expect("pool file keys lock poisoned").take();
+        for (key_size, pool_file_keys) in pool_file_keys.unwrap_or_default() {
+            keys.insert(key_size, pool_file_keys.into_iter().map(with_key_pair).collect::<Result<Vec<_>>>()?);
+        }
+
+        let shortfall = key_size_policy
+            .apply_to_pool_sizes(pool_sizes)
             .into_iter()
-            .collect::<Result<Vec<_>, _>>()?
+            .map(|pool_size| PoolSize {
+                key_size: pool_size.key_size,
+                num_keys: pool_size.num_keys.saturating_sub(keys.get(&pool_size.key_size).map_or(0, Vec::len)),
+            })
+            .collect::<Vec<_>>();
+        for (key_size, generated) in generate_rsa_keys(&shortfall).await? {
+            keys.entry(key_size).or_insert_with(Vec::new).extend(generated);
+        }
+
+        let ec_keys = EcCurve::ALL
             .into_iter()
-            .collect::<Result<Vec<_>, _>>()?,
+            .map(|curve| {
+                Ok((
+                    curve,
+                    (0..EC_POOL_SIZE).map(|_| generate_ec_key(curve)).collect::<Result<Vec<_>>>()?,
+                ))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        Ok(Self {
+            keys,
+            ec_keys,
+            key_size_policy,
+            usage: KeyPoolUsage::default(),
         })
     }
 
     pub fn get(&mut self, size: usize) -> Result<(RsaPrivateKey, InMemorySigningKeyPair)> {
-        let size = if size != 512 && size != 1024 && size != 2048 && size != 4096 {
-            // HACK: If the size is not a power of 2, this is probably not RSA.
-            // TODO: Remove this hack once we support non-RSA keys
-            4096
-        } else {
-            size
-        };
+        let size = self.checked_size(size)?;
+
+        if let Some(key) = self.keys.get_mut(&size).and_then(Vec::pop) {
+            *self.usage.from_pool.entry(size).or_default() += 1;
+            return Ok(key);
+        }
 
-        if size == 2048 {
-            if let Some(key) = self.keys_2048.pop() {
-                return Ok(key);
+        *self.usage.generated.entry(size).or_default() += 1;
+        generate_rsa_key(size).with_context(|| {
+            if self.keys.contains_key(&size) {
+                format!("the key pool ran out of {}-bit keys and generating one failed", size)
+            } else {
+                format!(
+                    "the key pool has no {}-bit keys (it was filled with {}) and generating one failed",
+                    size,
+                    self.describe_sizes()
+                )
             }
+        })
+    }
+
+    /// An EC key on the given curve, from the pool unless it ran out
+    pub(crate) fn get_ec(&mut self, curve: EcCurve) -> Result<(PrivateKey, InMemorySigningKeyPair)> {
+        match self.ec_keys.get_mut(&curve).and_then(Vec::pop) {
+            Some(key) => Ok(key),
+            None => generate_ec_key(curve),
         }
+    }
+
+    /// A new key of the same kind and size as the original key, so that the certs signed with it
+    /// are re-signed with the same algorithm (RSA or ECDSA on the same curve). EC keys on curves
+    /// we can't generate keys for are replaced by an RSA key
+    pub(crate) fn replacement_for(&mut self, original_public_key: &PublicKey) -> Result<(PrivateKey, InMemorySigningKeyPair)> {
+        // Deterministic runs derive each key from the key it replaces, see deterministic::rng_for
+        let (PublicKey::Rsa(original_public_key_bytes) | PublicKey::Ec(original_public_key_bytes)) = original_public_key;
+        let mut deterministic_rng = deterministic::rng_for("replacement key", original_public_key_bytes);
+
+        let key_size = match original_public_key {
+            PublicKey::Rsa(_) => original_public_key
+                .rsa_key_size()
+                .context("determining key size")?
+                .context("RSA key without a size")?,
+            PublicKey::Ec(_) => match original_public_key.ec_curve() {
+                Some(curve) => {
+                    return match &mut deterministic_rng {
+                        Some(rng) => generate_ec_key_with_rng(curve, rng),
+                        None => self.get_ec(curve),
+                    }
+                }
+                None => NON_RSA_REPLACEMENT_KEY_SIZE,
+            },
+        };
+
+        if let Some(rng) = &mut deterministic_rng {
+            let key_size = self.checked_size(key_size)?;
+            *self.usage.generated.entry(key_size).or_default() += 1;
+            let rsa_private_key = RsaPrivateKey::new(rng, key_size).context("generating deterministic RSA key")?;
+            let (rsa_private_key, key_pair) = with_key_pair(rsa_private_key)?;
+            return Ok((PrivateKey::Rsa(rsa_private_key), key_pair));
+        }
+
+        let (rsa_private_key, key_pair) = self.get(key_size).context("getting rsa key")?;
+        Ok((PrivateKey::Rsa(rsa_private_key), key_pair))
+    }
+
+    /// The size of the key replacing a key of the given size, according to the key size policy,
+    /// as long as it's one we can sign with
+    fn checked_size(&self, size: usize) -> Result<usize> {
+        let size = self.key_size_policy.apply(size);
+        if size < MIN_RSA_KEY_SIZE {
+            return Err(WeakKey { size }.into());
+        }
+        ensure!(
+            size <= MAX_RSA_KEY_SIZE,
+            "can't sign with {}-bit RSA keys, the maximum is {} bits",
+            size,
+            MAX_RSA_KEY_SIZE
+        );
+
+        Ok(size)
+    }
+
+    /// The sizes the pool was filled with, for error messages
+    fn describe_sizes(&self) -> String {
+        if self.keys.is_empty() {
+            return "no keys".to_string();
+        }
+
+        self.keys
+            .keys()
+            .map(|key_size| format!("{}-bit keys", key_size))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// A snapshot of how many keys were handed out so far
+    pub(crate) fn usage(&self) -> KeyPoolUsage {
+        self.usage.clone()
+    }
+}
+
+async fn generate_rsa_keys(pool_sizes: &[PoolSize]) -> Result<BTreeMap<usize, Vec<(RsaPrivateKey, InMemorySigningKeyPair)>>> {
+    let mut keys = BTreeMap::new();
+
+    for pool_size in pool_sizes {
+        let key_size = pool_size.key_size;
+        let generated = join_all(
+            (0..pool_size.num_keys)
+                .map(|_| tokio::spawn(async move { generate_rsa_key_async(key_size).await }))
+                .collect::<Vec<_>>(),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+        keys.entry(key_size).or_insert_with(Vec::new).extend(generated);
+    }
+
+    Ok(keys)
+}
+
+fn with_key_pair(rsa_private_key: RsaPrivateKey) -> Result<(RsaPrivateKey, InMemorySigningKeyPair)> {
+    let key_pair = InMemorySigningKeyPair::from_pkcs8_der(rsa_private_key.to_pkcs8_der().context("private to der")?.as_bytes())
+        .context("pair from der")?;
+    Ok((rsa_private_key, key_pair))
+}
+
+/// Generate the RSA keys of a pool ahead of time (e.g. while the node is still up, before the
+/// downtime of its reconfiguration) into a file for --key-pool-file, which must not exist yet
+pub(crate) async fn keygen(path: &Path, pool_sizes: &[PoolSize], key_size_policy: KeySizePolicy) -> Result<()> {
+    let pool_sizes = key_size_policy.apply_to_pool_sizes(pool_sizes);
+    let keys = generate_rsa_keys(&pool_sizes).await?;
+    write_pool_file(path, keys.values().flatten().map(|(rsa_private_key, _)| rsa_private_key))?;
+
+    println!(
+        "Generated {} into {:?}",
+        pool_sizes.iter().map(PoolSize::to_string).collect::<Vec<_>>().join(", "),
+        path
+    );
 
-        if size == 4096 {
-            if let Some(key) = self.keys_4096.pop() {
-                return Ok(key);
+    Ok(())
+}
+
+fn write_pool_file<'a>(path: &Path, keys: impl Iterator<Item = &'a RsaPrivateKey>) -> Result<()> {
+    let mut contents = Zeroizing::new(String::new());
+    for rsa_private_key in keys {
+        contents.push_str(&rsa_private_key.to_pkcs8_pem(LineEnding::LF).context("private to pem")?);
+    }
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .with_context(|| format!("writing {:?}", path))
+}
+
+/// The keys of a pool file written by keygen, by size
+fn read_pool_file(path: &Path) -> Result<BTreeMap<usize, Vec<RsaPrivateKey>>> {
+    let contents = Zeroizing::new(std::fs::read(path).with_context(|| format!("reading {:?}", path))?);
+
+    let mut keys = BTreeMap::new();
+    for pem in pem::parse_many(contents.as_slice()).context("parsing pem")? {
+        ensure!(pem.tag() == "PRIVATE KEY", "unexpected {} in key pool file", pem.tag());
+        let rsa_private_key = RsaPrivateKey::from_pkcs8_der(pem.contents()).context("private from der")?;
+        rsa_private_key.validate().context("validating key")?;
+
+        let key_size = rsa_private_key.size() * 8;
+        ensure!(
+            (MIN_RSA_KEY_SIZE..=MAX_RSA_KEY_SIZE).contains(&key_size),
+            "{}-bit key in key pool file, only {} to {} bits are supported",
+            key_size,
+            MIN_RSA_KEY_SIZE,
+            MAX_RSA_KEY_SIZE
+        );
+        keys.entry(key_size).or_insert_with(Vec::new).push(rsa_private_key);
+    }
+
+    Ok(keys)
+}
+
+/// Load the keys of the --key-pool-file for the pool to be filled with. Has to be called before
+/// sandboxing, as the file is removed once read: a key must never end up in more than one
+/// cluster, so each file is good for a single run, whether or not the run goes on to use its
+/// keys. This also means the file must be generated on (or for) each node separately, never
+/// shipped in an image that's deployed more than once
+pub(crate) fn load_pool_file(path: Option<&Path>) -> Result<()> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+
+    let keys = read_pool_file(path)?;
+    std::fs::remove_file(path).with_context(|| format!("removing {:?}", path))?;
+
+    println!(
+        "Loaded {} keys from key pool file {:?}",
+        keys.values().map(Vec::len).sum::<usize>(),
+        path
+    );
+    *POOL_FILE_KEYS.lock().expect("pool file keys lock poisoned") = Some(keys);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_certificate::{Sign, SignatureAlgorithm};
+
+    #[test]
+    fn test_pool_size() {
+        assert_eq!(
+            "3072=10".parse::<PoolSize>().unwrap(),
+            PoolSize {
+                key_size: 3072,
+                num_keys: 10
+            }
+        );
+        assert!("3072".parse::<PoolSize>().is_err());
+        assert!("big=10".parse::<PoolSize>().is_err());
+    }
+
+    #[test]
+    fn test_key_size_policy() {
+        assert_eq!("preserve".parse::<KeySizePolicy>().unwrap(), KeySizePolicy::Preserve);
+        assert!("min:1024".parse::<KeySizePolicy>().is_err());
+        assert!("max:4096".parse::<KeySizePolicy>().is_err());
+
+        let minimum = "min:4096".parse::<KeySizePolicy>().unwrap();
+        assert_eq!(minimum, KeySizePolicy::Minimum(4096));
+        assert_eq!(minimum.apply(2048), 4096);
+        assert_eq!(minimum.apply(8192), 8192);
+        assert_eq!(
+            minimum.apply_to_pool_sizes(&DEFAULT_POOL_SIZES),
+            vec![PoolSize {
+                key_size: 4096,
+                num_keys: 320
+            }]
+        );
+
+        let exact = "exact:3072".parse::<KeySizePolicy>().unwrap();
+        assert_eq!(exact.apply(4096), 3072);
+        assert_eq!(exact.to_string(), "exact:3072");
+
+        assert_eq!(KeySizePolicy::Preserve.with_minimum(2048).apply(1024), 2048);
+        assert_eq!(KeySizePolicy::Preserve.with_minimum(2048).apply(3072), 3072);
+        assert_eq!(minimum.with_minimum(2048), minimum);
+        assert_eq!(exact.with_minimum(2048), exact);
+    }
+
+    #[test]
+    fn test_usage_since() {
+        let earlier = KeyPoolUsage {
+            from_pool: BTreeMap::from([(2048, 3)]),
+            generated: BTreeMap::new(),
+        };
+        let now = KeyPoolUsage {
+            from_pool: BTreeMap::from([(2048, 5), (4096, 1)]),
+            generated: BTreeMap::from([(2048, 2)]),
+        };
+
+        let usage = now.since(&earlier);
+        assert_eq!(usage.from_pool, BTreeMap::from([(2048, 2), (4096, 1)]));
+        assert_eq!(usage.generated, BTreeMap::from([(2048, 2)]));
+        assert_eq!(usage.total(), 5);
+        assert_eq!(usage.to_string(), "from pool: 2x2048, 1x4096, generated on demand: 2x2048");
+    }
+
+    #[tokio::test]
+    async fn test_ec_replacement_keeps_curve() {
+        let mut rsa_key_pool = RsaKeyPool::fill(&[], Default::default()).await.unwrap();
+
+        for curve in EcCurve::ALL {
+            let (original_private_key, _) = generate_ec_key(curve).unwrap();
+            let original_public_key = PublicKey::try_from(&original_private_key).unwrap();
+            assert_eq!(original_public_key.ec_curve(), Some(curve));
+
+            // More replacements than the pool holds, the rest are generated on the spot
+            for _ in 0..=EC_POOL_SIZE {
+                let (private_key, key_pair) = rsa_key_pool.replacement_for(&original_public_key).unwrap();
+                let public_key = PublicKey::try_from(&private_key).unwrap();
+                assert_eq!(public_key.ec_curve(), Some(curve));
+                assert_ne!(public_key, original_public_key);
+                assert_eq!(
+                    key_pair.signature_algorithm().unwrap(),
+                    match curve {
+                        EcCurve::P256 => SignatureAlgorithm::EcdsaSha256,
+                        EcCurve::P384 => SignatureAlgorithm::EcdsaSha384,
+                    }
+                );
+                assert_eq!(private_key.pem().unwrap().tag(), "EC PRIVATE KEY");
             }
         }
+    }
+
+    #[test]
+    fn test_pool_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key-pool.pem");
+        let (rsa_private_key, _) = generate_rsa_key(2048).unwrap();
+
+        write_pool_file(&path, [&rsa_private_key].into_iter()).unwrap();
+        assert!(write_pool_file(&path, [&rsa_private_key].into_iter()).is_err());
+
+        let keys = read_pool_file(&path).unwrap();
+        assert_eq!(keys.keys().collect::<Vec<_>>(), vec![&2048]);
+        assert_eq!(keys[&2048], vec![rsa_private_key]);
 
-        Ok(generate_rsa_key(size)?)
+        std::fs::write(&path, pem::encode(&pem::Pem::new("RSA PRIVATE KEY", vec![0u8; 16]))).unwrap();
+        assert!(read_pool_file(&path).is_err());
     }
 }
@@ -45,6 +45,16 @@ lazy_static! {
         regex!("CN=olm-selfsigned-[0-9a-f]{10,32}, O=Red Hat, Inc."),
     ].into_iter().collect();
 
+    // Like KNOWN_MISSING_PRIVATE_KEY_CERTS, but for certs whose subject is up to the user, so
+    // they're recognized by the (secret name, data key) they're found in instead
+    pub(crate) static ref KNOWN_MISSING_PRIVATE_KEY_SECRET_ENTRIES: Vec<(&'static str, &'static str)> = vec![
+        // The root CA of an Istio / OpenShift Service Mesh plugged-in CA. The mesh is only given
+        // the intermediate (ca-cert.pem) and its key, the root's key stays with whoever created
+        // them. The root is still regenerated (and the intermediate re-signed by it), so the
+        // mesh doesn't keep trusting the seed's root
+        ("cacerts", "root-cert.pem"),
+    ];
+
     // TODO: Find a better way to identify these rather than maintaining this big list
     pub(crate) static ref EXTERNAL_CERTS: HashSet<String> = vec![
         "undecodable", // Some CA use Teletex encoding for their subject and our x509 lib doesn't like dealing with that
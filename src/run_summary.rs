@@ -0,0 +1,143 @@
+use crate::{
+    cluster_crypto::{crypto_utils::public_key_fingerprint, entropy, locations::Locations},
+    key_continuity::{cert_fingerprints, jwt_fingerprint, private_key_fingerprint, KeyContinuity},
+};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// A machine readable account of every crypto object recert found and what became of it, for
+/// automation (e.g. lifecycle-agent) to audit and record what changed. Built from the objects
+/// recorded before regeneration, which by now hold their regenerated versions
+fn render(key_continuity: &KeyContinuity) -> Result<String> {
+    let locations = |locations: &Locations| {
+        let mut locations = locations.0.iter().map(|location| location.to_string()).collect::<Vec<_>>();
+        locations.sort();
+        locations
+    };
+
+    let mut certs = vec![];
+    for (cert_key_pair, (old_fingerprint, old_key_fingerprint)) in &key_continuity.cert_key_pairs {
+        let cert_key_pair = (**cert_key_pair).borrow();
+        let (new_fingerprint, new_key_fingerprint) = cert_fingerprints(&cert_key_pair)?;
+        let distributed_cert = (*cert_key_pair.distributed_cert).borrow();
+        let certificate = &distributed_cert.certificate;
+        certs.push(serde_json::json!({
+            "subject": certificate.subject,
+            "issuer": certificate.issuer,
+            "sans": certificate.dns_sans()?,
+            "not_before": certificate.not_before().to_rfc3339(),
+            "not_after": certificate.not_after().to_rfc3339(),
+            "regenerated": cert_key_pair.regenerated,
+            "old_fingerprint": old_fingerprint,
+            "new_fingerprint": new_fingerprint,
+            "old_key_fingerprint": old_key_fingerprint,
+            "new_key_fingerprint": new_key_fingerprint,
+            "locations": locations(&distributed_cert.locations),
+            "private_key_locations": cert_key_pair
+                .distributed_private_key
+                .as_ref()
+                .map(|private_key| locations(&(**private_key).borrow().locations)),
+        }));
+    }
+
+    let mut private_keys = vec![];
+    for (private_key, old_key_fingerprint) in &key_continuity.private_keys {
+        let private_key = (**private_key).borrow();
+        private_keys.push(serde_json::json!({
+            "regenerated": private_key.regenerated,
+            "old_key_fingerprint": old_key_fingerprint,
+            "new_key_fingerprint": private_key_fingerprint(&private_key)?,
+            "locations": locations(&private_key.locations),
+        }));
+    }
+
+    let mut public_keys = vec![];
+    for (public_key, old_key_fingerprint) in &key_continuity.public_keys {
+        let public_key = (**public_key).borrow();
+        public_keys.push(serde_json::json!({
+            "regenerated": public_key.regenerated,
+            "old_key_fingerprint": old_key_fingerprint,
+            "new_key_fingerprint": public_key_fingerprint(&public_key.key)?,
+            "locations": locations(&public_key.locations),
+        }));
+    }
+
+    let mut jwts = vec![];
+    for (jwt, old_fingerprint) in &key_continuity.jwts {
+        let jwt = (**jwt).borrow();
+        jwts.push(serde_json::json!({
+            "regenerated": jwt.regenerated,
+            "old_fingerprint": old_fingerprint,
+            "new_fingerprint": jwt_fingerprint(&jwt),
+            "locations": locations(&jwt.locations),
+        }));
+    }
+
+    Ok(serde_json::to_string_pretty(&serde_json::json!({
+        "certs": certs,
+        "private_keys": private_keys,
+        "public_keys": public_keys,
+        "jwts": jwts,
+        "entropy_source": entropy::description(),
+    }))?)
+}
+
+pub(crate) fn write(path: &Path, key_continuity: &KeyContinuity) -> Result<()> {
+    std::fs::write(path, render(key_continuity)?).with_context(|| format!("writing run summary to {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster_crypto::{
+            crypto_objects,
+            locations::{FileContentLocation, FileLocation, Location, LocationValueType},
+            private_key_format::PrivateKeyPolicy,
+            ClusterCryptoObjects,
+        },
+        test_fixtures::CertFixture,
+    };
+
+    #[test]
+    fn test_render() {
+        let ca = CertFixture::ca("root");
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        for (path, contents) in [("/ca.crt", &ca.cert_pem), ("/ca.key", &ca.key_pem), ("/bundle.crt", &ca.cert_pem)] {
+            cluster_crypto.register_discovered_crypto_objects(
+                crypto_objects::process_pem_bundle(
+                    contents,
+                    &Location::Filesystem(FileLocation {
+                        path: path.to_string(),
+                        content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+                    }),
+                    &PrivateKeyPolicy::default(),
+                )
+                .unwrap(),
+            );
+        }
+        cluster_crypto.pair_certs_and_keys().unwrap();
+
+        let key_continuity = KeyContinuity::record_originals(&cluster_crypto).unwrap();
+        let summary: serde_json::Value = serde_json::from_str(&render(&key_continuity).unwrap()).unwrap();
+
+        let cert = &summary["certs"][0];
+        assert_eq!(cert["subject"], ca.subject());
+        assert_eq!(cert["sans"], serde_json::json!([]));
+        assert_eq!(cert["regenerated"], false);
+        // Nothing was regenerated
+        assert_eq!(cert["old_fingerprint"], cert["new_fingerprint"]);
+        assert_eq!(
+            cert["locations"],
+            serde_json::json!(["file:/bundle.crt::pem0", "file:/ca.crt::pem0"])
+        );
+        assert_eq!(cert["private_key_locations"], serde_json::json!(["file:/ca.key::pem0"]));
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(cert["not_before"].as_str().unwrap()).unwrap()
+                < chrono::DateTime::parse_from_rfc3339(cert["not_after"].as_str().unwrap()).unwrap()
+        );
+        assert_eq!(summary["private_keys"], serde_json::json!([]));
+        assert_eq!(summary["jwts"], serde_json::json!([]));
+        assert_eq!(summary["entropy_source"], "OS RNG");
+    }
+}
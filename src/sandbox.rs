@@ -0,0 +1,264 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    ffi::CString,
+    os::{fd::RawFd, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+};
+
+// See include/uapi/linux/landlock.h
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+const LANDLOCK_RULE_NET_PORT: u32 = 2;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+/// All the filesystem access rights of the first landlock ABI
+const ACCESS_FS_ABI_1: u64 = (1 << 13) - 1;
+/// The only access rights that may be granted on a path that is not a directory
+const ACCESS_FS_FILE: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE;
+
+const ACCESS_NET_BIND_TCP: u64 = 1 << 0;
+const ACCESS_NET_CONNECT_TCP: u64 = 1 << 1;
+
+/// The first landlock ABI version that can restrict TCP connections
+const MIN_ABI: i64 = 4;
+
+/// System paths recert (and the ouger/openssl binaries it runs) need to read in order to
+/// function at all. Paths which don't exist on the host are skipped.
+const SYSTEM_READ_PATHS: &[&str] = &[
+    "/usr",
+    "/lib",
+    "/lib64",
+    "/bin",
+    "/sbin",
+    "/etc/ssl",
+    "/etc/pki",
+    "/etc/ld.so.cache",
+    "/etc/hosts",
+    "/etc/resolv.conf",
+    "/etc/nsswitch.conf",
+    "/proc/self",
+    "/sys/devices/system/cpu",
+    "/sys/fs/cgroup",
+    "/dev/urandom",
+];
+
+const SYSTEM_READ_WRITE_PATHS: &[&str] = &["/dev/null"];
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+    handled_access_net: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+#[repr(C)]
+struct NetPortAttr {
+    allowed_access: u64,
+    port: u64,
+}
+
+/// Everything the sandboxed process is allowed to touch
+pub(crate) struct SandboxPolicy {
+    /// Paths that can be read and written, e.g. the static dirs being recertified
+    pub(crate) read_write_paths: Vec<PathBuf>,
+    /// TCP ports that can be connected to, e.g. that of the etcd endpoint
+    pub(crate) connect_ports: Vec<u16>,
+}
+
+/// Use landlock to restrict the filesystem and network access of this process (and any process
+/// it spawns) to what the given policy allows. Landlock restrictions only apply to the calling
+/// thread and threads/processes it creates later, so this must be called before the async
+/// runtime (and any other thread) is started. Fails if the kernel can't enforce the full policy,
+/// as a partially applied sandbox would give a false sense of security.
+pub(crate) fn restrict(policy: &SandboxPolicy) -> Result<()> {
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 0 {
+        bail!("landlock is not supported or not enabled by this kernel");
+    }
+    if abi < MIN_ABI {
+        bail!(
+            "kernel supports landlock ABI version {}, but at least {} is required to restrict network access",
+            abi,
+            MIN_ABI
+        );
+    }
+
+    let handled_access_fs = ACCESS_FS_ABI_1 | ACCESS_FS_REFER | ACCESS_FS_TRUNCATE;
+
+    let ruleset_attr = RulesetAttr {
+        handled_access_fs,
+        handled_access_net: ACCESS_NET_BIND_TCP | ACCESS_NET_CONNECT_TCP,
+    };
+    let ruleset_fd = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &ruleset_attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset_fd < 0 {
+        return Err(std::io::Error::last_os_error()).context("creating landlock ruleset");
+    }
+    let ruleset_fd = ruleset_fd as RawFd;
+
+    let result = (|| {
+        let read_only_access = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+        for path in SYSTEM_READ_PATHS.iter().map(Path::new).filter(|path| path.exists()) {
+            add_path_rule(ruleset_fd, path, read_only_access)?;
+        }
+
+        for path in SYSTEM_READ_WRITE_PATHS
+            .iter()
+            .map(PathBuf::from)
+            // Used for the temporary files we pass to openssl
+            .chain(std::iter::once(std::env::temp_dir()))
+            .chain(policy.read_write_paths.iter().cloned())
+            .filter(|path| path.exists())
+        {
+            add_path_rule(ruleset_fd, &path, handled_access_fs)?;
+        }
+
+        for port in &policy.connect_ports {
+            add_port_rule(ruleset_fd, *port)?;
+        }
+
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("setting no_new_privs");
+        }
+
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset_fd, 0) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("enforcing landlock ruleset");
+        }
+
+        Ok(())
+    })();
+
+    unsafe { libc::close(ruleset_fd) };
+
+    result
+}
+
+/// Lock all current and future memory of the process (most importantly the pre-generated key
+/// pool and the private keys we regenerate) into RAM so it's never written out to swap, and mark
+/// the process as non-dumpable so it doesn't end up in core dumps either.
+pub(crate) fn lock_memory() -> Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("disabling core dumps");
+    }
+
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("locking memory, consider raising RLIMIT_MEMLOCK");
+    }
+
+    Ok(())
+}
+
+fn add_path_rule(ruleset_fd: RawFd, path: &Path, allowed_access: u64) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).context("path contains nul byte")?;
+    let parent_fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if parent_fd < 0 {
+        return Err(std::io::Error::last_os_error()).with_context(|| format!("opening {:?}", path));
+    }
+
+    let allowed_access = if path.is_dir() {
+        allowed_access
+    } else {
+        allowed_access & ACCESS_FS_FILE
+    };
+
+    let rule = PathBeneathAttr { allowed_access, parent_fd };
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset_fd,
+            LANDLOCK_RULE_PATH_BENEATH,
+            &rule as *const PathBeneathAttr,
+            0,
+        )
+    };
+    let error = std::io::Error::last_os_error();
+    unsafe { libc::close(parent_fd) };
+
+    if result != 0 {
+        return Err(error).with_context(|| format!("adding landlock rule for {:?}", path));
+    }
+
+    Ok(())
+}
+
+fn add_port_rule(ruleset_fd: RawFd, port: u16) -> Result<()> {
+    let rule = NetPortAttr {
+        allowed_access: ACCESS_NET_CONNECT_TCP,
+        port: port.into(),
+    };
+    if unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset_fd,
+            LANDLOCK_RULE_NET_PORT,
+            &rule as *const NetPortAttr,
+            0,
+        )
+    } != 0
+    {
+        return Err(std::io::Error::last_os_error()).with_context(|| format!("adding landlock rule for port {}", port));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restrict() {
+        let allowed_dir = tempfile::tempdir_in(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let allowed_path = allowed_dir.path().to_path_buf();
+
+        // Landlock applies to the calling thread, so do this on a dedicated one to leave the
+        // other tests unaffected
+        std::thread::spawn(move || {
+            if let Err(err) = restrict(&SandboxPolicy {
+                read_write_paths: vec![allowed_path.clone()],
+                connect_ports: vec![2379],
+            }) {
+                println!("skipping, kernel can't sandbox: {:?}", err);
+                return;
+            }
+
+            std::fs::write(allowed_path.join("allowed"), "allowed").unwrap();
+            assert_eq!(
+                std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+                    .unwrap_err()
+                    .kind(),
+                std::io::ErrorKind::PermissionDenied
+            );
+            assert_eq!(
+                std::net::TcpStream::connect("127.0.0.1:1").unwrap_err().kind(),
+                std::io::ErrorKind::PermissionDenied
+            );
+        })
+        .join()
+        .unwrap();
+    }
+}
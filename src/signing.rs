@@ -0,0 +1,130 @@
+use crate::file_utils;
+use anyhow::{Context, Result};
+use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs8::EncodePrivateKey, signature::Signer, RsaPrivateKey};
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use x509_certificate::InMemorySigningKeyPair;
+use zeroize::Zeroizing;
+
+/// Signs the artifacts recert emits (e.g. the audit log) with a user provided key, so that
+/// downstream pipelines can verify they were produced by an authorized recert run. Signatures are
+/// detached signatures (PKCS#1 v1.5 SHA-256 for RSA keys, ASN.1 ECDSA for EC keys)
+/// written next to the artifact with a .sig suffix, which can be verified with e.g.:
+///
+///   openssl dgst -sha256 -verify public.pem -signature audit.log.sig audit.log
+pub(crate) struct ArtifactSigner {
+    key_pair: InMemorySigningKeyPair,
+}
+
+impl ArtifactSigner {
+    /// Load a PEM encoded private key, see read_pkcs8_private_key
+    pub(crate) fn load(key_source: &Path) -> Result<Self> {
+        let pkcs8_der = read_pkcs8_private_key(key_source).context("reading signing key")?;
+
+        Ok(Self {
+            key_pair: InMemorySigningKeyPair::from_pkcs8_der(&pkcs8_der).context("loading signing key")?,
+        })
+    }
+
+    pub(crate) fn sign(&self, contents: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.key_pair.try_sign(contents).context("signing")?.as_ref().to_vec())
+    }
+
+    /// Sign the given file, writing the signature to a .sig file next to it. Returns the path of
+    /// the signature file.
+    pub(crate) async fn sign_file(&self, path: &Path) -> Result<PathBuf> {
+        let mut signature_path = path.as_os_str().to_owned();
+        signature_path.push(".sig");
+        let signature_path = PathBuf::from(signature_path);
+
+        // Deliberately not going through file_utils, as that would add a record of these to the
+        // audit log we've just signed
+        let contents = tokio::fs::read(path).await.with_context(|| format!("reading {:?}", path))?;
+        tokio::fs::write(&signature_path, self.sign(&contents)?)
+            .await
+            .with_context(|| format!("writing {:?}", signature_path))?;
+
+        Ok(signature_path)
+    }
+}
+
+/// Read a PEM encoded private key, converted to the PKCS#8 InMemorySigningKeyPair loads. Supports
+/// PKCS#8 (RSA or EC), PKCS#1 (RSA) and SEC1 (P-256 or P-384) keys. See file_utils::read_secret
+/// for the supported key sources
+pub(crate) fn read_pkcs8_private_key(key_source: &Path) -> Result<Zeroizing<Vec<u8>>> {
+    let key_pem = file_utils::read_secret(key_source)?;
+
+    Ok(Zeroizing::new(
+        match pem::parse(key_pem.as_str()).context("parsing private key pem")? {
+            pem if pem.tag() == "RSA PRIVATE KEY" => RsaPrivateKey::from_pkcs1_pem(&key_pem)?
+                .to_pkcs8_der()
+                .context("converting RSA private key to pkcs8")?
+                .as_bytes()
+                .to_vec(),
+            pem if pem.tag() == "EC PRIVATE KEY" => match p256::SecretKey::from_sec1_der(pem.contents()) {
+                Ok(secret_key) => secret_key.to_pkcs8_der(),
+                Err(_) => p384::SecretKey::from_sec1_der(pem.contents())
+                    .context("parsing EC private key, only P-256 and P-384 are supported")?
+                    .to_pkcs8_der(),
+            }
+            .context("converting EC private key to pkcs8")?
+            .as_bytes()
+            .to_vec(),
+            pem => pem.into_contents(),
+        },
+    ))
+}
+
+static ARTIFACT_SIGNER: OnceLock<ArtifactSigner> = OnceLock::new();
+
+pub(crate) fn init(sign_key: Option<PathBuf>) -> Result<()> {
+    if let Some(sign_key) = sign_key {
+        ARTIFACT_SIGNER
+            .set(ArtifactSigner::load(&sign_key)?)
+            .ok()
+            .context("artifact signer already initialized")?;
+    }
+
+    Ok(())
+}
+
+/// Sign the given artifact if a signing key was configured, otherwise do nothing
+pub(crate) async fn sign_artifact(path: &Path) -> Result<()> {
+    if let Some(signer) = ARTIFACT_SIGNER.get() {
+        let signature_path = signer.sign_file(path).await?;
+        println!("Signed {:?}, signature written to {:?}", path, signature_path);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs1::EncodeRsaPrivateKey;
+    use x509_certificate::Sign;
+
+    #[tokio::test]
+    async fn test_sign_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&key_path, private_key.to_pkcs1_pem(Default::default()).unwrap().as_bytes()).unwrap();
+
+        let artifact_path = dir.path().join("audit.log");
+        std::fs::write(&artifact_path, "{}\n").unwrap();
+
+        let signer = ArtifactSigner::load(&key_path).unwrap();
+        let signature_path = signer.sign_file(&artifact_path).await.unwrap();
+        assert_eq!(signature_path, dir.path().join("audit.log.sig"));
+
+        let public_key =
+            ring::signature::UnparsedPublicKey::new(&ring::signature::RSA_PKCS1_2048_8192_SHA256, signer.key_pair.public_key_data());
+        let signature = std::fs::read(&signature_path).unwrap();
+        assert!(public_key.verify(b"{}\n", &signature).is_ok());
+        assert!(public_key.verify(b"{}\n\n", &signature).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{cleanup, console, error_catalog::CatalogedError};
+use anyhow::{Context, Result};
+use std::{
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How often the heartbeat timestamp is refreshed while recert runs
+pub(crate) const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Status {
+    phase: String,
+    percent: u8,
+    phase_started: f64,
+    error: Option<String>,
+    error_code: Option<&'static str>,
+}
+
+/// What recert is currently doing. Tracked even without a status file, for the failure report
+static STATUS: Mutex<Status> = Mutex::new(Status {
+    phase: String::new(),
+    percent: 0,
+    phase_started: 0.0,
+    error: None,
+    error_code: None,
+});
+
+/// A small JSON file describing what recert is currently doing, rewritten on every phase change
+/// and every HEARTBEAT_INTERVAL, so that external watchdogs can tell a slow run from a hung or dead
+/// one.
+static STATUS_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+pub(crate) fn init(path: Option<PathBuf>) -> Result<()> {
+    if let Some(path) = path {
+        STATUS_FILE.set(path).ok().context("status file already initialized")?;
+    }
+
+    record_phase("starting", 0)
+}
+
+/// Record that recert entered a new phase, which is roughly percent done with the whole run
+pub(crate) fn phase(phase: &str, percent: u8) -> Result<()> {
+    console::phase(phase);
+    record_phase(phase, percent)
+}
+
+fn record_phase(phase: &str, percent: u8) -> Result<()> {
+    update(|status| {
+        status.phase = phase.to_string();
+        status.percent = percent;
+        status.phase_started = now()?;
+        Ok(())
+    })
+}
+
+pub(crate) fn current_phase() -> Result<String> {
+    Ok(STATUS.lock().ok().context("status lock poisoned")?.phase.clone())
+}
+
+/// Record the outcome of the whole run
+pub(crate) fn finish(result: &Result<()>) -> Result<()> {
+    match result {
+        Ok(()) => record_phase("done", 100),
+        Err(err) => update(|status| {
+            status.error_code = Some(CatalogedError::classify(err, &status.phase).code.code());
+            status.phase = "failed".to_string();
+            status.error = Some(format!("{:#}", err));
+            Ok(())
+        }),
+    }
+}
+
+/// Refresh the heartbeat timestamp until the returned task is aborted
+pub(crate) fn spawn_heartbeat() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = write() {
+                println!("WARNING: failed to write status file: {:#}", err);
+            }
+        }
+    })
+}
+
+fn update(change: impl FnOnce(&mut Status) -> Result<()>) -> Result<()> {
+    change(&mut *STATUS.lock().ok().context("status lock poisoned")?)?;
+    write()
+}
+
+fn write() -> Result<()> {
+    let Some(path) = STATUS_FILE.get() else {
+        return Ok(());
+    };
+
+    let contents = render(&*STATUS.lock().ok().context("status lock poisoned")?, now()?)?;
+
+    // Written to a temporary file first so that watchdogs never see a partially written file
+    let mut temporary_path = path.clone().into_os_string();
+    temporary_path.push(".tmp");
+    let temporary_path = PathBuf::from(temporary_path);
+    cleanup::register_temporary(&temporary_path)?;
+    std::fs::write(&temporary_path, contents).with_context(|| format!("writing {:?}", temporary_path))?;
+    std::fs::rename(&temporary_path, path).with_context(|| format!("renaming to {:?}", path))?;
+    cleanup::unregister_temporary(&temporary_path)?;
+
+    Ok(())
+}
+
+fn render(status: &Status, heartbeat: f64) -> Result<String> {
+    Ok(serde_json::to_string(&serde_json::json!({
+        "pid": std::process::id(),
+        "phase": status.phase,
+        "percent": status.percent,
+        "phase_started": status.phase_started,
+        "heartbeat": heartbeat,
+        "error": status.error,
+        "error_code": status.error_code,
+    }))?)
+}
+
+fn now() -> Result<f64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let status = Status {
+            phase: "scanning".to_string(),
+            percent: 10,
+            phase_started: 1000.0,
+            error: None,
+            error_code: None,
+        };
+
+        let rendered: serde_json::Value = serde_json::from_str(&render(&status, 1005.0).unwrap()).unwrap();
+        assert_eq!(rendered["phase"], "scanning");
+        assert_eq!(rendered["percent"], 10);
+        assert_eq!(rendered["phase_started"], 1000.0);
+        assert_eq!(rendered["heartbeat"], 1005.0);
+        assert!(rendered["error"].is_null());
+        assert!(rendered["error_code"].is_null());
+    }
+}
@@ -0,0 +1,272 @@
+//! Synthesizes the resources recert scans (secrets, configmaps, kubeconfigs and machineconfigs)
+//! holding real certs and keys, so that supporting a new resource type or location can come with
+//! a regression test that scans it like a cluster would
+
+use crate::cluster_crypto::{
+    crypto_utils::{self, encode_tbs_cert_to_der},
+    keys::{EcCurve, PrivateKey},
+};
+use base64::{engine::general_purpose::STANDARD as base64_standard, Engine as _};
+use bcder::{BitString, Oid};
+use der::Encode;
+use rsa::signature::Signer;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use x509_cert::ext::pkix::{AuthorityKeyIdentifier, SubjectKeyIdentifier};
+use x509_certificate::{rfc5280, CapturedX509Certificate, InMemorySigningKeyPair, Sign, X509Certificate, X509CertificateBuilder};
+
+const BASIC_CONSTRAINTS_OID: [u8; 3] = [85, 29, 19];
+const SUBJECT_KEY_IDENTIFIER_OID: [u8; 3] = [85, 29, 14];
+const AUTHORITY_KEY_IDENTIFIER_OID: [u8; 3] = [85, 29, 35];
+
+/// A cert and its private key, both PEM encoded. Keys are SEC1, like most of the EC keys found in
+/// clusters, rather than PKCS#8. Certs carry the same basicConstraints, SKID and AKID extensions
+/// openssl req -x509 would give them, so that openssl can verify chains of them
+#[derive(Clone, Debug)]
+pub(crate) struct CertFixture {
+    pub(crate) common_name: String,
+    pub(crate) cert_pem: String,
+    pub(crate) key_pem: String,
+    key: PrivateKey,
+    skid: Vec<u8>,
+}
+
+impl CertFixture {
+    /// A self-signed CA
+    pub(crate) fn ca(common_name: &str) -> Self {
+        Self::issue(common_name, None, true)
+    }
+
+    /// An intermediate CA issued by the given CA
+    pub(crate) fn intermediate(common_name: &str, signer: &CertFixture) -> Self {
+        Self::issue(common_name, Some(signer), true)
+    }
+
+    /// A leaf cert issued by the given CA
+    pub(crate) fn leaf(common_name: &str, signer: &CertFixture) -> Self {
+        Self::issue(common_name, Some(signer), false)
+    }
+
+    fn issue(common_name: &str, signer: Option<&CertFixture>, is_ca: bool) -> Self {
+        let (key, key_pair) = crypto_utils::generate_ec_key(EcCurve::P256).unwrap();
+        let skid = Sha1::digest(key_pair.public_key_data()).to_vec();
+
+        let mut builder = X509CertificateBuilder::default();
+        builder.subject().append_common_name_utf8_string(common_name).unwrap();
+        builder
+            .issuer()
+            .append_common_name_utf8_string(signer.map_or(common_name, |signer| &signer.common_name))
+            .unwrap();
+        builder.serial_number(rand::random::<u32>().into());
+        builder.validity_duration(chrono::Duration::days(1));
+        // CA:TRUE is a SEQUENCE holding a TRUE BOOLEAN, CA:FALSE the empty SEQUENCE
+        let basic_constraints: &[u8] = if is_ca { &[0x30, 0x03, 0x01, 0x01, 0xff] } else { &[0x30, 0x00] };
+        builder.add_extension_der_data(Oid(BASIC_CONSTRAINTS_OID.as_ref().into()), true, basic_constraints);
+        let subject_key_identifier = SubjectKeyIdentifier(der::asn1::OctetString::new(skid.as_slice()).unwrap());
+        builder.add_extension_der_data(
+            Oid(SUBJECT_KEY_IDENTIFIER_OID.as_ref().into()),
+            false,
+            subject_key_identifier.to_der().unwrap(),
+        );
+        let authority_key_identifier = AuthorityKeyIdentifier {
+            key_identifier: Some(der::asn1::OctetString::new(signer.map_or(skid.as_slice(), |signer| &signer.skid)).unwrap()),
+            authority_cert_issuer: None,
+            authority_cert_serial_number: None,
+        };
+        builder.add_extension_der_data(
+            Oid(AUTHORITY_KEY_IDENTIFIER_OID.as_ref().into()),
+            false,
+            authority_key_identifier.to_der().unwrap(),
+        );
+
+        // The builder signs with the key it puts in the cert, so certs issued by a CA are re-signed
+        // with the CA's key
+        let self_signed = builder.create_with_key_pair(&key_pair).unwrap();
+        let certificate = match signer {
+            None => self_signed,
+            Some(signer) => {
+                let signer_key_pair: InMemorySigningKeyPair = signer.key.signing_key_pair().unwrap();
+                let mut tbs_certificate = AsRef::<rfc5280::Certificate>::as_ref(&self_signed).tbs_certificate.clone();
+                let signature_algorithm: rfc5280::AlgorithmIdentifier = signer_key_pair.signature_algorithm().unwrap().into();
+                tbs_certificate.signature = signature_algorithm.clone();
+                let signature = signer_key_pair
+                    .try_sign(&encode_tbs_cert_to_der(&tbs_certificate).unwrap())
+                    .unwrap();
+
+                let certificate = rfc5280::Certificate {
+                    tbs_certificate,
+                    signature_algorithm,
+                    signature: BitString::new(0, signature.as_ref().to_vec().into()),
+                };
+                CapturedX509Certificate::from_der(X509Certificate::from(certificate).encode_der().unwrap()).unwrap()
+            }
+        };
+
+        Self {
+            common_name: common_name.to_string(),
+            cert_pem: certificate.encode_pem(),
+            key_pem: pem::encode(&key.pem().unwrap()),
+            key,
+            skid,
+        }
+    }
+
+    pub(crate) fn subject(&self) -> String {
+        format!("CN={}", self.common_name)
+    }
+}
+
+/// A secret as stored in etcd, with its data base64 encoded
+pub(crate) fn secret(namespace: &str, name: &str, data: &[(&str, &str)]) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {"namespace": namespace, "name": name},
+        "type": "Opaque",
+        "data": data
+            .iter()
+            .map(|(key, value)| (key.to_string(), Value::String(base64_standard.encode(value))))
+            .collect::<serde_json::Map<_, _>>(),
+    })
+}
+
+/// A kubernetes.io/tls secret holding the cert and key of the given fixture
+pub(crate) fn tls_secret(namespace: &str, name: &str, cert: &CertFixture) -> Value {
+    let mut secret = secret(namespace, name, &[("tls.crt", &cert.cert_pem), ("tls.key", &cert.key_pem)]);
+    secret["type"] = json!("kubernetes.io/tls");
+    secret
+}
+
+pub(crate) fn configmap(namespace: &str, name: &str, data: &[(&str, &str)]) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "ConfigMap",
+        "metadata": {"namespace": namespace, "name": name},
+        "data": data
+            .iter()
+            .map(|(key, value)| (key.to_string(), Value::String(value.to_string())))
+            .collect::<serde_json::Map<_, _>>(),
+    })
+}
+
+/// A kubeconfig trusting the given CA, authenticating with the given client cert
+pub(crate) fn kubeconfig(ca: &CertFixture, client: &CertFixture) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Config",
+        "clusters": [{"name": "cluster", "cluster": {
+            "server": "https://api.example.com:6443",
+            "certificate-authority-data": base64_standard.encode(&ca.cert_pem),
+        }}],
+        "users": [{"name": "user", "user": {
+            "client-certificate-data": base64_standard.encode(&client.cert_pem),
+            "client-key-data": base64_standard.encode(&client.key_pem),
+        }}],
+        "contexts": [{"name": "context", "context": {"cluster": "cluster", "user": "user"}}],
+        "current-context": "context",
+    })
+}
+
+/// A machineconfig writing the given files, their contents encoded as data URLs like the MCO does
+pub(crate) fn machineconfig(name: &str, files: &[(&str, &str)]) -> Value {
+    json!({
+        "apiVersion": "machineconfiguration.openshift.io/v1",
+        "kind": "MachineConfig",
+        "metadata": {"name": name},
+        "spec": {"config": {
+            "ignition": {"version": "3.2.0"},
+            "storage": {"files": files
+                .iter()
+                .map(|(path, contents)| json!({
+                    "path": path,
+                    "mode": 420,
+                    "contents": {"source": format!("data:,{}", urlencoding(contents))},
+                }))
+                .collect::<Vec<_>>()},
+        }},
+    })
+}
+
+/// Percent-encode everything but unreserved characters, enough for data URLs
+fn urlencoding(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster_crypto::{
+        crypto_objects::{process_yaml_value, CryptoObject},
+        locations::{K8sResourceLocation, Location},
+        yaml_crawl::{self, ScanPolicy},
+        CryptoPolicies,
+    };
+
+    /// Crawl a resource and process its values the way the etcd scan does, returning the
+    /// subjects of the certs found and the number of private keys found
+    fn scan(resource: Value) -> (Vec<String>, usize) {
+        let k8s_resource_location = K8sResourceLocation::try_from(&resource).unwrap();
+        let mut subjects = vec![];
+        let mut private_keys = 0;
+
+        for yaml_value in yaml_crawl::crawl_yaml(resource, &ScanPolicy::default()).unwrap() {
+            let Some((yaml_location, value)) = yaml_crawl::decode_yaml_value(&yaml_value, &ScanPolicy::default()).unwrap() else {
+                continue;
+            };
+
+            for discovered in process_yaml_value(
+                value,
+                &Location::k8s_yaml(&k8s_resource_location, &yaml_location),
+                &CryptoPolicies::default(),
+            )
+            .unwrap()
+            {
+                match discovered.crypto_object {
+                    CryptoObject::Certificate(certificate) => subjects.push(certificate.subject),
+                    CryptoObject::PrivateKey(..) => private_keys += 1,
+                    _ => {}
+                }
+            }
+        }
+
+        subjects.sort();
+        (subjects, private_keys)
+    }
+
+    #[test]
+    fn test_fixtures_are_scanned() {
+        let ca = CertFixture::ca("fixture-ca");
+        let intermediate = CertFixture::intermediate("fixture-intermediate", &ca);
+        let leaf = CertFixture::leaf("fixture-leaf", &intermediate);
+
+        assert_eq!(scan(tls_secret("ns", "tls", &leaf)), (vec![leaf.subject()], 1), "tls secret");
+        assert_eq!(
+            scan(configmap(
+                "ns",
+                "bundle",
+                &[("ca-bundle.crt", &(ca.cert_pem.clone() + &intermediate.cert_pem))]
+            )),
+            (vec![ca.subject(), intermediate.subject()], 0),
+            "configmap"
+        );
+
+        let mut kubeconfig_secret = kubeconfig(&ca, &leaf);
+        kubeconfig_secret["metadata"] = json!({"name": "kubeconfig"});
+        assert_eq!(scan(kubeconfig_secret), (vec![ca.subject(), leaf.subject()], 1), "kubeconfig");
+
+        assert_eq!(
+            scan(machineconfig(
+                "mc",
+                &[("/etc/kubernetes/ca.crt", &ca.cert_pem), ("/etc/motd", "hello")]
+            )),
+            (vec![ca.subject()], 0),
+            "machineconfig"
+        );
+    }
+}
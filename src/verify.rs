@@ -0,0 +1,632 @@
+use crate::{
+    capabilities::Capabilities,
+    cluster_crypto::{
+        cert_key_pair::CertKeyPair,
+        crypto_utils,
+        keys::PublicKey,
+        locations::{Location, Locations},
+        resource_kinds::ResourceKindPolicy,
+        scanning, ClusterCryptoObjects, CryptoPolicies,
+    },
+    k8s_etcd::{EtcdAccess, InMemoryK8sEtcd},
+    key_continuity::{cert_fingerprints, private_key_fingerprint},
+    namespace_filter::NamespaceFilter,
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+use x509_certificate::X509CertificateError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, strum_macros::Display)]
+#[strum(serialize_all = "kebab-case")]
+enum Check {
+    /// Certs chain to their issuer
+    Chain,
+    /// Private keys match the certs / public keys kept next to them
+    KeyMatch,
+    /// JWTs verify against one of the cluster's keys
+    Jwt,
+    /// Nothing regenerated is still around in its original form
+    Stale,
+}
+
+/// An inconsistency found by verify
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Finding {
+    check: Check,
+    object: String,
+    problem: String,
+    locations: Vec<String>,
+}
+
+/// The outcome of all checks
+struct Verification {
+    findings: Vec<Finding>,
+    /// The certs whose chain checked out, by the same name as their findings would have
+    verified_chains: Vec<String>,
+}
+
+impl Finding {
+    fn new(check: Check, object: String, problem: String, locations: &Locations) -> Self {
+        let mut locations = locations.0.iter().map(|location| location.to_string()).collect::<Vec<_>>();
+        locations.sort();
+        Self {
+            check,
+            object,
+            problem,
+            locations,
+        }
+    }
+}
+
+/// Re-scan etcd and the static dirs (without modifying anything) after a run and check that the
+/// cluster's crypto is consistent. With the key continuity map of the run, also check that none of
+/// the original certs and keys it replaced are still around. Fails if anything is off
+pub(crate) async fn verify(
+    etcd_endpoint: &str,
+    etcd_access: &EtcdAccess,
+    static_dirs: Vec<PathBuf>,
+    key_continuity_map: Option<&Path>,
+    report: Option<&Path>,
+    junit_report: Option<&Path>,
+) -> Result<()> {
+    let stale_fingerprints = match key_continuity_map {
+        Some(path) => replaced_fingerprints(&std::fs::read(path).with_context(|| format!("reading {:?}", path))?)
+            .with_context(|| format!("parsing key continuity map {:?}", path))?,
+        None => HashSet::new(),
+    };
+
+    let etcd_client = etcd_access.connect(etcd_endpoint).await?;
+    let in_memory_etcd_client = Arc::new(InMemoryK8sEtcd::new(
+        etcd_client,
+        etcd_access,
+        NamespaceFilter::default(),
+        ResourceKindPolicy::default(),
+    ));
+
+    let capabilities = Capabilities::detect(&in_memory_etcd_client, None)
+        .await
+        .context("detecting cluster capabilities")?;
+    println!("Scanning etcd/filesystem... This might take a while");
+    let discovered_crypto_objects = scanning::crypto_scan(in_memory_etcd_client, static_dirs, capabilities, CryptoPolicies::default())
+        .await
+        .context("scanning")?;
+
+    let mut cluster_crypto = ClusterCryptoObjects::new();
+    cluster_crypto.register_discovered_crypto_objects(discovered_crypto_objects);
+    cluster_crypto.pair_certs_and_keys()?;
+    cluster_crypto.associate_public_keys()?;
+
+    let verification = run_checks(&cluster_crypto, &stale_fingerprints)?;
+    let findings = &verification.findings;
+
+    if let Some(report) = report {
+        std::fs::write(report, render_report(findings)?).with_context(|| format!("writing verification report to {:?}", report))?;
+    }
+
+    if let Some(junit_report) = junit_report {
+        let mut unsupported = cluster_crypto
+            .unsupported_objects
+            .iter()
+            .map(|(description, locations)| {
+                let mut locations = locations.0.iter().map(|location| location.to_string()).collect::<Vec<_>>();
+                locations.sort();
+                (description.clone(), locations.join(", "))
+            })
+            .collect::<Vec<_>>();
+        unsupported.sort();
+
+        std::fs::write(
+            junit_report,
+            render_junit_report(&verification, &unsupported, key_continuity_map.is_some()),
+        )
+        .with_context(|| format!("writing JUnit report to {:?}", junit_report))?;
+    }
+
+    if findings.is_empty() {
+        println!("Verification passed");
+        return Ok(());
+    }
+
+    for finding in findings {
+        println!(
+            "{}: {} {}, found in {}",
+            finding.check,
+            finding.object,
+            finding.problem,
+            finding.locations.join(", ")
+        );
+    }
+
+    bail!("verification found {} inconsistencies", findings.len())
+}
+
+fn run_checks(cluster_crypto: &ClusterCryptoObjects, stale_fingerprints: &HashSet<String>) -> Result<Verification> {
+    let mut findings = vec![];
+    let mut verified_chains = check_chains(cluster_crypto, &mut findings)?;
+    check_key_matches(cluster_crypto, &mut findings)?;
+    check_jwts(cluster_crypto, &mut findings)?;
+    check_stale(cluster_crypto, stale_fingerprints, &mut findings)?;
+    findings.sort();
+    verified_chains.sort();
+    Ok(Verification { findings, verified_chains })
+}
+
+fn is_signed_by(signee: &Rc<RefCell<CertKeyPair>>, signer: &Rc<RefCell<CertKeyPair>>) -> Result<bool> {
+    match crypto_utils::verify_signed_by_certificate(
+        &(*(**signee).borrow().distributed_cert).borrow().certificate.original,
+        &(*(**signer).borrow().distributed_cert).borrow().certificate.original,
+    ) {
+        Ok(()) => Ok(true),
+        Err(X509CertificateError::CertificateSignatureVerificationFailed) => Ok(false),
+        // Same as when filling cert signers
+        Err(X509CertificateError::UnsupportedSignatureVerification(..) | X509CertificateError::UnknownSignatureAlgorithm(..)) => {
+            crypto_utils::openssl_is_signed(signer, signee)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Every cert which isn't self-issued must be signed by one of the certs named as its issuer.
+/// Returns the certs whose chain checked out
+fn check_chains(cluster_crypto: &ClusterCryptoObjects, findings: &mut Vec<Finding>) -> Result<Vec<String>> {
+    let mut verified_chains = vec![];
+    for cert_key_pair in &cluster_crypto.cert_key_pairs {
+        let distributed_cert = (*(**cert_key_pair).borrow().distributed_cert).borrow().clone();
+        let certificate = &distributed_cert.certificate;
+        if certificate.original.subject_is_issuer() {
+            continue;
+        }
+
+        let issuers = cluster_crypto
+            .cert_key_pairs
+            .iter()
+            .filter(|potential_issuer| (*(**potential_issuer).borrow().distributed_cert).borrow().certificate.subject == certificate.issuer)
+            .collect::<Vec<_>>();
+
+        let problem = if issuers.is_empty() {
+            format!("was issued by {}, which isn't in the cluster", certificate.issuer)
+        } else if issuers
+            .iter()
+            .map(|issuer| is_signed_by(cert_key_pair, issuer))
+            .collect::<Result<Vec<_>>>()?
+            .contains(&true)
+        {
+            verified_chains.push(format!("cert {}", certificate.subject));
+            continue;
+        } else {
+            format!("isn't signed by any of the {} certs named {}", issuers.len(), certificate.issuer)
+        };
+
+        findings.push(Finding::new(
+            Check::Chain,
+            format!("cert {}", certificate.subject),
+            problem,
+            &distributed_cert.locations,
+        ));
+    }
+
+    Ok(verified_chains)
+}
+
+/// Where an object was found, down to the etcd key or file but not to the location within it
+fn container(location: &Location) -> String {
+    match location {
+        Location::K8s(k8s_location) => k8s_location.resource_location.as_etcd_key(),
+        Location::Filesystem(file_location) => file_location.path.clone(),
+    }
+}
+
+/// A private key matching none of the certs and public keys found anywhere, while sharing an etcd
+/// key or file with some of them (e.g. the tls.key of a secret whose tls.crt was replaced without
+/// it), means they've gone out of sync
+fn check_key_matches(cluster_crypto: &ClusterCryptoObjects, findings: &mut Vec<Finding>) -> Result<()> {
+    let mut containers_with_public_parts = HashSet::new();
+    for cert_key_pair in &cluster_crypto.cert_key_pairs {
+        containers_with_public_parts.extend(
+            (*(**cert_key_pair).borrow().distributed_cert)
+                .borrow()
+                .locations
+                .0
+                .iter()
+                .map(container),
+        );
+    }
+    for public_key in cluster_crypto.distributed_public_keys.values() {
+        containers_with_public_parts.extend((**public_key).borrow().locations.0.iter().map(container));
+    }
+
+    // Only standalone private keys are left unpaired
+    for private_key in cluster_crypto.distributed_private_keys.values() {
+        let private_key = (**private_key).borrow();
+        if private_key.associated_distributed_public_key.is_some() {
+            continue;
+        }
+
+        let mut shared = private_key
+            .locations
+            .0
+            .iter()
+            .map(container)
+            .filter(|container| containers_with_public_parts.contains(container))
+            .collect::<Vec<_>>();
+        if shared.is_empty() {
+            continue;
+        }
+        shared.sort();
+
+        findings.push(Finding::new(
+            Check::KeyMatch,
+            format!("private key {}", private_key_fingerprint(&private_key)?),
+            format!("matches none of the certs / public keys next to it in {}", shared.join(", ")),
+            &private_key.locations,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Every JWT must verify against the public part of one of the cluster's private keys
+fn check_jwts(cluster_crypto: &ClusterCryptoObjects, findings: &mut Vec<Finding>) -> Result<()> {
+    let mut public_keys = vec![];
+    for private_key in cluster_crypto.distributed_private_keys.values() {
+        public_keys.push(PublicKey::try_from(&(**private_key).borrow().key)?);
+    }
+    for cert_key_pair in &cluster_crypto.cert_key_pairs {
+        if let Some(private_key) = &(**cert_key_pair).borrow().distributed_private_key {
+            public_keys.push(PublicKey::try_from(&(**private_key).borrow().key)?);
+        }
+    }
+
+    // Usually a single key signs all of them, try the last one that worked first (see
+    // fill_jwt_signers)
+    let mut last_verifying_key = None;
+    for distributed_jwt in cluster_crypto.distributed_jwts.values() {
+        let distributed_jwt = (**distributed_jwt).borrow();
+        let verifying_key = last_verifying_key
+            .iter()
+            .chain(public_keys.iter())
+            .find(|public_key| crypto_utils::verify_jwt(public_key, &distributed_jwt).is_ok())
+            .cloned();
+
+        match verifying_key {
+            Some(verifying_key) => last_verifying_key = Some(verifying_key),
+            None => findings.push(Finding::new(
+                Check::Jwt,
+                "jwt".to_string(),
+                "verifies against none of the cluster's keys".to_string(),
+                &distributed_jwt.locations,
+            )),
+        }
+    }
+
+    Ok(())
+}
+
+/// The fingerprints of the originals in a key continuity map which were replaced by something else
+pub(crate) fn replaced_fingerprints(key_continuity_map: &[u8]) -> Result<HashSet<String>> {
+    let key_continuity_map: serde_json::Value = serde_json::from_slice(key_continuity_map)?;
+
+    let mut replaced = HashSet::new();
+    for object in key_continuity_map["objects"].as_array().context("objects is not an array")? {
+        for (old, new) in [
+            ("old_fingerprint", "new_fingerprint"),
+            ("old_key_fingerprint", "new_key_fingerprint"),
+        ] {
+            if let Some(old_fingerprint) = object[old].as_str() {
+                if !object[new]
+                    .as_str()
+                    .is_some_and(|new_fingerprint| crypto_utils::fingerprints_match(new_fingerprint, old_fingerprint))
+                {
+                    replaced.insert(old_fingerprint.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(replaced)
+}
+
+fn check_stale(cluster_crypto: &ClusterCryptoObjects, stale_fingerprints: &HashSet<String>, findings: &mut Vec<Finding>) -> Result<()> {
+    if stale_fingerprints.is_empty() {
+        return Ok(());
+    }
+
+    let mut stale = |fingerprint: &str, object: String, locations: &Locations| {
+        if stale_fingerprints
+            .iter()
+            .any(|stale_fingerprint| crypto_utils::fingerprints_match(stale_fingerprint, fingerprint))
+        {
+            findings.push(Finding::new(
+                Check::Stale,
+                object,
+                "is one of the originals which were replaced".to_string(),
+                locations,
+            ));
+        }
+    };
+
+    for cert_key_pair in &cluster_crypto.cert_key_pairs {
+        let cert_key_pair = (**cert_key_pair).borrow();
+        let (fingerprint, key_fingerprint) = cert_fingerprints(&cert_key_pair)?;
+        let distributed_cert = (*cert_key_pair.distributed_cert).borrow();
+        stale(
+            &fingerprint,
+            format!("cert {}", distributed_cert.certificate.subject),
+            &distributed_cert.locations,
+        );
+        stale(
+            &key_fingerprint,
+            format!("key of cert {}", distributed_cert.certificate.subject),
+            &distributed_cert.locations,
+        );
+        if let Some(private_key) = &cert_key_pair.distributed_private_key {
+            let private_key = (**private_key).borrow();
+            stale(
+                &key_fingerprint,
+                format!("private key of cert {}", distributed_cert.certificate.subject),
+                &private_key.locations,
+            );
+        }
+    }
+
+    for private_key in cluster_crypto.distributed_private_keys.values() {
+        let private_key = (**private_key).borrow();
+        let fingerprint = private_key_fingerprint(&private_key)?;
+        stale(&fingerprint, format!("private key {}", fingerprint), &private_key.locations);
+    }
+
+    for public_key in cluster_crypto.distributed_public_keys.values() {
+        let public_key = (**public_key).borrow();
+        let fingerprint = crypto_utils::public_key_fingerprint(&public_key.key)?;
+        stale(&fingerprint, format!("public key {}", fingerprint), &public_key.locations);
+    }
+
+    Ok(())
+}
+
+fn render_report(findings: &[Finding]) -> Result<String> {
+    let mut checks = BTreeMap::new();
+    for check in [Check::Chain, Check::KeyMatch, Check::Jwt, Check::Stale] {
+        checks.insert(check.to_string(), findings.iter().filter(|finding| finding.check == check).count());
+    }
+
+    Ok(serde_json::to_string_pretty(&serde_json::json!({
+        "passed": findings.is_empty(),
+        "inconsistencies_by_check": checks,
+        "inconsistencies": findings
+            .iter()
+            .map(|finding| serde_json::json!({
+                "check": finding.check.to_string(),
+                "object": finding.object,
+                "problem": finding.problem,
+                "locations": finding.locations,
+            }))
+            .collect::<Vec<_>>(),
+    }))?)
+}
+
+/// The results as JUnit XML, for CI systems to show as test results: a test suite per check, with a
+/// test case per verified chain and per inconsistency. Checks which don't go object by object pass
+/// as a single test case, the stale check is skipped without a key continuity map and unsupported
+/// objects, which can't be checked at all, are skipped test cases of their own suite
+fn render_junit_report(verification: &Verification, unsupported: &[(String, String)], stale_checked: bool) -> String {
+    let mut suites = vec![];
+    for check in [Check::Chain, Check::KeyMatch, Check::Jwt, Check::Stale] {
+        let findings = verification
+            .findings
+            .iter()
+            .filter(|finding| finding.check == check)
+            .collect::<Vec<_>>();
+        let mut test_cases = findings
+            .iter()
+            .map(|finding| {
+                junit_test_case(
+                    &check.to_string(),
+                    &finding.object,
+                    Some(format!(
+                        r#"<failure message="{}">found in {}</failure>"#,
+                        xml_escape(&finding.problem),
+                        xml_escape(&finding.locations.join(", "))
+                    )),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut skipped = 0;
+
+        match check {
+            Check::Chain => test_cases.extend(
+                verification
+                    .verified_chains
+                    .iter()
+                    .map(|object| junit_test_case(&check.to_string(), object, None)),
+            ),
+            Check::Stale if !stale_checked => {
+                skipped += 1;
+                test_cases.push(junit_test_case(
+                    &check.to_string(),
+                    "originals replaced",
+                    Some(r#"<skipped message="no key continuity map given"/>"#.to_string()),
+                ));
+            }
+            _ if findings.is_empty() => test_cases.push(junit_test_case(&check.to_string(), "no inconsistencies", None)),
+            _ => {}
+        }
+
+        suites.push(junit_test_suite(&check.to_string(), &test_cases, findings.len(), skipped));
+    }
+
+    let test_cases = unsupported
+        .iter()
+        .map(|(description, locations)| {
+            junit_test_case(
+                "unsupported",
+                description,
+                Some(format!(r#"<skipped message="left untouched at {}"/>"#, xml_escape(locations))),
+            )
+        })
+        .collect::<Vec<_>>();
+    suites.push(junit_test_suite("unsupported", &test_cases, 0, test_cases.len()));
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"recert verify\">\n{}</testsuites>\n",
+        suites.concat()
+    )
+}
+
+fn junit_test_suite(name: &str, test_cases: &[String], failures: usize, skipped: usize) -> String {
+    format!(
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\">\n{}  </testsuite>\n",
+        xml_escape(name),
+        test_cases.len(),
+        failures,
+        skipped,
+        test_cases.concat()
+    )
+}
+
+fn junit_test_case(classname: &str, name: &str, result: Option<String>) -> String {
+    match result {
+        Some(result) => format!(
+            "    <testcase classname=\"{}\" name=\"{}\">{}</testcase>\n",
+            xml_escape(classname),
+            xml_escape(name),
+            result
+        ),
+        None => format!(
+            "    <testcase classname=\"{}\" name=\"{}\"/>\n",
+            xml_escape(classname),
+            xml_escape(name)
+        ),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster_crypto::{
+            crypto_objects,
+            locations::{FileContentLocation, FileLocation, LocationValueType},
+            private_key_format::PrivateKeyPolicy,
+        },
+        test_fixtures::CertFixture,
+    };
+
+    fn scan(files: &[(&str, &str)]) -> ClusterCryptoObjects {
+        let mut cluster_crypto = ClusterCryptoObjects::new();
+        for (path, contents) in files {
+            cluster_crypto.register_discovered_crypto_objects(
+                crypto_objects::process_pem_bundle(
+                    contents,
+                    &Location::Filesystem(FileLocation {
+                        path: path.to_string(),
+                        content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+                    }),
+                    &PrivateKeyPolicy::default(),
+                )
+                .unwrap(),
+            );
+        }
+        cluster_crypto.pair_certs_and_keys().unwrap();
+        cluster_crypto.associate_public_keys().unwrap();
+        cluster_crypto
+    }
+
+    #[test]
+    fn test_consistent() {
+        let ca = CertFixture::ca("root");
+        let leaf = CertFixture::leaf("leaf", &ca);
+        let cluster_crypto = scan(&[
+            ("/ca.crt", &ca.cert_pem),
+            ("/ca.key", &ca.key_pem),
+            ("/tls.pem", &format!("{}{}", leaf.cert_pem, leaf.key_pem)),
+        ]);
+
+        let verification = run_checks(&cluster_crypto, &HashSet::new()).unwrap();
+        assert_eq!(verification.findings, vec![]);
+        assert_eq!(verification.verified_chains, vec!["cert CN=leaf".to_string()]);
+    }
+
+    #[test]
+    fn test_inconsistencies() {
+        let ca = CertFixture::ca("root");
+        let other_ca = CertFixture::ca("root");
+        let leaf = CertFixture::leaf("leaf", &other_ca);
+        let unrelated = CertFixture::ca("unrelated");
+        let cluster_crypto = scan(&[
+            ("/ca.crt", &ca.cert_pem),
+            ("/ca.key", &ca.key_pem),
+            // Signed by a CA with the same subject but a different key, next to a key it doesn't go with
+            ("/tls.pem", &format!("{}{}", leaf.cert_pem, unrelated.key_pem)),
+        ]);
+
+        let stale = HashSet::from([crypto_utils::fingerprint(
+            (*(*cluster_crypto
+                .cert_key_pairs
+                .iter()
+                .find(|pair| (*(***pair).borrow().distributed_cert).borrow().certificate.subject == ca.subject())
+                .unwrap())
+            .borrow()
+            .distributed_cert)
+                .borrow()
+                .certificate
+                .original
+                .constructed_data(),
+        )]);
+
+        let verification = run_checks(&cluster_crypto, &stale).unwrap();
+        let findings = &verification.findings;
+        assert_eq!(
+            findings.iter().map(|finding| finding.check).collect::<Vec<_>>(),
+            vec![Check::Chain, Check::KeyMatch, Check::Stale]
+        );
+        assert_eq!(findings[0].object, "cert CN=leaf");
+        assert_eq!(findings[0].problem, "isn't signed by any of the 1 certs named CN=root");
+        assert!(findings[1].object.starts_with("private key "));
+        assert_eq!(findings[1].locations, vec!["file:/tls.pem::pem1".to_string()]);
+        assert_eq!(findings[2].object, "cert CN=root");
+
+        let report: serde_json::Value = serde_json::from_str(&render_report(findings).unwrap()).unwrap();
+        assert_eq!(report["passed"], false);
+        assert_eq!(report["inconsistencies_by_check"]["key-match"], 1);
+
+        let junit_report = render_junit_report(&verification, &[("DSA private key".to_string(), "file:/dsa.key".to_string())], true);
+        assert!(junit_report.contains(r#"<testsuite name="chain" tests="1" failures="1" errors="0" skipped="0">"#));
+        assert!(junit_report.contains(
+            r#"<testcase classname="chain" name="cert CN=leaf"><failure message="isn&apos;t signed by any of the 1 certs named CN=root">found in file:/tls.pem::pem0</failure></testcase>"#
+        ));
+        assert!(junit_report.contains(r#"<testcase classname="jwt" name="no inconsistencies"/>"#));
+        assert!(junit_report.contains(
+            r#"<testcase classname="unsupported" name="DSA private key"><skipped message="left untouched at file:/dsa.key"/></testcase>"#
+        ));
+    }
+
+    #[test]
+    fn test_replaced_fingerprints() {
+        let map = serde_json::json!({
+            "objects": [
+                {"kind": "cert", "old_fingerprint": "A", "new_fingerprint": "B", "old_key_fingerprint": "C", "new_key_fingerprint": "C"},
+                {"kind": "private-key", "old_key_fingerprint": "D", "new_key_fingerprint": "E"},
+            ]
+        });
+
+        assert_eq!(
+            replaced_fingerprints(map.to_string().as_bytes()).unwrap(),
+            HashSet::from(["A".to_string(), "D".to_string()])
+        );
+    }
+}
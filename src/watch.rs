@@ -0,0 +1,211 @@
+use crate::{
+    cluster_crypto::{
+        crypto_objects::{process_yaml_value, CryptoObject},
+        crypto_utils::{fingerprint, fingerprints_match, public_key_fingerprint},
+        locations::{K8sResourceLocation, Location},
+        yaml_crawl, CryptoPolicies,
+    },
+    etcd_dump::PROTOBUF_MAGIC,
+    grep,
+    k8s_etcd::{run_ouger, EtcdAccess},
+    verify,
+};
+use anyhow::{bail, ensure, Context, Result};
+use etcd_client::{EventType, WatchOptions};
+use regex::Regex;
+use serde_json::Value;
+use std::{collections::HashSet, path::Path};
+
+/// Only these are watched, they're where operators restore the state they cache
+const WATCHED_PREFIXES: [&str; 2] = ["/kubernetes.io/secrets/", "/kubernetes.io/configmaps/"];
+
+/// What a written secret/configmap must not contain
+struct OldMaterial {
+    /// Of the original certs and keys which were replaced, see verify::replaced_fingerprints
+    fingerprints: HashSet<String>,
+    domains: Vec<Regex>,
+}
+
+/// Watch a live cluster's etcd for a while (e.g. after it first boots following a run) and alert
+/// on every secret/configmap written with one of the original certs / keys the run replaced or
+/// with an old domain, which happens when an operator restores stale state from one of its
+/// caches. Fails once the duration is up if anything was alerted on
+pub(crate) async fn watch(
+    etcd_endpoint: &str,
+    etcd_access: &EtcdAccess,
+    key_continuity_map: Option<&Path>,
+    old_domains: &[String],
+    duration: chrono::Duration,
+) -> Result<()> {
+    ensure!(
+        key_continuity_map.is_some() || !old_domains.is_empty(),
+        "nothing to watch for, give a key continuity map and/or old domains"
+    );
+
+    let old_material = OldMaterial {
+        fingerprints: match key_continuity_map {
+            Some(path) => verify::replaced_fingerprints(&std::fs::read(path).with_context(|| format!("reading {:?}", path))?)
+                .with_context(|| format!("parsing key continuity map {:?}", path))?,
+            None => HashSet::new(),
+        },
+        domains: old_domains.iter().map(|domain| domain_regex(domain)).collect::<Result<Vec<_>>>()?,
+    };
+
+    let etcd_client = etcd_access.connect(etcd_endpoint).await?;
+    let (_watcher, mut stream) = etcd_client
+        .watch_client()
+        .watch("/kubernetes.io/", Some(WatchOptions::new().with_prefix()))
+        .await
+        .context("starting watch")?;
+
+    println!("Watching secrets and configmaps for {} hours", duration.num_hours());
+    let deadline = tokio::time::Instant::now() + duration.to_std()?;
+    let mut alerts = 0;
+    while let Ok(response) = tokio::time::timeout_at(deadline, stream.message()).await {
+        let response = response.context("watching")?.context("watch ended")?;
+        for event in response.events() {
+            let Some(kv) = event.kv() else {
+                continue;
+            };
+            let key = kv.key_str()?;
+            if event.event_type() != EventType::Put || !WATCHED_PREFIXES.iter().any(|prefix| key.starts_with(prefix)) {
+                continue;
+            }
+
+            let value = etcd_access
+                .decrypt(key, kv.value().to_vec())
+                .await
+                .with_context(|| format!("decrypting {}", key))?;
+            let findings = check_value(&old_material, key, &value)
+                .await
+                .with_context(|| format!("checking {}", key))?;
+            for finding in &findings {
+                println!("ALERT: old material written to {}: {}", key, finding);
+            }
+            alerts += findings.len();
+        }
+    }
+
+    if alerts > 0 {
+        bail!("{} alerts while watching", alerts);
+    }
+
+    println!("Nothing re-introduced old material");
+
+    Ok(())
+}
+
+/// Matches the domain and its subdomains, but not other domains ending with it
+/// (other-seed.example.com for seed.example.com)
+fn domain_regex(domain: &str) -> Result<Regex> {
+    Ok(Regex::new(&format!(
+        r"(^|[^a-zA-Z0-9-]){}($|[^a-zA-Z0-9-])",
+        regex::escape(domain)
+    ))?)
+}
+
+async fn check_value(old_material: &OldMaterial, key: &str, value: &[u8]) -> Result<Vec<String>> {
+    let decoded = if value.starts_with(PROTOBUF_MAGIC) {
+        run_ouger("decode", value).await.context("decoding value with ouger")?
+    } else {
+        value.to_vec()
+    };
+    let document: Value = serde_yaml::from_slice(&decoded).context("parsing value")?;
+
+    let mut findings = vec![];
+    for domain in &old_material.domains {
+        grep::grep_document(domain, &format!("etcd:{}", key), "", &document, &mut findings);
+    }
+
+    if !old_material.fingerprints.is_empty() {
+        findings.extend(old_crypto_objects(&old_material.fingerprints, &document)?);
+    }
+
+    Ok(findings)
+}
+
+/// The locations of the certs and keys in the resource which are among the replaced originals
+fn old_crypto_objects(fingerprints: &HashSet<String>, document: &Value) -> Result<Vec<String>> {
+    let k8s_resource_location = K8sResourceLocation::try_from(document)?;
+    // Watching has no say in how the cluster was scanned, the defaults are the closest match
+    let policies = CryptoPolicies::default();
+
+    let mut old = vec![];
+    for yaml_value in yaml_crawl::crawl_yaml(document.clone(), &policies.scan).context("crawling")? {
+        let Some((yaml_location, decoded)) = yaml_crawl::decode_yaml_value(&yaml_value, &policies.scan).context("decoding")? else {
+            continue;
+        };
+
+        for discovered in process_yaml_value(decoded, &Location::k8s_yaml(&k8s_resource_location, &yaml_location), &policies)? {
+            let object_fingerprints = match &discovered.crypto_object {
+                CryptoObject::Certificate(certificate) => vec![
+                    fingerprint(certificate.original.constructed_data()),
+                    public_key_fingerprint(&certificate.public_key)?,
+                ],
+                CryptoObject::PrivateKey(_, public_key) | CryptoObject::PublicKey(public_key) => vec![public_key_fingerprint(public_key)?],
+                CryptoObject::Jwt(_) | CryptoObject::Crl(_) | CryptoObject::Unsupported(_) => vec![],
+            };
+
+            if object_fingerprints
+                .iter()
+                .any(|object_fingerprint| fingerprints.iter().any(|replaced| fingerprints_match(replaced, object_fingerprint)))
+            {
+                old.push(format!("replaced original at {}", discovered.location));
+            }
+        }
+    }
+
+    Ok(old)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::CertFixture;
+    use base64::{engine::general_purpose::STANDARD as base64_standard, Engine as _};
+
+    #[tokio::test]
+    async fn test_check_value() {
+        let old_ca = CertFixture::ca("old-ca");
+        let new_ca = CertFixture::ca("new-ca");
+        let old_fingerprint = fingerprint(pem::parse(&old_ca.cert_pem).unwrap().contents());
+        let old_material = OldMaterial {
+            fingerprints: HashSet::from([old_fingerprint]),
+            domains: vec![domain_regex("seed.example.com").unwrap()],
+        };
+
+        let secret = |ca: &CertFixture, server: &str| {
+            serde_json::json!({
+                "apiVersion": "v1",
+                "kind": "Secret",
+                "metadata": {"name": "cached", "namespace": "openshift-operator"},
+                "data": {
+                    "ca.crt": base64_standard.encode(&ca.cert_pem),
+                    "server": base64_standard.encode(server),
+                },
+            })
+            .to_string()
+        };
+
+        let key = "/kubernetes.io/secrets/openshift-operator/cached";
+        assert!(check_value(&old_material, key, secret(&new_ca, "api.edge.example.com").as_bytes())
+            .await
+            .unwrap()
+            .is_empty());
+
+        let findings = check_value(&old_material, key, secret(&old_ca, "api.seed.example.com").as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0], format!("etcd:{}:/data/server (base64): api.seed.example.com", key));
+        assert!(findings[1].starts_with("replaced original at k8s:"));
+
+        // Only the domain itself, not other domains ending with it
+        assert!(
+            check_value(&old_material, key, secret(&new_ca, "api.other-seed.example.com").as_bytes())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{
+    audit::{self, AuditAction},
+    cluster_crypto::{
+        locations::{FileContentLocation, FileLocation, LocationValueType},
+        pem_utils::PemBundle,
+        private_key_format::PrivateKeyPolicy,
+    },
+    file_utils::{self, FileKind, PermissionPolicy},
+};
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as base64_standard, Engine as _};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use x509_certificate::X509Certificate;
+
+/// The kubelet's rotated client and serving certs. The control plane CAs that signed them have
+/// been replaced, so rather than re-signing them (which would require the control plane's CA
+/// keys) they're deleted, and the kubelet requests new ones through the bootstrap kubeconfig
+const KUBELET_ROTATED_CERT_GLOBS: [&str; 2] = ["**/kubelet-client-*.pem", "**/kubelet-server-*.pem"];
+
+const CA_BUNDLE_GLOBS: [&str; 2] = ["**/*.crt", "**/*ca*.pem"];
+
+const KUBECONFIG_GLOBS: [&str; 2] = ["**/kubeconfig", "**/*.kubeconfig"];
+
+/// Re-key a worker node's local materials against a control plane which has already been
+/// recertified, without access to the cluster's etcd. Every CA cert in the worker's CA bundles and
+/// kubeconfigs is replaced with the control plane CA of the same CN, and the kubelet's own certs
+/// are removed so that it goes through bootstrapping again once it starts
+pub(crate) async fn worker(static_dirs: Vec<PathBuf>, control_plane_ca_bundle: &Path) -> Result<()> {
+    let control_plane_cas = load_control_plane_cas(control_plane_ca_bundle).context("loading control plane CA bundle")?;
+
+    for dir in &static_dirs {
+        remove_kubelet_rotated_certs(dir).await.context("removing kubelet certs")?;
+
+        for glob in CA_BUNDLE_GLOBS {
+            for file_path in file_utils::globvec(dir, glob)? {
+                fix_ca_bundle_file(&file_path, &control_plane_cas)
+                    .await
+                    .with_context(|| format!("fixing CA bundle {:?}", file_path))?;
+            }
+        }
+
+        for glob in KUBECONFIG_GLOBS {
+            for file_path in file_utils::globvec(dir, glob)? {
+                fix_kubeconfig_file(&file_path, &control_plane_cas)
+                    .await
+                    .with_context(|| format!("fixing kubeconfig {:?}", file_path))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The control plane CA certs, by CN. Regeneration preserves the CNs of the certs, so the CN of
+/// each of the worker's CAs identifies its replacement
+fn load_control_plane_cas(path: &Path) -> Result<HashMap<String, pem::Pem>> {
+    let contents = std::fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+
+    pem::parse_many(contents)?
+        .into_iter()
+        .filter(|pem| pem.tag() == "CERTIFICATE")
+        .map(|pem| {
+            let common_name = X509Certificate::from_der(pem.contents())?
+                .subject_common_name()
+                .context("control plane CA without a CN")?;
+            Ok((common_name, pem))
+        })
+        .collect()
+}
+
+async fn remove_kubelet_rotated_certs(dir: &Path) -> Result<()> {
+    for glob in KUBELET_ROTATED_CERT_GLOBS {
+        for file_path in file_utils::globvec(dir, glob)? {
+            println!("Removing {:?}, the kubelet will request a new one", file_path);
+            tokio::fs::remove_file(&file_path)
+                .await
+                .with_context(|| format!("removing {:?}", file_path))?;
+            audit::record(AuditAction::FileDelete, &file_path.to_string_lossy(), None)?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn fix_ca_bundle_file(file_path: &Path, control_plane_cas: &HashMap<String, pem::Pem>) -> Result<()> {
+    let original = file_utils::read_file(file_path).await?;
+    let Ok(contents) = std::str::from_utf8(&original) else {
+        return Ok(());
+    };
+
+    let new_contents = replace_cas(contents, control_plane_cas)?;
+    let file_location = FileLocation {
+        path: file_path.to_string_lossy().to_string(),
+        content_location: FileContentLocation::Raw(LocationValueType::Unknown),
+    };
+    file_utils::write_if_changed(
+        &file_location,
+        FileKind::Certificate,
+        PermissionPolicy::default(),
+        &original,
+        new_contents.into_bytes(),
+    )
+    .await
+}
+
+async fn fix_kubeconfig_file(file_path: &Path, control_plane_cas: &HashMap<String, pem::Pem>) -> Result<()> {
+    let original: Value = serde_yaml::from_slice(&file_utils::read_file(file_path).await?).context("parsing kubeconfig")?;
+
+    let mut kubeconfig = original.clone();
+    fix_kubeconfig(&mut kubeconfig, control_plane_cas)?;
+
+    if kubeconfig != original {
+        file_utils::write_file(file_path, serde_yaml::to_string(&kubeconfig).context("serializing kubeconfig")?).await?;
+    }
+
+    Ok(())
+}
+
+fn fix_kubeconfig(kubeconfig: &mut Value, control_plane_cas: &HashMap<String, pem::Pem>) -> Result<()> {
+    let Some(clusters) = kubeconfig.pointer_mut("/clusters") else {
+        return Ok(());
+    };
+
+    for cluster in clusters.as_array_mut().context("clusters not an array")? {
+        let Some(certificate_authority_data) = cluster.pointer_mut("/cluster/certificate-authority-data") else {
+            continue;
+        };
+
+        let bundle = String::from_utf8(
+            base64_standard
+                .decode(
+                    certificate_authority_data
+                        .as_str()
+                        .context("certificate-authority-data not a string")?,
+                )
+                .context("decoding certificate-authority-data")?,
+        )?;
+
+        *certificate_authority_data = Value::String(base64_standard.encode(replace_cas(&bundle, control_plane_cas)?));
+    }
+
+    Ok(())
+}
+
+/// Replace every cert in the bundle which has a control plane CA with the same CN, leaving the rest
+/// of the bundle as is
+fn replace_cas(bundle: &str, control_plane_cas: &HashMap<String, pem::Pem>) -> Result<String> {
+    let mut pem_bundle = PemBundle::parse(bundle)?;
+
+    for (pem_index, pem) in pem::parse_many(bundle)?.into_iter().enumerate() {
+        if pem.tag() != "CERTIFICATE" {
+            continue;
+        }
+
+        let Some(common_name) = X509Certificate::from_der(pem.contents())?.subject_common_name() else {
+            continue;
+        };
+
+        if let Some(control_plane_ca) = control_plane_cas.get(&common_name) {
+            pem_bundle.replace(u64::try_from(pem_index)?, control_plane_ca, &PrivateKeyPolicy::default())?;
+        }
+    }
+
+    Ok(pem_bundle.encode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_certificate::{EcdsaCurve, KeyAlgorithm, X509CertificateBuilder};
+
+    fn ca_pem(common_name: &str) -> pem::Pem {
+        let mut builder = X509CertificateBuilder::default();
+        builder.subject().append_common_name_utf8_string(common_name).unwrap();
+        let (cert, _) = builder
+            .create_with_random_keypair(KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1))
+            .unwrap();
+        pem::Pem::new("CERTIFICATE", cert.encode_der().unwrap())
+    }
+
+    #[test]
+    fn test_replace_cas() {
+        let old_signer = ca_pem("kube-apiserver-to-kubelet-signer");
+        let new_signer = ca_pem("kube-apiserver-to-kubelet-signer");
+        let unrelated = ca_pem("some-user-ca");
+        let control_plane_cas = HashMap::from([("kube-apiserver-to-kubelet-signer".to_string(), new_signer.clone())]);
+
+        let bundle = pem::encode_many(&[old_signer, unrelated.clone()]);
+        let replaced = replace_cas(&bundle, &control_plane_cas).unwrap();
+        assert_eq!(pem::parse_many(&replaced).unwrap(), vec![new_signer.clone(), unrelated]);
+
+        let mut kubeconfig = serde_json::json!({
+            "clusters": [{"name": "local", "cluster": {"certificate-authority-data": base64_standard.encode(&bundle), "server": "https://api-int.x:6443"}}],
+        });
+        fix_kubeconfig(&mut kubeconfig, &control_plane_cas).unwrap();
+        let new_data = base64_standard
+            .decode(kubeconfig["clusters"][0]["cluster"]["certificate-authority-data"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(pem::parse_many(new_data).unwrap()[0], new_signer);
+    }
+}
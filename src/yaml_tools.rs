@@ -0,0 +1,254 @@
+//! Like json_tools, but for YAML documents: rewrites a single string scalar in place, in the style
+//! it was written in (plain, quoted, or a literal / folded block scalar with its chomping), rather
+//! than re-serializing the whole document with serde_yaml, which picks its own styles
+
+use anyhow::Result;
+use serde_json::Value;
+use std::{mem::MaybeUninit, ops::Range};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScalarStyle {
+    Plain,
+    SingleQuoted,
+    DoubleQuoted,
+    Literal,
+    Folded,
+}
+
+/// A mapping or sequence being walked by find_scalar, and where in it the walk is
+enum Frame {
+    Mapping { key: Option<String> },
+    Sequence { index: usize },
+}
+
+impl Frame {
+    /// The JSON pointer token of the node currently being parsed in this collection
+    fn token(&self) -> Option<String> {
+        match self {
+            Frame::Mapping { key } => key.clone(),
+            Frame::Sequence { index } => Some(index.to_string()),
+        }
+    }
+
+    /// Move on to the next node of this collection, once the current one has been parsed
+    fn advance(&mut self) {
+        match self {
+            Frame::Mapping { key } => *key = None,
+            Frame::Sequence { index } => *index += 1,
+        }
+    }
+}
+
+/// Patch the string at the given JSON pointer of a YAML document, rewriting only the bytes of that
+/// scalar in the style it was written in, see json_tools::patch_string_at_pointer. Returns None if
+/// that's not possible (the scalar can't be located, or its new value can't be written in its
+/// style, e.g. a plain scalar which now spans multiple lines), in which case callers should fall
+/// back to re-serializing the document.
+pub(crate) fn patch_string_at_pointer(
+    document: &str,
+    json_pointer: &str,
+    patch: impl FnOnce(&str) -> Result<String>,
+) -> Result<Option<String>> {
+    let Ok(mut resource) = serde_yaml::from_str::<Value>(document) else {
+        return Ok(None);
+    };
+    let Some(Value::String(value_at_json_pointer)) = resource.pointer_mut(json_pointer) else {
+        return Ok(None);
+    };
+
+    let patched = patch(value_at_json_pointer)?;
+    if patched == *value_at_json_pointer {
+        return Ok(Some(document.to_string()));
+    }
+
+    let Some((span, style)) = find_scalar(document, json_pointer) else {
+        return Ok(None);
+    };
+    let Some(rendered) = document
+        .get(span.clone())
+        .and_then(|original| render_scalar(original, style, &patched))
+    else {
+        return Ok(None);
+    };
+    *value_at_json_pointer = patched;
+
+    // Rather than getting every corner of YAML right when rendering (indentation indicators,
+    // anchors, tags, plain scalars which read as something else, ...), make sure the document
+    // parses into exactly what we intended and let the caller re-serialize it otherwise
+    let spliced = format!("{}{}{}", &document[..span.start], rendered, &document[span.end..]);
+    if serde_yaml::from_str::<Value>(&spliced).ok().as_ref() != Some(&resource) {
+        return Ok(None);
+    }
+
+    Ok(Some(spliced))
+}
+
+/// The byte range and style of the scalar at the given JSON pointer of the (first) document
+fn find_scalar(document: &str, json_pointer: &str) -> Option<(Range<usize>, ScalarStyle)> {
+    let tokens = json_pointer
+        .split('/')
+        .skip(1)
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect::<Vec<_>>();
+
+    // libyaml keeps pointers into the parser struct, so it must not move while in use
+    let mut parser = Box::new(MaybeUninit::<unsafe_libyaml::yaml_parser_t>::uninit());
+    // SAFETY: the parser is initialized before use and deleted before the document (which it
+    // reads from) and itself go away. Events are deleted once read
+    unsafe {
+        let parser = parser.as_mut_ptr();
+        if unsafe_libyaml::yaml_parser_initialize(parser).fail {
+            return None;
+        }
+        unsafe_libyaml::yaml_parser_set_input_string(parser, document.as_ptr(), document.len() as u64);
+
+        let mut stack: Vec<Frame> = vec![];
+        let mut found = None;
+        let mut event = MaybeUninit::<unsafe_libyaml::yaml_event_t>::uninit();
+        loop {
+            if unsafe_libyaml::yaml_parser_parse(parser, event.as_mut_ptr()).fail {
+                break;
+            }
+            let event = event.assume_init_mut();
+            let at_pointer = stack.len() == tokens.len()
+                && stack
+                    .iter()
+                    .zip(&tokens)
+                    .all(|(frame, token)| frame.token().as_ref() == Some(token));
+
+            let done = match event.type_ {
+                unsafe_libyaml::YAML_SCALAR_EVENT => match stack.last_mut() {
+                    Some(Frame::Mapping { key: key @ None }) => {
+                        let scalar = &event.data.scalar;
+                        *key = String::from_utf8(std::slice::from_raw_parts(scalar.value, scalar.length as usize).to_vec()).ok();
+                        key.is_none()
+                    }
+                    _ if at_pointer => {
+                        found = scalar_style(event.data.scalar.style)
+                            .map(|style| (event.start_mark.index as usize..event.end_mark.index as usize, style));
+                        true
+                    }
+                    _ => {
+                        advance(&mut stack);
+                        false
+                    }
+                },
+                unsafe_libyaml::YAML_ALIAS_EVENT => {
+                    advance(&mut stack);
+                    false
+                }
+                // Complex (non-scalar) keys can't be pointed at
+                unsafe_libyaml::YAML_MAPPING_START_EVENT | unsafe_libyaml::YAML_SEQUENCE_START_EVENT
+                    if matches!(stack.last(), Some(Frame::Mapping { key: None })) =>
+                {
+                    true
+                }
+                unsafe_libyaml::YAML_MAPPING_START_EVENT => {
+                    stack.push(Frame::Mapping { key: None });
+                    false
+                }
+                unsafe_libyaml::YAML_SEQUENCE_START_EVENT => {
+                    stack.push(Frame::Sequence { index: 0 });
+                    false
+                }
+                unsafe_libyaml::YAML_MAPPING_END_EVENT | unsafe_libyaml::YAML_SEQUENCE_END_EVENT => {
+                    stack.pop();
+                    advance(&mut stack);
+                    false
+                }
+                unsafe_libyaml::YAML_DOCUMENT_END_EVENT | unsafe_libyaml::YAML_STREAM_END_EVENT => true,
+                _ => false,
+            };
+
+            unsafe_libyaml::yaml_event_delete(event);
+            if done {
+                break;
+            }
+        }
+
+        unsafe_libyaml::yaml_parser_delete(parser);
+        found
+    }
+}
+
+fn advance(stack: &mut [Frame]) {
+    if let Some(frame) = stack.last_mut() {
+        frame.advance();
+    }
+}
+
+fn scalar_style(style: unsafe_libyaml::yaml_scalar_style_t) -> Option<ScalarStyle> {
+    Some(match style {
+        unsafe_libyaml::YAML_PLAIN_SCALAR_STYLE => ScalarStyle::Plain,
+        unsafe_libyaml::YAML_SINGLE_QUOTED_SCALAR_STYLE => ScalarStyle::SingleQuoted,
+        unsafe_libyaml::YAML_DOUBLE_QUOTED_SCALAR_STYLE => ScalarStyle::DoubleQuoted,
+        unsafe_libyaml::YAML_LITERAL_SCALAR_STYLE => ScalarStyle::Literal,
+        unsafe_libyaml::YAML_FOLDED_SCALAR_STYLE => ScalarStyle::Folded,
+        _ => return None,
+    })
+}
+
+/// Write the given value as a scalar of the given style, replacing the original scalar (as written
+/// in the document, including its quotes or block header)
+fn render_scalar(original: &str, style: ScalarStyle, value: &str) -> Option<String> {
+    match style {
+        ScalarStyle::Plain if !value.is_empty() && !value.contains(['\n', '\r']) => Some(value.to_string()),
+        ScalarStyle::SingleQuoted if !value.contains(['\n', '\r']) => Some(format!("'{}'", value.replace('\'', "''"))),
+        // JSON strings are valid double quoted YAML scalars
+        ScalarStyle::DoubleQuoted => serde_json::to_string(value).ok(),
+        ScalarStyle::Literal | ScalarStyle::Folded if !value.contains('\r') => render_block_scalar(original, style, value),
+        _ => None,
+    }
+}
+
+/// Write the given value as a block scalar with the same header (style and chomping indicators)
+/// and indentation as the original one. The original is followed by the line breaks and
+/// indentation libyaml consumed after its content, which are kept as they were, unless they're
+/// part of the value (keep chomping)
+fn render_block_scalar(original: &str, style: ScalarStyle, value: &str) -> Option<String> {
+    let (header, body) = original.split_once('\n')?;
+    // Explicit indentation indicators are relative to the parent node, which we don't track
+    if header.contains(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let keep = header.contains('+');
+
+    let indent = body.lines().find(|line| !line.trim().is_empty())?;
+    let indent = &indent[..indent.len() - indent.trim_start_matches(' ').len()];
+
+    let content_end = body.trim_end_matches([' ', '\n']).len();
+    let original_tail = &body[content_end..];
+    let content = value.trim_end_matches('\n');
+    let tail = if keep {
+        let next_line_indent = &original_tail[original_tail.rfind('\n').map_or(0, |i| i + 1)..];
+        format!("{}{}", "\n".repeat(value.len() - content.len()), next_line_indent)
+    } else {
+        original_tail.to_string()
+    };
+
+    let mut lines = vec![];
+    for line in content.split('\n') {
+        if line.is_empty() {
+            lines.push(String::new());
+        } else if style == ScalarStyle::Folded && line.starts_with([' ', '\t']) {
+            // More indented lines aren't folded, which we don't bother with
+            return None;
+        } else {
+            lines.push(format!("{}{}", indent, line));
+        }
+    }
+
+    // Folding turns single line breaks between lines into spaces, and drops one break of every
+    // longer run, so every run of breaks gets an extra one
+    let mut rendered = String::new();
+    let joined = lines.join("\n");
+    let mut chars = joined.chars().peekable();
+    while let Some(c) = chars.next() {
+        rendered.push(c);
+        if c == '\n' && style == ScalarStyle::Folded && chars.peek() != Some(&'\n') {
+            rendered.push('\n');
+        }
+    }
+
+    Some(format!("{}\n{}{}", header, rendered, tail))
+}